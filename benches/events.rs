@@ -0,0 +1,128 @@
+// How many events a second the broadcaster can deliver to a station's worth
+// of subscribers, with the lines of output and unit file paths that make up
+// most of the traffic when several devices are tested at once.  Run with:
+//
+//     cargo bench --bench events
+//
+// The same events are also sent the way they were before subscribers shared
+// them, with a copy for each, for comparison.
+extern crate exclave;
+
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use exclave::unit::UnitName;
+use exclave::unitbroadcaster::{
+    EventStamp, LogEntry, StampedEvent, UnitBroadcaster, UnitEvent, UnitStatus, UnitStatusEvent,
+};
+
+/// Enough subscribers for the terminal, loggers, interfaces, and uploader.
+const SUBSCRIBERS: usize = 10;
+
+/// Events sent in each run.
+const EVENTS: usize = 100_000;
+
+/// The rate that streaming the output of many devices at once calls for.
+const TARGET_RATE: f64 = 10_000.0;
+
+/// What's sent: mostly lines of test output, with the odd unit status.
+fn events() -> Vec<UnitEvent> {
+    let test = UnitName::from_str("flash", "test").unwrap();
+    let line = "0123456789abcdef".repeat(32);
+    let path = PathBuf::from("/etc/exclave/units/production/line-4/flash.test");
+    (0..EVENTS)
+        .map(|i| {
+            if i % 10 == 0 {
+                UnitEvent::Status(UnitStatusEvent {
+                    name: test.clone(),
+                    status: UnitStatus::Updated(path.clone()),
+                })
+            } else {
+                UnitEvent::Log(LogEntry::new_info(test.clone(), line.clone()))
+            }
+        })
+        .collect()
+}
+
+/// Drain a receiver on its own thread until every event has arrived.
+fn drain<T: Send + 'static>(receiver: Receiver<T>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for _ in 0..EVENTS {
+            receiver.recv().unwrap();
+        }
+    })
+}
+
+/// Broadcast every event, and report how long it took for all of them to
+/// reach every subscriber.
+fn shared(events: &[UnitEvent]) -> Duration {
+    let broadcaster = UnitBroadcaster::new();
+    let drains: Vec<_> = (0..SUBSCRIBERS)
+        .map(|_| drain(broadcaster.subscribe()))
+        .collect();
+    let started = Instant::now();
+    for event in events {
+        broadcaster.broadcast(event);
+    }
+    for drain in drains {
+        drain.join().unwrap();
+    }
+    started.elapsed()
+}
+
+/// Send every event to each subscriber as a copy of its own.
+fn copied(events: &[UnitEvent]) -> Duration {
+    let (senders, drains): (Vec<Sender<StampedEvent>>, Vec<_>) = (0..SUBSCRIBERS)
+        .map(|_| {
+            let (sender, receiver) = channel();
+            (sender, drain(receiver))
+        })
+        .unzip();
+    let started = Instant::now();
+    for (sequence, event) in events.iter().enumerate() {
+        let stamped = StampedEvent {
+            stamp: EventStamp {
+                sequence: sequence as u64 + 1,
+                station: String::new(),
+                unix_time: 0,
+                unix_time_nsecs: 0,
+                monotonic_nsecs: 0,
+            },
+            event: event.clone(),
+        };
+        for sender in &senders {
+            sender.send(stamped.clone()).unwrap();
+        }
+    }
+    for drain in drains {
+        drain.join().unwrap();
+    }
+    started.elapsed()
+}
+
+fn report(name: &str, elapsed: Duration) -> f64 {
+    let rate = EVENTS as f64 / elapsed.as_secs_f64();
+    println!(
+        "{:<8} {:>9} events to {} subscribers in {:>8.3}s: {:>10.0} events/s",
+        name,
+        EVENTS,
+        SUBSCRIBERS,
+        elapsed.as_secs_f64(),
+        rate
+    );
+    rate
+}
+
+fn main() {
+    let events = events();
+    // Once to warm up, then for real.
+    shared(&events);
+    let rate = report("shared", shared(&events));
+    report("copied", copied(&events));
+    if rate < TARGET_RATE {
+        println!("below the target of {} events/s", TARGET_RATE);
+        std::process::exit(1);
+    }
+}
@@ -10,9 +10,10 @@ use std::process::Command;
 /// fn main() { git_version::set_env_with_name("CARGO_PKG_VERSION"); }
 /// ```
 use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
 fn set_env_with_name(name: &str) {
     let ver = match Command::new("git")
-        .args(&["describe", "--tags", "--dirty=-modified"])
+        .args(["describe", "--tags", "--dirty=-modified"])
         .output()
     {
         Ok(cmd) => {
@@ -40,7 +41,70 @@ fn set_env_with_name(name: &str) {
     println!("cargo:rerun-if-env-changed={}", name);
 }
 
+/// Set `GIT_HASH` to the commit being built, with "-modified" after it if
+/// there are uncommitted changes, or "unknown" outside of a git checkout.
+fn set_git_hash() {
+    let hash = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|cmd| cmd.status.success())
+        .and_then(|cmd| String::from_utf8(cmd.stdout).ok())
+        .map(|hash| hash.trim().to_owned());
+    let modified = Command::new("git")
+        .args(["diff-index", "--quiet", "HEAD", "--"])
+        .status()
+        .map(|status| !status.success())
+        .unwrap_or(false);
+    let hash = match hash {
+        Some(hash) if modified => format!("{}-modified", hash),
+        Some(hash) => hash,
+        None => "unknown".to_owned(),
+    };
+    println!("cargo:rustc-env=GIT_HASH={}", hash);
+}
+
+/// Set `BUILD_DATE` to when this was built, in UTC, as "YYYY-MM-DDTHH:MM:SSZ".
+/// SOURCE_DATE_EPOCH is used instead of the current time if it's set, so
+/// that builds can be reproduced.
+fn set_build_date() {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    // Days since the epoch to a civil date, after Howard Hinnant's
+    // days_from_civil algorithm, run backwards.
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let time = secs % 86400;
+    println!(
+        "cargo:rustc-env=BUILD_DATE={:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    );
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
+
 fn main() {
     env::set_var("RUST_BACKTRACE", "1");
     set_env_with_name("GIT_VERSION");
+    set_git_hash();
+    set_build_date();
 }
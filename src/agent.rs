@@ -0,0 +1,315 @@
+// Runs tests on another machine, such as a controller inside a shield box,
+// for fixtures that are split between two computers.  The other machine runs
+// "exclave --agent <address> --agent-key <file>", which loads no units and
+// only runs the commands it's sent.  A test with Agent= set has its ExecStart
+// sent to that agent, and its output and exit code come back as if it had
+// been run locally.
+//
+// Each test gets its own connection, and each side sends lines of text:
+//
+//     agent:   EXCLAVE-AGENT 1 <nonce>
+//     station: RUN <hmac> <request>
+//     agent:   OK, or DENIED <reason>
+//     agent:   OUT <line>, ERR <line>, ..., EXIT <code>
+//
+// The request is JSON, and the HMAC is HMAC-SHA256 over the nonce followed
+// by the request, under a key that the station and agent share.  So only a
+// station with the key can run anything, and a request can't be altered or
+// replayed on another connection.  Nothing is encrypted, so agents belong on
+// a private network.  Closing the connection stops the program.
+extern crate runny;
+extern crate serde_json;
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use init;
+use poison::Lock;
+use sha256;
+use sys;
+
+use self::runny::Runny;
+
+/// What an agent says first, so stations know what they've connected to.
+const GREETING: &str = "EXCLAVE-AGENT";
+
+/// The version of the protocol, which goes up if it changes incompatibly.
+const VERSION: u32 = 1;
+
+/// How long to wait for an agent to accept a connection or answer, and for
+/// a station to send its request.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The longest request an agent reads, so a connection without the key
+/// can't make it use up memory.
+const MAX_REQUEST_BYTES: u64 = 1 << 20;
+
+/// How long to wait for a program's output to drain after it exits, in case
+/// something it started is holding stdout open.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What a station asks an agent to run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    /// The command, run with "/bin/sh -c".
+    pub command: String,
+
+    /// The directory to run it from, relative to the agent's directory.
+    pub directory: Option<PathBuf>,
+
+    /// How long it may run for.
+    pub timeout: Option<Duration>,
+
+    /// How long it has to exit once it's asked to stop, before it's killed.
+    pub stop_timeout: Option<Duration>,
+}
+
+/// Something a program run on an agent did.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    /// It printed a line to stdout.
+    Stdout(String),
+
+    /// It printed a line to stderr.
+    Stderr(String),
+
+    /// It exited, with this code.
+    Exit(i32),
+}
+
+/// Read the key shared by a station and its agents, which must only be
+/// readable by its owner.
+pub fn read_key(path: &Path) -> Result<Vec<u8>, String> {
+    match sys::is_private_file(path) {
+        Ok(true) => (),
+        Ok(false) => {
+            return Err(format!(
+                "agent key file {} can be read by other users, so it won't be used",
+                path.display()
+            ))
+        }
+        Err(e) => return Err(format!("unable to read {}: {}", path.display(), e)),
+    }
+    let key = fs::read_to_string(path)
+        .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(format!("agent key file {} is empty", path.display()));
+    }
+    Ok(key.as_bytes().to_vec())
+}
+
+/// A program started on an agent.
+pub struct Connection {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Connection {
+    /// Connect to an agent and ask it to run something.
+    pub fn start(address: &str, key: &[u8], request: &Request) -> Result<Connection, String> {
+        let fail = |e: io::Error| format!("unable to reach agent at {}: {}", address, e);
+        let socket_address = address
+            .to_socket_addrs()
+            .map_err(fail)?
+            .next()
+            .ok_or_else(|| format!("no address found for agent {}", address))?;
+        let mut stream =
+            TcpStream::connect_timeout(&socket_address, HANDSHAKE_TIMEOUT).map_err(fail)?;
+        stream
+            .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+            .map_err(fail)?;
+        let mut reader = BufReader::new(stream.try_clone().map_err(fail)?);
+
+        let greeting = read_line(&mut reader).map_err(fail)?;
+        let nonce = match greeting.split(' ').collect::<Vec<&str>>()[..] {
+            [GREETING, version, nonce] if version == VERSION.to_string() => nonce.to_owned(),
+            _ => {
+                return Err(format!(
+                    "{} isn't an exclave agent that speaks version {}",
+                    address, VERSION
+                ))
+            }
+        };
+        let body = serde_json::to_string(request).map_err(|e| e.to_string())?;
+        let mac = sha256::hmac(key, format!("{}{}", nonce, body).as_bytes());
+        writeln!(stream, "RUN {} {}", mac, body).map_err(fail)?;
+        match read_line(&mut reader).map_err(fail)?.as_str() {
+            "OK" => (),
+            answer => {
+                return Err(format!(
+                    "agent at {} refused to run the test: {}",
+                    address,
+                    answer.trim_start_matches("DENIED").trim()
+                ))
+            }
+        }
+        // The program may take as long as it likes to print something.
+        stream.set_read_timeout(None).map_err(fail)?;
+        Ok(Connection { stream, reader })
+    }
+
+    /// Something that stops the program when it's shut down, for use from
+    /// another thread.
+    pub fn stopper(&self) -> io::Result<TcpStream> {
+        self.stream.try_clone()
+    }
+
+    /// The next thing the program did, or None if the connection was lost
+    /// before it exited.
+    pub fn next_output(&mut self) -> Option<Output> {
+        let line = read_line(&mut self.reader).ok()?;
+        let (kind, rest) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+        match kind {
+            "OUT" => Some(Output::Stdout(rest.to_owned())),
+            "ERR" => Some(Output::Stderr(rest.to_owned())),
+            "EXIT" => rest.parse().ok().map(Output::Exit),
+            _ => None,
+        }
+    }
+}
+
+/// Stop a program started on an agent, by closing its connection.
+pub fn stop(stopper: &TcpStream) {
+    stopper.shutdown(Shutdown::Both).ok();
+}
+
+/// Read one line, without its line ending.  The end of the stream is an error.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed",
+        ));
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+/// Run as an agent, running whatever stations with the key ask for, with
+/// relative directories taken from `directory`.  Never returns unless the
+/// listener fails.
+pub fn serve(listener: TcpListener, key: Vec<u8>, directory: PathBuf) -> io::Result<()> {
+    let key = Arc::new(key);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let key = key.clone();
+        let directory = directory.clone();
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map(|address| address.to_string())
+                .unwrap_or_default();
+            if let Err(e) = handle(stream, &key, &directory) {
+                eprintln!("agent: connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// A nonce that no other connection will get.
+fn nonce() -> io::Result<String> {
+    let mut bytes = [0; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Check a station's request, returning it if it came from someone with the key.
+fn authenticate(line: &str, key: &[u8], nonce: &str) -> Result<Request, String> {
+    let rest = line
+        .strip_prefix("RUN ")
+        .ok_or_else(|| "expected RUN".to_owned())?;
+    let (mac, body) = rest
+        .split_once(' ')
+        .ok_or_else(|| "expected RUN <hmac> <request>".to_owned())?;
+    let expected = sha256::hmac(key, format!("{}{}", nonce, body).as_bytes());
+    if !sha256::same_digest(mac, &expected) {
+        return Err("wrong key".to_owned());
+    }
+    serde_json::from_str(body).map_err(|e| format!("invalid request: {}", e))
+}
+
+fn handle(mut stream: TcpStream, key: &[u8], directory: &Path) -> io::Result<()> {
+    let nonce = nonce()?;
+    writeln!(stream, "{} {} {}", GREETING, VERSION, nonce)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let line = read_line(&mut (&mut reader).take(MAX_REQUEST_BYTES))?;
+    let request = match authenticate(&line, key, &nonce) {
+        Ok(request) => request,
+        Err(e) => {
+            writeln!(stream, "DENIED {}", e)?;
+            return Ok(());
+        }
+    };
+
+    let mut cmd = Runny::new(&request.command);
+    if let Some(timeout) = request.timeout {
+        cmd.timeout(timeout);
+    }
+    cmd.directory(&Some(match request.directory {
+        Some(ref dir) => directory.join(dir),
+        None => directory.to_owned(),
+    }));
+    let mut running = match cmd.start() {
+        Ok(running) => running,
+        Err(e) => {
+            writeln!(stream, "DENIED unable to start: {:?}", e)?;
+            return Ok(());
+        }
+    };
+    init::spawned(running.pid());
+    writeln!(stream, "OK")?;
+    stream.set_read_timeout(None)?;
+
+    // Stations say nothing more, so anything from them means they've gone,
+    // and the program should be stopped.
+    let waiter = running.waiter();
+    let stop_timeout = request.stop_timeout;
+    thread::spawn(move || {
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).ok();
+        waiter.terminate(&stop_timeout);
+    });
+
+    let writer = Arc::new(Mutex::new(stream));
+    // Each sender is dropped as its thread finishes, which disconnects the receiver.
+    let (done_sender, done_receiver) = channel::<()>();
+    let forward = |output: runny::running::RunningOutput, kind: &'static str| {
+        let writer = writer.clone();
+        let done_sender = done_sender.clone();
+        thread::spawn(move || {
+            let _done_sender = done_sender;
+            for line in BufReader::new(output).split(b'\n') {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                let line = String::from_utf8_lossy(&line);
+                let mut writer = writer.locked();
+                if writeln!(writer, "{} {}", kind, line.trim_end_matches('\r')).is_err() {
+                    break;
+                }
+            }
+        });
+    };
+    forward(running.take_output(), "OUT");
+    forward(running.take_error(), "ERR");
+    drop(done_sender);
+    running.wait().ok();
+    done_receiver.recv_timeout(OUTPUT_DRAIN_TIMEOUT).ok();
+    let mut writer = writer.locked();
+    writeln!(writer, "EXIT {}", running.result())?;
+    writer.shutdown(Shutdown::Both).ok();
+    Ok(())
+}
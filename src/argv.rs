@@ -0,0 +1,227 @@
+// Splitting commands such as ExecStart into a program and its arguments,
+// without a shell, so that signals reach the program itself, its exit code
+// is its own, and nothing in an argument (such as a serial number) can be
+// taken as shell syntax.  The rules are:
+//
+//  * Arguments are separated by spaces and tabs.
+//  * Text in '...' or "..." is part of the argument it's in, spaces and all,
+//    and quotes can be mixed with other text, as in --name="Test Jig".
+//  * Nothing else is special.  Backslashes are kept as they are, so Windows
+//    paths can be written as they are, and there are no escapes, variables,
+//    globs, pipes, redirections, or comments.
+//
+// Specifiers (see specifier.rs) and secrets are filled in after the command
+// is split, so each is always part of the one argument it was written in,
+// whatever's in it.  Units with
+// Shell=yes are run with "/bin/sh -c" (or "cmd /C" on Windows) instead, for
+// commands that need a pipe or a variable.  What a specifier stands for, such
+// as a serial number read from a device, is quoted for the shell then, so it
+// is still only ever one argument and never shell syntax.
+use std::cmp::Reverse;
+use std::fmt;
+use std::path::Path;
+
+use secrets::{self, Resolved, SecretError, Secrets};
+use specifier::{self, Piece, Specifiers};
+use unit::UnitDescriptionError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgvError {
+    /// There's nothing to run.
+    Empty,
+
+    /// A quote, either ' or ", was opened and never closed.
+    Unterminated(char),
+}
+
+impl fmt::Display for ArgvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArgvError::Empty => write!(f, "no command given"),
+            ArgvError::Unterminated(quote) => write!(f, "{} is never closed", quote),
+        }
+    }
+}
+
+/// Split a command into a program and its arguments.
+pub fn split(command: &str) -> Result<Vec<String>, ArgvError> {
+    let mut args = vec![];
+    let mut arg: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\r' | '\n' => args.extend(arg.take()),
+            '\'' | '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(quoted) if quoted == c => break,
+                        Some(quoted) => arg.push(quoted),
+                        None => return Err(ArgvError::Unterminated(c)),
+                    }
+                }
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    if args.is_empty() {
+        return Err(ArgvError::Empty);
+    }
+    Ok(args)
+}
+
+/// Check that a directive holds a command that can be split.
+pub fn check(section: &str, key: &str, command: &str) -> Result<(), UnitDescriptionError> {
+    split(command).map(|_| ()).map_err(|e| {
+        UnitDescriptionError::InvalidValue(
+            section.to_owned(),
+            key.to_owned(),
+            format!("{} ({})", command, e),
+            vec!["a program and its arguments, with every quote closed".to_owned()],
+        )
+    })
+}
+
+/// Quote an argument so that Runny, which doubles every backslash and then
+/// splits the command like a shell would, gives it back as it is.
+pub fn quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "/._-=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return arg.to_owned();
+    }
+    // Nothing is special in double quotes once backslashes are doubled,
+    // except double quotes, which go in single quotes of their own.
+    let mut quoted = "\"".to_owned();
+    for c in arg.chars() {
+        match c {
+            '"' => quoted.push_str("\"'\"'\""),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Put a program and its arguments together in the form Runny runs.
+pub fn join(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| quote(arg))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// The program and arguments that run a command with the shell.
+#[cfg(unix)]
+pub fn shell(command: &str) -> Vec<String> {
+    vec!["/bin/sh".to_owned(), "-c".to_owned(), command.to_owned()]
+}
+
+/// The program and arguments that run a command with the shell.
+#[cfg(windows)]
+pub fn shell(command: &str) -> Vec<String> {
+    vec!["cmd".to_owned(), "/C".to_owned(), command.to_owned()]
+}
+
+/// Quote a specifier's value for "/bin/sh -c", in single quotes, in which
+/// nothing is special but the single quote itself.
+#[cfg(unix)]
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Quote a specifier's value for "cmd /C": first as one argument the program
+/// will split back out, then with a caret before everything cmd would act on,
+/// the double quotes included, so none of it can start a variable or a pipe.
+#[cfg(windows)]
+pub fn shell_quote(value: &str) -> String {
+    // As the C runtime splits arguments: backslashes are only special when
+    // they come before a double quote, and then each one is doubled.
+    let mut arg = "\"".to_owned();
+    let mut backslashes = 0;
+    for c in value.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                continue;
+            }
+            '"' => backslashes = backslashes * 2 + 1,
+            _ => (),
+        }
+        arg.push_str(&"\\".repeat(backslashes));
+        arg.push(c);
+        backslashes = 0;
+    }
+    arg.push_str(&"\\".repeat(backslashes * 2));
+    arg.push('"');
+
+    let mut quoted = String::new();
+    for c in arg.chars() {
+        if "()%!^\"<>&|".contains(c) {
+            quoted.push('^');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
+/// Fill in the specifiers and secrets a command refers to, and put it in the
+/// form Runny runs, either split into arguments or handed to the shell whole.
+pub fn resolve(
+    file: Option<&Path>,
+    command: &str,
+    shell: bool,
+    specifiers: &Specifiers,
+) -> Result<Resolved, SecretError> {
+    // Commands are checked as their units are loaded, so one that can't be
+    // split is run as a program of that name, which fails to start.
+    let args = if shell {
+        vec![command.to_owned()]
+    } else {
+        split(command).unwrap_or_else(|_| vec![command.to_owned()])
+    };
+    let secrets = if secrets::has_references(command) {
+        Some(Secrets::load(file)?)
+    } else {
+        None
+    };
+    let mut resolved = Resolved::default();
+    let mut resolved_args = vec![];
+    for arg in &args {
+        // What a specifier stands for is never taken as a secret reference.
+        let pieces = specifier::parse(arg).unwrap_or_else(|_| vec![Piece::Text(arg.to_owned())]);
+        let mut resolved_arg = String::new();
+        for piece in pieces {
+            let text = match (piece, secrets.as_ref()) {
+                (Piece::Specifier(c), _) if shell => {
+                    resolved_arg.push_str(&shell_quote(specifiers.get(c)));
+                    continue;
+                }
+                (Piece::Specifier(c), _) => {
+                    resolved_arg.push_str(specifiers.get(c));
+                    continue;
+                }
+                (Piece::Text(text), None) => text,
+                (Piece::Text(text), Some(secrets)) => {
+                    let text = secrets.resolve(&text)?;
+                    for value in text.values {
+                        if !resolved.values.contains(&value) {
+                            resolved.values.push(value);
+                        }
+                    }
+                    text.text
+                }
+            };
+            resolved_arg.push_str(&text);
+        }
+        resolved_args.push(resolved_arg);
+    }
+    resolved.text = if shell {
+        join(&self::shell(&resolved_args[0]))
+    } else {
+        join(&resolved_args)
+    };
+    // Longest first, so a secret that contains another is masked whole.
+    resolved.values.sort_by_key(|value| Reverse(value.len()));
+    Ok(resolved)
+}
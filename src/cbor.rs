@@ -0,0 +1,142 @@
+// Just enough CBOR (RFC 8949) to send records to interfaces that asked for
+// binary framing, without pulling in a serializer for it.  Only the
+// definite-length forms are written, which every decoder accepts.
+use std::io::{self, Write};
+
+/// A value to be encoded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unsigned(u64),
+    Negative(i64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    /// A map, whose entries are written in the order given.
+    Map(Vec<(Value, Value)>),
+    Bool(bool),
+    Null,
+}
+
+impl Value {
+    /// A signed integer, which CBOR encodes differently depending on its sign.
+    pub fn integer(value: i64) -> Value {
+        if value < 0 {
+            Value::Negative(value)
+        } else {
+            Value::Unsigned(value as u64)
+        }
+    }
+
+    /// Text, or null if there isn't any.
+    pub fn optional_text<S: ToString>(value: Option<S>) -> Value {
+        value
+            .map(|s| Value::Text(s.to_string()))
+            .unwrap_or(Value::Null)
+    }
+
+    /// A number, or null if there isn't one.
+    pub fn optional_unsigned(value: Option<u64>) -> Value {
+        value.map(Value::Unsigned).unwrap_or(Value::Null)
+    }
+
+    /// Append the encoding of this value.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            Value::Unsigned(n) => head(out, 0, n),
+            // -1 is encoded as 0, -2 as 1, and so on.
+            Value::Negative(n) => head(out, 1, !(n as u64)),
+            Value::Bytes(ref bytes) => {
+                head(out, 2, bytes.len() as u64);
+                out.extend_from_slice(bytes);
+            }
+            Value::Text(ref text) => {
+                head(out, 3, text.len() as u64);
+                out.extend_from_slice(text.as_bytes());
+            }
+            Value::Array(ref items) => {
+                head(out, 4, items.len() as u64);
+                for item in items {
+                    item.encode(out);
+                }
+            }
+            Value::Map(ref entries) => {
+                head(out, 5, entries.len() as u64);
+                for (key, value) in entries {
+                    key.encode(out);
+                    value.encode(out);
+                }
+            }
+            Value::Bool(false) => out.push(0xf4),
+            Value::Bool(true) => out.push(0xf5),
+            Value::Null => out.push(0xf6),
+        }
+    }
+
+    /// The encoding of this value.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.encode(&mut out);
+        out
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(text: &'a str) -> Value {
+        Value::Text(text.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Value {
+        Value::Text(text)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Value {
+        Value::Unsigned(n)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(n: u32) -> Value {
+        Value::Unsigned(u64::from(n))
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+/// Write the initial bytes of an item: its major type, and a number that is
+/// its value, length, or count, in as few bytes as it fits in.
+fn head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    if n < 24 {
+        out.push(major | n as u8);
+    } else if n <= u64::from(u8::MAX) {
+        out.push(major | 24);
+        out.push(n as u8);
+    } else if n <= u64::from(u16::MAX) {
+        out.push(major | 25);
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= u64::from(u32::MAX) {
+        out.push(major | 26);
+        out.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+/// Write a value as one frame: its length, as four big-endian bytes,
+/// followed by its encoding.
+pub fn write_frame<W: Write>(out: &mut W, value: &Value) -> io::Result<()> {
+    let payload = value.to_bytes();
+    let mut frame = Vec::with_capacity(payload.len() + 4);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    out.write_all(&frame)
+}
@@ -0,0 +1,320 @@
+// Test certificates: a human-readable report for each device that passes,
+// which customers can be handed along with the device.  The certificate is
+// made from the CertificateTemplate in the settings file, with placeholders
+// such as {{serial}} filled in from the run's results, and a block between
+// {{#tests}} and {{/tests}} repeated for each test:
+//
+//     <h1>{{scenario}}: {{serial}}</h1>
+//     <table>
+//     {{#tests}}<tr><td>{{test}}</td><td>{{verdict}}</td><td>{{measurements}}</td></tr>{{/tests}}
+//     </table>
+//
+// It's saved in the run directory as "certificate-<serial>" with the
+// template's extension.  A CertificateRenderer, such as "wkhtmltopdf - -",
+// is given the certificate on stdin and its stdout is saved alongside it as
+// a PDF, and a CertificatePrinter, such as "lp", prints whichever was made
+// last.
+extern crate humantime;
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use self::humantime::format_rfc3339_seconds;
+
+use unitbroadcaster::{ScenarioSummary, TestSummary};
+
+/// How long the renderer may run for before it's killed.
+pub const RENDER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often to check whether the renderer has finished.
+const RENDER_POLL: Duration = Duration::from_millis(20);
+
+/// Settings for making certificates, copied out of the Config.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CertificateSettings {
+    /// The template that each certificate is made from.
+    pub template: PathBuf,
+
+    /// Shell command that turns a certificate on stdin into a PDF on stdout.
+    pub renderer: Option<String>,
+
+    /// Shell command that prints the certificate in $EXCLAVE_CERTIFICATE.
+    pub printer: Option<String>,
+}
+
+/// Fill in a template with a run's results.  Values are escaped for HTML
+/// if `html` is set.
+pub fn render(
+    template: &str,
+    summary: &ScenarioSummary,
+    finished: SystemTime,
+    html: bool,
+) -> Result<String, String> {
+    expand(template, summary, None, finished, html)
+}
+
+fn expand(
+    template: &str,
+    summary: &ScenarioSummary,
+    test: Option<&TestSummary>,
+    finished: SystemTime,
+    html: bool,
+) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find("}}")
+            .map(|end| start + end)
+            .ok_or_else(|| "a \"{{\" is never closed".to_owned())?;
+        let name = rest[start + 2..end].trim();
+        rest = &rest[end + 2..];
+        if let Some(section) = name.strip_prefix('#') {
+            // Only the tests can be repeated, and not inside themselves.
+            if section.trim() != "tests" || test.is_some() {
+                return Err(format!("unknown block \"{{{{{}}}}}\"", name));
+            }
+            let close = "{{/tests}}";
+            let body_end = rest
+                .find(close)
+                .ok_or_else(|| "\"{{#tests}}\" is never closed".to_owned())?;
+            for test in &summary.tests {
+                rendered.push_str(&expand(
+                    &rest[..body_end],
+                    summary,
+                    Some(test),
+                    finished,
+                    html,
+                )?);
+            }
+            rest = &rest[body_end + close.len()..];
+            continue;
+        }
+        let value = value(name, summary, test, finished)
+            .ok_or_else(|| format!("unknown placeholder \"{{{{{}}}}}\"", name))?;
+        if html {
+            rendered.push_str(&escape_html(&value));
+        } else {
+            rendered.push_str(&value);
+        }
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// What a placeholder stands for.  Inside {{#tests}}, a test's own values
+/// take the place of the run's.
+fn value(
+    name: &str,
+    summary: &ScenarioSummary,
+    test: Option<&TestSummary>,
+    finished: SystemTime,
+) -> Option<String> {
+    if let Some(test) = test {
+        match name {
+            "test" => return Some(test.test.to_string()),
+            "verdict" => return Some(test.verdict.to_string()),
+            "reason" => return Some(test.reason.clone()),
+            "duration" => return Some(test.duration.map(seconds).unwrap_or_default()),
+            "measurements" => {
+                let measurements: Vec<String> = test
+                    .measurements
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                return Some(measurements.join(", "));
+            }
+            _ => (),
+        }
+        if let Some(measurement) = name.strip_prefix("measurement:") {
+            return Some(
+                test.measurements
+                    .iter()
+                    .find(|(name, _)| name == measurement)
+                    .map(|(_, value)| value.clone())
+                    .unwrap_or_default(),
+            );
+        }
+    }
+    let or_empty = |value: Option<&String>| value.cloned().unwrap_or_default();
+    if let Some(allocator) = name.strip_prefix("allocation:") {
+        return Some(
+            summary
+                .allocations
+                .iter()
+                .find(|(name, _)| name.id() == allocator || name.to_string() == allocator)
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default(),
+        );
+    }
+    if let Some(parameter) = name.strip_prefix("param:") {
+        return Some(
+            summary
+                .parameters
+                .iter()
+                .find(|(name, _)| name == parameter)
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default(),
+        );
+    }
+    Some(match name {
+        "scenario" => summary.scenario.to_string(),
+        "jig" => summary
+            .jig
+            .as_ref()
+            .map(|jig| jig.to_string())
+            .unwrap_or_default(),
+        "serial" => or_empty(summary.serial.as_ref()),
+        "run" => or_empty(summary.run_id.as_ref()),
+        "payload" => or_empty(summary.payload.as_ref()),
+        "verdict" => summary.verdict().to_string(),
+        "reason" => summary.reason.clone(),
+        "date" => format_rfc3339_seconds(finished).to_string(),
+        "duration" => seconds(summary.duration),
+        "passed" => summary.passed.to_string(),
+        "failed" => summary.failed.to_string(),
+        "faults" => summary.faults.to_string(),
+        "skipped" => summary.skipped.to_string(),
+        _ => return None,
+    })
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{}.{:03}s", duration.as_secs(), duration.subsec_millis())
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// What a run's certificate is called, without an extension.  A serial
+/// number that can't be part of a file name has those parts replaced.
+pub fn file_stem(summary: &ScenarioSummary) -> String {
+    match summary.serial {
+        Some(ref serial) if !serial.is_empty() => {
+            let mut stem = "certificate-".to_owned();
+            for c in serial.chars() {
+                if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                    stem.push(c);
+                } else {
+                    stem.push('_');
+                }
+            }
+            stem
+        }
+        _ => "certificate".to_owned(),
+    }
+}
+
+/// Make a run's certificate in its run directory, and render it if there's
+/// a renderer.  Returns the file that should be printed: the rendered one,
+/// if there is one.
+pub fn write(
+    run: &Path,
+    settings: &CertificateSettings,
+    summary: &ScenarioSummary,
+    finished: SystemTime,
+) -> io::Result<PathBuf> {
+    let template = fs::read_to_string(&settings.template)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", settings.template.display(), e)))?;
+    let extension = settings
+        .template
+        .extension()
+        .map(|extension| extension.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "txt".to_owned());
+    let html = extension.eq_ignore_ascii_case("html") || extension.eq_ignore_ascii_case("htm");
+    let rendered = render(&template, summary, finished, html).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", settings.template.display(), e),
+        )
+    })?;
+
+    let stem = file_stem(summary);
+    let certificate = run.join(format!("{}.{}", stem, extension));
+    fs::write(&certificate, rendered)?;
+    match settings.renderer {
+        Some(ref renderer) => {
+            let pdf = run.join(format!("{}.pdf", stem));
+            let result = render_pdf(renderer, &certificate, &pdf);
+            if result.is_err() {
+                fs::remove_file(&pdf).ok();
+            }
+            result.map(|_| pdf)
+        }
+        None => Ok(certificate),
+    }
+}
+
+/// Pipe a certificate through the renderer into a PDF.
+fn render_pdf(renderer: &str, certificate: &Path, pdf: &Path) -> io::Result<()> {
+    let mut child = shell(renderer)
+        .env("EXCLAVE_CERTIFICATE", certificate)
+        .stdin(File::open(certificate)?)
+        .stdout(File::create(pdf)?)
+        .stderr(Stdio::null())
+        .spawn()?;
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() >= RENDER_TIMEOUT {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("renderer timed out after {:?}", RENDER_TIMEOUT),
+            ));
+        }
+        thread::sleep(RENDER_POLL);
+    };
+    if !status.success() {
+        return Err(io::Error::other(format!("renderer exited with {}", status)));
+    }
+    Ok(())
+}
+
+/// Print a certificate, waiting for the printer command to finish.
+pub fn print(printer: &str, certificate: &Path) -> io::Result<()> {
+    let status = shell(printer)
+        .env("EXCLAVE_CERTIFICATE", certificate)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("printer exited with {}", status)));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("/bin/sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
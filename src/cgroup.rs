@@ -0,0 +1,196 @@
+// Control groups, which let what a test cost be read once its processes
+// have exited, however many of them it started.  Each run of a test gets a
+// cgroup of its own, beneath the one exclave is in: in the "cpuacct" and
+// "memory" hierarchies with cgroup v1, or in the unified hierarchy with v2.
+// They're only on Linux, and exclave has to be allowed to make them, such as
+// by running as root or in a unit with systemd's Delegate=yes.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// How many cgroups have been made, so each one gets a new name.
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+/// A cgroup made for one run of a test.  It's removed when dropped, if its
+/// processes have all exited by then.
+#[derive(Debug)]
+pub struct Cgroup {
+    /// Its directory in the cgroup v1 cpuacct hierarchy, if there is one.
+    cpuacct: Option<PathBuf>,
+
+    /// Its directory in the cgroup v1 memory hierarchy, if there is one.
+    memory: Option<PathBuf>,
+
+    /// Its directory in the cgroup v2 hierarchy, if there's no v1 one.
+    unified: Option<PathBuf>,
+}
+
+/// Where a cgroup hierarchy is mounted, as (the cgroup at the mount point,
+/// the mount point), for the v1 hierarchy with `controller`, or for the v2
+/// hierarchy if `controller` is None.
+fn mount(controller: Option<&str>) -> Option<(PathBuf, PathBuf)> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+    mountinfo.lines().find_map(|line| {
+        let (mount, filesystem) = line.split_once(" - ")?;
+        let mount: Vec<&str> = mount.split(' ').collect();
+        let filesystem: Vec<&str> = filesystem.split(' ').collect();
+        let found = match (controller, filesystem.first()) {
+            (None, Some(&"cgroup2")) => true,
+            (Some(controller), Some(&"cgroup")) => filesystem
+                .get(2)
+                .is_some_and(|options| options.split(',').any(|o| o == controller)),
+            _ => false,
+        };
+        if found && mount.len() > 4 {
+            Some((PathBuf::from(mount[3]), PathBuf::from(mount[4])))
+        } else {
+            None
+        }
+    })
+}
+
+/// The directory of the cgroup the calling thread is in, in the v1
+/// hierarchy with `controller`, or in the v2 hierarchy if it's None.
+fn current(controller: Option<&str>) -> Option<PathBuf> {
+    let cgroups = fs::read_to_string("/proc/thread-self/cgroup").ok()?;
+    let path = cgroups.lines().find_map(|line| {
+        let mut fields = line.splitn(3, ':');
+        let (id, controllers, path) = (fields.next()?, fields.next()?, fields.next()?);
+        let found = match controller {
+            None => id == "0" && controllers.is_empty(),
+            Some(controller) => controllers.split(',').any(|c| c == controller),
+        };
+        if found {
+            Some(path)
+        } else {
+            None
+        }
+    })?;
+    let (root, mount_point) = mount(controller)?;
+    let relative = Path::new(path).strip_prefix(&root).ok()?;
+    Some(mount_point.join(relative))
+}
+
+/// Make a new cgroup beneath the calling thread's, in the v1 hierarchy with
+/// `controller`, or in the v2 hierarchy if it's None.
+fn create_in(controller: Option<&str>, name: &str) -> io::Result<PathBuf> {
+    let parent = current(controller).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no {} cgroup", controller.unwrap_or("v2")),
+        )
+    })?;
+    let dir = parent.join(name);
+    fs::create_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Stop using a cgroup, such as when a process couldn't be put into it, so
+/// that it isn't mistaken for having measured the process.
+fn forget(dir: &mut Option<PathBuf>) {
+    if let Some(dir) = dir.take() {
+        fs::remove_dir(dir).ok();
+    }
+}
+
+impl Cgroup {
+    /// Make a new cgroup, beneath the one the calling thread is in.
+    pub fn create() -> io::Result<Cgroup> {
+        let name = format!(
+            "exclave-{}-{}",
+            process::id(),
+            CREATED.fetch_add(1, Ordering::Relaxed)
+        );
+        match (
+            create_in(Some("cpuacct"), &name),
+            create_in(Some("memory"), &name),
+        ) {
+            (Err(_), Err(_)) => Ok(Cgroup {
+                cpuacct: None,
+                memory: None,
+                unified: Some(create_in(None, &name)?),
+            }),
+            (cpuacct, memory) => Ok(Cgroup {
+                cpuacct: cpuacct.ok(),
+                memory: memory.ok(),
+                unified: None,
+            }),
+        }
+    }
+
+    /// Start a process with `start`, in this cgroup, so that every process
+    /// it starts is in it too.  With v1, the calling thread joins the cgroup
+    /// while the process is started, so the process starts out in it.  A v2
+    /// cgroup can't take one thread on its own, so the process is moved in
+    /// as soon as it has started instead.
+    pub fn spawn<T, E, F>(&mut self, start: F, pid: fn(&T) -> i32) -> Result<T, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        // Writing 0 moves the thread that writes it.
+        for dir in [&mut self.cpuacct, &mut self.memory] {
+            if let Some(ref path) = *dir {
+                if fs::write(path.join("tasks"), "0").is_err() {
+                    forget(dir);
+                }
+            }
+        }
+        let started = start();
+        for dir in [&self.cpuacct, &self.memory]
+            .iter()
+            .filter_map(|d| d.as_ref())
+        {
+            if let Some(parent) = dir.parent() {
+                fs::write(parent.join("tasks"), "0").ok();
+            }
+        }
+        let moved = match (&started, &self.unified) {
+            (Ok(started), Some(dir)) => {
+                fs::write(dir.join("cgroup.procs"), pid(started).to_string()).is_ok()
+            }
+            _ => true,
+        };
+        if !moved {
+            forget(&mut self.unified);
+        }
+        started
+    }
+
+    /// CPU time (user and system) used by the processes in the cgroup,
+    /// including ones that have exited.
+    pub fn cpu(&self) -> Option<Duration> {
+        if let Some(ref dir) = self.cpuacct {
+            let nanos = fs::read_to_string(dir.join("cpuacct.usage")).ok()?;
+            return nanos.trim().parse().ok().map(Duration::from_nanos);
+        }
+        let stat = fs::read_to_string(self.unified.as_ref()?.join("cpu.stat")).ok()?;
+        stat.lines()
+            .find_map(|line| line.strip_prefix("usage_usec "))
+            .and_then(|usec| usec.trim().parse().ok())
+            .map(Duration::from_micros)
+    }
+
+    /// The most memory the processes in the cgroup used at once, in KiB.
+    pub fn peak_memory_kb(&self) -> Option<u64> {
+        let path = match (&self.memory, &self.unified) {
+            (Some(dir), _) => dir.join("memory.max_usage_in_bytes"),
+            (None, Some(dir)) => dir.join("memory.peak"),
+            (None, None) => return None,
+        };
+        let bytes: u64 = fs::read_to_string(path).ok()?.trim().parse().ok()?;
+        Some(bytes / 1024)
+    }
+}
+
+impl Drop for Cgroup {
+    // Removing a cgroup fails if any of its processes are still running,
+    // such as a daemon that was left behind, and then it's left as well.
+    fn drop(&mut self) {
+        forget(&mut self.cpuacct);
+        forget(&mut self.memory);
+        forget(&mut self.unified);
+    }
+}
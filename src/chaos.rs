@@ -0,0 +1,274 @@
+// Fault injection, for shaking out the bugs that only turn up when things
+// happen in an unusual order or fail at awkward moments.  Run exclave with
+// "--chaos <profile>" and it will, at random, hold events back before
+// broadcasting them, fail to start programs, cut lines read from tests and
+// interfaces short, and announce unit file changes a second time while the
+// first is still being handled.
+//
+// A profile says how likely each fault is, from 0 to 1, and the seed:
+//
+//     seed=1234,delay=0.05,max-delay=200ms,spawn=0.02,truncate=0.01,watcher=0.1
+//
+// Each kind of fault draws from its own stream of numbers, so with the same
+// seed, the nth program started fails (or doesn't) in every run, however
+// the threads happen to interleave.  Without a seed, one is picked, and it
+// is logged so that a failure can be reproduced.
+extern crate runny;
+
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use self::runny::RunnyError;
+
+use duration;
+use poison::Lock;
+use unit::UnitName;
+
+/// How long an event is held back for at most, if the profile doesn't say.
+const DEFAULT_MAX_DELAY: Duration = Duration::from_millis(100);
+
+/// The faults that can be injected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Fault {
+    /// An event is held back before being broadcast, letting events from
+    /// other threads overtake it.
+    Delay,
+
+    /// A program fails to start.
+    Spawn,
+
+    /// A line read from a test or interface loses its end.
+    Truncate,
+
+    /// A change to a unit file is announced again a little later.
+    Watcher,
+}
+
+impl Fault {
+    const ALL: [Fault; 4] = [Fault::Delay, Fault::Spawn, Fault::Truncate, Fault::Watcher];
+
+    fn key(self) -> &'static str {
+        match self {
+            Fault::Delay => "delay",
+            Fault::Spawn => "spawn",
+            Fault::Truncate => "truncate",
+            Fault::Watcher => "watcher",
+        }
+    }
+}
+
+/// How likely each fault is, and where the random numbers start.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub seed: u64,
+    pub max_delay: Duration,
+    chances: Vec<(Fault, f64)>,
+}
+
+impl Profile {
+    /// Read a profile such as "seed=1,delay=0.1,spawn=0.05".  Faults that
+    /// aren't mentioned never happen.
+    pub fn parse(text: &str) -> Result<Profile, String> {
+        let mut profile = Profile {
+            seed: new_seed(),
+            max_delay: DEFAULT_MAX_DELAY,
+            chances: vec![],
+        };
+        for item in text.split(',').map(|item| item.trim()) {
+            if item.is_empty() {
+                continue;
+            }
+            let (key, value) = item
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, not \"{}\"", item))?;
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "seed" => {
+                    profile.seed = value
+                        .parse()
+                        .map_err(|_| format!("seed \"{}\" isn't a number", value))?
+                }
+                "max-delay" => {
+                    profile.max_delay = duration::parse(value).map_err(|e| e.to_string())?
+                }
+                key => {
+                    let fault = Fault::ALL
+                        .iter()
+                        .find(|fault| fault.key() == key)
+                        .ok_or_else(|| format!("unknown fault \"{}\"", key))?;
+                    let chance = value
+                        .parse()
+                        .ok()
+                        .filter(|chance: &f64| (0.0..=1.0).contains(chance))
+                        .ok_or_else(|| {
+                            format!("{} should be between 0 and 1, not \"{}\"", key, value)
+                        })?;
+                    profile.chances.retain(|(f, _)| f != fault);
+                    profile.chances.push((*fault, chance));
+                }
+            }
+        }
+        Ok(profile)
+    }
+
+    /// How likely a fault is.
+    pub fn chance(&self, fault: Fault) -> f64 {
+        self.chances
+            .iter()
+            .find(|(f, _)| *f == fault)
+            .map(|(_, chance)| *chance)
+            .unwrap_or(0.0)
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "seed={}", self.seed)?;
+        for fault in Fault::ALL.iter() {
+            let chance = self.chance(*fault);
+            if chance > 0.0 {
+                write!(f, ",{}={}", fault.key(), chance)?;
+            }
+        }
+        if self.chance(Fault::Delay) > 0.0 {
+            write!(f, ",max-delay={}ms", self.max_delay.as_millis())?;
+        }
+        Ok(())
+    }
+}
+
+/// A profile, and a stream of numbers for each fault.
+pub struct Chaos {
+    profile: Profile,
+    streams: Vec<(Fault, u64)>,
+}
+
+impl Chaos {
+    pub fn new(profile: Profile) -> Chaos {
+        let streams = Fault::ALL
+            .iter()
+            .enumerate()
+            .map(|(index, fault)| (*fault, profile.seed ^ ((index as u64 + 1) << 56)))
+            .collect();
+        Chaos { profile, streams }
+    }
+
+    /// A number from 0 up to (but not including) 1, from a fault's stream.
+    fn next(&mut self, fault: Fault) -> f64 {
+        let state = &mut self
+            .streams
+            .iter_mut()
+            .find(|(f, _)| *f == fault)
+            .expect("every fault has a stream")
+            .1;
+        // SplitMix64
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Whether a fault should happen this time.
+    pub fn strikes(&mut self, fault: Fault) -> bool {
+        let chance = self.profile.chance(fault);
+        chance > 0.0 && self.next(fault) < chance
+    }
+
+    /// How long to hold something back for, if at all.
+    pub fn delay(&mut self, fault: Fault) -> Option<Duration> {
+        if !self.strikes(fault) {
+            return None;
+        }
+        Some(self.profile.max_delay.mul_f64(self.next(fault)))
+    }
+
+    /// A line with its end cut off, if the fault strikes.
+    pub fn truncate(&mut self, line: String) -> String {
+        if line.is_empty() || !self.strikes(Fault::Truncate) {
+            return line;
+        }
+        let mut end = (line.len() as f64 * self.next(Fault::Truncate)) as usize;
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        line[..end].to_owned()
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static CHAOS: Mutex<Option<Chaos>> = Mutex::new(None);
+
+/// Start injecting faults.  Until this is called, nothing is.
+pub fn enable(profile: Profile) {
+    *CHAOS.locked() = Some(Chaos::new(profile));
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn with<T>(default: T, f: impl FnOnce(&mut Chaos) -> T) -> T {
+    if !is_enabled() {
+        return default;
+    }
+    match *CHAOS.locked() {
+        Some(ref mut chaos) => f(chaos),
+        None => default,
+    }
+}
+
+/// Whether a fault should happen this time.
+pub fn strikes(fault: Fault) -> bool {
+    with(false, |chaos| chaos.strikes(fault))
+}
+
+/// Hold an event back for a while, if the fault strikes.
+pub fn delay_event() {
+    if let Some(delay) = with(None, |chaos| chaos.delay(Fault::Delay)) {
+        thread::sleep(delay);
+    }
+}
+
+/// A delay to announce a unit file change again after, if the fault
+/// strikes.
+pub fn watcher_race() -> Option<Duration> {
+    with(None, |chaos| chaos.delay(Fault::Watcher))
+}
+
+/// Fail to start a unit's program, if the fault strikes.
+pub fn spawn(unit: &UnitName) -> Result<(), RunnyError> {
+    if strikes(Fault::Spawn) {
+        return Err(RunnyError::RunnyIoError(io::Error::other(format!(
+            "chaos: {} failed to start",
+            unit
+        ))));
+    }
+    Ok(())
+}
+
+/// A line read from a program, perhaps cut short.
+pub fn truncate(line: String) -> String {
+    if !is_enabled() {
+        return line;
+    }
+    match *CHAOS.locked() {
+        Some(ref mut chaos) => chaos.truncate(line),
+        None => line,
+    }
+}
+
+/// A seed for when the profile doesn't give one.
+fn new_seed() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_secs() ^ (u64::from(now.subsec_nanos()) << 20) ^ u64::from(std::process::id())
+}
@@ -0,0 +1,199 @@
+// Built-in checks, for tests that would otherwise be a three-line shell script
+// that looks at a file.  A test gives one or more Check directives instead of
+// ExecStart:
+//
+//     Check=exists /dev/ttyACM0
+//     Check=size firmware.bin 1K..4M
+//     Check=sha256 /boot/u-boot.bin 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+//     Check=equals /sys/class/net/eth0/operstate up
+//     Check=range /sys/class/thermal/thermal_zone0/temp 20000..80000
+//
+// Every check is run, without starting a program, and each prints a line
+// saying how it went.  The test passes if they all pass.
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use sha256;
+
+/// One thing about a file that a test checks.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Check {
+    /// The file exists.
+    Exists(PathBuf),
+
+    /// The file's size, in bytes, is within a range.
+    Size(PathBuf, Range<u64>),
+
+    /// The file's contents have a SHA-256 digest.
+    Sha256(PathBuf, String),
+
+    /// The file's contents, without surrounding whitespace, are some text.
+    Equals(PathBuf, String),
+
+    /// The file's contents are a number within a range.
+    Range(PathBuf, Range<f64>),
+}
+
+/// An inclusive range, either end of which may be left open.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Range<T> {
+    low: Option<T>,
+    high: Option<T>,
+    text: String,
+}
+
+impl<T: PartialOrd + Copy> Range<T> {
+    /// Parse "low..high", where either end may be left out, using a parser
+    /// for each end.
+    fn parse<F: Fn(&str) -> Option<T>>(text: &str, parse_end: F) -> Option<Range<T>> {
+        let (low, high) = text.split_once("..")?;
+        let end = |s: &str| {
+            if s.is_empty() {
+                Some(None)
+            } else {
+                parse_end(s).map(Some)
+            }
+        };
+        let range = Range {
+            low: end(low)?,
+            high: end(high)?,
+            text: text.to_owned(),
+        };
+        match (range.low, range.high) {
+            (None, None) => None,
+            (Some(low), Some(high)) if low > high => None,
+            _ => Some(range),
+        }
+    }
+
+    fn contains(&self, value: T) -> bool {
+        self.low.map(|low| value >= low).unwrap_or(true)
+            && self.high.map(|high| value <= high).unwrap_or(true)
+    }
+}
+
+impl<T> fmt::Display for Range<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Parse a size in bytes, with an optional K, M, or G suffix.
+fn parse_size(text: &str) -> Option<u64> {
+    let (number, multiplier) = match text.chars().last()? {
+        'K' | 'k' => (&text[..text.len() - 1], 1 << 10),
+        'M' | 'm' => (&text[..text.len() - 1], 1 << 20),
+        'G' | 'g' => (&text[..text.len() - 1], 1 << 30),
+        _ => (text, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+impl Check {
+    /// The forms a Check can take, for error messages.
+    pub fn forms() -> Vec<String> {
+        vec![
+            "exists <path>".to_owned(),
+            "size <path> <min>..<max>".to_owned(),
+            "sha256 <path> <hex digest>".to_owned(),
+            "equals <path> <text>".to_owned(),
+            "range <path> <min>..<max>".to_owned(),
+        ]
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Check::Exists(ref path)
+            | Check::Size(ref path, _)
+            | Check::Sha256(ref path, _)
+            | Check::Equals(ref path, _)
+            | Check::Range(ref path, _) => path,
+        }
+    }
+
+    /// Run the check, with relative paths taken from a directory, saying
+    /// what was found either way.
+    pub fn run(&self, directory: &Path) -> Result<String, String> {
+        let path = directory.join(self.path());
+        let name = self.path().display();
+        let read = || {
+            fs::read_to_string(&path)
+                .map(|contents| contents.trim().to_owned())
+                .map_err(|e| format!("unable to read {}: {}", name, e))
+        };
+        match self {
+            Check::Exists(_) => {
+                if path.exists() {
+                    Ok(format!("{} exists", name))
+                } else {
+                    Err(format!("{} doesn't exist", name))
+                }
+            }
+            Check::Size(_, ref range) => {
+                let size = fs::metadata(&path)
+                    .map_err(|e| format!("unable to check {}: {}", name, e))?
+                    .len();
+                if range.contains(size) {
+                    Ok(format!("{} is {} bytes", name, size))
+                } else {
+                    Err(format!("{} is {} bytes, not {}", name, size, range))
+                }
+            }
+            Check::Sha256(_, ref expected) => {
+                let digest =
+                    sha256::file(&path).map_err(|e| format!("unable to check {}: {}", name, e))?;
+                if digest == *expected {
+                    Ok(format!("{} has SHA-256 {}", name, digest))
+                } else {
+                    Err(format!("{} has SHA-256 {}, not {}", name, digest, expected))
+                }
+            }
+            Check::Equals(_, ref expected) => {
+                let value = read()?;
+                if value == *expected {
+                    Ok(format!("{} is \"{}\"", name, value))
+                } else {
+                    Err(format!("{} is \"{}\", not \"{}\"", name, value, expected))
+                }
+            }
+            Check::Range(_, ref range) => {
+                let value = read()?;
+                match value.parse::<f64>() {
+                    Ok(number) if range.contains(number) => Ok(format!("{} is {}", name, value)),
+                    Ok(_) => Err(format!("{} is {}, not {}", name, value, range)),
+                    Err(_) => Err(format!("{} is \"{}\", which isn't a number", name, value)),
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Check {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (kind, rest) = s.split_once(char::is_whitespace).ok_or(())?;
+        let rest = rest.trim_start();
+        // The text that Equals compares against may contain spaces.
+        let (path, argument) = match rest.split_once(char::is_whitespace) {
+            Some((path, argument)) => (PathBuf::from(path), argument.trim()),
+            None => (PathBuf::from(rest), ""),
+        };
+        match (kind, argument) {
+            ("exists", "") => Ok(Check::Exists(path)),
+            ("size", range) => Range::parse(range, parse_size)
+                .map(|range| Check::Size(path, range))
+                .ok_or(()),
+            ("sha256", digest) if sha256::valid_digest(digest) => {
+                Ok(Check::Sha256(path, digest.to_ascii_lowercase()))
+            }
+            ("equals", text) if !text.is_empty() => Ok(Check::Equals(path, text.to_owned())),
+            ("range", range) => Range::parse(range, |end| end.parse().ok())
+                .map(|range| Check::Range(path, range))
+                .ok_or(()),
+            _ => Err(()),
+        }
+    }
+}
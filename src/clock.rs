@@ -0,0 +1,74 @@
+// Where exclave gets the time from when timing scenarios and tests.  That's
+// normally the system clock, but tests can use a fake one that only moves
+// when told to, so that timeouts and durations come out the same on every
+// run.  Programs that are run still see the real time, and their own
+// timeouts still use it.
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use poison::Lock;
+
+#[derive(Debug)]
+struct FakeTime {
+    instant: Instant,
+    system: SystemTime,
+    elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Clock {
+    /// The fake time, shared by every copy of the clock, or None to use the
+    /// system clock.
+    fake: Option<Arc<Mutex<FakeTime>>>,
+}
+
+impl Clock {
+    pub fn system() -> Clock {
+        Clock { fake: None }
+    }
+
+    /// A clock that reads `start` until it's advanced.
+    pub fn fake(start: SystemTime) -> Clock {
+        Clock {
+            fake: Some(Arc::new(Mutex::new(FakeTime {
+                instant: Instant::now(),
+                system: start,
+                elapsed: Duration::from_secs(0),
+            }))),
+        }
+    }
+
+    pub fn is_fake(&self) -> bool {
+        self.fake.is_some()
+    }
+
+    /// The time of day.
+    pub fn now(&self) -> SystemTime {
+        match self.fake {
+            None => SystemTime::now(),
+            Some(ref fake) => {
+                let fake = fake.locked();
+                fake.system + fake.elapsed
+            }
+        }
+    }
+
+    /// A reading for measuring how long something took.
+    pub fn instant(&self) -> Instant {
+        match self.fake {
+            None => Instant::now(),
+            Some(ref fake) => {
+                let fake = fake.locked();
+                fake.instant + fake.elapsed
+            }
+        }
+    }
+
+    /// Move a fake clock, and every copy of it, forward.  The system clock
+    /// can't be moved, so this does nothing to it.
+    pub fn advance(&self, by: Duration) {
+        if let Some(ref fake) = self.fake {
+            fake.locked().elapsed += by;
+        }
+    }
+}
@@ -0,0 +1,221 @@
+// Periodically makes sure the system clock can be trusted, so that results
+// recorded while it can't be are flagged rather than silently mistimed.
+extern crate humantime;
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::Command;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use self::humantime::{format_duration, parse_rfc3339};
+
+use config::{Config, ConfigChange};
+use poison::Lock;
+use unit::UnitName;
+use unitbroadcaster::{UnitBroadcaster, UnitEvent};
+use unitmanager::{ManagerControlMessage, ManagerControlMessageContents};
+
+/// How long to wait for the reference server to answer.
+const REFERENCE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings for checking the clock, copied out of the Config for the checking thread.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ClockSettings {
+    /// The clock is wrong if it reads earlier than this.
+    pub not_before: Option<SystemTime>,
+
+    /// An "http://" URL whose Date header is compared against the clock.
+    pub reference: Option<String>,
+
+    /// How far the clock may be from the reference.
+    pub max_skew: Duration,
+
+    /// If true, the clock is wrong unless the system reports it as NTP-synchronized.
+    pub require_ntp: bool,
+
+    /// How often to check the clock.
+    pub interval: Duration,
+}
+
+impl ClockSettings {
+    fn is_enabled(&self) -> bool {
+        self.not_before.is_some() || self.reference.is_some() || self.require_ntp
+    }
+
+    /// Check the clock, returning a description of the problem if it can't be trusted.
+    fn check(&self) -> Result<(), String> {
+        let now = SystemTime::now();
+        if let Some(not_before) = self.not_before {
+            if now < not_before {
+                return Err("clock is set earlier than ClockNotBefore".to_owned());
+            }
+        }
+        if self.require_ntp {
+            Self::check_ntp()?;
+        }
+        if let Some(ref url) = self.reference {
+            let reference = Self::reference_time(url)
+                .map_err(|e| format!("unable to get the time from {}: {}", url, e))?;
+            let skew = match now.duration_since(reference) {
+                Ok(d) => d,
+                Err(e) => e.duration(),
+            };
+            if skew > self.max_skew {
+                return Err(format!(
+                    "clock is {} away from {}",
+                    format_duration(Duration::from_secs(skew.as_secs())),
+                    url
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_ntp() -> Result<(), String> {
+        let output = Command::new("timedatectl")
+            .args(["show", "--property=NTPSynchronized", "--value"])
+            .output()
+            .map_err(|e| format!("unable to check NTP status: {}", e))?;
+        if String::from_utf8_lossy(&output.stdout).trim() == "yes" {
+            Ok(())
+        } else {
+            Err("clock is not synchronized with NTP".to_owned())
+        }
+    }
+
+    /// Fetch the Date header from an HTTP server.
+    fn reference_time(url: &str) -> Result<SystemTime, String> {
+        let rest = match url.strip_prefix("http://") {
+            Some(r) => r,
+            None => return Err("only http:// references are supported".to_owned()),
+        };
+        let (host, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+        let address = if host.contains(':') {
+            host.to_owned()
+        } else {
+            format!("{}:80", host)
+        };
+        let address = address
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| format!("no address found for {}", host))?;
+
+        let mut stream =
+            TcpStream::connect_timeout(&address, REFERENCE_TIMEOUT).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(REFERENCE_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        write!(
+            stream,
+            "HEAD {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            path, host
+        )
+        .map_err(|e| e.to_string())?;
+        let mut response = vec![];
+        stream
+            .take(65536)
+            .read_to_end(&mut response)
+            .map_err(|e| e.to_string())?;
+
+        for line in String::from_utf8_lossy(&response).lines() {
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("date") {
+                    return parse_http_date(value.trim())
+                        .ok_or_else(|| format!("invalid Date header: {}", value.trim()));
+                }
+            }
+        }
+        Err("no Date header in response".to_owned())
+    }
+}
+
+/// Parse an HTTP date, such as "Sun, 06 Nov 1994 08:49:37 GMT".
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let fields: Vec<&str> = date.split_whitespace().collect();
+    if fields.len() != 6 || fields[5] != "GMT" {
+        return None;
+    }
+    let month = match fields[2] {
+        "Jan" => 1,
+        "Feb" => 2,
+        "Mar" => 3,
+        "Apr" => 4,
+        "May" => 5,
+        "Jun" => 6,
+        "Jul" => 7,
+        "Aug" => 8,
+        "Sep" => 9,
+        "Oct" => 10,
+        "Nov" => 11,
+        "Dec" => 12,
+        _ => return None,
+    };
+    let day = fields[1].parse::<u32>().ok()?;
+    parse_rfc3339(&format!(
+        "{}-{:02}-{:02}T{}Z",
+        fields[3], month, day, fields[4]
+    ))
+    .ok()
+}
+
+pub struct ClockCheck {
+    config: Arc<Mutex<Config>>,
+    sender: Sender<ClockSettings>,
+}
+
+impl ClockCheck {
+    pub fn new(broadcaster: &UnitBroadcaster, config: &Arc<Mutex<Config>>) -> ClockCheck {
+        let (sender, receiver) = channel();
+        let broadcaster = broadcaster.clone();
+
+        thread::spawn(move || {
+            let mut settings: Option<ClockSettings> = None;
+            loop {
+                let request = match settings {
+                    Some(ref s) if s.is_enabled() => receiver.recv_timeout(s.interval),
+                    _ => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match request {
+                    Ok(new_settings) => settings = Some(new_settings),
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+                let problem = match settings {
+                    Some(ref s) if s.is_enabled() => s.check().err(),
+                    _ => None,
+                };
+                broadcaster.broadcast(&UnitEvent::ManagerRequest(ManagerControlMessage::new(
+                    &UnitName::internal("clock"),
+                    ManagerControlMessageContents::ClockStatus(problem),
+                )));
+            }
+        });
+
+        let clock_check = ClockCheck {
+            config: config.clone(),
+            sender,
+        };
+        clock_check.send_settings();
+        clock_check
+    }
+
+    pub fn process_message(&self, evt: &UnitEvent) {
+        if let UnitEvent::ConfigChanged(ConfigChange { ref applied, .. }) = *evt {
+            if applied.iter().any(|s| s.starts_with("Clock")) {
+                self.send_settings();
+            }
+        }
+    }
+
+    fn send_settings(&self) {
+        let settings = self.config.locked().clock_settings();
+        self.sender.send(settings).ok();
+    }
+}
@@ -0,0 +1,114 @@
+// Limits on how many tests of a kind may run at once, such as only one RF
+// test per shield box.  A test joins a group with
+//
+//     ConcurrencyGroup=rf
+//
+// and the settings file says how many of the group may run at once, with
+// "ConcurrencyLimit=rf 1" (one, if it doesn't say).  Each group has that many
+// slots, which are lock files in the LockDirectory, and a test takes a free
+// slot before it starts and gives it back when it's stopped.  Since the slots
+// are files, every exclave on the machine that uses the same LockDirectory
+// shares them, as when each DUT position has its own exclave.  A test that
+// finds every slot taken waits for one, without holding up anything else.
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use sys;
+
+/// Where slots are kept if LockDirectory isn't set.  It's the same for
+/// every exclave on the machine, so that they all share it.
+pub fn default_lock_directory() -> PathBuf {
+    env::temp_dir().join("exclave-locks")
+}
+
+/// How many tests of a group may run at once, if the settings file doesn't say.
+pub const DEFAULT_LIMIT: u32 = 1;
+
+/// How often a waiting test checks whether a slot has come free.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A slot in a group, held until it's dropped.
+#[derive(Debug)]
+pub struct Slot {
+    // Closing the file releases the lock.
+    _file: File,
+    index: u32,
+}
+
+impl Slot {
+    /// Which of the group's slots this is, starting from 0.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+/// Whether a name can be used for a group.  Names become file names, so
+/// only letters, digits, "-", "_", and "." are allowed.
+pub fn valid_group(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn slot_path(directory: &Path, group: &str, index: u32) -> PathBuf {
+    directory.join(format!("{}.{}.lock", group, index))
+}
+
+/// Take a free slot in a group, or return None if they're all taken.
+pub fn try_acquire(directory: &Path, group: &str, limit: u32) -> io::Result<Option<Slot>> {
+    fs::create_dir_all(directory)?;
+    for index in 0..limit {
+        let path = slot_path(directory, group, index);
+        // Another user's exclave may have made the file, which is still
+        // fine to lock if it can be read.
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(_) => OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?,
+        };
+        if sys::try_lock(&file)? {
+            return Ok(Some(Slot { _file: file, index }));
+        }
+    }
+    Ok(None)
+}
+
+/// Call `free` once a slot in the group looks to be free, unless `cancelled`
+/// is set first.  The slot isn't taken, so someone else may get it first.
+pub fn when_free<F>(
+    directory: PathBuf,
+    group: String,
+    limit: u32,
+    cancelled: Arc<AtomicBool>,
+    free: F,
+) where
+    F: FnOnce() + Send + 'static,
+{
+    thread::spawn(move || loop {
+        thread::sleep(POLL_INTERVAL);
+        if cancelled.load(Ordering::SeqCst) {
+            return;
+        }
+        // Errors are left for the next attempt to take a slot to report.
+        match try_acquire(&directory, &group, limit) {
+            Ok(None) => continue,
+            Ok(Some(_)) | Err(_) => {
+                if !cancelled.load(Ordering::SeqCst) {
+                    free();
+                }
+                return;
+            }
+        }
+    });
+}
@@ -1,16 +1,281 @@
+extern crate humantime;
+extern crate systemd_parser;
+
 use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use self::humantime::parse_rfc3339_weak;
+use self::systemd_parser::items::DirectiveEntry;
+
+use agent;
+use argv;
+use certificate::CertificateSettings;
+use clock::Clock;
+use clockcheck::ClockSettings;
+use concurrency;
+use container;
+use duration;
+use ed25519::SigningKey;
+use estimate::DurationHistory;
+use fetch;
+use flakiness::{
+    FlakinessTracker, FlakyTest, TestStats, DEFAULT_FLAKINESS_THRESHOLD, DEFAULT_FLAKINESS_WINDOW,
+};
+use redact::Redaction;
+use results::SinkSpec;
+use secrets::{self, Resolved, SecretError, Secrets};
+use specifier::Specifiers;
+use station::{self, Station};
+use store;
+use sys;
+use unit::{parse_bool, UnitDescriptionError, UnitKind, UnitName};
+use unitbroadcaster::ScenarioSummary;
+use unitfile;
+use uploader::{RetentionSettings, UploadSettings};
 
 const DEFAULT_TIMEOUT_SECS: u64 = 5;
 
+/// How far the clock may drift from ClockReference, unless set otherwise.
+const DEFAULT_CLOCK_MAX_SKEW_SECS: u64 = 60;
+
+/// How often to check the clock, unless set otherwise.
+const DEFAULT_CLOCK_CHECK_INTERVAL_SECS: u64 = 3600;
+
+/// How long unit files must go unchanged before they're rescanned, unless
+/// set otherwise.
+const DEFAULT_RESCAN_DELAY_SECS: u64 = 1;
+
+/// Name of the file under the state directory that lists disabled units.
+const DISABLED_UNITS_FILE: &str = "disabled-units";
+
+/// Name of the file under the state directory that lists when each jig was
+/// last calibrated.
+const CALIBRATIONS_FILE: &str = "calibrations";
+
+/// Name of the file under the state directory that holds each jig's counters.
+const COUNTERS_FILE: &str = "counters";
+
+/// Name of the file under the state directory that holds the last value each
+/// allocator handed out, so values are never handed out twice.
+const ALLOCATIONS_FILE: &str = "allocations";
+
+/// Name of the file under the state directory that exists while the station
+/// is in maintenance mode, holding the reason.
+const MAINTENANCE_FILE: &str = "maintenance";
+
+/// Name of the file under the state directory that holds how each test has
+/// done, so flakiness is tracked across restarts.
+const STATISTICS_FILE: &str = "statistics";
+
+/// Name of the file under the state directory that holds how long each
+/// scenario's recent runs took, so estimates survive restarts.
+const DURATIONS_FILE: &str = "durations";
+
+/// A list of settings that were modified by reloading the config file.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigChange {
+    /// Settings that took effect immediately.
+    pub applied: Vec<String>,
+
+    /// Settings that changed on disk, but that only take effect after a restart.
+    pub restart_required: Vec<String>,
+}
+
+impl ConfigChange {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.restart_required.is_empty()
+    }
+}
+
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no settings changed");
+        }
+        if !self.applied.is_empty() {
+            write!(f, "applied {}", self.applied.join(", "))?;
+        }
+        if !self.restart_required.is_empty() {
+            if !self.applied.is_empty() {
+                write!(f, "; ")?;
+            }
+            write!(
+                f,
+                "restart required for {}",
+                self.restart_required.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
 pub struct Config {
     timeout: Duration,
     jig_working_directory: Rc<RefCell<Option<PathBuf>>>,
+
+    /// The jig that tests are run against, if any.
+    bound_jig: Rc<RefCell<Option<UnitName>>>,
     scenario_working_directory: Rc<RefCell<Option<PathBuf>>>,
     paths: Vec<PathBuf>,
     terminate_timeout: Duration,
+
+    /// The file the settings were loaded from, if any
+    config_file: Option<PathBuf>,
+
+    /// Unit directories listed in the config file.  These are only read at startup.
+    unit_directories: Vec<PathBuf>,
+
+    /// Directories that only hold units of one kind, such as tests kept on a
+    /// separate partition, listed in the config file.  Also only read at startup.
+    kind_directories: BTreeMap<UnitKind, Vec<PathBuf>>,
+
+    /// Unit directories given on the command line, for any kind of unit or
+    /// for just one.  Reloading the config file leaves these alone.
+    added_directories: Vec<(PathBuf, Option<UnitKind>)>,
+
+    /// Directory where runtime state is kept, if any
+    state_directory: Option<PathBuf>,
+
+    /// The StateDirectory from the config file, which the command line may
+    /// have overridden.
+    state_directory_setting: Option<PathBuf>,
+
+    /// Units that were disabled at runtime (e.g. with DISABLE).
+    disabled_units: BTreeSet<UnitName>,
+
+    /// When each jig last passed its calibration scenario.
+    calibrations: BTreeMap<UnitName, SystemTime>,
+
+    /// The count of each jig counter, by jig and counter name.
+    counters: BTreeMap<(UnitName, String), u64>,
+
+    /// The last value each allocator handed out.
+    allocations: BTreeMap<UnitName, String>,
+
+    /// Why the station is in maintenance mode, if it is.
+    maintenance: Option<String>,
+
+    /// How each test has done, for STATS and flakiness tracking.
+    statistics: FlakinessTracker,
+
+    /// How long each scenario's recent runs took, for estimates.
+    durations: DurationHistory,
+
+    /// Which station this is, from the identity file in the StateDirectory.
+    station: Station,
+
+    /// Directory under which each run gets a directory of test output, if any
+    log_directory: Option<PathBuf>,
+
+    /// Directory for test output from the current run, if any
+    run_directory: Rc<RefCell<Option<PathBuf>>>,
+
+    /// The serial number the current run's tests have reported, if any
+    run_serial: Rc<RefCell<Option<String>>>,
+
+    /// Strip escape sequences and control characters from test output
+    sanitize_output: bool,
+
+    /// File of secrets that unit commands may refer to, if any
+    secrets_file: Option<PathBuf>,
+
+    /// Rules for taking sensitive details out of test output
+    redactions: Vec<Redaction>,
+
+    /// Where files that tests Fetch are kept, if not in the state directory
+    fetch_cache: Option<PathBuf>,
+
+    /// The address of each agent that tests can be run on, by name
+    agents: BTreeMap<String, String>,
+
+    /// File holding the key shared with agents, if any
+    agent_key_file: Option<PathBuf>,
+
+    /// How many tests of each ConcurrencyGroup may run at once, if not the default
+    concurrency_limits: BTreeMap<String, u32>,
+
+    /// Where the slots for each ConcurrencyGroup are kept, if not the default
+    lock_directory: Option<PathBuf>,
+
+    /// Program that runs tests that name a Container, if not podman
+    container_runtime: Option<String>,
+
+    /// Command that finished runs are piped into as a .tar.gz, if any
+    upload_command: Option<String>,
+
+    /// How often to upload finished runs, rather than as they finish
+    upload_interval: Option<Duration>,
+
+    /// How long unit files must go unchanged before they're rescanned
+    rescan_delay: Duration,
+
+    /// Maximum upload speed, in bytes per second
+    upload_rate_limit: Option<u64>,
+
+    /// How long runs are kept in the LogDirectory, if not forever
+    log_retention: Option<Duration>,
+
+    /// Whether runs must be uploaded before they're deleted
+    retention_export: bool,
+
+    /// Runs are deleted, oldest first, while less than this many bytes are
+    /// free in the LogDirectory
+    minimum_free_space: Option<u64>,
+
+    /// Template that each passing run's certificate is made from, if any
+    certificate_template: Option<PathBuf>,
+
+    /// Command that renders certificates into PDFs, if any
+    certificate_renderer: Option<String>,
+
+    /// Command that prints certificates, if any
+    certificate_printer: Option<String>,
+
+    /// How to decide whether the system clock can be trusted
+    clock: ClockSettings,
+
+    /// Where the results of each run are stored, in addition to LogDirectory
+    result_sinks: Vec<SinkSpec>,
+
+    /// The secret holding the key that result records are signed with, if any
+    result_signing_key: Option<String>,
+
+    /// Keep every jig active at once, rather than only one
+    multiple_jigs: bool,
+
+    /// Leave scenarios that can't be selected out of listings, rather than
+    /// saying why they can't be
+    hide_incompatible_scenarios: bool,
+
+    /// Only parse tests once a scenario uses them, or one is started
+    lazy_tests: bool,
+
+    /// Refuse durations given as a bare number, without a unit
+    strict_durations: bool,
+
+    /// How many recent pass/fail verdicts a test's flakiness is measured over
+    flakiness_window: usize,
+
+    /// The flip rate, in percent, at which a test is reported as flaky
+    flakiness_threshold: u32,
+
+    /// Where exclave's own timings are served from, if they're measured at all
+    metrics_address: Option<String>,
+
+    /// Where scenarios and tests get the time from
+    time: Clock,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::new()
+    }
 }
 
 impl Config {
@@ -19,17 +284,93 @@ impl Config {
             timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
             terminate_timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
             jig_working_directory: Rc::new(RefCell::new(None)),
+            bound_jig: Rc::new(RefCell::new(None)),
             scenario_working_directory: Rc::new(RefCell::new(None)),
-            paths: vec![
-                Path::new("/usr/local/sbin").to_owned(),
-                Path::new("/usr/local/bin").to_owned(),
-                Path::new("/usr/sbin").to_owned(),
-                Path::new("/usr/bin").to_owned(),
-                Path::new("/sbin:/bin").to_owned(),
-            ],
+            paths: Self::default_paths(),
+            config_file: None,
+            unit_directories: vec![],
+            kind_directories: BTreeMap::new(),
+            added_directories: vec![],
+            state_directory: None,
+            state_directory_setting: None,
+            disabled_units: BTreeSet::new(),
+            calibrations: BTreeMap::new(),
+            counters: BTreeMap::new(),
+            allocations: BTreeMap::new(),
+            maintenance: None,
+            statistics: FlakinessTracker::new(),
+            durations: DurationHistory::new(),
+            station: Station::from_hostname(),
+            log_directory: None,
+            run_directory: Rc::new(RefCell::new(None)),
+            run_serial: Rc::new(RefCell::new(None)),
+            sanitize_output: false,
+            secrets_file: None,
+            redactions: vec![],
+            fetch_cache: None,
+            agents: BTreeMap::new(),
+            agent_key_file: None,
+            concurrency_limits: BTreeMap::new(),
+            lock_directory: None,
+            container_runtime: None,
+            upload_command: None,
+            upload_interval: None,
+            rescan_delay: Duration::from_secs(DEFAULT_RESCAN_DELAY_SECS),
+            result_sinks: vec![],
+            result_signing_key: None,
+            upload_rate_limit: None,
+            log_retention: None,
+            retention_export: false,
+            minimum_free_space: None,
+            certificate_template: None,
+            certificate_renderer: None,
+            certificate_printer: None,
+            clock: Self::default_clock_settings(),
+            multiple_jigs: false,
+            hide_incompatible_scenarios: false,
+            lazy_tests: false,
+            strict_durations: false,
+            flakiness_window: DEFAULT_FLAKINESS_WINDOW,
+            flakiness_threshold: DEFAULT_FLAKINESS_THRESHOLD,
+            metrics_address: None,
+            time: Clock::system(),
         }
     }
 
+    fn default_clock_settings() -> ClockSettings {
+        ClockSettings {
+            not_before: None,
+            reference: None,
+            max_skew: Duration::from_secs(DEFAULT_CLOCK_MAX_SKEW_SECS),
+            require_ntp: false,
+            interval: Duration::from_secs(DEFAULT_CLOCK_CHECK_INTERVAL_SECS),
+        }
+    }
+
+    /// The kind of unit that a setting such as "TestDirectory" is for.
+    fn directory_kind(key: &str) -> Option<UnitKind> {
+        key.strip_suffix("Directory")
+            .filter(|kind| kind.starts_with(char::is_uppercase))
+            .and_then(|kind| UnitKind::from_suffix(&kind.to_lowercase()))
+    }
+
+    /// The setting that lists directories for a kind of unit, without the
+    /// "Directory", such as "Test".
+    fn kind_setting(kind: &UnitKind) -> String {
+        let kind = kind.to_string();
+        kind[..1].to_uppercase() + &kind[1..]
+    }
+
+    fn default_paths() -> Vec<PathBuf> {
+        vec![
+            Path::new("/usr/local/sbin").to_owned(),
+            Path::new("/usr/local/bin").to_owned(),
+            Path::new("/usr/sbin").to_owned(),
+            Path::new("/usr/bin").to_owned(),
+            Path::new("/sbin:/bin").to_owned(),
+        ]
+    }
+
     pub fn timeout(&self) -> &Duration {
         &self.timeout
     }
@@ -75,6 +416,15 @@ impl Config {
         *self.jig_working_directory.borrow_mut() = None;
     }
 
+    /// Note which jig tests are being run against, or that there isn't one.
+    pub fn set_bound_jig(&self, jig: Option<&UnitName>) {
+        *self.bound_jig.borrow_mut() = jig.cloned();
+    }
+
+    pub fn bound_jig(&self) -> Option<UnitName> {
+        self.bound_jig.borrow().clone()
+    }
+
     pub fn set_scenario_working_directory(&self, new_path: &Path) {
         *self.scenario_working_directory.borrow_mut() = Some(new_path.to_owned());
     }
@@ -82,4 +432,1350 @@ impl Config {
     pub fn clear_scenario_working_directory(&self) {
         *self.scenario_working_directory.borrow_mut() = None;
     }
+
+    /// Begin a new run of the given scenario.  If there is a log directory,
+    /// create a directory named "<unix-time>-<scenario>" under it to hold the
+    /// output of each test, and return its path.  The station's identity is
+    /// copied into it, so the run can be traced back to the station that made
+    /// it wherever its logs end up.
+    pub fn start_run(&self, scenario: &UnitName) -> io::Result<Option<PathBuf>> {
+        *self.run_directory.borrow_mut() = None;
+        *self.run_serial.borrow_mut() = None;
+        let log_directory = match self.log_directory {
+            Some(ref l) => l,
+            None => return Ok(None),
+        };
+        fs::create_dir_all(log_directory)?;
+
+        let secs = self
+            .time
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        // Namespaced scenarios still get a single directory.
+        let run_id = format!("{}-{}", secs, scenario.id().replace('/', "-"));
+        let mut run_directory = log_directory.join(&run_id);
+        let mut attempt = 1;
+        loop {
+            match fs::create_dir(&run_directory) {
+                Ok(()) => break,
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    attempt += 1;
+                    run_directory = log_directory.join(format!("{}-{}", run_id, attempt));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        fs::write(
+            run_directory.join(station::STATION_FILE),
+            self.station.file_contents(),
+        )?;
+        *self.run_directory.borrow_mut() = Some(run_directory.clone());
+        Ok(Some(run_directory))
+    }
+
+    /// Directory that each run's directory is made in, if any.
+    pub fn log_directory(&self) -> Option<&Path> {
+        self.log_directory.as_deref()
+    }
+
+    /// Directory for test output from the current run, if any.
+    pub fn run_directory(&self) -> Option<PathBuf> {
+        self.run_directory.borrow().clone()
+    }
+
+    /// Note a serial number that a test reported.  The first one reported
+    /// in a run is the run's.
+    pub fn report_serial(&self, serial: &str) {
+        self.run_serial
+            .borrow_mut()
+            .get_or_insert_with(|| serial.to_owned());
+    }
+
+    /// What each specifier stands for in a command that a unit is starting.
+    pub fn specifiers(&self, unit: &UnitName) -> Specifiers {
+        Specifiers {
+            unit: unit.to_string(),
+            jig: self.bound_jig().map(|jig| jig.id().clone()),
+            serial: self.run_serial.borrow().clone(),
+            run_id: self.run_directory().and_then(|run| {
+                run.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            }),
+            state_directory: self
+                .state_directory()
+                .map(|dir| dir.to_string_lossy().into_owned()),
+        }
+    }
+
+    pub fn sanitize_output(&self) -> bool {
+        self.sanitize_output
+    }
+
+    /// The rules applied to test output before it goes anywhere.
+    pub fn redactions(&self) -> &[Redaction] {
+        &self.redactions
+    }
+
+    pub fn secrets_file(&self) -> Option<&Path> {
+        self.secrets_file.as_deref()
+    }
+
+    /// The secrets that unit commands may refer to, read afresh from the
+    /// secrets file so that changes to it take effect straight away.
+    pub fn secrets(&self) -> Result<Secrets, SecretError> {
+        Secrets::load(self.secrets_file())
+    }
+
+    /// Fill in the secrets a command refers to.
+    pub fn resolve_secrets(&self, text: &str) -> Result<Resolved, SecretError> {
+        secrets::resolve(self.secrets_file(), text)
+    }
+
+    /// Fill in the specifiers and secrets in a command a unit is starting,
+    /// and put it in the form Runny runs: split into arguments, or handed to
+    /// the shell whole.
+    pub fn resolve_command(
+        &self,
+        unit: &UnitName,
+        command: &str,
+        shell: bool,
+    ) -> Result<Resolved, SecretError> {
+        argv::resolve(self.secrets_file(), command, shell, &self.specifiers(unit))
+    }
+
+    /// Where files that tests Fetch are kept, creating it if need be: the
+    /// FetchCache setting, or a directory in the state directory.
+    pub fn fetch_cache(&self) -> io::Result<Option<PathBuf>> {
+        if let Some(ref dir) = self.fetch_cache {
+            fs::create_dir_all(dir)?;
+            return Ok(Some(dir.clone()));
+        }
+        Ok(self
+            .create_state_directory()?
+            .map(|state_directory| state_directory.join(fetch::FETCH_DIRECTORY)))
+    }
+
+    /// The address of an agent, from its Agent setting.
+    pub fn agent_address(&self, name: &str) -> Option<&String> {
+        self.agents.get(name)
+    }
+
+    /// The key shared with agents, read afresh so that it can be changed
+    /// without a restart.
+    pub fn agent_key(&self) -> Result<Vec<u8>, String> {
+        match self.agent_key_file {
+            Some(ref path) => agent::read_key(path),
+            None => Err("no AgentKeyFile is set".to_owned()),
+        }
+    }
+
+    /// How many tests of a ConcurrencyGroup may run at once.
+    pub fn concurrency_limit(&self, group: &str) -> u32 {
+        self.concurrency_limits
+            .get(group)
+            .cloned()
+            .unwrap_or(concurrency::DEFAULT_LIMIT)
+    }
+
+    /// Where the slots for each ConcurrencyGroup are kept.
+    pub fn lock_directory(&self) -> PathBuf {
+        self.lock_directory
+            .clone()
+            .unwrap_or_else(concurrency::default_lock_directory)
+    }
+
+    /// The program that runs tests that name a Container.
+    pub fn container_runtime(&self) -> &str {
+        self.container_runtime
+            .as_deref()
+            .unwrap_or(container::DEFAULT_RUNTIME)
+    }
+
+    pub fn multiple_jigs(&self) -> bool {
+        self.multiple_jigs
+    }
+
+    /// True if scenarios that can't be selected are left out of listings.
+    pub fn hide_incompatible_scenarios(&self) -> bool {
+        self.hide_incompatible_scenarios
+    }
+
+    /// True if test files are only parsed once they're needed.
+    pub fn lazy_tests(&self) -> bool {
+        self.lazy_tests
+    }
+
+    /// True if durations in unit files must have a unit, such as "90s".
+    pub fn strict_durations(&self) -> bool {
+        self.strict_durations
+    }
+
+    pub fn flakiness_window(&self) -> usize {
+        self.flakiness_window
+    }
+
+    pub fn flakiness_threshold(&self) -> u32 {
+        self.flakiness_threshold
+    }
+
+    /// The address to serve timings from.  Only read at startup.
+    pub fn metrics_address(&self) -> Option<&String> {
+        self.metrics_address.as_ref()
+    }
+
+    /// How long unit files must go unchanged before they're rescanned, so
+    /// that a burst of changes is only rescanned once.
+    pub fn rescan_delay(&self) -> Duration {
+        self.rescan_delay
+    }
+
+    /// How finished runs should be shipped, if at all.
+    pub fn upload_settings(&self) -> Option<UploadSettings> {
+        self.upload_command.as_ref().map(|command| UploadSettings {
+            command: command.clone(),
+            interval: self.upload_interval,
+            rate_limit: self.upload_rate_limit,
+        })
+    }
+
+    /// How old runs should be deleted from the LogDirectory, if at all.
+    pub fn retention_settings(&self) -> Option<RetentionSettings> {
+        let log_directory = self.log_directory.as_ref()?;
+        if self.log_retention.is_none() && self.minimum_free_space.is_none() {
+            return None;
+        }
+        Some(RetentionSettings {
+            log_directory: log_directory.clone(),
+            max_age: self.log_retention,
+            export: self.retention_export,
+            minimum_free_space: self.minimum_free_space,
+        })
+    }
+
+    /// How passing runs' certificates should be made, if at all.
+    pub fn certificate_settings(&self) -> Option<CertificateSettings> {
+        self.certificate_template
+            .as_ref()
+            .map(|template| CertificateSettings {
+                template: template.clone(),
+                renderer: self.certificate_renderer.clone(),
+                printer: self.certificate_printer.clone(),
+            })
+    }
+
+    /// Everywhere the results of each run should be stored.
+    pub fn result_sinks(&self) -> &Vec<SinkSpec> {
+        &self.result_sinks
+    }
+
+    /// The key to sign result records with, if they're to be signed, read
+    /// afresh from the secrets it refers to.
+    pub fn result_signing_key(&self) -> Result<Option<SigningKey>, String> {
+        let reference = match self.result_signing_key {
+            Some(ref reference) => reference,
+            None => return Ok(None),
+        };
+        let resolved = self.resolve_secrets(reference).map_err(|e| e.to_string())?;
+        SigningKey::from_hex(&resolved.text)
+            .map(Some)
+            .ok_or_else(|| "ResultSigningKey isn't an Ed25519 seed of 64 hex digits".to_owned())
+    }
+
+    /// Where scenarios and tests get the time from.
+    pub fn clock(&self) -> &Clock {
+        &self.time
+    }
+
+    /// Time scenarios and tests with a different clock, such as a fake one.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.time = clock;
+    }
+
+    pub fn clock_settings(&self) -> ClockSettings {
+        self.clock.clone()
+    }
+
+    pub fn config_file(&self) -> &Option<PathBuf> {
+        &self.config_file
+    }
+
+    pub fn unit_directories(&self) -> &Vec<PathBuf> {
+        &self.unit_directories
+    }
+
+    /// Look for units of every kind in another directory, as with "-c".
+    pub fn add_unit_directory(&mut self, dir: &Path) {
+        self.added_directories.push((dir.to_owned(), None));
+    }
+
+    /// Look for units of just one kind in another directory, as with
+    /// "--tests-dir".  Other kinds of unit in it are ignored.
+    pub fn add_kind_directory(&mut self, kind: UnitKind, dir: &Path) {
+        self.added_directories.push((dir.to_owned(), Some(kind)));
+    }
+
+    /// The directories that only hold units of the given kind.
+    pub fn kind_directories(&self, kind: &UnitKind) -> Vec<PathBuf> {
+        self.search_paths()
+            .into_iter()
+            .filter(|(_, dir_kind)| dir_kind.as_ref() == Some(kind))
+            .map(|(dir, _)| dir)
+            .collect()
+    }
+
+    /// Every directory to look for units in, along with the kind of unit it's
+    /// limited to, if any.  Directories from the command line come first, then
+    /// the UnitDirectory, and then the directories for each kind.  A directory
+    /// is only listed once for each kind.
+    pub fn search_paths(&self) -> Vec<(PathBuf, Option<UnitKind>)> {
+        let added = self
+            .added_directories
+            .iter()
+            .filter(|(_, kind)| kind.is_none())
+            .cloned();
+        let unit_directories = self.unit_directories.iter().map(|dir| (dir.clone(), None));
+        let added_kinds = self
+            .added_directories
+            .iter()
+            .filter(|(_, kind)| kind.is_some())
+            .cloned();
+        let kind_directories = self.kind_directories.iter().flat_map(|(kind, dirs)| {
+            dirs.iter()
+                .map(move |dir| (dir.clone(), Some(kind.clone())))
+        });
+
+        let mut paths: Vec<(PathBuf, Option<UnitKind>)> = vec![];
+        for path in added
+            .chain(unit_directories)
+            .chain(added_kinds)
+            .chain(kind_directories)
+        {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+
+    /// The settings in effect, written out as a config file, with defaults
+    /// filled in and directories from the command line included.  Durations
+    /// are given in milliseconds, so that they're read back exactly.
+    pub fn effective(&self) -> String {
+        let millis = |d: &Duration| format!("{}ms", d.as_millis());
+        let display = |p: &Path| p.display().to_string();
+        let mut settings: Vec<(String, String)> = vec![
+            ("Timeout".to_owned(), millis(&self.timeout)),
+            (
+                "TerminateTimeout".to_owned(),
+                millis(&self.terminate_timeout),
+            ),
+            (
+                "Path".to_owned(),
+                self.paths
+                    .iter()
+                    .map(|p| display(p))
+                    .collect::<Vec<String>>()
+                    .join(":"),
+            ),
+        ];
+        for (dir, kind) in self.search_paths() {
+            let setting = match kind {
+                Some(kind) => format!("{}Directory", Self::kind_setting(&kind)),
+                None => "UnitDirectory".to_owned(),
+            };
+            settings.push((setting, display(&dir)));
+        }
+        let optional_paths = [
+            ("StateDirectory", &self.state_directory),
+            ("LogDirectory", &self.log_directory),
+            ("SecretsFile", &self.secrets_file),
+            ("FetchCache", &self.fetch_cache),
+            ("AgentKeyFile", &self.agent_key_file),
+            ("LockDirectory", &self.lock_directory),
+        ];
+        for (setting, path) in &optional_paths {
+            if let Some(ref path) = path {
+                settings.push((setting.to_string(), display(path)));
+            }
+        }
+        let yes_no = |b: bool| if b { "yes" } else { "no" }.to_owned();
+        settings.push(("SanitizeOutput".to_owned(), yes_no(self.sanitize_output)));
+        for redaction in &self.redactions {
+            settings.push(("Redact".to_owned(), redaction.to_string()));
+        }
+        for (name, address) in &self.agents {
+            settings.push(("Agent".to_owned(), format!("{} {}", name, address)));
+        }
+        for (group, limit) in &self.concurrency_limits {
+            settings.push((
+                "ConcurrencyLimit".to_owned(),
+                format!("{} {}", group, limit),
+            ));
+        }
+        settings.push((
+            "ContainerRuntime".to_owned(),
+            self.container_runtime().to_owned(),
+        ));
+        if let Some(ref command) = self.upload_command {
+            settings.push(("UploadCommand".to_owned(), command.clone()));
+        }
+        if let Some(ref interval) = self.upload_interval {
+            settings.push(("UploadInterval".to_owned(), millis(interval)));
+        }
+        if let Some(rate) = self.upload_rate_limit {
+            settings.push(("UploadRateLimit".to_owned(), rate.to_string()));
+        }
+        if let Some(ref retention) = self.log_retention {
+            settings.push(("LogRetention".to_owned(), millis(retention)));
+        }
+        settings.push((
+            "RetentionExport".to_owned(),
+            if self.retention_export { "yes" } else { "no" }.to_owned(),
+        ));
+        if let Some(space) = self.minimum_free_space {
+            settings.push(("MinimumFreeSpace".to_owned(), space.to_string()));
+        }
+        settings.push(("RescanDelay".to_owned(), millis(&self.rescan_delay)));
+        if let Some(ref template) = self.certificate_template {
+            settings.push(("CertificateTemplate".to_owned(), display(template)));
+        }
+        if let Some(ref command) = self.certificate_renderer {
+            settings.push(("CertificateRenderer".to_owned(), command.clone()));
+        }
+        if let Some(ref command) = self.certificate_printer {
+            settings.push(("CertificatePrinter".to_owned(), command.clone()));
+        }
+        for sink in &self.result_sinks {
+            settings.push(("ResultSink".to_owned(), sink.to_string()));
+        }
+        if let Some(ref reference) = self.result_signing_key {
+            settings.push(("ResultSigningKey".to_owned(), reference.clone()));
+        }
+        if let Some(ref address) = self.metrics_address {
+            settings.push(("MetricsAddress".to_owned(), address.clone()));
+        }
+        if let Some(not_before) = self.clock.not_before {
+            settings.push((
+                "ClockNotBefore".to_owned(),
+                humantime::format_rfc3339_seconds(not_before).to_string(),
+            ));
+        }
+        if let Some(ref reference) = self.clock.reference {
+            settings.push(("ClockReference".to_owned(), reference.clone()));
+        }
+        settings.push(("ClockMaxSkew".to_owned(), millis(&self.clock.max_skew)));
+        settings.push(("ClockRequireNtp".to_owned(), yes_no(self.clock.require_ntp)));
+        settings.push((
+            "ClockCheckInterval".to_owned(),
+            millis(&self.clock.interval),
+        ));
+        settings.push(("MultipleJigs".to_owned(), yes_no(self.multiple_jigs)));
+        settings.push((
+            "HideIncompatibleScenarios".to_owned(),
+            yes_no(self.hide_incompatible_scenarios),
+        ));
+        settings.push(("LazyTests".to_owned(), yes_no(self.lazy_tests)));
+        settings.push(("StrictDurations".to_owned(), yes_no(self.strict_durations)));
+        settings.push((
+            "FlakinessWindow".to_owned(),
+            self.flakiness_window.to_string(),
+        ));
+        settings.push((
+            "FlakinessThreshold".to_owned(),
+            format!("{}%", self.flakiness_threshold),
+        ));
+
+        let mut contents = "[Exclave]\n".to_owned();
+        for (setting, value) in settings {
+            contents.push_str(&format!("{}={}\n", setting, value));
+        }
+        contents
+    }
+
+    /// Load settings from a config file, and remember it so it can be reloaded later.
+    pub fn load_file(&mut self, path: &Path) -> Result<(), UnitDescriptionError> {
+        self.config_file = Some(path.to_owned());
+        self.read_file(path, true).map(|_| ())
+    }
+
+    /// Re-read the config file and apply any settings that may be changed while
+    /// running.  Returns a list of settings that changed, including the ones that
+    /// won't take effect until exclave is restarted.
+    pub fn reload(&mut self) -> Result<ConfigChange, UnitDescriptionError> {
+        match self.config_file.clone() {
+            Some(path) => self.read_file(&path, false),
+            None => Ok(ConfigChange::default()),
+        }
+    }
+
+    fn read_file(
+        &mut self,
+        path: &Path,
+        initial: bool,
+    ) -> Result<ConfigChange, UnitDescriptionError> {
+        let mut contents = String::with_capacity(8192);
+        File::open(path)?.read_to_string(&mut contents)?;
+        let config_file = unitfile::parse(&contents)?;
+
+        if !config_file.has_category("Exclave") {
+            return Err(UnitDescriptionError::MissingSection("Exclave".to_owned()));
+        }
+
+        // Settings that are absent from the file revert to their defaults.
+        let mut timeout = Duration::from_secs(DEFAULT_TIMEOUT_SECS);
+        let mut terminate_timeout = Duration::from_secs(DEFAULT_TIMEOUT_SECS);
+        let mut paths = Self::default_paths();
+        let mut unit_directories = vec![];
+        let mut kind_directories: BTreeMap<UnitKind, Vec<PathBuf>> = BTreeMap::new();
+        let mut state_directory = None;
+        let mut log_directory = None;
+        let mut sanitize_output = false;
+        let mut secrets_file = None;
+        let mut redactions = vec![];
+        let mut fetch_cache = None;
+        let mut agents = BTreeMap::new();
+        let mut agent_key_file = None;
+        let mut concurrency_limits = BTreeMap::new();
+        let mut lock_directory = None;
+        let mut container_runtime = None;
+        let mut upload_command = None;
+        let mut upload_interval = None;
+        let mut rescan_delay = Duration::from_secs(DEFAULT_RESCAN_DELAY_SECS);
+        let mut upload_rate_limit = None;
+        let mut log_retention = None;
+        let mut retention_export = false;
+        let mut minimum_free_space = None;
+        let mut certificate_template = None;
+        let mut certificate_renderer = None;
+        let mut certificate_printer = None;
+        let mut clock = Self::default_clock_settings();
+        let mut result_sinks = vec![];
+        let mut result_signing_key = None;
+        let mut multiple_jigs = false;
+        let mut hide_incompatible_scenarios = false;
+        let mut lazy_tests = false;
+        let mut flakiness_window = DEFAULT_FLAKINESS_WINDOW;
+        let mut flakiness_threshold = DEFAULT_FLAKINESS_THRESHOLD;
+        let mut metrics_address = None;
+
+        // Found first, since it decides how the other durations are read.
+        let strict_durations = match config_file.lookup_by_key("StrictDurations") {
+            Some(DirectiveEntry::Solo(directive)) => {
+                parse_bool("Exclave", "StrictDurations", directive.value())?
+            }
+            _ => false,
+        };
+        let parse_time = |value: &str| {
+            if strict_durations {
+                duration::parse_strict(value)
+            } else {
+                duration::parse(value)
+            }
+        };
+
+        // A setting given more than once comes back as a single Many entry.
+        let directives = config_file
+            .lookup_by_category("Exclave")
+            .into_iter()
+            .flat_map(|entry| match entry {
+                DirectiveEntry::Solo(directive) => vec![directive],
+                DirectiveEntry::Many(directives) => directives.iter().collect(),
+            });
+        for directive in directives {
+            let value = match directive.value() {
+                Some(s) => s,
+                None => {
+                    return Err(UnitDescriptionError::MissingValue(
+                        "Exclave".to_owned(),
+                        directive.key().to_owned(),
+                    ))
+                }
+            };
+            match directive.key() {
+                "Timeout" => timeout = parse_time(value)?,
+                "TerminateTimeout" => terminate_timeout = parse_time(value)?,
+                "Path" => paths = value.split(':').map(PathBuf::from).collect(),
+                "UnitDirectory" => unit_directories = value.split(':').map(PathBuf::from).collect(),
+                "StateDirectory" => state_directory = Some(PathBuf::from(value)),
+                key if Self::directory_kind(key).is_some() => kind_directories
+                    .entry(Self::directory_kind(key).unwrap())
+                    .or_default()
+                    .extend(value.split(':').map(PathBuf::from)),
+                "LogDirectory" => log_directory = Some(PathBuf::from(value)),
+                "SanitizeOutput" => {
+                    sanitize_output = parse_bool("Exclave", "SanitizeOutput", Some(value))?
+                }
+                "SecretsFile" => secrets_file = Some(PathBuf::from(value)),
+                // May be given more than once, and applied in order.
+                "Redact" => redactions.push(value.parse().map_err(|_| {
+                    UnitDescriptionError::InvalidValue(
+                        "Exclave".to_owned(),
+                        "Redact".to_owned(),
+                        value.to_owned(),
+                        Redaction::forms(),
+                    )
+                })?),
+                "FetchCache" => fetch_cache = Some(PathBuf::from(value)),
+                // May be given more than once, once for each agent.
+                "Agent" => match value.split_once(char::is_whitespace) {
+                    Some((name, address)) if !address.trim().is_empty() => {
+                        agents.insert(name.to_owned(), address.trim().to_owned());
+                    }
+                    _ => {
+                        return Err(UnitDescriptionError::InvalidValue(
+                            "Exclave".to_owned(),
+                            "Agent".to_owned(),
+                            value.to_owned(),
+                            vec!["<name> <host>:<port>".to_owned()],
+                        ))
+                    }
+                },
+                "AgentKeyFile" => agent_key_file = Some(PathBuf::from(value)),
+                // May be given more than once, once for each group.
+                "ConcurrencyLimit" => {
+                    let limit = value
+                        .split_once(char::is_whitespace)
+                        .filter(|(group, _)| concurrency::valid_group(group))
+                        .and_then(|(group, limit)| match limit.trim().parse::<u32>() {
+                            Ok(limit) if limit > 0 => Some((group, limit)),
+                            _ => None,
+                        });
+                    match limit {
+                        Some((group, limit)) => {
+                            concurrency_limits.insert(group.to_owned(), limit);
+                        }
+                        None => {
+                            return Err(UnitDescriptionError::InvalidValue(
+                                "Exclave".to_owned(),
+                                "ConcurrencyLimit".to_owned(),
+                                value.to_owned(),
+                                vec!["<group> <number of tests, at least 1>".to_owned()],
+                            ))
+                        }
+                    }
+                }
+                "LockDirectory" => lock_directory = Some(PathBuf::from(value)),
+                "ContainerRuntime" => container_runtime = Some(value.to_owned()),
+                "UploadCommand" => upload_command = Some(value.to_owned()),
+                "UploadInterval" => upload_interval = Some(parse_time(value)?),
+                "RescanDelay" => rescan_delay = parse_time(value)?,
+                "UploadRateLimit" => upload_rate_limit = Some(Self::parse_size(value)?),
+                "LogRetention" => log_retention = Some(parse_time(value)?),
+                "RetentionExport" => {
+                    retention_export = parse_bool("Exclave", "RetentionExport", Some(value))?
+                }
+                "MinimumFreeSpace" => minimum_free_space = Some(Self::parse_size(value)?),
+                "CertificateTemplate" => certificate_template = Some(PathBuf::from(value)),
+                "CertificateRenderer" => certificate_renderer = Some(value.to_owned()),
+                "CertificatePrinter" => certificate_printer = Some(value.to_owned()),
+                // May be given more than once, to store results in several places.
+                "ResultSink" => result_sinks.push(value.parse().map_err(|_| {
+                    UnitDescriptionError::InvalidValue(
+                        "Exclave".to_owned(),
+                        "ResultSink".to_owned(),
+                        value.to_owned(),
+                        SinkSpec::forms(),
+                    )
+                })?),
+                // Only ever a reference, so the key isn't kept with the settings.
+                "ResultSigningKey" if secrets::has_references(value) => {
+                    result_signing_key = Some(value.to_owned())
+                }
+                "ResultSigningKey" => {
+                    return Err(UnitDescriptionError::InvalidValue(
+                        "Exclave".to_owned(),
+                        "ResultSigningKey".to_owned(),
+                        value.to_owned(),
+                        vec!["a reference to a secret, such as ${secret:results-key}".to_owned()],
+                    ))
+                }
+                "ClockNotBefore" => {
+                    clock.not_before = Some(parse_rfc3339_weak(value).map_err(|_| {
+                        UnitDescriptionError::InvalidValue(
+                            "Exclave".to_owned(),
+                            "ClockNotBefore".to_owned(),
+                            value.to_owned(),
+                            vec!["YYYY-MM-DD HH:MM:SS".to_owned()],
+                        )
+                    })?)
+                }
+                "ClockReference" => clock.reference = Some(value.to_owned()),
+                "ClockMaxSkew" => clock.max_skew = parse_time(value)?,
+                "ClockRequireNtp" => {
+                    clock.require_ntp = parse_bool("Exclave", "ClockRequireNtp", Some(value))?
+                }
+                "ClockCheckInterval" => clock.interval = parse_time(value)?,
+                "MultipleJigs" => {
+                    multiple_jigs = parse_bool("Exclave", "MultipleJigs", Some(value))?
+                }
+                "HideIncompatibleScenarios" => {
+                    hide_incompatible_scenarios =
+                        parse_bool("Exclave", "HideIncompatibleScenarios", Some(value))?
+                }
+                "LazyTests" => lazy_tests = parse_bool("Exclave", "LazyTests", Some(value))?,
+                "MetricsAddress" => metrics_address = Some(value.to_owned()),
+                "FlakinessWindow" => match value.parse() {
+                    Ok(runs) if runs >= 2 => flakiness_window = runs,
+                    _ => {
+                        return Err(UnitDescriptionError::InvalidValue(
+                            "Exclave".to_owned(),
+                            "FlakinessWindow".to_owned(),
+                            value.to_owned(),
+                            vec!["a number of runs, at least 2".to_owned()],
+                        ))
+                    }
+                },
+                "FlakinessThreshold" => match value.trim_end_matches('%').trim().parse() {
+                    Ok(percent) if percent <= 100 => flakiness_threshold = percent,
+                    _ => {
+                        return Err(UnitDescriptionError::InvalidValue(
+                            "Exclave".to_owned(),
+                            "FlakinessThreshold".to_owned(),
+                            value.to_owned(),
+                            vec!["a percentage from 0 to 100".to_owned()],
+                        ))
+                    }
+                },
+                &_ => (),
+            }
+        }
+        // Runs can only be exported somewhere if there's somewhere to send them.
+        if retention_export && upload_command.is_none() {
+            return Err(UnitDescriptionError::InvalidValue(
+                "Exclave".to_owned(),
+                "RetentionExport".to_owned(),
+                "yes".to_owned(),
+                vec!["no, unless UploadCommand is set".to_owned()],
+            ));
+        }
+
+        let mut change = ConfigChange::default();
+        if timeout != self.timeout {
+            self.timeout = timeout;
+            change.applied.push("Timeout".to_owned());
+        }
+        if terminate_timeout != self.terminate_timeout {
+            self.terminate_timeout = terminate_timeout;
+            change.applied.push("TerminateTimeout".to_owned());
+        }
+        if paths != self.paths {
+            self.paths = paths;
+            change.applied.push("Path".to_owned());
+        }
+        if log_directory != self.log_directory {
+            self.log_directory = log_directory;
+            change.applied.push("LogDirectory".to_owned());
+        }
+        if sanitize_output != self.sanitize_output {
+            self.sanitize_output = sanitize_output;
+            change.applied.push("SanitizeOutput".to_owned());
+        }
+        if secrets_file != self.secrets_file {
+            self.secrets_file = secrets_file;
+            change.applied.push("SecretsFile".to_owned());
+        }
+        if redactions != self.redactions {
+            self.redactions = redactions;
+            change.applied.push("Redact".to_owned());
+        }
+        if fetch_cache != self.fetch_cache {
+            self.fetch_cache = fetch_cache;
+            change.applied.push("FetchCache".to_owned());
+        }
+        if agents != self.agents {
+            self.agents = agents;
+            change.applied.push("Agent".to_owned());
+        }
+        if agent_key_file != self.agent_key_file {
+            self.agent_key_file = agent_key_file;
+            change.applied.push("AgentKeyFile".to_owned());
+        }
+        if concurrency_limits != self.concurrency_limits {
+            self.concurrency_limits = concurrency_limits;
+            change.applied.push("ConcurrencyLimit".to_owned());
+        }
+        if lock_directory != self.lock_directory {
+            self.lock_directory = lock_directory;
+            change.applied.push("LockDirectory".to_owned());
+        }
+        if container_runtime != self.container_runtime {
+            self.container_runtime = container_runtime;
+            change.applied.push("ContainerRuntime".to_owned());
+        }
+        if upload_command != self.upload_command {
+            self.upload_command = upload_command;
+            change.applied.push("UploadCommand".to_owned());
+        }
+        if upload_interval != self.upload_interval {
+            self.upload_interval = upload_interval;
+            change.applied.push("UploadInterval".to_owned());
+        }
+        if upload_rate_limit != self.upload_rate_limit {
+            self.upload_rate_limit = upload_rate_limit;
+            change.applied.push("UploadRateLimit".to_owned());
+        }
+        if log_retention != self.log_retention {
+            self.log_retention = log_retention;
+            change.applied.push("LogRetention".to_owned());
+        }
+        if retention_export != self.retention_export {
+            self.retention_export = retention_export;
+            change.applied.push("RetentionExport".to_owned());
+        }
+        if minimum_free_space != self.minimum_free_space {
+            self.minimum_free_space = minimum_free_space;
+            change.applied.push("MinimumFreeSpace".to_owned());
+        }
+        if hide_incompatible_scenarios != self.hide_incompatible_scenarios {
+            self.hide_incompatible_scenarios = hide_incompatible_scenarios;
+            change.applied.push("HideIncompatibleScenarios".to_owned());
+        }
+        if certificate_template != self.certificate_template {
+            self.certificate_template = certificate_template;
+            change.applied.push("CertificateTemplate".to_owned());
+        }
+        if certificate_renderer != self.certificate_renderer {
+            self.certificate_renderer = certificate_renderer;
+            change.applied.push("CertificateRenderer".to_owned());
+        }
+        if certificate_printer != self.certificate_printer {
+            self.certificate_printer = certificate_printer;
+            change.applied.push("CertificatePrinter".to_owned());
+        }
+        if rescan_delay != self.rescan_delay {
+            self.rescan_delay = rescan_delay;
+            change.applied.push("RescanDelay".to_owned());
+        }
+        if result_sinks != self.result_sinks {
+            self.result_sinks = result_sinks;
+            change.applied.push("ResultSink".to_owned());
+        }
+        if result_signing_key != self.result_signing_key {
+            self.result_signing_key = result_signing_key;
+            change.applied.push("ResultSigningKey".to_owned());
+        }
+        if strict_durations != self.strict_durations {
+            self.strict_durations = strict_durations;
+            change.applied.push("StrictDurations".to_owned());
+        }
+        if flakiness_window != self.flakiness_window {
+            self.flakiness_window = flakiness_window;
+            change.applied.push("FlakinessWindow".to_owned());
+        }
+        if flakiness_threshold != self.flakiness_threshold {
+            self.flakiness_threshold = flakiness_threshold;
+            change.applied.push("FlakinessThreshold".to_owned());
+        }
+        if clock != self.clock {
+            for (setting, changed) in &[
+                ("ClockNotBefore", clock.not_before != self.clock.not_before),
+                ("ClockReference", clock.reference != self.clock.reference),
+                ("ClockMaxSkew", clock.max_skew != self.clock.max_skew),
+                (
+                    "ClockRequireNtp",
+                    clock.require_ntp != self.clock.require_ntp,
+                ),
+                ("ClockCheckInterval", clock.interval != self.clock.interval),
+            ] {
+                if *changed {
+                    change.applied.push((*setting).to_owned());
+                }
+            }
+            self.clock = clock;
+        }
+
+        // Unit directories are handed to the watcher at startup, runtime
+        // state is only read in once, jigs are only chosen as they load, and
+        // tests left unparsed stay that way, and the metrics listener is
+        // only started once, so changing any of these requires a restart.
+        if initial {
+            self.metrics_address = metrics_address;
+            self.unit_directories = unit_directories;
+            self.kind_directories = kind_directories;
+            self.multiple_jigs = multiple_jigs;
+            self.lazy_tests = lazy_tests;
+            self.state_directory_setting = state_directory.clone();
+            self.set_state_directory(state_directory)?;
+        } else {
+            if multiple_jigs != self.multiple_jigs {
+                change.restart_required.push("MultipleJigs".to_owned());
+            }
+            if lazy_tests != self.lazy_tests {
+                change.restart_required.push("LazyTests".to_owned());
+            }
+            if metrics_address != self.metrics_address {
+                change.restart_required.push("MetricsAddress".to_owned());
+            }
+            if unit_directories != self.unit_directories {
+                change.restart_required.push("UnitDirectory".to_owned());
+            }
+            for kind in kind_directories
+                .keys()
+                .chain(self.kind_directories.keys())
+                .collect::<BTreeSet<_>>()
+            {
+                if kind_directories.get(kind) != self.kind_directories.get(kind) {
+                    change
+                        .restart_required
+                        .push(format!("{}Directory", Self::kind_setting(kind)));
+                }
+            }
+            if state_directory != self.state_directory_setting {
+                change.restart_required.push("StateDirectory".to_owned());
+            }
+        }
+
+        Ok(change)
+    }
+
+    /// The directory where runtime state is kept, if any.
+    pub fn state_directory(&self) -> Option<&Path> {
+        self.state_directory.as_deref()
+    }
+
+    /// Keep runtime state in a different directory, or nowhere, and read in
+    /// whatever state was left there.  This is how "--state-dir" overrides the
+    /// StateDirectory setting.  The directory may not be a unit directory, or
+    /// inside one, since those may be read-only.
+    pub fn set_state_directory(
+        &mut self,
+        state_directory: Option<PathBuf>,
+    ) -> Result<(), UnitDescriptionError> {
+        if let Some(ref dir) = state_directory {
+            let in_unit_directory = self
+                .search_paths()
+                .iter()
+                .any(|(unit_dir, _)| dir.starts_with(unit_dir));
+            if in_unit_directory {
+                return Err(UnitDescriptionError::InvalidValue(
+                    "Exclave".to_owned(),
+                    "StateDirectory".to_owned(),
+                    dir.to_string_lossy().into_owned(),
+                    vec!["a directory outside of every unit directory".to_owned()],
+                ));
+            }
+        }
+        self.state_directory = state_directory;
+        self.create_state_directory()?;
+        self.load_disabled_units()?;
+        self.load_calibrations()?;
+        self.load_counters()?;
+        self.load_allocations()?;
+        self.load_maintenance()?;
+        self.load_statistics()?;
+        self.load_durations()?;
+        self.load_station()?;
+        Ok(())
+    }
+
+    /// Make sure the state directory exists, and return it.  It's created so
+    /// that only the user exclave runs as can get into it.
+    pub fn create_state_directory(&self) -> io::Result<Option<&Path>> {
+        match self.state_directory {
+            Some(ref dir) => {
+                sys::create_private_dir(dir)?;
+                Ok(Some(dir))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns true if the unit was disabled at runtime.
+    pub fn unit_disabled(&self, name: &UnitName) -> bool {
+        self.disabled_units.contains(name)
+    }
+
+    /// Mark a unit as disabled or enabled.  If there is a state directory,
+    /// the list of disabled units is saved there so it survives a restart.
+    pub fn set_unit_disabled(&mut self, name: &UnitName, disabled: bool) -> io::Result<()> {
+        if disabled {
+            self.disabled_units.insert(name.clone());
+        } else {
+            self.disabled_units.remove(name);
+        }
+
+        let state_directory = match self.create_state_directory()? {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let mut contents = String::new();
+        for unit in &self.disabled_units {
+            contents.push_str(&format!("{}\n", unit));
+        }
+        fs::write(state_directory.join(DISABLED_UNITS_FILE), contents)
+    }
+
+    fn load_disabled_units(&mut self) -> Result<(), UnitDescriptionError> {
+        self.disabled_units.clear();
+        let path = match self.state_directory {
+            Some(ref s) => s.join(DISABLED_UNITS_FILE),
+            None => return Ok(()),
+        };
+        if !path.exists() {
+            return Ok(());
+        }
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            self.disabled_units.insert(UnitName::from_str(line, "")?);
+        }
+        Ok(())
+    }
+
+    /// When the jig last passed its calibration scenario, if it ever has.
+    pub fn last_calibration(&self, jig: &UnitName) -> Option<SystemTime> {
+        self.calibrations.get(jig).cloned()
+    }
+
+    /// Record that a jig was calibrated.  If there is a state directory, the
+    /// time is saved there so it survives a restart.
+    pub fn set_last_calibration(&mut self, jig: &UnitName, when: SystemTime) -> io::Result<()> {
+        self.calibrations.insert(jig.clone(), when);
+
+        let state_directory = match self.create_state_directory()? {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let mut contents = String::new();
+        for (jig, when) in &self.calibrations {
+            let secs = when
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            contents.push_str(&format!("{}\t{}\n", jig, secs));
+        }
+        fs::write(state_directory.join(CALIBRATIONS_FILE), contents)
+    }
+
+    fn load_calibrations(&mut self) -> Result<(), UnitDescriptionError> {
+        self.calibrations.clear();
+        let path = match self.state_directory {
+            Some(ref s) => s.join(CALIBRATIONS_FILE),
+            None => return Ok(()),
+        };
+        if !path.exists() {
+            return Ok(());
+        }
+        for line in fs::read_to_string(path)?.lines() {
+            let mut fields = line.trim().splitn(2, '\t');
+            let (jig, secs) = match (fields.next(), fields.next()) {
+                (Some(jig), Some(secs)) => (jig, secs),
+                _ => continue,
+            };
+            let secs = secs.trim().parse::<u64>().map_err(|_| {
+                UnitDescriptionError::InvalidValue(
+                    "Exclave".to_owned(),
+                    CALIBRATIONS_FILE.to_owned(),
+                    line.to_owned(),
+                    vec!["<jig><tab><unix-time>".to_owned()],
+                )
+            })?;
+            self.calibrations.insert(
+                UnitName::from_str(jig, "")?,
+                UNIX_EPOCH + Duration::from_secs(secs),
+            );
+        }
+        Ok(())
+    }
+
+    /// The current count of one of a jig's counters.
+    pub fn counter(&self, jig: &UnitName, name: &str) -> u64 {
+        self.counters
+            .get(&(jig.clone(), name.to_owned()))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Add one to each of the named counters on a jig (a counter named
+    /// twice goes up by two).  If there is a state directory, the counts are
+    /// saved there so they survive a restart.
+    pub fn increment_counters(&mut self, jig: &UnitName, names: &[String]) -> io::Result<()> {
+        for name in names {
+            *self
+                .counters
+                .entry((jig.clone(), name.clone()))
+                .or_insert(0) += 1;
+        }
+        self.save_counters()
+    }
+
+    /// Set a counter back to zero, such as after the part it counts was replaced.
+    pub fn reset_counter(&mut self, jig: &UnitName, name: &str) -> io::Result<()> {
+        self.counters.remove(&(jig.clone(), name.to_owned()));
+        self.save_counters()
+    }
+
+    fn save_counters(&self) -> io::Result<()> {
+        let state_directory = match self.create_state_directory()? {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let mut contents = String::new();
+        for ((jig, name), count) in &self.counters {
+            contents.push_str(&format!("{}\t{}\t{}\n", jig, name, count));
+        }
+        fs::write(state_directory.join(COUNTERS_FILE), contents)
+    }
+
+    fn load_counters(&mut self) -> Result<(), UnitDescriptionError> {
+        self.counters.clear();
+        let path = match self.state_directory {
+            Some(ref s) => s.join(COUNTERS_FILE),
+            None => return Ok(()),
+        };
+        if !path.exists() {
+            return Ok(());
+        }
+        for line in fs::read_to_string(path)?.lines() {
+            let fields: Vec<&str> = line.trim().split('\t').collect();
+            if fields.len() != 3 {
+                continue;
+            }
+            let count = fields[2].parse::<u64>().map_err(|_| {
+                UnitDescriptionError::InvalidValue(
+                    "Exclave".to_owned(),
+                    COUNTERS_FILE.to_owned(),
+                    line.to_owned(),
+                    vec!["<jig><tab><counter><tab><count>".to_owned()],
+                )
+            })?;
+            self.counters.insert(
+                (UnitName::from_str(fields[0], "")?, fields[1].to_owned()),
+                count,
+            );
+        }
+        Ok(())
+    }
+
+    /// The last value an allocator handed out, if it has handed any out.
+    pub fn last_allocation(&self, allocator: &UnitName) -> Option<&String> {
+        self.allocations.get(allocator)
+    }
+
+    /// Note that an allocator has handed out a value.  The file is replaced
+    /// in one step, so a crash can't leave it half-written, and the value
+    /// mustn't be used unless this succeeds.
+    pub fn record_allocation(&mut self, allocator: &UnitName, value: &str) -> io::Result<()> {
+        let previous = self.allocations.insert(allocator.clone(), value.to_owned());
+        let result = self.save_allocations();
+        if result.is_err() {
+            match previous {
+                Some(previous) => self.allocations.insert(allocator.clone(), previous),
+                None => self.allocations.remove(allocator),
+            };
+        }
+        result
+    }
+
+    fn save_allocations(&self) -> io::Result<()> {
+        let state_directory = match self.create_state_directory()? {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let mut contents = String::new();
+        for (allocator, value) in &self.allocations {
+            contents.push_str(&format!("{}\t{}\n", allocator, value));
+        }
+        let path = state_directory.join(ALLOCATIONS_FILE);
+        let temporary = state_directory.join(format!("{}.new", ALLOCATIONS_FILE));
+        let mut file = File::create(&temporary)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(temporary, path)
+    }
+
+    fn load_allocations(&mut self) -> Result<(), UnitDescriptionError> {
+        self.allocations.clear();
+        let path = match self.state_directory {
+            Some(ref s) => s.join(ALLOCATIONS_FILE),
+            None => return Ok(()),
+        };
+        if !path.exists() {
+            return Ok(());
+        }
+        for line in fs::read_to_string(path)?.lines() {
+            let (allocator, value) = match line.trim().split_once('\t') {
+                Some(fields) => fields,
+                None => continue,
+            };
+            self.allocations
+                .insert(UnitName::from_str(allocator, "")?, value.to_owned());
+        }
+        Ok(())
+    }
+
+    /// Why the station is in maintenance mode, or None if it's in production.
+    pub fn maintenance(&self) -> Option<&String> {
+        self.maintenance.as_ref()
+    }
+
+    /// Put the station into maintenance mode for the given reason, or back
+    /// into production with None.  If there is a state directory, the mode
+    /// is saved there so it survives a restart.
+    pub fn set_maintenance(&mut self, reason: Option<String>) -> io::Result<()> {
+        self.maintenance = reason;
+
+        let state_directory = match self.create_state_directory()? {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let path = state_directory.join(MAINTENANCE_FILE);
+        match self.maintenance {
+            Some(ref reason) => fs::write(path, format!("{}\n", reason)),
+            None if path.exists() => fs::remove_file(path),
+            None => Ok(()),
+        }
+    }
+
+    fn load_maintenance(&mut self) -> Result<(), UnitDescriptionError> {
+        self.maintenance = None;
+        if let Some(ref s) = self.state_directory {
+            let path = s.join(MAINTENANCE_FILE);
+            if path.exists() {
+                self.maintenance = Some(fs::read_to_string(path)?.trim().to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    /// The path to a jig's key-value store, or None if there's no state
+    /// directory to keep it in.
+    pub fn store_path(&self, jig: &UnitName) -> io::Result<Option<PathBuf>> {
+        Ok(self
+            .create_state_directory()?
+            .map(|state_directory| store::path(state_directory, jig)))
+    }
+
+    /// Everything in a jig's key-value store.
+    pub fn store(&self, jig: &UnitName) -> io::Result<store::Entries> {
+        match self.state_directory {
+            Some(ref state_directory) => store::read(&store::path(state_directory, jig)),
+            None => Ok(store::Entries::new()),
+        }
+    }
+
+    /// Remove one key from a jig's key-value store, or every key with None,
+    /// and return the keys that were removed.
+    pub fn clear_store(&self, jig: &UnitName, key: Option<&str>) -> io::Result<Vec<String>> {
+        let path = match self.store_path(jig)? {
+            Some(path) => path,
+            None => return Ok(vec![]),
+        };
+        let mut entries = store::read(&path)?;
+        let removed = match key {
+            Some(key) => entries
+                .remove_entry(key)
+                .map(|(k, _)| k)
+                .into_iter()
+                .collect(),
+            None => {
+                let keys = entries.keys().cloned().collect();
+                entries.clear();
+                keys
+            }
+        };
+        store::write(&path, &entries)?;
+        Ok(removed)
+    }
+
+    /// Add the verdicts from a finished run to each test's history, and
+    /// return the tests that have just become flaky.
+    pub fn record_verdicts(&mut self, summary: &ScenarioSummary) -> Vec<FlakyTest> {
+        let (window, threshold) = (self.flakiness_window, self.flakiness_threshold);
+        self.statistics.record(summary, window, threshold)
+    }
+
+    /// How each test has done, sorted by name.
+    pub fn test_stats(&self) -> Vec<TestStats> {
+        self.statistics.stats()
+    }
+
+    /// If there is a state directory, save how each test has done there, so
+    /// flakiness tracking picks up where it left off after a restart.
+    pub fn save_statistics(&self) -> io::Result<()> {
+        let state_directory = match self.create_state_directory()? {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        fs::write(
+            state_directory.join(STATISTICS_FILE),
+            self.statistics.to_state(),
+        )
+    }
+
+    fn load_statistics(&mut self) -> Result<(), UnitDescriptionError> {
+        self.statistics = FlakinessTracker::new();
+        let path = match self.state_directory {
+            Some(ref s) => s.join(STATISTICS_FILE),
+            None => return Ok(()),
+        };
+        if path.exists() {
+            self.statistics = FlakinessTracker::from_state(&fs::read_to_string(path)?)?;
+        }
+        Ok(())
+    }
+
+    /// Add a finished run to its scenario's run times, saving them if it
+    /// was added.
+    pub fn record_duration(&mut self, summary: &ScenarioSummary) -> io::Result<()> {
+        if !self.durations.record(summary) {
+            return Ok(());
+        }
+        let state_directory = match self.create_state_directory()? {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        fs::write(
+            state_directory.join(DURATIONS_FILE),
+            self.durations.to_state(),
+        )
+    }
+
+    /// How long a scenario's recent runs took, if it has been run through.
+    pub fn historical_duration(&self, scenario: &UnitName) -> Option<Duration> {
+        self.durations.estimate(scenario)
+    }
+
+    fn load_durations(&mut self) -> Result<(), UnitDescriptionError> {
+        self.durations = DurationHistory::new();
+        let path = match self.state_directory {
+            Some(ref s) => s.join(DURATIONS_FILE),
+            None => return Ok(()),
+        };
+        if path.exists() {
+            self.durations = DurationHistory::from_state(&fs::read_to_string(path)?)?;
+        }
+        Ok(())
+    }
+
+    /// Which station this is.  Without a state directory, that's just its
+    /// hostname.
+    pub fn station(&self) -> &Station {
+        &self.station
+    }
+
+    fn load_station(&mut self) -> Result<(), UnitDescriptionError> {
+        self.station = match self.state_directory {
+            Some(ref s) => Station::load_or_create(s)?,
+            None => Station::from_hostname(),
+        };
+        Ok(())
+    }
+
+    /// Parse a number of bytes, or of bytes per second, with an optional
+    /// "K", "M", or "G" suffix.
+    fn parse_size(size_str: &str) -> Result<u64, UnitDescriptionError> {
+        let size_str = size_str.trim();
+        let (number, multiplier) = match size_str.chars().last().map(|c| c.to_ascii_uppercase()) {
+            Some('K') => (&size_str[..size_str.len() - 1], 1024),
+            Some('M') => (&size_str[..size_str.len() - 1], 1024 * 1024),
+            Some('G') => (&size_str[..size_str.len() - 1], 1024 * 1024 * 1024),
+            _ => (size_str, 1),
+        };
+        Ok(number.trim().parse::<u64>()? * multiplier)
+    }
 }
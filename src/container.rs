@@ -0,0 +1,227 @@
+// Runs tests inside a container, for vendor test environments whose
+// libraries don't get along with the station's or each other's.  A test
+// names an image, pinned to one exact build by its digest, and what it needs
+// from the station:
+//
+//     Container=registry.example.com/vendor/rf-tools@sha256:9f86d0...
+//     ContainerMount=/opt/calibration:/calibration:ro
+//     ContainerDevice=/dev/ttyACM0
+//
+// ExecStart is then run with "<runtime> run" rather than directly, where the
+// runtime is podman unless ContainerRuntime says otherwise, and can be any
+// program that takes the same arguments as podman and docker.  What it prints
+// and how it exits come back the same as for any other test, since the
+// runtime passes them through.  The test's working directory is mounted at
+// the same path, and is where the command starts, so relative paths work
+// the same inside and out, and the container shares the station's network.
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::str::FromStr;
+
+use sha256;
+use unit::UnitName;
+
+/// The runtime to use if ContainerRuntime isn't set.
+pub const DEFAULT_RUNTIME: &str = "podman";
+
+/// What podman and docker exit with when the container couldn't be run at
+/// all, such as when the image is missing, which is the station's fault.
+pub const RUNTIME_FAILED: i32 = 125;
+
+/// Whether an image reference is pinned to one build by its digest, as
+/// "<name>@sha256:<digest>", so that it can't change under a test.
+pub fn pinned_image(image: &str) -> bool {
+    match image.rsplit_once("@sha256:") {
+        Some((name, digest)) => !name.is_empty() && sha256::valid_digest(digest),
+        None => false,
+    }
+}
+
+/// The forms Container can take, for error messages.
+pub fn image_forms() -> Vec<String> {
+    vec!["<image>@sha256:<hex digest>".to_owned()]
+}
+
+/// A file or directory on the station that the container can see.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    /// Where it is on the station, relative to the test's working directory.
+    host: PathBuf,
+
+    /// Where it is in the container.  The same as on the station if the
+    /// unit file doesn't say.
+    container: Option<PathBuf>,
+
+    read_only: bool,
+}
+
+impl Mount {
+    /// The forms a ContainerMount can take, for error messages.
+    pub fn forms() -> Vec<String> {
+        vec![
+            "<path>".to_owned(),
+            "<path>:<path in container>".to_owned(),
+            "<path>:<path in container>:ro".to_owned(),
+        ]
+    }
+
+    /// The argument to pass to the runtime's --volume, with relative paths
+    /// taken from a directory.
+    fn volume(&self, directory: &Path) -> String {
+        let host = directory.join(&self.host);
+        let container = self.container.as_ref().unwrap_or(&host);
+        format!(
+            "{}:{}{}",
+            host.display(),
+            container.display(),
+            if self.read_only { ":ro" } else { "" }
+        )
+    }
+}
+
+impl FromStr for Mount {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.trim().split(':').collect();
+        let (host, container, read_only) = match parts[..] {
+            [host] => (host, None, false),
+            [host, container] => (host, Some(container), false),
+            [host, container, "ro"] => (host, Some(container), true),
+            _ => return Err(()),
+        };
+        if host.is_empty() || container.map(|c| !c.starts_with('/')).unwrap_or(false) {
+            return Err(());
+        }
+        Ok(Mount {
+            host: PathBuf::from(host),
+            container: container.map(PathBuf::from),
+            read_only,
+        })
+    }
+}
+
+/// A device on the station, such as a serial port, that the container can use.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Device {
+    host: PathBuf,
+
+    /// Where it is in the container, if not at the same path.
+    container: Option<PathBuf>,
+}
+
+impl Device {
+    /// The forms a ContainerDevice can take, for error messages.
+    pub fn forms() -> Vec<String> {
+        vec![
+            "/dev/<device>".to_owned(),
+            "/dev/<device>:<path in container>".to_owned(),
+        ]
+    }
+}
+
+impl FromStr for Device {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, container) = match s.trim().split_once(':') {
+            Some((host, container)) => (host, Some(container)),
+            None => (s.trim(), None),
+        };
+        if !host.starts_with('/') || container.map(|c| !c.starts_with('/')).unwrap_or(false) {
+            return Err(());
+        }
+        Ok(Device {
+            host: PathBuf::from(host),
+            container: container.map(PathBuf::from),
+        })
+    }
+}
+
+/// Where a test runs, if it runs in a container.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Container {
+    pub image: String,
+    pub mounts: Vec<Mount>,
+    pub devices: Vec<Device>,
+}
+
+impl Container {
+    /// The command that runs `cmd` in the container, as a command line for
+    /// Runny.  `directory` is the test's working directory, `files` are any
+    /// other paths on the station it's given in `vars`, such as its store,
+    /// which are made visible at the same path.
+    pub fn command(
+        &self,
+        runtime: &str,
+        name: &str,
+        directory: &Path,
+        files: &[&Path],
+        vars: &[(&str, &str)],
+        cmd: &str,
+    ) -> String {
+        let mut args = vec![
+            runtime.to_owned(),
+            "run".to_owned(),
+            "--rm".to_owned(),
+            "--interactive".to_owned(),
+            format!("--name={}", name),
+            "--network=host".to_owned(),
+            format!("--volume={}:{}", directory.display(), directory.display()),
+            format!("--workdir={}", directory.display()),
+        ];
+        for file in files {
+            args.push(format!("--volume={}:{}", file.display(), file.display()));
+        }
+        for mount in &self.mounts {
+            args.push(format!("--volume={}", mount.volume(directory)));
+        }
+        for device in &self.devices {
+            args.push(match device.container {
+                Some(ref container) => {
+                    format!("--device={}:{}", device.host.display(), container.display())
+                }
+                None => format!("--device={}", device.host.display()),
+            });
+        }
+        for (name, value) in vars {
+            args.push(format!("--env={}={}", name, value));
+        }
+        args.push(self.image.clone());
+        // Quoted for Runny, which splits the command like a shell would.
+        let mut command: Vec<String> = args
+            .iter()
+            .map(|arg| format!("'{}'", arg.replace('\'', "'\"'\"'")))
+            .collect();
+        command.push(cmd.to_owned());
+        command.join(" ")
+    }
+}
+
+/// The name to give the container a test runs in, so that it can be found
+/// again to remove it.  It's unique to this exclave, which only runs each
+/// test once at a time.
+pub fn container_name(test: &UnitName) -> String {
+    let id: String = test
+        .id()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("exclave-{}-{}", process::id(), id)
+}
+
+/// Remove a test's container, in case stopping the runtime left it behind.
+/// It's fine if it's already gone.
+pub fn remove(runtime: &str, name: &str) {
+    Command::new(runtime)
+        .args(["rm", "--force", name])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .ok();
+}
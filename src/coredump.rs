@@ -0,0 +1,198 @@
+// Works out what happened to a test program that was killed by a signal:
+// which signal it was, and whether the kernel left a core dump behind.
+// Core dumps are found by following the kernel's core_pattern, so this only
+// knows where to look on Linux; elsewhere it assumes the traditional "core".
+extern crate regex;
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use self::regex::Regex;
+
+use sys;
+
+/// How much of a core dump to read when looking for the signal that caused
+/// it.  The notes describing the crashed process come first, so this only
+/// needs to cover the ELF headers and the start of the notes.
+const MAX_CORE_HEADER: u64 = 1024 * 1024;
+
+/// ELF constants needed to find the signal in a core dump.
+const ET_CORE: u64 = 4;
+const PT_NOTE: u64 = 4;
+const NT_PRSTATUS: u64 = 1;
+
+/// What is known about a program that was killed by a signal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crash {
+    /// The signal that killed the program, if it could be determined.
+    pub signal: Option<i32>,
+
+    /// The core dump it left behind, if one was found.
+    pub core: Option<PathBuf>,
+
+    /// The program core dumps are piped to, if the kernel is set up that way.
+    pub handler: Option<String>,
+}
+
+impl fmt::Display for Crash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.signal {
+            Some(signal) => write!(f, "killed by {}", sys::signal_name(signal))?,
+            None => write!(f, "killed by a signal")?,
+        }
+        match (&self.core, &self.handler) {
+            (Some(core), _) => write!(f, ", core dumped to {}", core.display()),
+            (None, Some(handler)) => write!(f, ", any core dump was passed to {}", handler),
+            (None, None) => write!(f, ", without dumping core"),
+        }
+    }
+}
+
+/// Look for the core dump left by process `pid`, which ran in
+/// `working_directory` and was started at `since`.
+pub fn examine(working_directory: &Path, pid: i32, since: SystemTime) -> Crash {
+    let pattern = core_pattern();
+    if let Some(handler) = pattern.strip_prefix('|') {
+        return Crash {
+            signal: None,
+            core: None,
+            handler: Some(
+                handler
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or(handler)
+                    .to_owned(),
+            ),
+        };
+    }
+
+    let core = find_core(&working_directory.join(pattern), pid, since);
+    Crash {
+        signal: core.as_ref().and_then(|path| core_signal(path)),
+        core,
+        handler: None,
+    }
+}
+
+/// The kernel's core_pattern, with ".%p" added if core_uses_pid is set.
+fn core_pattern() -> String {
+    let read = |name| {
+        fs::read_to_string(Path::new("/proc/sys/kernel").join(name))
+            .map(|s| s.trim().to_owned())
+            .ok()
+    };
+    let mut pattern = read("core_pattern")
+        .filter(|p| !p.is_empty())
+        .unwrap_or_else(|| "core".to_owned());
+    if !pattern.starts_with('|')
+        && !pattern.contains("%p")
+        && read("core_uses_pid").as_deref() == Some("1")
+    {
+        pattern.push_str(".%p");
+    }
+    pattern
+}
+
+/// Find the newest file matching `pattern` that was written since `since`.
+/// Specifiers other than %p (the process ID) can't be known in advance, so
+/// they match anything.
+fn find_core(pattern: &Path, pid: i32, since: SystemTime) -> Option<PathBuf> {
+    let directory = pattern.parent()?;
+    let name = pattern.file_name()?.to_string_lossy().into_owned();
+
+    let mut regex = "^".to_owned();
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        match (c, if c == '%' { chars.next() } else { None }) {
+            ('%', Some('%')) => regex.push_str(&regex::escape("%")),
+            ('%', Some('p')) => regex.push_str(&pid.to_string()),
+            ('%', _) => regex.push_str(".*"),
+            (c, _) => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    let regex = Regex::new(&regex).ok()?;
+
+    // Allow for filesystems that only store times to the nearest second or two.
+    let since = since.checked_sub(Duration::from_secs(2)).unwrap_or(since);
+    fs::read_dir(directory)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| regex.is_match(&entry.file_name().to_string_lossy()))
+        .filter_map(|entry| {
+            let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+            if modified >= since {
+                Some((modified, entry.path()))
+            } else {
+                None
+            }
+        })
+        .max()
+        .map(|(_, path)| path)
+}
+
+/// Read the number of the signal that caused a core dump from its
+/// NT_PRSTATUS note.
+fn core_signal(path: &Path) -> Option<i32> {
+    let mut data = vec![];
+    File::open(path)
+        .ok()?
+        .take(MAX_CORE_HEADER)
+        .read_to_end(&mut data)
+        .ok()?;
+    if data.len() < 6 || &data[..4] != b"\x7fELF" {
+        return None;
+    }
+    let is_64 = data[4] == 2;
+    let little_endian = data[5] == 1;
+    let read = |offset: u64, size: usize| -> Option<u64> {
+        let start = offset as usize;
+        let bytes = data.get(start..start.checked_add(size)?)?;
+        let mut value = 0u64;
+        for i in 0..size {
+            let byte = if little_endian {
+                bytes[size - 1 - i]
+            } else {
+                bytes[i]
+            };
+            value = (value << 8) | byte as u64;
+        }
+        Some(value)
+    };
+
+    if read(16, 2)? != ET_CORE {
+        return None;
+    }
+    let (phoff, phentsize, phnum) = if is_64 {
+        (read(0x20, 8)?, read(0x36, 2)?, read(0x38, 2)?)
+    } else {
+        (read(0x1c, 4)?, read(0x2a, 2)?, read(0x2c, 2)?)
+    };
+    for index in 0..phnum {
+        let header = phoff + index * phentsize;
+        if read(header, 4)? != PT_NOTE {
+            continue;
+        }
+        let (offset, size) = if is_64 {
+            (read(header + 8, 8)?, read(header + 0x20, 8)?)
+        } else {
+            (read(header + 4, 4)?, read(header + 16, 4)?)
+        };
+        let align = |n: u64| (n + 3) & !3;
+        let mut note = offset;
+        while note + 12 <= offset + size {
+            let name_size = read(note, 4)?;
+            let desc_size = read(note + 4, 4)?;
+            let desc = note + 12 + align(name_size);
+            if read(note + 8, 4)? == NT_PRSTATUS {
+                // elf_prstatus starts with the signal number.
+                return Some(read(desc, 4)? as i32);
+            }
+            note = desc + align(desc_size);
+        }
+    }
+    None
+}
@@ -0,0 +1,104 @@
+// Measures what it costs to run a test or scenario: wall-clock time, CPU
+// time, and peak memory.  CPU time and memory come from the cgroup each run
+// of a test is started in, so they're only known where exclave can make
+// cgroups, and a scenario's are worked out from its tests'.
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use cgroup::Cgroup;
+use clock::Clock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Cost {
+    /// How long it took, start to finish.
+    pub wall: Duration,
+
+    /// CPU time (user and system) used by the processes that were run, if known.
+    pub cpu: Option<Duration>,
+
+    /// The most memory the processes that were run used at once, in KiB, if
+    /// known.  A scenario's is the largest of its tests'.
+    pub peak_memory_kb: Option<u64>,
+}
+
+impl fmt::Display for Cost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:03}s wall",
+            self.wall.as_secs(),
+            self.wall.subsec_millis()
+        )?;
+        if let Some(cpu) = self.cpu {
+            write!(f, ", {}.{:03}s CPU", cpu.as_secs(), cpu.subsec_millis())?;
+        }
+        if let Some(peak) = self.peak_memory_kb {
+            write!(f, ", {} KiB peak", peak)?;
+        }
+        Ok(())
+    }
+}
+
+/// Started when a test or scenario starts, and read when it finishes.
+pub struct CostMeter {
+    clock: Clock,
+    start: Instant,
+
+    /// The cgroup the test's processes were started in, if one could be made.
+    cgroup: Option<Cgroup>,
+
+    /// What the parts measured on their own cost, such as a scenario's tests.
+    parts: Vec<Cost>,
+}
+
+impl CostMeter {
+    pub fn start(clock: &Clock) -> CostMeter {
+        CostMeter {
+            clock: clock.clone(),
+            start: clock.instant(),
+            cgroup: None,
+            parts: vec![],
+        }
+    }
+
+    /// Start a process with `start`, so that what it and every process it
+    /// starts cost is measured.  If no cgroup can be made for them, they're
+    /// started anyway, and their CPU time and memory aren't known.
+    pub fn spawn<T, E, F>(&mut self, start: F, pid: fn(&T) -> i32) -> Result<T, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        self.cgroup = Cgroup::create().ok();
+        match self.cgroup {
+            Some(ref mut cgroup) => cgroup.spawn(start, pid),
+            None => start(),
+        }
+    }
+
+    /// Count what a part that was measured on its own cost, such as a test
+    /// in a scenario.
+    pub fn include(&mut self, part: Cost) {
+        self.parts.push(part);
+    }
+
+    /// The cost so far.  CPU time is the total of every process and part,
+    /// and peak memory is the largest of them.
+    pub fn cost(&self) -> Cost {
+        let cgroup = self.cgroup.as_ref();
+        let mut cpu = cgroup.and_then(Cgroup::cpu);
+        let mut peak_memory_kb = cgroup.and_then(Cgroup::peak_memory_kb);
+        for part in &self.parts {
+            if let Some(part_cpu) = part.cpu {
+                cpu = Some(cpu.unwrap_or_default() + part_cpu);
+            }
+            if let Some(part_peak) = part.peak_memory_kb {
+                peak_memory_kb = Some(peak_memory_kb.unwrap_or_default().max(part_peak));
+            }
+        }
+        Cost {
+            wall: self.clock.instant().duration_since(self.start),
+            cpu,
+            peak_memory_kb,
+        }
+    }
+}
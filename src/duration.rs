@@ -0,0 +1,157 @@
+// Parses the durations given in unit files and the settings file, such as
+// "90s", "5m", "1h30m", "1h 30min", or "0.5s".  A bare number is a number of
+// seconds, which has led authors who thought in milliseconds to set timeouts
+// a thousand times too long, so with StrictDurations set, a bare number
+// other than 0 is refused and the unit must be written out.
+use std::fmt;
+use std::time::Duration;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DurationError {
+    /// Nothing was given.
+    Empty,
+
+    /// Something that should have been a number wasn't.
+    InvalidNumber(String),
+
+    /// A number was followed by something that isn't a unit of time.
+    UnknownUnit(String),
+
+    /// A number was given without a unit, either in strict mode or after
+    /// another part with a unit, as in "1h 30".
+    MissingUnit(String),
+
+    /// The duration is too long to be represented.
+    Overflow,
+}
+
+impl fmt::Display for DurationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DurationError::Empty => write!(f, "no duration given"),
+            DurationError::InvalidNumber(ref s) => write!(f, "\"{}\" isn't a number", s),
+            DurationError::UnknownUnit(ref s) => write!(
+                f,
+                "unknown unit \"{}\", must be one of: ms, s, m, h, d, w",
+                s
+            ),
+            DurationError::MissingUnit(ref s) => write!(
+                f,
+                "\"{}\" has no unit, so it could be seconds or milliseconds; write \"{}s\" or \"{}ms\"",
+                s, s, s
+            ),
+            DurationError::Overflow => write!(f, "duration is too long"),
+        }
+    }
+}
+
+/// Parse a duration, treating a bare number as a number of seconds.
+pub fn parse(value: &str) -> Result<Duration, DurationError> {
+    parse_with(value, false)
+}
+
+/// Parse a duration, refusing bare numbers other than 0.
+pub fn parse_strict(value: &str) -> Result<Duration, DurationError> {
+    parse_with(value, true)
+}
+
+fn parse_with(value: &str, strict: bool) -> Result<Duration, DurationError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(DurationError::Empty);
+    }
+    if value.chars().all(is_number_char) {
+        let nanos = scale(value, NANOS_PER_SEC)?;
+        if strict && nanos != 0 {
+            return Err(DurationError::MissingUnit(value.to_owned()));
+        }
+        return to_duration(nanos);
+    }
+
+    let mut total: u128 = 0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !is_number_char(c))
+            .unwrap_or(rest.len());
+        let (number, after) = rest.split_at(number_end);
+        if number.is_empty() {
+            let word = rest.split_whitespace().next().unwrap_or(rest);
+            return Err(DurationError::InvalidNumber(word.to_owned()));
+        }
+        let after = after.trim_start();
+        let unit_end = after
+            .find(|c: char| is_number_char(c) || c.is_whitespace())
+            .unwrap_or(after.len());
+        let (unit, after) = after.split_at(unit_end);
+        if unit.is_empty() {
+            return Err(DurationError::MissingUnit(number.to_owned()));
+        }
+        let unit_nanos =
+            unit_nanos(unit).ok_or_else(|| DurationError::UnknownUnit(unit.to_owned()))?;
+        total = total
+            .checked_add(scale(number, unit_nanos)?)
+            .ok_or(DurationError::Overflow)?;
+        rest = after.trim_start();
+    }
+    to_duration(total)
+}
+
+fn is_number_char(c: char) -> bool {
+    c.is_ascii_digit() || c == '.'
+}
+
+/// How many nanoseconds are in one of a unit, using the same names as
+/// systemd and humantime.  A month is 30.44 days and a year 365.25 days.
+fn unit_nanos(unit: &str) -> Option<u128> {
+    let secs = match unit {
+        "ns" | "nsec" | "nsecs" => return Some(1),
+        "us" | "\u{b5}s" | "usec" | "usecs" => return Some(1_000),
+        "ms" | "msec" | "msecs" => return Some(1_000_000),
+        "s" | "sec" | "secs" | "second" | "seconds" => 1,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60,
+        "h" | "hr" | "hrs" | "hour" | "hours" => 3_600,
+        "d" | "day" | "days" => 86_400,
+        "w" | "week" | "weeks" => 604_800,
+        "M" | "month" | "months" => 2_630_016,
+        "y" | "year" | "years" => 31_557_600,
+        _ => return None,
+    };
+    Some(secs * NANOS_PER_SEC)
+}
+
+/// Multiply a number that may have a fractional part, such as "1.5", by a
+/// number of nanoseconds, without going through floating point.
+fn scale(number: &str, nanos: u128) -> Result<u128, DurationError> {
+    let invalid = || DurationError::InvalidNumber(number.to_owned());
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if (whole.is_empty() && fraction.is_empty()) || fraction.contains('.') {
+        return Err(invalid());
+    }
+    let whole: u128 = match whole {
+        "" => 0,
+        digits => digits.parse().map_err(|_| DurationError::Overflow)?,
+    };
+    // Digits beyond the eighteenth are far below a nanosecond.
+    let fraction = &fraction[..fraction.len().min(18)];
+    let fraction_nanos = match fraction {
+        "" => 0,
+        digits => {
+            digits.parse::<u128>().map_err(|_| invalid())? * nanos / 10u128.pow(digits.len() as u32)
+        }
+    };
+    whole
+        .checked_mul(nanos)
+        .and_then(|n| n.checked_add(fraction_nanos))
+        .ok_or(DurationError::Overflow)
+}
+
+fn to_duration(nanos: u128) -> Result<Duration, DurationError> {
+    let secs = nanos / NANOS_PER_SEC;
+    if secs > u128::from(u64::MAX) {
+        return Err(DurationError::Overflow);
+    }
+    Ok(Duration::new(secs as u64, (nanos % NANOS_PER_SEC) as u32))
+}
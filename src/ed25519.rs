@@ -0,0 +1,439 @@
+// Ed25519 signatures (RFC 8032), for signing result records so that whoever
+// receives them can tell they weren't changed after they left the station.
+// Only signing is needed here, so there's no verification.  The arithmetic
+// follows TweetNaCl: field elements are sixteen 16-bit limbs held in i64s,
+// which is slow but small, and a run only signs a handful of records.  It
+// isn't hardened against timing attacks by anyone who can run code on the
+// station, who could read the key anyway.
+//
+// SHA-512 (FIPS 180-4) is here too, since nothing else uses it.
+
+/// The length of a private key's seed, and of a public key, in bytes.
+pub const KEY_LENGTH: usize = 32;
+
+/// The length of a signature, in bytes.
+pub const SIGNATURE_LENGTH: usize = 64;
+
+const K: [u64; 80] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+
+const INITIAL: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+/// The SHA-512 digest of some pieces of data, one after another.
+pub fn sha512(pieces: &[&[u8]]) -> [u8; 64] {
+    let mut state = INITIAL;
+    let mut message: Vec<u8> = pieces.concat();
+    let bits = (message.len() as u128).wrapping_mul(8);
+    message.push(0x80);
+    while message.len() % 128 != 112 {
+        message.push(0);
+    }
+    message.extend_from_slice(&bits.to_be_bytes());
+    for block in message.chunks(128) {
+        let mut w = [0u64; 80];
+        for (i, word) in block.chunks(8).enumerate() {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(word);
+            w[i] = u64::from_be_bytes(bytes);
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..80 {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+    let mut digest = [0; 64];
+    for (bytes, word) in digest.chunks_mut(8).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// An element of the field of integers modulo 2^255 - 19.
+type Gf = [i64; 16];
+
+/// A point on the curve, in extended coordinates (X, Y, Z, T).
+type Point = [Gf; 4];
+
+const GF0: Gf = [0; 16];
+const GF1: Gf = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/// 2 * d, where d is the curve's constant.
+const D2: Gf = [
+    0xf159, 0x26b2, 0x9b94, 0xebd6, 0xb156, 0x8283, 0x149a, 0x00e0, 0xd130, 0xeef3, 0x80f2, 0x198e,
+    0xfce7, 0x56df, 0xd9dc, 0x2406,
+];
+
+/// The base point's coordinates.
+const BASE_X: Gf = [
+    0xd51a, 0x8f25, 0x2d60, 0xc956, 0xa7b2, 0x9525, 0xc760, 0x692c, 0xdc5c, 0xfdd6, 0xe231, 0xc0a4,
+    0x53fe, 0xcd6e, 0x36d3, 0x2169,
+];
+const BASE_Y: Gf = [
+    0x6658, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
+    0x6666, 0x6666, 0x6666, 0x6666,
+];
+
+/// The order of the base point, little-endian.
+const ORDER: [i64; 32] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+];
+
+fn carry(o: &mut Gf) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        if i < 15 {
+            o[i + 1] += c - 1;
+        } else {
+            o[0] += 38 * (c - 1);
+        }
+        o[i] -= c << 16;
+    }
+}
+
+/// Swap p and q if b is 1, without branching on it.
+fn select(p: &mut Gf, q: &mut Gf, b: i64) {
+    let c = !(b - 1);
+    for i in 0..16 {
+        let t = c & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+fn pack_gf(n: &Gf) -> [u8; 32] {
+    let mut t = *n;
+    carry(&mut t);
+    carry(&mut t);
+    carry(&mut t);
+    for _ in 0..2 {
+        let mut m = GF0;
+        m[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            m[i] = t[i] - 0xffff - ((m[i - 1] >> 16) & 1);
+            m[i - 1] &= 0xffff;
+        }
+        m[15] = t[15] - 0x7fff - ((m[14] >> 16) & 1);
+        let b = (m[15] >> 16) & 1;
+        m[14] &= 0xffff;
+        select(&mut t, &mut m, 1 - b);
+    }
+    let mut o = [0; 32];
+    for i in 0..16 {
+        o[2 * i] = (t[i] & 0xff) as u8;
+        o[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+    o
+}
+
+fn parity(a: &Gf) -> u8 {
+    pack_gf(a)[0] & 1
+}
+
+fn add_gf(a: &Gf, b: &Gf) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = a[i] + b[i];
+    }
+    o
+}
+
+fn sub_gf(a: &Gf, b: &Gf) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = a[i] - b[i];
+    }
+    o
+}
+
+fn mul_gf(a: &Gf, b: &Gf) -> Gf {
+    let mut t = [0i64; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+    let mut o = GF0;
+    o.copy_from_slice(&t[..16]);
+    carry(&mut o);
+    carry(&mut o);
+    o
+}
+
+fn invert_gf(i: &Gf) -> Gf {
+    let mut c = *i;
+    for a in (0..=253).rev() {
+        c = mul_gf(&c, &c);
+        if a != 2 && a != 4 {
+            c = mul_gf(&c, i);
+        }
+    }
+    c
+}
+
+fn add_point(p: &mut Point, q: &Point) {
+    let a = mul_gf(&sub_gf(&p[1], &p[0]), &sub_gf(&q[1], &q[0]));
+    let b = mul_gf(&add_gf(&p[0], &p[1]), &add_gf(&q[0], &q[1]));
+    let c = mul_gf(&mul_gf(&p[3], &q[3]), &D2);
+    let d = mul_gf(&p[2], &q[2]);
+    let d = add_gf(&d, &d);
+    let e = sub_gf(&b, &a);
+    let f = sub_gf(&d, &c);
+    let g = add_gf(&d, &c);
+    let h = add_gf(&b, &a);
+    p[0] = mul_gf(&e, &f);
+    p[1] = mul_gf(&h, &g);
+    p[2] = mul_gf(&g, &f);
+    p[3] = mul_gf(&e, &h);
+}
+
+fn swap_points(p: &mut Point, q: &mut Point, b: i64) {
+    for i in 0..4 {
+        select(&mut p[i], &mut q[i], b);
+    }
+}
+
+fn pack_point(p: &Point) -> [u8; 32] {
+    let zi = invert_gf(&p[2]);
+    let x = mul_gf(&p[0], &zi);
+    let y = mul_gf(&p[1], &zi);
+    let mut packed = pack_gf(&y);
+    packed[31] ^= parity(&x) << 7;
+    packed
+}
+
+/// The base point multiplied by a scalar.
+fn scalar_base(s: &[u8]) -> Point {
+    let mut q = [BASE_X, BASE_Y, GF1, mul_gf(&BASE_X, &BASE_Y)];
+    let mut p = [GF0, GF1, GF1, GF0];
+    for i in (0..256).rev() {
+        let b = ((s[i / 8] >> (i & 7)) & 1) as i64;
+        swap_points(&mut p, &mut q, b);
+        let sum = p;
+        add_point(&mut q, &sum);
+        let double = p;
+        add_point(&mut p, &double);
+        swap_points(&mut p, &mut q, b);
+    }
+    p
+}
+
+/// Reduce a 64-byte little-endian number modulo the base point's order.
+fn reduce_scalar(x: &mut [i64; 64]) -> [u8; 32] {
+    for i in (32..64).rev() {
+        let mut carry = 0;
+        for j in (i - 32)..(i - 12) {
+            x[j] += carry - 16 * x[i] * ORDER[j - (i - 32)];
+            carry = (x[j] + 128) >> 8;
+            x[j] -= carry << 8;
+        }
+        x[i - 12] += carry;
+        x[i] = 0;
+    }
+    let mut carry = 0;
+    for j in 0..32 {
+        x[j] += carry - (x[31] >> 4) * ORDER[j];
+        carry = x[j] >> 8;
+        x[j] &= 255;
+    }
+    for j in 0..32 {
+        x[j] -= carry * ORDER[j];
+    }
+    let mut r = [0; 32];
+    for i in 0..32 {
+        x[i + 1] += x[i] >> 8;
+        r[i] = (x[i] & 255) as u8;
+    }
+    r
+}
+
+fn reduce_digest(digest: &[u8; 64]) -> [u8; 32] {
+    let mut x = [0i64; 64];
+    for (x, byte) in x.iter_mut().zip(digest.iter()) {
+        *x = *byte as i64;
+    }
+    reduce_scalar(&mut x)
+}
+
+/// A private key, kept as its seed along with the public key made from it.
+#[derive(Clone)]
+pub struct SigningKey {
+    seed: [u8; KEY_LENGTH],
+    public: [u8; KEY_LENGTH],
+}
+
+impl SigningKey {
+    /// The key made from a 32-byte seed, as RFC 8032 gives private keys.
+    pub fn from_seed(seed: [u8; KEY_LENGTH]) -> SigningKey {
+        let public = pack_point(&scalar_base(&Self::expand(&seed)[..32]));
+        SigningKey { seed, public }
+    }
+
+    /// The key made from a seed written as 64 hex digits.
+    pub fn from_hex(hex: &str) -> Option<SigningKey> {
+        let hex = hex.trim();
+        if hex.len() != 2 * KEY_LENGTH || !hex.is_ascii() {
+            return None;
+        }
+        let mut seed = [0; KEY_LENGTH];
+        for (i, byte) in seed.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+        }
+        Some(SigningKey::from_seed(seed))
+    }
+
+    /// The hashed seed, with the scalar in its first half clamped.
+    fn expand(seed: &[u8; KEY_LENGTH]) -> [u8; 64] {
+        let mut d = sha512(&[seed]);
+        d[0] &= 248;
+        d[31] &= 127;
+        d[31] |= 64;
+        d
+    }
+
+    /// The public key, which is what verifies this key's signatures.
+    pub fn public_key(&self) -> [u8; KEY_LENGTH] {
+        self.public
+    }
+
+    /// Sign a message.
+    pub fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_LENGTH] {
+        let d = Self::expand(&self.seed);
+        let r = reduce_digest(&sha512(&[&d[32..], message]));
+        let mut signature = [0; SIGNATURE_LENGTH];
+        signature[..32].copy_from_slice(&pack_point(&scalar_base(&r)));
+        let h = reduce_digest(&sha512(&[&signature[..32], &self.public, message]));
+        let mut x = [0i64; 64];
+        for i in 0..32 {
+            x[i] = r[i] as i64;
+        }
+        for i in 0..32 {
+            for j in 0..32 {
+                x[i + j] += h[i] as i64 * d[j] as i64;
+            }
+        }
+        signature[32..].copy_from_slice(&reduce_scalar(&mut x));
+        signature
+    }
+}
@@ -0,0 +1,134 @@
+// How long a scenario can be expected to take, so that operators can see
+// when a run should finish as soon as it starts.  A scenario can say, with
+//
+//     EstimatedDuration=4min 30s
+//
+// and otherwise it's worked out from how long the scenario took the last few
+// times every test passed, which are the runs that go all the way through.
+// Runs that failed may have stopped early, and runs in maintenance mode
+// aren't production runs, so neither is counted.  With a StateDirectory, the
+// history is kept across restarts.
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+use unit::{UnitDescriptionError, UnitName};
+use unitbroadcaster::ScenarioSummary;
+
+/// How many of a scenario's recent runs its estimate is worked out from.
+pub const HISTORY_LENGTH: usize = 10;
+
+/// The FINISH code of a run in which every test passed.
+const PASSED: u32 = 200;
+
+/// How each line of a saved history is laid out, for errors about it.
+const STATE_FORMAT: &str = "<scenario><tab><milliseconds>,<milliseconds>,...";
+
+/// Where an estimate came from.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Source {
+    /// The scenario's EstimatedDuration.
+    Declared,
+
+    /// The scenario's recent runs.
+    History,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Source::Declared => write!(f, "declared"),
+            Source::History => write!(f, "history"),
+        }
+    }
+}
+
+/// How long a scenario is expected to take.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub struct Estimate {
+    pub duration: Duration,
+    pub source: Source,
+}
+
+/// How long each scenario took in its recent runs.
+#[derive(Default)]
+pub struct DurationHistory {
+    scenarios: HashMap<UnitName, VecDeque<Duration>>,
+}
+
+impl DurationHistory {
+    pub fn new() -> DurationHistory {
+        Default::default()
+    }
+
+    /// Add a finished run, if it went all the way through.  Returns true if
+    /// it was added.
+    pub fn record(&mut self, summary: &ScenarioSummary) -> bool {
+        if summary.maintenance || summary.code != PASSED {
+            return false;
+        }
+        let runs = self.scenarios.entry(summary.scenario.clone()).or_default();
+        // Kept to the millisecond, as they're saved.
+        runs.push_back(Duration::from_millis(summary.duration.as_millis() as u64));
+        while runs.len() > HISTORY_LENGTH {
+            runs.pop_front();
+        }
+        true
+    }
+
+    /// The median of a scenario's recent run times, so that one slow run
+    /// doesn't throw it off, or None if it hasn't been run through yet.
+    pub fn estimate(&self, scenario: &UnitName) -> Option<Duration> {
+        let mut runs: Vec<Duration> = self.scenarios.get(scenario)?.iter().cloned().collect();
+        if runs.is_empty() {
+            return None;
+        }
+        runs.sort();
+        let middle = runs.len() / 2;
+        if runs.len() % 2 == 1 {
+            Some(runs[middle])
+        } else {
+            Some((runs[middle - 1] + runs[middle]) / 2)
+        }
+    }
+
+    /// Write out every scenario's recent run times, one line each, to be
+    /// read back in with from_state().
+    pub fn to_state(&self) -> String {
+        let mut scenarios: Vec<(&UnitName, &VecDeque<Duration>)> = self.scenarios.iter().collect();
+        scenarios.sort_by(|a, b| a.0.cmp(b.0));
+        let mut contents = String::new();
+        for (scenario, runs) in scenarios {
+            let runs: Vec<String> = runs.iter().map(|d| d.as_millis().to_string()).collect();
+            contents.push_str(&format!("{}\t{}\n", scenario, runs.join(",")));
+        }
+        contents
+    }
+
+    /// Read back run times written by to_state().
+    pub fn from_state(contents: &str) -> Result<DurationHistory, UnitDescriptionError> {
+        let mut history = DurationHistory::new();
+        for line in contents.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = || {
+                UnitDescriptionError::InvalidValue(
+                    "Exclave".to_owned(),
+                    "durations".to_owned(),
+                    line.to_owned(),
+                    vec![STATE_FORMAT.to_owned()],
+                )
+            };
+            let (scenario, runs) = line.trim().split_once('\t').ok_or_else(invalid)?;
+            let runs = runs
+                .split(',')
+                .map(|ms| ms.parse().map(Duration::from_millis).map_err(|_| invalid()))
+                .collect::<Result<VecDeque<Duration>, UnitDescriptionError>>()?;
+            history
+                .scenarios
+                .insert(UnitName::from_str(scenario, "")?, runs);
+        }
+        Ok(history)
+    }
+}
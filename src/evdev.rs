@@ -0,0 +1,263 @@
+// Reading key presses straight from a Linux input device, such as a USB foot
+// pedal or a keypad, for triggers with Device= rather than a program of their
+// own.  A device is named by its path under /dev/input, or by its USB vendor
+// and product ids, as "0c45:7403", in which case the first input device with
+// those ids is used.  Keys are named as in <linux/input-event-codes.h>, such
+// as KEY_B or BTN_0, or given as numbers.
+//
+// Each event read from the device is a struct input_event: the time, as two
+// longs, then the type and code as u16s and the value as an i32.  Only key
+// presses (EV_KEY with a value of 1) are of interest, not releases or repeats.
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// Where input devices are listed, with their ids.
+const SYS_CLASS_INPUT: &str = "/sys/class/input";
+
+/// Where input devices are opened.
+const DEV_INPUT: &str = "/dev/input";
+
+/// The size of a struct input_event.
+pub const EVENT_SIZE: usize = 2 * mem::size_of::<usize>() + 8;
+
+/// The type of events that keys and buttons send.
+const EV_KEY: u16 = 1;
+
+/// The value of an EV_KEY event when the key goes down.
+const KEY_PRESSED: i32 = 1;
+
+/// Keys that may be given by name, with their codes.
+const KEYS: &[(&str, u16)] = &[
+    ("KEY_ESC", 1),
+    ("KEY_1", 2),
+    ("KEY_2", 3),
+    ("KEY_3", 4),
+    ("KEY_4", 5),
+    ("KEY_5", 6),
+    ("KEY_6", 7),
+    ("KEY_7", 8),
+    ("KEY_8", 9),
+    ("KEY_9", 10),
+    ("KEY_0", 11),
+    ("KEY_BACKSPACE", 14),
+    ("KEY_TAB", 15),
+    ("KEY_Q", 16),
+    ("KEY_W", 17),
+    ("KEY_E", 18),
+    ("KEY_R", 19),
+    ("KEY_T", 20),
+    ("KEY_Y", 21),
+    ("KEY_U", 22),
+    ("KEY_I", 23),
+    ("KEY_O", 24),
+    ("KEY_P", 25),
+    ("KEY_ENTER", 28),
+    ("KEY_LEFTCTRL", 29),
+    ("KEY_A", 30),
+    ("KEY_S", 31),
+    ("KEY_D", 32),
+    ("KEY_F", 33),
+    ("KEY_G", 34),
+    ("KEY_H", 35),
+    ("KEY_J", 36),
+    ("KEY_K", 37),
+    ("KEY_L", 38),
+    ("KEY_LEFTSHIFT", 42),
+    ("KEY_Z", 44),
+    ("KEY_X", 45),
+    ("KEY_C", 46),
+    ("KEY_V", 47),
+    ("KEY_B", 48),
+    ("KEY_N", 49),
+    ("KEY_M", 50),
+    ("KEY_RIGHTSHIFT", 54),
+    ("KEY_LEFTALT", 56),
+    ("KEY_SPACE", 57),
+    ("KEY_F1", 59),
+    ("KEY_F2", 60),
+    ("KEY_F3", 61),
+    ("KEY_F4", 62),
+    ("KEY_F5", 63),
+    ("KEY_F6", 64),
+    ("KEY_F7", 65),
+    ("KEY_F8", 66),
+    ("KEY_F9", 67),
+    ("KEY_F10", 68),
+    ("KEY_KP7", 71),
+    ("KEY_KP8", 72),
+    ("KEY_KP9", 73),
+    ("KEY_KPMINUS", 74),
+    ("KEY_KP4", 75),
+    ("KEY_KP5", 76),
+    ("KEY_KP6", 77),
+    ("KEY_KPPLUS", 78),
+    ("KEY_KP1", 79),
+    ("KEY_KP2", 80),
+    ("KEY_KP3", 81),
+    ("KEY_KP0", 82),
+    ("KEY_KPDOT", 83),
+    ("KEY_F11", 87),
+    ("KEY_F12", 88),
+    ("KEY_KPENTER", 96),
+    ("KEY_RIGHTCTRL", 97),
+    ("KEY_RIGHTALT", 100),
+    ("KEY_UP", 103),
+    ("KEY_LEFT", 105),
+    ("KEY_RIGHT", 106),
+    ("KEY_DOWN", 108),
+    ("KEY_F13", 183),
+    ("KEY_F14", 184),
+    ("KEY_F15", 185),
+    ("KEY_F16", 186),
+    ("KEY_F17", 187),
+    ("KEY_F18", 188),
+    ("KEY_F19", 189),
+    ("KEY_F20", 190),
+    ("KEY_F21", 191),
+    ("KEY_F22", 192),
+    ("KEY_F23", 193),
+    ("KEY_F24", 194),
+    ("BTN_0", 0x100),
+    ("BTN_1", 0x101),
+    ("BTN_2", 0x102),
+    ("BTN_3", 0x103),
+    ("BTN_4", 0x104),
+    ("BTN_5", 0x105),
+    ("BTN_6", 0x106),
+    ("BTN_7", 0x107),
+    ("BTN_8", 0x108),
+    ("BTN_9", 0x109),
+    ("BTN_LEFT", 0x110),
+    ("BTN_RIGHT", 0x111),
+    ("BTN_MIDDLE", 0x112),
+];
+
+/// The code of a key, given by name, such as "KEY_B", or as a number.
+pub fn key_code(key: &str) -> Option<u16> {
+    let key = key.trim();
+    if let Ok(code) = key.parse() {
+        return Some(code);
+    }
+    let key = key.to_ascii_uppercase();
+    KEYS.iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, code)| *code)
+}
+
+/// Which input device to read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Device {
+    /// A device node, such as /dev/input/event3.
+    Path(PathBuf),
+
+    /// The first input device with this USB vendor and product id.
+    Usb(u16, u16),
+}
+
+impl Device {
+    /// Read a device given as a path, or as "vendor:product" in hex.
+    pub fn parse(device: &str) -> Option<Device> {
+        let device = device.trim();
+        if device.is_empty() {
+            return None;
+        }
+        if let Some((vendor, product)) = device.split_once(':') {
+            if let (Ok(vendor), Ok(product)) = (
+                u16::from_str_radix(vendor, 16),
+                u16::from_str_radix(product, 16),
+            ) {
+                return Some(Device::Usb(vendor, product));
+            }
+        }
+        Some(Device::Path(PathBuf::from(device)))
+    }
+
+    /// Where the device is now.  A USB device may be at a different path
+    /// each time it's plugged in.
+    pub fn find(&self) -> io::Result<PathBuf> {
+        let (vendor, product) = match *self {
+            Device::Path(ref path) => return Ok(path.clone()),
+            Device::Usb(vendor, product) => (vendor, product),
+        };
+        let id = |dir: &Path, name: &str| {
+            fs::read_to_string(dir.join("device").join("id").join(name))
+                .ok()
+                .and_then(|id| u16::from_str_radix(id.trim(), 16).ok())
+        };
+        let mut events: Vec<String> = fs::read_dir(SYS_CLASS_INPUT)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("event"))
+            .collect();
+        // Devices with several nodes, such as a keypad that's also a mouse,
+        // list their keys on the first.
+        events.sort_by_key(|name| name[5..].parse::<u32>().unwrap_or(u32::MAX));
+        events
+            .into_iter()
+            .find(|name| {
+                let dir = Path::new(SYS_CLASS_INPUT).join(name);
+                id(&dir, "vendor") == Some(vendor) && id(&dir, "product") == Some(product)
+            })
+            .map(|name| Path::new(DEV_INPUT).join(name))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("no {} plugged in", self))
+            })
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Device::Path(ref path) => write!(f, "{}", path.display()),
+            Device::Usb(vendor, product) => {
+                write!(f, "input device {:04x}:{:04x}", vendor, product)
+            }
+        }
+    }
+}
+
+/// Pick the type, code, and value out of a struct input_event.
+pub fn parse_event(event: &[u8; EVENT_SIZE]) -> (u16, u16, i32) {
+    let rest = &event[EVENT_SIZE - 8..];
+    (
+        u16::from_ne_bytes([rest[0], rest[1]]),
+        u16::from_ne_bytes([rest[2], rest[3]]),
+        i32::from_ne_bytes([rest[4], rest[5], rest[6], rest[7]]),
+    )
+}
+
+/// Reads the keys pressed on an input device.
+pub struct Keys<R: Read> {
+    input: R,
+}
+
+impl Keys<File> {
+    /// Start reading the keys pressed on a device.
+    pub fn open(device: &Device) -> io::Result<Keys<File>> {
+        let path = device.find()?;
+        File::open(&path)
+            .map(Keys::new)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+}
+
+impl<R: Read> Keys<R> {
+    pub fn new(input: R) -> Keys<R> {
+        Keys { input }
+    }
+
+    /// Wait for the next key to be pressed, and return its code.
+    pub fn next_press(&mut self) -> io::Result<u16> {
+        let mut event = [0; EVENT_SIZE];
+        loop {
+            self.input.read_exact(&mut event)?;
+            match parse_event(&event) {
+                (EV_KEY, code, KEY_PRESSED) => return Ok(code),
+                _ => continue,
+            }
+        }
+    }
+}
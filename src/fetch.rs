@@ -0,0 +1,235 @@
+// Files that tests need, such as firmware images, downloaded once into a
+// cache that every test shares, rather than by each test's own script.  A
+// test lists each file it needs as
+//
+//     Fetch=https://example.com/fw-1.2.bin sha256=<64 hex digits>
+//
+// and, unless the cache already has it, the file is downloaded before the
+// test starts.  Downloads are made with curl, so https:// works, and one that
+// is cut off is resumed the next time.  A file only goes into the cache once
+// its SHA-256 matches, so a test never sees a partial or
+// corrupt file.  The test is given the path to each file in an environment
+// variable, named with "as=VARIABLE", or after the file.
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use sha256;
+
+/// Name of the directory under the state directory that holds the cache,
+/// if FetchCache doesn't put it somewhere else.
+pub const FETCH_DIRECTORY: &str = "fetch";
+
+/// The kinds of URL that can be fetched.
+const SCHEMES: [&str; 3] = ["http://", "https://", "file://"];
+
+/// How long curl may take to connect before giving up.
+const CONNECT_TIMEOUT_SECS: u32 = 30;
+
+/// How long a download may stall before curl gives up on it.
+const STALL_TIMEOUT_SECS: u32 = 60;
+
+/// The exit codes curl gives when a download can't be resumed, such as
+/// when the server doesn't support ranges.
+const CANNOT_RESUME: [i32; 2] = [33, 36];
+
+/// A file that a test needs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fetch {
+    url: String,
+    sha256: String,
+    variable: String,
+}
+
+impl Fetch {
+    /// The forms a Fetch can take, for error messages.
+    pub fn forms() -> Vec<String> {
+        vec![
+            "<url> sha256=<hex digest>".to_owned(),
+            "<url> sha256=<hex digest> as=<variable>".to_owned(),
+        ]
+    }
+
+    /// The URL, as written in the unit file, with any secrets unresolved.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn sha256(&self) -> &str {
+        &self.sha256
+    }
+
+    /// The environment variable the test gets the file's path in.
+    pub fn variable(&self) -> &str {
+        &self.variable
+    }
+
+    /// The name the file is saved under: the last part of the URL's path.
+    pub fn file_name(&self) -> String {
+        let path = self.url.split(['?', '#']).next().unwrap_or("");
+        match path.rsplit('/').next() {
+            Some(name) if !name.is_empty() && name != "." && name != ".." => name.to_owned(),
+            _ => "download".to_owned(),
+        }
+    }
+
+    /// Where the file is kept in the cache.  Files are stored by their
+    /// digest, so tests that fetch the same file share it, and a new file
+    /// at the same URL doesn't replace the old one.
+    pub fn path(&self, cache: &Path) -> PathBuf {
+        cache.join(&self.sha256).join(self.file_name())
+    }
+
+    /// Make sure the file is in the cache, downloading it from `url` (the
+    /// URL with any secrets filled in) if it isn't, and return its path.
+    pub fn fetch(&self, cache: &Path, url: &str) -> Result<PathBuf, String> {
+        let path = self.path(cache);
+        if path.is_file() {
+            return Ok(path);
+        }
+        let directory = path.parent().unwrap();
+        fs::create_dir_all(directory)
+            .map_err(|e| format!("unable to create {}: {}", directory.display(), e))?;
+
+        // A download that was cut off may have finished after all.
+        let partial = directory.join(format!("{}.partial", self.file_name()));
+        if partial.is_file() && self.verify(&partial).is_ok() {
+            return self.keep(&partial, &path);
+        }
+        // Anything already downloaded is kept for next time, unless the
+        // server can't carry on from it, in which case it starts over.
+        if let Err((code, e)) = Self::download(url, &partial) {
+            if !CANNOT_RESUME.contains(&code) {
+                return Err(e);
+            }
+            fs::remove_file(&partial).ok();
+            Self::download(url, &partial).map_err(|(_, e)| e)?;
+        }
+        if let Err(e) = self.verify(&partial) {
+            fs::remove_file(&partial).ok();
+            return Err(e);
+        }
+        self.keep(&partial, &path)
+    }
+
+    fn keep(&self, partial: &Path, path: &Path) -> Result<PathBuf, String> {
+        fs::rename(partial, path)
+            .map_err(|e| format!("unable to move {} into place: {}", self.file_name(), e))?;
+        Ok(path.to_owned())
+    }
+
+    /// Download a URL to a file, carrying on from the end of the file if
+    /// there's already some of it there.  Errors come with curl's exit code.
+    fn download(url: &str, file: &Path) -> Result<(), (i32, String)> {
+        let output = Command::new("curl")
+            .args(["--fail", "--silent", "--show-error", "--location"])
+            .args(["--connect-timeout", &CONNECT_TIMEOUT_SECS.to_string()])
+            .args(["--speed-limit", "1"])
+            .args(["--speed-time", &STALL_TIMEOUT_SECS.to_string()])
+            .args(["--continue-at", "-", "--output"])
+            .arg(file)
+            .arg(url)
+            .output()
+            .map_err(|e| (-1, format!("unable to run curl: {}", e)))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err((
+                output.status.code().unwrap_or(-1),
+                format!(
+                    "download failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ))
+        }
+    }
+
+    /// Check that a file has the digest it should.
+    fn verify(&self, file: &Path) -> Result<(), String> {
+        let digest = sha256::file(file)
+            .map_err(|e| format!("unable to check {}: {}", self.file_name(), e))?;
+        if digest == self.sha256 {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} has SHA-256 {}, but {} was expected",
+                self.file_name(),
+                digest,
+                self.sha256
+            ))
+        }
+    }
+
+    /// The variable a file is given in if the Fetch doesn't say: its name
+    /// in upper case, with anything other than letters and digits as "_".
+    fn default_variable(file_name: &str) -> String {
+        let variable: String = file_name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        if variable.starts_with(|c: char| c.is_ascii_digit()) {
+            format!("_{}", variable)
+        } else {
+            variable
+        }
+    }
+
+    fn valid_variable(name: &str) -> bool {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+}
+
+impl FromStr for Fetch {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let url = words.next().ok_or(())?;
+        if !SCHEMES.iter().any(|scheme| url.starts_with(scheme)) {
+            return Err(());
+        }
+        let mut fetch = Fetch {
+            url: url.to_owned(),
+            sha256: String::new(),
+            variable: String::new(),
+        };
+        for word in words {
+            match word.split_once('=').ok_or(())? {
+                ("sha256", digest) if sha256::valid_digest(digest) => {
+                    fetch.sha256 = digest.to_ascii_lowercase()
+                }
+                ("as", variable) if Self::valid_variable(variable) => {
+                    fetch.variable = variable.to_owned()
+                }
+                _ => return Err(()),
+            }
+        }
+        // Without a digest, there'd be no telling a good download from a bad one.
+        if fetch.sha256.is_empty() {
+            return Err(());
+        }
+        if fetch.variable.is_empty() {
+            fetch.variable = Self::default_variable(&fetch.file_name());
+        }
+        Ok(fetch)
+    }
+}
+
+impl fmt::Display for Fetch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} sha256={} as={}",
+            self.url, self.sha256, self.variable
+        )
+    }
+}
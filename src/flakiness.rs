@@ -0,0 +1,242 @@
+// Keeps a running tally of how each test has done since exclave started, and
+// watches for tests that keep flipping between passing and failing.  A test
+// that fails every time is broken, not flaky; one that passes and fails in
+// turn on the same station probably depends on something it shouldn't.
+use std::collections::{HashMap, VecDeque};
+
+use unit::{UnitDescriptionError, UnitName};
+use unitbroadcaster::ScenarioSummary;
+use units::test::TestVerdict;
+
+/// How many recent pass/fail verdicts to judge a test by, unless set otherwise.
+pub const DEFAULT_FLAKINESS_WINDOW: usize = 20;
+
+/// The flip rate, in percent, at which a test is called flaky, unless set otherwise.
+pub const DEFAULT_FLAKINESS_THRESHOLD: u32 = 30;
+
+/// How each line of a saved tracker is laid out, for errors about it.
+const STATE_FORMAT: &str =
+    "<test><tab><runs><tab><passed><tab><failed><tab><faults><tab><skipped><tab><recent><tab><flaky>";
+
+/// How a test has done, since exclave started or since the statistics in
+/// the state directory were started.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TestStats {
+    pub test: UnitName,
+    pub runs: u64,
+    pub passed: u64,
+    pub failed: u64,
+    pub faults: u64,
+    pub skipped: u64,
+
+    /// How often the test flipped between pass and fail over its recent
+    /// verdicts, in percent.
+    pub flakiness: u32,
+
+    /// The number of recent verdicts that flakiness was measured over.
+    pub window: usize,
+}
+
+/// A test whose flakiness just reached the threshold.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct FlakyTest {
+    pub test: UnitName,
+    pub flips: usize,
+    pub window: usize,
+    pub flakiness: u32,
+}
+
+#[derive(Default)]
+struct History {
+    runs: u64,
+    passed: u64,
+    failed: u64,
+    faults: u64,
+    skipped: u64,
+
+    /// Recent verdicts, true for a pass, oldest first.  Faults and skips say
+    /// nothing about the test itself, so they aren't included.
+    recent: VecDeque<bool>,
+
+    /// Whether the test has been reported as flaky, so it's only reported
+    /// once each time it crosses the threshold.
+    flaky: bool,
+}
+
+impl History {
+    fn flips(&self) -> usize {
+        self.recent
+            .iter()
+            .zip(self.recent.iter().skip(1))
+            .filter(|(a, b)| a != b)
+            .count()
+    }
+
+    /// The flip rate in percent.  A single verdict can't flip.
+    fn flakiness(&self) -> u32 {
+        if self.recent.len() < 2 {
+            return 0;
+        }
+        (self.flips() * 100 / (self.recent.len() - 1)) as u32
+    }
+}
+
+#[derive(Default)]
+pub struct FlakinessTracker {
+    tests: HashMap<UnitName, History>,
+}
+
+impl FlakinessTracker {
+    pub fn new() -> FlakinessTracker {
+        Default::default()
+    }
+
+    /// Add the verdicts from a finished run, and return the tests that have
+    /// just become flaky.  A test is only judged once it has a full window of
+    /// pass/fail verdicts.  Runs in maintenance mode are left out.
+    pub fn record(
+        &mut self,
+        summary: &ScenarioSummary,
+        window: usize,
+        threshold: u32,
+    ) -> Vec<FlakyTest> {
+        let mut flaky = vec![];
+        if summary.maintenance {
+            return flaky;
+        }
+        for test in &summary.tests {
+            let history = self.tests.entry(test.test.clone()).or_default();
+            history.runs += 1;
+            match test.verdict {
+                TestVerdict::Pass => history.passed += 1,
+                TestVerdict::Fail => history.failed += 1,
+                TestVerdict::Fault => history.faults += 1,
+                TestVerdict::Skip | TestVerdict::Retry => history.skipped += 1,
+            }
+            match test.verdict {
+                TestVerdict::Pass | TestVerdict::Fail => {
+                    history.recent.push_back(test.verdict == TestVerdict::Pass)
+                }
+                _ => continue,
+            }
+            while history.recent.len() > window {
+                history.recent.pop_front();
+            }
+            if history.recent.len() < window.max(2) {
+                continue;
+            }
+
+            let flakiness = history.flakiness();
+            if flakiness >= threshold && !history.flaky {
+                history.flaky = true;
+                flaky.push(FlakyTest {
+                    test: test.test.clone(),
+                    flips: history.flips(),
+                    window: history.recent.len(),
+                    flakiness,
+                });
+            } else if flakiness < threshold {
+                history.flaky = false;
+            }
+        }
+        flaky
+    }
+
+    /// How every test that has run so far has done, sorted by name.
+    pub fn stats(&self) -> Vec<TestStats> {
+        let mut stats: Vec<TestStats> = self
+            .tests
+            .iter()
+            .map(|(test, history)| TestStats {
+                test: test.clone(),
+                runs: history.runs,
+                passed: history.passed,
+                failed: history.failed,
+                faults: history.faults,
+                skipped: history.skipped,
+                flakiness: history.flakiness(),
+                window: history.recent.len(),
+            })
+            .collect();
+        stats.sort_by(|a, b| a.test.id().cmp(b.test.id()));
+        stats
+    }
+
+    /// Write out every test's history, one line each, to be read back in
+    /// with from_state().  Recent verdicts are written as "P" and "F", oldest
+    /// first.
+    pub fn to_state(&self) -> String {
+        let mut tests: Vec<(&UnitName, &History)> = self.tests.iter().collect();
+        tests.sort_by(|a, b| a.0.cmp(b.0));
+        let mut contents = String::new();
+        for (test, history) in tests {
+            let recent: String = history
+                .recent
+                .iter()
+                .map(|&pass| if pass { 'P' } else { 'F' })
+                .collect();
+            contents.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                test,
+                history.runs,
+                history.passed,
+                history.failed,
+                history.faults,
+                history.skipped,
+                if recent.is_empty() { "-" } else { &recent },
+                if history.flaky { "flaky" } else { "-" },
+            ));
+        }
+        contents
+    }
+
+    /// Read back histories written by to_state().
+    pub fn from_state(contents: &str) -> Result<FlakinessTracker, UnitDescriptionError> {
+        let mut tracker = FlakinessTracker::new();
+        for line in contents.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = || {
+                UnitDescriptionError::InvalidValue(
+                    "Exclave".to_owned(),
+                    "statistics".to_owned(),
+                    line.to_owned(),
+                    vec![STATE_FORMAT.to_owned()],
+                )
+            };
+            let fields: Vec<&str> = line.trim().split('\t').collect();
+            if fields.len() != 8 {
+                return Err(invalid());
+            }
+            let mut counts = [0u64; 5];
+            for (count, field) in counts.iter_mut().zip(&fields[1..6]) {
+                *count = field.parse().map_err(|_| invalid())?;
+            }
+            let recent = match fields[6] {
+                "-" => VecDeque::new(),
+                recent => recent
+                    .chars()
+                    .map(|c| match c {
+                        'P' => Ok(true),
+                        'F' => Ok(false),
+                        _ => Err(invalid()),
+                    })
+                    .collect::<Result<VecDeque<bool>, UnitDescriptionError>>()?,
+            };
+            tracker.tests.insert(
+                UnitName::from_str(fields[0], "")?,
+                History {
+                    runs: counts[0],
+                    passed: counts[1],
+                    failed: counts[2],
+                    faults: counts[3],
+                    skipped: counts[4],
+                    recent,
+                    flaky: fields[7] == "flaky",
+                },
+            );
+        }
+        Ok(tracker)
+    }
+}
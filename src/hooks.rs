@@ -0,0 +1,278 @@
+// Commands that interfaces and jigs run when a scenario finishes, for small
+// jobs that don't deserve a logger of their own, such as copying results to
+// a USB stick or lighting a lamp:
+//
+//     OnPass=cp -r %t/runs/%R /media/usb
+//     OnFail=/usr/local/bin/lamp red
+//     HookTimeoutSec=30s
+//
+// A hook is given the run's result in its environment (see environment()),
+// its output is logged as the unit's, and it's killed if it runs for longer
+// than HookTimeoutSec.  Hooks run in the background, so a slow one doesn't
+// hold up the next run, and one that fails is only logged.
+extern crate runny;
+
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use argv;
+use config::Config;
+use duration;
+use init;
+use redact::{self, Redaction};
+use secrets::Resolved;
+use specifier;
+use unit::{UnitDescriptionError, UnitName};
+use unitbroadcaster::ScenarioSummary;
+use unitmanager::{ManagerControlMessage, ManagerControlMessageContents};
+use units::test::TestVerdict;
+
+use self::runny::running::RunningOutput;
+use self::runny::Runny;
+
+/// How long a hook may run for, unless HookTimeoutSec says otherwise.
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The commands a unit runs when a scenario passes or fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hooks {
+    /// Run when every test in a scenario passed.
+    pub on_pass: Option<String>,
+
+    /// Run when a scenario failed, hit a fault, or was aborted.
+    pub on_fail: Option<String>,
+
+    /// How long either may run for before it's killed.
+    pub timeout: Duration,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Hooks {
+            on_pass: None,
+            on_fail: None,
+            timeout: DEFAULT_HOOK_TIMEOUT,
+        }
+    }
+}
+
+impl Hooks {
+    /// Read one of OnPass, OnFail, or HookTimeoutSec from a section.
+    pub fn set(
+        &mut self,
+        section: &str,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<(), UnitDescriptionError> {
+        let value = match value {
+            Some(value) => value.trim(),
+            None => {
+                return Err(UnitDescriptionError::MissingValue(
+                    section.to_owned(),
+                    key.to_owned(),
+                ))
+            }
+        };
+        match key {
+            "OnPass" | "OnFail" => {
+                specifier::check(section, key, value)?;
+                if key == "OnPass" {
+                    self.on_pass = Some(value.to_owned());
+                } else {
+                    self.on_fail = Some(value.to_owned());
+                }
+            }
+            "HookTimeoutSec" => self.timeout = duration::parse(value)?,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Check that the hooks can be split into arguments, for units that
+    /// don't run them with the shell.
+    pub fn check(&self, section: &str, shell: bool) -> Result<(), UnitDescriptionError> {
+        if !shell {
+            if let Some(ref on_pass) = self.on_pass {
+                argv::check(section, "OnPass", on_pass)?;
+            }
+            if let Some(ref on_fail) = self.on_fail {
+                argv::check(section, "OnFail", on_fail)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The hook to run for a finished scenario, if there is one.
+    pub fn command(&self, summary: &ScenarioSummary) -> Option<&str> {
+        match summary.verdict() {
+            TestVerdict::Pass => self.on_pass.as_deref(),
+            _ => self.on_fail.as_deref(),
+        }
+    }
+
+    /// Start the hook for a finished scenario, if there is one, from a unit's
+    /// working directory.
+    pub fn run(
+        &self,
+        unit: &UnitName,
+        summary: &ScenarioSummary,
+        shell: bool,
+        directory: PathBuf,
+        config: &Config,
+        ctrl: &Sender<ManagerControlMessage>,
+    ) {
+        let command = match self.command(summary) {
+            Some(command) => command,
+            None => return,
+        };
+        let log = |contents: ManagerControlMessageContents| {
+            ctrl.send(ManagerControlMessage::new(unit, contents)).ok();
+        };
+        let key = match summary.verdict() {
+            TestVerdict::Pass => "OnPass",
+            _ => "OnFail",
+        };
+        // Only the command as written is logged, never with its secrets.
+        let resolved = match config.resolve_command(unit, command, shell) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                log(ManagerControlMessageContents::LogError(format!(
+                    "unable to run {}: {}",
+                    key, e
+                )));
+                return;
+            }
+        };
+        let mut runny = Runny::new(&with_environment(&environment(summary), &resolved.text));
+        runny.timeout(self.timeout);
+        runny.directory(&Some(directory));
+        log(ManagerControlMessageContents::Log(format!(
+            "{}: starting [{}]",
+            key, command
+        )));
+        let started = Instant::now();
+        let mut running = match runny.start() {
+            Ok(running) => running,
+            Err(e) => {
+                log(ManagerControlMessageContents::LogError(format!(
+                    "unable to run {}: {:?}",
+                    key, e
+                )));
+                return;
+            }
+        };
+        init::spawned(running.pid());
+        let redactions = config.redactions().to_vec();
+        let output = [running.take_output(), running.take_error()].map(|output| {
+            let (unit, ctrl, resolved, redactions) = (
+                unit.clone(),
+                ctrl.clone(),
+                resolved.clone(),
+                redactions.clone(),
+            );
+            thread::spawn(move || log_output(unit, ctrl, output, resolved, redactions))
+        });
+        let waiter = running.waiter();
+        let (unit, ctrl, timeout) = (unit.clone(), ctrl.clone(), self.timeout);
+        thread::spawn(move || {
+            // The hook is owned here until it exits, so it isn't killed as
+            // soon as it's started.
+            let _running = running;
+            waiter.wait();
+            for output in output {
+                output.join().ok();
+            }
+            let contents = match waiter.result() {
+                0 => ManagerControlMessageContents::Log(format!("{}: finished", key)),
+                _ if started.elapsed() >= timeout => ManagerControlMessageContents::LogError(
+                    format!("{} timed out after {:?}", key, timeout),
+                ),
+                code => {
+                    ManagerControlMessageContents::LogError(format!("{} exited with {}", key, code))
+                }
+            };
+            ctrl.send(ManagerControlMessage::new(&unit, contents)).ok();
+        });
+    }
+}
+
+/// What a hook is told about the run that finished, as environment variables.
+pub fn environment(summary: &ScenarioSummary) -> Vec<(&'static str, String)> {
+    let or_empty = |value: Option<&String>| value.cloned().unwrap_or_default();
+    vec![
+        ("EXCLAVE_SCENARIO", summary.scenario.to_string()),
+        (
+            "EXCLAVE_JIG",
+            summary
+                .jig
+                .as_ref()
+                .map(|jig| jig.to_string())
+                .unwrap_or_default(),
+        ),
+        ("EXCLAVE_VERDICT", summary.verdict().to_string()),
+        ("EXCLAVE_CODE", summary.code.to_string()),
+        ("EXCLAVE_REASON", summary.reason.clone()),
+        ("EXCLAVE_SERIAL", or_empty(summary.serial.as_ref())),
+        ("EXCLAVE_RUN_ID", or_empty(summary.run_id.as_ref())),
+        ("EXCLAVE_PAYLOAD", or_empty(summary.payload.as_ref())),
+        ("EXCLAVE_PASSED", summary.passed.to_string()),
+        ("EXCLAVE_FAILED", summary.failed.to_string()),
+        ("EXCLAVE_FAULTS", summary.faults.to_string()),
+        ("EXCLAVE_SKIPPED", summary.skipped.to_string()),
+        (
+            "EXCLAVE_FIRST_FAILURE",
+            summary
+                .first_failure
+                .as_ref()
+                .map(|(test, _)| test.to_string())
+                .unwrap_or_default(),
+        ),
+    ]
+}
+
+/// Wrap a command, in the form Runny runs, so that it runs with extra
+/// environment variables.  Runny doesn't pass an environment through, so
+/// it's set with env(1).
+#[cfg(unix)]
+fn with_environment(vars: &[(&str, String)], command: &str) -> String {
+    let mut wrapped = vec!["/usr/bin/env".to_owned()];
+    for (name, value) in vars {
+        wrapped.push(argv::quote(&format!("{}={}", name, value)));
+    }
+    wrapped.push(command.to_owned());
+    wrapped.join(" ")
+}
+
+/// There's no env(1) to set variables with on this platform.
+#[cfg(not(unix))]
+fn with_environment(_vars: &[(&str, String)], command: &str) -> String {
+    command.to_owned()
+}
+
+/// Log each line a hook prints, with its secrets masked and redactions made.
+fn log_output(
+    unit: UnitName,
+    ctrl: Sender<ManagerControlMessage>,
+    output: RunningOutput,
+    resolved: Resolved,
+    redactions: Vec<Redaction>,
+) {
+    for line in BufReader::new(output).lines() {
+        let line = match line {
+            Ok(line) => redact::apply(&redactions, &resolved.mask(&line)),
+            Err(_) => break,
+        };
+        if ctrl
+            .send(ManagerControlMessage::new(
+                &unit,
+                ManagerControlMessageContents::Log(line),
+            ))
+            .is_err()
+        {
+            break;
+        }
+    }
+}
@@ -0,0 +1,167 @@
+// Lets exclave be the first process (PID 1) of a container used as a station
+// OS, which it does when started with "--init" or as PID 1.  The first
+// process has jobs that nothing else does:
+//
+// * Processes whose parent exits are handed to it, and it has to wait for
+//   them when they exit, or they stay around as zombies.  exclave also asks
+//   to be handed everything its tests leave behind when not PID 1, so that
+//   daemons started by tests don't end up elsewhere.
+// * It starts with whatever signal settings and environment the container
+//   runtime gave it, which its tests then inherit.  Signals are put back to
+//   their defaults, and only well-known variables, and any named with
+//   "--keep-env", are kept.
+//
+// Only orphans are waited for.  Programs that exclave starts itself are
+// waited for by whatever started them, which may not be until long after
+// they exit, and whose exit status would be lost if anything else waited for
+// them first.  They're noted in a registry as they're started, and an orphan
+// is a zombie that isn't in it, is in another session than exclave (as
+// programs started without Runny aren't), and has been left for a whole
+// REAP_INTERVAL.
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::io;
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use poison::Lock;
+use sys;
+
+/// How often to look for orphans that have exited.
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Variables that are kept when the environment is scrubbed.
+const KEPT_VARIABLES: &[&str] = &[
+    "HOME",
+    "LANG",
+    "LOGNAME",
+    "PATH",
+    "SHELL",
+    "STATE_DIRECTORY",
+    "TERM",
+    "TZ",
+    "USER",
+];
+
+/// Programs that exclave started itself, by process ID, with the time each
+/// started, so that a process ID that has since been used again isn't
+/// mistaken for one of them.
+static SPAWNED: Mutex<BTreeMap<i32, u64>> = Mutex::new(BTreeMap::new());
+
+/// The search path to use if the container didn't set one.
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Whether this is the first process, and so needs to act as init whether
+/// it was asked to or not.
+pub fn is_pid1() -> bool {
+    process::id() == 1
+}
+
+/// Which of a list of environment variables are to be removed, keeping the
+/// well-known ones, the locale's LC_ variables, and any in `keep`.
+pub fn scrubbed_variables<I: IntoIterator<Item = String>>(
+    names: I,
+    keep: &[String],
+) -> Vec<String> {
+    names
+        .into_iter()
+        .filter(|name| {
+            !KEPT_VARIABLES.contains(&name.as_str())
+                && !name.starts_with("LC_")
+                && !keep.contains(name)
+        })
+        .collect()
+}
+
+/// Remove every environment variable that isn't kept, and set PATH if it's
+/// missing.  This must be called before any other threads are spawned.
+fn scrub_environment(keep: &[String]) {
+    let names = env::vars_os().filter_map(|(name, _)| name.into_string().ok());
+    for name in scrubbed_variables(names, keep) {
+        env::remove_var(name);
+    }
+    if env::var_os("PATH").is_none() {
+        env::set_var("PATH", DEFAULT_PATH);
+    }
+}
+
+/// Set up everything the first process needs to do.  This must be called
+/// before any other threads are spawned, since they inherit the signal mask,
+/// and changing the environment isn't safe once there are.
+pub fn become_init(keep: &[String]) -> io::Result<()> {
+    sys::reset_signals()?;
+    scrub_environment(keep);
+    // The first process is given orphans whether it asks or not.
+    if let Err(e) = sys::set_child_subreaper() {
+        if !is_pid1() {
+            return Err(e);
+        }
+    }
+    let mut reaper = Reaper::new();
+    thread::spawn(move || loop {
+        thread::sleep(REAP_INTERVAL);
+        reaper.reap();
+    });
+    Ok(())
+}
+
+/// Note that exclave started a program itself, so that whatever started it
+/// is left to wait for it.
+pub fn spawned(pid: i32) {
+    if let Some(start_time) = sys::start_time(pid) {
+        SPAWNED.locked().insert(pid, start_time);
+    }
+}
+
+/// Whether a process is one that exclave started itself.
+pub fn is_spawned(pid: i32, start_time: u64) -> bool {
+    SPAWNED.locked().get(&pid) == Some(&start_time)
+}
+
+/// Waits for orphans that have exited.
+#[derive(Default)]
+pub struct Reaper {
+    /// The orphans that had exited at the last look, which are waited for if
+    /// they're still there at the next.
+    exited: HashSet<i32>,
+}
+
+impl Reaper {
+    pub fn new() -> Reaper {
+        Reaper::default()
+    }
+
+    /// Wait for every orphan that has been a zombie since the last call,
+    /// returning their process IDs.
+    pub fn reap(&mut self) -> Vec<i32> {
+        // Forget programs that have been waited for, and so are gone.
+        SPAWNED
+            .locked()
+            .retain(|pid, start_time| sys::start_time(*pid) == Some(*start_time));
+        let exited: HashSet<i32> = sys::orphaned_zombies().into_iter().collect();
+        let reaped = exited
+            .iter()
+            .cloned()
+            .filter(|pid| self.exited.contains(pid) && sys::reap(*pid))
+            .collect();
+        self.exited = exited;
+        reaped
+    }
+}
+
+/// The state, parent, session, and start time of a process, from the
+/// contents of its /proc/<pid>/stat.
+pub fn parse_stat(stat: &str) -> Option<(char, i32, i32, u64)> {
+    // The command name is in parentheses, and may itself contain spaces
+    // and parentheses, so the fields start after the last ")".
+    let (_, rest) = stat.rsplit_once(')')?;
+    let mut fields = rest.split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let parent = fields.next()?.parse().ok()?;
+    let _group = fields.next()?;
+    let session = fields.next()?.parse().ok()?;
+    let start_time = fields.nth(15)?.parse().ok()?;
+    Some((state, parent, session, start_time))
+}
@@ -0,0 +1,137 @@
+// Triggers that only start a run together with others, or while something
+// about the fixture holds.  A trigger made of others lists them with AllOf,
+// and starts a run once each of them has sent START within its Window, as
+// for a press that needs both hands on buttons:
+//
+//     [Trigger]
+//     Name=Both hands
+//     AllOf=left-button right-button
+//     Window=500ms
+//
+// The triggers it's made of no longer start runs on their own.  Any trigger
+// can also be held back until others report a state with STATE, such as a
+// sensor that reports "STATE closed" and "STATE open" as the fixture's lid
+// moves:
+//
+//     RequireState=lid-sensor closed
+//
+// States are only kept while the trigger that reported them is running, so
+// a sensor that exits or is stopped holds everything that depends on it back.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use unit::{UnitDescriptionError, UnitName};
+
+/// How long, by default, every trigger in an AllOf has to send START in.
+pub const DEFAULT_WINDOW: Duration = Duration::from_millis(500);
+
+/// A state that another trigger must have reported, from RequireState.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequiredState {
+    pub trigger: UnitName,
+    pub state: String,
+}
+
+impl RequiredState {
+    /// Read a RequireState directive, such as "lid-sensor closed".
+    pub fn parse(value: &str) -> Result<RequiredState, UnitDescriptionError> {
+        let invalid = || {
+            UnitDescriptionError::InvalidValue(
+                "Trigger".to_owned(),
+                "RequireState".to_owned(),
+                value.to_owned(),
+                vec!["a trigger and the state it must report".to_owned()],
+            )
+        };
+        let (trigger, state) = value
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(invalid)?;
+        let state = state.trim();
+        if state.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        Ok(RequiredState {
+            trigger: UnitName::from_str(trigger, "trigger").map_err(|_| invalid())?,
+            state: state.to_owned(),
+        })
+    }
+}
+
+/// Keeps track of which triggers have sent START lately, and the state each
+/// trigger last reported.
+#[derive(Default)]
+pub struct Interlock {
+    presses: HashMap<UnitName, Instant>,
+    states: HashMap<UnitName, String>,
+}
+
+impl Interlock {
+    pub fn new() -> Self {
+        Interlock::default()
+    }
+
+    /// Note that a trigger sent START.
+    pub fn press(&mut self, trigger: &UnitName, now: Instant) {
+        self.presses.insert(trigger.clone(), now);
+    }
+
+    /// Whether every one of some triggers has sent START within a window
+    /// before now.  If so, they're forgotten, so the next run needs them all
+    /// again.
+    pub fn together(&mut self, triggers: &[UnitName], window: Duration, now: Instant) -> bool {
+        let all = triggers.iter().all(|trigger| {
+            self.presses
+                .get(trigger)
+                .is_some_and(|pressed| now.saturating_duration_since(*pressed) <= window)
+        });
+        if all {
+            for trigger in triggers {
+                self.presses.remove(trigger);
+            }
+        }
+        all
+    }
+
+    /// Note the state a trigger reported.  Returns true if it changed.
+    pub fn set_state(&mut self, trigger: &UnitName, state: &str) -> bool {
+        self.states
+            .insert(trigger.clone(), state.to_owned())
+            .as_deref()
+            != Some(state)
+    }
+
+    /// The state a trigger last reported, if it's running and has reported one.
+    pub fn state(&self, trigger: &UnitName) -> Option<&str> {
+        self.states.get(trigger).map(|state| state.as_str())
+    }
+
+    /// Check that other triggers are in the states a trigger requires, or
+    /// say which isn't.
+    pub fn check(&self, required: &[RequiredState]) -> Result<(), String> {
+        for requirement in required {
+            match self.state(&requirement.trigger) {
+                Some(state) if state == requirement.state => (),
+                Some(state) => {
+                    return Err(format!(
+                        "{} is {}, not {}",
+                        requirement.trigger, state, requirement.state
+                    ))
+                }
+                None => {
+                    return Err(format!(
+                        "{} hasn't reported that it's {}",
+                        requirement.trigger, requirement.state
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget about a trigger that has stopped.
+    pub fn forget(&mut self, trigger: &UnitName) {
+        self.presses.remove(trigger);
+        self.states.remove(trigger);
+    }
+}
@@ -0,0 +1,76 @@
+//! The core of exclave: unit files and their parsing, dependency
+//! resolution, the manager that runs units, and the broadcaster that ties
+//! them together.  The `exclave` daemon is a thin wrapper around this, and
+//! other programs may embed it or reuse parts of it, such as loading unit
+//! files without running anything.
+
+// The vendored serde_derive predates the non_local_definitions and
+// unexpected_cfgs lints, and trips both on every derive.
+#![allow(non_local_definitions, unexpected_cfgs)]
+
+#[cfg(unix)]
+extern crate nix;
+
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+
+pub mod agent;
+pub mod argv;
+pub mod cbor;
+pub mod certificate;
+pub mod cgroup;
+pub mod chaos;
+pub mod check;
+pub mod clock;
+pub mod clockcheck;
+pub mod concurrency;
+pub mod config;
+pub mod container;
+pub mod coredump;
+pub mod cost;
+pub mod duration;
+pub mod ed25519;
+pub mod estimate;
+pub mod evdev;
+pub mod fetch;
+pub mod flakiness;
+pub mod hooks;
+pub mod init;
+pub mod interlock;
+pub mod parameter;
+pub mod poison;
+pub mod presentation;
+pub mod profile;
+pub mod quiesce;
+pub mod redact;
+pub mod replay;
+pub mod results;
+pub mod schema;
+pub mod secrets;
+pub mod sha256;
+pub mod snapshot;
+pub mod soak;
+pub mod specifier;
+pub mod station;
+pub mod store;
+pub mod supervisor;
+pub mod sys;
+pub mod testing;
+pub mod timeline;
+pub mod unit;
+pub mod unitbroadcaster;
+pub mod unitcache;
+pub mod unitfile;
+pub mod unitlibrary;
+pub mod unitloader;
+pub mod unitmanager;
+pub mod units;
+pub mod unitstate;
+pub mod unitwatcher;
+pub mod uploader;
+pub mod version;
+
+#[cfg(test)]
+mod test;
@@ -1,52 +1,38 @@
 extern crate clap;
 extern crate ctrlc;
-
-#[macro_use]
-extern crate serde_derive;
-extern crate serde;
-extern crate serde_json;
+extern crate exclave;
 
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
 
-mod config;
-mod quiesce;
 mod terminal;
-mod unit;
-mod unitbroadcaster;
-mod unitlibrary;
-mod unitloader;
-mod unitmanager;
-mod units;
-mod unitwatcher;
-
-use unitbroadcaster::{UnitBroadcaster, UnitEvent};
-use unitlibrary::UnitLibrary;
-use unitloader::UnitLoader;
-use unitwatcher::UnitWatcher;
+
+use exclave::agent;
+use exclave::chaos;
+use exclave::clockcheck::ClockCheck;
+use exclave::config;
+use exclave::init;
+use exclave::profile::{self, Timing};
+use exclave::quiesce;
+use exclave::replay::{self, EventRecorder};
+use exclave::results::ResultRecorder;
+use exclave::sys::watch_for_sighup;
+use exclave::timeline;
+use exclave::unit::UnitKind;
+use exclave::unitbroadcaster::{UnitBroadcaster, UnitEvent};
+use exclave::unitlibrary::UnitLibrary;
+use exclave::unitloader::UnitLoader;
+use exclave::unitwatcher::UnitWatcher;
+use exclave::uploader::Uploader;
+use exclave::version;
 
 use clap::{App, Arg};
 
+#[allow(clippy::arc_with_non_send_sync)]
 fn main() {
-    let config = Arc::new(Mutex::new(config::Config::new()));
-
-    let unit_broadcaster = UnitBroadcaster::new();
-    let message_receiver = unit_broadcaster.subscribe();
-    let unit_library = UnitLibrary::new(&unit_broadcaster, &config);
-    let unit_loader = UnitLoader::new(&unit_broadcaster);
-    let mut unit_watcher = UnitWatcher::new(&unit_broadcaster);
-
-    // The signal handler must come first, so that the same mask gets
-    // applied to all threads.
-    let ctrl_c_broadcaster = unit_broadcaster.clone();
-    ctrlc::set_handler(move || {
-        ctrl_c_broadcaster.broadcast(&UnitEvent::Shutdown);
-    })
-    .expect("Error setting Ctrl-C handler");
-
+    let long_version = version::summary();
     let matches = App::new("Exclave Testing System")
-        .version(env!("CARGO_PKG_VERSION"))
-        .long_version(env!("GIT_VERSION"))
+        .version(version::VERSION)
+        .long_version(long_version.as_str())
         .author("Sean Cross <sean@xobs.io>")
         .about("Orchestrates the Common Factory Test Interface server")
         .arg(
@@ -55,11 +41,44 @@ fn main() {
                 .long("config-dir")
                 .value_name("CONFIG_DIR")
                 .number_of_values(1)
-                .required(true)
+                .required_unless_one(&["CONFIG_FILE", "AGENT"])
                 .multiple(true)
                 .takes_value(true)
                 .help("Directory where configuration unit files are stored"),
         )
+        .arg(
+            Arg::with_name("TESTS_DIR")
+                .long("tests-dir")
+                .value_name("DIR")
+                .number_of_values(1)
+                .multiple(true)
+                .takes_value(true)
+                .help("Additional directory to load only .test units from"),
+        )
+        .arg(
+            Arg::with_name("SCENARIOS_DIR")
+                .long("scenarios-dir")
+                .value_name("DIR")
+                .number_of_values(1)
+                .multiple(true)
+                .takes_value(true)
+                .help("Additional directory to load only .scenario units from"),
+        )
+        .arg(
+            Arg::with_name("CONFIG_FILE")
+                .short("f")
+                .long("config-file")
+                .value_name("CONFIG_FILE")
+                .takes_value(true)
+                .help("Settings file, re-read on SIGHUP or RELOAD CONFIG"),
+        )
+        .arg(
+            Arg::with_name("STATE_DIR")
+                .long("state-dir")
+                .value_name("DIR")
+                .takes_value(true)
+                .help("Directory to keep runtime state in, overriding StateDirectory"),
+        )
         .arg(
             Arg::with_name("PLAIN")
                 .short("p")
@@ -86,9 +105,180 @@ fn main() {
                 .long("keyboard-trigger")
                 .help("Run default scenario on enter key press"),
         )
+        .arg(
+            Arg::with_name("RECORD")
+                .long("record")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Record every event, with its timing, to the specified file"),
+        )
+        .arg(
+            Arg::with_name("REPLAY")
+                .long("replay")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Replay a recording once units are loaded, instead of running scenarios"),
+        )
+        .arg(
+            Arg::with_name("REPLAY_SPEED")
+                .long("replay-speed")
+                .value_name("SPEED")
+                .takes_value(true)
+                .requires("REPLAY")
+                .help("How much faster to replay the recording, or 0 for no delays"),
+        )
+        .arg(
+            Arg::with_name("SNAPSHOT")
+                .long("snapshot")
+                .value_name("FILE")
+                .takes_value(true)
+                .conflicts_with("REPLAY")
+                .help("Save a .tar.gz of the configuration once units are loaded, then exit"),
+        )
+        .arg(
+            Arg::with_name("CHAOS")
+                .long("chaos")
+                .value_name("PROFILE")
+                .takes_value(true)
+                .help("Inject faults at random, such as \"seed=1,delay=0.05,spawn=0.02\", for testing"),
+        )
+        .arg(
+            Arg::with_name("AGENT")
+                .long("agent")
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .requires("AGENT_KEY")
+                .help("Run as an agent on the specified address, running tests for a station"),
+        )
+        .arg(
+            Arg::with_name("AGENT_KEY")
+                .long("agent-key")
+                .value_name("FILE")
+                .takes_value(true)
+                .requires("AGENT")
+                .help("File holding the key that stations must have to use this agent"),
+        )
+        .arg(
+            Arg::with_name("AGENT_DIR")
+                .long("agent-dir")
+                .value_name("DIR")
+                .takes_value(true)
+                .requires("AGENT")
+                .help("Directory that an agent runs tests from, if not the current one"),
+        )
+        .arg(
+            Arg::with_name("INIT")
+                .long("init")
+                .help("Act as the first process of a container, even if not PID 1"),
+        )
+        .arg(
+            Arg::with_name("KEEP_ENV")
+                .long("keep-env")
+                .value_name("NAME")
+                .number_of_values(1)
+                .multiple(true)
+                .takes_value(true)
+                .help("Environment variable to keep when acting as init"),
+        )
         .get_matches();
 
-    let config_dirs: Vec<_> = matches.values_of("CONFIG_DIR").unwrap().collect();
+    // This must come before any threads are spawned, as they'd inherit the
+    // signal mask, and the environment can't safely be changed once they are.
+    if matches.is_present("INIT") || init::is_pid1() {
+        let keep: Vec<String> = matches
+            .values_of("KEEP_ENV")
+            .into_iter()
+            .flatten()
+            .map(|name| name.to_owned())
+            .collect();
+        init::become_init(&keep).unwrap_or_else(|e| panic!("Unable to act as init: {}", e));
+    }
+
+    // Before anything is started, so that the first events can be delayed
+    // and the first programs can fail.
+    let chaos_profile = matches.value_of("CHAOS").map(|text| {
+        let profile = chaos::Profile::parse(text)
+            .unwrap_or_else(|e| panic!("Invalid chaos profile {}: {}", text, e));
+        chaos::enable(profile.clone());
+        profile
+    });
+
+    let config = Arc::new(Mutex::new(config::Config::new()));
+
+    let unit_broadcaster = UnitBroadcaster::new();
+
+    // SIGHUP must be blocked before any other threads are spawned, so that
+    // it only gets delivered to the thread waiting for it.
+    watch_for_sighup(&unit_broadcaster);
+
+    let unit_library = UnitLibrary::new(&unit_broadcaster, &config);
+    let message_receiver = unit_library.get_manager().borrow().subscribe();
+    let unit_loader = UnitLoader::new(&unit_broadcaster);
+    let mut unit_watcher = UnitWatcher::new(&unit_broadcaster);
+    let uploader = Uploader::new(&unit_broadcaster, &config);
+    let result_recorder = ResultRecorder::new(&unit_broadcaster, &config);
+
+    // The signal handler must come first, so that the same mask gets
+    // applied to all threads.
+    let ctrl_c_broadcaster = unit_broadcaster.clone();
+    ctrlc::set_handler(move || {
+        ctrl_c_broadcaster.broadcast(&UnitEvent::Shutdown);
+    })
+    .expect("Error setting Ctrl-C handler");
+
+    if let Some(address) = matches.value_of("AGENT") {
+        let key_file = matches.value_of("AGENT_KEY").unwrap();
+        let key = agent::read_key(std::path::Path::new(key_file))
+            .unwrap_or_else(|e| panic!("Unable to load agent key: {}", e));
+        let directory = match matches.value_of("AGENT_DIR") {
+            Some(dir) => std::path::PathBuf::from(dir),
+            None => std::env::current_dir().expect("Unable to get current directory"),
+        };
+        let listener = std::net::TcpListener::bind(address)
+            .unwrap_or_else(|e| panic!("Unable to listen on {}: {}", address, e));
+        agent::serve(listener, key, directory).unwrap_or_else(|e| panic!("Agent stopped: {}", e));
+        return;
+    }
+
+    let search_paths = {
+        let mut config = config.lock().unwrap();
+        for dir in matches.values_of("CONFIG_DIR").into_iter().flatten() {
+            config.add_unit_directory(std::path::Path::new(dir));
+        }
+        for (arg, kind) in &[
+            ("TESTS_DIR", UnitKind::Test),
+            ("SCENARIOS_DIR", UnitKind::Scenario),
+        ] {
+            for dir in matches.values_of(arg).into_iter().flatten() {
+                config.add_kind_directory(kind.clone(), std::path::Path::new(dir));
+            }
+        }
+        if let Some(config_file) = matches.value_of("CONFIG_FILE") {
+            config
+                .load_file(std::path::Path::new(config_file))
+                .unwrap_or_else(|e| panic!("Unable to load config file {}: {}", config_file, e));
+        }
+        // Under systemd, StateDirectory= in the service file gives a
+        // writable directory even when the root filesystem is read-only.
+        let state_dir = matches
+            .value_of("STATE_DIR")
+            .map(std::path::PathBuf::from)
+            .or_else(|| match config.state_directory() {
+                Some(_) => None,
+                None => std::env::var("STATE_DIRECTORY")
+                    .ok()
+                    .and_then(|dirs| dirs.split(':').next().map(std::path::PathBuf::from))
+                    .filter(|dir| !dir.as_os_str().is_empty()),
+            });
+        if let Some(dir) = state_dir {
+            let display = dir.display().to_string();
+            config
+                .set_state_directory(Some(dir))
+                .unwrap_or_else(|e| panic!("Unable to use state directory {}: {}", display, e));
+        }
+        config.search_paths()
+    };
+
     let output_type = if matches.is_present("PLAIN") {
         Some(terminal::TerminalOutputType::Plain)
     } else if matches.is_present("QUIET") {
@@ -103,15 +293,67 @@ fn main() {
         matches.is_present("KEYBOARD_TRIGGER"),
     );
 
-    for config_dir in config_dirs {
+    // Timings are only measured if there's somewhere to serve them from,
+    // and that has to be known before the first scan.
+    let station_labels = config.lock().unwrap().station().labels();
+    if let Some(address) = config.lock().unwrap().metrics_address() {
+        let listener = std::net::TcpListener::bind(address)
+            .unwrap_or_else(|e| panic!("Unable to serve metrics on {}: {}", address, e));
+        profile::set_labels(station_labels);
+        profile::enable();
+        profile::serve(listener);
+    }
+
+    // The clock check reads its settings from the config file, so start it
+    // only once that's loaded.
+    let clock_check = ClockCheck::new(&unit_broadcaster, &config);
+
+    // Only now that the state directory is known, so that the saved cache
+    // is used.
+    unit_watcher.set_unit_cache(&unit_library.unit_cache());
+    unit_watcher.set_lazy_tests(config.lock().unwrap().lazy_tests());
+    for (dir, kind) in search_paths {
         unit_watcher
-            .add_path(config_dir)
-            .unwrap_or_else(|_| panic!("Unable to add config directory {}", config_dir));
+            .add_search_path(&dir, kind)
+            .unwrap_or_else(|_| panic!("Unable to add config directory {}", dir.display()));
     }
 
-    let mut quiesce = quiesce::Quiesce::new(Duration::from_secs(1), &unit_broadcaster);
+    let mut quiesce = quiesce::Quiesce::new(&unit_broadcaster, &config);
+
+    unit_broadcaster.log(
+        "main",
+        format!("Exclave {} initializing", version::summary()),
+    );
+    if let Some(profile) = chaos_profile {
+        unit_broadcaster.log(
+            "main",
+            format!("Injecting faults with chaos profile {}", profile),
+        );
+    }
+
+    let mut recorder = matches.value_of("RECORD").map(|path| {
+        EventRecorder::create(std::path::Path::new(path))
+            .unwrap_or_else(|e| panic!("Unable to create recording {}: {}", path, e))
+    });
+
+    // The replay starts once the first rescan finishes, so that the units
+    // watching it are loaded.
+    let mut replay_path = matches.value_of("REPLAY").map(std::path::PathBuf::from);
+    let replay_speed: f64 = matches
+        .value_of("REPLAY_SPEED")
+        .map(|s| {
+            s.parse()
+                .ok()
+                .filter(|speed: &f64| *speed >= 0.0)
+                .unwrap_or_else(|| panic!("Invalid replay speed {}", s))
+        })
+        .unwrap_or(1.0);
+    if replay_path.is_some() {
+        unit_library.get_manager().borrow().set_replaying(true);
+    }
 
-    unit_broadcaster.log("main", "Exclave initializing".to_string());
+    // Likewise, the snapshot is taken once the units have loaded.
+    let mut snapshot_path = matches.value_of("SNAPSHOT").map(std::path::PathBuf::from);
 
     let mut debug_file = match matches.value_of("DEBUG_LOGFILE") {
         None => None,
@@ -119,37 +361,52 @@ fn main() {
             use std::fs::File;
             use std::path::Path;
             let path = Path::new(dv);
-            Some(File::create(&path).expect("Couldn't create logfile"))
+            Some(File::create(path).expect("Couldn't create logfile"))
         }
     };
     // Main message loop.  Monitor messages and pass them to each component.
-    let mut loops = 1;
-    while let Ok(msg) = message_receiver.recv() {
+    while let Ok(stamped) = message_receiver.recv() {
+        if profile::is_enabled() {
+            // The monotonic clock isn't stepped, unlike the time of day.
+            let waited = timeline::nanos().saturating_sub(stamped.stamp.monotonic_nsecs);
+            profile::record(Timing::Dispatch, std::time::Duration::from_nanos(waited));
+        }
         if let Some(file) = debug_file.as_mut() {
             use std::io::Write;
-            use std::time;
-
-            let now = match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
-                Ok(d) => d,
-                Err(_) => time::Duration::new(0, 0),
-            };
-
-            let unix_time = now.as_secs();
-            let unix_time_nsecs = now.subsec_nanos();
 
             writeln!(
                 file,
-                "{}:{}.{} {:?}",
-                loops, unix_time, unix_time_nsecs, msg
+                "{}:{}.{:09} {:?}",
+                stamped.stamp.sequence,
+                stamped.stamp.unix_time,
+                stamped.stamp.unix_time_nsecs,
+                stamped.event
             )
             .expect("Couldn't write message to logfile");
         }
-        loops += 1;
-        unit_loader.process_message(&msg);
-        unit_library.process_message(&msg);
-        quiesce.process_message(&msg);
+        // Recording stops at the first error, rather than failing on every event.
+        if let Some(Err(e)) = recorder.as_mut().map(|r| r.record(&stamped)) {
+            recorder = None;
+            unit_broadcaster.log("main", format!("Unable to record events: {}", e));
+        }
+        let msg = &stamped.event;
+        if *msg == UnitEvent::RescanFinish {
+            if let Some(path) = replay_path.take() {
+                replay::replay(&path, &unit_broadcaster, replay_speed);
+            }
+            if let Some(path) = snapshot_path.take() {
+                unit_library.snapshot().write(&path).unwrap_or_else(|e| {
+                    panic!("Unable to save snapshot {}: {}", path.display(), e)
+                });
+                println!("Saved snapshot to {}", path.display());
+                unit_broadcaster.broadcast(&UnitEvent::Shutdown);
+            }
+        }
+        unit_loader.process_message(msg);
+        unit_library.process_message(msg);
+        quiesce.process_message(msg);
+        uploader.process_message(msg);
+        result_recorder.process_message(msg);
+        clock_check.process_message(msg);
     }
 }
-
-#[cfg(test)]
-mod test;
@@ -0,0 +1,313 @@
+// Values that a scenario needs to be given each time it's started, such as
+// the radio channel to test on.  A scenario declares each with a directive
+// such as "Param=channel required int 1..11", interfaces give them with
+// "START scenario channel=6", and exclave checks them before the scenario
+// starts.  Tests get each value in $EXCLAVE_PARAM_<NAME>, and the values are
+// kept with the run's results.
+//
+// A scenario can also be run over a matrix of values, with directives such
+// as "Matrix=frequency 2412,2437,2462" and "Matrix=power 10,20".  It then
+// makes one pass through its tests for each combination, the last axis
+// changing fastest, and the values of each pass are given to its tests in
+// the same way.
+use std::fmt;
+
+use unit::UnitDescriptionError;
+
+/// The sort of value a parameter takes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParameterKind {
+    /// A whole number, between the two given (inclusive) if there are any.
+    Integer(Option<(i64, i64)>),
+
+    /// Any text.
+    Text,
+
+    /// One of a fixed set of values.
+    Choice(Vec<String>),
+}
+
+/// A parameter that a scenario declares.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameter {
+    name: String,
+    required: bool,
+    kind: ParameterKind,
+}
+
+impl Parameter {
+    /// The forms that a Param directive's value can take.
+    fn forms() -> Vec<String> {
+        vec![
+            "<name> required|optional int".to_owned(),
+            "<name> required|optional int <min>..<max>".to_owned(),
+            "<name> required|optional text".to_owned(),
+            "<name> required|optional choice <value>,<value>,...".to_owned(),
+        ]
+    }
+
+    /// Parse the value of a Param directive, such as
+    /// "channel required int 1..11".  Names are made of letters, digits,
+    /// "_", and "-".
+    pub fn parse(value: &str) -> Result<Parameter, UnitDescriptionError> {
+        let invalid = || {
+            UnitDescriptionError::InvalidValue(
+                "Scenario".to_owned(),
+                "Param".to_owned(),
+                value.to_owned(),
+                Self::forms(),
+            )
+        };
+        let words: Vec<&str> = value.split_whitespace().collect();
+        let name = match words.first() {
+            Some(name) if is_name(name) => name,
+            _ => return Err(invalid()),
+        };
+        let required = match words.get(1) {
+            Some(&"required") => true,
+            Some(&"optional") => false,
+            _ => return Err(invalid()),
+        };
+        let kind = match words[2..] {
+            ["int"] | ["integer"] => ParameterKind::Integer(None),
+            ["int", range] | ["integer", range] => {
+                let (min, max) = range.split_once("..").ok_or_else(invalid)?;
+                match (min.parse::<i64>(), max.parse::<i64>()) {
+                    (Ok(min), Ok(max)) if min <= max => ParameterKind::Integer(Some((min, max))),
+                    _ => return Err(invalid()),
+                }
+            }
+            ["text"] => ParameterKind::Text,
+            ["choice", choices] => {
+                let choices: Vec<String> = choices
+                    .split(',')
+                    .filter(|c| !c.is_empty())
+                    .map(|c| c.to_owned())
+                    .collect();
+                if choices.is_empty() {
+                    return Err(invalid());
+                }
+                ParameterKind::Choice(choices)
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(Parameter {
+            name: name.to_string(),
+            required,
+            kind,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
+    /// The environment variable tests are given the value in, such as
+    /// "EXCLAVE_PARAM_CHANNEL".
+    pub fn variable(name: &str) -> String {
+        let name: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("EXCLAVE_PARAM_{}", name)
+    }
+
+    /// Check that a value is one this parameter takes.
+    pub fn check(&self, value: &str) -> Result<(), String> {
+        match self.kind {
+            ParameterKind::Integer(range) => {
+                let number = value
+                    .parse::<i64>()
+                    .map_err(|_| format!("{} must be a whole number, not {}", self.name, value))?;
+                match range {
+                    Some((min, max)) if number < min || number > max => Err(format!(
+                        "{} must be from {} to {}, not {}",
+                        self.name, min, max, number
+                    )),
+                    _ => Ok(()),
+                }
+            }
+            ParameterKind::Text => Ok(()),
+            ParameterKind::Choice(ref choices) => {
+                if choices.iter().any(|c| c == value) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "{} must be one of {}, not {}",
+                        self.name,
+                        choices.join(", "),
+                        value
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Written the way it's declared, such as "channel required int 1..11".
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} ",
+            self.name,
+            if self.required {
+                "required"
+            } else {
+                "optional"
+            }
+        )?;
+        match self.kind {
+            ParameterKind::Integer(None) => write!(f, "int"),
+            ParameterKind::Integer(Some((min, max))) => write!(f, "int {}..{}", min, max),
+            ParameterKind::Text => write!(f, "text"),
+            ParameterKind::Choice(ref choices) => write!(f, "choice {}", choices.join(",")),
+        }
+    }
+}
+
+/// One axis of a scenario's Matrix: a parameter, and the values it takes in
+/// turn.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Axis {
+    name: String,
+    values: Vec<String>,
+}
+
+impl Axis {
+    /// Parse the value of a Matrix directive, such as "power 10,20".
+    pub fn parse(value: &str) -> Result<Axis, UnitDescriptionError> {
+        let invalid = || {
+            UnitDescriptionError::InvalidValue(
+                "Scenario".to_owned(),
+                "Matrix".to_owned(),
+                value.to_owned(),
+                vec!["<name> <value>,<value>,...".to_owned()],
+            )
+        };
+        let (name, values) = match value.split_whitespace().collect::<Vec<&str>>()[..] {
+            [name, values] if is_name(name) => (name, values),
+            _ => return Err(invalid()),
+        };
+        let values: Vec<String> = values
+            .split(',')
+            .filter(|v| !v.is_empty())
+            .map(|v| v.to_owned())
+            .collect();
+        if values.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Axis {
+            name: name.to_owned(),
+            values,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn values(&self) -> &[String] {
+        &self.values
+    }
+}
+
+/// Every combination of the values of a matrix's axes, as (name, value),
+/// with the last axis changing fastest.  A matrix without axes has one
+/// combination, with nothing in it.
+pub fn combinations(axes: &[Axis]) -> Vec<Vec<(String, String)>> {
+    let mut combinations = vec![vec![]];
+    for axis in axes {
+        combinations = combinations
+            .iter()
+            .flat_map(|combination| {
+                axis.values.iter().map(move |value| {
+                    let mut combination: Vec<(String, String)> = combination.clone();
+                    combination.push((axis.name.clone(), value.clone()));
+                    combination
+                })
+            })
+            .collect();
+    }
+    combinations
+}
+
+/// Whether a parameter may be called this: letters, digits, "_", and "-".
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Split the words after START's scenario into (name, value) pairs.
+pub fn parse_assignments(words: &[String]) -> Result<Vec<(String, String)>, String> {
+    words
+        .iter()
+        .map(|word| match word.split_once('=') {
+            Some((name, value)) if !name.is_empty() => Ok((name.to_owned(), value.to_owned())),
+            _ => Err(format!(
+                "parameters must be given as name=value, not {}",
+                word
+            )),
+        })
+        .collect()
+}
+
+/// Check the values a scenario was started with against the parameters it
+/// declares, and return them in the order they're declared.  Every value
+/// must be for a declared parameter and of the right sort, and every
+/// required parameter must be given, apart from those the scenario's matrix
+/// sets.  The matrix's values must be of the right sort, too.
+pub fn check(
+    declared: &[Parameter],
+    matrix: &[Axis],
+    given: &[(String, String)],
+) -> Result<Vec<(String, String)>, String> {
+    for axis in matrix {
+        if let Some(parameter) = declared.iter().find(|p| p.name() == axis.name()) {
+            for value in axis.values() {
+                parameter.check(value)?;
+            }
+        }
+    }
+    let declared: Vec<&Parameter> = declared
+        .iter()
+        .filter(|p| !matrix.iter().any(|axis| axis.name() == p.name()))
+        .collect();
+    for (index, (name, _)) in given.iter().enumerate() {
+        if matrix.iter().any(|axis| axis.name() == name) {
+            return Err(format!("{} is set by the scenario's Matrix", name));
+        }
+        if !declared.iter().any(|p| p.name() == name) {
+            return Err(format!("there's no parameter named {}", name));
+        }
+        if given[..index].iter().any(|(earlier, _)| earlier == name) {
+            return Err(format!("{} is given more than once", name));
+        }
+    }
+    let mut values = vec![];
+    for parameter in declared {
+        match given.iter().find(|(name, _)| name == parameter.name()) {
+            Some((name, value)) => {
+                parameter.check(value)?;
+                values.push((name.clone(), value.clone()));
+            }
+            None if parameter.is_required() => {
+                return Err(format!("{} is required", parameter.name()))
+            }
+            None => (),
+        }
+    }
+    Ok(values)
+}
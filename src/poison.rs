@@ -0,0 +1,33 @@
+// Locking that carries on after a panic.  A thread that panics while holding
+// a mutex poisons it, and unwrapping every lock() afterwards would turn one
+// crashed helper thread into a crashed exclave.  What the mutexes here guard
+// (settings, results, output buffers) is left consistent between statements,
+// so the data is used as it was, the poisoning is cleared, and a count is
+// kept so the manager can report that it happened.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+static RECOVERED: AtomicUsize = AtomicUsize::new(0);
+
+pub trait Lock<T> {
+    /// Lock the mutex, recovering it if it was poisoned.
+    fn locked(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> Lock<T> for Mutex<T> {
+    fn locked(&self) -> MutexGuard<'_, T> {
+        match self.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => {
+                RECOVERED.fetch_add(1, Ordering::SeqCst);
+                self.clear_poison();
+                poisoned.into_inner()
+            }
+        }
+    }
+}
+
+/// How many poisoned mutexes have been recovered since exclave started.
+pub fn recovered() -> usize {
+    RECOVERED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,84 @@
+// Hints for operator GUIs about how to show a unit, kept in the unit file so
+// that every GUI shows it the same way:
+//
+//     Icon=antenna
+//     Color=ff8800
+//     Group=RF
+//     HideFromOperator=yes
+//
+// exclave doesn't use them itself, and only checks that they're well formed.
+// Jigs, scenarios, and tests may have them, and they're sent to interfaces
+// with DESCRIBE, along with each unit's name and description.
+use unit::{parse_bool, UnitDescriptionError};
+
+/// How a unit should be shown, if the unit file says.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Presentation {
+    /// The name of an icon, from whatever set the GUI uses.
+    pub icon: Option<String>,
+
+    /// A color, as "rgb" or "rrggbb" in hex, or a name such as "orange".
+    /// There's no "#" in front, since it starts a comment in unit files.
+    pub color: Option<String>,
+
+    /// The heading to show the unit under, with others in the same group.
+    pub group: Option<String>,
+
+    /// Whether operators shouldn't see the unit, such as for setup steps.
+    pub hidden: bool,
+}
+
+/// Whether a color is three or six hex digits, or a name made of letters.
+pub fn valid_color(color: &str) -> bool {
+    let hex =
+        (color.len() == 3 || color.len() == 6) && color.chars().all(|c| c.is_ascii_hexdigit());
+    hex || (!color.is_empty() && color.chars().all(|c| c.is_ascii_alphabetic()))
+}
+
+impl Presentation {
+    /// Read one of Icon, Color, Group, or HideFromOperator from a section.
+    pub fn set(
+        &mut self,
+        section: &str,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<(), UnitDescriptionError> {
+        let text = value.map(|s| s.trim()).filter(|s| !s.is_empty());
+        match key {
+            "Icon" => self.icon = text.map(|s| s.to_owned()),
+            "Color" => {
+                if let Some(color) = text {
+                    if !valid_color(color) {
+                        return Err(UnitDescriptionError::InvalidValue(
+                            section.to_owned(),
+                            key.to_owned(),
+                            color.to_owned(),
+                            vec![
+                                "<rgb>".to_owned(),
+                                "<rrggbb>".to_owned(),
+                                "<color name>".to_owned(),
+                            ],
+                        ));
+                    }
+                }
+                self.color = text.map(|s| s.to_owned());
+            }
+            "Group" => self.group = text.map(|s| s.to_owned()),
+            "HideFromOperator" => self.hidden = parse_bool(section, key, value)?,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// These hints, with any that aren't given taken from a parent's.
+    /// Whether the unit is hidden isn't taken, since a parent is often
+    /// hidden when only its children are meant to be run.
+    pub fn inherit(&self, parent: &Presentation) -> Presentation {
+        Presentation {
+            icon: self.icon.clone().or_else(|| parent.icon.clone()),
+            color: self.color.clone().or_else(|| parent.color.clone()),
+            group: self.group.clone().or_else(|| parent.group.clone()),
+            hidden: self.hidden,
+        }
+    }
+}
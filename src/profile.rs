@@ -0,0 +1,238 @@
+// Timings of exclave's own work, for tracking down where time goes between
+// tests: how long events wait between being broadcast and being handled, how
+// long a scan of a unit directory takes, and how long it is from a test being
+// started (by a scenario, or an interface) to its program being running.
+//
+// Nothing is measured unless MetricsAddress is set, in which case the
+// timings are served from http://<MetricsAddress>/metrics, in the
+// Prometheus text format, labelled with the station they came from.
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use poison::Lock;
+use unit::UnitName;
+
+/// The upper bounds of each histogram's buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 2.5, 10.0,
+];
+
+/// How long a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The things that are timed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Timing {
+    /// From an event being broadcast to the main loop handling it.
+    Dispatch,
+
+    /// Scanning a unit directory and announcing what's in it.
+    Scan,
+
+    /// From a test being started to its program being running.
+    Spawn,
+}
+
+impl Timing {
+    const ALL: [Timing; 3] = [Timing::Dispatch, Timing::Scan, Timing::Spawn];
+
+    fn metric(self) -> &'static str {
+        match self {
+            Timing::Dispatch => "exclave_event_dispatch_seconds",
+            Timing::Scan => "exclave_unit_scan_seconds",
+            Timing::Spawn => "exclave_test_spawn_seconds",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Timing::Dispatch => "Time from an event being broadcast to it being handled",
+            Timing::Scan => "Time taken to scan a unit directory",
+            Timing::Spawn => "Time from a test being started to its program running",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+    max: f64,
+}
+
+impl Histogram {
+    fn record(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS.iter()) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+        self.max = self.max.max(seconds);
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static HISTOGRAMS: Mutex<Vec<(Timing, Histogram)>> = Mutex::new(Vec::new());
+
+/// Labels that every metric gets, as (name, value).
+static LABELS: Mutex<Vec<(&'static str, String)>> = Mutex::new(Vec::new());
+
+/// When each test that hasn't started running yet was started.
+static STARTING: Mutex<Option<HashMap<UnitName, Instant>>> = Mutex::new(None);
+
+/// Start timing things.  Until this is called, recording does nothing.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Give every metric these labels, such as which station it's from.
+pub fn set_labels(labels: Vec<(&'static str, String)>) {
+    *LABELS.locked() = labels;
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Note how long something took.
+pub fn record(timing: Timing, elapsed: Duration) {
+    if !is_enabled() {
+        return;
+    }
+    let mut histograms = HISTOGRAMS.locked();
+    let index = match histograms.iter().position(|(t, _)| *t == timing) {
+        Some(index) => index,
+        None => {
+            histograms.push((timing, Histogram::default()));
+            histograms.len() - 1
+        }
+    };
+    histograms[index].1.record(elapsed.as_secs_f64());
+}
+
+/// Note that a test has been asked to start, which is when its spawn time
+/// is measured from.
+pub fn test_starting(test: &UnitName) {
+    if !is_enabled() {
+        return;
+    }
+    STARTING
+        .locked()
+        .get_or_insert_with(HashMap::new)
+        .insert(test.clone(), Instant::now());
+}
+
+/// Note that a test's program is now running.
+pub fn test_running(test: &UnitName) {
+    if !is_enabled() {
+        return;
+    }
+    let started = STARTING
+        .locked()
+        .as_mut()
+        .and_then(|starting| starting.remove(test));
+    if let Some(started) = started {
+        record(Timing::Spawn, started.elapsed());
+    }
+}
+
+/// Every timing, in the Prometheus text format.
+pub fn render() -> String {
+    let labels = LABELS.locked().clone();
+    render_with(&labels)
+}
+
+/// Every timing, in the Prometheus text format, with the given labels.
+pub fn render_with(labels: &[(&str, String)]) -> String {
+    let histograms = HISTOGRAMS.locked();
+    let empty = Histogram::default();
+    let mut common = String::new();
+    for (name, value) in labels {
+        write!(common, "{}=\"{}\",", name, escape_label(value)).unwrap();
+    }
+    let plain = if common.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", common.trim_end_matches(','))
+    };
+    let mut text = String::new();
+    for timing in &Timing::ALL {
+        let metric = timing.metric();
+        let histogram = histograms
+            .iter()
+            .find(|(t, _)| t == timing)
+            .map(|(_, h)| h)
+            .unwrap_or(&empty);
+        writeln!(text, "# HELP {} {}", metric, timing.help()).unwrap();
+        writeln!(text, "# TYPE {} histogram", metric).unwrap();
+        for (bound, count) in BUCKETS.iter().zip(histogram.buckets.iter()) {
+            writeln!(
+                text,
+                "{}_bucket{{{}le=\"{}\"}} {}",
+                metric, common, bound, count
+            )
+            .unwrap();
+        }
+        writeln!(
+            text,
+            "{}_bucket{{{}le=\"+Inf\"}} {}",
+            metric, common, histogram.count
+        )
+        .unwrap();
+        writeln!(text, "{}_sum{} {}", metric, plain, histogram.sum).unwrap();
+        writeln!(text, "{}_count{} {}", metric, plain, histogram.count).unwrap();
+        writeln!(text, "# HELP {}_max The longest time seen", metric).unwrap();
+        writeln!(text, "# TYPE {}_max gauge", metric).unwrap();
+        writeln!(text, "{}_max{} {}", metric, plain, histogram.max).unwrap();
+    }
+    text
+}
+
+/// Escape a label value: backslashes, double quotes, and newlines.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serve the timings over HTTP, at /metrics, on a thread of its own.
+pub fn serve(listener: TcpListener) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            handle(stream).ok();
+        }
+    });
+}
+
+fn handle(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = String::new();
+    BufReader::new(stream.try_clone()?).read_line(&mut request)?;
+    let (status, content_type, body) = match request.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", "/metrics", _] => ("200 OK", "text/plain; version=0.0.4", render()),
+        _ => ("404 Not Found", "text/plain", "not found\n".to_owned()),
+    };
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
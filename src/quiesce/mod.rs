@@ -5,36 +5,56 @@
 mod timer;
 
 use self::timer::WatchTimer;
-use super::unitbroadcaster::{UnitBroadcaster, UnitEvent};
+use super::config::Config;
+use super::poison::Lock;
+use super::unitbroadcaster::{UnitBroadcaster, UnitEvent, UnitStatus};
 
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
 
+/// Asks for a rescan once unit files have stopped changing for RescanDelay,
+/// so that a burst of changes, such as from deploying with rsync, is only
+/// rescanned once, after it's over.
 pub struct Quiesce {
     timer_id: Option<u64>,
     timer: WatchTimer,
+    cfg: Arc<Mutex<Config>>,
 }
 
 impl Quiesce {
-    pub fn new(delay: Duration, broadcaster: &UnitBroadcaster) -> Quiesce {
+    pub fn new(broadcaster: &UnitBroadcaster, config: &Arc<Mutex<Config>>) -> Quiesce {
+        let delay = config.locked().rescan_delay();
         Quiesce {
             timer_id: None,
             timer: WatchTimer::new(broadcaster, delay),
+            cfg: config.clone(),
         }
     }
+
     pub fn process_message(&mut self, evt: &UnitEvent) {
-        if evt == &UnitEvent::RescanRequest
-            || evt == &UnitEvent::RescanStart
-            || evt == &UnitEvent::RescanFinish
-        {
-            return;
+        // Only changes to unit files, and not what's done with the units
+        // once they're loaded, which would lead to another rescan.
+        let changed = match evt {
+            UnitEvent::Status(ref status) => matches!(
+                status.status,
+                UnitStatus::Added(_)
+                    | UnitStatus::Updated(_)
+                    | UnitStatus::Removed(_)
+                    | UnitStatus::LoadStarted(_)
+                    | UnitStatus::UpdateStarted(_)
+                    | UnitStatus::UnloadStarted(_)
+            ),
+            _ => false,
+        };
+        if changed {
+            self.restart_timer(UnitEvent::RescanRequest);
         }
-        self.restart_timer(UnitEvent::RescanRequest);
     }
 
     fn restart_timer(&mut self, event: UnitEvent) {
         if let Some(timer_id) = self.timer_id {
             self.timer.ignore(timer_id);
         }
+        self.timer.set_delay(self.cfg.locked().rescan_delay());
         self.timer_id = Some(self.timer.schedule(event));
     }
 }
@@ -9,10 +9,11 @@ use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
+use super::super::poison::Lock;
 use super::super::unitbroadcaster::{UnitBroadcaster, UnitEvent};
 
 enum Action {
-    Schedule(ScheduledEvent),
+    Schedule(Box<ScheduledEvent>),
     Ignore(u64),
 }
 
@@ -31,7 +32,7 @@ impl Ord for ScheduledEvent {
 
 impl PartialOrd for ScheduledEvent {
     fn partial_cmp(&self, other: &ScheduledEvent) -> Option<Ordering> {
-        other.when.partial_cmp(&self.when)
+        Some(self.cmp(other))
     }
 }
 
@@ -61,9 +62,9 @@ impl ScheduleWorker {
     fn drain_request_queue(&mut self) {
         while let Ok(action) = self.request_source.try_recv() {
             match action {
-                Action::Schedule(event) => self.schedule.push(event),
+                Action::Schedule(event) => self.schedule.push(*event),
                 Action::Ignore(ignore_id) => {
-                    for &ScheduledEvent { ref id, .. } in &self.schedule {
+                    for ScheduledEvent { id, .. } in &self.schedule {
                         if *id == ignore_id {
                             self.ignore.insert(ignore_id);
                             break;
@@ -106,7 +107,7 @@ impl ScheduleWorker {
 
         // Unwrapping is safe because the mutex can't be poisoned,
         // since we just created it.
-        let mut g = m.lock().unwrap();
+        let mut g = m.locked();
 
         loop {
             self.drain_request_queue();
@@ -154,15 +155,20 @@ impl WatchTimer {
         }
     }
 
+    /// Schedule events this long from when they're scheduled, from now on.
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+
     pub fn schedule(&mut self, event: UnitEvent) -> u64 {
         self.counter = self.counter.wrapping_add(1);
 
         self.schedule_tx
-            .send(Action::Schedule(ScheduledEvent {
+            .send(Action::Schedule(Box::new(ScheduledEvent {
                 id: self.counter,
                 when: Instant::now() + self.delay,
                 event,
-            }))
+            })))
             .expect("Failed to send a request to the global scheduling worker");
 
         self.trigger.notify_one();
@@ -0,0 +1,86 @@
+// Redaction rules, from the Redact setting, that take things such as MAC
+// addresses and keys burned into each unit out of test output before it is
+// logged, sent to interfaces, saved under LogDirectory, or stored as a
+// result.  Each rule is a regex, optionally followed by " => " and what to
+// replace each match with, which may refer to capture groups as $1 or
+// ${name}:
+//
+//     Redact=(?i)\b([0-9a-f]{2}:){5}[0-9a-f]{2}\b => [mac]
+//     Redact=(key=)[0-9a-f]{32} => ${1}[key]
+//
+// Without a replacement, matches are replaced with "[redacted]".
+extern crate regex;
+
+use std::fmt;
+use std::str::FromStr;
+
+use self::regex::Regex;
+
+/// What matches are replaced with when a rule doesn't say.
+pub const DEFAULT_REPLACEMENT: &str = "[redacted]";
+
+/// What separates a rule's regex from its replacement.
+const SEPARATOR: &str = " => ";
+
+#[derive(Debug, Clone)]
+pub struct Redaction {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl Redaction {
+    pub fn new(pattern: Regex, replacement: &str) -> Redaction {
+        Redaction {
+            pattern,
+            replacement: replacement.to_owned(),
+        }
+    }
+
+    /// The forms a rule can take, for error messages.
+    pub fn forms() -> Vec<String> {
+        vec!["<regex>".to_owned(), "<regex> => <replacement>".to_owned()]
+    }
+
+    /// Replace everything in a line that this rule matches.
+    pub fn apply(&self, line: &str) -> String {
+        self.pattern
+            .replace_all(line, self.replacement.as_str())
+            .into_owned()
+    }
+}
+
+impl PartialEq for Redaction {
+    fn eq(&self, other: &Redaction) -> bool {
+        self.pattern.as_str() == other.pattern.as_str() && self.replacement == other.replacement
+    }
+}
+
+impl FromStr for Redaction {
+    type Err = regex::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // The last separator is used, since a regex is more likely to
+        // contain one than a replacement is.
+        let (pattern, replacement) = match s.rfind(SEPARATOR) {
+            Some(pos) => (&s[..pos], &s[pos + SEPARATOR.len()..]),
+            None => (s, DEFAULT_REPLACEMENT),
+        };
+        Ok(Redaction::new(Regex::new(pattern)?, replacement))
+    }
+}
+
+impl fmt::Display for Redaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}{}", self.pattern, SEPARATOR, self.replacement)
+    }
+}
+
+/// Apply every rule to a line, in the order they were given.
+pub fn apply(redactions: &[Redaction], line: &str) -> String {
+    let mut line = line.to_owned();
+    for redaction in redactions {
+        if redaction.pattern.is_match(&line) {
+            line = redaction.apply(&line);
+        }
+    }
+    line
+}
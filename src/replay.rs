@@ -0,0 +1,162 @@
+// Records every event that's broadcast to a file, and plays a recording back
+// into the broadcaster later with the same timing.  Interfaces and loggers can
+// then be developed against a real station's events, and an interleaving of
+// events that was seen in the field can be reproduced as often as needed.
+//
+// A recording has one event per line, as JSON: {"ms":...,"event":...}, where
+// "ms" is the number of milliseconds since the first event was recorded.
+extern crate serde_json;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use unit::UnitName;
+use unitbroadcaster::{LogEntry, StampedEvent, UnitBroadcaster, UnitEvent, UnitStatus};
+use unitmanager::ManagerControlMessageContents;
+
+/// One line of a recording.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// Milliseconds since the first event in the recording.
+    pub ms: u64,
+
+    pub event: UnitEvent,
+}
+
+/// Writes each event it's given to a recording.
+pub struct EventRecorder {
+    file: LineWriter<File>,
+
+    /// When the first event was broadcast, in nanoseconds since the epoch.
+    start: Option<u128>,
+}
+
+impl EventRecorder {
+    /// Start a new recording, replacing any file already at the path.
+    pub fn create(path: &Path) -> io::Result<EventRecorder> {
+        Ok(EventRecorder {
+            file: LineWriter::new(File::create(path)?),
+            start: None,
+        })
+    }
+
+    pub fn record(&mut self, stamped: &StampedEvent) -> io::Result<()> {
+        let now = u128::from(stamped.stamp.unix_time) * 1_000_000_000
+            + u128::from(stamped.stamp.unix_time_nsecs);
+        let start = *self.start.get_or_insert(now);
+        let line = RecordedEvent {
+            ms: (now.saturating_sub(start) / 1_000_000) as u64,
+            event: stamped.event.clone(),
+        };
+        let json = serde_json::to_string(&line).map_err(io::Error::other)?;
+        writeln!(self.file, "{}", json)
+    }
+}
+
+/// Read every event in a recording.
+pub fn read_recording(path: &Path) -> io::Result<Vec<RecordedEvent>> {
+    let mut events = vec![];
+    for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        events.push(serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", number + 1, e),
+            )
+        })?);
+    }
+    Ok(events)
+}
+
+/// Whether a recorded event should be broadcast again.  Events that would
+/// make this station load units, run anything, or act on a command are left
+/// out, so that a replay only shows what happened on the recorded station.
+pub fn is_replayable(event: &UnitEvent) -> bool {
+    match event {
+        UnitEvent::Status(ref status) => !matches!(
+            status.status,
+            UnitStatus::Added(_)
+                | UnitStatus::Updated(_)
+                | UnitStatus::Removed(_)
+                | UnitStatus::LoadStarted(_)
+                | UnitStatus::UpdateStarted(_)
+                | UnitStatus::UnloadStarted(_)
+        ),
+        UnitEvent::Category(_)
+        | UnitEvent::Log(_)
+        | UnitEvent::ScenarioFinished(_)
+        | UnitEvent::RunNoted(_) => true,
+
+        // Only reports from units, not requests for the manager to do something.
+        UnitEvent::ManagerRequest(ref req) => matches!(
+            req.contents,
+            ManagerControlMessageContents::TestStarted
+                | ManagerControlMessageContents::TestFinished(_, _)
+                | ManagerControlMessageContents::Skip(_, _)
+                | ManagerControlMessageContents::Log(_)
+                | ManagerControlMessageContents::LogError(_)
+                | ManagerControlMessageContents::Error(_)
+        ),
+
+        // The run directory is on the recorded station, and a recorded
+        // rescan, reload, or shutdown would act on this one.
+        UnitEvent::RescanRequest
+        | UnitEvent::RescanStart
+        | UnitEvent::RescanFinish
+        | UnitEvent::ConfigChanged(_)
+        | UnitEvent::RunFinished(_)
+        | UnitEvent::Shutdown => false,
+    }
+}
+
+/// Broadcast the events in a recording from a new thread, keeping the time
+/// between them.  A speed of 2.0 plays the recording twice as fast, and 0.0
+/// plays it as fast as possible.
+pub fn replay(path: &Path, broadcaster: &UnitBroadcaster, speed: f64) {
+    let path: PathBuf = path.to_owned();
+    let broadcaster = broadcaster.clone();
+    thread::spawn(move || {
+        let id = UnitName::internal("replay");
+        let events = match read_recording(&path) {
+            Ok(events) => events,
+            Err(e) => {
+                broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    id,
+                    format!("unable to read recording {}: {}", path.display(), e),
+                )));
+                return;
+            }
+        };
+        broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_info(
+            id.clone(),
+            format!("replaying {} events from {}", events.len(), path.display()),
+        )));
+
+        let start = Instant::now();
+        let mut replayed = 0;
+        for recorded in events {
+            if !is_replayable(&recorded.event) {
+                continue;
+            }
+            if speed > 0.0 {
+                let due = start + Duration::from_millis(recorded.ms).div_f64(speed);
+                let now = Instant::now();
+                if due > now {
+                    thread::sleep(due - now);
+                }
+            }
+            broadcaster.broadcast(&recorded.event);
+            replayed += 1;
+        }
+        broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_info(
+            id,
+            format!("finished replaying {} events", replayed),
+        )));
+    });
+}
@@ -0,0 +1,119 @@
+// Appends results to a CSV file, writing a header line first if the file
+// is new, so it can be opened directly in a spreadsheet.  A file written by
+// an older exclave has its header brought up to date, and older rows are
+// left without the newer columns.  Allocations, parameters, measurements,
+// and notes are written as JSON, so that a row has every field its signature covers.
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::{json, ResultRecord, ResultSink};
+
+const HEADER: &str = "time,scenario,run_id,parent_run,test,verdict,reason,duration_ms,mode,build,station,line,site,public_key,signature,boot_id,monotonic_ms,jig,serial,allocations,parameters,payload,measurements,clock_unsynced,notes,code,log_path,cpu_ms,peak_memory_kb";
+
+pub struct CsvSink {
+    path: PathBuf,
+    header_ready: bool,
+}
+
+impl CsvSink {
+    pub fn new(path: &Path) -> CsvSink {
+        CsvSink {
+            path: path.to_owned(),
+            header_ready: false,
+        }
+    }
+
+    /// Bring the header of a file written by an older exclave up to date.
+    /// Columns are only ever added at the end, so an older header is the
+    /// start of this one.  A file with any other header is left alone.
+    fn migrate(&mut self) -> io::Result<()> {
+        if self.header_ready {
+            return Ok(());
+        }
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let header = contents.lines().next().unwrap_or("");
+        if !header.is_empty() && header != HEADER && HEADER.starts_with(header) {
+            let rest = &contents[header.len()..];
+            let upgraded = self.path.with_extension("csv.upgrading");
+            fs::write(&upgraded, format!("{}{}", HEADER, rest))?;
+            fs::rename(&upgraded, &self.path)?;
+        }
+        self.header_ready = true;
+        Ok(())
+    }
+
+    /// Quote a field if it contains anything that would break up the line.
+    fn field(value: &str) -> String {
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_owned()
+        }
+    }
+}
+
+impl ResultSink for CsvSink {
+    fn store(&mut self, records: &[ResultRecord]) -> io::Result<()> {
+        self.migrate()?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // Build the whole batch first, so a run is appended in one write.
+        let mut contents = String::new();
+        if file.metadata()?.len() == 0 {
+            contents.push_str(HEADER);
+            contents.push('\n');
+        }
+        for record in records {
+            let fields = [
+                record.time.to_string(),
+                Self::field(&record.scenario),
+                Self::field(record.run_id.as_deref().unwrap_or("")),
+                Self::field(record.parent_run.as_deref().unwrap_or("")),
+                Self::field(record.test.as_deref().unwrap_or("")),
+                record.verdict.clone(),
+                Self::field(&record.reason),
+                record
+                    .duration_ms
+                    .map(|d| d.to_string())
+                    .unwrap_or_default(),
+                record.mode.clone(),
+                Self::field(&record.build),
+                Self::field(&record.station),
+                Self::field(record.line.as_deref().unwrap_or("")),
+                Self::field(record.site.as_deref().unwrap_or("")),
+                record.public_key.clone().unwrap_or_default(),
+                record.signature.clone().unwrap_or_default(),
+                Self::field(record.boot_id.as_deref().unwrap_or("")),
+                record
+                    .monotonic_ms
+                    .map(|m| m.to_string())
+                    .unwrap_or_default(),
+                Self::field(record.jig.as_deref().unwrap_or("")),
+                Self::field(record.serial.as_deref().unwrap_or("")),
+                Self::field(&json(&record.allocations)),
+                Self::field(&json(&record.parameters)),
+                Self::field(record.payload.as_deref().unwrap_or("")),
+                Self::field(&json(&record.measurements)),
+                record.clock_unsynced.to_string(),
+                Self::field(&json(&record.notes)),
+                record.code.map(|c| c.to_string()).unwrap_or_default(),
+                Self::field(record.log_path.as_deref().unwrap_or("")),
+                record.cpu_ms.map(|c| c.to_string()).unwrap_or_default(),
+                record
+                    .peak_memory_kb
+                    .map(|p| p.to_string())
+                    .unwrap_or_default(),
+            ];
+            contents.push_str(&fields.join(","));
+            contents.push('\n');
+        }
+        file.write_all(contents.as_bytes())
+    }
+}
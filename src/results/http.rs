@@ -0,0 +1,235 @@
+// POSTs each run's results to an HTTP server as a JSON array.  Only plain
+// "http://" is spoken, so anything sensitive should go through a local proxy.
+//
+// Each run is sent with an Idempotency-Key header naming the station and the
+// run, and each record carries a key of its own, so that a server can tell a
+// run it has already stored from a new one.  A run that can't be sent is
+// tried again, with the same keys, along with the next one, so a server that
+// stored it but whose answer was lost can drop the copy rather than count the
+// run twice.  A server that answers 409 Conflict is taken to already have it.
+extern crate serde_json;
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use self::serde_json::Value;
+
+use super::{ResultRecord, ResultSink};
+
+/// How long to wait for the server to accept the results.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many times to try sending a run before holding it for the next one.
+const SEND_ATTEMPTS: u32 = 3;
+
+/// How long to wait before the first retry.  This doubles with each attempt.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// The most runs to hold while the server can't be reached.  Beyond this,
+/// the oldest are dropped.
+const MAX_HELD: usize = 1000;
+
+/// A run's records, ready to send.
+struct Batch {
+    key: String,
+    body: Vec<u8>,
+    count: usize,
+}
+
+/// Why a run wasn't stored.
+enum SendError {
+    /// The server can't be reached, or can't store it right now.
+    Retry(String),
+
+    /// The server refused it, and would refuse it again.
+    Refused(String),
+}
+
+pub struct HttpSink {
+    url: String,
+    held: Vec<Batch>,
+}
+
+impl HttpSink {
+    pub fn new(url: &str) -> HttpSink {
+        HttpSink {
+            url: url.to_owned(),
+            held: vec![],
+        }
+    }
+
+    /// Send a run, trying a few times if the server can't be reached.
+    fn send(&self, batch: &Batch) -> Result<(), SendError> {
+        let mut delay = RETRY_DELAY;
+        let mut attempt = 1;
+        loop {
+            let error = match request(
+                &self.url,
+                "application/json",
+                &[("Idempotency-Key", &batch.key)],
+                &batch.body,
+            ) {
+                // The server already has the run, from an earlier attempt.
+                Ok((code, _)) if (200..300).contains(&code) || code == 409 => return Ok(()),
+                // Timeouts, rate limits, and server errors may pass.
+                Ok((code, status)) if code == 408 || code == 429 || code >= 500 => {
+                    format!("server answered {}", status)
+                }
+                Ok((_, status)) => {
+                    return Err(SendError::Refused(format!("server answered {}", status)))
+                }
+                Err(e) => e.to_string(),
+            };
+            if attempt >= SEND_ATTEMPTS {
+                return Err(SendError::Retry(error));
+            }
+            thread::sleep(delay);
+            delay *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+/// The key that names a run: the station's id, then the run's directory
+/// name, or when it finished and its scenario if it has none.  A run with
+/// notes also has how many, so that it's sent again when one is added after
+/// it finished.
+pub fn run_key(record: &ResultRecord) -> String {
+    let key = match record.run_id {
+        Some(ref run_id) => format!("{}/{}", record.station, run_id),
+        None => format!("{}/{}-{}", record.station, record.time, record.scenario),
+    };
+    match record.notes.len() {
+        0 => key,
+        count => format!("{}/notes/{}", key, count),
+    }
+}
+
+/// The key that names one record: its run's key, followed by its test, if
+/// it's a test's record.
+pub fn record_key(record: &ResultRecord) -> String {
+    match record.test {
+        Some(ref test) => format!("{}/{}", run_key(record), test),
+        None => run_key(record),
+    }
+}
+
+impl ResultSink for HttpSink {
+    fn store(&mut self, records: &[ResultRecord]) -> io::Result<()> {
+        let mut values = vec![];
+        for record in records {
+            let mut value = serde_json::to_value(record)?;
+            if let Value::Object(ref mut fields) = value {
+                fields.insert(
+                    "idempotency_key".to_owned(),
+                    Value::String(record_key(record)),
+                );
+            }
+            values.push(value);
+        }
+        if let Some(record) = records.first() {
+            self.held.push(Batch {
+                key: run_key(record),
+                body: serde_json::to_vec(&values)?,
+                count: records.len(),
+            });
+        }
+
+        // Runs are sent in the order they finished, so the oldest go first.
+        let mut errors = vec![];
+        while !self.held.is_empty() {
+            match self.send(&self.held[0]) {
+                Ok(()) => {
+                    self.held.remove(0);
+                }
+                Err(SendError::Refused(e)) => {
+                    let batch = self.held.remove(0);
+                    errors.push(format!("{} results of {}: {}", batch.count, batch.key, e));
+                }
+                Err(SendError::Retry(e)) => {
+                    if self.held.len() > MAX_HELD {
+                        let excess = self.held.len() - MAX_HELD;
+                        self.held.drain(..excess);
+                    }
+                    errors.push(format!(
+                        "{} ({} runs held for the next run)",
+                        e,
+                        self.held.len()
+                    ));
+                    break;
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::other(errors.join("; ")))
+        }
+    }
+}
+
+/// POST a body to an "http://" URL, succeeding if the server answers with a 2xx status.
+pub fn post(url: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    match request(url, content_type, &[], body)? {
+        (code, _) if (200..300).contains(&code) => Ok(()),
+        (_, status) => Err(io::Error::other(format!("server answered {}", status))),
+    }
+}
+
+/// POST a body to an "http://" URL, with some extra headers, and return the
+/// status code the server answered with, along with its status line.
+pub fn request(
+    url: &str,
+    content_type: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> io::Result<(u16, String)> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| io::Error::other("only http:// URLs are supported"))?;
+    let (host, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+    let address = if host.contains(':') {
+        host.to_owned()
+    } else {
+        format!("{}:80", host)
+    };
+    let address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::other(format!("no address found for {}", host)))?;
+
+    let mut stream = TcpStream::connect_timeout(&address, HTTP_TIMEOUT)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+    let mut head = format!(
+        "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        path,
+        host,
+        content_type,
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+
+    let mut response = vec![];
+    stream.take(65536).read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or("");
+    match status
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+    {
+        Some(code) => Ok((code, status.to_owned())),
+        None => Err(io::Error::other("no response from server")),
+    }
+}
@@ -0,0 +1,175 @@
+// Writes results in InfluxDB line protocol, either straight to an InfluxDB
+// server or to a file for another agent (such as Telegraf) to pick up.  Each
+// test becomes an "exclave_test" point, its measurements an
+// "exclave_measurement" point, and the run an "exclave_scenario" point, all
+// tagged with the station and its line and site, the jig, scenario, serial
+// number, and mode.
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::http;
+use super::{ResultRecord, ResultSink};
+
+pub struct InfluxSink {
+    url: String,
+}
+
+impl InfluxSink {
+    pub fn new(url: &str) -> InfluxSink {
+        InfluxSink {
+            url: url.to_owned(),
+        }
+    }
+}
+
+impl ResultSink for InfluxSink {
+    fn store(&mut self, records: &[ResultRecord]) -> io::Result<()> {
+        let body = lines(records);
+        http::post(&self.url, "text/plain; charset=utf-8", body.as_bytes())
+    }
+}
+
+pub struct LineProtocolSink {
+    path: PathBuf,
+}
+
+impl LineProtocolSink {
+    pub fn new(path: &Path) -> LineProtocolSink {
+        LineProtocolSink {
+            path: path.to_owned(),
+        }
+    }
+}
+
+impl ResultSink for LineProtocolSink {
+    fn store(&mut self, records: &[ResultRecord]) -> io::Result<()> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(lines(records).as_bytes())
+    }
+}
+
+/// Escape a tag key, tag value, or field key.  Line protocol has no way to
+/// write a newline in these, so newlines become spaces.
+fn key(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            ',' | '=' | ' ' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' | '\r' => escaped.push_str("\\ "),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A quoted string field value.
+fn string(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace(['\n', '\r'], " ")
+    )
+}
+
+/// The lines for a run's records.
+fn lines(records: &[ResultRecord]) -> String {
+    let mut lines = String::new();
+    for record in records {
+        // Tags are sorted by key, as InfluxDB prefers.
+        let tags = [
+            ("jig", record.jig.as_deref()),
+            ("line", record.line.as_deref()),
+            ("mode", Some(record.mode.as_str())),
+            ("scenario", Some(record.scenario.as_str())),
+            ("serial", record.serial.as_deref()),
+            ("site", record.site.as_deref()),
+            ("station", Some(record.station.as_str())),
+            ("test", record.test.as_deref()),
+        ]
+        .iter()
+        .filter_map(|&(name, value)| match value {
+            Some(value) if !value.is_empty() => Some(format!(",{}={}", name, key(value))),
+            _ => None,
+        })
+        .collect::<String>();
+        let timestamp = record.time * 1_000_000_000;
+
+        let mut fields = vec![format!("verdict={}", string(&record.verdict))];
+        if !record.reason.is_empty() {
+            fields.push(format!("reason={}", string(&record.reason)));
+        }
+        if let Some(ref run_id) = record.run_id {
+            fields.push(format!("run_id={}", string(run_id)));
+        }
+        if let Some(duration_ms) = record.duration_ms {
+            fields.push(format!("duration_ms={}i", duration_ms));
+        }
+        if let Some(code) = record.code {
+            fields.push(format!("code={}i", code));
+        }
+        if let Some(ref log_path) = record.log_path {
+            fields.push(format!("log_path={}", string(log_path)));
+        }
+        if let Some(cpu_ms) = record.cpu_ms {
+            fields.push(format!("cpu_ms={}i", cpu_ms));
+        }
+        if let Some(peak_memory_kb) = record.peak_memory_kb {
+            fields.push(format!("peak_memory_kb={}i", peak_memory_kb));
+        }
+        fields.push(format!("build={}", string(&record.build)));
+        if let Some(ref boot_id) = record.boot_id {
+            fields.push(format!("boot_id={}", string(boot_id)));
+        }
+        if let Some(monotonic_ms) = record.monotonic_ms {
+            fields.push(format!("monotonic_ms={}i", monotonic_ms));
+        }
+        fields.push(format!("clock_unsynced={}", record.clock_unsynced));
+        if !record.notes.is_empty() {
+            fields.push(format!("notes={}", string(&super::json(&record.notes))));
+        }
+        let kind = if record.test.is_some() {
+            "exclave_test"
+        } else {
+            "exclave_scenario"
+        };
+        lines.push_str(&format!(
+            "{}{} {} {}\n",
+            kind,
+            tags,
+            fields.join(","),
+            timestamp
+        ));
+
+        if !record.measurements.is_empty() {
+            // A field can only appear once in a line, so a name that was
+            // measured more than once keeps its last value.
+            let mut fields: Vec<(&str, f64)> = vec![];
+            for m in &record.measurements {
+                match fields.iter_mut().find(|(name, _)| *name == m.name) {
+                    Some(field) => field.1 = m.value,
+                    None => fields.push((&m.name, m.value)),
+                }
+            }
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(name, value)| format!("{}={}", key(name), value))
+                .collect();
+            lines.push_str(&format!(
+                "exclave_measurement{} {} {}\n",
+                tags,
+                fields.join(","),
+                timestamp
+            ));
+        }
+    }
+    lines
+}
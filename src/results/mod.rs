@@ -0,0 +1,544 @@
+// Stores the result of every scenario run in one or more places, such as a
+// CSV file, an SQLite database, or an HTTP server.  Each ResultSink setting in
+// the config file adds a sink, and every sink is given the same records.
+extern crate serde_json;
+
+mod csv;
+pub mod http;
+mod influx;
+mod null;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod sql;
+mod sqlite;
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use config::Config;
+use ed25519::SigningKey;
+use poison::Lock;
+use sha256;
+use station::Station;
+use unit::UnitName;
+use unitbroadcaster::{LogEntry, ScenarioSummary, UnitBroadcaster, UnitEvent};
+use version;
+
+pub use self::csv::CsvSink;
+pub use self::http::HttpSink;
+pub use self::influx::{InfluxSink, LineProtocolSink};
+pub use self::null::NullSink;
+#[cfg(feature = "postgres")]
+pub use self::postgres::PostgresSink;
+pub use self::sqlite::SqliteSink;
+
+/// The result of one test, or of a whole scenario run.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct ResultRecord {
+    /// When the run finished, in seconds since the epoch.
+    pub time: u64,
+
+    pub scenario: String,
+
+    /// The jig the scenario ran on, if any.
+    pub jig: Option<String>,
+
+    /// The serial number of the device under test, if a test reported one.
+    pub serial: Option<String>,
+
+    /// The values allocators handed out for the run, by allocator.
+    pub allocations: BTreeMap<String, String>,
+
+    /// The values the scenario's parameters were given, by name.
+    pub parameters: BTreeMap<String, String>,
+
+    /// What the trigger that started the run saw, if it passed it on.
+    pub payload: Option<String>,
+
+    /// The notes operators added to the run, oldest first.
+    pub notes: Vec<String>,
+
+    /// The name of the run's directory in the LogDirectory, if there is one.
+    pub run_id: Option<String>,
+
+    /// The run_id of the run that this one retested, if any.
+    pub parent_run: Option<String>,
+
+    /// The test, or None for the record of the scenario as a whole.
+    pub test: Option<String>,
+
+    /// One of "pass", "fail", "fault", or "skip".
+    pub verdict: String,
+
+    /// Why the test failed or was skipped, or how the scenario finished.
+    pub reason: String,
+
+    /// How long the test or scenario took, if it ran.
+    pub duration_ms: Option<u64>,
+
+    /// The test's exit code, or the scenario's result code, if it ran.
+    pub code: Option<i32>,
+
+    /// Where the test's output was saved, or the run's directory for the
+    /// scenario, if there's a LogDirectory.
+    pub log_path: Option<String>,
+
+    /// CPU time used by the test, or by all of the scenario's tests, if known.
+    pub cpu_ms: Option<u64>,
+
+    /// The most memory the test, or any of the scenario's tests, used at
+    /// once, in KiB, if known.
+    pub peak_memory_kb: Option<u64>,
+
+    /// What the test reported with its MeasurementPattern.
+    pub measurements: Vec<Measurement>,
+
+    /// "production", or "maintenance" if the run was made in maintenance
+    /// mode and shouldn't count towards yield.
+    pub mode: String,
+
+    /// The build of exclave that made the run, as given by version::build_id().
+    pub build: String,
+
+    /// The id of the station that made the run, from its identity file.
+    pub station: String,
+
+    /// The production line the station is on, if it's been set.
+    pub line: Option<String>,
+
+    /// Where the station is, if it's been set.
+    pub site: Option<String>,
+
+    /// Which boot of the station the run finished in, if known.
+    pub boot_id: Option<String>,
+
+    /// When the run finished, in milliseconds since that boot, on a clock
+    /// that isn't changed when the time of day is.  Within a boot, this puts
+    /// runs in the order they really finished in, even if `time` jumped.
+    pub monotonic_ms: Option<u64>,
+
+    /// True if the run started while the station's clock couldn't be
+    /// trusted, so `time` may be wrong.
+    pub clock_unsynced: bool,
+
+    /// The public key that the record was signed with, in hex, if
+    /// ResultSigningKey is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+
+    /// The Ed25519 signature of signed_text(), in hex.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// A number reported by a test.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Measurement {
+    pub name: String,
+    pub value: f64,
+}
+
+impl ResultRecord {
+    /// One record for each test in the run, followed by one for the scenario.
+    pub fn from_summary(summary: &ScenarioSummary, finished: SystemTime) -> Vec<ResultRecord> {
+        let time = finished
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let build = version::build_id();
+        let station = summary
+            .station
+            .clone()
+            .unwrap_or_else(Station::from_hostname);
+        let record = |test: Option<&UnitName>,
+                      verdict: String,
+                      reason: &str,
+                      duration: Option<Duration>| ResultRecord {
+            time,
+            scenario: summary.scenario.id().to_owned(),
+            jig: summary.jig.as_ref().map(|j| j.id().to_owned()),
+            serial: summary.serial.clone(),
+            allocations: summary
+                .allocations
+                .iter()
+                .map(|(allocator, value)| (allocator.id().to_owned(), value.clone()))
+                .collect(),
+            parameters: summary.parameters.iter().cloned().collect(),
+            payload: summary.payload.clone(),
+            notes: summary.notes.clone(),
+            run_id: summary.run_id.clone(),
+            parent_run: summary.parent_run.clone(),
+            test: test.map(|t| t.id().to_owned()),
+            verdict,
+            reason: reason.to_owned(),
+            duration_ms: duration.map(|d| d.as_millis() as u64),
+            code: None,
+            log_path: None,
+            cpu_ms: None,
+            peak_memory_kb: None,
+            measurements: vec![],
+            mode: if summary.maintenance {
+                "maintenance"
+            } else {
+                "production"
+            }
+            .to_owned(),
+            build: build.clone(),
+            station: station.id.clone(),
+            line: station.line.clone(),
+            site: station.site.clone(),
+            boot_id: summary.finished.as_ref().map(|f| f.boot_id.clone()),
+            monotonic_ms: summary.finished.as_ref().map(|f| f.millis()),
+            clock_unsynced: summary.clock_unsynced,
+            public_key: None,
+            signature: None,
+        };
+        let mut records: Vec<ResultRecord> = summary
+            .tests
+            .iter()
+            .map(|test| {
+                let mut record = record(
+                    Some(&test.test),
+                    test.verdict.to_string(),
+                    &test.reason,
+                    test.duration,
+                );
+                record.measurements = test
+                    .measurements
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        value.parse().ok().map(|value| Measurement {
+                            name: name.clone(),
+                            value,
+                        })
+                    })
+                    .collect();
+                record.code = test.code;
+                record.log_path = test.log_path.as_ref().map(|p| p.display().to_string());
+                record.cpu_ms = test.cpu.map(|c| c.as_millis() as u64);
+                record.peak_memory_kb = test.peak_memory_kb;
+                record
+            })
+            .collect();
+        let mut scenario = record(
+            None,
+            summary.verdict().to_string(),
+            &summary.reason,
+            Some(summary.duration),
+        );
+        scenario.code = Some(summary.code as i32);
+        scenario.log_path = summary.log_path.as_ref().map(|p| p.display().to_string());
+        scenario.cpu_ms = summary.cpu.map(|c| c.as_millis() as u64);
+        scenario.peak_memory_kb = summary.peak_memory_kb;
+        records.push(scenario);
+        records
+    }
+
+    /// What a record's signature covers: every other field, each on a line
+    /// of its own as "name=value", in the order they're declared in.  Values
+    /// that are missing are empty, and backslashes, newlines, and carriage
+    /// returns in values are written as \\, \n, and \r.  Allocations,
+    /// parameters, and measurements get a line each, named after the field
+    /// and then the entry, as "allocation.mac=...", with measurements'
+    /// values as they appear in JSON.  Each note gets a "note=" line.  True and false are "true" and "false".
+    pub fn signed_text(&self) -> String {
+        fn escape(value: &str) -> String {
+            value
+                .replace('\\', "\\\\")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r")
+        }
+        let optional = |value: &Option<String>| escape(value.as_deref().unwrap_or(""));
+        let mut lines = vec![
+            format!("time={}", self.time),
+            format!("scenario={}", escape(&self.scenario)),
+            format!("jig={}", optional(&self.jig)),
+            format!("serial={}", optional(&self.serial)),
+        ];
+        for (allocator, value) in &self.allocations {
+            lines.push(format!(
+                "allocation.{}={}",
+                escape(allocator),
+                escape(value)
+            ));
+        }
+        for (name, value) in &self.parameters {
+            lines.push(format!("parameter.{}={}", escape(name), escape(value)));
+        }
+        lines.extend(vec![format!("payload={}", optional(&self.payload))]);
+        for note in &self.notes {
+            lines.push(format!("note={}", escape(note)));
+        }
+        lines.extend(vec![
+            format!("run_id={}", optional(&self.run_id)),
+            format!("parent_run={}", optional(&self.parent_run)),
+            format!("test={}", optional(&self.test)),
+            format!("verdict={}", escape(&self.verdict)),
+            format!("reason={}", escape(&self.reason)),
+        ]);
+        let number = |value: Option<u64>| value.map(|v| v.to_string()).unwrap_or_default();
+        lines.push(format!("duration_ms={}", number(self.duration_ms)));
+        lines.push(format!(
+            "code={}",
+            self.code.map(|c| c.to_string()).unwrap_or_default()
+        ));
+        lines.push(format!("log_path={}", optional(&self.log_path)));
+        lines.push(format!("cpu_ms={}", number(self.cpu_ms)));
+        lines.push(format!("peak_memory_kb={}", number(self.peak_memory_kb)));
+        for measurement in &self.measurements {
+            lines.push(format!(
+                "measurement.{}={}",
+                escape(&measurement.name),
+                serde_json::to_string(&measurement.value).unwrap_or_default()
+            ));
+        }
+        lines.push(format!("mode={}", escape(&self.mode)));
+        lines.push(format!("build={}", escape(&self.build)));
+        lines.push(format!("station={}", escape(&self.station)));
+        lines.push(format!("line={}", optional(&self.line)));
+        lines.push(format!("site={}", optional(&self.site)));
+        lines.push(format!("boot_id={}", optional(&self.boot_id)));
+        lines.push(format!("monotonic_ms={}", number(self.monotonic_ms)));
+        lines.push(format!("clock_unsynced={}", self.clock_unsynced));
+        lines.push(format!("public_key={}", optional(&self.public_key)));
+        let mut text = lines.join("\n");
+        text.push('\n');
+        text
+    }
+
+    /// Sign the record, so that whoever receives it can tell it hasn't been
+    /// changed since.
+    pub fn sign(&mut self, key: &SigningKey) {
+        self.public_key = Some(sha256::to_hex(&key.public_key()));
+        self.signature = Some(sha256::to_hex(&key.sign(self.signed_text().as_bytes())));
+    }
+}
+
+/// A field that holds several values, such as a record's allocations, as
+/// JSON, for the sinks that store each field as a single value.
+fn json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+/// Somewhere results can be stored.
+pub trait ResultSink: Send {
+    /// Store the records from one run.  If this fails, the records are not
+    /// offered again.
+    fn store(&mut self, records: &[ResultRecord]) -> io::Result<()>;
+}
+
+/// Where to store results, as given by a ResultSink setting.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum SinkSpec {
+    /// "null": Throw results away.
+    Null,
+
+    /// "csv:<path>": Append a line per record to a CSV file.
+    Csv(PathBuf),
+
+    /// "sqlite:<path>": Insert a row per record into an SQLite database.
+    Sqlite(PathBuf),
+
+    /// "http://<host>[:port]/<path>": POST each run's records as a JSON array.
+    Http(String),
+
+    /// "influxdb:<url>": POST each run's records to InfluxDB's write endpoint,
+    /// in line protocol.
+    Influx(String),
+
+    /// "lines:<path>": Append each run's records to a file, in InfluxDB line protocol.
+    Lines(PathBuf),
+
+    /// "postgres://<user>@<host>/<database>": Insert a row per record into a
+    /// PostgreSQL database.  Only available with the "postgres" feature.
+    #[cfg(feature = "postgres")]
+    Postgres(String),
+}
+
+impl SinkSpec {
+    /// Forms that a ResultSink setting may take, for error messages.
+    pub fn forms() -> Vec<String> {
+        #[allow(unused_mut)]
+        let mut forms = vec![
+            "null".to_owned(),
+            "csv:<path>".to_owned(),
+            "sqlite:<path>".to_owned(),
+            "http://<host>/<path>".to_owned(),
+            "influxdb:http://<host>:8086/write?db=<database>".to_owned(),
+            "lines:<path>".to_owned(),
+        ];
+        #[cfg(feature = "postgres")]
+        forms.push("postgres://<user>@<host>/<database>".to_owned());
+        forms
+    }
+
+    pub fn open(&self) -> Box<dyn ResultSink> {
+        match self {
+            SinkSpec::Null => Box::new(NullSink),
+            SinkSpec::Csv(path) => Box::new(CsvSink::new(path)),
+            SinkSpec::Sqlite(path) => Box::new(SqliteSink::new(path)),
+            SinkSpec::Http(url) => Box::new(HttpSink::new(url)),
+            SinkSpec::Influx(url) => Box::new(InfluxSink::new(url)),
+            SinkSpec::Lines(path) => Box::new(LineProtocolSink::new(path)),
+            #[cfg(feature = "postgres")]
+            SinkSpec::Postgres(url) => Box::new(PostgresSink::new(url)),
+        }
+    }
+}
+
+impl FromStr for SinkSpec {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "null" {
+            return Ok(SinkSpec::Null);
+        }
+        if s.starts_with("http://") {
+            return Ok(SinkSpec::Http(s.to_owned()));
+        }
+        #[cfg(feature = "postgres")]
+        {
+            if s.starts_with("postgres://") || s.starts_with("postgresql://") {
+                return Ok(SinkSpec::Postgres(s.to_owned()));
+            }
+        }
+        match s.split_once(':') {
+            Some(("csv", path)) if !path.is_empty() => Ok(SinkSpec::Csv(PathBuf::from(path))),
+            Some(("sqlite", path)) if !path.is_empty() => Ok(SinkSpec::Sqlite(PathBuf::from(path))),
+            Some(("influxdb", url)) if url.starts_with("http://") => {
+                Ok(SinkSpec::Influx(url.to_owned()))
+            }
+            Some(("lines", path)) if !path.is_empty() => Ok(SinkSpec::Lines(PathBuf::from(path))),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for SinkSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SinkSpec::Null => write!(f, "null"),
+            SinkSpec::Csv(path) => write!(f, "csv:{}", path.display()),
+            SinkSpec::Sqlite(path) => write!(f, "sqlite:{}", path.display()),
+            SinkSpec::Http(url) => write!(f, "{}", url),
+            // The query often carries credentials, so it isn't shown.
+            SinkSpec::Influx(url) => match url.find('?') {
+                Some(pos) => write!(f, "influxdb:{}?...", &url[..pos]),
+                None => write!(f, "influxdb:{}", url),
+            },
+            SinkSpec::Lines(path) => write!(f, "lines:{}", path.display()),
+            #[cfg(feature = "postgres")]
+            SinkSpec::Postgres(url) => write!(f, "{}", postgres::redacted(url)),
+        }
+    }
+}
+
+/// Hands the records of each finished run to every configured sink.
+pub struct ResultRecorder {
+    config: Arc<Mutex<Config>>,
+    broadcaster: UnitBroadcaster,
+    sender: Sender<(Vec<ResultRecord>, Vec<SinkSpec>)>,
+
+    /// When the most recent run finished, so that its record keeps that
+    /// time if it's stored again with a new note.
+    finished: Cell<Option<SystemTime>>,
+}
+
+impl ResultRecorder {
+    pub fn new(broadcaster: &UnitBroadcaster, config: &Arc<Mutex<Config>>) -> ResultRecorder {
+        let (sender, receiver) = channel::<(Vec<ResultRecord>, Vec<SinkSpec>)>();
+        let logger = broadcaster.clone();
+
+        thread::spawn(move || {
+            // Sinks are kept open for as long as they stay in the config.
+            let mut sinks: Vec<(SinkSpec, Box<dyn ResultSink>)> = vec![];
+            let id = UnitName::internal("results");
+            while let Ok((records, specs)) = receiver.recv() {
+                sinks.retain(|(spec, _)| specs.contains(spec));
+                for spec in specs {
+                    if !sinks.iter().any(|(s, _)| *s == spec) {
+                        let sink = spec.open();
+                        sinks.push((spec, sink));
+                    }
+                }
+                for (spec, sink) in &mut sinks {
+                    let entry = match sink.store(&records) {
+                        Ok(()) => LogEntry::new_info(
+                            id.clone(),
+                            format!("stored {} results in {}", records.len(), spec),
+                        ),
+                        Err(e) => LogEntry::new_error(
+                            id.clone(),
+                            format!("unable to store results in {}: {}", spec, e),
+                        ),
+                    };
+                    logger.broadcast(&UnitEvent::Log(entry));
+                }
+            }
+        });
+
+        ResultRecorder {
+            config: config.clone(),
+            broadcaster: broadcaster.clone(),
+            sender,
+            finished: Cell::new(None),
+        }
+    }
+
+    pub fn process_message(&self, evt: &UnitEvent) {
+        match *evt {
+            UnitEvent::ScenarioFinished(ref summary) => {
+                let now = self.config.locked().clock().now();
+                self.finished.set(Some(now));
+                self.record(summary, now, false);
+            }
+            // The tests' records haven't changed, so only the run's own
+            // record is stored again.
+            UnitEvent::RunNoted(ref summary) => {
+                if let Some(finished) = self.finished.get() {
+                    self.record(summary, finished, true);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn record(&self, summary: &ScenarioSummary, finished: SystemTime, run_only: bool) {
+        let (specs, key) = {
+            let config = self.config.locked();
+            (config.result_sinks().clone(), config.result_signing_key())
+        };
+        if !specs.is_empty() {
+            let mut records = ResultRecord::from_summary(summary, finished);
+            if run_only {
+                records = records.split_off(records.len() - 1);
+            }
+            match key {
+                Ok(Some(key)) => {
+                    for record in &mut records {
+                        record.sign(&key);
+                    }
+                }
+                Ok(None) => (),
+                // Results are still stored, so that none are lost, but
+                // whoever checks them will see they aren't signed.
+                Err(e) => self
+                    .broadcaster
+                    .broadcast(&UnitEvent::Log(LogEntry::new_error(
+                        UnitName::internal("results"),
+                        format!("unable to sign results: {}", e),
+                    ))),
+            }
+            self.sender.send((records, specs)).ok();
+        }
+    }
+}
@@ -0,0 +1,258 @@
+// Inserts results into a central PostgreSQL database.  The statements are
+// piped into the psql program, so it must be installed on the station.  The
+// schema is brought up to date before the first run is stored, and runs that
+// can't be stored are held and sent along with the next one.  The password,
+// if the URL has one, is given to psql in PGPASSWORD rather than on its
+// command line, where any user on the station could read it.
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use super::sql::{self, Dialect};
+use super::{ResultRecord, ResultSink};
+
+/// Each step brings the schema up one version.  Steps must be safe to run
+/// twice, in case two stations upgrade the same database at once.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS results (
+    time BIGINT NOT NULL,
+    scenario TEXT NOT NULL,
+    run_id TEXT,
+    parent_run TEXT,
+    test TEXT,
+    verdict TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    duration_ms BIGINT
+)",
+    "CREATE INDEX IF NOT EXISTS results_scenario_time ON results (scenario, time)",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS mode TEXT NOT NULL DEFAULT 'production'",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS build TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS station TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS line TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS site TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS boot_id TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS monotonic_ms BIGINT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS public_key TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS signature TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS jig TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS serial TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS allocations TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS parameters TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS payload TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS measurements TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS clock_unsynced BOOLEAN NOT NULL DEFAULT FALSE",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS notes TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS code INTEGER",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS log_path TEXT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS cpu_ms BIGINT",
+    "ALTER TABLE results ADD COLUMN IF NOT EXISTS peak_memory_kb BIGINT",
+];
+
+/// How many times to try reaching the server before giving up on a run.
+const CONNECT_ATTEMPTS: u32 = 3;
+
+/// How long to wait before the first retry.  This doubles with each attempt.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// The most results to hold while the server is unreachable.  Beyond this,
+/// the oldest are dropped.
+const MAX_HELD: usize = 10_000;
+
+pub struct PostgresSink {
+    /// The URL, without its password.
+    url: String,
+    password: Option<String>,
+    schema_ready: bool,
+    held: Vec<ResultRecord>,
+}
+
+impl PostgresSink {
+    pub fn new(url: &str) -> PostgresSink {
+        let (url, password) = split_password(url);
+        PostgresSink {
+            url,
+            password,
+            schema_ready: false,
+            held: vec![],
+        }
+    }
+
+    /// Run statements in psql and return what they printed, retrying if
+    /// the server can't be reached.
+    fn psql(&self, statements: &str) -> io::Result<String> {
+        let mut delay = RETRY_DELAY;
+        let mut attempt = 1;
+        loop {
+            let mut psql = Command::new("psql");
+            psql.args(["-X", "-q", "-A", "-t", "-v", "ON_ERROR_STOP=1", "-d"])
+                .arg(&self.url)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            if let Some(ref password) = self.password {
+                psql.env("PGPASSWORD", password);
+            }
+            let mut psql = psql.spawn()?;
+            let written = psql.stdin.take().unwrap().write_all(statements.as_bytes());
+            let output = psql.wait_with_output()?;
+            match output.status.code() {
+                Some(0) => {
+                    written?;
+                    return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+                }
+                // psql exits with 2 when it couldn't connect to the server.
+                Some(2) if attempt < CONNECT_ATTEMPTS => {
+                    thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                _ => {
+                    return Err(io::Error::other(format!(
+                        "psql exited with {}: {}",
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Apply any migrations the database hasn't seen yet.
+    fn migrate(&mut self) -> io::Result<()> {
+        if self.schema_ready {
+            return Ok(());
+        }
+        let version = self.psql(
+            "CREATE TABLE IF NOT EXISTS exclave_schema (version INTEGER NOT NULL);
+SELECT COALESCE(MAX(version), 0) FROM exclave_schema;\n",
+        )?;
+        let version: usize = version.trim().parse().map_err(|_| {
+            io::Error::other(format!("unexpected schema version {:?}", version.trim()))
+        })?;
+        if version > MIGRATIONS.len() {
+            return Err(io::Error::other(format!(
+                "database schema version {} is newer than this exclave understands ({})",
+                version,
+                MIGRATIONS.len()
+            )));
+        }
+        if version < MIGRATIONS.len() {
+            let mut statements = "BEGIN;\nLOCK TABLE exclave_schema;\n".to_owned();
+            for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+                statements.push_str(&format!(
+                    "{};\nINSERT INTO exclave_schema (version) VALUES ({});\n",
+                    migration,
+                    index + 1
+                ));
+            }
+            statements.push_str("COMMIT;\n");
+            self.psql(&statements)?;
+        }
+        self.schema_ready = true;
+        Ok(())
+    }
+}
+
+impl ResultSink for PostgresSink {
+    fn store(&mut self, records: &[ResultRecord]) -> io::Result<()> {
+        self.held.extend_from_slice(records);
+        let result = self.migrate().and_then(|_| {
+            self.psql(&format!(
+                "BEGIN;\n{}COMMIT;\n",
+                sql::inserts(Dialect::Postgres, &self.held)
+            ))
+        });
+        match result {
+            Ok(_) => {
+                self.held.clear();
+                Ok(())
+            }
+            Err(e) => {
+                if self.held.len() > MAX_HELD {
+                    let excess = self.held.len() - MAX_HELD;
+                    self.held.drain(..excess);
+                }
+                Err(io::Error::other(format!(
+                    "{} ({} results held for the next run)",
+                    e,
+                    self.held.len()
+                )))
+            }
+        }
+    }
+}
+
+/// The URL with any password taken out, so it can be logged.
+pub fn redacted(url: &str) -> String {
+    split_password(url).0
+}
+
+/// The URL with its password taken out, and the password.  It may be in the
+/// URL's user info, as "user:password@host", or in its query, as
+/// "?password=...", and is percent-decoded, as psql would.
+fn split_password(url: &str) -> (String, Option<String>) {
+    let (scheme, rest) = match url.find("://") {
+        Some(pos) => url.split_at(pos + 3),
+        None => return (url.to_owned(), None),
+    };
+    let authority_end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(authority_end);
+    let mut password = None;
+    let authority = match authority.rfind('@') {
+        Some(at) => match authority[..at].find(':') {
+            Some(colon) => {
+                password = Some(percent_decode(&authority[colon + 1..at]));
+                format!("{}{}", &authority[..colon], &authority[at..])
+            }
+            None => authority.to_owned(),
+        },
+        None => authority.to_owned(),
+    };
+    let path = match path.split_once('?') {
+        Some((path, query)) => {
+            let parameters: Vec<&str> = query
+                .split('&')
+                .filter(|parameter| match parameter.strip_prefix("password=") {
+                    Some(value) => {
+                        password = Some(percent_decode(value));
+                        false
+                    }
+                    None => true,
+                })
+                .collect();
+            if parameters.is_empty() {
+                path.to_owned()
+            } else {
+                format!("{}?{}", path, parameters.join("&"))
+            }
+        }
+        None => path.to_owned(),
+    };
+    (format!("{}{}{}", scheme, authority, path), password)
+}
+
+/// Undo the %XX escapes in part of a URL.  Anything that isn't a valid
+/// escape is kept as it is.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = text
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,97 @@
+// SQL shared by the database sinks.  Both SQLite and PostgreSQL accept the
+// same statements, as long as strings are quoted the standard way.
+// Allocations, parameters, measurements, and notes are stored as JSON text.
+use super::{json, ResultRecord};
+
+/// The most rows to put into a single INSERT statement.
+const ROWS_PER_INSERT: usize = 100;
+
+/// Which database the statements are for.  They only differ in how a
+/// carriage return is written.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Dialect {
+    Sqlite,
+    #[cfg(feature = "postgres")]
+    Postgres,
+}
+
+/// An SQL string, or NULL.  The sqlite3 program takes a carriage return
+/// before a newline for part of the line ending, even in a string, so each
+/// one is put in with a function instead, in either database.
+fn text(dialect: Dialect, value: Option<&str>) -> String {
+    let carriage_return = match dialect {
+        Dialect::Sqlite => "char(13)",
+        #[cfg(feature = "postgres")]
+        Dialect::Postgres => "chr(13)",
+    };
+    match value {
+        Some(v) => v
+            .split('\r')
+            .map(|part| format!("'{}'", part.replace('\'', "''")))
+            .collect::<Vec<String>>()
+            .join(&format!(" || {} || ", carriage_return)),
+        None => "NULL".to_owned(),
+    }
+}
+
+/// An SQL number, or NULL.
+fn number(value: Option<i64>) -> String {
+    value
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "NULL".to_owned())
+}
+
+fn row(dialect: Dialect, record: &ResultRecord) -> String {
+    let text = |value| text(dialect, value);
+    format!(
+        "({}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {})",
+        record.time,
+        text(Some(&record.scenario)),
+        text(record.run_id.as_deref()),
+        text(record.parent_run.as_deref()),
+        text(record.test.as_deref()),
+        text(Some(&record.verdict)),
+        text(Some(&record.reason)),
+        record
+            .duration_ms
+            .map(|d| d.to_string())
+            .unwrap_or_else(|| "NULL".to_owned()),
+        text(Some(&record.mode)),
+        text(Some(&record.build)),
+        text(Some(&record.station)),
+        text(record.line.as_deref()),
+        text(record.site.as_deref()),
+        text(record.boot_id.as_deref()),
+        record
+            .monotonic_ms
+            .map(|m| m.to_string())
+            .unwrap_or_else(|| "NULL".to_owned()),
+        text(record.public_key.as_deref()),
+        text(record.signature.as_deref()),
+        text(record.jig.as_deref()),
+        text(record.serial.as_deref()),
+        text(Some(&json(&record.allocations))),
+        text(Some(&json(&record.parameters))),
+        text(record.payload.as_deref()),
+        text(Some(&json(&record.measurements))),
+        if record.clock_unsynced { "TRUE" } else { "FALSE" },
+        text(Some(&json(&record.notes))),
+        number(record.code.map(i64::from)),
+        text(record.log_path.as_deref()),
+        number(record.cpu_ms.map(|c| c as i64)),
+        number(record.peak_memory_kb.map(|p| p as i64)),
+    )
+}
+
+/// Statements that insert the records into the "results" table, several rows at a time.
+pub fn inserts(dialect: Dialect, records: &[ResultRecord]) -> String {
+    let mut sql = String::new();
+    for chunk in records.chunks(ROWS_PER_INSERT) {
+        let rows: Vec<String> = chunk.iter().map(|record| row(dialect, record)).collect();
+        sql.push_str(&format!(
+            "INSERT INTO results (time, scenario, run_id, parent_run, test, verdict, reason, duration_ms, mode, build, station, line, site, boot_id, monotonic_ms, public_key, signature, jig, serial, allocations, parameters, payload, measurements, clock_unsynced, notes, code, log_path, cpu_ms, peak_memory_kb) VALUES\n{};\n",
+            rows.join(",\n")
+        ));
+    }
+    sql
+}
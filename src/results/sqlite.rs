@@ -0,0 +1,128 @@
+// Inserts results into an SQLite database, creating or upgrading the
+// "results" table if needed.  The statements are piped into the sqlite3 program, so it must be
+// installed on the station.
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use super::sql::{self, Dialect};
+use super::{ResultRecord, ResultSink};
+
+/// Each step brings the schema up one version, which is kept in the
+/// database's user_version.  The first step is also safe to run on databases
+/// made before versions were kept.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS results (
+    time INTEGER NOT NULL,
+    scenario TEXT NOT NULL,
+    run_id TEXT,
+    parent_run TEXT,
+    test TEXT,
+    verdict TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    duration_ms INTEGER
+)",
+    "ALTER TABLE results ADD COLUMN mode TEXT NOT NULL DEFAULT 'production'",
+    "ALTER TABLE results ADD COLUMN build TEXT",
+    "ALTER TABLE results ADD COLUMN station TEXT",
+    "ALTER TABLE results ADD COLUMN line TEXT",
+    "ALTER TABLE results ADD COLUMN site TEXT",
+    "ALTER TABLE results ADD COLUMN boot_id TEXT",
+    "ALTER TABLE results ADD COLUMN monotonic_ms INTEGER",
+    "ALTER TABLE results ADD COLUMN public_key TEXT",
+    "ALTER TABLE results ADD COLUMN signature TEXT",
+    "ALTER TABLE results ADD COLUMN jig TEXT",
+    "ALTER TABLE results ADD COLUMN serial TEXT",
+    "ALTER TABLE results ADD COLUMN allocations TEXT",
+    "ALTER TABLE results ADD COLUMN parameters TEXT",
+    "ALTER TABLE results ADD COLUMN payload TEXT",
+    "ALTER TABLE results ADD COLUMN measurements TEXT",
+    "ALTER TABLE results ADD COLUMN clock_unsynced INTEGER NOT NULL DEFAULT 0",
+    "ALTER TABLE results ADD COLUMN notes TEXT",
+    "ALTER TABLE results ADD COLUMN code INTEGER",
+    "ALTER TABLE results ADD COLUMN log_path TEXT",
+    "ALTER TABLE results ADD COLUMN cpu_ms INTEGER",
+    "ALTER TABLE results ADD COLUMN peak_memory_kb INTEGER",
+];
+
+pub struct SqliteSink {
+    path: PathBuf,
+    schema_ready: bool,
+}
+
+impl SqliteSink {
+    pub fn new(path: &Path) -> SqliteSink {
+        SqliteSink {
+            path: path.to_owned(),
+            schema_ready: false,
+        }
+    }
+
+    /// Run statements in sqlite3 and return what they printed.
+    fn sqlite(&self, statements: &str) -> io::Result<String> {
+        let mut sqlite = Command::new("sqlite3")
+            .arg("-bail")
+            .arg(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let written = sqlite
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(statements.as_bytes());
+        let output = sqlite.wait_with_output()?;
+        written?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "sqlite3 exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Apply any migrations the database hasn't seen yet.
+    fn migrate(&mut self) -> io::Result<()> {
+        if self.schema_ready {
+            return Ok(());
+        }
+        let version = self.sqlite("PRAGMA user_version;\n")?;
+        let version: usize = version.trim().parse().map_err(|_| {
+            io::Error::other(format!("unexpected schema version {:?}", version.trim()))
+        })?;
+        if version > MIGRATIONS.len() {
+            return Err(io::Error::other(format!(
+                "database schema version {} is newer than this exclave understands ({})",
+                version,
+                MIGRATIONS.len()
+            )));
+        }
+        if version < MIGRATIONS.len() {
+            let mut statements = "BEGIN IMMEDIATE;\n".to_owned();
+            for migration in MIGRATIONS.iter().skip(version) {
+                statements.push_str(&format!("{};\n", migration));
+            }
+            statements.push_str(&format!(
+                "PRAGMA user_version = {};\nCOMMIT;\n",
+                MIGRATIONS.len()
+            ));
+            self.sqlite(&statements)?;
+        }
+        self.schema_ready = true;
+        Ok(())
+    }
+}
+
+impl ResultSink for SqliteSink {
+    fn store(&mut self, records: &[ResultRecord]) -> io::Result<()> {
+        self.migrate()?;
+        self.sqlite(&format!(
+            "BEGIN;\n{}COMMIT;\n",
+            sql::inserts(Dialect::Sqlite, records)
+        ))
+        .map(|_| ())
+    }
+}
@@ -0,0 +1,176 @@
+// Describes what each kind of unit file accepts: the section it must have,
+// and for each directive, the sort of value it takes, what it is if it's
+// left out, and whether it's required.  Each kind's schema is kept beside the
+// code that parses it, so that tools such as unit file editors can offer
+// completions that match the version of exclave that's running.
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+
+use unit::UnitKind;
+use units::allocator::AllocatorDescription;
+use units::interface::InterfaceDescription;
+use units::jig::JigDescription;
+use units::logger::LoggerDescription;
+use units::notifier::NotifierDescription;
+use units::scenario::ScenarioDescription;
+use units::test::TestDescription;
+use units::trigger::TriggerDescription;
+
+/// The sort of value a directive takes.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum ValueType {
+    /// Any text, such as a Name.
+    Text,
+
+    /// "yes" or "no", or any of the other spellings parse_bool accepts.
+    Bool,
+
+    /// A time such as "90s", "5m", or "1h 30min".  A bare number is a number
+    /// of seconds, unless StrictDurations is set.
+    Duration,
+
+    /// A whole number.
+    Integer,
+
+    /// A percentage from 0 to 100, with or without a "%".
+    Percentage,
+
+    /// A regular expression.
+    Regex,
+
+    /// A path, relative to the unit's directory or working directory.
+    Path,
+
+    /// A space-separated list of paths.
+    Paths,
+
+    /// A command line to run.
+    Command,
+
+    /// An "http://" URL.
+    Url,
+
+    /// A space- or comma-separated list of names, such as counters.
+    Names,
+
+    /// A space- or comma-separated list of exit codes and ranges.
+    ExitStatus,
+
+    /// A space- or comma-separated list of exit codes and their verdicts.
+    ExitStatusMap,
+
+    /// The name of a unit of the given kind.
+    Unit(UnitKind),
+
+    /// A space- or comma-separated list of units of the given kind.
+    Units(UnitKind),
+
+    /// One of a fixed set of values.
+    Choice(Vec<String>),
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueType::Text => write!(f, "text"),
+            ValueType::Bool => write!(f, "bool"),
+            ValueType::Duration => write!(f, "duration"),
+            ValueType::Integer => write!(f, "integer"),
+            ValueType::Percentage => write!(f, "percentage"),
+            ValueType::Regex => write!(f, "regex"),
+            ValueType::Path => write!(f, "path"),
+            ValueType::Paths => write!(f, "paths"),
+            ValueType::Command => write!(f, "command"),
+            ValueType::Url => write!(f, "url"),
+            ValueType::Names => write!(f, "names"),
+            ValueType::ExitStatus => write!(f, "exit-status"),
+            ValueType::ExitStatusMap => write!(f, "exit-status-map"),
+            ValueType::Unit(ref kind) => write!(f, "unit:{}", kind),
+            ValueType::Units(ref kind) => write!(f, "units:{}", kind),
+            ValueType::Choice(ref values) => write!(f, "choice:{}", values.join(",")),
+        }
+    }
+}
+
+/// Types are written the same way in JSON as they are to interfaces.
+impl Serialize for ValueType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+fn serialize_kind<S: Serializer>(kind: &UnitKind, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(kind)
+}
+
+/// One directive that a unit file may contain.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize)]
+pub struct DirectiveSchema {
+    pub name: &'static str,
+
+    #[serde(rename = "type")]
+    pub value_type: ValueType,
+
+    /// The value it has if it's left out, as it would be written in the
+    /// unit file, or None if leaving it out turns the feature off.
+    pub default: Option<&'static str>,
+
+    pub required: bool,
+}
+
+impl DirectiveSchema {
+    pub fn new(name: &'static str, value_type: ValueType) -> Self {
+        DirectiveSchema {
+            name,
+            value_type,
+            default: None,
+            required: false,
+        }
+    }
+
+    pub fn with_default(mut self, default: &'static str) -> Self {
+        self.default = Some(default);
+        self
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+}
+
+/// Everything one kind of unit file may contain.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize)]
+pub struct UnitSchema {
+    /// The kind of unit, which is also the suffix of its files.
+    #[serde(serialize_with = "serialize_kind")]
+    pub kind: UnitKind,
+
+    /// The section that the directives go in, such as "Test".
+    pub section: &'static str,
+
+    pub directives: Vec<DirectiveSchema>,
+}
+
+/// The schema of every kind of unit file, in order of kind.
+pub fn unit_schemas() -> Vec<UnitSchema> {
+    vec![
+        AllocatorDescription::schema(),
+        InterfaceDescription::schema(),
+        JigDescription::schema(),
+        LoggerDescription::schema(),
+        NotifierDescription::schema(),
+        ScenarioDescription::schema(),
+        TestDescription::schema(),
+        TriggerDescription::schema(),
+    ]
+}
+
+/// The schema of one kind of unit file, or None for internal units, which
+/// don't have files.
+pub fn unit_schema(kind: &UnitKind) -> Option<UnitSchema> {
+    unit_schemas()
+        .into_iter()
+        .find(|schema| schema.kind == *kind)
+}
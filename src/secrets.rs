@@ -0,0 +1,231 @@
+// Secrets, such as Wi-Fi passphrases and API tokens, that unit files refer
+// to rather than spell out, so they aren't visible to anyone who can read the
+// unit directories or connect to the interface.  A command refers to one as
+//
+//     ExecStart=wifi-join --ssid factory --psk ${secret:factory-psk}
+//
+// and the reference is only replaced as the command is started.  Secrets are
+// read from SecretsFile, which must only be readable by its owner, as
+// "NAME=value" lines, and from environment variables named
+// EXCLAVE_SECRET_<NAME>, with "-" and "." in the name written as "_".  The
+// secrets file is read afresh for each command, so it can be changed without
+// a restart.  Resolved values are masked in test output, and nothing that
+// reports a command reports it with its secrets filled in.
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sys;
+
+/// What starts a reference to a secret.
+const REFERENCE_START: &str = "${secret:";
+
+/// The prefix of environment variables that hold secrets.
+pub const ENVIRONMENT_PREFIX: &str = "EXCLAVE_SECRET_";
+
+/// What a secret's value is replaced with in test output.
+pub const MASK: &str = "********";
+
+#[derive(Debug)]
+pub enum SecretError {
+    /// A command referred to a secret that isn't defined anywhere.
+    Unknown(String),
+
+    /// A reference wasn't closed, or named something that can't be a secret.
+    InvalidReference(String),
+
+    /// The secrets file can be read by users other than its owner.
+    NotPrivate(PathBuf),
+
+    /// The secrets file couldn't be read.
+    Io(PathBuf, io::Error),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SecretError::Unknown(ref name) => write!(f, "unknown secret \"{}\"", name),
+            SecretError::InvalidReference(ref reference) => {
+                write!(f, "invalid secret reference \"{}\"", reference)
+            }
+            SecretError::NotPrivate(ref path) => write!(
+                f,
+                "secrets file {} can be read by other users, so it won't be used",
+                path.display()
+            ),
+            SecretError::Io(ref path, ref e) => {
+                write!(f, "unable to read secrets file {}: {}", path.display(), e)
+            }
+        }
+    }
+}
+
+/// Whether a name can be used for a secret.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// The environment variable a secret may be given in.
+pub fn environment_variable(name: &str) -> String {
+    format!(
+        "{}{}",
+        ENVIRONMENT_PREFIX,
+        name.to_ascii_uppercase().replace(['-', '.'], "_")
+    )
+}
+
+/// Whether some text refers to any secrets, and so needs resolving.
+pub fn has_references(text: &str) -> bool {
+    text.contains(REFERENCE_START)
+}
+
+/// Fill in the secrets some text refers to from a secrets file, if there is
+/// one, and the environment.  The file is only read if there are any, so a
+/// missing or unsafe secrets file doesn't stop commands that don't use it.
+pub fn resolve(file: Option<&Path>, text: &str) -> Result<Resolved, SecretError> {
+    if !has_references(text) {
+        return Ok(Resolved {
+            text: text.to_owned(),
+            values: vec![],
+        });
+    }
+    Secrets::load(file)?.resolve(text)
+}
+
+/// A command with its secrets filled in, along with the values that went
+/// into it, so they can be masked in whatever it prints.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Resolved {
+    pub text: String,
+    pub values: Vec<String>,
+}
+
+impl Resolved {
+    /// Mask every secret that went into the command in a line it printed.
+    pub fn mask(&self, line: &str) -> String {
+        let mut line = line.to_owned();
+        for value in &self.values {
+            if line.contains(value.as_str()) {
+                line = line.replace(value.as_str(), MASK);
+            }
+        }
+        line
+    }
+}
+
+/// The secrets that can be referred to.
+#[derive(Debug, Clone)]
+pub struct Secrets {
+    values: BTreeMap<String, String>,
+    environment: bool,
+}
+
+impl Default for Secrets {
+    fn default() -> Self {
+        Secrets::new()
+    }
+}
+
+impl Secrets {
+    /// Secrets that are only found in the environment.
+    pub fn new() -> Secrets {
+        Secrets {
+            values: BTreeMap::new(),
+            environment: true,
+        }
+    }
+
+    /// Secrets from a secrets file, if there is one, and the environment.
+    pub fn load(file: Option<&Path>) -> Result<Secrets, SecretError> {
+        let mut secrets = Secrets::new();
+        if let Some(path) = file {
+            match sys::is_private_file(path) {
+                Ok(true) => (),
+                Ok(false) => return Err(SecretError::NotPrivate(path.to_owned())),
+                Err(e) => return Err(SecretError::Io(path.to_owned(), e)),
+            }
+            let contents =
+                fs::read_to_string(path).map_err(|e| SecretError::Io(path.to_owned(), e))?;
+            secrets.values = Self::parse(&contents);
+        }
+        Ok(secrets)
+    }
+
+    /// Secrets with the given values, and nothing from the environment.
+    pub fn from_values(values: BTreeMap<String, String>) -> Secrets {
+        Secrets {
+            values,
+            environment: false,
+        }
+    }
+
+    /// Read "NAME=value" lines, skipping blank lines and "#" comments.  The
+    /// value is everything after the first "=", which may itself contain "=".
+    pub fn parse(contents: &str) -> BTreeMap<String, String> {
+        let mut values = BTreeMap::new();
+        for line in contents.lines() {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            if let Some((name, value)) = line.split_once('=') {
+                let name = name.trim();
+                if valid_name(name) {
+                    values.insert(name.to_owned(), value.trim().to_owned());
+                }
+            }
+        }
+        values
+    }
+
+    /// The value of a secret, with the secrets file taking precedence over
+    /// the environment.
+    pub fn get(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.values.get(name) {
+            return Some(value.clone());
+        }
+        if self.environment {
+            return env::var(environment_variable(name)).ok();
+        }
+        None
+    }
+
+    /// Replace every `${secret:NAME}` in some text with the secret's value.
+    pub fn resolve(&self, text: &str) -> Result<Resolved, SecretError> {
+        let mut resolved = Resolved::default();
+        let mut rest = text;
+        while let Some(start) = rest.find(REFERENCE_START) {
+            resolved.text.push_str(&rest[..start]);
+            let after = &rest[start + REFERENCE_START.len()..];
+            let end = match after.find('}') {
+                Some(end) => end,
+                None => return Err(SecretError::InvalidReference(rest[start..].to_owned())),
+            };
+            let name = &after[..end];
+            if !valid_name(name) {
+                return Err(SecretError::InvalidReference(
+                    rest[start..start + REFERENCE_START.len() + end + 1].to_owned(),
+                ));
+            }
+            let value = self
+                .get(name)
+                .ok_or_else(|| SecretError::Unknown(name.to_owned()))?;
+            resolved.text.push_str(&value);
+            if !value.is_empty() && !resolved.values.contains(&value) {
+                resolved.values.push(value);
+            }
+            rest = &after[end + 1..];
+        }
+        resolved.text.push_str(rest);
+        // Longest first, so a secret that contains another is masked whole.
+        resolved
+            .values
+            .sort_by_key(|value| std::cmp::Reverse(value.len()));
+        Ok(resolved)
+    }
+}
@@ -0,0 +1,100 @@
+// Bundles up how a station is set up, to attach to a support ticket, so that
+// a misbehaving station can be set up the same way somewhere else.  A
+// snapshot is a .tar.gz holding one directory, "exclave-snapshot", with:
+//
+// * version.txt: which build of exclave took the snapshot, one "key=value"
+//   per line, with the same fields as VERSION.
+// * config/effective.conf: the settings in effect, defaults included, and
+//   config/<name>: the config file as it was given with "-f", if it was.
+// * units/<unit>: every unit file that's loaded, or that LazyTests put off,
+//   and units.txt: the state of each and where its file was found.
+// * stats.txt: how each test has done since exclave started, as with STATS.
+//
+// The archive is made with "tar -cz", the same as runs that are uploaded.
+// Secrets aren't included, since unit files and settings only name them.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The directory that everything in a snapshot is in.
+pub const SNAPSHOT_DIRECTORY: &str = "exclave-snapshot";
+
+/// The directory under the StateDirectory that SNAPSHOT saves snapshots to.
+pub const SNAPSHOTS_DIRECTORY: &str = "snapshots";
+
+/// The name to give a snapshot taken at a given time.
+pub fn file_name(when: SystemTime) -> String {
+    let secs = when
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format!("snapshot-{}.tar.gz", secs)
+}
+
+/// The files that go into a snapshot, by where they go in it.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    files: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl Snapshot {
+    pub fn new() -> Snapshot {
+        Default::default()
+    }
+
+    /// Add a file, at a path relative to the snapshot's directory.
+    pub fn add<P: AsRef<Path>>(&mut self, path: P, contents: &[u8]) {
+        self.files
+            .push((path.as_ref().to_owned(), contents.to_owned()));
+    }
+
+    /// Write the snapshot out as a .tar.gz.  The files are put in a
+    /// directory under the system's temporary directory first, which is
+    /// removed again afterwards.
+    pub fn write(&self, archive: &Path) -> io::Result<()> {
+        // tar is run from elsewhere, so it needs the whole path.
+        let archive = std::env::current_dir()?.join(archive);
+        let staging = std::env::temp_dir().join(format!(
+            "exclave-snapshot-{}-{}",
+            process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0)
+        ));
+        let result = self.write_from(&staging, &archive);
+        fs::remove_dir_all(&staging).ok();
+        result
+    }
+
+    fn write_from(&self, staging: &Path, archive: &Path) -> io::Result<()> {
+        let root = staging.join(SNAPSHOT_DIRECTORY);
+        fs::create_dir_all(&root)?;
+        for (path, contents) in &self.files {
+            let path = root.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, contents)?;
+        }
+        if let Some(parent) = archive.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let status = Command::new("tar")
+            .arg("-czf")
+            .arg(archive)
+            .arg("-C")
+            .arg(staging)
+            .arg(SNAPSHOT_DIRECTORY)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("tar exited with {}", status)));
+        }
+        Ok(())
+    }
+}
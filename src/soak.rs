@@ -0,0 +1,293 @@
+// Reports on burn-in runs, so that nobody has to dig through hours of logs to
+// find where a soak first went wrong.  When a scenario with Repeat or Matrix
+// finishes, its run directory gets:
+//
+//     iterations.csv     one row per pass, with its Matrix values and each
+//                        test's verdict
+//     soak-report.txt    the first failing pass and test, failures by test,
+//                        and passes by Matrix value
+//
+// and a line for the jig it ran on (its DUT position) is updated in
+// "soak-positions.tsv" in the LogDirectory, which always holds the latest
+// burn-in on each position.
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use unit::UnitName;
+use unitbroadcaster::ScenarioSummary;
+use units::test::TestVerdict;
+
+/// The per-pass result matrix, in each run directory.
+pub const MATRIX_FILE: &str = "iterations.csv";
+
+/// The summarized report, in each run directory.
+pub const REPORT_FILE: &str = "soak-report.txt";
+
+/// The latest burn-in on each position, in the LogDirectory.
+pub const POSITIONS_FILE: &str = "soak-positions.tsv";
+
+/// What a run without a jig is listed as in the positions file.
+const NO_POSITION: &str = "-";
+
+const POSITIONS_HEADER: &str = "# position\tscenario\trun\tserial\titerations\tfailed\tfirst failing iteration\tfirst failing test\treason";
+
+/// Where a burn-in first went wrong.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct FirstFailure {
+    /// The pass it happened in, starting from 1.
+    pub iteration: u32,
+    pub test: UnitName,
+    pub verdict: TestVerdict,
+    pub reason: String,
+}
+
+/// Find the first test to fail or hit a fault, in the first pass that had one.
+pub fn first_failure(summary: &ScenarioSummary) -> Option<FirstFailure> {
+    summary.iterations.iter().find_map(|iteration| {
+        let (test, reason) = iteration.first_failure.clone()?;
+        let verdict = iteration
+            .verdicts
+            .iter()
+            .find(|(name, _)| *name == test)
+            .map(|(_, verdict)| *verdict)
+            .unwrap_or(TestVerdict::Fail);
+        Some(FirstFailure {
+            iteration: iteration.iteration,
+            test,
+            verdict,
+            reason,
+        })
+    })
+}
+
+/// Every test that appears in any pass, in the order they first appear.
+fn tests(summary: &ScenarioSummary) -> Vec<UnitName> {
+    let mut tests: Vec<UnitName> = vec![];
+    for iteration in &summary.iterations {
+        for (test, _) in &iteration.verdicts {
+            if !tests.contains(test) {
+                tests.push(test.clone());
+            }
+        }
+    }
+    tests
+}
+
+/// Every parameter the scenario's Matrix set in any pass, in the order they
+/// first appear.
+fn parameters(summary: &ScenarioSummary) -> Vec<String> {
+    let mut parameters: Vec<String> = vec![];
+    for iteration in &summary.iterations {
+        for (name, _) in &iteration.parameters {
+            if !parameters.contains(name) {
+                parameters.push(name.clone());
+            }
+        }
+    }
+    parameters
+}
+
+/// The result matrix, as CSV: a row for each pass, giving how long it took,
+/// the values its Matrix set, and each test's verdict, or nothing for tests
+/// that weren't in that pass.
+pub fn matrix(summary: &ScenarioSummary) -> String {
+    let tests = tests(summary);
+    let parameters = parameters(summary);
+    let mut csv = String::from("iteration,duration");
+    for parameter in &parameters {
+        write!(csv, ",{}", parameter).unwrap();
+    }
+    for test in &tests {
+        write!(csv, ",{}", test).unwrap();
+    }
+    csv.push('\n');
+    for iteration in &summary.iterations {
+        write!(
+            csv,
+            "{},{}.{:03}",
+            iteration.iteration,
+            iteration.duration.as_secs(),
+            iteration.duration.subsec_millis()
+        )
+        .unwrap();
+        for parameter in &parameters {
+            csv.push(',');
+            if let Some((_, value)) = iteration.parameters.iter().find(|(n, _)| n == parameter) {
+                csv.push_str(value);
+            }
+        }
+        for test in &tests {
+            csv.push(',');
+            if let Some((_, verdict)) = iteration.verdicts.iter().find(|(name, _)| name == test) {
+                write!(csv, "{}", verdict).unwrap();
+            }
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+/// A summary of a burn-in for people: where it ran, where it first failed,
+/// and which passes each test failed in.
+pub fn report(summary: &ScenarioSummary) -> String {
+    let failed = summary.iterations.iter().filter(|i| !i.is_pass()).count();
+    let mut text = String::new();
+    writeln!(text, "Scenario: {}", summary.scenario).unwrap();
+    writeln!(text, "Position: {}", position(summary)).unwrap();
+    if let Some(ref serial) = summary.serial {
+        writeln!(text, "Serial: {}", serial).unwrap();
+    }
+    if let Some(ref run) = summary.run_id {
+        writeln!(text, "Run: {}", run).unwrap();
+    }
+    writeln!(text, "Result: {}", summary).unwrap();
+    writeln!(
+        text,
+        "Iterations: {} ({} failed)",
+        summary.iterations.len(),
+        failed
+    )
+    .unwrap();
+    match first_failure(summary) {
+        Some(first) => writeln!(
+            text,
+            "First failure: iteration {}, {} ({}): {}",
+            first.iteration, first.test, first.verdict, first.reason
+        )
+        .unwrap(),
+        None => writeln!(text, "First failure: none").unwrap(),
+    }
+
+    let mut by_test: BTreeMap<&UnitName, Vec<u32>> = BTreeMap::new();
+    for iteration in &summary.iterations {
+        for (test, verdict) in &iteration.verdicts {
+            if matches!(verdict, TestVerdict::Fail | TestVerdict::Fault) {
+                by_test.entry(test).or_default().push(iteration.iteration);
+            }
+        }
+    }
+    if !by_test.is_empty() {
+        writeln!(text, "Failures by test:").unwrap();
+        for (test, iterations) in by_test {
+            let list: Vec<String> = iterations.iter().map(|i| i.to_string()).collect();
+            writeln!(
+                text,
+                "    {}: {} (iterations {})",
+                test,
+                iterations.len(),
+                list.join(", ")
+            )
+            .unwrap();
+        }
+    }
+
+    // How the passes went for each value of each axis of the Matrix.
+    let parameters = parameters(summary);
+    if !parameters.is_empty() {
+        writeln!(text, "Passes by value:").unwrap();
+        for parameter in &parameters {
+            let mut values: Vec<(&str, u32, u32)> = vec![];
+            for iteration in &summary.iterations {
+                let value = match iteration.parameters.iter().find(|(n, _)| n == parameter) {
+                    Some((_, value)) => value.as_str(),
+                    None => continue,
+                };
+                let index = match values.iter().position(|(v, _, _)| *v == value) {
+                    Some(index) => index,
+                    None => {
+                        values.push((value, 0, 0));
+                        values.len() - 1
+                    }
+                };
+                values[index].2 += 1;
+                if iteration.is_pass() {
+                    values[index].1 += 1;
+                }
+            }
+            for (value, passed, total) in values {
+                writeln!(
+                    text,
+                    "    {}={}: {} of {} passed",
+                    parameter, value, passed, total
+                )
+                .unwrap();
+            }
+        }
+    }
+    text
+}
+
+/// The position a run was on: the jig's name, if it had one.
+fn position(summary: &ScenarioSummary) -> String {
+    match summary.jig {
+        Some(ref jig) => jig.to_string(),
+        None => NO_POSITION.to_owned(),
+    }
+}
+
+/// One line of the positions file, without its line ending.
+fn position_line(summary: &ScenarioSummary) -> String {
+    let clean = |s: &str| s.replace(['\t', '\r', '\n'], " ");
+    let first = first_failure(summary);
+    [
+        position(summary),
+        summary.scenario.to_string(),
+        summary.run_id.clone().unwrap_or_default(),
+        clean(summary.serial.as_deref().unwrap_or("")),
+        summary.iterations.len().to_string(),
+        summary
+            .iterations
+            .iter()
+            .filter(|i| !i.is_pass())
+            .count()
+            .to_string(),
+        first
+            .as_ref()
+            .map(|f| f.iteration.to_string())
+            .unwrap_or_default(),
+        first
+            .as_ref()
+            .map(|f| f.test.to_string())
+            .unwrap_or_default(),
+        clean(first.as_ref().map(|f| f.reason.as_str()).unwrap_or("")),
+    ]
+    .join("\t")
+}
+
+/// Write the matrix and report into a run directory.
+pub fn write_run_report(run_directory: &Path, summary: &ScenarioSummary) -> io::Result<()> {
+    fs::write(run_directory.join(MATRIX_FILE), matrix(summary))?;
+    fs::write(run_directory.join(REPORT_FILE), report(summary))
+}
+
+/// Replace the line for a run's position in the positions file, keeping
+/// the others, sorted by position.
+pub fn update_positions(log_directory: &Path, summary: &ScenarioSummary) -> io::Result<()> {
+    let path = log_directory.join(POSITIONS_FILE);
+    let mut lines: BTreeMap<String, String> = BTreeMap::new();
+    match fs::read_to_string(&path) {
+        Ok(contents) => {
+            for line in contents.lines().filter(|l| !l.starts_with('#')) {
+                if let Some((position, _)) = line.split_once('\t') {
+                    lines.insert(position.to_owned(), line.to_owned());
+                }
+            }
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+    lines.insert(position(summary), position_line(summary));
+
+    let mut contents = format!("{}\n", POSITIONS_HEADER);
+    for line in lines.values() {
+        contents.push_str(line);
+        contents.push('\n');
+    }
+    // Written aside and moved into place, so readers never see half a file.
+    let partial = log_directory.join(format!("{}.partial", POSITIONS_FILE));
+    fs::write(&partial, contents)?;
+    fs::rename(&partial, &path)
+}
@@ -0,0 +1,132 @@
+// Specifiers, as in systemd, that commands such as ExecStart use to refer to
+// what they're running for, rather than digging it out of the environment in
+// a wrapper script:
+//
+//     ExecStart=flash-firmware --jig %J --serial %S --log %t/flash-%R.log
+//
+//  * %n is the name of the unit, such as "flash.test".
+//  * %J is the name of the jig that tests are run against.
+//  * %S is the serial number the current run's tests have reported.
+//  * %R is the current run's id, the name of its directory in LogDirectory.
+//  * %t is the StateDirectory.
+//  * %% is a "%".
+//
+// Any other "%" is refused when the unit is loaded.  Those that have nothing
+// to refer to, such as %S before any test has reported a serial number, are
+// left empty.  Specifiers are filled in after a command is split into
+// arguments, so each one stays part of the argument it was written in.
+use std::fmt;
+
+use unit::UnitDescriptionError;
+
+/// The specifiers that may be used, for error messages.
+const SPECIFIERS: &str = "%n, %J, %S, %R, %t, or %%";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpecifierError {
+    /// A "%" was followed by something that isn't a specifier.
+    Unknown(String),
+}
+
+impl fmt::Display for SpecifierError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SpecifierError::Unknown(ref s) => write!(f, "unknown specifier \"{}\"", s),
+        }
+    }
+}
+
+/// A piece of text with its specifiers picked out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Piece {
+    /// Text that's used as it is.
+    Text(String),
+
+    /// A specifier, such as 'n' for %n.
+    Specifier(char),
+}
+
+/// Pick out the specifiers in some text.  "%%" comes back as text.
+pub fn parse(text: &str) -> Result<Vec<Piece>, SpecifierError> {
+    let mut pieces = vec![];
+    let mut literal = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => literal.push('%'),
+            Some(c) if "nJSRt".contains(c) => {
+                if !literal.is_empty() {
+                    pieces.push(Piece::Text(literal.split_off(0)));
+                }
+                pieces.push(Piece::Specifier(c));
+            }
+            Some(c) => return Err(SpecifierError::Unknown(format!("%{}", c))),
+            None => return Err(SpecifierError::Unknown("%".to_owned())),
+        }
+    }
+    if !literal.is_empty() {
+        pieces.push(Piece::Text(literal));
+    }
+    Ok(pieces)
+}
+
+/// Check that a directive only uses specifiers that exist.
+pub fn check(section: &str, key: &str, text: &str) -> Result<(), UnitDescriptionError> {
+    parse(text).map(|_| ()).map_err(|e| {
+        UnitDescriptionError::InvalidValue(
+            section.to_owned(),
+            key.to_owned(),
+            format!("{} ({})", text, e),
+            vec![format!("text with no specifiers other than {}", SPECIFIERS)],
+        )
+    })
+}
+
+/// What each specifier stands for when a command is started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Specifiers {
+    /// %n
+    pub unit: String,
+
+    /// %J
+    pub jig: Option<String>,
+
+    /// %S
+    pub serial: Option<String>,
+
+    /// %R
+    pub run_id: Option<String>,
+
+    /// %t
+    pub state_directory: Option<String>,
+}
+
+impl Specifiers {
+    /// What a specifier stands for, or nothing if it has nothing to refer to.
+    pub fn get(&self, specifier: char) -> &str {
+        match specifier {
+            'n' => &self.unit,
+            'J' => self.jig.as_deref().unwrap_or(""),
+            'S' => self.serial.as_deref().unwrap_or(""),
+            'R' => self.run_id.as_deref().unwrap_or(""),
+            't' => self.state_directory.as_deref().unwrap_or(""),
+            _ => "",
+        }
+    }
+
+    /// Fill in the specifiers in some text.
+    pub fn expand(&self, text: &str) -> Result<String, SpecifierError> {
+        let mut expanded = String::new();
+        for piece in parse(text)? {
+            match piece {
+                Piece::Text(text) => expanded.push_str(&text),
+                Piece::Specifier(c) => expanded.push_str(self.get(c)),
+            }
+        }
+        Ok(expanded)
+    }
+}
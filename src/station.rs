@@ -0,0 +1,160 @@
+// The station's identity: an id that is made up the first time exclave runs
+// with a StateDirectory and kept in the "station" file there, so that fleet
+// analytics can tell stations apart even after one is renamed or reimaged
+// with a new hostname.  The file also says which production line and site
+// the station belongs to, which are left for whoever sets it up to fill in:
+//
+//     Id=5f0c9a3e1d2b4c8a9e7f6a5b4c3d2e1f
+//     Line=Line 4
+//     Site=Shenzhen
+//
+// The identity goes into every scenario summary, result record, run
+// directory, and metric, and interfaces can ask for it with STATION.
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha256::Sha256;
+use sys;
+
+/// The name of the identity file in the StateDirectory.
+pub const STATION_FILE: &str = "station";
+
+/// What a station is called if it has neither an identity file nor a hostname.
+const UNKNOWN_STATION: &str = "station";
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
+pub struct Station {
+    /// Stays the same for as long as the StateDirectory does.
+    pub id: String,
+
+    /// The production line the station is on, if it's been set.
+    #[serde(default)]
+    pub line: Option<String>,
+
+    /// Where the station is, if it's been set.
+    #[serde(default)]
+    pub site: Option<String>,
+}
+
+impl Station {
+    /// The identity of a station with nowhere to keep one: its hostname.
+    pub fn from_hostname() -> Station {
+        Station {
+            id: sys::hostname().unwrap_or_else(|| UNKNOWN_STATION.to_owned()),
+            line: None,
+            site: None,
+        }
+    }
+
+    /// Read the identity file in a state directory, making one with a new
+    /// id if there isn't one yet.
+    pub fn load_or_create(state_directory: &Path) -> io::Result<Station> {
+        let path = state_directory.join(STATION_FILE);
+        if path.exists() {
+            return Station::parse(&fs::read_to_string(&path)?).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            });
+        }
+        let station = Station {
+            id: new_id(),
+            line: None,
+            site: None,
+        };
+        fs::write(&path, station.file_contents())?;
+        Ok(station)
+    }
+
+    /// Read an identity file.  Lines are "Key=Value", and blank lines and
+    /// those starting with "#" are skipped.
+    pub fn parse(contents: &str) -> Result<Station, String> {
+        let mut id = None;
+        let mut line = None;
+        let mut site = None;
+        for text in contents.lines() {
+            let text = text.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+            let (key, value) = text
+                .split_once('=')
+                .ok_or_else(|| format!("expected Key=Value, not \"{}\"", text))?;
+            let value = value.trim();
+            let value = if value.is_empty() {
+                None
+            } else {
+                Some(value.to_owned())
+            };
+            match key.trim() {
+                "Id" => id = value,
+                "Line" => line = value,
+                "Site" => site = value,
+                key => return Err(format!("unknown key \"{}\"", key)),
+            }
+        }
+        let id = id.ok_or_else(|| "there is no Id".to_owned())?;
+        if id.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(format!("Id \"{}\" may not contain spaces", id));
+        }
+        Ok(Station { id, line, site })
+    }
+
+    /// What the identity file for this station says.
+    pub fn file_contents(&self) -> String {
+        format!(
+            "Id={}\nLine={}\nSite={}\n",
+            self.id,
+            self.line.as_deref().unwrap_or(""),
+            self.site.as_deref().unwrap_or("")
+        )
+    }
+
+    /// The line and site, as (name, value), leaving out those not set.
+    pub fn details(&self) -> Vec<(&'static str, String)> {
+        let mut details = vec![];
+        if let Some(ref line) = self.line {
+            details.push(("line", line.clone()));
+        }
+        if let Some(ref site) = self.site {
+            details.push(("site", site.clone()));
+        }
+        details
+    }
+
+    /// The station as (name, value) labels: its id, then its details.
+    pub fn labels(&self) -> Vec<(&'static str, String)> {
+        let mut labels = vec![("station", self.id.clone())];
+        labels.extend(self.details());
+        labels
+    }
+}
+
+impl fmt::Display for Station {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.id)
+    }
+}
+
+/// A new 128-bit id, as hex.  If the system has no random numbers, one is
+/// made from things that are unlikely to be the same on two stations.
+pub fn new_id() -> String {
+    let mut bytes = [0; 16];
+    let random = File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut bytes));
+    if random.is_err() {
+        let mut hasher = Sha256::new();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        hasher.update(format!("{:?}", now).as_bytes());
+        hasher.update(process::id().to_string().as_bytes());
+        hasher.update(sys::hostname().unwrap_or_default().as_bytes());
+        bytes.copy_from_slice(&hasher.finish_bytes()[..16]);
+    }
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
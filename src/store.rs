@@ -0,0 +1,104 @@
+// A small key-value store for each jig, kept in the state directory, so that
+// something a test measures in one run, such as a calibration constant, can
+// be read back by later runs.  Tests are given the path to their jig's store
+// in $EXCLAVE_STORE.  Each line of the file is "key=value", and a later line
+// for the same key replaces an earlier one, so a test can add to it with
+// nothing more than:
+//
+//     echo "offset=0.137" >> "$EXCLAVE_STORE"
+//
+// and read it back with grep.  An empty value removes the key.  Exclave tidies
+// the file up, so each key is on one line, before each test that uses it.
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use unit::UnitName;
+
+/// Name of the directory under the state directory that holds each jig's store.
+pub const STORE_DIRECTORY: &str = "store";
+
+/// The entries in a store, by key.
+pub type Entries = BTreeMap<String, String>;
+
+/// Where the store for a jig is kept under the state directory.  Jigs in a
+/// subdirectory of a unit directory share a file name with "/" escaped.
+pub fn path(state_directory: &Path, jig: &UnitName) -> PathBuf {
+    state_directory
+        .join(STORE_DIRECTORY)
+        .join(jig.id().replace('%', "%25").replace('/', "%2F"))
+}
+
+/// Whether a key can be written to a store and read back again.
+pub fn valid_key(key: &str) -> bool {
+    !key.is_empty() && !key.contains('=') && !key.contains(char::is_whitespace)
+}
+
+/// Read the entries in a store.  Lines that aren't "key=value", and
+/// comments starting with "#", are skipped.
+pub fn parse(contents: &str) -> Entries {
+    let mut entries = Entries::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        if !valid_key(key) {
+            continue;
+        }
+        if value.is_empty() {
+            entries.remove(key);
+        } else {
+            entries.insert(key.to_owned(), value.to_owned());
+        }
+    }
+    entries
+}
+
+/// Write out entries, one line each, sorted by key.
+pub fn to_string(entries: &Entries) -> String {
+    let mut contents = String::new();
+    for (key, value) in entries {
+        contents.push_str(&format!("{}={}\n", key, value));
+    }
+    contents
+}
+
+/// Read a store, which is empty if it doesn't exist yet.
+pub fn read(path: &Path) -> io::Result<Entries> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(parse(&contents)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Entries::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replace the contents of a store.
+pub fn write(path: &Path, entries: &Entries) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, to_string(entries))
+}
+
+/// Rewrite a store so each key is on one line, creating it if it doesn't
+/// exist, so a test can read it and append to it.
+pub fn tidy(path: &Path) -> io::Result<()> {
+    if path.exists() {
+        write(path, &read(path)?)
+    } else {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map(|_| ())
+    }
+}
@@ -0,0 +1,195 @@
+// Starting programs that are meant to keep running (interfaces, loggers,
+// triggers, and daemon tests) again when they exit without being stopped.
+// Units say what they want with the same directives as systemd:
+//
+//     Restart=on-failure
+//     RestartSec=2s
+//     StartLimitBurst=5
+//
+// The first restart is after RestartSec, and the wait doubles each time the
+// program exits again soon after, up to MAX_RESTART_DELAY, so one that can't
+// stay up doesn't spin.  Once it has been restarted StartLimitBurst times in
+// a row it's left stopped.  A program that stays up for MAX_RESTART_DELAY
+// starts the count again.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use duration;
+use unit::{UnitDescriptionError, UnitName};
+
+/// The longest to wait before restarting a program.  One that ran for at
+/// least this long before exiting is treated as having started afresh.
+pub const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// When to start a program again after it exits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Leave it stopped.
+    No,
+
+    /// Start it again if it failed.  Interfaces, loggers, and triggers are
+    /// meant to keep running, so for them any exit is a failure.  Daemon
+    /// tests fail if they exit with anything other than 0.
+    OnFailure,
+
+    /// Start it again however it exited.
+    Always,
+}
+
+impl RestartPolicy {
+    /// The values that Restart= may be given.
+    pub fn choices() -> Vec<String> {
+        vec![
+            "no".to_owned(),
+            "on-failure".to_owned(),
+            "always".to_owned(),
+        ]
+    }
+
+    /// Whether a program that exited, failing or not, should be started again.
+    pub fn restarts(self, failed: bool) -> bool {
+        match self {
+            RestartPolicy::No => false,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+/// How a unit is restarted, from its Restart, RestartSec, and
+/// StartLimitBurst directives.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Restart {
+    pub policy: RestartPolicy,
+
+    /// How long to wait before the first restart in a row.
+    pub delay: Duration,
+
+    /// How many restarts in a row are allowed before giving up, or 0 to
+    /// never give up.
+    pub burst: u32,
+}
+
+impl Default for Restart {
+    fn default() -> Self {
+        Restart {
+            policy: RestartPolicy::No,
+            delay: Duration::from_secs(1),
+            burst: 5,
+        }
+    }
+}
+
+impl Restart {
+    /// Read one of Restart, RestartSec, or StartLimitBurst from a section.
+    pub fn set(
+        &mut self,
+        section: &str,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<(), UnitDescriptionError> {
+        let value = match value {
+            Some(value) => value.trim(),
+            None => {
+                return Err(UnitDescriptionError::MissingValue(
+                    section.to_owned(),
+                    key.to_owned(),
+                ))
+            }
+        };
+        let invalid = |choices: Vec<String>| {
+            UnitDescriptionError::InvalidValue(
+                section.to_owned(),
+                key.to_owned(),
+                value.to_owned(),
+                choices,
+            )
+        };
+        match key {
+            "Restart" => {
+                self.policy = match value {
+                    "no" => RestartPolicy::No,
+                    "on-failure" => RestartPolicy::OnFailure,
+                    "always" => RestartPolicy::Always,
+                    _ => return Err(invalid(RestartPolicy::choices())),
+                }
+            }
+            "RestartSec" => self.delay = duration::parse(value)?,
+            "StartLimitBurst" => {
+                self.burst = value
+                    .parse()
+                    .map_err(|_| invalid(vec!["a number of restarts".to_owned()]))?
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// What to do about a program that exited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Leave it stopped, as its Restart policy says.
+    Leave,
+
+    /// Start it again after a wait.  This is the given restart in a row.
+    Restart(Duration, u32),
+
+    /// It has already been restarted StartLimitBurst times in a row.
+    GiveUp(u32),
+}
+
+/// Keeps track of how often each unit's program has been restarted.
+#[derive(Default)]
+pub struct Supervisor {
+    /// How many times in a row each program has been restarted, and when it
+    /// was last started.
+    history: HashMap<UnitName, (u32, Instant)>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Supervisor::default()
+    }
+
+    /// Note that a unit's program has been started.
+    pub fn started(&mut self, id: &UnitName) {
+        self.history
+            .entry(id.clone())
+            .or_insert((0, Instant::now()))
+            .1 = Instant::now();
+    }
+
+    /// Decide what to do about a unit's program having exited.
+    pub fn exited(&mut self, id: &UnitName, restart: &Restart, failed: bool) -> Decision {
+        if !restart.policy.restarts(failed) {
+            self.history.remove(id);
+            return Decision::Leave;
+        }
+        let (in_a_row, started) = self
+            .history
+            .entry(id.clone())
+            .or_insert((0, Instant::now()));
+        if started.elapsed() >= MAX_RESTART_DELAY {
+            *in_a_row = 0;
+        }
+        if restart.burst > 0 && *in_a_row >= restart.burst {
+            let in_a_row = *in_a_row;
+            // Starting it by hand starts the count again.
+            self.history.remove(id);
+            return Decision::GiveUp(in_a_row);
+        }
+        let delay = restart
+            .delay
+            .checked_mul(2u32.saturating_pow(*in_a_row))
+            .unwrap_or(MAX_RESTART_DELAY)
+            .min(MAX_RESTART_DELAY.max(restart.delay));
+        *in_a_row += 1;
+        Decision::Restart(delay, *in_a_row)
+    }
+
+    /// Stop keeping track of a unit, such as when it's unloaded.
+    pub fn forget(&mut self, id: &UnitName) {
+        self.history.remove(id);
+    }
+}
@@ -0,0 +1,505 @@
+//! Everything that calls into the operating system through nix lives here,
+//! so the rest of exclave doesn't depend on it directly, and porting to
+//! another platform only means filling in this module.  Each call has a
+//! fallback for platforms that don't support it yet.
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+
+#[cfg(unix)]
+use nix::sys::termios::{self, SetArg, Termios};
+
+#[cfg(unix)]
+use unit::UnitName;
+use unitbroadcaster::UnitBroadcaster;
+#[cfg(unix)]
+use unitbroadcaster::UnitEvent;
+#[cfg(unix)]
+use unitmanager::{ManagerControlMessage, ManagerControlMessageContents};
+
+/// The size of a terminal, in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerminalSize {
+    pub rows: u16,
+    pub columns: u16,
+}
+
+#[cfg(unix)]
+fn nix_error(e: nix::Error) -> io::Error {
+    match e {
+        nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+        other => io::Error::other(other.to_string()),
+    }
+}
+
+/// Whether `file` is a terminal.
+#[cfg(unix)]
+pub fn is_terminal<F: AsRawFd>(file: &F) -> bool {
+    nix::unistd::isatty(file.as_raw_fd()).unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+pub fn is_terminal<F>(_file: &F) -> bool {
+    false
+}
+
+/// The size of the terminal `file` refers to, or None if it isn't one.
+#[cfg(unix)]
+pub fn terminal_size<F: AsRawFd>(file: &F) -> Option<TerminalSize> {
+    use nix::libc;
+    use std::mem;
+
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } != 0 {
+        return None;
+    }
+    if size.ws_row == 0 || size.ws_col == 0 {
+        return None;
+    }
+    Some(TerminalSize {
+        rows: size.ws_row,
+        columns: size.ws_col,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn terminal_size<F>(_file: &F) -> Option<TerminalSize> {
+    None
+}
+
+/// A terminal in raw mode.  The original settings are put back when this
+/// is dropped.
+#[cfg(unix)]
+pub struct RawMode {
+    fd: RawFd,
+    original: Termios,
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        termios::tcsetattr(self.fd, SetArg::TCSANOW, &self.original).ok();
+    }
+}
+
+#[cfg(not(unix))]
+pub struct RawMode;
+
+/// Put the terminal `file` refers to into raw mode, so that input arrives a
+/// byte at a time, without being echoed or turned into signals.
+#[cfg(unix)]
+pub fn set_raw_mode<F: AsRawFd>(file: &F) -> io::Result<RawMode> {
+    let fd = file.as_raw_fd();
+    let original = termios::tcgetattr(fd).map_err(nix_error)?;
+    let mut raw = original.clone();
+    termios::cfmakeraw(&mut raw);
+    termios::tcsetattr(fd, SetArg::TCSANOW, &raw).map_err(nix_error)?;
+    Ok(RawMode { fd, original })
+}
+
+#[cfg(not(unix))]
+pub fn set_raw_mode<F>(_file: &F) -> io::Result<RawMode> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "raw mode is not supported on this platform",
+    ))
+}
+
+/// Make reads from and writes to `file` return WouldBlock rather than wait.
+#[cfg(unix)]
+pub fn set_nonblocking<F: AsRawFd>(file: &F, nonblocking: bool) -> io::Result<()> {
+    use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+    let fd = file.as_raw_fd();
+    let mut flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL).map_err(nix_error)?);
+    flags.set(OFlag::O_NONBLOCK, nonblocking);
+    fcntl(fd, FcntlArg::F_SETFL(flags)).map_err(nix_error)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_nonblocking<F>(_file: &F, _nonblocking: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "non-blocking files are not supported on this platform",
+    ))
+}
+
+/// Take an exclusive lock on `file` if nobody else holds one, returning
+/// false if they do.  The lock is released when the file is closed, or the
+/// process exits.
+#[cfg(unix)]
+pub fn try_lock<F: AsRawFd>(file: &F) -> io::Result<bool> {
+    use nix::fcntl::{flock, FlockArg};
+
+    match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+        Ok(()) => Ok(true),
+        Err(e) => match nix_error(e) {
+            ref e if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            e => Err(e),
+        },
+    }
+}
+
+#[cfg(not(unix))]
+pub fn try_lock<F>(_file: &F) -> io::Result<bool> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "file locks are not supported on this platform",
+    ))
+}
+
+/// A signal that may be sent to stop a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KillSignal {
+    Hangup,
+    Interrupt,
+    Quit,
+    Kill,
+    User1,
+    User2,
+    Terminate,
+}
+
+impl KillSignal {
+    const ALL: [(KillSignal, &'static str); 7] = [
+        (KillSignal::Hangup, "SIGHUP"),
+        (KillSignal::Interrupt, "SIGINT"),
+        (KillSignal::Quit, "SIGQUIT"),
+        (KillSignal::Kill, "SIGKILL"),
+        (KillSignal::User1, "SIGUSR1"),
+        (KillSignal::User2, "SIGUSR2"),
+        (KillSignal::Terminate, "SIGTERM"),
+    ];
+
+    /// The names that may be parsed, such as "SIGINT".
+    pub fn names() -> Vec<String> {
+        Self::ALL.iter().map(|&(_, name)| name.to_owned()).collect()
+    }
+
+    #[cfg(unix)]
+    fn to_nix(self) -> nix::sys::signal::Signal {
+        use nix::sys::signal::Signal;
+        match self {
+            KillSignal::Hangup => Signal::SIGHUP,
+            KillSignal::Interrupt => Signal::SIGINT,
+            KillSignal::Quit => Signal::SIGQUIT,
+            KillSignal::Kill => Signal::SIGKILL,
+            KillSignal::User1 => Signal::SIGUSR1,
+            KillSignal::User2 => Signal::SIGUSR2,
+            KillSignal::Terminate => Signal::SIGTERM,
+        }
+    }
+}
+
+impl fmt::Display for KillSignal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = Self::ALL.iter().find(|&&(s, _)| s == *self).unwrap().1;
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for KillSignal {
+    type Err = ();
+
+    /// Parse a signal name, with or without the "SIG" prefix, in any case.
+    fn from_str(s: &str) -> Result<KillSignal, ()> {
+        let upper = s.trim().to_uppercase();
+        let name = if upper.starts_with("SIG") {
+            upper
+        } else {
+            format!("SIG{}", upper)
+        };
+        Self::ALL
+            .iter()
+            .find(|&&(_, n)| n == name)
+            .map(|&(signal, _)| signal)
+            .ok_or(())
+    }
+}
+
+/// The name of signal number `signal`, such as "SIGSEGV".
+#[cfg(unix)]
+pub fn signal_name(signal: i32) -> String {
+    match nix::sys::signal::Signal::from_c_int(signal) {
+        Ok(s) => format!("{:?}", s),
+        Err(_) => format!("signal {}", signal),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn signal_name(signal: i32) -> String {
+    format!("signal {}", signal)
+}
+
+/// Send `signal` to every process in the process group led by `pid`.
+#[cfg(unix)]
+pub fn signal_process_group(pid: i32, signal: KillSignal) -> io::Result<()> {
+    use nix::sys::signal::kill;
+    use nix::unistd::Pid;
+
+    kill(Pid::from_raw(-pid), signal.to_nix()).map_err(nix_error)
+}
+
+#[cfg(not(unix))]
+pub fn signal_process_group(_pid: i32, signal: KillSignal) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is not supported on this platform", signal),
+    ))
+}
+
+/// The name of this machine, if it has one.
+#[cfg(unix)]
+pub fn hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    nix::unistd::gethostname(&mut buffer)
+        .ok()
+        .and_then(|name| name.to_str().ok())
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_owned())
+}
+
+#[cfg(not(unix))]
+pub fn hostname() -> Option<String> {
+    None
+}
+
+/// How long it's been since the system booted, on a clock that only moves
+/// forward, and isn't stepped when the time of day is set.
+#[cfg(unix)]
+pub fn monotonic_time() -> Option<Duration> {
+    use nix::libc;
+    use std::mem;
+
+    let mut now: libc::timespec = unsafe { mem::zeroed() };
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } != 0 {
+        return None;
+    }
+    Some(Duration::new(now.tv_sec as u64, now.tv_nsec as u32))
+}
+
+#[cfg(not(unix))]
+pub fn monotonic_time() -> Option<Duration> {
+    None
+}
+
+/// How many bytes are free for exclave to use on the filesystem that `path`
+/// is on.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+pub fn free_space(path: &Path) -> Option<u64> {
+    use nix::libc;
+    use std::ffi::CString;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// An id that's different each time the system boots, if it has one.
+#[cfg(target_os = "linux")]
+pub fn boot_id() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .ok()
+        .map(|id| id.trim().to_owned())
+        .filter(|id| !id.is_empty())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn boot_id() -> Option<String> {
+    None
+}
+
+/// Put signals back to how a freshly-started program would find them: none
+/// blocked, and the ones exclave cares about not ignored, so that a parent
+/// that changed them can't stop exclave from hearing about its children or
+/// being asked to stop.  Only the calling thread's mask is changed, so this
+/// must be called before any other threads are spawned.
+#[cfg(unix)]
+pub fn reset_signals() -> io::Result<()> {
+    use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+    SigSet::empty().thread_set_mask().map_err(nix_error)?;
+    let default = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+    for signal in &[
+        Signal::SIGHUP,
+        Signal::SIGINT,
+        Signal::SIGQUIT,
+        Signal::SIGTERM,
+        Signal::SIGUSR1,
+        Signal::SIGUSR2,
+        Signal::SIGCHLD,
+    ] {
+        unsafe { sigaction(*signal, &default) }.map_err(nix_error)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn reset_signals() -> io::Result<()> {
+    Ok(())
+}
+
+/// Ask to be given the children of any of our descendants that exit, rather
+/// than them going to the first process.
+#[cfg(target_os = "linux")]
+pub fn set_child_subreaper() -> io::Result<()> {
+    use nix::libc;
+
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_child_subreaper() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "adopting orphans is not supported on this platform",
+    ))
+}
+
+/// Our children that have exited and not been waited for, that are in
+/// another session than we are, and that exclave didn't start itself.
+#[cfg(target_os = "linux")]
+pub fn orphaned_zombies() -> Vec<i32> {
+    use init::{is_spawned, parse_stat};
+    use nix::unistd::{getpid, getsid};
+
+    let ours = i32::from(getpid());
+    let our_session = match getsid(None) {
+        Ok(session) => i32::from(session),
+        Err(_) => return vec![],
+    };
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<i32>().ok())
+        .filter(|pid| {
+            match std::fs::read_to_string(format!("/proc/{}/stat", pid))
+                .ok()
+                .and_then(|stat| parse_stat(&stat))
+            {
+                Some((state, parent, session, start_time)) => {
+                    state == 'Z'
+                        && parent == ours
+                        && session != our_session
+                        && !is_spawned(*pid, start_time)
+                }
+                None => false,
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn orphaned_zombies() -> Vec<i32> {
+    vec![]
+}
+
+/// When a process started, in clock ticks since boot, or None if there is
+/// no such process.
+#[cfg(target_os = "linux")]
+pub fn start_time(pid: i32) -> Option<u64> {
+    use init::parse_stat;
+
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_stat(&stat).map(|(_, _, _, start_time)| start_time)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn start_time(_pid: i32) -> Option<u64> {
+    None
+}
+
+/// Wait for a child that has exited, returning false if it wasn't one.
+#[cfg(unix)]
+pub fn reap(pid: i32) -> bool {
+    use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+    use nix::unistd::Pid;
+
+    match waitpid(Pid::from_raw(pid), Some(WaitPidFlag::WNOHANG)) {
+        Ok(WaitStatus::StillAlive) | Err(_) => false,
+        Ok(_) => true,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn reap(_pid: i32) -> bool {
+    false
+}
+
+/// Block SIGHUP on this thread (and every thread spawned from it), and
+/// spawn a thread that turns each SIGHUP into a request to reload the config.
+/// This must be called before any other threads are spawned, so that SIGHUP
+/// only gets delivered to the thread waiting for it.
+#[cfg(unix)]
+pub fn watch_for_sighup(broadcaster: &UnitBroadcaster) {
+    use nix::sys::signal::{SigSet, Signal};
+    use std::thread;
+
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGHUP);
+    mask.thread_block().expect("Unable to block SIGHUP");
+
+    let broadcaster = broadcaster.clone();
+    thread::spawn(move || {
+        while let Ok(Signal::SIGHUP) = mask.wait() {
+            broadcaster.broadcast(&UnitEvent::ManagerRequest(ManagerControlMessage::new(
+                &UnitName::internal("signal"),
+                ManagerControlMessageContents::ReloadConfig,
+            )));
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn watch_for_sighup(_broadcaster: &UnitBroadcaster) {}
+
+/// Create a directory, and any missing parents, that only the current user
+/// may read or write.  An existing directory is left as it is.
+#[cfg(unix)]
+pub fn create_private_dir(path: &Path) -> io::Result<()> {
+    use std::fs::DirBuilder;
+    use std::os::unix::fs::DirBuilderExt;
+
+    DirBuilder::new().recursive(true).mode(0o700).create(path)
+}
+
+#[cfg(not(unix))]
+pub fn create_private_dir(path: &Path) -> io::Result<()> {
+    std::fs::create_dir_all(path)
+}
+
+/// Whether a file can only be read by its owner, so it's fit to hold
+/// secrets.  A file that anyone else can read or write is refused.
+#[cfg(unix)]
+pub fn is_private_file(path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Ok(std::fs::metadata(path)?.permissions().mode() & 0o077 == 0)
+}
+
+#[cfg(not(unix))]
+pub fn is_private_file(path: &Path) -> io::Result<bool> {
+    std::fs::metadata(path).map(|_| true)
+}
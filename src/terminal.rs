@@ -1,12 +1,13 @@
 extern crate console;
 
 use self::console::Term;
+use exclave::sys;
+use exclave::unit::{UnitKind, UnitName};
+use exclave::unitbroadcaster::UnitBroadcaster;
+use exclave::unitbroadcaster::{LogEntry, UnitCategoryStatus, UnitEvent, UnitStatus};
+use exclave::unitmanager::{ManagerControlMessage, ManagerControlMessageContents};
 use std::collections::{BTreeMap, HashMap};
 use std::thread;
-use unit::{UnitKind, UnitName};
-use unitbroadcaster::UnitBroadcaster;
-use unitbroadcaster::{LogEntry, UnitCategoryStatus, UnitEvent, UnitStatus};
-use unitmanager::{ManagerControlMessage, ManagerControlMessageContents};
 
 #[derive(PartialEq)]
 pub enum TerminalOutputType {
@@ -52,7 +53,7 @@ impl TerminalInterface {
         let stdout = Term::stdout();
         let output_type = match output_type {
             Some(s) => s,
-            None if stdout.is_term() => TerminalOutputType::Fancy,
+            None if sys::is_terminal(&std::io::stdout()) => TerminalOutputType::Fancy,
             None => TerminalOutputType::Plain,
         };
         let receiver = broadcaster.subscribe();
@@ -68,8 +69,8 @@ impl TerminalInterface {
                 log_history: MAX_LOG_HISTORY,
             };
 
-            while let Ok(event) = receiver.recv() {
-                ti.update_unit(event);
+            while let Ok(stamped) = receiver.recv() {
+                ti.update_unit(&stamped.event);
             }
             eprintln!("Receiver has closed -- shutting down");
         });
@@ -89,16 +90,16 @@ impl TerminalInterface {
                 thread_broadcaster.broadcast(&UnitEvent::ManagerRequest(
                     ManagerControlMessage::new(
                         &id,
-                        ManagerControlMessageContents::StartScenario(None),
+                        ManagerControlMessageContents::StartScenario(None, vec![], None),
                     ),
                 ));
             });
         }
     }
 
-    fn update_unit(&mut self, event: UnitEvent) {
+    fn update_unit(&mut self, event: &UnitEvent) {
         // Insert the new event into the relevent data structures
-        match event {
+        match *event {
             UnitEvent::Category(ref cat) => {
                 self.category_status
                     .insert(cat.kind().clone(), cat.status().clone());
@@ -131,6 +132,10 @@ impl TerminalInterface {
             UnitEvent::RescanRequest => (),
             UnitEvent::Shutdown => (),
             UnitEvent::ManagerRequest(_) => (),
+            UnitEvent::ConfigChanged(_) => (),
+            UnitEvent::ScenarioFinished(_) => (),
+            UnitEvent::RunNoted(_) => (),
+            UnitEvent::RunFinished(_) => (),
         }
 
         match self.output_type {
@@ -140,7 +145,7 @@ impl TerminalInterface {
         };
     }
 
-    fn draw_event(&self, event: UnitEvent) {
+    fn draw_event(&self, event: &UnitEvent) {
         match event {
             UnitEvent::Status(stat) => println!("    {} -> {}", stat.name(), stat.status()),
             UnitEvent::Category(stat) => println!("{}: {}", stat.kind(), stat.status()),
@@ -150,11 +155,18 @@ impl TerminalInterface {
             UnitEvent::Shutdown => println!("Shutting down"),
             UnitEvent::Log(log) => println!("{}", log),
             UnitEvent::ManagerRequest(_) => (),
+            UnitEvent::ConfigChanged(change) => println!("Configuration reloaded: {}", change),
+            UnitEvent::ScenarioFinished(summary) => {
+                println!("Scenario {} finished: {}", summary.scenario, summary)
+            }
+            // The note itself was already logged.
+            UnitEvent::RunNoted(_) => (),
+            UnitEvent::RunFinished(run) => println!("Run saved to {}", run.display()),
         };
     }
 
-    fn redraw_screen(&mut self, evt: UnitEvent) {
-        if evt != UnitEvent::RescanFinish {
+    fn redraw_screen(&mut self, evt: &UnitEvent) {
+        if *evt != UnitEvent::RescanFinish {
             return;
         }
 
@@ -204,7 +216,12 @@ impl TerminalInterface {
                 .write_line("Logs: ".to_string().as_str())
                 .expect("Unable to write log");
         }
-        for log_line in self.logs.iter() {
+        // Only show as many of the most recent logs as fit on the screen.
+        let rows = sys::terminal_size(&std::io::stdout())
+            .map(|size| size.rows as usize)
+            .unwrap_or(usize::MAX);
+        let room = rows.saturating_sub(line_count + 1).min(self.logs.len());
+        for log_line in self.logs.iter().skip(self.logs.len() - room) {
             line_count += 1;
             self.terminal
                 .write_line(format!("  {}", log_line).as_str())
@@ -0,0 +1,290 @@
+// Runs scenarios inside the calling process, for testing changes to how
+// scenarios are run.  Units are given as strings rather than files, time is
+// read from a fake clock that only moves when the test says so, the output
+// of each test is captured rather than printed, and every event is kept so
+// that the order things happened in can be checked:
+//
+//     let harness = Harness::new();
+//     harness.add_unit("flash.test", "[Test]\nName=Flash\nExecStart=true\n");
+//     harness.add_unit("board.scenario", "[Scenario]\nName=Board\nTests=flash\n");
+//     harness.run_scenario("board");
+//     harness.assert_trace(&["start board", "run flash", "exit flash 0", "finish board 200"]);
+//
+// Tests still run as real programs, so their own timeouts use the real time.
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use clock::Clock;
+use config::Config;
+use unit::{UnitKind, UnitName};
+use unitbroadcaster::{
+    ScenarioSummary, StampedEvent, UnitBroadcaster, UnitEvent, UnitStatus, UnitStatusEvent,
+};
+use unitlibrary::UnitLibrary;
+use unitmanager::{ManagerControlMessage, ManagerControlMessageContents};
+use units::allocator::AllocatorDescription;
+use units::jig::JigDescription;
+use units::notifier::NotifierDescription;
+use units::scenario::ScenarioDescription;
+use units::test::TestDescription;
+use units::trigger::TriggerDescription;
+
+/// When a harness's fake clock starts, in seconds since the epoch.
+pub const FAKE_START_SECS: u64 = 1_600_000_000;
+
+/// How long to wait for the next event before deciding that nothing more is
+/// going to happen.
+const STEP_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Harness {
+    broadcaster: UnitBroadcaster,
+    receiver: Receiver<Arc<StampedEvent>>,
+    control: Sender<ManagerControlMessage>,
+    library: UnitLibrary,
+    config: Arc<Mutex<Config>>,
+    clock: Clock,
+
+    /// Every event so far, in the order it was handled.
+    events: RefCell<Vec<UnitEvent>>,
+
+    /// True if units were added since the last rescan.
+    added: Cell<bool>,
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Harness::new()
+    }
+}
+
+impl Harness {
+    pub fn new() -> Harness {
+        Self::with_config(Config::new())
+    }
+
+    /// A harness with the given settings, apart from the clock, which is
+    /// always a fake one.
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn with_config(mut config: Config) -> Harness {
+        let clock = Clock::fake(UNIX_EPOCH + Duration::from_secs(FAKE_START_SECS));
+        config.set_clock(clock.clone());
+        let config = Arc::new(Mutex::new(config));
+        let broadcaster = UnitBroadcaster::new();
+        let receiver = broadcaster.subscribe();
+        let library = UnitLibrary::new(&broadcaster, &config);
+        let control = library.get_manager().borrow().get_control_channel();
+        Harness {
+            broadcaster,
+            receiver,
+            control,
+            library,
+            config,
+            clock,
+            events: RefCell::new(vec![]),
+            added: Cell::new(false),
+        }
+    }
+
+    /// Load a unit from a string, as though it had been read from a file
+    /// with the given name, such as "flash.test".  Tests, jigs, scenarios,
+    /// notifiers, allocators, and triggers may be added.  Panics if the unit
+    /// can't be loaded.
+    pub fn add_unit(&self, file_name: &str, text: &str) -> UnitName {
+        let name = UnitName::from_str(file_name, "")
+            .unwrap_or_else(|e| panic!("invalid unit name {}: {}", file_name, e));
+        // Units act as though their file were in the current directory, so
+        // that's where tests are run.
+        let path = env::current_dir().unwrap_or_default().join(file_name);
+        let manager = self.library.get_manager().borrow();
+        let result = match *name.kind() {
+            UnitKind::Test => TestDescription::from_string(text, name.clone(), &path)
+                .map(|desc| manager.load_test(&desc)),
+            UnitKind::Jig => JigDescription::from_string(text, name.clone(), &path)
+                .map(|desc| manager.load_jig(&desc)),
+            UnitKind::Scenario => ScenarioDescription::from_string(text, name.clone(), &path)
+                .map(|desc| manager.load_scenario(&desc)),
+            UnitKind::Notifier => NotifierDescription::from_string(text, name.clone(), &path)
+                .map(|desc| manager.load_notifier(&desc)),
+            UnitKind::Allocator => AllocatorDescription::from_string(text, name.clone(), &path)
+                .map(|desc| manager.load_allocator(&desc)),
+            UnitKind::Trigger => TriggerDescription::from_string(text, name.clone(), &path)
+                .map(|desc| manager.load_trigger(&desc)),
+            ref kind => panic!("{} units can't be added to a harness", kind),
+        };
+        match result {
+            Ok(Ok(_)) => (),
+            Ok(Err(e)) => panic!("unable to load {}: {}", name, e),
+            Err(e) => panic!("unable to parse {}: {}", name, e),
+        }
+        self.added.set(true);
+        name
+    }
+
+    pub fn library(&self) -> &UnitLibrary {
+        &self.library
+    }
+
+    /// The settings the manager runs with.
+    pub fn config(&self) -> &Arc<Mutex<Config>> {
+        &self.config
+    }
+
+    /// The fake clock that scenarios and tests are timed with.
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Move the fake clock forward, as though that much time had passed.
+    pub fn advance(&self, by: Duration) {
+        self.clock.advance(by);
+    }
+
+    /// Send a request to the manager, as an internal "harness" unit.
+    pub fn send(&self, contents: ManagerControlMessageContents) {
+        self.control
+            .send(ManagerControlMessage::new(
+                &UnitName::internal("harness"),
+                contents,
+            ))
+            .expect("manager has gone away");
+    }
+
+    /// Send a request to the manager as though it came from a unit, such as
+    /// "button.trigger", whether or not that unit is loaded.
+    pub fn send_as(&self, unit: &str, contents: ManagerControlMessageContents) {
+        if self.added.replace(false) {
+            self.broadcaster.broadcast(&UnitEvent::RescanRequest);
+        }
+        let name = UnitName::from_str(unit, "")
+            .unwrap_or_else(|e| panic!("invalid unit name {}: {}", unit, e));
+        self.control
+            .send(ManagerControlMessage::new(&name, contents))
+            .expect("manager has gone away");
+    }
+
+    /// Ask the manager to start a scenario, without waiting for it.
+    pub fn start_scenario(&self, scenario: &str) {
+        self.start_scenario_with(scenario, &[]);
+    }
+
+    /// Ask the manager to start a scenario with values for its parameters,
+    /// as (name, value), without waiting for it.
+    pub fn start_scenario_with(&self, scenario: &str, parameters: &[(&str, &str)]) {
+        if self.added.replace(false) {
+            self.broadcaster.broadcast(&UnitEvent::RescanRequest);
+        }
+        let name = UnitName::from_str(scenario, "scenario")
+            .unwrap_or_else(|e| panic!("invalid scenario name {}: {}", scenario, e));
+        let parameters = parameters
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        self.send(ManagerControlMessageContents::StartScenario(
+            Some(name),
+            parameters,
+            None,
+        ));
+    }
+
+    /// Handle the next event, or return None if nothing happens for a while.
+    pub fn step(&self) -> Option<UnitEvent> {
+        let event = self.receiver.recv_timeout(STEP_TIMEOUT).ok()?.event.clone();
+        self.library.process_message(&event);
+        self.events.borrow_mut().push(event.clone());
+        Some(event)
+    }
+
+    /// Handle events until one matches, and return it.  Panics if nothing
+    /// happens for a while first.
+    pub fn run_until<F: FnMut(&UnitEvent) -> bool>(&self, mut matches: F) -> UnitEvent {
+        loop {
+            match self.step() {
+                Some(event) => {
+                    if matches(&event) {
+                        return event;
+                    }
+                }
+                None => panic!("gave up waiting for an event, after:\n{:#?}", self.trace()),
+            }
+        }
+    }
+
+    /// Start a scenario, and handle events until it finishes.
+    pub fn run_scenario(&self, scenario: &str) -> ScenarioSummary {
+        self.start_scenario(scenario);
+        match self.run_until(|event| matches!(event, UnitEvent::ScenarioFinished(_))) {
+            UnitEvent::ScenarioFinished(summary) => summary,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Every event handled so far.
+    pub fn events(&self) -> Vec<UnitEvent> {
+        self.events.borrow().clone()
+    }
+
+    /// The lines a test has printed so far, over all of its runs.
+    pub fn output(&self, test: &str) -> Vec<String> {
+        let test = UnitName::from_str(test, "test")
+            .unwrap_or_else(|e| panic!("invalid test name {}: {}", test, e));
+        self.events
+            .borrow()
+            .iter()
+            .filter_map(|event| match event {
+                UnitEvent::ManagerRequest(ManagerControlMessage {
+                    sender,
+                    contents: ManagerControlMessageContents::Log(line),
+                }) if *sender == test => Some(line.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// What has happened to scenarios and tests so far, one line each:
+    /// "start <scenario>", "run <test>", "exit <test> <code>",
+    /// "skip <test>: <reason>", and "finish <scenario> <code>".  Timing and
+    /// output are left out, so the same units give the same trace each time.
+    pub fn trace(&self) -> Vec<String> {
+        self.events
+            .borrow()
+            .iter()
+            .filter_map(|event| match event {
+                UnitEvent::Status(UnitStatusEvent {
+                    name,
+                    status: UnitStatus::Active,
+                }) if *name.kind() == UnitKind::Scenario => Some(format!("start {}", name.id())),
+                UnitEvent::ManagerRequest(ManagerControlMessage { sender, contents }) => {
+                    match contents {
+                        ManagerControlMessageContents::TestStarted => {
+                            Some(format!("run {}", sender.id()))
+                        }
+                        ManagerControlMessageContents::TestFinished(code, _) => {
+                            Some(format!("exit {} {}", sender.id(), code))
+                        }
+                        ManagerControlMessageContents::Skip(test, reason) => {
+                            Some(format!("skip {}: {}", test.id(), reason))
+                        }
+                        ManagerControlMessageContents::ScenarioFinished(code, _) => {
+                            Some(format!("finish {} {}", sender.id(), code))
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Panic unless the trace so far is exactly as expected.
+    pub fn assert_trace(&self, expected: &[&str]) {
+        let trace = self.trace();
+        assert_eq!(
+            trace.iter().map(|line| line.as_str()).collect::<Vec<_>>(),
+            expected,
+            "unexpected sequence of events"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+// Timestamps that a station's events and results can be put in order by,
+// even when its clock is stepped, such as by NTP.  Along with the time of
+// day, each is stamped with how long it had been since the system booted,
+// on a clock that only moves forward, and the boot it was in.  Within a
+// boot, that orders them exactly; across boots, the time of day has to do.
+//
+// Systems without a boot id, or without a monotonic clock, get an id for
+// each time exclave starts instead, with time counted from then.
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use station;
+use sys;
+
+/// Where a moment falls on a boot's monotonic clock.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
+pub struct Monotonic {
+    /// Which boot the time is from.
+    pub boot_id: String,
+
+    /// Nanoseconds since the boot.
+    pub nanos: u64,
+}
+
+impl Monotonic {
+    /// The time in milliseconds, as result records give it.
+    pub fn millis(&self) -> u64 {
+        self.nanos / 1_000_000
+    }
+}
+
+struct Origin {
+    boot_id: String,
+
+    /// When exclave started, if time is counted from then rather than from
+    /// when the system booted.
+    started: Option<Instant>,
+}
+
+static ORIGIN: OnceLock<Origin> = OnceLock::new();
+
+fn origin() -> &'static Origin {
+    ORIGIN.get_or_init(|| match (sys::boot_id(), sys::monotonic_time()) {
+        (Some(boot_id), Some(_)) => Origin {
+            boot_id,
+            started: None,
+        },
+        _ => Origin {
+            boot_id: format!("exclave-{}", station::new_id()),
+            started: Some(Instant::now()),
+        },
+    })
+}
+
+/// Which boot this is.
+pub fn boot_id() -> &'static str {
+    &origin().boot_id
+}
+
+/// How long it's been since the boot.
+pub fn since_boot() -> Duration {
+    match origin().started {
+        Some(started) => started.elapsed(),
+        None => sys::monotonic_time().unwrap_or_default(),
+    }
+}
+
+/// Nanoseconds since the boot.
+pub fn nanos() -> u64 {
+    since_boot().as_nanos() as u64
+}
+
+/// Now, on this boot's monotonic clock.
+pub fn now() -> Monotonic {
+    Monotonic {
+        boot_id: boot_id().to_owned(),
+        nanos: nanos(),
+    }
+}
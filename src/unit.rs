@@ -1,24 +1,32 @@
 extern crate dependy;
-extern crate humantime;
 extern crate regex;
 extern crate runny;
-extern crate systemd_parser;
 
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io;
-use std::path::Path;
+use std::path::{Component, Path};
+use std::sync::{Arc, Mutex, Weak};
+
+use serde::{Deserialize, Deserializer};
 
 use self::dependy::DepError;
-use self::humantime::DurationError;
 use self::runny::running::RunningError;
 use self::runny::RunnyError;
-use self::systemd_parser::errors::ParserError;
+use duration::DurationError;
+use poison::Lock;
+use secrets::SecretError;
+use unitfile::{SourceLocation, SyntaxError};
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone, PartialOrd, Ord, Serialize)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum UnitKind {
+    Allocator,
     Interface,
     Jig,
     Logger,
+    Notifier,
     Scenario,
     Test,
     Trigger,
@@ -27,13 +35,32 @@ pub enum UnitKind {
     Internal,
 }
 
+impl UnitKind {
+    /// The kind of unit that files with this suffix, such as "test", hold.
+    pub fn from_suffix(suffix: &str) -> Option<UnitKind> {
+        match suffix {
+            "allocator" => Some(UnitKind::Allocator),
+            "interface" => Some(UnitKind::Interface),
+            "jig" => Some(UnitKind::Jig),
+            "logger" => Some(UnitKind::Logger),
+            "notifier" => Some(UnitKind::Notifier),
+            "scenario" => Some(UnitKind::Scenario),
+            "test" => Some(UnitKind::Test),
+            "trigger" => Some(UnitKind::Trigger),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for UnitKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            UnitKind::Allocator => write!(f, "allocator"),
             UnitKind::Interface => write!(f, "interface"),
             UnitKind::Internal => write!(f, "internal"),
             UnitKind::Jig => write!(f, "jig"),
             UnitKind::Logger => write!(f, "logger"),
+            UnitKind::Notifier => write!(f, "notifier"),
             UnitKind::Scenario => write!(f, "scenario"),
             UnitKind::Test => write!(f, "test"),
             UnitKind::Trigger => write!(f, "trigger"),
@@ -41,16 +68,160 @@ impl fmt::Display for UnitKind {
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone, PartialOrd, Ord, Serialize)]
+/// Every unit id in use, each kept once and given a number, so that names
+/// are compared and hashed by number rather than by their text, which adds
+/// up during a rescan of a large unit tree.  An id is dropped from here once
+/// the last name using it is, so names that clients make up and throw away,
+/// such as a SCENARIO that doesn't exist, don't pile up.
+static IDS: Mutex<Option<Ids>> = Mutex::new(None);
+
+struct Ids {
+    by_text: HashMap<String, Weak<IdText>>,
+    next_number: u64,
+}
+
+/// The text of an interned id, and its number, which no other id in use
+/// has.
+struct IdText {
+    number: u64,
+    text: String,
+}
+
+impl Drop for IdText {
+    fn drop(&mut self) {
+        if let Some(ref mut ids) = *IDS.locked() {
+            // The text may already have been interned again, as a new id.
+            if let Some(id) = ids.by_text.get(&self.text) {
+                // Upgrading it could leave the last reference to be dropped
+                // while the lock is still held, so count them instead.
+                if id.strong_count() == 0 {
+                    ids.by_text.remove(&self.text);
+                }
+            }
+        }
+    }
+}
+
+/// An interned unit id.
+#[derive(Clone)]
+struct Id(Arc<IdText>);
+
+impl Id {
+    fn new(text: &str) -> Id {
+        let mut ids = IDS.locked();
+        let ids = ids.get_or_insert_with(|| Ids {
+            by_text: HashMap::new(),
+            next_number: 0,
+        });
+        if let Some(id) = ids.by_text.get(text).and_then(Weak::upgrade) {
+            return Id(id);
+        }
+        let id = Arc::new(IdText {
+            number: ids.next_number,
+            text: text.to_owned(),
+        });
+        ids.next_number += 1;
+        ids.by_text.insert(id.text.clone(), Arc::downgrade(&id));
+        Id(id)
+    }
+
+    fn number(&self) -> u64 {
+        self.0.number
+    }
+
+    fn text(&self) -> &String {
+        &self.0.text
+    }
+}
+
+/// Whether any name with this id is in use.
+pub fn is_interned(text: &str) -> bool {
+    match *IDS.locked() {
+        Some(ref ids) => ids
+            .by_text
+            .get(text)
+            .is_some_and(|id| id.strong_count() > 0),
+        None => false,
+    }
+}
+
+impl PartialEq for Id {
+    fn eq(&self, other: &Id) -> bool {
+        self.number() == other.number()
+    }
+}
+
+impl Eq for Id {}
+
+impl Hash for Id {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.number().hash(state)
+    }
+}
+
+// Names still sort by their text, as they always have.
+impl Ord for Id {
+    fn cmp(&self, other: &Id) -> Ordering {
+        if self.number() == other.number() {
+            Ordering::Equal
+        } else {
+            self.text().cmp(other.text())
+        }
+    }
+}
+
+impl PartialOrd for Id {
+    fn partial_cmp(&self, other: &Id) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Debug for Id {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.text(), f)
+    }
+}
+
+/// The name of a unit, such as "flash.test".  Units kept in a subdirectory
+/// of a unit directory are namespaced by that subdirectory, so the id of
+/// "boardA/flash.test" is "boardA/flash".
+#[derive(PartialEq, Eq, Hash, Debug, Clone, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(from = "SerializedUnitName", into = "SerializedUnitName")]
 pub struct UnitName {
+    id: Id,
+    kind: UnitKind,
+}
+
+/// A UnitName as it's written out, with its id in full.
+#[derive(Clone, Serialize, Deserialize)]
+struct SerializedUnitName {
     id: String,
     kind: UnitKind,
 }
 
+impl From<SerializedUnitName> for UnitName {
+    fn from(name: SerializedUnitName) -> UnitName {
+        UnitName {
+            id: Id::new(&name.id),
+            kind: name.kind,
+        }
+    }
+}
+
+impl From<UnitName> for SerializedUnitName {
+    fn from(name: UnitName) -> SerializedUnitName {
+        SerializedUnitName {
+            id: name.id.text().clone(),
+            kind: name.kind,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum UnitNameError {
     NoFileExtension,
     UnrecognizedUnitType(String),
+    InvalidName(String),
 }
 
 impl fmt::Display for UnitNameError {
@@ -60,6 +231,7 @@ impl fmt::Display for UnitNameError {
             UnitNameError::UnrecognizedUnitType(ref t) => {
                 write!(f, "unrecognized unit type \".{}\"", t)
             }
+            UnitNameError::InvalidName(ref n) => write!(f, "invalid unit name \"{}\"", n),
         }
     }
 }
@@ -70,7 +242,62 @@ impl UnitName {
     }
 
     pub fn id(&self) -> &String {
-        &self.id
+        self.id.text()
+    }
+
+    /// The subdirectory this unit came from, if it isn't at the top of a unit directory.
+    pub fn namespace(&self) -> Option<&str> {
+        let id = self.id.text();
+        id.rfind('/').map(|pos| &id[..pos])
+    }
+
+    /// Returns true if this name is a pattern such as "rf-*.test", rather
+    /// than the name of a single unit.
+    pub fn is_pattern(&self) -> bool {
+        self.id.text().contains(['*', '?'])
+    }
+
+    /// Returns true if `name` matches this pattern.  "*" matches any number of
+    /// characters and "?" matches one, but neither matches a "/".
+    pub fn matches(&self, name: &UnitName) -> bool {
+        fn glob(pattern: &[char], text: &[char]) -> bool {
+            match pattern.split_first() {
+                None => text.is_empty(),
+                Some((&'*', rest)) => (0..=text.len())
+                    .take_while(|&i| i == 0 || text[i - 1] != '/')
+                    .any(|i| glob(rest, &text[i..])),
+                Some((&'?', rest)) => match text.split_first() {
+                    Some((&c, text)) => c != '/' && glob(rest, text),
+                    None => false,
+                },
+                Some((&p, rest)) => match text.split_first() {
+                    Some((&c, text)) => c == p && glob(rest, text),
+                    None => false,
+                },
+            }
+        }
+        let pattern: Vec<char> = self.id.text().chars().collect();
+        let text: Vec<char> = name.id.text().chars().collect();
+        self.kind == name.kind && glob(&pattern, &text)
+    }
+
+    /// Resolve a name that was written inside a unit in `namespace`.  Names
+    /// that are already namespaced are left alone, and local names are looked
+    /// up in `namespace` first, falling back to the top-level unit.
+    pub fn resolve<F>(&self, namespace: Option<&str>, exists: F) -> UnitName
+    where
+        F: Fn(&UnitName) -> bool,
+    {
+        if let (None, Some(namespace)) = (self.namespace(), namespace) {
+            let local = UnitName {
+                id: Id::new(&format!("{}/{}", namespace, self.id.text())),
+                kind: self.kind.clone(),
+            };
+            if exists(&local) {
+                return local;
+            }
+        }
+        self.clone()
     }
 
     pub fn from_path(path: &Path) -> Result<Self, UnitNameError> {
@@ -89,37 +316,68 @@ impl UnitName {
 
         // Perform the extension-to-unit-kind mapping.  Reject invalid
         // or unrecognized unit kinds.
-        let unit_kind = match extension.as_str() {
-            "interface" => UnitKind::Interface,
-            "jig" => UnitKind::Jig,
-            "logger" => UnitKind::Logger,
-            "scenario" => UnitKind::Scenario,
-            "test" => UnitKind::Test,
-            "trigger" => UnitKind::Trigger,
-            _ => return Err(UnitNameError::UnrecognizedUnitType(extension)),
+        let unit_kind = match UnitKind::from_suffix(&extension) {
+            Some(kind) => kind,
+            None => return Err(UnitNameError::UnrecognizedUnitType(extension)),
         };
 
         Ok(UnitName {
-            id: unit_id,
+            id: Id::new(&unit_id),
             kind: unit_kind,
         })
     }
 
+    /// Generate a UnitName for a file found under the unit directory `root`,
+    /// namespaced by the subdirectory it's in.
+    pub fn from_unit_path(root: &Path, path: &Path) -> Result<Self, UnitNameError> {
+        let mut name = Self::from_path(path)?;
+        let namespace: Vec<String> = match path.strip_prefix(root).ok().and_then(|p| p.parent()) {
+            Some(dir) => dir
+                .components()
+                .filter_map(|c| match c {
+                    Component::Normal(s) => Some(s.to_string_lossy().into_owned()),
+                    _ => None,
+                })
+                .collect(),
+            None => vec![],
+        };
+        if !namespace.is_empty() {
+            name.id = Id::new(&format!("{}/{}", namespace.join("/"), name.id.text()));
+        }
+        Ok(name)
+    }
+
     /// Generate a UnitName from the specified name.
     /// If no extension is present, use default_type as the extension.
     pub fn from_str(name: &str, default_type: &str) -> Result<Self, UnitNameError> {
+        if let Some(pos) = name.rfind('/') {
+            // Namespaces are subdirectories of a unit directory, and names
+            // end up in paths, so they may only go down into one.
+            if name[..pos]
+                .split('/')
+                .any(|part| part.is_empty() || part == "." || part == "..")
+            {
+                return Err(UnitNameError::InvalidName(name.to_owned()));
+            }
+            let mut unit_name = Self::from_str(&name[pos + 1..], default_type)?;
+            unit_name.id = Id::new(&format!("{}/{}", &name[..pos], unit_name.id.text()));
+            return Ok(unit_name);
+        }
         let path = Path::new(name);
         let result = if path.extension().is_none() {
             let new_path = format!("{}.{}", path.to_string_lossy(), default_type);
             Self::from_path(Path::new(&new_path))
         } else {
             Self::from_path(path)
-        };
-        result
+        }?;
+        if result.id.text() == "." || result.id.text() == ".." {
+            return Err(UnitNameError::InvalidName(name.to_owned()));
+        }
+        Ok(result)
     }
 
     pub fn from_list(s: &str, default_type: &str) -> Result<Vec<Self>, UnitNameError> {
-        let in_list_list: Vec<&str> = s.split(|c| c == ',').collect();
+        let in_list_list: Vec<&str> = s.split(',').collect();
         let mut out_list = vec![];
         for in_list in in_list_list {
             for item in in_list.split_whitespace() {
@@ -131,7 +389,7 @@ impl UnitName {
 
     pub fn internal(s: &str) -> Self {
         UnitName {
-            id: s.to_owned(),
+            id: Id::new(s),
             kind: UnitKind::Internal,
         }
     }
@@ -139,7 +397,97 @@ impl UnitName {
 
 impl fmt::Display for UnitName {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}.{}", self.id, self.kind)
+        write!(f, "{}.{}", self.id.text(), self.kind)
+    }
+}
+
+/// An error that clients may branch on.  The code is short, stable, and
+/// machine-readable, while the Display impl provides a message for humans.
+pub trait ErrorCode: fmt::Display {
+    /// A name for this kind of error, e.g. "unit-not-found".
+    fn code(&self) -> &'static str;
+
+    /// Capture this error so that it may be broadcast.
+    fn to_failure(&self) -> UnitFailure {
+        UnitFailure::new(self.code(), format!("{}", self))
+    }
+}
+
+/// Why a unit failed to load, select, activate, or deactivate.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize)]
+pub struct UnitFailure {
+    code: &'static str,
+    message: String,
+    location: Option<SourceLocation>,
+}
+
+impl UnitFailure {
+    pub fn new(code: &'static str, message: String) -> Self {
+        UnitFailure {
+            code,
+            message,
+            location: None,
+        }
+    }
+
+    /// Note where in the unit file the problem is.
+    pub fn with_location(mut self, location: SourceLocation) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn message(&self) -> &String {
+        &self.message
+    }
+
+    /// Where in the unit file the problem is, for syntax errors.
+    pub fn location(&self) -> Option<&SourceLocation> {
+        self.location.as_ref()
+    }
+}
+
+/// Codes of failures read back from a recording.  Codes are normally static
+/// strings, so each one read is kept for as long as the program runs.
+static RECORDED_CODES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+impl<'de> Deserialize<'de> for UnitFailure {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Recorded {
+            code: String,
+            message: String,
+            #[serde(default)]
+            location: Option<SourceLocation>,
+        }
+        let Recorded {
+            code,
+            message,
+            location,
+        } = Recorded::deserialize(deserializer)?;
+        let mut codes = RECORDED_CODES.locked();
+        let code = match codes.get(code.as_str()) {
+            Some(code) => *code,
+            None => {
+                let code: &'static str = Box::leak(code.into_boxed_str());
+                codes.insert(code);
+                code
+            }
+        };
+        Ok(UnitFailure {
+            code,
+            message,
+            location,
+        })
+    }
+}
+
+impl fmt::Display for UnitFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
     }
 }
 
@@ -150,6 +498,7 @@ pub enum UnitIncompatibleReason {
     TestFileNotPresent(String),
     IncompatibleJig,
     DependencyError(DepError<UnitName>),
+    InvalidInheritance(String),
 }
 
 impl fmt::Display for UnitIncompatibleReason {
@@ -165,6 +514,9 @@ impl fmt::Display for UnitIncompatibleReason {
                 write!(f, "Test file {} not present", file_name)
             }
             UnitIncompatibleReason::IncompatibleJig => write!(f, "Jig not compatible"),
+            UnitIncompatibleReason::InvalidInheritance(ref reason) => {
+                write!(f, "Unable to inherit: {}", reason)
+            }
             UnitIncompatibleReason::DependencyError(ref dep_error) => match dep_error {
                 DepError::RequirementsNotFound(ref req) => {
                     write!(f, "Requirement '{}' not found", req)
@@ -189,6 +541,19 @@ impl fmt::Display for UnitIncompatibleReason {
     }
 }
 
+impl ErrorCode for UnitIncompatibleReason {
+    fn code(&self) -> &'static str {
+        match self {
+            UnitIncompatibleReason::TestProgramReturnedNonzero(_, _) => "test-program-nonzero",
+            UnitIncompatibleReason::TestProgramFailed(_) => "test-program-failed",
+            UnitIncompatibleReason::TestFileNotPresent(_) => "test-file-not-present",
+            UnitIncompatibleReason::IncompatibleJig => "incompatible-jig",
+            UnitIncompatibleReason::DependencyError(_) => "dependency-error",
+            UnitIncompatibleReason::InvalidInheritance(_) => "invalid-inheritance",
+        }
+    }
+}
+
 impl From<RunnyError> for UnitIncompatibleReason {
     fn from(error: RunnyError) -> Self {
         match error {
@@ -230,6 +595,15 @@ impl fmt::Display for UnitSelectError {
     }
 }
 
+impl ErrorCode for UnitSelectError {
+    fn code(&self) -> &'static str {
+        match self {
+            UnitSelectError::UnitNotFound => "unit-not-found",
+            UnitSelectError::NoCompatibleJig => "no-compatible-jig",
+        }
+    }
+}
+
 pub enum UnitDeselectError {
     /// We tried to select a unit based on its ID, but it wasn't found.
     UnitNotFound,
@@ -250,6 +624,27 @@ pub enum UnitActivateError {
 
     /// We tried to Exec, but an error occurred.
     ExecFailed(RunnyError),
+
+    /// The command referred to a secret that couldn't be filled in.
+    SecretFailed(SecretError),
+
+    /// An allocator couldn't hand out a value that a test needs.
+    AllocationFailed(String),
+
+    /// A file that a test needs couldn't be downloaded.
+    FetchFailed(String),
+
+    /// The agent a test is run on couldn't be asked to run it.
+    AgentFailed(String),
+
+    /// The test's concurrency group couldn't be checked for a free slot.
+    ConcurrencyFailed(String),
+
+    /// The jig's overrides for the test aren't valid for it.
+    OverrideFailed(String),
+
+    /// The input device a trigger reads couldn't be opened.
+    DeviceFailed(String),
 }
 
 impl From<RunnyError> for UnitActivateError {
@@ -258,6 +653,12 @@ impl From<RunnyError> for UnitActivateError {
     }
 }
 
+impl From<SecretError> for UnitActivateError {
+    fn from(kind: SecretError) -> Self {
+        UnitActivateError::SecretFailed(kind)
+    }
+}
+
 impl fmt::Display for UnitActivateError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -273,12 +674,42 @@ impl fmt::Display for UnitActivateError {
                     write!(f, "Unable to activate unit: Nix library error: {:?}", e)
                 }
             },
+            UnitActivateError::SecretFailed(ref e) => write!(f, "Unable to activate unit: {}", e),
+            UnitActivateError::AllocationFailed(ref e) => {
+                write!(f, "Unable to activate unit: {}", e)
+            }
+            UnitActivateError::FetchFailed(ref e) => write!(f, "Unable to activate unit: {}", e),
+            UnitActivateError::AgentFailed(ref e) => write!(f, "Unable to activate unit: {}", e),
+            UnitActivateError::ConcurrencyFailed(ref e) => {
+                write!(f, "Unable to activate unit: {}", e)
+            }
+            UnitActivateError::OverrideFailed(ref e) => {
+                write!(f, "Unable to activate unit: {}", e)
+            }
+            UnitActivateError::DeviceFailed(ref e) => write!(f, "Unable to activate unit: {}", e),
             UnitActivateError::UnitNotFound => write!(f, "Couldn't find unit by id"),
             UnitActivateError::UnitNotSelected => write!(f, "Tried to activate a deselected unit"),
         }
     }
 }
 
+impl ErrorCode for UnitActivateError {
+    fn code(&self) -> &'static str {
+        match self {
+            UnitActivateError::UnitNotFound => "unit-not-found",
+            UnitActivateError::UnitNotSelected => "unit-not-selected",
+            UnitActivateError::ExecFailed(_) => "exec-failed",
+            UnitActivateError::SecretFailed(_) => "secret-failed",
+            UnitActivateError::AllocationFailed(_) => "allocation-failed",
+            UnitActivateError::FetchFailed(_) => "fetch-failed",
+            UnitActivateError::AgentFailed(_) => "agent-failed",
+            UnitActivateError::ConcurrencyFailed(_) => "concurrency-failed",
+            UnitActivateError::OverrideFailed(_) => "override-failed",
+            UnitActivateError::DeviceFailed(_) => "device-failed",
+        }
+    }
+}
+
 pub enum UnitDeactivateError {
     /// We tried to deactivate a unit based on its ID, but it wasn't found.
     UnitNotFound,
@@ -306,15 +737,25 @@ impl fmt::Display for UnitDeactivateError {
     }
 }
 
+impl ErrorCode for UnitDeactivateError {
+    fn code(&self) -> &'static str {
+        match self {
+            UnitDeactivateError::UnitNotFound => "unit-not-found",
+            UnitDeactivateError::NonZeroReturn(_) => "nonzero-return",
+            UnitDeactivateError::RunningError(_) => "running-error",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum UnitDescriptionError {
     InvalidUnitName(UnitNameError),
     MissingSection(String /* section name */),
     MissingValue(String /* section name */, String /* key name */),
     FileOpenError(io::Error),
-    ParseError(ParserError),
+    ParseError(SyntaxError),
     RegexError(self::regex::Error),
-    HumantimeError(DurationError),
+    DurationError(DurationError),
     ParseIntError(std::num::ParseIntError),
     InvalidValue(
         String,      // Section name
@@ -336,8 +777,8 @@ impl From<io::Error> for UnitDescriptionError {
     }
 }
 
-impl From<self::systemd_parser::errors::ParserError> for UnitDescriptionError {
-    fn from(error: self::systemd_parser::errors::ParserError) -> Self {
+impl From<SyntaxError> for UnitDescriptionError {
+    fn from(error: SyntaxError) -> Self {
         UnitDescriptionError::ParseError(error)
     }
 }
@@ -350,7 +791,7 @@ impl From<self::regex::Error> for UnitDescriptionError {
 
 impl From<DurationError> for UnitDescriptionError {
     fn from(error: DurationError) -> Self {
-        UnitDescriptionError::HumantimeError(error)
+        UnitDescriptionError::DurationError(error)
     }
 }
 
@@ -375,9 +816,7 @@ impl fmt::Display for UnitDescriptionError {
             UnitDescriptionError::ParseError(ref e) => {
                 write!(f, "syntax error: {}", e)
             }
-            UnitDescriptionError::HumantimeError(ref e) => {
-                write!(f, "time parse error: {}", e)
-            }
+            UnitDescriptionError::DurationError(ref e) => write!(f, "invalid duration: {}", e),
             UnitDescriptionError::RegexError(ref e) => write!(f, "unable to parse regex: {}", e),
             UnitDescriptionError::MissingValue(ref sec, ref key) => {
                 write!(f, "key '{}' in section '{}' requires a value", key, sec)
@@ -396,3 +835,75 @@ impl fmt::Display for UnitDescriptionError {
         }
     }
 }
+
+impl ErrorCode for UnitDescriptionError {
+    fn code(&self) -> &'static str {
+        match self {
+            UnitDescriptionError::InvalidUnitName(_) => "invalid-unit-name",
+            UnitDescriptionError::MissingSection(_) => "missing-section",
+            UnitDescriptionError::MissingValue(_, _) => "missing-value",
+            UnitDescriptionError::FileOpenError(_) => "file-open-error",
+            UnitDescriptionError::ParseError(_) => "syntax-error",
+            UnitDescriptionError::RegexError(_) => "invalid-regex",
+            UnitDescriptionError::DurationError(_) => "invalid-duration",
+            UnitDescriptionError::ParseIntError(_) => "invalid-number",
+            UnitDescriptionError::InvalidValue(_, _, _, _) => "invalid-value",
+        }
+    }
+
+    fn to_failure(&self) -> UnitFailure {
+        let failure = UnitFailure::new(self.code(), format!("{}", self));
+        match self {
+            UnitDescriptionError::ParseError(ref e) => match e.location() {
+                Some(location) => failure.with_location(location.clone()),
+                None => failure,
+            },
+            _ => failure,
+        }
+    }
+}
+
+/// Parse an Alias directive: the names a unit had before it was renamed,
+/// which must be of the same kind as the unit.
+pub fn parse_aliases(
+    section: &str,
+    value: Option<&str>,
+    kind: &str,
+) -> Result<Vec<UnitName>, UnitDescriptionError> {
+    let aliases = UnitName::from_list(value.unwrap_or(""), kind)?;
+    for alias in &aliases {
+        if alias.kind().to_string() != kind {
+            return Err(UnitDescriptionError::InvalidValue(
+                section.to_owned(),
+                "Alias".to_owned(),
+                alias.to_string(),
+                vec![format!("names of {} units", kind)],
+            ));
+        }
+    }
+    Ok(aliases)
+}
+
+/// Parse a boolean directive such as "Disabled=yes".
+pub fn parse_bool(
+    section: &str,
+    key: &str,
+    value: Option<&str>,
+) -> Result<bool, UnitDescriptionError> {
+    match value.map(|s| s.to_lowercase()) {
+        None => Err(UnitDescriptionError::MissingValue(
+            section.to_owned(),
+            key.to_owned(),
+        )),
+        Some(s) => match s.as_str() {
+            "true" | "yes" | "on" | "1" => Ok(true),
+            "false" | "no" | "off" | "0" => Ok(false),
+            other => Err(UnitDescriptionError::InvalidValue(
+                section.to_owned(),
+                key.to_owned(),
+                other.to_owned(),
+                vec!["true".to_owned(), "false".to_owned()],
+            )),
+        },
+    }
+}
@@ -1,13 +1,20 @@
 use std::fmt;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::time;
 
-use unit::{UnitKind, UnitName};
+use chaos;
+use config::ConfigChange;
+use poison::Lock;
+use station::Station;
+use timeline::{self, Monotonic};
+use unit::{UnitFailure, UnitKind, UnitName};
 use unitmanager::ManagerControlMessage;
+use units::test::TestVerdict;
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
 pub enum UnitStatus {
     /// A new unit file has appeared on the disk
     Added(PathBuf),
@@ -19,7 +26,7 @@ pub enum UnitStatus {
     LoadStarted(PathBuf /* path to the unit file that's gong away */),
 
     /// The unit file failed to load for some reason
-    LoadFailed(String /* reason */),
+    LoadFailed(UnitFailure),
 
     /// The unit file has been loaded from disk, and may be selected.
     Loaded,
@@ -30,7 +37,7 @@ pub enum UnitStatus {
     Selected,
 
     /// The unit couldn't be selected for some reason.
-    SelectFailed(String /* reason */),
+    SelectFailed(UnitFailure),
 
     /// The unit has been deselected (but is still loaded, and may be selected later)
     Deselected(String /* reason */),
@@ -43,13 +50,13 @@ pub enum UnitStatus {
     /// to move into the Active state but fails, then ActivationFailed will be sent.
     /// If instead the unit is Active for a while but then fails at a later time,
     /// ActivationFailed will be sent.
-    ActivationFailed(String /* reason */),
+    ActivationFailed(UnitFailure),
 
     /// The unit was active, then stopped being active due to finishing successfully
     DeactivatedSuccessfully(String /* reason */),
 
     /// The unit was active, then stopped being active due to finishing unsuccessfully
-    DeactivatedUnsuccessfully(String /* reason */),
+    DeactivatedUnsuccessfully(UnitFailure),
 
     /// The unit already successfully loaded, but is being removed
     UnloadStarted(PathBuf /* path to the unit file that's gong away */),
@@ -59,6 +66,12 @@ pub enum UnitStatus {
 
     /// The unit file was removed from the disk
     Removed(PathBuf),
+
+    /// The unit file is present, but the unit has been disabled and won't be loaded
+    Disabled(String /* reason */),
+
+    /// The unit is loaded, but can't be used with the current jig
+    Incompatible(String /* reason */),
 }
 
 impl fmt::Display for UnitStatus {
@@ -87,11 +100,13 @@ impl fmt::Display for UnitStatus {
             }
             UnitStatus::UpdateStarted(ref path) => write!(f, "updating {}", path.to_string_lossy()),
             UnitStatus::Removed(ref path) => write!(f, "removed file {}", path.to_string_lossy()),
+            UnitStatus::Disabled(ref reason) => write!(f, "disabled: {}", reason),
+            UnitStatus::Incompatible(ref reason) => write!(f, "incompatible: {}", reason),
         }
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
 pub struct UnitStatusEvent {
     pub name: UnitName,
     pub status: UnitStatus,
@@ -107,8 +122,8 @@ impl UnitStatusEvent {
     pub fn kind(&self) -> &UnitKind {
         self.name.kind()
     }
-    pub fn new_added(path: &Path) -> Option<UnitStatusEvent> {
-        let name = match UnitName::from_path(path) {
+    pub fn new_added(root: &Path, path: &Path) -> Option<UnitStatusEvent> {
+        let name = match UnitName::from_unit_path(root, path) {
             Err(_) => return None,
             Ok(s) => s,
         };
@@ -118,8 +133,8 @@ impl UnitStatusEvent {
             status: UnitStatus::Added(path.to_owned()),
         })
     }
-    pub fn new_updated(path: &Path) -> Option<UnitStatusEvent> {
-        let name = match UnitName::from_path(path) {
+    pub fn new_updated(root: &Path, path: &Path) -> Option<UnitStatusEvent> {
+        let name = match UnitName::from_unit_path(root, path) {
             Err(_) => return None,
             Ok(s) => s,
         };
@@ -129,8 +144,8 @@ impl UnitStatusEvent {
             status: UnitStatus::Updated(path.to_owned()),
         })
     }
-    pub fn new_removed(path: &Path) -> Option<UnitStatusEvent> {
-        let name = match UnitName::from_path(path) {
+    pub fn new_removed(root: &Path, path: &Path) -> Option<UnitStatusEvent> {
+        let name = match UnitName::from_unit_path(root, path) {
             Err(_) => return None,
             Ok(s) => s,
         };
@@ -148,7 +163,7 @@ impl UnitStatusEvent {
         }
     }
 
-    pub fn new_select_failed(name: &UnitName, msg: String) -> UnitStatusEvent {
+    pub fn new_select_failed(name: &UnitName, msg: UnitFailure) -> UnitStatusEvent {
         UnitStatusEvent {
             name: name.clone(),
             status: UnitStatus::SelectFailed(msg),
@@ -176,7 +191,7 @@ impl UnitStatusEvent {
         }
     }
 
-    pub fn new_load_failed(name: &UnitName, msg: String) -> UnitStatusEvent {
+    pub fn new_load_failed(name: &UnitName, msg: UnitFailure) -> UnitStatusEvent {
         UnitStatusEvent {
             name: name.clone(),
             status: UnitStatus::LoadFailed(msg),
@@ -190,7 +205,7 @@ impl UnitStatusEvent {
         }
     }
 
-    pub fn new_active_failed(name: &UnitName, msg: String) -> UnitStatusEvent {
+    pub fn new_active_failed(name: &UnitName, msg: UnitFailure) -> UnitStatusEvent {
         UnitStatusEvent {
             name: name.clone(),
             status: UnitStatus::ActivationFailed(msg),
@@ -204,7 +219,7 @@ impl UnitStatusEvent {
         }
     }
 
-    pub fn new_deactivate_failure(name: &UnitName, msg: String) -> UnitStatusEvent {
+    pub fn new_deactivate_failure(name: &UnitName, msg: UnitFailure) -> UnitStatusEvent {
         UnitStatusEvent {
             name: name.clone(),
             status: UnitStatus::DeactivatedUnsuccessfully(msg),
@@ -218,6 +233,20 @@ impl UnitStatusEvent {
         }
     }
 
+    pub fn new_disabled(name: &UnitName, reason: String) -> UnitStatusEvent {
+        UnitStatusEvent {
+            name: name.clone(),
+            status: UnitStatus::Disabled(reason),
+        }
+    }
+
+    pub fn new_incompatible(name: &UnitName, reason: String) -> UnitStatusEvent {
+        UnitStatusEvent {
+            name: name.clone(),
+            status: UnitStatus::Incompatible(reason),
+        }
+    }
+
     pub fn new_unload_started(name: &UnitName, path: &Path) -> UnitStatusEvent {
         UnitStatusEvent {
             name: name.clone(),
@@ -228,7 +257,7 @@ impl UnitStatusEvent {
 
 pub type UnitCategoryStatus = String;
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
 pub struct UnitCategoryEvent {
     kind: UnitKind,
     status: UnitCategoryStatus,
@@ -249,7 +278,7 @@ impl UnitCategoryEvent {
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
 pub enum LogType {
     Error,
     Info,
@@ -264,7 +293,7 @@ impl LogType {
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
     unit: UnitName,
     log_type: LogType,
@@ -274,6 +303,23 @@ pub struct LogEntry {
 
     /// Number of nanoseconds since the epoch
     pub unix_time_nsecs: u32,
+
+    /// Sequence number of the event that carried this entry, or 0 if it
+    /// hasn't been broadcast yet
+    pub sequence: u64,
+
+    /// The station the entry was made on, or empty if it hasn't been
+    /// broadcast yet
+    #[serde(default)]
+    pub station: String,
+
+    /// Which boot the entry was made in (see timeline)
+    #[serde(default)]
+    pub boot_id: String,
+
+    /// Nanoseconds since that boot, on the monotonic clock
+    #[serde(default)]
+    pub monotonic_nsecs: u64,
 }
 
 impl LogEntry {
@@ -285,6 +331,10 @@ impl LogEntry {
             log_message: message,
             unix_time: elapsed.as_secs(),
             unix_time_nsecs: elapsed.subsec_nanos(),
+            sequence: 0,
+            station: String::new(),
+            boot_id: timeline::boot_id().to_owned(),
+            monotonic_nsecs: timeline::nanos(),
         }
     }
 
@@ -296,6 +346,10 @@ impl LogEntry {
             log_message: message,
             unix_time: elapsed.as_secs(),
             unix_time_nsecs: elapsed.subsec_nanos(),
+            sequence: 0,
+            station: String::new(),
+            boot_id: timeline::boot_id().to_owned(),
+            monotonic_nsecs: timeline::nanos(),
         }
     }
 
@@ -307,6 +361,22 @@ impl LogEntry {
         self.unix_time_nsecs
     }
 
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn station(&self) -> &str {
+        &self.station
+    }
+
+    pub fn boot_id(&self) -> &str {
+        &self.boot_id
+    }
+
+    pub fn monotonic_nsecs(&self) -> u64 {
+        self.monotonic_nsecs
+    }
+
     pub fn message(&self) -> &String {
         &self.log_message
     }
@@ -337,7 +407,10 @@ impl fmt::Display for LogEntry {
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+// Scenarios finish far less often than anything else happens, so their
+// summaries aren't worth boxing.
+#[allow(clippy::large_enum_variant)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
 pub enum UnitEvent {
     /// A unit has updated its status.
     Status(UnitStatusEvent),
@@ -360,39 +433,346 @@ pub enum UnitEvent {
     /// A unit made a request to a Manager, which will be passed to the main thread.
     ManagerRequest(ManagerControlMessage),
 
+    /// The config file was reloaded.
+    ConfigChanged(ConfigChange),
+
+    /// A scenario finished running.
+    ScenarioFinished(ScenarioSummary),
+
+    /// An operator added a note to a run that had already finished.  This is
+    /// its summary again, with every note added to it so far.
+    RunNoted(ScenarioSummary),
+
+    /// A scenario finished, and its output is complete in this run directory.
+    RunFinished(PathBuf),
+
     /// The system is shutting down.
     Shutdown,
 }
 
+/// Everything about how a scenario run turned out, in one place.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
+pub struct ScenarioSummary {
+    pub scenario: UnitName,
+
+    /// The jig the scenario ran on, if any.
+    pub jig: Option<UnitName>,
+
+    /// The serial number of the device under test, if a test reported one.
+    pub serial: Option<String>,
+
+    /// The values allocators handed out for the run, as (allocator, value).
+    #[serde(default)]
+    pub allocations: Vec<(UnitName, String)>,
+
+    /// The values the scenario's parameters were given, as (name, value).
+    #[serde(default)]
+    pub parameters: Vec<(String, String)>,
+
+    /// What the trigger that started the run saw, such as a scanned barcode,
+    /// if it passed it on.
+    #[serde(default)]
+    pub payload: Option<String>,
+
+    /// The name of the run's directory in the LogDirectory, if there is one.
+    pub run_id: Option<String>,
+
+    /// The run_id of the run this one retested with RERUN FAILED, if any.
+    pub parent_run: Option<String>,
+
+    /// The station that ran the scenario.
+    #[serde(default)]
+    pub station: Option<Station>,
+
+    /// When the run finished, on the station's monotonic clock, so that
+    /// runs can be put in order even if the time of day was changed.
+    #[serde(default)]
+    pub finished: Option<Monotonic>,
+
+    /// True if the station was in maintenance mode, so the run says nothing
+    /// about production.
+    pub maintenance: bool,
+
+    /// True if the run started while the system clock couldn't be trusted,
+    /// so its times shouldn't be relied on.
+    #[serde(default)]
+    pub clock_unsynced: bool,
+
+    /// The notes operators added to the run, oldest first.
+    #[serde(default)]
+    pub notes: Vec<String>,
+
+    /// The result code, as sent with FINISH: 200 if every test passed.
+    pub code: u32,
+
+    /// The run's directory, where its output was saved, if there's a
+    /// LogDirectory.
+    #[serde(default)]
+    pub log_path: Option<PathBuf>,
+
+    /// CPU time used by the run's tests, if it's known.
+    #[serde(default)]
+    pub cpu: Option<time::Duration>,
+
+    /// The most memory any of the run's tests used at once, in KiB, if it's
+    /// known.
+    #[serde(default)]
+    pub peak_memory_kb: Option<u64>,
+
+    /// Why the scenario finished, for humans.
+    pub reason: String,
+
+    /// How long the scenario took to run.
+    pub duration: time::Duration,
+
+    pub passed: u32,
+    pub failed: u32,
+    pub faults: u32,
+
+    /// Tests that were skipped, or never got to run.
+    pub skipped: u32,
+
+    /// The first test that failed or hit a fault, and why.
+    pub first_failure: Option<(UnitName, String)>,
+
+    /// How each test in the run turned out, in the order they were run.
+    /// For scenarios that Repeat, this is the last iteration.
+    pub tests: Vec<TestSummary>,
+
+    /// How each pass through the tests went, for scenarios that Repeat.
+    /// The counts above are then totals over every iteration.
+    #[serde(default)]
+    pub iterations: Vec<IterationSummary>,
+}
+
+/// How one pass through a repeating scenario's tests turned out.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
+pub struct IterationSummary {
+    /// Which pass this was, starting from 1.
+    pub iteration: u32,
+
+    /// The values the scenario's Matrix gave the pass, as (name, value).
+    #[serde(default)]
+    pub parameters: Vec<(String, String)>,
+
+    /// How long the pass took.
+    pub duration: time::Duration,
+
+    pub passed: u32,
+    pub failed: u32,
+    pub faults: u32,
+    pub skipped: u32,
+
+    /// The first test in the pass that failed or hit a fault, and why.
+    pub first_failure: Option<(UnitName, String)>,
+
+    /// How each test in the pass turned out, in the order they were run.
+    #[serde(default)]
+    pub verdicts: Vec<(UnitName, TestVerdict)>,
+}
+
+impl IterationSummary {
+    pub fn is_pass(&self) -> bool {
+        self.failed == 0 && self.faults == 0
+    }
+}
+
+impl fmt::Display for IterationSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "iteration {}{}: {} passed, {} failed, {} faults, {} skipped in {}.{:03}s",
+            self.iteration,
+            if self.parameters.is_empty() {
+                String::new()
+            } else {
+                let values: Vec<String> = self
+                    .parameters
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                format!(" ({})", values.join(", "))
+            },
+            self.passed,
+            self.failed,
+            self.faults,
+            self.skipped,
+            self.duration.as_secs(),
+            self.duration.subsec_millis()
+        )?;
+        if let Some((ref test, ref reason)) = self.first_failure {
+            write!(f, ", first failure: {}: {}", test, reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// How one test in a scenario run turned out.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
+pub struct TestSummary {
+    pub test: UnitName,
+    pub verdict: TestVerdict,
+
+    /// Why the test failed or was skipped, if it did.
+    pub reason: String,
+
+    /// How long the test took, if it ran.
+    pub duration: Option<time::Duration>,
+
+    /// What the test reported with its MeasurementPattern, as (name, value).
+    pub measurements: Vec<(String, String)>,
+
+    /// The test's exit code, if it ran.
+    #[serde(default)]
+    pub code: Option<i32>,
+
+    /// Where the test's output was saved, if it ran and there's a
+    /// LogDirectory.
+    #[serde(default)]
+    pub log_path: Option<PathBuf>,
+
+    /// CPU time used by the test's processes, if it ran and it's known.
+    #[serde(default)]
+    pub cpu: Option<time::Duration>,
+
+    /// The most memory the test's processes used at once, in KiB, if it ran
+    /// and it's known.
+    #[serde(default)]
+    pub peak_memory_kb: Option<u64>,
+}
+
+impl ScenarioSummary {
+    pub fn verdict(&self) -> TestVerdict {
+        if self.code == 200 {
+            TestVerdict::Pass
+        } else if self.faults > 0 {
+            TestVerdict::Fault
+        } else {
+            TestVerdict::Fail
+        }
+    }
+}
+
+impl fmt::Display for ScenarioSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ({} passed, {} failed, {} faults, {} skipped) in {}.{:03}s",
+            self.verdict(),
+            self.reason,
+            self.passed,
+            self.failed,
+            self.faults,
+            self.skipped,
+            self.duration.as_secs(),
+            self.duration.subsec_millis()
+        )?;
+        if let Some((ref test, ref reason)) = self.first_failure {
+            write!(f, ", first failure: {}: {}", test, reason)?;
+        }
+        if let Some(ref parent) = self.parent_run {
+            write!(f, ", rerun of {}", parent)?;
+        }
+        if !self.iterations.is_empty() {
+            write!(f, ", over {} iterations", self.iterations.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// When, where, and in what order, an event was broadcast.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct EventStamp {
+    /// Position of this event among all events broadcast, starting from 1
+    pub sequence: u64,
+
+    /// The id of the station that broadcast it
+    pub station: String,
+
+    /// Number of seconds since the epoch
+    pub unix_time: u64,
+
+    /// Number of nanoseconds since the epoch
+    pub unix_time_nsecs: u32,
+
+    /// Number of nanoseconds since the boot, on the monotonic clock, which
+    /// isn't stepped when the time of day is (see timeline)
+    pub monotonic_nsecs: u64,
+}
+
+/// A UnitEvent, along with when it was broadcast.  Every subscriber is sent
+/// the same one, behind an Arc, so that a long line of output isn't copied
+/// once for each of them.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct StampedEvent {
+    pub stamp: EventStamp,
+    pub event: UnitEvent,
+}
+
 #[derive(Debug, Clone)]
 pub struct UnitBroadcaster {
-    senders: Arc<Mutex<Vec<Sender<UnitEvent>>>>,
+    senders: Arc<Mutex<Vec<Sender<Arc<StampedEvent>>>>>,
+
+    /// Sequence number of the most recently broadcast event.
+    sequence: Arc<AtomicU64>,
+
+    /// The id of the station, which every event is stamped with.
+    station: Arc<Mutex<String>>,
+}
+
+impl Default for UnitBroadcaster {
+    fn default() -> Self {
+        UnitBroadcaster::new()
+    }
 }
 
 impl UnitBroadcaster {
     pub fn new() -> Self {
         UnitBroadcaster {
             senders: Arc::new(Mutex::new(vec![])),
+            sequence: Arc::new(AtomicU64::new(0)),
+            station: Arc::new(Mutex::new(Station::from_hostname().id)),
         }
     }
 
-    fn broadcast_core(senders: &Arc<Mutex<Vec<Sender<UnitEvent>>>>, event: &UnitEvent) {
-        let mut to_remove = None;
-        // Send a copy of the message to each of the listeners.
-        let mut notify_senders_ref = senders.lock().unwrap();
+    /// Stamp events broadcast from now on with this station id, such as
+    /// once the config says which station this is.
+    pub fn set_station(&self, id: &str) {
+        *self.station.locked() = id.to_owned();
+    }
+
+    fn broadcast_core(
+        senders: &Arc<Mutex<Vec<Sender<Arc<StampedEvent>>>>>,
+        sequence: &AtomicU64,
+        station: &Mutex<String>,
+        event: &UnitEvent,
+    ) {
+        // Held back without the lock, so that events broadcast from other
+        // threads in the meantime go first.
+        chaos::delay_event();
+        // Send the message to each of the listeners, which share one copy of it.
+        let mut notify_senders_ref = senders.locked();
         {
-            for (idx, sender) in notify_senders_ref.iter().enumerate() {
-                if sender.send(event.clone()).is_err() {
-                    // If an error occurred, that means the receiver has closed
-                    // and so we must remove it.
-                    to_remove = Some(idx);
-                }
+            // Stamp the event while holding the lock, so that sequence
+            // numbers arrive in order.
+            let elapsed = LogEntry::elapsed();
+            let stamp = EventStamp {
+                sequence: sequence.fetch_add(1, Ordering::SeqCst) + 1,
+                station: station.locked().clone(),
+                unix_time: elapsed.as_secs(),
+                unix_time_nsecs: elapsed.subsec_nanos(),
+                monotonic_nsecs: timeline::nanos(),
+            };
+            let mut event = event.clone();
+            if let UnitEvent::Log(ref mut entry) = event {
+                entry.sequence = stamp.sequence;
+                entry.station = stamp.station.clone();
             }
-        }
+            let stamped = Arc::new(StampedEvent { stamp, event });
 
-        // If a sender threw an error, simply remove it from the list of elements to update
-        if let Some(idx) = to_remove {
-            notify_senders_ref.remove(idx);
+            // If an error occurred, that means the receiver has closed and
+            // so we must remove it.
+            notify_senders_ref.retain(|sender| sender.send(stamped.clone()).is_ok());
         }
 
         if *event == UnitEvent::Shutdown {
@@ -401,12 +781,12 @@ impl UnitBroadcaster {
     }
 
     pub fn broadcast(&self, event: &UnitEvent) {
-        Self::broadcast_core(&self.senders, event)
+        Self::broadcast_core(&self.senders, &self.sequence, &self.station, event)
     }
 
-    pub fn subscribe(&self) -> Receiver<UnitEvent> {
+    pub fn subscribe(&self) -> Receiver<Arc<StampedEvent>> {
         let (sender, receiver) = channel();
-        self.senders.lock().unwrap().push(sender);
+        self.senders.locked().push(sender);
         receiver
     }
 
@@ -0,0 +1,181 @@
+// Keeps what each unit file was parsed into, so that starting up on a large
+// unit tree doesn't mean parsing every file again, which can take a while on
+// a slow SD card.  With a StateDirectory, the cache is saved there after each
+// rescan, and read back in the next time a unit is loaded.
+//
+// Every file is still read, and its SHA-256 compared with the one it had
+// when it was parsed, rather than trusting its size or modification time,
+// which can stay the same across an edit or an image update.  Any file that
+// doesn't match is parsed in full, and a cache that can't be read, or was
+// written by another build of exclave, which may parse files differently,
+// is thrown away.
+//
+// The cache is shared with the unit watcher, which parses the files it finds
+// at startup on several threads at once, so that by the time the library
+// loads them, they're already in the cache.
+extern crate serde_json;
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use poison::Lock;
+use sha256;
+use unit::{UnitDescriptionError, UnitKind};
+use unitfile::{self, ParsedFile};
+use units::jig;
+use version;
+
+/// Name of the file under the state directory that holds the cache.
+pub const UNIT_CACHE_FILE: &str = "unit-cache";
+
+/// A unit cache that the library and the watcher both use.
+pub type SharedUnitCache = Arc<Mutex<UnitCache>>;
+
+/// The kinds of named sections that a kind of unit's files may have.
+pub fn named_sections(kind: &UnitKind) -> &'static [&'static str] {
+    match *kind {
+        UnitKind::Jig => jig::NAMED_SECTIONS,
+        _ => &[],
+    }
+}
+
+/// A unit file as it was parsed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CachedFile {
+    /// The SHA-256 of the file's contents.
+    digest: String,
+    parsed: ParsedFile,
+}
+
+/// What's saved in the state directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheContents {
+    version: String,
+    files: HashMap<PathBuf, CachedFile>,
+}
+
+/// The parsed unit files, by path.
+#[derive(Debug, Default)]
+pub struct UnitCache {
+    /// The state directory the cache was read from and is saved to, if any.
+    directory: Option<PathBuf>,
+
+    files: HashMap<PathBuf, CachedFile>,
+
+    /// How many files were read from the cache rather than parsed.
+    hits: usize,
+
+    /// Whether there's anything to save.
+    changed: bool,
+}
+
+impl UnitCache {
+    pub fn new() -> UnitCache {
+        Default::default()
+    }
+
+    pub fn new_shared() -> SharedUnitCache {
+        Arc::new(Mutex::new(UnitCache::new()))
+    }
+
+    /// Use the cache kept in a state directory, or none, reading it in if
+    /// it's not the one already in use.  Files that were parsed since are
+    /// kept, since they're checked against their digest before being used.
+    pub fn use_directory(&mut self, directory: Option<&Path>) {
+        if self.directory.as_deref() == directory {
+            return;
+        }
+        self.directory = directory.map(|d| d.to_owned());
+        self.changed = true;
+        let contents = directory
+            .and_then(|d| fs::read_to_string(d.join(UNIT_CACHE_FILE)).ok())
+            .and_then(|contents| serde_json::from_str::<CacheContents>(&contents).ok());
+        if let Some(contents) = contents {
+            if contents.version == version::build_id() {
+                for (path, cached) in contents.files {
+                    self.files.entry(path).or_insert(cached);
+                }
+            }
+        }
+    }
+
+    /// Parse a unit file into a shared cache, unless it's already there,
+    /// without holding the cache locked while parsing, so that several
+    /// files can be parsed at once.
+    pub fn preload(
+        cache: &Mutex<UnitCache>,
+        path: &Path,
+        allowed: &[&str],
+    ) -> Result<(), UnitDescriptionError> {
+        let contents = fs::read_to_string(path)?;
+        let digest = sha256::digest(contents.as_bytes());
+        if cache.locked().cached(path, &digest).is_some() {
+            return Ok(());
+        }
+        let parsed = unitfile::parse_to_keep(&contents, allowed)?;
+        cache.locked().insert(path, digest, parsed);
+        Ok(())
+    }
+
+    /// What a file was parsed into, if it had the given digest.
+    fn cached(&self, path: &Path, digest: &str) -> Option<&ParsedFile> {
+        self.files
+            .get(path)
+            .filter(|cached| cached.digest == digest)
+            .map(|cached| &cached.parsed)
+    }
+
+    fn insert(&mut self, path: &Path, digest: String, parsed: ParsedFile) {
+        self.files
+            .insert(path.to_owned(), CachedFile { digest, parsed });
+        self.changed = true;
+    }
+
+    /// Read a unit file that may have named sections of the kinds given,
+    /// parsing it only if it isn't the same as when it was last parsed.
+    pub fn read(
+        &mut self,
+        path: &Path,
+        allowed: &[&str],
+    ) -> Result<ParsedFile, UnitDescriptionError> {
+        let contents = fs::read_to_string(path)?;
+        let digest = sha256::digest(contents.as_bytes());
+        if let Some(parsed) = self.cached(path, &digest).cloned() {
+            self.hits += 1;
+            return Ok(parsed);
+        }
+        let parsed = unitfile::parse_to_keep(&contents, allowed)?;
+        self.insert(path, digest, parsed.clone());
+        Ok(parsed)
+    }
+
+    /// How many files have been read from the cache rather than parsed.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Forget files other than the ones given, which are the unit files
+    /// still loaded, and save the cache if it's changed.
+    pub fn save(&mut self, paths: &HashSet<PathBuf>) -> io::Result<()> {
+        let before = self.files.len();
+        self.files.retain(|path, _| paths.contains(path));
+        if self.files.len() != before {
+            self.changed = true;
+        }
+        let directory = match self.directory {
+            Some(ref d) if self.changed => d,
+            _ => return Ok(()),
+        };
+        let contents = CacheContents {
+            version: version::build_id(),
+            files: self.files.clone(),
+        };
+        let json = serde_json::to_string(&contents).map_err(io::Error::other)?;
+        fs::write(directory.join(UNIT_CACHE_FILE), json)?;
+        self.changed = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,496 @@
+// Reads unit files, and the settings file, into the directives they contain.
+// The format is systemd's, and each line is checked here before it's handed
+// to systemd_parser, so that a mistake is reported with the line and column
+// it's on and the directive it's in, rather than as a generic parse failure.
+// Files saved by Windows editors, with a byte order mark and CRLF line
+// endings, are read the same as any other, and no input makes this panic.
+extern crate systemd_parser;
+
+use std::collections::HashMap;
+use std::fmt;
+
+use self::systemd_parser::items::{SystemdItem, SystemdUnit};
+
+/// The longest directive name that's quoted in full in an error.
+const MAX_QUOTED_CHARS: usize = 64;
+
+/// Where in a unit file a problem was found.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
+pub struct SourceLocation {
+    /// The line, counting from 1.
+    pub line: u32,
+
+    /// The column, in characters, counting from 1.
+    pub column: u32,
+
+    /// The directive the problem is in, if it's in one.
+    pub directive: Option<String>,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)?;
+        if let Some(ref directive) = self.directive {
+            write!(f, ", in {}", directive)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SyntaxError {
+    location: Option<SourceLocation>,
+    message: String,
+}
+
+impl SyntaxError {
+    fn new(message: String) -> Self {
+        SyntaxError {
+            location: None,
+            message,
+        }
+    }
+
+    /// Where the error is, or None if it's about the file as a whole.
+    pub fn location(&self) -> Option<&SourceLocation> {
+        self.location.as_ref()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location {
+            Some(ref location) => write!(f, "{}: {}", location, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Directives as (key, value), in the order given.
+pub type Directives = Vec<(String, Option<String>)>;
+
+/// A section about one particular thing, with its name in quotes after the
+/// section's, such as [Override "flash.test"] in a jig.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct NamedSection {
+    pub section: String,
+    pub name: String,
+
+    /// The directives in the section.  Unlike other sections, these may
+    /// repeat directives in other sections.
+    pub directives: Directives,
+}
+
+/// What a unit file was parsed into, which can be kept and turned back into
+/// a unit without parsing the file again.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct ParsedFile {
+    /// Each section, in the order given, with its directives.
+    pub sections: Vec<(String, Directives)>,
+
+    pub named: Vec<NamedSection>,
+}
+
+impl ParsedFile {
+    /// The unit that the sections make up.
+    pub fn unit(&self) -> Result<SystemdUnit, SyntaxError> {
+        let mut items = vec![];
+        for (section, directives) in &self.sections {
+            items.push(SystemdItem::Category(section));
+            for (key, value) in directives {
+                items.push(SystemdItem::Directive(key, value.as_deref()));
+            }
+        }
+        SystemdUnit::new(&items).map_err(SyntaxError::new)
+    }
+}
+
+/// Parse the contents of a unit file.
+pub fn parse(contents: &str) -> Result<SystemdUnit, SyntaxError> {
+    parse_file(contents, &[]).map(|(unit, _)| unit)
+}
+
+/// Parse the contents of a unit file that may have named sections of the
+/// kinds given.
+pub fn parse_with_named(
+    contents: &str,
+    allowed: &[&str],
+) -> Result<(SystemdUnit, Vec<NamedSection>), SyntaxError> {
+    parse_file(contents, allowed).map(|(unit, parsed)| (unit, parsed.named))
+}
+
+/// Parse the contents of a unit file that may have named sections of the
+/// kinds given, into something that can be kept.
+pub fn parse_to_keep(contents: &str, allowed: &[&str]) -> Result<ParsedFile, SyntaxError> {
+    parse_file(contents, allowed).map(|(_, parsed)| parsed)
+}
+
+/// Make a unit with one section, out of directives given as (key, value).
+pub fn from_directives(
+    section: &str,
+    directives: &[(String, Option<String>)],
+) -> Result<SystemdUnit, SyntaxError> {
+    let mut items = vec![SystemdItem::Category(section)];
+    for (key, value) in directives {
+        items.push(SystemdItem::Directive(key, value.as_deref()));
+    }
+    SystemdUnit::new(&items).map_err(SyntaxError::new)
+}
+
+fn parse_file(contents: &str, allowed: &[&str]) -> Result<(SystemdUnit, ParsedFile), SyntaxError> {
+    let lines = logical_lines(contents);
+    let mut parsed = ParsedFile {
+        sections: vec![],
+        named: vec![],
+    };
+    let named = &mut parsed.named;
+    let mut in_named = false;
+    let mut section = None;
+    let mut sections: HashMap<&str, &str> = HashMap::new();
+    for line in &lines {
+        let (item, name, column) = match parse_line(line)? {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+        match (&item, name) {
+            (&SystemdItem::Category(section), Some(name)) => {
+                if !allowed.contains(&section) {
+                    return Err(line.error(
+                        column,
+                        None,
+                        format!("[{} \"{}\"] sections aren't allowed here", section, name),
+                    ));
+                }
+                named.push(NamedSection {
+                    section: section.to_owned(),
+                    name: name.to_owned(),
+                    directives: vec![],
+                });
+                in_named = true;
+                continue;
+            }
+            (&SystemdItem::Category(name), None) => {
+                section = Some(name);
+                in_named = false;
+                parsed.sections.push((name.to_owned(), vec![]));
+            }
+            (&SystemdItem::Directive(key, value), _) if in_named => {
+                if let Some(last) = named.last_mut() {
+                    last.directives
+                        .push((key.to_owned(), value.map(|v| v.to_owned())));
+                }
+                continue;
+            }
+            (&SystemdItem::Directive(key, value), _) => {
+                let section = section.ok_or_else(|| {
+                    line.error(
+                        column,
+                        Some(key),
+                        "directives must come after a section header, such as [Test]".to_owned(),
+                    )
+                })?;
+                let first = *sections.entry(key).or_insert(section);
+                if first != section {
+                    return Err(line.error(
+                        column,
+                        Some(key),
+                        format!(
+                            "{} was already given in [{}], and can't also be in [{}]",
+                            key, first, section
+                        ),
+                    ));
+                }
+                if let Some((_, directives)) = parsed.sections.last_mut() {
+                    directives.push((key.to_owned(), value.map(|v| v.to_owned())));
+                }
+            }
+            (&SystemdItem::Comment(_), _) => (),
+        }
+    }
+    if sections.is_empty() {
+        return Err(SyntaxError::new("the file has no directives".to_owned()));
+    }
+    let unit = parsed.unit()?;
+    Ok((unit, parsed))
+}
+
+/// A line with any continuation lines joined on to it.
+struct LogicalLine {
+    text: String,
+
+    /// The number of characters in the text.
+    chars: usize,
+
+    /// Where each joined line starts in the text, in characters, and its
+    /// line number in the file.
+    pieces: Vec<(usize, u32)>,
+}
+
+impl LogicalLine {
+    /// An error at a character offset into the text.
+    fn error(&self, offset: usize, directive: Option<&str>, message: String) -> SyntaxError {
+        let (start, line) = self
+            .pieces
+            .iter()
+            .rev()
+            .find(|&&(start, _)| start <= offset)
+            .cloned()
+            .unwrap_or((0, 0));
+        SyntaxError {
+            location: Some(SourceLocation {
+                line,
+                column: (offset - start + 1).min(u32::MAX as usize) as u32,
+                directive: directive.map(quote),
+            }),
+            message,
+        }
+    }
+}
+
+/// Split a file into lines, dropping any byte order mark and CR before each
+/// LF, and joining lines that end in a backslash to the line after them.
+fn logical_lines(contents: &str) -> Vec<LogicalLine> {
+    let contents = contents.strip_prefix('\u{feff}').unwrap_or(contents);
+    let mut lines = vec![];
+    let mut current: Option<LogicalLine> = None;
+    for (index, line) in contents.split('\n').enumerate() {
+        let number = (index + 1).min(u32::MAX as usize) as u32;
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let (text, continued) = match line.strip_suffix('\\') {
+            Some(text) => (text, true),
+            None => (line, false),
+        };
+        let logical = current.get_or_insert_with(|| LogicalLine {
+            text: String::new(),
+            chars: 0,
+            pieces: vec![],
+        });
+        logical.pieces.push((logical.chars, number));
+        logical.text.push_str(text);
+        logical.chars += text.chars().count();
+        if !continued {
+            lines.extend(current.take());
+        }
+    }
+    lines.extend(current);
+    lines
+}
+
+/// Reads through a line a character at a time, keeping track of the column.
+struct Cursor<'a> {
+    rest: &'a str,
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<char> {
+        self.rest.chars().next()
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, accept: F) -> &'a str {
+        let end = self
+            .rest
+            .char_indices()
+            .find(|&(_, c)| !accept(c))
+            .map(|(pos, _)| pos)
+            .unwrap_or(self.rest.len());
+        let (taken, rest) = self.rest.split_at(end);
+        self.offset += taken.chars().count();
+        self.rest = rest;
+        taken
+    }
+
+    fn bump(&mut self) {
+        let mut chars = self.rest.chars();
+        if chars.next().is_some() {
+            self.offset += 1;
+        }
+        self.rest = chars.as_str();
+    }
+}
+
+fn is_section_char(c: char) -> bool {
+    c.is_alphabetic() || c == '-'
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_alphabetic() || c == '!' || c == '|' || c == '@'
+}
+
+fn is_value_char(c: char) -> bool {
+    c != '#' && c != '\r' && c != '\n'
+}
+
+/// A character, in quotes, with anything invisible escaped.
+fn describe(c: char) -> String {
+    format!("'{}'", c.escape_debug())
+}
+
+/// A directive name to put in an error, cut short if it's very long.
+fn quote(name: &str) -> String {
+    if name.chars().count() > MAX_QUOTED_CHARS {
+        format!(
+            "{}...",
+            name.chars().take(MAX_QUOTED_CHARS).collect::<String>()
+        )
+    } else {
+        name.to_owned()
+    }
+}
+
+/// Parse one line, returning what's on it, the name of a named section if it
+/// starts one, and the column it starts at, or None if it's blank.
+#[allow(clippy::type_complexity)]
+fn parse_line(
+    line: &LogicalLine,
+) -> Result<Option<(SystemdItem<'_>, Option<&str>, usize)>, SyntaxError> {
+    if line.text.trim().is_empty() {
+        return Ok(None);
+    }
+    let mut cursor = Cursor {
+        rest: &line.text,
+        offset: 0,
+    };
+    cursor.take_while(|c| c == ' ' || c == '\t');
+    let start = cursor.offset;
+
+    match cursor.peek() {
+        Some('#') => {
+            cursor.bump();
+            Ok(Some((
+                SystemdItem::Comment(cursor.rest.trim()),
+                None,
+                start,
+            )))
+        }
+        Some('[') => {
+            cursor.bump();
+            cursor.take_while(|c| c == ' ');
+            let name = cursor.take_while(is_section_char);
+            if name.is_empty() {
+                return Err(match cursor.peek() {
+                    Some(c) if c != ']' => line.error(
+                        cursor.offset,
+                        None,
+                        format!("{} isn't allowed in a section name", describe(c)),
+                    ),
+                    _ => line.error(cursor.offset, None, "missing section name".to_owned()),
+                });
+            }
+            cursor.take_while(|c| c == ' ');
+            let mut section_name = None;
+            if cursor.peek() == Some('"') {
+                cursor.bump();
+                let quoted = cursor.take_while(|c| c != '"');
+                if cursor.peek() != Some('"') {
+                    return Err(line.error(
+                        cursor.offset,
+                        None,
+                        "missing '\"' at the end of the section's name".to_owned(),
+                    ));
+                }
+                if quoted.trim().is_empty() {
+                    return Err(line.error(cursor.offset, None, "empty section name".to_owned()));
+                }
+                cursor.bump();
+                cursor.take_while(|c| c == ' ');
+                section_name = Some(quoted);
+            }
+            match cursor.peek() {
+                Some(']') => cursor.bump(),
+                Some(c) => {
+                    return Err(line.error(
+                        cursor.offset,
+                        None,
+                        format!("{} isn't allowed in a section name", describe(c)),
+                    ))
+                }
+                None => {
+                    return Err(line.error(
+                        cursor.offset,
+                        None,
+                        "missing ']' at the end of the section header".to_owned(),
+                    ))
+                }
+            }
+            cursor.take_while(|c| c == ' ' || c == '\t');
+            match cursor.peek() {
+                Some(c) => Err(line.error(
+                    cursor.offset,
+                    None,
+                    format!("unexpected {} after the section header", describe(c)),
+                )),
+                None => Ok(Some((SystemdItem::Category(name), section_name, start))),
+            }
+        }
+        _ => {
+            // The whole name as written, for errors about it.
+            let written = cursor
+                .rest
+                .split(|c: char| c == '=' || c.is_whitespace())
+                .next()
+                .filter(|name| !name.is_empty());
+            let key = cursor.take_while(is_key_char);
+            let c = cursor.peek().unwrap_or('=');
+            if key.is_empty() {
+                return Err(line.error(
+                    cursor.offset,
+                    written,
+                    format!("expected a directive name, found {}", describe(c)),
+                ));
+            }
+            cursor.take_while(|c| c == ' ');
+            match cursor.peek() {
+                Some('=') => cursor.bump(),
+                Some(c) if written == Some(key) => {
+                    return Err(line.error(
+                        cursor.offset,
+                        Some(key),
+                        format!(
+                            "expected '=' after the directive name, found {}",
+                            describe(c)
+                        ),
+                    ))
+                }
+                Some(c) => {
+                    return Err(line.error(
+                        cursor.offset,
+                        written,
+                        format!("{} isn't allowed in a directive name", describe(c)),
+                    ))
+                }
+                None => {
+                    return Err(line.error(
+                        cursor.offset,
+                        Some(key),
+                        "expected '=' after the directive name".to_owned(),
+                    ))
+                }
+            }
+            cursor.take_while(|c| c == ' ');
+            let value = cursor.take_while(is_value_char);
+            match cursor.peek() {
+                Some('#') => Err(line.error(
+                    cursor.offset,
+                    Some(key),
+                    "'#' isn't allowed in a value, and comments must be on a line of their own"
+                        .to_owned(),
+                )),
+                Some(c) => Err(line.error(
+                    cursor.offset,
+                    Some(key),
+                    format!("{} isn't allowed in a value", describe(c)),
+                )),
+                None => Ok(Some((
+                    SystemdItem::Directive(key, Some(value).filter(|v| !v.is_empty())),
+                    None,
+                    start,
+                ))),
+            }
+        }
+    }
+}
@@ -1,37 +1,81 @@
 // The UnitLibrary contains plans to load each valid Unit.  Units may
 // not actually be selected, e.g. if they aren't compatible.
+extern crate systemd_parser;
 
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use self::systemd_parser::items::DirectiveEntry;
 
 use config::Config;
-use unit::{UnitKind, UnitName};
-use unitbroadcaster::{UnitBroadcaster, UnitCategoryEvent, UnitEvent, UnitStatus, UnitStatusEvent};
-use unitmanager::UnitManager;
+use duration;
+use poison::Lock;
+use schema::{self, ValueType};
+use snapshot::{self, Snapshot};
+use unit::{
+    ErrorCode, UnitDescriptionError, UnitFailure, UnitIncompatibleReason, UnitKind, UnitName,
+};
+use unitbroadcaster::{
+    LogEntry, UnitBroadcaster, UnitCategoryEvent, UnitEvent, UnitStatus, UnitStatusEvent,
+};
+use unitcache::{self, SharedUnitCache, UnitCache};
+use unitfile::ParsedFile;
+use unitmanager::{
+    ManagerControlMessage, ManagerControlMessageContents, ManagerStatusMessage, UnitManager,
+};
+use units::allocator::AllocatorDescription;
 use units::interface::InterfaceDescription;
 use units::jig::JigDescription;
 use units::logger::LoggerDescription;
+use units::notifier::NotifierDescription;
 use units::scenario::ScenarioDescription;
 use units::test::TestDescription;
 use units::trigger::TriggerDescription;
+use version;
 
 macro_rules! process_if {
     ($slf:ident, $name:ident, $status:ident, $tstkind:path, $path:ident, $trgt:ident, $desc:ident) => {
         if $name.kind() == &$tstkind {
             // Add the unit name to a list of "dirty units" that will be checked during "rescan()"
             $slf.mark_dirty($name);
-            match $trgt::from_path($path) {
-                Err(e) => {
-                    let status = UnitStatus::LoadFailed(format!("{}", e));
+            $slf.unit_paths
+                .borrow_mut()
+                .insert($name.clone(), $path.to_owned());
+            let description = $slf.read_unit_file($name, $path).and_then(|parsed| {
+                $trgt::from_parsed(&parsed, $name.clone(), $path)
+                    .and_then(|d| $slf.check_durations($name, &parsed).map(|_| d))
+            });
+            let disabled_reason = match description {
+                Ok(ref d) => $slf.disabled_reason($name, $path, d.is_disabled()),
+                Err(_) => None,
+            };
+            match (description, disabled_reason) {
+                (Err(e), _) => {
+                    let status = UnitStatus::LoadFailed(e.to_failure());
 
                     $slf.broadcaster.broadcast(&UnitEvent::Status(
-                        UnitStatusEvent::new_load_failed($name, format!("{}", e)),
+                        UnitStatusEvent::new_load_failed($name, e.to_failure()),
                     ));
                     // Add an entry to the status to report unit failure.
                     $slf.unit_status.borrow_mut().insert($name.clone(), status);
                 }
-                Ok(description) => {
+                (Ok(_), Some(reason)) => {
+                    // Forget the description, so rescan() will unload the unit.
+                    $slf.$desc.borrow_mut().remove($name);
+                    $slf.broadcaster
+                        .broadcast(&UnitEvent::Status(UnitStatusEvent::new_disabled(
+                            $name,
+                            reason.clone(),
+                        )));
+                    $slf.unit_status
+                        .borrow_mut()
+                        .insert($name.clone(), UnitStatus::Disabled(reason));
+                }
+                (Ok(description), None) => {
                     // Insert it into the description table
                     $slf.$desc.borrow_mut().insert($name.clone(), description);
 
@@ -75,21 +119,20 @@ macro_rules! load_units_for_activation {
                 }
                 let description = description.unwrap();
 
-                $slf.unit_manager.borrow_mut().unload(id);
+                $slf.unit_manager.borrow().unload(id);
 
                 match status {
-                    &UnitStatus::LoadStarted(_) => {
-                        $slf.unit_manager.borrow_mut().$load(description)
-                    }
-                    &UnitStatus::UpdateStarted(_) => {
-                        $slf.unit_manager.borrow_mut().$load(description)
-                    }
+                    &UnitStatus::LoadStarted(_) => $slf.unit_manager.borrow().$load(description),
+                    &UnitStatus::UpdateStarted(_) => $slf.unit_manager.borrow().$load(description),
+                    &UnitStatus::Incompatible(_) => $slf.unit_manager.borrow().$load(description),
                     x => panic!("Unexpected unit status: {}", x),
                 }
             };
 
+            // Keep the description, so it's tried again when whatever it
+            // lacked changes.
             if let Err(e) = load_result {
-                $statuses.insert(id.clone(), UnitStatus::LoadFailed(format!("{}", e)));
+                $statuses.insert(id.clone(), UnitStatus::Incompatible(e.to_string()));
                 to_remove.push(id.clone());
             }
         }
@@ -103,8 +146,8 @@ macro_rules! load_units_for_activation {
 macro_rules! select_and_activate_units {
     ($slf:ident, $dirty:ident) => {{
         for (id, _) in $slf.$dirty.borrow().iter() {
-            $slf.unit_manager.borrow_mut().select(id);
-            $slf.unit_manager.borrow_mut().activate(id);
+            $slf.unit_manager.borrow().select(id);
+            $slf.unit_manager.borrow().activate(id);
         }
         $slf.$dirty.borrow_mut().clear();
     }};
@@ -119,6 +162,13 @@ macro_rules! load_units {
 
 pub struct UnitLibrary {
     broadcaster: UnitBroadcaster,
+    cfg: Arc<Mutex<Config>>,
+
+    /// The path to each unit file, used to reload units when they are enabled or disabled.
+    unit_paths: RefCell<HashMap<UnitName, PathBuf>>,
+
+    /// What each unit file was parsed into, so unchanged files aren't parsed again.
+    unit_cache: SharedUnitCache,
 
     /// The unit status is used to determine whether to reload units or not.
     unit_status: RefCell<HashMap<UnitName, UnitStatus>>,
@@ -132,12 +182,24 @@ pub struct UnitLibrary {
     /// Currently available logger descriptions.
     logger_descriptions: RefCell<HashMap<UnitName, LoggerDescription>>,
 
+    /// Currently available notifier descriptions.
+    notifier_descriptions: RefCell<HashMap<UnitName, NotifierDescription>>,
+    /// Currently available allocator descriptions.
+    allocator_descriptions: RefCell<HashMap<UnitName, AllocatorDescription>>,
+
     /// Currently available scenario descriptions.  The scenarios they describe might not be valid.
     scenario_descriptions: RefCell<HashMap<UnitName, ScenarioDescription>>,
 
+    /// Scenario descriptions merged with the scenarios they inherit from, ready to be loaded.
+    merged_scenario_descriptions: RefCell<HashMap<UnitName, ScenarioDescription>>,
+
     /// Currently available test descriptions.  The tests they describe might not be valid.
     test_descriptions: RefCell<HashMap<UnitName, TestDescription>>,
 
+    /// With LazyTests, the tests that haven't been parsed yet, since nothing
+    /// has needed them.
+    lazy_tests: RefCell<HashMap<UnitName, PathBuf>>,
+
     /// Currently available trigger descriptions.  The triggers they describe might not be valid.
     trigger_descriptions: RefCell<HashMap<UnitName, TriggerDescription>>,
 
@@ -145,6 +207,8 @@ pub struct UnitLibrary {
     dirty_interfaces: RefCell<HashMap<UnitName, ()>>,
     dirty_jigs: RefCell<HashMap<UnitName, ()>>,
     dirty_loggers: RefCell<HashMap<UnitName, ()>>,
+    dirty_notifiers: RefCell<HashMap<UnitName, ()>>,
+    dirty_allocators: RefCell<HashMap<UnitName, ()>>,
     dirty_scenarios: RefCell<HashMap<UnitName, ()>>,
     dirty_tests: RefCell<HashMap<UnitName, ()>>,
     dirty_triggers: RefCell<HashMap<UnitName, ()>>,
@@ -157,18 +221,27 @@ impl UnitLibrary {
     pub fn new(broadcaster: &UnitBroadcaster, config: &Arc<Mutex<Config>>) -> Self {
         UnitLibrary {
             broadcaster: broadcaster.clone(),
+            cfg: config.clone(),
+            unit_paths: RefCell::new(HashMap::new()),
+            unit_cache: UnitCache::new_shared(),
             unit_status: RefCell::new(HashMap::new()),
 
             interface_descriptions: RefCell::new(HashMap::new()),
             jig_descriptions: RefCell::new(HashMap::new()),
             logger_descriptions: RefCell::new(HashMap::new()),
+            notifier_descriptions: RefCell::new(HashMap::new()),
+            allocator_descriptions: RefCell::new(HashMap::new()),
             scenario_descriptions: RefCell::new(HashMap::new()),
+            merged_scenario_descriptions: RefCell::new(HashMap::new()),
             test_descriptions: RefCell::new(HashMap::new()),
+            lazy_tests: RefCell::new(HashMap::new()),
             trigger_descriptions: RefCell::new(HashMap::new()),
 
             dirty_interfaces: RefCell::new(HashMap::new()),
             dirty_jigs: RefCell::new(HashMap::new()),
             dirty_loggers: RefCell::new(HashMap::new()),
+            dirty_notifiers: RefCell::new(HashMap::new()),
+            dirty_allocators: RefCell::new(HashMap::new()),
             dirty_scenarios: RefCell::new(HashMap::new()),
             dirty_tests: RefCell::new(HashMap::new()),
             dirty_triggers: RefCell::new(HashMap::new()),
@@ -183,6 +256,8 @@ impl UnitLibrary {
             UnitKind::Interface => self.dirty_interfaces.borrow_mut().insert(name.clone(), ()),
             UnitKind::Jig => self.dirty_jigs.borrow_mut().insert(name.clone(), ()),
             UnitKind::Logger => self.dirty_loggers.borrow_mut().insert(name.clone(), ()),
+            UnitKind::Notifier => self.dirty_notifiers.borrow_mut().insert(name.clone(), ()),
+            UnitKind::Allocator => self.dirty_allocators.borrow_mut().insert(name.clone(), ()),
             UnitKind::Scenario => self.dirty_scenarios.borrow_mut().insert(name.clone(), ()),
             UnitKind::Test => self.dirty_tests.borrow_mut().insert(name.clone(), ()),
             UnitKind::Trigger => self.dirty_triggers.borrow_mut().insert(name.clone(), ()),
@@ -190,6 +265,371 @@ impl UnitLibrary {
         };
     }
 
+    /// Determine whether a unit has been disabled, and if so, why.  Units may be
+    /// disabled by a Disabled= directive, by a "<unit>.disabled" marker file next
+    /// to the unit file, or at runtime with a DISABLE command.
+    /// With StrictDurations, refuse units that give a duration as a bare
+    /// number, since it's not clear whether seconds or milliseconds were meant.
+    fn check_durations(
+        &self,
+        name: &UnitName,
+        parsed: &ParsedFile,
+    ) -> Result<(), UnitDescriptionError> {
+        if !self.cfg.locked().strict_durations() {
+            return Ok(());
+        }
+        let schema = match schema::unit_schema(name.kind()) {
+            Some(schema) => schema,
+            None => return Ok(()),
+        };
+        let unit_file = parsed.unit()?;
+        for directive in &schema.directives {
+            if directive.value_type != ValueType::Duration {
+                continue;
+            }
+            let value = match unit_file.lookup_by_key(directive.name) {
+                Some(DirectiveEntry::Solo(entry)) if entry.category() == schema.section => {
+                    entry.value()
+                }
+                _ => None,
+            };
+            if let Some(value) = value {
+                if duration::parse_strict(value).is_err() {
+                    return Err(UnitDescriptionError::InvalidValue(
+                        schema.section.to_owned(),
+                        directive.name.to_owned(),
+                        value.to_owned(),
+                        vec!["a duration with a unit, such as 90s, 5m, or 1h30m".to_owned()],
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// With LazyTests, put off parsing a test until a scenario or another test
+    /// needs it, or it's started.  Returns true if it was put off.
+    fn defer_test(&self, name: &UnitName, path: &Path) -> bool {
+        if *name.kind() != UnitKind::Test
+            || !self.cfg.locked().lazy_tests()
+            || self.test_descriptions.borrow().contains_key(name)
+        {
+            return false;
+        }
+        self.unit_paths
+            .borrow_mut()
+            .insert(name.clone(), path.to_owned());
+        self.lazy_tests
+            .borrow_mut()
+            .insert(name.clone(), path.to_owned());
+        true
+    }
+
+    /// Parse a test that was put off.
+    fn load_lazy_test(&self, name: &UnitName) {
+        let path = match self.lazy_tests.borrow_mut().remove(name) {
+            Some(path) => path,
+            None => return,
+        };
+        let status = UnitStatus::LoadStarted(path.clone());
+        let path = path.as_path();
+        process_if!(
+            self,
+            name,
+            status,
+            UnitKind::Test,
+            path,
+            TestDescription,
+            test_descriptions
+        );
+    }
+
+    /// Parse every test that was put off and is now needed: those that a
+    /// scenario uses, unless it's only for jigs that aren't here, and those
+    /// that a parsed test Requires or Suggests, until there are no more.
+    fn load_wanted_tests(&self) {
+        if self.lazy_tests.borrow().is_empty() {
+            return;
+        }
+        let jigs: Vec<UnitName> = self.jig_descriptions.borrow().keys().cloned().collect();
+        let ids: Vec<UnitName> = self
+            .scenario_descriptions
+            .borrow()
+            .keys()
+            .cloned()
+            .collect();
+        let scenarios: Vec<ScenarioDescription> = ids
+            .iter()
+            .filter_map(|id| self.merge_scenario(id).ok())
+            .filter(|s| s.jigs().is_empty() || jigs.iter().any(|j| s.supports_jig(j)))
+            .collect();
+        loop {
+            let wanted: Vec<UnitName> = self
+                .lazy_tests
+                .borrow()
+                .keys()
+                .filter(|name| {
+                    scenarios.iter().any(|s| s.refers_to_test(name))
+                        || self
+                            .test_descriptions
+                            .borrow()
+                            .values()
+                            .any(|t| t.uses_test(name))
+                })
+                .cloned()
+                .collect();
+            if wanted.is_empty() {
+                break;
+            }
+            for name in wanted {
+                self.load_lazy_test(&name);
+            }
+        }
+    }
+
+    /// Read a unit file, using what it was parsed into last time if it hasn't
+    /// changed since.
+    fn read_unit_file(
+        &self,
+        name: &UnitName,
+        path: &Path,
+    ) -> Result<ParsedFile, UnitDescriptionError> {
+        let mut cache = self.unit_cache.locked();
+        cache.use_directory(self.cfg.locked().state_directory());
+        cache.read(path, unitcache::named_sections(name.kind()))
+    }
+
+    /// The cache of parsed unit files, to share with a UnitWatcher so that
+    /// it can parse the files it finds ahead of time.  It's read in from the
+    /// state directory first, so that files that haven't changed since the
+    /// last run aren't parsed at all.
+    pub fn unit_cache(&self) -> SharedUnitCache {
+        self.unit_cache
+            .locked()
+            .use_directory(self.cfg.locked().state_directory());
+        self.unit_cache.clone()
+    }
+
+    /// How many unit files were read from the unit cache rather than parsed.
+    pub fn unit_cache_hits(&self) -> usize {
+        self.unit_cache.locked().hits()
+    }
+
+    /// Save the unit cache, keeping only the unit files that are still there.
+    fn save_unit_cache(&self) {
+        let paths: HashSet<PathBuf> = self
+            .unit_paths
+            .borrow()
+            .values()
+            .filter(|path| path.exists())
+            .cloned()
+            .collect();
+        let saved = self.unit_cache.locked().save(&paths);
+        if let Err(e) = saved {
+            self.broadcaster
+                .broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    UnitName::internal("unit-library"),
+                    format!("unable to save the unit cache: {}", e),
+                )));
+        }
+    }
+
+    /// Gather up how the station is set up: every unit file, including
+    /// tests that were put off, the settings, and how each test has done.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        snapshot.add(
+            "version.txt",
+            format!("{}\n", version::fields().replace('\t', "\n")).as_bytes(),
+        );
+        {
+            let cfg = self.cfg.locked();
+            snapshot.add("config/effective.conf", cfg.effective().as_bytes());
+            if let Some(ref path) = *cfg.config_file() {
+                if let (Some(name), Ok(contents)) = (path.file_name(), fs::read(path)) {
+                    snapshot.add(Path::new("config").join(name), &contents);
+                }
+            }
+        }
+
+        let manager = self.unit_manager.borrow();
+        let states = manager.unit_states();
+        let mut units: Vec<(UnitName, PathBuf)> = self
+            .unit_paths
+            .borrow()
+            .iter()
+            .map(|(name, path)| (name.clone(), path.clone()))
+            .collect();
+        units.sort();
+        let mut listing = String::new();
+        for (name, path) in units {
+            let state = match states.get(&name) {
+                Some(state) => state.to_string(),
+                None if self.lazy_tests.borrow().contains_key(&name) => "deferred".to_owned(),
+                None => "unloaded".to_owned(),
+            };
+            listing.push_str(&format!("{}\t{}\t{}\n", name, state, path.display()));
+            // Files removed since they were loaded are only listed.
+            if let Ok(contents) = fs::read(&path) {
+                snapshot.add(Path::new("units").join(name.to_string()), &contents);
+            }
+        }
+        snapshot.add("units.txt", listing.as_bytes());
+
+        let mut stats = String::new();
+        for test in manager.test_stats() {
+            stats.push_str(&format!(
+                "{}\truns={}\tpassed={}\tfailed={}\tfaults={}\tskipped={}\tflakiness={}\twindow={}\n",
+                test.test.id(),
+                test.runs,
+                test.passed,
+                test.failed,
+                test.faults,
+                test.skipped,
+                test.flakiness,
+                test.window
+            ));
+        }
+        snapshot.add("stats.txt", stats.as_bytes());
+        snapshot
+    }
+
+    /// Save a snapshot for an interface that asked for one with SNAPSHOT,
+    /// in the state directory if there is one, and tell it where it went.
+    fn send_snapshot_to(&self, sender: &UnitName) {
+        let directory = match self.cfg.locked().state_directory() {
+            Some(dir) => dir.join(snapshot::SNAPSHOTS_DIRECTORY),
+            None => std::env::temp_dir(),
+        };
+        let path = directory.join(snapshot::file_name(SystemTime::now()));
+        let manager = self.unit_manager.borrow();
+        match self.snapshot().write(&path) {
+            Ok(()) => {
+                self.broadcaster
+                    .broadcast(&UnitEvent::Log(LogEntry::new_info(
+                        sender.clone(),
+                        format!("saved a snapshot to {}", path.display()),
+                    )));
+                manager.send_messages_to(sender, vec![ManagerStatusMessage::Snapshot(path)]);
+            }
+            Err(e) => {
+                let message = format!("unable to save a snapshot to {}: {}", path.display(), e);
+                self.broadcaster
+                    .broadcast(&UnitEvent::Log(LogEntry::new_error(
+                        sender.clone(),
+                        message.clone(),
+                    )));
+                manager.send_messages_to(
+                    sender,
+                    vec![ManagerStatusMessage::UnitFailed(
+                        sender.clone(),
+                        UnitFailure::new("snapshot-failed", message),
+                    )],
+                );
+            }
+        }
+    }
+
+    fn disabled_reason(&self, name: &UnitName, path: &Path, directive: bool) -> Option<String> {
+        if directive {
+            return Some("disabled in unit file".to_owned());
+        }
+        let marker = disabled_marker_path(path);
+        if marker.exists() {
+            return Some(format!("marker file {} present", marker.to_string_lossy()));
+        }
+        if self.cfg.locked().unit_disabled(name) {
+            return Some("disabled at runtime".to_owned());
+        }
+        None
+    }
+
+    /// Disable or enable a unit at runtime, then reload it so the change takes effect.
+    fn set_unit_disabled(&self, sender: &UnitName, name: &UnitName, disabled: bool) {
+        let verb = if disabled { "disable" } else { "enable" };
+        if let Err(e) = self.cfg.locked().set_unit_disabled(name, disabled) {
+            self.broadcaster
+                .broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender.clone(),
+                    format!("unable to save state after {} of {}: {}", verb, name, e),
+                )));
+        }
+
+        match self.unit_paths.borrow().get(name) {
+            None => self
+                .broadcaster
+                .broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender.clone(),
+                    format!("unable to {} {}: unit not found", verb, name),
+                ))),
+            Some(path) => {
+                self.broadcaster
+                    .broadcast(&UnitEvent::Status(UnitStatusEvent {
+                        name: name.clone(),
+                        status: UnitStatus::Updated(path.clone()),
+                    }));
+            }
+        }
+    }
+
+    /// The scenario a scenario inherits from, if any.  It may be named by an
+    /// old name, from the parent's Alias.
+    fn scenario_parent(&self, description: &ScenarioDescription) -> Option<UnitName> {
+        let descriptions = self.scenario_descriptions.borrow();
+        let renamed = |n: &UnitName| descriptions.values().find(|d| d.aliases().contains(n));
+        description.inherits().as_ref().map(|parent| {
+            let parent = parent.resolve(description.id().namespace(), |n| {
+                descriptions.contains_key(n) || renamed(n).is_some()
+            });
+            if descriptions.contains_key(&parent) {
+                return parent;
+            }
+            match renamed(&parent) {
+                Some(target) => {
+                    self.unit_manager.borrow().warn_deprecated(
+                        &parent,
+                        target.id(),
+                        description.id(),
+                    );
+                    target.id().clone()
+                }
+                None => parent,
+            }
+        })
+    }
+
+    /// Merge a scenario with every scenario it inherits from.
+    fn merge_scenario(&self, id: &UnitName) -> Result<ScenarioDescription, UnitIncompatibleReason> {
+        let descriptions = self.scenario_descriptions.borrow();
+        let mut merged = match descriptions.get(id) {
+            Some(d) => d.clone(),
+            None => {
+                return Err(UnitIncompatibleReason::InvalidInheritance(format!(
+                    "{} not found",
+                    id
+                )))
+            }
+        };
+        let mut seen = vec![id.clone()];
+        while let Some(parent_id) = self.scenario_parent(&merged) {
+            if seen.contains(&parent_id) {
+                return Err(UnitIncompatibleReason::InvalidInheritance(format!(
+                    "{} inherits from itself",
+                    parent_id
+                )));
+            }
+            let parent = descriptions.get(&parent_id).ok_or_else(|| {
+                UnitIncompatibleReason::InvalidInheritance(format!(
+                    "{} is not available",
+                    parent_id
+                ))
+            })?;
+            merged = merged.inherit(parent);
+            seen.push(parent_id);
+        }
+        Ok(merged)
+    }
+
     /// Examine all of the loaded units and ensure they can be loaded.
     ///
     /// Each unit type must be handled differently.
@@ -198,7 +638,7 @@ impl UnitLibrary {
     ///    That way, they will be rescanned.
     /// 2. Mark every Scenario that uses a dirty Test as dirty.
     ///    That way, scenario dependency graphs will be re-evaluated.
-    /// 3. Delete any "dirty" objects that were Deleted.
+    /// 3. Delete any "dirty" objects that were Deleted or Disabled.
     /// 4. Select all Jigs that are valid.
     /// 5. Select all Interfaces that are valid.
     /// 6. Select all Tests that are compatible with this Jig.
@@ -207,6 +647,10 @@ impl UnitLibrary {
     /// 9. Activate all Interfaces.
     pub fn rescan(&self) {
         self.broadcaster.broadcast(&UnitEvent::RescanStart);
+
+        // 0. Parse any tests that were put off and are now needed.
+        self.load_wanted_tests();
+
         let mut statuses = self.unit_status.borrow_mut();
 
         // 1. Go through jigs and mark dependent scenarios and tests as dirty.
@@ -244,6 +688,24 @@ impl UnitLibrary {
                 }
             }
 
+            for (notifier_name, notifier_description) in self.notifier_descriptions.borrow().iter()
+            {
+                if notifier_description.supports_jig(jig_name) {
+                    self.dirty_notifiers
+                        .borrow_mut()
+                        .insert(notifier_name.clone(), ());
+                }
+            }
+            for (allocator_name, allocator_description) in
+                self.allocator_descriptions.borrow().iter()
+            {
+                if allocator_description.supports_jig(jig_name) {
+                    self.dirty_allocators
+                        .borrow_mut()
+                        .insert(allocator_name.clone(), ());
+                }
+            }
+
             for (trigger_name, trigger_description) in self.trigger_descriptions.borrow().iter() {
                 if trigger_description.supports_jig(jig_name) {
                     self.dirty_triggers
@@ -253,8 +715,27 @@ impl UnitLibrary {
             }
         }
 
-        // 2. Go through tests and mark scenarios as dirty.
+        // 2. Go through tests and mark scenarios as dirty, including ones
+        //    whose Tests= patterns would now expand differently, and ones
+        //    that couldn't be loaded, which may have lacked these tests.
+        if !self.dirty_tests.borrow().is_empty() {
+            for (id, status) in statuses.iter() {
+                if *id.kind() == UnitKind::Scenario && matches!(status, UnitStatus::Incompatible(_))
+                {
+                    self.dirty_scenarios.borrow_mut().insert(id.clone(), ());
+                }
+            }
+        }
         for (test_name, _) in self.dirty_tests.borrow().iter() {
+            for (scenario_name, scenario_description) in self.scenario_descriptions.borrow().iter()
+            {
+                if scenario_description.matches_test(test_name) {
+                    self.dirty_scenarios
+                        .borrow_mut()
+                        .insert(scenario_name.clone(), ());
+                }
+            }
+
             let unit_manager = self.unit_manager.borrow();
             let scenarios_rc = unit_manager.get_scenarios();
             let scenarios = scenarios_rc.borrow();
@@ -267,7 +748,28 @@ impl UnitLibrary {
             }
         }
 
-        // 3. Delete any "dirty" objects that were Deleted.
+        // 2a. Mark every Scenario that inherits from a dirty Scenario as dirty.
+        loop {
+            let children: Vec<UnitName> = self
+                .scenario_descriptions
+                .borrow()
+                .iter()
+                .filter(|(id, _)| !self.dirty_scenarios.borrow().contains_key(id))
+                .filter(|(_, d)| match self.scenario_parent(d) {
+                    Some(ref parent) => self.dirty_scenarios.borrow().contains_key(parent),
+                    None => false,
+                })
+                .map(|(id, _)| id.clone())
+                .collect();
+            if children.is_empty() {
+                break;
+            }
+            for child in children {
+                self.dirty_scenarios.borrow_mut().insert(child, ());
+            }
+        }
+
+        // 3. Delete any "dirty" objects that were Deleted or Disabled.
         {
             let mut to_remove = vec![];
             for (id, _) in self.dirty_jigs.borrow().iter() {
@@ -275,9 +777,11 @@ impl UnitLibrary {
                     .get(id)
                     .expect("Unable to find dirty jig in status list")
                 {
-                    UnitStatus::UnloadStarted(_) | UnitStatus::LoadFailed(_) => {
+                    UnitStatus::UnloadStarted(_)
+                    | UnitStatus::LoadFailed(_)
+                    | UnitStatus::Disabled(_) => {
                         self.jig_descriptions.borrow_mut().remove(id);
-                        self.unit_manager.borrow_mut().unload(id);
+                        self.unit_manager.borrow().unload(id);
                         to_remove.push(id.clone());
                     }
                     _ => (),
@@ -288,9 +792,11 @@ impl UnitLibrary {
                     .get(id)
                     .expect("Unable to find dirty test in status list")
                 {
-                    UnitStatus::UnloadStarted(_) | UnitStatus::LoadFailed(_) => {
+                    UnitStatus::UnloadStarted(_)
+                    | UnitStatus::LoadFailed(_)
+                    | UnitStatus::Disabled(_) => {
                         self.test_descriptions.borrow_mut().remove(id);
-                        self.unit_manager.borrow_mut().unload(id);
+                        self.unit_manager.borrow().unload(id);
                         to_remove.push(id.clone());
                     }
                     _ => (),
@@ -301,9 +807,12 @@ impl UnitLibrary {
                     .get(id)
                     .expect("Unable to find dirty scenario in status list")
                 {
-                    UnitStatus::UnloadStarted(_) | UnitStatus::LoadFailed(_) => {
+                    UnitStatus::UnloadStarted(_)
+                    | UnitStatus::LoadFailed(_)
+                    | UnitStatus::Disabled(_) => {
                         self.scenario_descriptions.borrow_mut().remove(id);
-                        self.unit_manager.borrow_mut().unload(id);
+                        self.merged_scenario_descriptions.borrow_mut().remove(id);
+                        self.unit_manager.borrow().unload(id);
                         to_remove.push(id.clone());
                     }
                     _ => (),
@@ -314,9 +823,11 @@ impl UnitLibrary {
                     .get(id)
                     .expect("Unable to find dirty interface in status list")
                 {
-                    UnitStatus::UnloadStarted(_) | UnitStatus::LoadFailed(_) => {
+                    UnitStatus::UnloadStarted(_)
+                    | UnitStatus::LoadFailed(_)
+                    | UnitStatus::Disabled(_) => {
                         self.interface_descriptions.borrow_mut().remove(id);
-                        self.unit_manager.borrow_mut().unload(id);
+                        self.unit_manager.borrow().unload(id);
                         to_remove.push(id.clone());
                     }
                     _ => (),
@@ -328,9 +839,42 @@ impl UnitLibrary {
                     .get(id)
                     .expect("Unable to find dirty logger in status list")
                 {
-                    UnitStatus::UnloadStarted(_) | UnitStatus::LoadFailed(_) => {
+                    UnitStatus::UnloadStarted(_)
+                    | UnitStatus::LoadFailed(_)
+                    | UnitStatus::Disabled(_) => {
                         self.logger_descriptions.borrow_mut().remove(id);
-                        self.unit_manager.borrow_mut().unload(id);
+                        self.unit_manager.borrow().unload(id);
+                        to_remove.push(id.clone());
+                    }
+                    _ => (),
+                }
+            }
+
+            for (id, _) in self.dirty_notifiers.borrow().iter() {
+                match *statuses
+                    .get(id)
+                    .expect("Unable to find dirty notifier in status list")
+                {
+                    UnitStatus::UnloadStarted(_)
+                    | UnitStatus::LoadFailed(_)
+                    | UnitStatus::Disabled(_) => {
+                        self.notifier_descriptions.borrow_mut().remove(id);
+                        self.unit_manager.borrow().unload(id);
+                        to_remove.push(id.clone());
+                    }
+                    _ => (),
+                }
+            }
+            for (id, _) in self.dirty_allocators.borrow().iter() {
+                match *statuses
+                    .get(id)
+                    .expect("Unable to find dirty allocator in status list")
+                {
+                    UnitStatus::UnloadStarted(_)
+                    | UnitStatus::LoadFailed(_)
+                    | UnitStatus::Disabled(_) => {
+                        self.allocator_descriptions.borrow_mut().remove(id);
+                        self.unit_manager.borrow().unload(id);
                         to_remove.push(id.clone());
                     }
                     _ => (),
@@ -342,9 +886,11 @@ impl UnitLibrary {
                     .get(id)
                     .expect("Unable to find dirty trigger in status list")
                 {
-                    UnitStatus::UnloadStarted(_) | UnitStatus::LoadFailed(_) => {
+                    UnitStatus::UnloadStarted(_)
+                    | UnitStatus::LoadFailed(_)
+                    | UnitStatus::Disabled(_) => {
                         self.trigger_descriptions.borrow_mut().remove(id);
-                        self.unit_manager.borrow_mut().unload(id);
+                        self.unit_manager.borrow().unload(id);
                         to_remove.push(id.clone());
                     }
                     _ => (),
@@ -356,6 +902,8 @@ impl UnitLibrary {
                     UnitKind::Interface => self.dirty_interfaces.borrow_mut().remove(&id),
                     UnitKind::Jig => self.dirty_jigs.borrow_mut().remove(&id),
                     UnitKind::Logger => self.dirty_loggers.borrow_mut().remove(&id),
+                    UnitKind::Notifier => self.dirty_notifiers.borrow_mut().remove(&id),
+                    UnitKind::Allocator => self.dirty_allocators.borrow_mut().remove(&id),
                     UnitKind::Scenario => self.dirty_scenarios.borrow_mut().remove(&id),
                     UnitKind::Test => self.dirty_tests.borrow_mut().remove(&id),
                     UnitKind::Trigger => self.dirty_triggers.borrow_mut().remove(&id),
@@ -386,6 +934,24 @@ impl UnitLibrary {
             load_logger
         );
 
+        // 6a. Load all notifiers that are compatible with this Jig.
+        load_units_for_activation!(
+            self,
+            statuses,
+            dirty_notifiers,
+            notifier_descriptions,
+            load_notifier
+        );
+
+        // 6b. Load all allocators that are compatible with this Jig.
+        load_units_for_activation!(
+            self,
+            statuses,
+            dirty_allocators,
+            allocator_descriptions,
+            load_allocator
+        );
+
         // 7. Load all Triggers that are compatible with this Jig.
         load_units_for_activation!(
             self,
@@ -398,12 +964,34 @@ impl UnitLibrary {
         // 8. Load all Tests that are compatible with this Jig.
         load_units!(self, statuses, dirty_tests, test_descriptions, load_test);
 
-        // 9. Load all Scenarios that are compatible with this Jig.
+        // 9. Merge Scenarios with the ones they inherit from, and load all
+        //    Scenarios that are compatible with this Jig.
+        for (id, _) in self.dirty_scenarios.borrow().iter() {
+            if !statuses.contains_key(id) {
+                continue;
+            }
+            match self.merge_scenario(id) {
+                Ok(merged) => {
+                    self.merged_scenario_descriptions
+                        .borrow_mut()
+                        .insert(id.clone(), merged);
+                }
+                Err(e) => {
+                    // Keep the scenario's description, so it's merged again
+                    // when the scenario it inherits from changes.
+                    self.merged_scenario_descriptions.borrow_mut().remove(id);
+                    self.unit_manager.borrow().unload(id);
+                    self.broadcaster.broadcast(&UnitEvent::Status(
+                        UnitStatusEvent::new_load_failed(id, e.to_failure()),
+                    ));
+                }
+            }
+        }
         load_units!(
             self,
             statuses,
             dirty_scenarios,
-            scenario_descriptions,
+            merged_scenario_descriptions,
             load_scenario
         );
 
@@ -416,11 +1004,19 @@ impl UnitLibrary {
         // 11. Activate all loggers that were just loaded.
         select_and_activate_units!(self, dirty_loggers);
 
+        // 11a. Activate all notifiers that were just loaded.
+        select_and_activate_units!(self, dirty_notifiers);
+        // 11b. Activate all allocators that were just loaded.
+        select_and_activate_units!(self, dirty_allocators);
+
         // 12. Activate all triggers that were just loaded.
         select_and_activate_units!(self, dirty_triggers);
 
         // 13. Prepare any defaults that need loading (i.e. jigs, scenarios, etc.)
-        self.unit_manager.borrow_mut().refresh_defaults();
+        self.unit_manager.borrow().refresh_defaults();
+
+        // 14. Keep what the unit files were parsed into for next time.
+        self.save_unit_cache();
 
         self.broadcaster.broadcast(&UnitEvent::RescanFinish);
     }
@@ -428,12 +1024,10 @@ impl UnitLibrary {
     pub fn process_message(&self, evt: &UnitEvent) {
         match evt {
             UnitEvent::Status(ref msg) => {
-                let &UnitStatusEvent {
-                    ref name,
-                    ref status,
-                } = msg;
+                let UnitStatusEvent { name, status } = msg;
 
                 match status {
+                    UnitStatus::LoadStarted(ref path) if self.defer_test(name, path) => (),
                     UnitStatus::LoadStarted(ref path) => {
                         process_if!(
                             self,
@@ -480,6 +1074,24 @@ impl UnitLibrary {
                             TestDescription,
                             test_descriptions
                         );
+                        process_if!(
+                            self,
+                            name,
+                            status,
+                            UnitKind::Notifier,
+                            path,
+                            NotifierDescription,
+                            notifier_descriptions
+                        );
+                        process_if!(
+                            self,
+                            name,
+                            status,
+                            UnitKind::Allocator,
+                            path,
+                            AllocatorDescription,
+                            allocator_descriptions
+                        );
                         process_if!(
                             self,
                             name,
@@ -527,6 +1139,33 @@ impl UnitLibrary {
                             ScenarioDescription,
                             scenario_descriptions
                         );
+                        process_if!(
+                            self,
+                            name,
+                            status,
+                            UnitKind::Test,
+                            path,
+                            TestDescription,
+                            test_descriptions
+                        );
+                        process_if!(
+                            self,
+                            name,
+                            status,
+                            UnitKind::Notifier,
+                            path,
+                            NotifierDescription,
+                            notifier_descriptions
+                        );
+                        process_if!(
+                            self,
+                            name,
+                            status,
+                            UnitKind::Allocator,
+                            path,
+                            AllocatorDescription,
+                            allocator_descriptions
+                        );
                         process_if!(
                             self,
                             name,
@@ -537,6 +1176,8 @@ impl UnitLibrary {
                             trigger_descriptions
                         );
                     }
+                    UnitStatus::UnloadStarted(_)
+                        if self.lazy_tests.borrow_mut().remove(name).is_some() => {}
                     UnitStatus::UnloadStarted(ref path) => {
                         self.unit_status
                             .borrow_mut()
@@ -547,6 +1188,26 @@ impl UnitLibrary {
                 }
             }
             UnitEvent::RescanRequest => self.rescan(),
+            // A test that was put off is parsed as soon as it's started.
+            UnitEvent::ManagerRequest(ManagerControlMessage {
+                contents: ManagerControlMessageContents::StartTest(ref name),
+                ..
+            }) if self.lazy_tests.borrow().contains_key(name) => {
+                self.load_lazy_test(name);
+                self.rescan();
+            }
+            UnitEvent::ManagerRequest(ManagerControlMessage {
+                ref sender,
+                contents: ManagerControlMessageContents::DisableUnit(ref name),
+            }) => self.set_unit_disabled(sender, name, true),
+            UnitEvent::ManagerRequest(ManagerControlMessage {
+                ref sender,
+                contents: ManagerControlMessageContents::EnableUnit(ref name),
+            }) => self.set_unit_disabled(sender, name, false),
+            UnitEvent::ManagerRequest(ManagerControlMessage {
+                ref sender,
+                contents: ManagerControlMessageContents::Snapshot,
+            }) => self.send_snapshot_to(sender),
             _ => (),
         }
 
@@ -554,8 +1215,17 @@ impl UnitLibrary {
         self.unit_manager.borrow().process_message(evt);
     }
 
-    #[cfg(test)]
+    /// The manager that holds every loaded unit.  Programs that embed
+    /// exclave use it to look up units and to subscribe to events.
     pub fn get_manager(&self) -> &RefCell<UnitManager> {
         &self.unit_manager
     }
 }
+
+/// Return the path of the marker file that disables the unit at `path`,
+/// e.g. "foo.test.disabled" for "foo.test".
+fn disabled_marker_path(path: &Path) -> PathBuf {
+    let mut marker = path.as_os_str().to_owned();
+    marker.push(".disabled");
+    PathBuf::from(marker)
+}
@@ -24,6 +24,10 @@ impl UnitLoader {
             UnitEvent::Category(_) => (),
             UnitEvent::Log(_) => (),
             UnitEvent::ManagerRequest(_) => (),
+            UnitEvent::ConfigChanged(_) => (),
+            UnitEvent::ScenarioFinished(_) => (),
+            UnitEvent::RunNoted(_) => (),
+            UnitEvent::RunFinished(_) => (),
         }
     }
 
@@ -1,25 +1,70 @@
 // The UnitManager contains all units that are Selected.  This includes
 // units that are Active.
-use std::cell::RefCell;
-use std::collections::HashMap;
+extern crate dependy;
+extern crate humantime;
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::slice;
+use std::sync::atomic::AtomicBool;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
+use self::dependy::Dependency;
+use self::humantime::{format_duration, format_rfc3339_seconds};
+
+use certificate;
+use concurrency;
 use config::Config;
+use cost::{Cost, CostMeter};
+use estimate::{Estimate, Source};
+use flakiness::TestStats;
+use interlock::Interlock;
+use parameter::{self, Parameter};
+use poison::{self, Lock};
+use presentation::Presentation;
+use profile;
+use schema::{self, DirectiveSchema};
+use soak;
+use station::Station;
+use supervisor::{Decision, Restart, RestartPolicy, Supervisor};
+use timeline;
 use unit::{
-    UnitActivateError, UnitDeactivateError, UnitDeselectError, UnitIncompatibleReason, UnitKind,
-    UnitName, UnitSelectError,
+    ErrorCode, UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
+    UnitFailure, UnitIncompatibleReason, UnitKind, UnitName, UnitSelectError,
 };
-use unitbroadcaster::{LogEntry, UnitBroadcaster, UnitEvent, UnitStatus, UnitStatusEvent};
-use units::interface::{Interface, InterfaceDescription};
+use unitbroadcaster::{
+    LogEntry, ScenarioSummary, StampedEvent, UnitBroadcaster, UnitEvent, UnitStatus,
+    UnitStatusEvent,
+};
+use units::allocator::{Allocator, AllocatorDescription};
+use units::interface::{Framing, Interface, InterfaceDescription};
 use units::jig::{Jig, JigDescription};
 use units::logger::{Logger, LoggerDescription};
-use units::scenario::{Scenario, ScenarioDescription};
-use units::test::{Test, TestDescription};
-use units::trigger::{Trigger, TriggerDescription};
+use units::notifier::{Notifier, NotifierDescription};
+use units::scenario::{Resolution, ResolutionInputs, Scenario, ScenarioDescription};
+use units::test::{Test, TestDescription, TestVerdict};
+use units::trigger::{Route, Trigger, TriggerDescription};
+use unitstate::{
+    Active, Known, Loaded, Selected, Step, TypedState, UnitState, UnitTransition, Unloaded,
+    Unselected,
+};
+use version;
+
+/// Written into a run directory if the run started while the clock couldn't be trusted.
+const CLOCK_UNSYNCED_FILE: &str = "clock-unsynced";
+
+/// Operator notes about a run, one per line, are appended to this file in its run directory.
+const RUN_NOTES_FILE: &str = "notes.txt";
+
+/// Tests are given what the trigger that started their run saw in this variable.
+pub const PAYLOAD_VARIABLE: &str = "EXCLAVE_PAYLOAD";
 
 macro_rules! load {
     ($slf:ident, $dest:ident, $desc:ident) => {{
@@ -29,27 +74,30 @@ macro_rules! load {
             $slf.deselect($desc.id(), "reloading");
         };
         // "Load" the Unit, which means we can select or activate it later on.
-        match $desc.load($slf, &*$slf.cfg.lock().unwrap()) {
+        let result = match $desc.load($slf, &*$slf.cfg.locked()) {
             Ok(o) => {
                 $slf.$dest
                     .borrow_mut()
                     .insert($desc.id().clone(), Rc::new(RefCell::new(o)));
-
-                // Announce the fact that the unit was loaded successfully.
-                $slf.bc
-                    .broadcast(&UnitEvent::Status(UnitStatusEvent::new_loaded($desc.id())));
-
                 Ok($desc.id().clone())
             }
-            Err(e) => {
-                $slf.bc
-                    .broadcast(&UnitEvent::Status(UnitStatusEvent::new_load_failed(
-                        $desc.id(),
-                        format!("{}", e),
-                    )));
-                Err(e)
-            }
+            Err(e) => Err(e),
+        };
+
+        // Announce whether the unit was loaded successfully.  It's new, or
+        // it was just deselected, unless deselecting it failed.
+        let failure = result.as_ref().err().map(|e| e.to_failure());
+        if let Some(unit) = $slf.known::<Unloaded>($desc.id()) {
+            $slf.finish_load(unit, failure);
+        } else if let Some(unit) = $slf.known::<Loaded>($desc.id()) {
+            $slf.finish_load(unit, failure);
+        } else {
+            $slf.transition(
+                $desc.id(),
+                failure.map_or(UnitTransition::Loaded, UnitTransition::LoadFailed),
+            );
         }
+        result
     }};
 }
 
@@ -57,6 +105,18 @@ macro_rules! load {
 pub enum FieldType {
     Name,
     Description,
+
+    /// Hints for operator GUIs, from the unit's Presentation.
+    Icon,
+    Color,
+    Group,
+    Hidden,
+
+    /// How long a scenario is expected to take, in milliseconds.
+    Estimate,
+
+    /// The parameters a scenario must (or may) be started with.
+    Params,
 }
 
 impl fmt::Display for FieldType {
@@ -64,11 +124,18 @@ impl fmt::Display for FieldType {
         match self {
             FieldType::Name => write!(f, "name"),
             FieldType::Description => write!(f, "description"),
+            FieldType::Icon => write!(f, "icon"),
+            FieldType::Color => write!(f, "color"),
+            FieldType::Group => write!(f, "group"),
+            FieldType::Hidden => write!(f, "hidden"),
+            FieldType::Estimate => write!(f, "estimate"),
+            FieldType::Params => write!(f, "params"),
         }
     }
 }
 
 /// Messages for Library -> Unit communication
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone)]
 pub enum ManagerStatusMessage {
     /// Return the first name of the jig we're running on.
@@ -100,8 +167,11 @@ pub enum ManagerStatusMessage {
     /// A test has started running.
     Running(UnitName),
 
-    /// A scenario has started
-    Start(UnitName),
+    /// A scenario has started, on the given jig if there is one
+    Start(UnitName, Option<UnitName>),
+
+    /// How long a scenario that just started is expected to take.
+    Estimate(UnitName, Estimate),
 
     /// Indicates that a test passed successfully.
     Pass(UnitName, String /* log message */),
@@ -113,19 +183,145 @@ pub enum ManagerStatusMessage {
         String, /* log message */
     ),
 
+    /// The output of a test was saved to a file.
+    Output(UnitName, PathBuf),
+
+    /// A test produced an artifact, which interfaces may fetch.
+    Artifact(
+        UnitName, /* Test name */
+        String,   /* Artifact name */
+        u64,      /* Size in bytes */
+    ),
+
+    /// Indicates that a test couldn't run properly because of a station fault.
+    Fault(
+        UnitName,
+        i32,    /* return code */
+        String, /* log message */
+    ),
+
     /// Indicates that a test was skipped for some reason.
     Skipped(UnitName, String /* reason */),
 
+    /// Indicates that a test hit a problem that may go away, and will be run again.
+    Retry(
+        UnitName,
+        i32,    /* return code */
+        String, /* log message */
+    ),
+
     /// Sent when a scenario has finished running.
-    Finished(
+    Finished(ScenarioSummary),
+
+    /// The lifecycle state of a loaded unit.
+    UnitState(UnitName, UnitState),
+
+    /// A unit failed to load, select, activate, or deactivate.
+    UnitFailed(UnitName, UnitFailure),
+
+    /// Whether the system clock can be trusted, and if not, why.
+    Clock(Option<String>),
+
+    /// What it cost to run a test, or a whole scenario.
+    Cost(UnitName, Cost),
+
+    /// Check that the interface is still alive.  It must echo the id back with PONG.
+    Ping(u64),
+
+    /// An operator attached a note to the current run.
+    Note(UnitName /* Author */, String /* Note */),
+
+    /// The running scenario was aborted, by an interface or a trigger.
+    Aborted(
         UnitName, /* Scenario name */
-        u32,      /* Result code */
-        String,   /* Reason for finishing */
+        UnitName, /* The unit that aborted it */
+    ),
+
+    /// How a test has done since exclave started.
+    Stats(TestStats),
+
+    /// A jig became degraded (with the reason), or recovered (None).
+    Health(UnitName, Option<String>),
+
+    /// A scenario in the last list of scenarios can't be selected, and why.
+    Incompatible(UnitName, String),
+
+    /// Whether an interface is in control of its FailoverGroup, or is a
+    /// spare, and which interface is.
+    Role(
+        bool,     /* Whether this interface is primary */
+        String,   /* Failover group */
+        UnitName, /* The primary interface */
+    ),
+
+    /// The station is in maintenance mode (with the reason), or in production (None).
+    Mode(Option<String>),
+
+    /// One entry in a jig's key-value store.
+    StoreEntry(
+        UnitName, /* Jig name */
+        String,   /* Key */
+        String,   /* Value */
     ),
+
+    /// The count of one of a jig's counters.
+    Counter(
+        UnitName,    /* Jig name */
+        String,      /* Counter name */
+        u64,         /* Count */
+        Option<u64>, /* Limit, if there is one */
+    ),
+
+    /// A jig counter reached its limit, so the part it counts needs maintenance.
+    MaintenanceDue(
+        UnitName, /* Jig name */
+        String,   /* Counter name */
+        u64,      /* Count */
+        u64,      /* Limit */
+    ),
+
+    /// A unit used the old name of a renamed test or scenario.
+    Deprecated(
+        UnitName, /* Old name */
+        UnitName, /* The unit it now refers to */
+        UnitName, /* The unit that used the old name */
+    ),
+
+    /// A test keeps flipping between passing and failing.
+    Flaky(
+        UnitName, /* Test name */
+        u32,      /* Flip rate, in percent */
+        usize,    /* Number of recent runs the rate was measured over */
+    ),
+
+    /// One directive that a kind of unit file may contain.
+    Schema(
+        UnitKind,
+        &'static str, /* Section name */
+        DirectiveSchema,
+    ),
+
+    /// A setting changed when the config file was reloaded.
+    ConfigChanged(
+        String, /* Setting name */
+        bool,   /* True if a restart is required for it to take effect */
+    ),
+
+    /// Everything after this is sent in a different framing.
+    Framing(Framing),
+
+    /// A snapshot of the station's configuration was saved to a file.
+    Snapshot(PathBuf),
+
+    /// Which build of exclave is running.
+    Version,
+
+    /// Which station this is.
+    Station(Station),
 }
 
 /// Messages for Unit -> Library communication
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
 pub enum ManagerControlMessageContents {
     /// Get the current Jig
     Jig,
@@ -151,17 +347,29 @@ pub enum ManagerControlMessageContents {
     /// Indicates the child (Interface, Test, etc.) has exited.
     ChildExited,
 
+    /// A daemon test that may be restarted exited, with this result, without
+    /// being stopped.
+    DaemonExited(i32),
+
     /// Client sent an unimplemented message.
     Unimplemented(String /* verb */, String /* rest of line */),
 
+    /// A trigger reported its state, such as whether a fixture is closed.
+    TriggerState(String),
+
     /// Send an INFO message to the logging system
     Log(String /* log message */),
 
     /// Send an ERROR message to the logging system
     LogError(String /* log message */),
 
-    /// Start running a scenario, or the default scenario if None
-    StartScenario(Option<UnitName>),
+    /// Start running a scenario, or the default scenario if None, with the
+    /// values given for its parameters, as (name, value), and what the
+    /// trigger that started it saw, if anything
+    StartScenario(Option<UnitName>, Vec<(String, String)>, Option<String>),
+
+    /// Run the current scenario again with only the tests that failed or were skipped
+    RerunFailed,
 
     /// Start running a given test.
     StartTest(UnitName),
@@ -172,6 +380,13 @@ pub enum ManagerControlMessageContents {
     /// Sent when a test has started running.
     TestStarted,
 
+    /// A slot may have come free in the concurrency group of a test that's
+    /// waiting for one.
+    SlotFree,
+
+    /// A unit that exited on its own is due to be started again.
+    RestartUnit,
+
     /// Indicates that a test was skipped, and why.
     Skip(UnitName, String /* reason */),
 
@@ -192,9 +407,82 @@ pub enum ManagerControlMessageContents {
 
     /// Abort the currently-running tests
     AbortTests,
+
+    /// Re-read the config file and apply any settings that changed
+    ReloadConfig,
+
+    /// Prevent a unit from being loaded, without removing its file
+    DisableUnit(UnitName),
+
+    /// Allow a previously-disabled unit to be loaded again
+    EnableUnit(UnitName),
+
+    /// Get the lifecycle state of every loaded unit
+    UnitStates,
+
+    /// Time for an interface to send its next PING.
+    Heartbeat,
+
+    /// An interface answered a PING.
+    Pong(String /* PING id */),
+
+    /// An interface's BatchWindow has ended, so what it holds should be sent.
+    FlushBatch,
+
+    /// An interface asked for records to be sent in another framing.
+    Framing(Framing),
+
+    /// The result of checking the system clock: None if it can be trusted,
+    /// or the reason it can't.
+    ClockStatus(Option<String>),
+
+    /// Send an artifact from the current run to the requesting interface
+    FetchArtifact(
+        UnitName, /* Test name */
+        String,   /* Artifact name */
+    ),
+
+    /// Send a line of operator input to a running test
+    TestInput(UnitName /* Test name */, String /* Input */),
+
+    /// Attach an operator's note to the current run, or the last one if none is running
+    Note(String /* Note */),
+
+    /// Get how each test has done since exclave started
+    Stats,
+
+    /// Get the count of every counter on the selected jigs
+    Counters,
+
+    /// Get the directives accepted by one kind of unit file, or by every kind (None)
+    Schema(Option<UnitKind>),
+
+    /// Set a jig counter back to zero, after maintenance
+    ResetCounter(UnitName /* Jig name */, String /* Counter name */),
+
+    /// Get everything in a jig's key-value store
+    Store(UnitName /* Jig name */),
+
+    /// Remove one key from a jig's key-value store, or all of them (None)
+    ResetStore(UnitName /* Jig name */, Option<String> /* Key */),
+
+    /// Put the station into maintenance mode for a reason, or back into production (None)
+    SetMaintenance(Option<String>),
+
+    /// An observer interface sent a verb that it isn't allowed to
+    Denied(String /* verb */),
+
+    /// Save a snapshot of the station's configuration, for a support ticket
+    Snapshot,
+
+    /// Get which build of exclave is running
+    Version,
+
+    /// Get the station's identity
+    Station,
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
 pub struct ManagerControlMessage {
     pub sender: UnitName,
     pub contents: ManagerControlMessageContents,
@@ -202,6 +490,9 @@ pub struct ManagerControlMessage {
 
 impl ManagerControlMessage {
     pub fn new(id: &UnitName, contents: ManagerControlMessageContents) -> Self {
+        if let ManagerControlMessageContents::StartTest(ref test) = contents {
+            profile::test_starting(test);
+        }
         ManagerControlMessage {
             sender: id.clone(),
             contents,
@@ -209,6 +500,29 @@ impl ManagerControlMessage {
     }
 }
 
+/// What is known about a loaded unit.  Returned by `UnitManager::unit_info()`
+/// for programs that embed exclave.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitInfo {
+    pub id: UnitName,
+
+    /// The unit's short display name, from Name=.
+    pub name: String,
+
+    /// The unit's longer description, from Description=.
+    pub description: String,
+
+    /// Where the unit is in its lifecycle.
+    pub state: UnitState,
+
+    /// The jigs the unit works with.  If empty, it works with any jig.
+    pub jigs: Vec<UnitName>,
+
+    /// How operator GUIs should show the unit.  Only jigs, scenarios, and
+    /// tests have any hints.
+    pub presentation: Presentation,
+}
+
 pub struct UnitManager {
     cfg: Arc<Mutex<Config>>,
     bc: UnitBroadcaster,
@@ -216,12 +530,18 @@ pub struct UnitManager {
     /// Loaded Interfaces, available for selection and activation.
     interfaces: RefCell<HashMap<UnitName, Rc<RefCell<Interface>>>>,
 
+    /// Loaded Allocators, which hand out MAC addresses and serial numbers.
+    allocators: RefCell<HashMap<UnitName, Rc<RefCell<Allocator>>>>,
+
     /// Loaded Jigs, available for selection and activation.
     jigs: RefCell<HashMap<UnitName, Rc<RefCell<Jig>>>>,
 
     /// Loaded Loggers.
     loggers: RefCell<HashMap<UnitName, Rc<RefCell<Logger>>>>,
 
+    /// Loaded Notifiers, which watch finished runs.
+    notifiers: RefCell<HashMap<UnitName, Rc<RefCell<Notifier>>>>,
+
     /// Loaded Scenarios, available for selected and activation.
     scenarios: Rc<RefCell<HashMap<UnitName, Rc<RefCell<Scenario>>>>>,
 
@@ -237,14 +557,83 @@ pub struct UnitManager {
     /// The currently-selected Scenario, if any
     current_scenario: Rc<RefCell<Option<Rc<RefCell<Scenario>>>>>,
 
-    /// The currently-selected Jig, if any
-    current_jig: Rc<RefCell<Option<Rc<RefCell<Jig>>>>>,
+    /// The currently-selected Jigs, in the order they were selected.  Unless
+    /// MultipleJigs is set, there is at most one.
+    selected_jigs: Rc<RefCell<Vec<Rc<RefCell<Jig>>>>>,
+
+    /// The lifecycle state of every loaded unit.
+    states: RefCell<HashMap<UnitName, UnitState>>,
+
+    /// Why the system clock can't be trusted, if it can't.
+    clock_problem: RefCell<Option<String>>,
+
+    /// True if the current run started while the clock couldn't be trusted.
+    run_clock_unsynced: Cell<bool>,
+
+    /// The notes operators have added to the current run, or to the most
+    /// recent one once it has finished.
+    run_notes: RefCell<Vec<String>>,
+
+    /// The summary of the most recent run once it has finished, so that notes
+    /// added afterwards still get recorded with it.
+    finished_run: RefCell<Option<ScenarioSummary>>,
+
+    /// Measures the cost of the scenario that is running, if any.
+    scenario_cost: RefCell<Option<CostMeter>>,
+
+    /// The values allocators have handed out for the current run, so every
+    /// test in the run that asks for one gets the same value.
+    run_allocations: RefCell<BTreeMap<UnitName, String>>,
+
+    /// The values the current run's parameters were given, as (name, value).
+    run_parameters: RefCell<Vec<(String, String)>>,
+
+    /// What the trigger that started the current run saw, such as a scanned
+    /// barcode, if it passed it on.
+    run_payload: RefCell<Option<String>>,
 
-    /// A list of selected units.
-    selected: Rc<RefCell<HashMap<UnitName, ()>>>,
+    /// How each test has done, and whether it's flaky.
 
-    /// A list of active units.  These units must also be selected.
-    active: Rc<RefCell<HashMap<UnitName, ()>>>,
+    /// Jigs that can't be relied on, and why.
+    degraded_jigs: RefCell<HashMap<UnitName, String>>,
+
+    /// Scenarios that couldn't be loaded because they aren't compatible, and why.
+    incompatible_scenarios: RefCell<HashMap<UnitName, String>>,
+
+    /// The running interfaces in each FailoverGroup, in the order they
+    /// started.  The first is in control, and the rest are spares.
+    failover_groups: RefCell<HashMap<String, Vec<UnitName>>>,
+
+    /// True while a recording is being replayed.  Scenarios aren't run, and
+    /// the recorded runs are reported to interfaces instead.
+    replaying: Cell<bool>,
+
+    /// The old names of loaded tests and scenarios, from Alias, and the
+    /// units they now refer to.
+    aliases: RefCell<HashMap<UnitName, UnitName>>,
+
+    /// The loaded tests that provide each name tests can be required by,
+    /// from Provides and Alias, so that a scenario's dependency graph can
+    /// be built from the tests it involves rather than every loaded test.
+    test_providers: RefCell<HashMap<UnitName, BTreeSet<UnitName>>>,
+
+    /// How each scenario's tests were last resolved, and the tests involved,
+    /// so that it's only done again once one changes.
+    /// Loading or unloading a test drops the scenarios it's involved in.
+    scenario_resolutions: RefCell<HashMap<UnitName, (ResolutionInputs, Resolution)>>,
+
+    /// Each (alias, unit that used it) that has been warned about, so that
+    /// each use is only reported once.
+    alias_warnings: RefCell<HashSet<(UnitName, UnitName)>>,
+
+    /// How many poisoned locks had been recovered when that was last reported.
+    poisonings_reported: Cell<usize>,
+
+    /// How often units that may be restarted have been.
+    supervisor: RefCell<Supervisor>,
+
+    /// Which triggers have sent START lately, and the states they've reported.
+    interlock: RefCell<Interlock>,
 }
 
 impl UnitManager {
@@ -253,23 +642,44 @@ impl UnitManager {
 
         let monitor_broadcaster = broadcaster.clone();
         thread::spawn(move || Self::control_message_monitor(receiver, monitor_broadcaster));
+        broadcaster.set_station(&config.locked().station().id);
 
         UnitManager {
             cfg: config.clone(),
             bc: broadcaster.clone(),
 
+            allocators: RefCell::new(HashMap::new()),
             interfaces: RefCell::new(HashMap::new()),
             jigs: RefCell::new(HashMap::new()),
             loggers: RefCell::new(HashMap::new()),
+            notifiers: RefCell::new(HashMap::new()),
             scenarios: Rc::new(RefCell::new(HashMap::new())),
             tests: Rc::new(RefCell::new(HashMap::new())),
             triggers: Rc::new(RefCell::new(HashMap::new())),
 
-            selected: Rc::new(RefCell::new(HashMap::new())),
-            active: Rc::new(RefCell::new(HashMap::new())),
+            states: RefCell::new(HashMap::new()),
+            clock_problem: RefCell::new(None),
+            run_clock_unsynced: Cell::new(false),
+            run_notes: RefCell::new(vec![]),
+            finished_run: RefCell::new(None),
+            scenario_cost: RefCell::new(None),
+            run_allocations: RefCell::new(BTreeMap::new()),
+            run_parameters: RefCell::new(vec![]),
+            run_payload: RefCell::new(None),
+            degraded_jigs: RefCell::new(HashMap::new()),
+            incompatible_scenarios: RefCell::new(HashMap::new()),
+            failover_groups: RefCell::new(HashMap::new()),
+            replaying: Cell::new(false),
+            aliases: RefCell::new(HashMap::new()),
+            test_providers: RefCell::new(HashMap::new()),
+            scenario_resolutions: RefCell::new(HashMap::new()),
+            alias_warnings: RefCell::new(HashSet::new()),
+            poisonings_reported: Cell::new(poison::recovered()),
+            supervisor: RefCell::new(Supervisor::new()),
+            interlock: RefCell::new(Interlock::new()),
 
             current_scenario: Rc::new(RefCell::new(None)),
-            current_jig: Rc::new(RefCell::new(None)),
+            selected_jigs: Rc::new(RefCell::new(vec![])),
 
             control_sender: sender,
         }
@@ -303,11 +713,31 @@ impl UnitManager {
         load!(self, loggers, description)
     }
 
+    pub fn load_allocator(
+        &self,
+        description: &AllocatorDescription,
+    ) -> Result<UnitName, UnitIncompatibleReason> {
+        load!(self, allocators, description)
+    }
+
+    pub fn load_notifier(
+        &self,
+        description: &NotifierDescription,
+    ) -> Result<UnitName, UnitIncompatibleReason> {
+        load!(self, notifiers, description)
+    }
+
     pub fn load_test(
         &self,
         desceription: &TestDescription,
     ) -> Result<UnitName, UnitIncompatibleReason> {
-        load!(self, tests, desceription)
+        let id = load!(self, tests, desceription)?;
+        self.set_aliases(&id, desceription.aliases());
+        let mut provides = self.tests.borrow()[&id].borrow().provides().clone();
+        self.set_provides(&id, &provides);
+        provides.push(id.clone());
+        self.forget_resolutions(&provides);
+        Ok(id)
     }
 
     pub fn load_jig(
@@ -321,7 +751,143 @@ impl UnitManager {
         &self,
         desceription: &ScenarioDescription,
     ) -> Result<UnitName, UnitIncompatibleReason> {
-        load!(self, scenarios, desceription)
+        let result = load!(self, scenarios, desceription);
+        let id = match result {
+            Ok(id) => id,
+            Err(e) => {
+                // Listings say why it can't be selected.
+                self.incompatible_scenarios
+                    .borrow_mut()
+                    .insert(desceription.id().clone(), e.to_string());
+                self.broadcast_scenario_list();
+                return Err(e);
+            }
+        };
+        self.set_aliases(&id, desceription.aliases());
+        Ok(id)
+    }
+
+    /// Replace the old names a unit can be referred to by.
+    fn set_aliases(&self, id: &UnitName, aliases: &[UnitName]) {
+        let mut map = self.aliases.borrow_mut();
+        map.retain(|_, target| target != id);
+        for alias in aliases {
+            map.insert(alias.clone(), id.clone());
+        }
+    }
+
+    /// Replace the names a test can be required by, other than its own.
+    fn set_provides(&self, id: &UnitName, provides: &[UnitName]) {
+        let mut map = self.test_providers.borrow_mut();
+        map.retain(|_, providers| {
+            providers.remove(id);
+            !providers.is_empty()
+        });
+        for name in provides {
+            map.entry(name.clone()).or_default().insert(id.clone());
+        }
+    }
+
+    /// The loaded tests that a test requiring `name` might get: the test of
+    /// that name, if there is one, and every test that provides it.
+    pub fn test_providers(&self, name: &UnitName) -> Vec<UnitName> {
+        let mut providers = vec![];
+        if self.tests.borrow().contains_key(name) {
+            providers.push(name.clone());
+        }
+        if let Some(others) = self.test_providers.borrow().get(name) {
+            providers.extend(others.iter().filter(|p| *p != name).cloned());
+        }
+        providers
+    }
+
+    /// Forget how each scenario involving any of these tests, or a test
+    /// providing any of these names, resolved, so that only those scenarios
+    /// resolve their tests again.
+    fn forget_resolutions(&self, names: &[UnitName]) {
+        self.scenario_resolutions
+            .borrow_mut()
+            .retain(|_, (_, resolution)| !names.iter().any(|name| resolution.involves(name)));
+    }
+
+    /// How a scenario's tests were last resolved, if the tests involved
+    /// are still the same.
+    pub fn cached_resolution(
+        &self,
+        scenario: &UnitName,
+        inputs: &ResolutionInputs,
+    ) -> Option<Resolution> {
+        match self.scenario_resolutions.borrow().get(scenario) {
+            Some((cached_inputs, resolution)) if cached_inputs == inputs => {
+                Some(resolution.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Remember how a scenario's tests were resolved, in place of whatever
+    /// was remembered before.
+    pub fn cache_resolution(
+        &self,
+        scenario: &UnitName,
+        inputs: ResolutionInputs,
+        resolution: &Resolution,
+    ) {
+        self.scenario_resolutions
+            .borrow_mut()
+            .insert(scenario.clone(), (inputs, resolution.clone()));
+    }
+
+    /// The tests involved the last time a scenario's tests were resolved.
+    pub fn resolution_inputs(&self, scenario: &UnitName) -> Option<ResolutionInputs> {
+        self.scenario_resolutions
+            .borrow()
+            .get(scenario)
+            .map(|(inputs, _)| inputs.clone())
+    }
+
+    /// The unit an old name now refers to, if it's an alias.  Loaded units
+    /// keep their names even if another unit claims them as an alias.
+    pub fn alias_target(&self, name: &UnitName) -> Option<UnitName> {
+        if self.tests.borrow().contains_key(name) || self.scenarios.borrow().contains_key(name) {
+            return None;
+        }
+        self.aliases.borrow().get(name).cloned()
+    }
+
+    /// The unit a name refers to, following an alias if it's one, in which
+    /// case everyone is warned that `user` still uses the old name.
+    pub fn unalias(&self, name: &UnitName, user: &UnitName) -> UnitName {
+        let target = match self.alias_target(name) {
+            Some(target) => target,
+            None => return name.clone(),
+        };
+        self.warn_deprecated(name, &target, user);
+        target
+    }
+
+    /// Warn everyone that `user` refers to `target` by an old name, unless
+    /// they've already been told.
+    pub fn warn_deprecated(&self, alias: &UnitName, target: &UnitName, user: &UnitName) {
+        let first_use = self
+            .alias_warnings
+            .borrow_mut()
+            .insert((alias.clone(), user.clone()));
+        if !first_use {
+            return;
+        }
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+            user.clone(),
+            format!(
+                "{} is deprecated: it was renamed to {}, and the old name will stop working",
+                alias, target
+            ),
+        )));
+        self.broadcast_message(ManagerStatusMessage::Deprecated(
+            alias.clone(),
+            target.clone(),
+            user.clone(),
+        ));
     }
 
     pub fn load_trigger(
@@ -333,34 +899,40 @@ impl UnitManager {
 
     pub fn select(&self, id: &UnitName) {
         // Don't select already-selected units.
-        if self.selected.borrow().contains_key(id) {
+        if let Some(UnitState::Selected) | Some(UnitState::Active) = self.unit_state(id) {
             return;
         }
+        let unit = self.known::<Loaded>(id);
 
-        let result = match *id.kind() {
-            UnitKind::Interface => self.select_interface(id),
-            UnitKind::Jig => self.select_jig(id),
-            UnitKind::Logger => self.select_logger(id),
-            UnitKind::Scenario => self.select_scenario(id),
-            UnitKind::Test => self.select_test(id),
-            UnitKind::Trigger => self.select_trigger(id),
-            UnitKind::Internal => Ok(()),
+        // Units that only work on particular jigs can't be selected on other jigs.
+        let result = if !self.compatible_with_selected_jigs(id) {
+            Err(UnitSelectError::NoCompatibleJig)
+        } else {
+            match *id.kind() {
+                UnitKind::Interface => self.select_interface(id),
+                UnitKind::Jig => self.select_jig(id),
+                UnitKind::Logger => self.select_logger(id),
+                UnitKind::Scenario => self.select_scenario(id),
+                UnitKind::Test => self.select_test(id),
+                UnitKind::Trigger => self.select_trigger(id),
+                UnitKind::Notifier => self.select_notifier(id),
+                UnitKind::Allocator => self.select_allocator(id),
+                UnitKind::Internal => Ok(()),
+            }
         };
 
-        // Announce that the interface was successfully started.
-        match result {
-            Ok(_) => {
-                self.selected.borrow_mut().insert(id.clone(), ());
-                self.bc
-                    .broadcast(&UnitEvent::Status(UnitStatusEvent::new_selected(id)));
+        // Announce that the interface was successfully started.  One that
+        // isn't loaded is checked, and can only fail to be selected.
+        match (unit, result) {
+            (Some(unit), Ok(_)) => {
+                self.step(unit.select());
             }
-            Err(e) => self
-                .bc
-                .broadcast(&UnitEvent::Status(UnitStatusEvent::new_select_failed(
-                    id,
-                    format!("{}", e),
-                ))),
-        }
+            (Some(unit), Err(e)) => {
+                self.step(unit.select_failed(e.to_failure()));
+            }
+            (None, Ok(_)) => self.transition(id, UnitTransition::Selected),
+            (None, Err(e)) => self.transition(id, UnitTransition::SelectFailed(e.to_failure())),
+        };
     }
 
     pub fn select_scenario(&self, id: &UnitName) -> Result<(), UnitSelectError> {
@@ -373,7 +945,7 @@ impl UnitManager {
         // If so, there is nothing to do.
         // If not, deselect it.
         // There Can Only Be One.
-        let deselct_id_opt = if let Some(ref old_scenario) = *self.current_scenario.borrow() {
+        let deselct_id_opt = if let Some(old_scenario) = self.current_scenario() {
             if old_scenario.borrow().id() == id {
                 // Units match, so do nothing.
                 return Ok(());
@@ -404,30 +976,33 @@ impl UnitManager {
             None => return Err(UnitSelectError::UnitNotFound),
         };
 
-        // If there is an existing current jig, check to see if the ID matches.
-        // If so, there is nothing to do.
-        // If not, deselect it.
-        // There Can Only Be One.
-        let deselct_id_opt = if let Some(ref old_jig) = *self.current_jig.borrow() {
-            if old_jig.borrow().id() == id {
-                // Units match, so do nothing.
-                return Ok(());
+        // If this jig is already selected, there is nothing to do.
+        if self.is_selected_jig(id) {
+            return Ok(());
+        }
+
+        // Unless several fixtures are attached, There Can Only Be One,
+        // so deselect whichever jig is currently selected.
+        if !self.cfg.locked().multiple_jigs() {
+            let old_ids: Vec<UnitName> = self
+                .selected_jigs
+                .borrow()
+                .iter()
+                .map(|j| j.borrow().id().clone())
+                .collect();
+            for old_id in &old_ids {
+                self.deselect(old_id, "switching to a new jig");
             }
-            Some(old_jig.borrow().id().clone())
-        } else {
-            None
-        };
-        if let Some(ref old_id) = deselct_id_opt {
-            self.deselect(old_id, "switching to a new jig");
         }
 
         // Select this jig.
         new_jig.borrow_mut().select()?;
-        *self.current_jig.borrow_mut() = Some(new_jig.clone());
+        self.selected_jigs.borrow_mut().push(new_jig.clone());
 
         // If this jig has a default scenario, select that too.
-        if let Some(ref scenario_name) = *new_jig.borrow().default_scenario() {
-            self.select(scenario_name);
+        let default_scenario = new_jig.borrow().default_scenario().clone();
+        if let Some(scenario_name) = default_scenario {
+            self.select(&self.unalias(&scenario_name, id));
         }
 
         Ok(())
@@ -454,6 +1029,20 @@ impl UnitManager {
         }
     }
 
+    fn select_allocator(&self, id: &UnitName) -> Result<(), UnitSelectError> {
+        match self.allocators.borrow().get(id) {
+            Some(s) => s.borrow_mut().select(),
+            None => Err(UnitSelectError::UnitNotFound),
+        }
+    }
+
+    fn select_notifier(&self, id: &UnitName) -> Result<(), UnitSelectError> {
+        match self.notifiers.borrow().get(id) {
+            Some(s) => s.borrow_mut().select(),
+            None => Err(UnitSelectError::UnitNotFound),
+        }
+    }
+
     fn select_logger(&self, id: &UnitName) -> Result<(), UnitSelectError> {
         match self.loggers.borrow().get(id) {
             Some(s) => s.borrow_mut().select(),
@@ -465,9 +1054,12 @@ impl UnitManager {
         self.deactivate(id, "unit is being deselcted");
 
         // Don't deselect a unit that hasn't been selected.
-        if !self.selected.borrow().contains_key(id) {
-            return;
-        }
+        let unit = match self.known::<Selected>(id) {
+            Some(unit) => unit,
+            None => return,
+        };
+
+        let was_selected_jig = self.is_selected_jig(id);
 
         // Remove the item from its associated Rc array.
         // Note that because these are Rcs, they may live on for a little while
@@ -480,16 +1072,154 @@ impl UnitManager {
             UnitKind::Scenario => self.deselect_scenario(id),
             UnitKind::Test => self.deselect_test(id),
             UnitKind::Trigger => self.deselect_trigger(id),
+            UnitKind::Notifier => self.deselect_notifier(id),
+            UnitKind::Allocator => self.deselect_allocator(id),
         };
 
         // A not-okay result is fine, it just means we couldn't find the unit.
         if result.is_ok() {
-            self.selected.borrow_mut().remove(id);
-            self.bc
-                .broadcast(&UnitEvent::Status(UnitStatusEvent::new_deselected(
-                    id,
-                    reason.to_owned(),
-                )));
+            self.step(unit.deselect(reason.to_owned()));
+
+            // Anything that relied on this jig can no longer be used.
+            if was_selected_jig {
+                self.deselect_jig_dependents(id);
+            }
+        }
+    }
+
+    /// Returns true if the specified unit is one of the selected jigs.
+    fn is_selected_jig(&self, id: &UnitName) -> bool {
+        self.get_selected_jig(id).is_some()
+    }
+
+    fn get_selected_jig(&self, id: &UnitName) -> Option<Rc<RefCell<Jig>>> {
+        self.selected_jigs
+            .borrow()
+            .iter()
+            .find(|jig| jig.borrow().id() == id)
+            .cloned()
+    }
+
+    /// Return the list of jigs a loaded unit is compatible with.
+    /// An empty list means the unit works with any jig.
+    fn unit_jigs(&self, id: &UnitName) -> Vec<UnitName> {
+        let jigs = match *id.kind() {
+            UnitKind::Interface => self
+                .interfaces
+                .borrow()
+                .get(id)
+                .map(|u| u.borrow().jigs().clone()),
+            UnitKind::Logger => self
+                .loggers
+                .borrow()
+                .get(id)
+                .map(|u| u.borrow().jigs().clone()),
+            UnitKind::Scenario => self
+                .scenarios
+                .borrow()
+                .get(id)
+                .map(|u| u.borrow().jigs().clone()),
+            UnitKind::Test => self
+                .tests
+                .borrow()
+                .get(id)
+                .map(|u| u.borrow().jigs().clone()),
+            UnitKind::Trigger => self
+                .triggers
+                .borrow()
+                .get(id)
+                .map(|u| u.borrow().jigs().clone()),
+            UnitKind::Notifier => self
+                .notifiers
+                .borrow()
+                .get(id)
+                .map(|u| u.borrow().jigs().clone()),
+            UnitKind::Allocator => self
+                .allocators
+                .borrow()
+                .get(id)
+                .map(|u| u.borrow().jigs().clone()),
+            UnitKind::Jig | UnitKind::Internal => None,
+        };
+        jigs.unwrap_or_default()
+    }
+
+    /// Returns true if the unit may be used with any of the selected jigs.
+    fn compatible_with_selected_jigs(&self, id: &UnitName) -> bool {
+        let jigs = self.unit_jigs(id);
+        if jigs.is_empty() {
+            return true;
+        }
+        self.selected_jigs
+            .borrow()
+            .iter()
+            .any(|jig| jigs.contains(jig.borrow().id()))
+    }
+
+    /// Pick the jig a scenario will run on: the first selected jig that the
+    /// scenario lists, or the first selected jig if it works with any.
+    fn scenario_jig(&self, id: &UnitName) -> Option<Rc<RefCell<Jig>>> {
+        let jigs = self.unit_jigs(id);
+        self.selected_jigs
+            .borrow()
+            .iter()
+            .find(|jig| jigs.is_empty() || jigs.contains(jig.borrow().id()))
+            .cloned()
+    }
+
+    /// Deselect every unit that depends on a jig that has just been deselected,
+    /// shutting down any that are running.  Units that also work with another
+    /// selected jig are left alone.
+    fn deselect_jig_dependents(&self, jig_id: &UnitName) {
+        let dependents: Vec<UnitName> = self
+            .states
+            .borrow()
+            .iter()
+            .filter(|&(_, state)| *state != UnitState::Loaded)
+            .map(|(id, _)| id)
+            .filter(|id| {
+                self.unit_jigs(id).contains(jig_id) && !self.compatible_with_selected_jigs(id)
+            })
+            .cloned()
+            .collect();
+        for id in dependents {
+            let reason = format!("jig {} was deselected", jig_id);
+            self.deselect(&id, &reason);
+            self.transition(&id, UnitTransition::Incompatible(reason));
+        }
+    }
+
+    /// Start up any Allocators, Interfaces, Loggers, Notifiers, and Triggers that depend on a jig
+    /// that has just been activated.
+    fn activate_jig_dependents(&self, jig_id: &UnitName) {
+        let mut dependents = vec![];
+        for (id, unit) in self.interfaces.borrow().iter() {
+            if unit.borrow().jigs().contains(jig_id) {
+                dependents.push(id.clone());
+            }
+        }
+        for (id, unit) in self.loggers.borrow().iter() {
+            if unit.borrow().jigs().contains(jig_id) {
+                dependents.push(id.clone());
+            }
+        }
+        for (id, unit) in self.allocators.borrow().iter() {
+            if unit.borrow().jigs().contains(jig_id) {
+                dependents.push(id.clone());
+            }
+        }
+        for (id, unit) in self.notifiers.borrow().iter() {
+            if unit.borrow().jigs().contains(jig_id) {
+                dependents.push(id.clone());
+            }
+        }
+        for (id, unit) in self.triggers.borrow().iter() {
+            if unit.borrow().jigs().contains(jig_id) {
+                dependents.push(id.clone());
+            }
+        }
+        for id in dependents {
+            self.activate(&id);
         }
     }
 
@@ -514,6 +1244,20 @@ impl UnitManager {
         }
     }
 
+    fn deselect_allocator(&self, id: &UnitName) -> Result<(), UnitDeselectError> {
+        match self.allocators.borrow().get(id) {
+            Some(s) => s.borrow_mut().deselect(),
+            None => Err(UnitDeselectError::UnitNotFound),
+        }
+    }
+
+    fn deselect_notifier(&self, id: &UnitName) -> Result<(), UnitDeselectError> {
+        match self.notifiers.borrow().get(id) {
+            Some(s) => s.borrow_mut().deselect(),
+            None => Err(UnitDeselectError::UnitNotFound),
+        }
+    }
+
     fn deselect_logger(&self, id: &UnitName) -> Result<(), UnitDeselectError> {
         match self.loggers.borrow().get(id) {
             Some(s) => s.borrow_mut().deselect(),
@@ -522,43 +1266,38 @@ impl UnitManager {
     }
 
     fn deselect_jig(&self, id: &UnitName) -> Result<(), UnitDeselectError> {
-        // If the specified jig isn't the current jig, then there's nothing to do.
-        let mut current_jig_opt = self.current_jig.borrow_mut();
-
-        let current_jig = match *current_jig_opt {
+        // If the specified jig isn't selected, then there's nothing to do.
+        let jig = match self.get_selected_jig(id) {
             None => return Ok(()),
-            Some(ref s) => {
-                let current_jig = s.borrow();
-                if current_jig.id() != id {
-                    return Ok(());
-                }
-                s.clone()
-            }
+            Some(j) => j,
         };
 
         // If there is a default scenario, make sure it's deselected.
-        if let Some(new_scenario_id) = current_jig.borrow().default_scenario().clone() {
-            self.deselect(&new_scenario_id, "jig is deselecting");
+        let default_scenario = jig.borrow().default_scenario().clone();
+        if let Some(new_scenario_id) = default_scenario {
+            self.deselect(&self.unalias(&new_scenario_id, id), "jig is deselecting");
         }
 
-        current_jig.borrow_mut().deselect()?;
-        *current_jig_opt = None;
+        jig.borrow_mut().deselect()?;
+        self.selected_jigs
+            .borrow_mut()
+            .retain(|j| j.borrow().id() != id);
         Ok(())
     }
 
     fn deselect_scenario(&self, id: &UnitName) -> Result<(), UnitDeselectError> {
         // If the specified scenario isn't the current scenario, then there's nothing to do.
-        match *self.current_scenario.borrow() {
+        match self.current_scenario() {
             None => return Ok(()),
-            Some(ref s) => {
-                let current_scenario = s.borrow();
-                if current_scenario.id() != id {
+            Some(s) => {
+                if s.borrow().id() != id {
                     return Ok(());
                 }
             }
         }
 
-        if let Some(ref old_scenario) = self.current_scenario.borrow_mut().take() {
+        let old_scenario = self.current_scenario.borrow_mut().take();
+        if let Some(ref old_scenario) = old_scenario {
             // Deselect every test in this scenario first.
             for test_id in &old_scenario.borrow().test_sequence() {
                 self.deselect(test_id, "scenario is deselecting");
@@ -573,15 +1312,11 @@ impl UnitManager {
     pub fn activate(&self, id: &UnitName) {
         self.select(id);
 
-        // Don't activate a unit that is already active.
-        if self.active.borrow().contains_key(id) {
-            return;
-        }
-
-        // If the unit still hasn't been selected, don't activate it.
-        if !self.selected.borrow().contains_key(id) {
-            return;
-        }
+        // Only activate units that are selected but not yet active.
+        let unit = match self.known::<Selected>(id) {
+            Some(unit) => unit,
+            None => return,
+        };
 
         let result = match *id.kind() {
             UnitKind::Interface => self.activate_interface(id),
@@ -590,46 +1325,69 @@ impl UnitManager {
             UnitKind::Scenario => self.activate_scenario(id),
             UnitKind::Test => self.activate_test(id),
             UnitKind::Trigger => self.activate_trigger(id),
+            UnitKind::Notifier => self.activate_notifier(id),
+            UnitKind::Allocator => self.activate_allocator(id),
             UnitKind::Internal => Ok(()),
         };
 
         // Announce that the interface was successfully started.
         match result {
             Ok(_) => {
-                self.active.borrow_mut().insert(id.clone(), ());
-                self.bc
-                    .broadcast(&UnitEvent::Status(UnitStatusEvent::new_active(id)))
+                self.step(unit.activate());
+                if self.restart_settings(id).policy != RestartPolicy::No {
+                    self.supervisor.borrow_mut().started(id);
+                }
+                if *id.kind() == UnitKind::Jig {
+                    self.check_calibration(id);
+                    self.activate_jig_dependents(id);
+                }
+            }
+            Err(e) => {
+                self.step(unit.activation_failed(e.to_failure()));
             }
-            Err(e) => self
-                .bc
-                .broadcast(&UnitEvent::Status(UnitStatusEvent::new_active_failed(
-                    id,
-                    format!("unable to activate: {}", e),
-                ))),
         }
     }
 
     /// If there are unselected defaults, activate them.
-    /// For example, if there is no current Jig, activate the first Jig we find.
+    /// For example, if there is no current Jig, activate the first Jig we find,
+    /// or every Jig if MultipleJigs is set.
     /// Likewise, if there is no selected Scenario, select the first scenario we find.
     pub fn refresh_defaults(&self) {
-        // Activate a "random" available jig.
-        if self.current_jig.borrow().is_none() && !self.jigs.borrow().is_empty() {
+        if self.cfg.locked().multiple_jigs() {
+            let new_jig_ids: Vec<UnitName> = self
+                .jigs
+                .borrow()
+                .keys()
+                .filter(|id| !self.is_selected_jig(id))
+                .cloned()
+                .collect();
+            for new_jig_id in &new_jig_ids {
+                self.activate(new_jig_id);
+            }
+        } else if self.selected_jigs.borrow().is_empty() && !self.jigs.borrow().is_empty() {
+            // Activate a "random" available jig.
             let new_jig_id = self.jigs.borrow().keys().next().unwrap().clone();
             self.activate(&new_jig_id);
         }
 
-        // If there is no current scenario, select a random one.
-        if self.current_scenario.borrow().is_none() && !self.scenarios.borrow().is_empty() {
-            let new_scenario_id = self.scenarios.borrow().keys().next().unwrap().clone();
-            self.select(&new_scenario_id);
+        // If there is no current scenario, select a random one that works with this jig.
+        if self.current_scenario.borrow().is_none() {
+            let new_scenario_id = self
+                .scenarios
+                .borrow()
+                .keys()
+                .find(|id| self.compatible_with_selected_jigs(id))
+                .cloned();
+            if let Some(new_scenario_id) = new_scenario_id {
+                self.select(&new_scenario_id);
+            }
         }
     }
 
     fn activate_interface(&self, id: &UnitName) -> Result<(), UnitActivateError> {
         // Activate the interface, which actually starts it up.
         match self.interfaces.borrow().get(id) {
-            Some(i) => i.borrow_mut().activate(self, &*self.cfg.lock().unwrap()),
+            Some(i) => i.borrow_mut().activate(self, &self.cfg.locked()),
             None => Err(UnitActivateError::UnitNotFound),
         }
     }
@@ -637,7 +1395,21 @@ impl UnitManager {
     fn activate_trigger(&self, id: &UnitName) -> Result<(), UnitActivateError> {
         // Activate the interface, which actually starts it up.
         match self.triggers.borrow().get(id) {
-            Some(i) => i.borrow_mut().activate(self, &*self.cfg.lock().unwrap()),
+            Some(i) => i.borrow_mut().activate(self, &self.cfg.locked()),
+            None => Err(UnitActivateError::UnitNotFound),
+        }
+    }
+
+    fn activate_allocator(&self, id: &UnitName) -> Result<(), UnitActivateError> {
+        match self.allocators.borrow().get(id) {
+            Some(i) => i.borrow_mut().activate(self, &self.cfg.locked()),
+            None => Err(UnitActivateError::UnitNotFound),
+        }
+    }
+
+    fn activate_notifier(&self, id: &UnitName) -> Result<(), UnitActivateError> {
+        match self.notifiers.borrow().get(id) {
+            Some(i) => i.borrow_mut().activate(self, &self.cfg.locked()),
             None => Err(UnitActivateError::UnitNotFound),
         }
     }
@@ -645,37 +1417,26 @@ impl UnitManager {
     fn activate_logger(&self, id: &UnitName) -> Result<(), UnitActivateError> {
         // Activate the interface, which actually starts it up.
         match self.loggers.borrow().get(id) {
-            Some(i) => i.borrow_mut().activate(self, &*self.cfg.lock().unwrap()),
+            Some(i) => i.borrow_mut().activate(self, &self.cfg.locked()),
             None => Err(UnitActivateError::UnitNotFound),
         }
     }
 
     /// Set the new jig as "Active".
-    /// The jig must already be set as the current jig.
+    /// The jig must already be selected.
     fn activate_jig(&self, id: &UnitName) -> Result<(), UnitActivateError> {
-        let current_jig_opt = self.current_jig.borrow();
-
-        match *current_jig_opt {
+        match self.get_selected_jig(id) {
             None => Err(UnitActivateError::UnitNotSelected),
-            Some(ref s) => {
-                if s.borrow_mut().id() != id {
-                    Err(UnitActivateError::UnitNotSelected)
-                } else {
-                    // Activate this jig.
-                    s.borrow_mut().activate(self, &*self.cfg.lock().unwrap())
-                }
-            }
+            Some(jig) => jig.borrow_mut().activate(self, &self.cfg.locked()),
         }
     }
 
     /// Set the specified scenario as "Active".
     /// This actually runs the scenario.
     fn activate_scenario(&self, id: &UnitName) -> Result<(), UnitActivateError> {
-        let current_opt = self.current_scenario.borrow();
-
-        match *current_opt {
+        match self.current_scenario() {
             None => Err(UnitActivateError::UnitNotSelected),
-            Some(ref s) => {
+            Some(s) => {
                 if s.borrow().id() != id {
                     Err(UnitActivateError::UnitNotSelected)
                 } else {
@@ -684,7 +1445,7 @@ impl UnitManager {
                         id.clone(),
                         "scenario starting".to_string(),
                     )));
-                    s.borrow_mut().activate(self, &*self.cfg.lock().unwrap())
+                    s.borrow_mut().activate(self, &self.cfg.locked())
                 }
             }
         }
@@ -693,15 +1454,231 @@ impl UnitManager {
     fn activate_test(&self, id: &UnitName) -> Result<(), UnitActivateError> {
         match self.tests.borrow().get(id) {
             None => Err(UnitActivateError::UnitNotFound),
-            Some(s) => s.borrow_mut().activate(self, &*self.cfg.lock().unwrap()),
+            Some(s) => {
+                if let Err(e) = self.apply_overrides(&mut s.borrow_mut()) {
+                    let e = format!("the jig's overrides aren't valid: {}", e);
+                    Test::report_start_failure(
+                        id,
+                        &self.control_sender,
+                        format!("unable to start test: {}", e),
+                    );
+                    return Err(UnitActivateError::OverrideFailed(e));
+                }
+                if s.borrow().concurrency_group().is_some() {
+                    let claimed = self.claim_slot(&s.borrow());
+                    match claimed {
+                        Ok(true) => (),
+                        // It's started once a slot comes free.
+                        Ok(false) => return Ok(()),
+                        Err(e) => {
+                            Test::report_start_failure(
+                                id,
+                                &self.control_sender,
+                                format!("unable to start test: {}", e),
+                            );
+                            return Err(UnitActivateError::ConcurrencyFailed(e));
+                        }
+                    }
+                }
+                self.start_test(id, s)
+            }
         }
     }
 
-    pub fn deactivate(&self, id: &UnitName, reason: &str) {
-        // Don't deactivate an inactive unit.
-        if !self.active.borrow().contains_key(id) {
+    /// Give a test the overrides of the jig it's about to be run on, or
+    /// none if that jig has none for it.
+    fn apply_overrides(&self, test: &mut Test) -> Result<(), UnitDescriptionError> {
+        let jig = match self.current_scenario() {
+            Some(scenario) => self.scenario_jig(scenario.borrow().id()),
+            None => self.selected_jigs.borrow().first().cloned(),
+        };
+        let (jig_id, overrides) = match jig {
+            Some(jig) => {
+                let jig = jig.borrow();
+                (jig.id().clone(), jig.overrides_for(test.id()).to_vec())
+            }
+            None => return test.set_overrides(&[]),
+        };
+        if !overrides.is_empty() {
+            let list: Vec<String> = overrides
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value.as_deref().unwrap_or("")))
+                .collect();
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+                test.id().clone(),
+                format!("using overrides from {}: {}", jig_id, list.join(", ")),
+            )));
+        }
+        test.set_overrides(&overrides)
+    }
+
+    fn start_test(&self, id: &UnitName, test: &RefCell<Test>) -> Result<(), UnitActivateError> {
+        if let Err(e) = self.allocate_for(&test.borrow()) {
+            test.borrow().release_slot();
+            Test::report_start_failure(
+                id,
+                &self.control_sender,
+                format!("unable to start test: {}", e),
+            );
+            return Err(UnitActivateError::AllocationFailed(e));
+        }
+        // Tests get the values the scenario was started with, and the
+        // values of the pass through its Matrix.
+        let mut parameters = self.run_parameters.borrow().clone();
+        if let Some(scenario) = self.current_scenario() {
+            parameters.extend(scenario.borrow().matrix_parameters());
+        }
+        // As well as what the trigger that started the run saw.
+        test.borrow().set_parameters(
+            parameters
+                .iter()
+                .map(|(name, value)| (Parameter::variable(name), value.clone()))
+                .chain(
+                    self.run_payload
+                        .borrow()
+                        .iter()
+                        .map(|payload| (PAYLOAD_VARIABLE.to_owned(), payload.clone())),
+                )
+                .collect(),
+        );
+        let result = test.borrow_mut().activate(self, &self.cfg.locked());
+        if result.is_err() {
+            test.borrow().release_slot();
+        }
+        result
+    }
+
+    /// Take a slot in a test's concurrency group.  Returns false if they're
+    /// all taken, in which case the test is sent SlotFree when one may have
+    /// come free.
+    fn claim_slot(&self, test: &Test) -> Result<bool, String> {
+        let group = match test.concurrency_group() {
+            Some(group) => group.to_owned(),
+            None => return Ok(true),
+        };
+        let (directory, limit) = {
+            let config = self.cfg.locked();
+            (config.lock_directory(), config.concurrency_limit(&group))
+        };
+        match concurrency::try_acquire(&directory, &group, limit) {
+            Ok(Some(slot)) => {
+                test.set_slot(slot);
+                Ok(true)
+            }
+            Ok(None) => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+                    test.id().clone(),
+                    format!(
+                        "waiting for a free slot in concurrency group {} (limit {})",
+                        group, limit
+                    ),
+                )));
+                let cancelled = Arc::new(AtomicBool::new(false));
+                test.wait_for_slot(cancelled.clone());
+                let id = test.id().clone();
+                let ctrl = self.control_sender.clone();
+                concurrency::when_free(directory, group, limit, cancelled, move || {
+                    ctrl.send(ManagerControlMessage::new(
+                        &id,
+                        ManagerControlMessageContents::SlotFree,
+                    ))
+                    .ok();
+                });
+                Ok(false)
+            }
+            Err(e) => Err(format!(
+                "unable to use concurrency group {} in {}: {}",
+                group,
+                directory.display(),
+                e
+            )),
+        }
+    }
+
+    /// Start a test that was waiting for a slot, if it can get one now.
+    fn slot_free(&self, id: &UnitName) {
+        let tests = self.tests.borrow();
+        let test = match tests.get(id) {
+            Some(test) => test,
+            None => return,
+        };
+        // It may have been stopped while it was waiting.
+        if !test.borrow().stop_waiting() {
             return;
         }
+        let claimed = self.claim_slot(&test.borrow());
+        let result = match claimed {
+            Ok(true) => self.start_test(id, test),
+            Ok(false) => Ok(()),
+            Err(e) => {
+                Test::report_start_failure(
+                    id,
+                    &self.control_sender,
+                    format!("unable to start test: {}", e),
+                );
+                Err(UnitActivateError::ConcurrencyFailed(e))
+            }
+        };
+        if let Err(e) = result {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                id.clone(),
+                format!("unable to start test: {}", e),
+            )));
+        }
+    }
+
+    /// Give a test the values it needs from allocators, handing out new
+    /// ones the first time they're needed in a run.
+    fn allocate_for(&self, test: &Test) -> Result<(), String> {
+        let mut allocations = vec![];
+        for allocator_id in test.allocate() {
+            let allocators = self.allocators.borrow();
+            let allocator = match allocators.get(allocator_id) {
+                Some(allocator) => allocator.borrow(),
+                None => return Err(format!("allocator {} isn't loaded", allocator_id)),
+            };
+            let existing = self.run_allocations.borrow().get(allocator_id).cloned();
+            let value = match existing {
+                Some(value) => value,
+                None => {
+                    let mut config = self.cfg.locked();
+                    let value = allocator
+                        .allocate(&mut config)
+                        .map_err(|e| format!("unable to allocate from {}: {}", allocator_id, e))?;
+                    let remaining = allocator.remaining(&config);
+                    drop(config);
+                    let message = match remaining {
+                        Some(remaining) => {
+                            format!("allocated {} for {} ({} left)", value, test.id(), remaining)
+                        }
+                        None => format!("allocated {} for {}", value, test.id()),
+                    };
+                    self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+                        allocator_id.clone(),
+                        message,
+                    )));
+                    self.run_allocations
+                        .borrow_mut()
+                        .insert(allocator_id.clone(), value.clone());
+                    value
+                }
+            };
+            allocations.push((allocator.variable().to_owned(), value));
+        }
+        test.set_allocations(allocations);
+        Ok(())
+    }
+
+    pub fn deactivate(&self, id: &UnitName, reason: &str) {
+        // A unit that's stopped on purpose starts counting restarts afresh.
+        self.supervisor.borrow_mut().forget(id);
+        self.interlock.borrow_mut().forget(id);
+
+        // Don't deactivate an inactive unit.
+        let unit = match self.known::<Active>(id) {
+            Some(unit) => unit,
+            None => return,
+        };
 
         let result = match *id.kind() {
             UnitKind::Interface => self.deactivate_interface(id),
@@ -710,25 +1687,14 @@ impl UnitManager {
             UnitKind::Scenario => self.deactivate_scenario(id),
             UnitKind::Test => self.deactivate_test(id),
             UnitKind::Trigger => self.deactivate_trigger(id),
+            UnitKind::Notifier => self.deactivate_notifier(id),
+            UnitKind::Allocator => self.deactivate_allocator(id),
             UnitKind::Internal => Ok(()),
         };
         match result {
-            Ok(_) => {
-                self.active.borrow_mut().remove(id);
-                self.bc
-                    .broadcast(&UnitEvent::Status(UnitStatusEvent::new_deactivate_success(
-                        id,
-                        reason.to_owned(),
-                    )))
-            }
-            Err(e) => {
-                self.bc
-                    .broadcast(&UnitEvent::Status(UnitStatusEvent::new_deactivate_failure(
-                        id,
-                        format!("unable to deactivate: {}", e),
-                    )))
-            }
-        }
+            Ok(_) => self.step(unit.deactivate(reason.to_owned())),
+            Err(e) => self.step(unit.deactivation_failed(e.to_failure())),
+        };
     }
 
     fn deactivate_interface(&self, id: &UnitName) -> Result<(), UnitDeactivateError> {
@@ -747,6 +1713,22 @@ impl UnitManager {
         }
     }
 
+    fn deactivate_allocator(&self, id: &UnitName) -> Result<(), UnitDeactivateError> {
+        let allocators = self.allocators.borrow();
+        match allocators.get(id) {
+            None => Err(UnitDeactivateError::UnitNotFound),
+            Some(allocator) => allocator.borrow_mut().deactivate(),
+        }
+    }
+
+    fn deactivate_notifier(&self, id: &UnitName) -> Result<(), UnitDeactivateError> {
+        let notifiers = self.notifiers.borrow();
+        match notifiers.get(id) {
+            None => Err(UnitDeactivateError::UnitNotFound),
+            Some(notifier) => notifier.borrow_mut().deactivate(),
+        }
+    }
+
     fn deactivate_logger(&self, id: &UnitName) -> Result<(), UnitDeactivateError> {
         let loggers = self.loggers.borrow();
         match loggers.get(id) {
@@ -764,13 +1746,11 @@ impl UnitManager {
     }
 
     fn deactivate_scenario(&self, id: &UnitName) -> Result<(), UnitDeactivateError> {
-        let current_scenario_opt = self.current_scenario.borrow_mut();
-
         // If the specified scenario isn't the current scenario, then there's nothing to do.
-        match *current_scenario_opt {
+        match self.current_scenario() {
             None => Ok(()),
-            Some(ref s) => {
-                let current_scenario = s.borrow_mut();
+            Some(s) => {
+                let current_scenario = s.borrow();
                 if current_scenario.id() != id {
                     Ok(())
                 } else {
@@ -785,24 +1765,17 @@ impl UnitManager {
     }
 
     fn deactivate_jig(&self, id: &UnitName) -> Result<(), UnitDeactivateError> {
-        let current_opt = self.current_jig.borrow_mut();
-
-        // If the specified jig isn't the current jig, then there's nothing to do.
-        match *current_opt {
+        // If the specified jig isn't selected, then there's nothing to do.
+        match self.get_selected_jig(id) {
             None => Ok(()),
-            Some(ref s) => {
-                let current = s.borrow_mut();
-                if current.id() != id {
-                    Ok(())
-                } else {
-                    current.deactivate()
-                }
-            }
+            Some(jig) => jig.borrow().deactivate(),
         }
     }
 
     pub fn unload(&self, id: &UnitName) {
         self.deselect(id, "unloading");
+        self.supervisor.borrow_mut().forget(id);
+        self.interlock.borrow_mut().forget(id);
         match *id.kind() {
             UnitKind::Interface => self.unload_interface(id),
             UnitKind::Jig => self.unload_jig(id),
@@ -810,8 +1783,313 @@ impl UnitManager {
             UnitKind::Scenario => self.unload_scenario(id),
             UnitKind::Test => self.unload_test(id),
             UnitKind::Trigger => self.unload_trigger(id),
+            UnitKind::Notifier => self.unload_notifier(id),
+            UnitKind::Allocator => self.unload_allocator(id),
             UnitKind::Internal => (),
         }
+        if self.unit_state(id).is_some() {
+            self.transition(id, UnitTransition::Unloaded);
+        }
+    }
+
+    /// Return the current state of a unit, or None if it isn't loaded.
+    pub fn unit_state(&self, id: &UnitName) -> Option<UnitState> {
+        self.states.borrow().get(id).cloned()
+    }
+
+    /// Return the current state of every loaded unit.
+    pub fn unit_states(&self) -> HashMap<UnitName, UnitState> {
+        self.states.borrow().clone()
+    }
+
+    /// Say so if a thread panicked while holding a lock, which was then
+    /// recovered, as whatever that thread was doing was left unfinished.
+    fn report_poisoning(&self) {
+        let total = poison::recovered();
+        let recovered = total - self.poisonings_reported.replace(total);
+        if recovered > 0 {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                UnitName::internal("manager"),
+                format!(
+                    "recovered {} lock(s) left poisoned by a thread that panicked",
+                    recovered
+                ),
+            )));
+        }
+    }
+
+    /// A unit, if it's in the state `S`.
+    fn known<'a, S: TypedState>(&self, id: &'a UnitName) -> Option<Known<'a, S>> {
+        Known::check(id, self.unit_state(id))
+    }
+
+    /// Make a transition of a unit whose state is known.
+    fn step<'a, S: TypedState>(&self, step: Step<'a, S>) -> Known<'a, S> {
+        self.record(step.id(), step.from(), step.to(), step.transition());
+        step.done()
+    }
+
+    /// Announce how loading a unit went, from whichever state it may be
+    /// loaded from.
+    fn finish_load<S: Unselected>(&self, unit: Known<S>, failure: Option<UnitFailure>) {
+        match failure {
+            None => {
+                self.step(unit.load());
+            }
+            Some(failure) => {
+                self.step(unit.load_failed(failure));
+            }
+        }
+    }
+
+    /// Move a unit to its next state and announce the change, where which
+    /// state it's in wasn't known.  Transitions that aren't valid from the
+    /// unit's current state are logged and ignored.
+    fn transition(&self, id: &UnitName, transition: UnitTransition) {
+        let from = self.unit_state(id);
+        let to = match transition.apply(id, from) {
+            Ok(to) => to,
+            Err(e) => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    UnitName::internal("manager"),
+                    format!("{}", e),
+                )));
+                return;
+            }
+        };
+        self.record(id, from, to, &transition);
+    }
+
+    /// Record a unit's new state and announce the change.  This is the only
+    /// place that the manager sends UnitStatusEvents.
+    fn record(
+        &self,
+        id: &UnitName,
+        from: Option<UnitState>,
+        to: Option<UnitState>,
+        transition: &UnitTransition,
+    ) {
+        match to {
+            Some(state) => self.states.borrow_mut().insert(id.clone(), state),
+            None => self.states.borrow_mut().remove(id),
+        };
+        if let Some(event) = transition.status_event(id) {
+            self.bc.broadcast(&UnitEvent::Status(event));
+        }
+        if *id.kind() == UnitKind::Interface {
+            let was_active = from == Some(UnitState::Active);
+            let is_active = to == Some(UnitState::Active);
+            if is_active && !was_active {
+                self.join_failover_group(id);
+            } else if was_active && !is_active {
+                self.leave_failover_group(id);
+            }
+        }
+    }
+
+    /// The FailoverGroup an interface belongs to, if any.
+    fn failover_group(&self, id: &UnitName) -> Option<String> {
+        self.interfaces
+            .borrow()
+            .get(id)
+            .and_then(|interface| interface.borrow().failover_group().cloned())
+    }
+
+    /// Add an interface that has started to the end of its FailoverGroup,
+    /// putting it in control if nothing else in the group is running.
+    fn join_failover_group(&self, id: &UnitName) {
+        let group = match self.failover_group(id) {
+            Some(group) => group,
+            None => return,
+        };
+        let primary = {
+            let mut groups = self.failover_groups.borrow_mut();
+            let members = groups.entry(group.clone()).or_default();
+            if !members.contains(id) {
+                members.push(id.clone());
+            }
+            members[0].clone()
+        };
+        // It's told its role along with the rest of its initial greeting.
+        if let Some(interface) = self.interfaces.borrow().get(id) {
+            interface.borrow().set_spare(primary != *id);
+        }
+    }
+
+    /// Take an interface that has stopped out of its FailoverGroup.  If it
+    /// was in control, the spare that has been running longest takes over,
+    /// and every member of the group is told.
+    fn leave_failover_group(&self, id: &UnitName) {
+        let group = match self.failover_group(id) {
+            Some(group) => group,
+            None => return,
+        };
+        if let Some(interface) = self.interfaces.borrow().get(id) {
+            interface.borrow().set_spare(true);
+        }
+        let members = {
+            let mut groups = self.failover_groups.borrow_mut();
+            let members = match groups.get_mut(&group) {
+                Some(members) => members,
+                None => return,
+            };
+            let was_primary = members.first() == Some(id);
+            members.retain(|member| member != id);
+            if !was_primary || members.is_empty() {
+                if members.is_empty() {
+                    groups.remove(&group);
+                }
+                return;
+            }
+            members.clone()
+        };
+        let primary = members[0].clone();
+        if let Some(interface) = self.interfaces.borrow().get(&primary) {
+            interface.borrow().set_spare(false);
+        }
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+            primary.clone(),
+            format!(
+                "now in control of failover group {}, since {} stopped",
+                group, id
+            ),
+        )));
+        for member in &members {
+            self.send_messages_to(
+                member,
+                vec![ManagerStatusMessage::Role(
+                    *member == primary,
+                    group.clone(),
+                    primary.clone(),
+                )],
+            );
+        }
+    }
+
+    /// The interface in control of the FailoverGroup that a spare belongs
+    /// to, or None if the interface isn't a spare.
+    fn primary_for(&self, id: &UnitName) -> Option<UnitName> {
+        let group = self.failover_group(id)?;
+        let groups = self.failover_groups.borrow();
+        let primary = groups.get(&group)?.first()?;
+        if primary == id {
+            None
+        } else {
+            Some(primary.clone())
+        }
+    }
+
+    /// How a unit is restarted if it exits without being stopped.  Only
+    /// interfaces, loggers, triggers, and daemon tests are restarted.
+    fn restart_settings(&self, id: &UnitName) -> Restart {
+        let restart = match *id.kind() {
+            UnitKind::Interface => self
+                .interfaces
+                .borrow()
+                .get(id)
+                .map(|u| u.borrow().restart().clone()),
+            UnitKind::Logger => self
+                .loggers
+                .borrow()
+                .get(id)
+                .map(|u| u.borrow().restart().clone()),
+            UnitKind::Trigger => self
+                .triggers
+                .borrow()
+                .get(id)
+                .map(|u| u.borrow().restart().clone()),
+            UnitKind::Test => self.get_test_named(id).map(|u| u.borrow().restart()),
+            _ => None,
+        };
+        restart.unwrap_or_default()
+    }
+
+    /// Arrange for a unit that exited to be started again, if its Restart
+    /// policy says to.  Returns false if it's to be left stopped.
+    fn schedule_restart(&self, id: &UnitName, failed: bool) -> bool {
+        let restart = self.restart_settings(id);
+        let decision = self.supervisor.borrow_mut().exited(id, &restart, failed);
+        let (delay, in_a_row) = match decision {
+            Decision::Leave => return false,
+            Decision::GiveUp(in_a_row) => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    id.clone(),
+                    format!(
+                        "exited unexpectedly after {} restarts in a row, leaving it stopped",
+                        in_a_row
+                    ),
+                )));
+                return false;
+            }
+            Decision::Restart(delay, in_a_row) => (delay, in_a_row),
+        };
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+            id.clone(),
+            format!(
+                "exited unexpectedly, restarting in {} (restart {} in a row)",
+                format_duration(delay),
+                in_a_row
+            ),
+        )));
+        let id = id.clone();
+        let ctrl = self.control_sender.clone();
+        thread::spawn(move || {
+            thread::sleep(delay);
+            ctrl.send(ManagerControlMessage::new(
+                &id,
+                ManagerControlMessageContents::RestartUnit,
+            ))
+            .ok();
+        });
+        true
+    }
+
+    /// Start a unit that exited on its own again, unless it's been stopped,
+    /// unloaded, or started again some other way in the meantime.
+    fn restart(&self, id: &UnitName) {
+        if *id.kind() == UnitKind::Test {
+            return self.restart_daemon(id);
+        }
+        if self.unit_state(id) != Some(UnitState::Selected) {
+            return;
+        }
+        // The process has already gone, but is still waited for.
+        match *id.kind() {
+            UnitKind::Interface => self.deactivate_interface(id).ok(),
+            UnitKind::Logger => self.deactivate_logger(id).ok(),
+            UnitKind::Trigger => self.deactivate_trigger(id).ok(),
+            _ => return,
+        };
+        self.activate(id);
+        // One that can't even be started is tried again later.
+        if self.unit_state(id) != Some(UnitState::Active) {
+            self.schedule_restart(id, true);
+        }
+    }
+
+    /// Start a daemon test that exited on its own again, if its scenario is
+    /// still running.  Daemons stay active until the scenario stops them.
+    fn restart_daemon(&self, id: &UnitName) {
+        if self.unit_state(id) != Some(UnitState::Active) {
+            return;
+        }
+        let test = match self.get_test_named(id) {
+            Some(test) => test,
+            None => return,
+        };
+        let result = test.borrow_mut().respawn(self, &self.cfg.locked());
+        match result {
+            Ok(()) => self.supervisor.borrow_mut().started(id),
+            Err(e) => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    id.clone(),
+                    format!("unable to restart daemon: {}", e),
+                )));
+                if !self.schedule_restart(id, true) {
+                    test.borrow().report_finished(&self.control_sender, -1);
+                }
+            }
+        }
     }
 
     fn unload_interface(&self, id: &UnitName) {
@@ -828,6 +2106,20 @@ impl UnitManager {
         self.triggers.borrow_mut().remove(id);
     }
 
+    fn unload_allocator(&self, id: &UnitName) {
+        self.deactivate(id, "allocator is being unloaded");
+        self.deselect(id, "allocator is being unloaded");
+
+        self.allocators.borrow_mut().remove(id);
+    }
+
+    fn unload_notifier(&self, id: &UnitName) {
+        self.deactivate(id, "notifier is being unloaded");
+        self.deselect(id, "notifier is being unloaded");
+
+        self.notifiers.borrow_mut().remove(id);
+    }
+
     fn unload_logger(&self, id: &UnitName) {
         self.deactivate(id, "logger is being unloaded");
         self.deselect(id, "logger is being unloaded");
@@ -847,6 +2139,9 @@ impl UnitManager {
         self.deselect(id, "test is being unloaded");
 
         self.tests.borrow_mut().remove(id);
+        self.set_aliases(id, &[]);
+        self.set_provides(id, &[]);
+        self.forget_resolutions(slice::from_ref(id));
     }
 
     fn unload_scenario(&self, id: &UnitName) {
@@ -854,9 +2149,19 @@ impl UnitManager {
         self.deselect(id, "scenario is being unloaded");
 
         self.scenarios.borrow_mut().remove(id);
+        self.incompatible_scenarios.borrow_mut().remove(id);
+        self.scenario_resolutions.borrow_mut().remove(id);
+        self.set_aliases(id, &[]);
         self.broadcast_scenario_list();
     }
 
+    /// The selected scenario, if any.  This is a clone of the Rc, so that
+    /// current_scenario isn't left borrowed while the scenario is used,
+    /// since using it may select or deselect another.
+    fn current_scenario(&self) -> Option<Rc<RefCell<Scenario>>> {
+        self.current_scenario.borrow().clone()
+    }
+
     pub fn get_scenario_named(&self, id: &UnitName) -> Option<Rc<RefCell<Scenario>>> {
         self.scenarios.borrow().get(id).cloned()
     }
@@ -877,10 +2182,76 @@ impl UnitManager {
         self.jigs.borrow().get(id).is_some()
     }
 
+    /// The features a loaded jig is fitted with.
+    pub fn jig_features(&self, id: &UnitName) -> Vec<String> {
+        self.jigs
+            .borrow()
+            .get(id)
+            .map(|jig| jig.borrow().features().clone())
+            .unwrap_or_default()
+    }
+
+    /// Every loaded unit of the given kind, sorted by name.
+    pub fn units(&self, kind: &UnitKind) -> Vec<UnitName> {
+        let mut units: Vec<UnitName> = self
+            .states
+            .borrow()
+            .keys()
+            .filter(|id| id.kind() == kind)
+            .cloned()
+            .collect();
+        units.sort();
+        units
+    }
+
+    /// Describe a loaded unit, or return None if it isn't loaded.
+    pub fn unit_info(&self, id: &UnitName) -> Option<UnitInfo> {
+        macro_rules! info {
+            ($units:ident) => {
+                info!($units, |_| Presentation::default())
+            };
+            ($units:ident, $presentation:expr) => {
+                self.$units.borrow().get(id).map(|u| {
+                    let u = u.borrow();
+                    (u.name().clone(), u.description().clone(), $presentation(&u))
+                })
+            };
+        }
+        let (name, description, presentation) = match *id.kind() {
+            UnitKind::Interface => info!(interfaces),
+            UnitKind::Jig => info!(jigs, |u: &Jig| u.presentation().clone()),
+            UnitKind::Logger => info!(loggers),
+            UnitKind::Scenario => info!(scenarios, |u: &Scenario| u.presentation().clone()),
+            UnitKind::Test => info!(tests, |u: &Test| u.presentation().clone()),
+            UnitKind::Trigger => info!(triggers),
+            UnitKind::Notifier => info!(notifiers),
+            UnitKind::Allocator => info!(allocators),
+            UnitKind::Internal => None,
+        }?;
+        Some(UnitInfo {
+            id: id.clone(),
+            name,
+            description,
+            state: self.unit_state(id).unwrap_or(UnitState::Loaded),
+            jigs: self.unit_jigs(id),
+            presentation,
+        })
+    }
+
+    /// Receive a copy of every event from now on, such as unit status
+    /// changes, log messages, and requests sent to the manager.
+    pub fn subscribe(&self) -> Receiver<Arc<StampedEvent>> {
+        self.bc.subscribe()
+    }
+
     pub fn process_message(&self, msg: &UnitEvent) {
+        self.report_poisoning();
         match msg {
             UnitEvent::ManagerRequest(ref req) => self.manager_request(req),
             UnitEvent::Status(ref stat) => self.status_message(stat),
+            UnitEvent::ScenarioFinished(ref summary) if self.replaying.get() => {
+                self.broadcast_message(ManagerStatusMessage::Finished(summary.clone()))
+            }
             UnitEvent::Log(ref log) => {
                 let mut units_to_deactivate = vec![];
                 for (interface_id, interface) in self.interfaces.borrow().iter() {
@@ -910,10 +2281,7 @@ impl UnitManager {
     }
 
     fn status_message(&self, msg: &UnitStatusEvent) {
-        let &UnitStatusEvent {
-            ref name,
-            ref status,
-        } = msg;
+        let UnitStatusEvent { name, status } = msg;
         match status {
             UnitStatus::Loaded => match name.kind() {
                 UnitKind::Jig => self.broadcast_jig_named(name),
@@ -922,18 +2290,34 @@ impl UnitManager {
                 _ => (),
             },
             UnitStatus::Selected => match name.kind() {
-                UnitKind::Jig => self.broadcast_selected_jig(),
+                UnitKind::Jig => {
+                    self.broadcast_selected_jig();
+                    // Scenarios that need this jig can now be selected.
+                    self.broadcast_scenario_list();
+                }
                 UnitKind::Scenario => self.broadcast_selected_scenario(),
                 _ => (),
             },
+            UnitStatus::Deselected(_) if *name.kind() == UnitKind::Jig => {
+                self.broadcast_scenario_list()
+            }
+            UnitStatus::LoadFailed(ref failure)
+            | UnitStatus::SelectFailed(ref failure)
+            | UnitStatus::ActivationFailed(ref failure)
+            | UnitStatus::DeactivatedUnsuccessfully(ref failure) => self.broadcast_message(
+                ManagerStatusMessage::UnitFailed(name.clone(), failure.clone()),
+            ),
+            UnitStatus::Active if self.replaying.get() && *name.kind() == UnitKind::Scenario => {
+                self.broadcast_message(ManagerStatusMessage::Start(name.clone(), None))
+            }
             _ => (),
         }
     }
 
     fn manager_request(&self, msg: &ManagerControlMessage) {
-        let &ManagerControlMessage {
-            sender: ref sender_name,
-            contents: ref msg,
+        let ManagerControlMessage {
+            sender: sender_name,
+            contents: msg,
         } = msg;
 
         match *msg {
@@ -948,8 +2332,9 @@ impl UnitManager {
                 LogEntry::new_error(sender_name.clone(), txt.clone()),
             )),
             ManagerControlMessageContents::Scenario(ref new_scenario_name) => {
-                if self.get_scenario_named(new_scenario_name).is_some() {
-                    self.select(new_scenario_name);
+                let new_scenario_name = self.unalias(new_scenario_name, sender_name);
+                if self.get_scenario_named(&new_scenario_name).is_some() {
+                    self.select(&new_scenario_name);
                     self.broadcast_selected_scenario();
                 } else {
                     self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
@@ -971,21 +2356,94 @@ impl UnitManager {
                 self.send_jig_to(sender_name);
                 self.send_scenarios_to(sender_name);
                 // If there is a scenario selected, send that too.
-                if let Some(ref sc) = *self.current_scenario.borrow() {
+                if let Some(sc) = self.current_scenario() {
                     self.send_scenario_to(sender_name, &sc.borrow().id().clone());
                 }
+                if let Some(ref problem) = *self.clock_problem.borrow() {
+                    self.send_messages_to(
+                        sender_name,
+                        vec![ManagerStatusMessage::Clock(Some(problem.clone()))],
+                    );
+                }
+                if let Some(group) = self.failover_group(sender_name) {
+                    let primary = self
+                        .failover_groups
+                        .borrow()
+                        .get(&group)
+                        .and_then(|members| members.first().cloned());
+                    if let Some(primary) = primary {
+                        self.send_messages_to(
+                            sender_name,
+                            vec![ManagerStatusMessage::Role(
+                                primary == *sender_name,
+                                group,
+                                primary,
+                            )],
+                        );
+                    }
+                }
+                let maintenance = self.cfg.locked().maintenance().cloned();
+                if maintenance.is_some() {
+                    self.send_messages_to(
+                        sender_name,
+                        vec![ManagerStatusMessage::Mode(maintenance)],
+                    );
+                }
+                let degraded = self
+                    .degraded_jigs
+                    .borrow()
+                    .iter()
+                    .map(|(jig, problem)| {
+                        ManagerStatusMessage::Health(jig.clone(), Some(problem.clone()))
+                    })
+                    .collect();
+                self.send_messages_to(sender_name, degraded);
+                let due = self
+                    .counter_messages()
+                    .into_iter()
+                    .filter_map(|msg| match msg {
+                        ManagerStatusMessage::Counter(jig, name, count, Some(limit))
+                            if count >= limit =>
+                        {
+                            Some(ManagerStatusMessage::MaintenanceDue(
+                                jig, name, count, limit,
+                            ))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                self.send_messages_to(sender_name, due);
             }
             ManagerControlMessageContents::ChildExited => {
-                self.bc
-                    .broadcast(&UnitEvent::Status(UnitStatusEvent::new_active_failed(
+                // Whatever a trigger reported no longer holds.
+                self.interlock.borrow_mut().forget(sender_name);
+                // Units that were deliberately stopped also report exiting.
+                if self.unit_state(sender_name) == Some(UnitState::Active) {
+                    self.transition(
                         sender_name,
-                        "Unit unexpectedly exited".to_owned(),
-                    )));
+                        UnitTransition::ActivationFailed(UnitFailure::new(
+                            "unexpected-exit",
+                            "Unit unexpectedly exited".to_owned(),
+                        )),
+                    );
+                    self.schedule_restart(sender_name, true);
+                }
             }
+            ManagerControlMessageContents::DaemonExited(result) => {
+                // A daemon that isn't restarted has finished.
+                if self.unit_state(sender_name) == Some(UnitState::Active)
+                    && !self.schedule_restart(sender_name, result != 0)
+                {
+                    if let Some(test) = self.get_test_named(sender_name) {
+                        test.borrow().report_finished(&self.control_sender, result);
+                    }
+                }
+            }
+            ManagerControlMessageContents::RestartUnit => self.restart(sender_name),
             ManagerControlMessageContents::AdvanceScenario(result) => {
-                match *self.current_scenario.borrow() {
+                match self.current_scenario() {
                     None => (),
-                    Some(ref current_scenario) => current_scenario.borrow_mut().advance(
+                    Some(current_scenario) => current_scenario.borrow_mut().advance(
                         sender_name,
                         result,
                         &self.control_sender,
@@ -998,53 +2456,153 @@ impl UnitManager {
                     format!("unimplemented verb: {} (args: {})", verb, remainder),
                 )));
             }
-            ManagerControlMessageContents::StartScenario(ref scenario_name_opt) => {
-                // If a scenario exists and is running, don't start a new one.
-                if let Some(ref scenario) = *self.current_scenario.borrow() {
-                    if scenario.borrow().is_running() {
-                        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
-                            sender_name.clone(),
-                            "unable to start scenario: scenario not idle".to_owned(),
-                        )));
-                        return;
-                    }
-                }
-                let scenario_name = if let Some(ref scenario_name) = *scenario_name_opt {
-                    self.select(scenario_name);
-                    scenario_name.clone()
+            ManagerControlMessageContents::StartScenario(
+                ref scenario_name_opt,
+                ref parameters,
+                ref payload,
+            ) => {
+                if *sender_name.kind() == UnitKind::Trigger {
+                    self.trigger_fired(sender_name, scenario_name_opt, parameters, payload)
                 } else {
-                    match *self.current_scenario.borrow() {
-                        None => {
-                            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(sender_name.clone(), "unable to start scenario: no scenario selected and no scenario specified".to_owned())));
-                            return;
-                        }
-                        Some(ref scenario) => scenario.borrow().id().clone(),
-                    }
-                };
-
-                self.activate(&scenario_name);
-                self.broadcast_message(ManagerStatusMessage::Start(scenario_name));
+                    self.start_scenario(sender_name, scenario_name_opt, parameters, payload)
+                }
+            }
+            ManagerControlMessageContents::TriggerState(ref state) => {
+                if self.interlock.borrow_mut().set_state(sender_name, state) {
+                    self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+                        sender_name.clone(),
+                        format!("state: {}", state),
+                    )));
+                }
             }
+            ManagerControlMessageContents::RerunFailed => self.rerun_failed(sender_name),
             ManagerControlMessageContents::Skip(ref test_name, ref reason) => {
                 self.broadcast_skipped(test_name, reason);
             }
             ManagerControlMessageContents::TestStarted => {
                 self.broadcast_message(ManagerStatusMessage::Running(sender_name.clone()));
             }
+            ManagerControlMessageContents::SlotFree => self.slot_free(sender_name),
             ManagerControlMessageContents::TestFinished(result, ref message) => {
-                self.broadcast_message(match result {
-                    0 => ManagerStatusMessage::Pass(sender_name.clone(), message.clone()),
-                    i => ManagerStatusMessage::Fail(sender_name.clone(), i, message.clone()),
+                // The slot goes to the next test as soon as this one is done.
+                if let Some(test) = self.get_test_named(sender_name) {
+                    test.borrow().release_slot();
+                }
+                let mut message = message.clone();
+                // A replayed test may share a name with one loaded here, but
+                // its output and artifacts aren't here to look at.
+                let test = self
+                    .get_test_named(sender_name)
+                    .filter(|_| !self.replaying.get());
+                let (verdict, output_path, artifacts, cost) = match test {
+                    Some(test) => {
+                        let test = test.borrow();
+                        let config = self.cfg.locked();
+                        if let Some(serial) = test.serial() {
+                            config.report_serial(&serial);
+                        }
+                        let mut artifacts = test.save_artifacts(&self.control_sender, &config);
+                        if let Some(reason) = test.output_reason(result) {
+                            message = reason;
+                        }
+                        if let Some(crash) = test.examine_crash(result, &config) {
+                            artifacts.extend(test.save_core_dump(
+                                &crash,
+                                &self.control_sender,
+                                &config,
+                            ));
+                            message = crash.to_string();
+                        }
+                        (
+                            test.verdict(result),
+                            test.output_path(),
+                            artifacts,
+                            test.cost(),
+                        )
+                    }
+                    None if result == 0 => (TestVerdict::Pass, None, vec![], None),
+                    None => (TestVerdict::Fail, None, vec![], None),
+                };
+                if let Some(cost) = cost {
+                    if let Some(ref mut meter) = *self.scenario_cost.borrow_mut() {
+                        meter.include(cost);
+                    }
+                    self.report_cost(sender_name, cost);
+                }
+                if let Some(path) = output_path {
+                    self.broadcast_message(ManagerStatusMessage::Output(sender_name.clone(), path));
+                }
+                for (name, size) in artifacts {
+                    self.broadcast_message(ManagerStatusMessage::Artifact(
+                        sender_name.clone(),
+                        name,
+                        size,
+                    ));
+                }
+                self.broadcast_message(match verdict {
+                    TestVerdict::Pass => ManagerStatusMessage::Pass(sender_name.clone(), message),
+                    TestVerdict::Fail => {
+                        ManagerStatusMessage::Fail(sender_name.clone(), result, message)
+                    }
+                    TestVerdict::Fault => {
+                        ManagerStatusMessage::Fault(sender_name.clone(), result, message)
+                    }
+                    TestVerdict::Skip => {
+                        ManagerStatusMessage::Skipped(sender_name.clone(), message)
+                    }
+                    TestVerdict::Retry => {
+                        ManagerStatusMessage::Retry(sender_name.clone(), result, message)
+                    }
                 });
             }
             ManagerControlMessageContents::ScenarioFinished(code, ref message) => {
                 // Deactivate the current scenario.
                 // Since a scenario is finishing, the current scenario MUST not be None.
-                {
-                    let cs = self.current_scenario.borrow();
-                    self.deactivate(cs.as_ref().unwrap().borrow().id(), message);
+                let scenario = self.current_scenario().unwrap();
+                let cost = self.scenario_cost.borrow_mut().take().map(|m| m.cost());
+                let (id, summary) = {
+                    let scenario = scenario.borrow();
+                    let mut summary = scenario.summary(code, message);
+                    summary.allocations = self
+                        .run_allocations
+                        .borrow()
+                        .iter()
+                        .map(|(allocator, value)| (allocator.clone(), value.clone()))
+                        .collect();
+                    summary.parameters = self.run_parameters.borrow().clone();
+                    summary.payload = self.run_payload.borrow().clone();
+                    summary.jig = self
+                        .scenario_jig(scenario.id())
+                        .map(|jig| jig.borrow().id().clone());
+                    summary.maintenance = self.cfg.locked().maintenance().is_some();
+                    summary.clock_unsynced = self.run_clock_unsynced.get();
+                    summary.notes = self.run_notes.borrow().clone();
+                    summary.log_path = self.cfg.locked().run_directory();
+                    summary.cpu = cost.and_then(|c| c.cpu);
+                    summary.peak_memory_kb = cost.and_then(|c| c.peak_memory_kb);
+                    summary.station = Some(self.cfg.locked().station().clone());
+                    summary.finished = Some(timeline::now());
+                    (scenario.id().clone(), summary)
+                };
+                self.deactivate(&id, message);
+                if let Some(cost) = cost {
+                    self.report_cost(sender_name, cost);
+                }
+                self.record_calibration(&summary);
+                self.count_cycles(&summary);
+                self.notify(&summary);
+                self.run_hooks(&summary);
+                self.check_flakiness(&summary);
+                self.record_duration(&summary);
+                self.report_soak(&summary);
+                self.report_certificate(&summary);
+                self.bc
+                    .broadcast(&UnitEvent::ScenarioFinished(summary.clone()));
+                *self.finished_run.borrow_mut() = Some(summary.clone());
+                self.broadcast_message(ManagerStatusMessage::Finished(summary));
+                if let Some(run) = self.cfg.locked().run_directory() {
+                    self.bc.broadcast(&UnitEvent::RunFinished(run));
                 }
-                self.broadcast_finished(sender_name, code, message);
             }
             ManagerControlMessageContents::StartTest(ref test_name) => {
                 self.activate(test_name);
@@ -1063,52 +2621,1110 @@ impl UnitManager {
                 )));
                 self.bc.broadcast(&UnitEvent::Shutdown);
             }
-            ManagerControlMessageContents::AbortTests => {
-                if let Some(scenario) = &mut *self.current_scenario.borrow_mut() {
-                    scenario.borrow().indicate_failure();
-                    for test in (&*self.tests.borrow()).values() {
-                        test.borrow().deactivate(self).ok();
-                    }
+            ManagerControlMessageContents::AbortTests => self.abort(sender_name),
+            ManagerControlMessageContents::ReloadConfig => self.reload_config(sender_name),
+            // Enabling and disabling units is handled by the UnitLibrary.
+            ManagerControlMessageContents::DisableUnit(_) => (),
+            ManagerControlMessageContents::EnableUnit(_) => (),
+            ManagerControlMessageContents::Snapshot => (),
+            ManagerControlMessageContents::UnitStates => self.send_unit_states_to(sender_name),
+            ManagerControlMessageContents::Heartbeat => self.send_heartbeat(sender_name),
+            ManagerControlMessageContents::FlushBatch => self.flush_batch(sender_name),
+            ManagerControlMessageContents::Framing(framing) => {
+                let result = match self.interfaces.borrow().get(sender_name) {
+                    Some(interface) => interface.borrow().set_framing(framing),
+                    None => return,
+                };
+                if let Err(e) = result {
+                    self.deactivate(sender_name, &format!("communication error: {}", e));
                 }
             }
-        }
-    }
+            ManagerControlMessageContents::Pong(ref id) => {
+                let known = match self.interfaces.borrow().get(sender_name) {
+                    Some(interface) => interface.borrow().pong(id),
+                    None => true,
+                };
+                if !known {
+                    self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                        sender_name.clone(),
+                        format!("PONG for unknown PING {}", id),
+                    )));
+                }
+            }
+            ManagerControlMessageContents::ClockStatus(ref problem) => {
+                self.update_clock_status(sender_name, problem)
+            }
+            ManagerControlMessageContents::FetchArtifact(ref test_name, ref artifact) => {
+                self.send_artifact_to(sender_name, test_name, artifact)
+            }
+            ManagerControlMessageContents::TestInput(ref test_name, ref text) => {
+                let result = match self.get_test_named(test_name) {
+                    Some(test) if self.unit_state(test_name) == Some(UnitState::Active) => {
+                        test.borrow().send_input(text)
+                    }
+                    _ => Err(io::Error::other("test is not running")),
+                };
+                if let Err(e) = result {
+                    self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                        sender_name.clone(),
+                        format!("unable to send input to {}: {}", test_name, e),
+                    )));
+                }
+            }
+            ManagerControlMessageContents::Note(ref note) => self.add_note(sender_name, note),
+            ManagerControlMessageContents::Stats => self.send_stats_to(sender_name),
+            ManagerControlMessageContents::Counters => self.send_counters_to(sender_name),
+            ManagerControlMessageContents::Schema(ref kind) => {
+                self.send_schema_to(sender_name, kind.as_ref())
+            }
+            ManagerControlMessageContents::SetMaintenance(ref reason) => {
+                self.set_maintenance(sender_name, reason)
+            }
+            ManagerControlMessageContents::ResetCounter(ref jig, ref counter) => {
+                self.reset_counter(sender_name, jig, counter)
+            }
+            ManagerControlMessageContents::Store(ref jig) => self.send_store_to(sender_name, jig),
+            ManagerControlMessageContents::ResetStore(ref jig, ref key) => {
+                self.reset_store(sender_name, jig, key.as_ref().map(|k| k.as_str()))
+            }
+            ManagerControlMessageContents::Denied(ref verb) => self.deny(sender_name, verb),
+            ManagerControlMessageContents::Version => {
+                self.send_messages_to(sender_name, vec![ManagerStatusMessage::Version])
+            }
+            ManagerControlMessageContents::Station => {
+                let station = self.cfg.locked().station().clone();
+                self.send_messages_to(sender_name, vec![ManagerStatusMessage::Station(station)])
+            }
+        }
+    }
 
-    pub fn send_hello_to(&self, sender_name: &UnitName) {
+    /// Refuse a verb from an observer interface, or a spare in a
+    /// FailoverGroup, telling only that interface why.
+    fn deny(&self, sender_name: &UnitName, verb: &str) {
+        let message = match self.primary_for(sender_name) {
+            Some(primary) => format!(
+                "spares may not send {} while {} is in control",
+                verb.to_uppercase(),
+                primary
+            ),
+            None => format!("observers may not send {}", verb.to_uppercase()),
+        };
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+            sender_name.clone(),
+            message.clone(),
+        )));
         self.send_messages_to(
             sender_name,
-            vec![ManagerStatusMessage::Hello("Jig/20 1.0".to_owned())],
+            vec![ManagerStatusMessage::UnitFailed(
+                sender_name.clone(),
+                UnitFailure::new("not-permitted", message),
+            )],
         );
     }
 
-    pub fn send_jig_to(&self, sender_name: &UnitName) {
-        let messages = match *self.current_jig.borrow() {
-            None => vec![ManagerStatusMessage::Jig(None)],
-            Some(ref jig_rc) => {
-                let jig = jig_rc.borrow();
-                vec![
-                    ManagerStatusMessage::Jig(Some(jig.id().clone())),
-                    ManagerStatusMessage::Describe(
-                        jig.id().clone(),
-                        FieldType::Name,
-                        jig.name().clone(),
-                    ),
-                    ManagerStatusMessage::Describe(
-                        jig.id().clone(),
-                        FieldType::Description,
-                        jig.description().clone(),
+    /// Start running a scenario, or the current scenario if None.
+    fn start_scenario(
+        &self,
+        sender_name: &UnitName,
+        scenario_name_opt: &Option<UnitName>,
+        parameters: &[(String, String)],
+        payload: &Option<String>,
+    ) {
+        if self.replaying.get() {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                sender_name.clone(),
+                "unable to start scenario: a recording is being replayed".to_owned(),
+            )));
+            return;
+        }
+        // If a scenario exists and is running, don't start a new one.
+        if let Some(scenario) = self.current_scenario() {
+            if scenario.borrow().is_running() {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender_name.clone(),
+                    "unable to start scenario: scenario not idle".to_owned(),
+                )));
+                return;
+            }
+        }
+        let scenario_name = if let Some(ref scenario_name) = *scenario_name_opt {
+            let scenario_name = self.unalias(scenario_name, sender_name);
+            self.select(&scenario_name);
+            scenario_name
+        } else {
+            match self.current_scenario() {
+                None => {
+                    self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                        sender_name.clone(),
+                        "unable to start scenario: no scenario selected and no scenario specified"
+                            .to_owned(),
+                    )));
+                    return;
+                }
+                Some(scenario) => scenario.borrow().id().clone(),
+            }
+        };
+
+        // In maintenance mode, triggers are disarmed and only diagnostics run.
+        let maintenance = self.cfg.locked().maintenance().cloned();
+        if let Some(reason) = maintenance {
+            let refusal = if *sender_name.kind() == UnitKind::Trigger {
+                Some("triggers are disarmed")
+            } else if self
+                .scenarios
+                .borrow()
+                .get(&scenario_name)
+                .is_some_and(|s| !s.borrow().is_diagnostic())
+            {
+                Some("only diagnostic scenarios may run")
+            } else {
+                None
+            };
+            if let Some(refusal) = refusal {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender_name.clone(),
+                    format!(
+                        "unable to start scenario: {} in maintenance mode ({})",
+                        refusal, reason
                     ),
-                ]
+                )));
+                return;
+            }
+        }
+
+        // A jig that must be calibrated only runs its calibration scenario
+        // until the calibration is renewed.
+        if let Some(jig) = self.scenario_jig(&scenario_name) {
+            let jig_id = jig.borrow().id().clone();
+            if let Some(problem) = self.check_calibration(&jig_id) {
+                let jig = jig.borrow();
+                if jig.requires_calibration() && jig.calibration_scenario() != Some(&scenario_name)
+                {
+                    self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                        sender_name.clone(),
+                        format!(
+                            "unable to start scenario: jig {} needs calibrating with {} ({})",
+                            jig_id,
+                            jig.calibration_scenario().unwrap(),
+                            problem
+                        ),
+                    )));
+                    return;
+                }
+            }
+        }
+
+        // The scenario's parameters must all be given, and make sense.
+        let (declared, matrix) = self
+            .scenarios
+            .borrow()
+            .get(&scenario_name)
+            .map(|s| {
+                let s = s.borrow();
+                (s.parameters().to_vec(), s.matrix().to_vec())
+            })
+            .unwrap_or_default();
+        let parameters = match parameter::check(&declared, &matrix, parameters) {
+            Ok(parameters) => parameters,
+            Err(e) => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender_name.clone(),
+                    format!("unable to start scenario: {}", e),
+                )));
+                return;
+            }
+        };
+
+        // Tests run with the working directory of whichever jig
+        // this scenario is bound to.
+        let jig_name = self.scenario_jig(&scenario_name).map(|jig| {
+            let jig = jig.borrow();
+            jig.bind(&self.cfg.locked());
+            jig.id().clone()
+        });
+        match jig_name {
+            Some(ref jig_name) => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+                scenario_name.clone(),
+                format!("running on jig {}", jig_name),
+            ))),
+            None => self.cfg.locked().set_bound_jig(None),
+        }
+
+        *self.scenario_cost.borrow_mut() = Some(CostMeter::start(self.cfg.locked().clock()));
+        self.run_allocations.borrow_mut().clear();
+        self.run_notes.borrow_mut().clear();
+        *self.finished_run.borrow_mut() = None;
+        if !parameters.is_empty() {
+            let list: Vec<String> = parameters
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+                scenario_name.clone(),
+                format!("parameters: {}", list.join(", ")),
+            )));
+        }
+        *self.run_parameters.borrow_mut() = parameters;
+        if let Some(ref payload) = *payload {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+                scenario_name.clone(),
+                format!("payload from {}: {}", sender_name, payload),
+            )));
+        }
+        *self.run_payload.borrow_mut() = payload.clone();
+        self.activate(&scenario_name);
+        self.flag_unsynced_run();
+        let estimate = self.estimated_duration(&scenario_name);
+        self.broadcast_message(ManagerStatusMessage::Start(scenario_name.clone(), jig_name));
+        if let Some(estimate) = estimate {
+            self.broadcast_message(ManagerStatusMessage::Estimate(scenario_name, estimate));
+        }
+    }
+
+    /// A trigger sent START.  If it's part of a trigger made of others, that
+    /// trigger starts the run once they've all sent START, and either way the
+    /// run only starts if the states the trigger requires hold.
+    fn trigger_fired(
+        &self,
+        sender_name: &UnitName,
+        scenario_name_opt: &Option<UnitName>,
+        parameters: &[(String, String)],
+        payload: &Option<String>,
+    ) {
+        let now = self.cfg.locked().clock().instant();
+        let triggers = self.triggers.borrow().clone();
+        let composites: Vec<_> = triggers
+            .values()
+            .filter(|trigger| trigger.borrow().all_of().contains(sender_name))
+            .collect();
+        let fired = if composites.is_empty() {
+            sender_name.clone()
+        } else {
+            let mut interlock = self.interlock.borrow_mut();
+            interlock.press(sender_name, now);
+            let composite = composites.into_iter().find(|composite| {
+                let composite = composite.borrow();
+                interlock.together(composite.all_of(), composite.window(), now)
+            });
+            match composite {
+                Some(composite) => composite.borrow().id().clone(),
+                // Still waiting for the others.
+                None => return,
+            }
+        };
+        let route = triggers
+            .get(&fired)
+            .map(|trigger| trigger.borrow().route(scenario_name_opt, payload))
+            .unwrap_or_else(|| Ok(Route::Start(scenario_name_opt.clone())));
+        let required = triggers
+            .get(&fired)
+            .map(|trigger| trigger.borrow().required_states().to_vec())
+            .unwrap_or_default();
+        // Aborts are never held back.
+        let checked = route.and_then(|route| match route {
+            Route::Abort => Ok(route),
+            _ => self.interlock.borrow().check(&required).map(|_| route),
+        });
+        match checked {
+            Ok(Route::Start(scenario_name_opt)) => {
+                self.start_scenario(&fired, &scenario_name_opt, parameters, payload)
+            }
+            Ok(Route::Abort) => self.abort(&fired),
+            Ok(Route::RerunFailed) => self.rerun_failed(&fired),
+            Err(e) => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                fired,
+                format!("unable to start scenario: {}", e),
+            ))),
+        }
+    }
+
+    /// Run the current scenario again, but only with the tests that failed
+    /// or were skipped last time, and the tests they depend on.
+    fn rerun_failed(&self, sender_name: &UnitName) {
+        let result = match self.current_scenario() {
+            None => Err("no scenario selected".to_owned()),
+            Some(ref scenario) if scenario.borrow().is_running() => {
+                Err("scenario not idle".to_owned())
+            }
+            Some(scenario) => scenario.borrow_mut().rerun_failed(),
+        };
+        match result {
+            Ok(tests) => {
+                let names: Vec<String> = tests.iter().map(|t| t.to_string()).collect();
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+                    sender_name.clone(),
+                    format!("rerunning {}", names.join(", ")),
+                )));
+                // The rerun is given the same parameters and payload.
+                let parameters = self.run_parameters.borrow().clone();
+                let payload = self.run_payload.borrow().clone();
+                self.start_scenario(sender_name, &None, &parameters, &payload);
+            }
+            Err(e) => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                sender_name.clone(),
+                format!("unable to rerun failed tests: {}", e),
+            ))),
+        }
+    }
+
+    /// Stop the running scenario's tests and fail the run, such as for an
+    /// emergency stop button.  Aborts are never held back the way starts
+    /// can be.
+    fn abort(&self, sender_name: &UnitName) {
+        let scenario = match self.current_scenario() {
+            Some(scenario) => scenario,
+            None => return,
+        };
+        let scenario_name = scenario.borrow().id().clone();
+        scenario.borrow().indicate_failure();
+        if scenario.borrow().is_running() {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                sender_name.clone(),
+                format!("aborting {}", scenario_name),
+            )));
+            self.broadcast_message(ManagerStatusMessage::Aborted(
+                scenario_name,
+                sender_name.clone(),
+            ));
+        }
+        let tests: Vec<Rc<RefCell<Test>>> = self.tests.borrow().values().cloned().collect();
+        for test in tests {
+            test.borrow().deactivate(self).ok();
+        }
+    }
+
+    /// Record an operator's note about the current (or most recent) run.  It
+    /// is logged, so it ends up in every logger, appended to the run's notes
+    /// file if there's a run directory, and kept for the run's summary.  If
+    /// the run has already finished, its summary is sent out again with the
+    /// note, so that it's recorded with the run's results.
+    fn add_note(&self, sender_name: &UnitName, note: &str) {
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+            sender_name.clone(),
+            format!("note: {}", note),
+        )));
+        let now = self.cfg.locked().clock().now();
+        if let Some(run) = self.cfg.locked().run_directory() {
+            let path = run.join(RUN_NOTES_FILE);
+            let result = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| {
+                    writeln!(
+                        file,
+                        "{}\t{}\t{}",
+                        format_rfc3339_seconds(now),
+                        sender_name,
+                        note.replace(['\r', '\n'], " ")
+                    )
+                });
+            if let Err(e) = result {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender_name.clone(),
+                    format!("unable to write {}: {}", path.display(), e),
+                )));
+            }
+        }
+        self.run_notes
+            .borrow_mut()
+            .push(note.replace(['\r', '\n'], " "));
+        if let Some(ref mut summary) = *self.finished_run.borrow_mut() {
+            summary.notes = self.run_notes.borrow().clone();
+            self.bc.broadcast(&UnitEvent::RunNoted(summary.clone()));
+        }
+        self.broadcast_message(ManagerStatusMessage::Note(
+            sender_name.clone(),
+            note.to_owned(),
+        ));
+    }
+
+    /// PING an interface, and disconnect it if it stopped answering.
+    fn send_heartbeat(&self, interface_name: &UnitName) {
+        // Heartbeats may still be queued up after the interface was stopped.
+        if self.unit_state(interface_name) != Some(UnitState::Active) {
+            return;
+        }
+        let result = match self.interfaces.borrow().get(interface_name) {
+            Some(interface) => interface.borrow().heartbeat(),
+            None => return,
+        };
+        if let Err(e) = result {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                interface_name.clone(),
+                format!("interface went silent: {}", e),
+            )));
+            self.deactivate(interface_name, &format!("heartbeat failed: {}", e));
+        }
+    }
+
+    /// Send the records an interface has been holding back.
+    fn flush_batch(&self, interface_name: &UnitName) {
+        // The window may end after the interface was stopped.
+        if self.unit_state(interface_name) != Some(UnitState::Active) {
+            return;
+        }
+        let result = match self.interfaces.borrow().get(interface_name) {
+            Some(interface) => interface.borrow().flush_batch(),
+            None => return,
+        };
+        if let Err(e) = result {
+            self.deactivate(interface_name, &format!("communication error: {}", e));
+        }
+    }
+
+    /// Let every active notifier know how a run turned out.
+    fn notify(&self, summary: &ScenarioSummary) {
+        let config = self.cfg.locked();
+        for (id, notifier) in self.notifiers.borrow().iter() {
+            if self.unit_state(id) == Some(UnitState::Active) {
+                notifier
+                    .borrow()
+                    .scenario_finished(summary, &config, &self.control_sender);
+            }
+        }
+    }
+
+    /// Run the OnPass or OnFail commands of the jig the scenario ran on and
+    /// of every running interface.
+    fn run_hooks(&self, summary: &ScenarioSummary) {
+        let config = self.cfg.locked();
+        if let Some(jig) = summary
+            .jig
+            .as_ref()
+            .and_then(|jig| self.jigs.borrow().get(jig).cloned())
+        {
+            jig.borrow()
+                .run_hooks(summary, &config, &self.control_sender);
+        }
+        for (id, interface) in self.interfaces.borrow().iter() {
+            if self.unit_state(id) == Some(UnitState::Active) {
+                interface
+                    .borrow()
+                    .run_hooks(summary, &config, &self.control_sender);
+            }
+        }
+    }
+
+    /// Add a finished run to each test's history, and warn everyone about
+    /// tests that have become flaky.
+    fn check_flakiness(&self, summary: &ScenarioSummary) {
+        let (flaky, saved) = {
+            let mut config = self.cfg.locked();
+            let flaky = config.record_verdicts(summary);
+            (flaky, config.save_statistics())
+        };
+        if let Err(e) = saved {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                UnitName::internal("manager"),
+                format!("unable to save test statistics: {}", e),
+            )));
+        }
+        for test in flaky {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                test.test.clone(),
+                format!(
+                    "test is flaky: it flipped between pass and fail {} times in its last {} runs ({}%)",
+                    test.flips, test.window, test.flakiness
+                ),
+            )));
+            self.broadcast_message(ManagerStatusMessage::Flaky(
+                test.test,
+                test.flakiness,
+                test.window,
+            ));
+        }
+    }
+
+    /// For burn-in runs, write the per-pass result matrix and report into
+    /// the run directory, and note the run against its position.
+    fn report_soak(&self, summary: &ScenarioSummary) {
+        if summary.iterations.is_empty() {
+            return;
+        }
+        if let Some(first) = soak::first_failure(summary) {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                summary.scenario.clone(),
+                format!(
+                    "burn-in first failed in iteration {} of {}: {}: {}",
+                    first.iteration,
+                    summary.iterations.len(),
+                    first.test,
+                    first.reason
+                ),
+            )));
+        }
+        let (run, log_directory) = {
+            let config = self.cfg.locked();
+            (
+                config.run_directory(),
+                config.log_directory().map(|dir| dir.to_owned()),
+            )
+        };
+        let mut result = Ok(());
+        if let Some(run) = run {
+            result = soak::write_run_report(&run, summary);
+        }
+        if let Some(log_directory) = log_directory {
+            result = result.and(soak::update_positions(&log_directory, summary));
+        }
+        if let Err(e) = result {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                summary.scenario.clone(),
+                format!("unable to write burn-in report: {}", e),
+            )));
+        }
+    }
+
+    /// Save a certificate for a passing production run into its run
+    /// directory, and print it if there's a printer.
+    fn report_certificate(&self, summary: &ScenarioSummary) {
+        if summary.verdict() != TestVerdict::Pass || summary.maintenance {
+            return;
+        }
+        let (settings, run, now) = {
+            let config = self.cfg.locked();
+            (
+                config.certificate_settings(),
+                config.run_directory(),
+                config.clock().now(),
+            )
+        };
+        let (settings, run) = match (settings, run) {
+            (Some(settings), Some(run)) => (settings, run),
+            _ => return,
+        };
+        let path = match certificate::write(&run, &settings, summary, now) {
+            Ok(path) => path,
+            Err(e) => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    summary.scenario.clone(),
+                    format!("unable to make certificate: {}", e),
+                )));
+                return;
+            }
+        };
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+            summary.scenario.clone(),
+            format!("saved certificate to {}", path.display()),
+        )));
+        if let Some(printer) = settings.printer {
+            // Printers can be slow to take a job, so don't wait for them.
+            let (bc, scenario) = (self.bc.clone(), summary.scenario.clone());
+            thread::spawn(move || {
+                if let Err(e) = certificate::print(&printer, &path) {
+                    bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                        scenario,
+                        format!("unable to print certificate: {}", e),
+                    )));
+                }
+            });
+        }
+    }
+
+    /// How each test has done, sorted by name.
+    pub fn test_stats(&self) -> Vec<TestStats> {
+        self.cfg.locked().test_stats()
+    }
+
+    /// Send how each test has done to the specified endpoint.
+    pub fn send_stats_to(&self, sender_name: &UnitName) {
+        let messages = self
+            .test_stats()
+            .into_iter()
+            .map(ManagerStatusMessage::Stats)
+            .collect();
+        self.send_messages_to(sender_name, messages);
+    }
+
+    /// Send the directives that unit files may contain to the specified
+    /// endpoint, either for one kind of unit or for all of them.
+    pub fn send_schema_to(&self, sender_name: &UnitName, kind: Option<&UnitKind>) {
+        let messages = schema::unit_schemas()
+            .into_iter()
+            .filter(|unit| kind.map(|kind| unit.kind == *kind).unwrap_or(true))
+            .flat_map(|unit| {
+                let (kind, section) = (unit.kind, unit.section);
+                unit.directives.into_iter().map(move |directive| {
+                    ManagerStatusMessage::Schema(kind.clone(), section, directive)
+                })
+            })
+            .collect();
+        self.send_messages_to(sender_name, messages);
+    }
+
+    /// Check whether a jig's calibration has expired, and if that changed,
+    /// warn everyone.  Returns the problem with the calibration, if any.
+    fn check_calibration(&self, jig_id: &UnitName) -> Option<String> {
+        let problem = {
+            let jigs = self.jigs.borrow();
+            let jig = jigs.get(jig_id)?.borrow();
+            let config = self.cfg.locked();
+            jig.calibration_problem(config.last_calibration(jig_id), config.clock().now())
+        };
+        let changed = match problem {
+            Some(ref p) => {
+                self.degraded_jigs
+                    .borrow_mut()
+                    .insert(jig_id.clone(), p.clone())
+                    != problem
+            }
+            None => self.degraded_jigs.borrow_mut().remove(jig_id).is_some(),
+        };
+        if changed {
+            self.bc.broadcast(&UnitEvent::Log(match problem {
+                Some(ref p) => LogEntry::new_error(jig_id.clone(), format!("jig degraded: {}", p)),
+                None => LogEntry::new_info(jig_id.clone(), "jig calibrated".to_owned()),
+            }));
+            self.broadcast_message(ManagerStatusMessage::Health(
+                jig_id.clone(),
+                problem.clone(),
+            ));
+        }
+        problem
+    }
+
+    /// If a run that just passed was its jig's calibration scenario, note
+    /// that the jig has been calibrated.
+    fn record_calibration(&self, summary: &ScenarioSummary) {
+        let jig_id = match summary.jig {
+            Some(ref jig_id) if summary.verdict() == TestVerdict::Pass => jig_id,
+            _ => return,
+        };
+        let calibrates = match self.jigs.borrow().get(jig_id) {
+            Some(jig) => jig.borrow().calibration_scenario() == Some(&summary.scenario),
+            None => false,
+        };
+        if !calibrates {
+            return;
+        }
+        let result = {
+            let mut config = self.cfg.locked();
+            let now = config.clock().now();
+            config.set_last_calibration(jig_id, now)
+        };
+        if let Err(e) = result {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                jig_id.clone(),
+                format!("unable to save calibration time: {}", e),
+            )));
+        }
+        self.check_calibration(jig_id);
+    }
+
+    /// Add a finished run to its jig's counters, and warn everyone about
+    /// counters that have just reached their limit.
+    fn count_cycles(&self, summary: &ScenarioSummary) {
+        let jig_id = match summary.jig {
+            Some(ref jig_id) => jig_id,
+            None => return,
+        };
+        let (counters, mut names) = match self.jigs.borrow().get(jig_id) {
+            Some(jig) => {
+                let jig = jig.borrow();
+                (jig.counters().clone(), jig.count_runs().clone())
             }
+            None => return,
         };
+        if counters.is_empty() {
+            return;
+        }
+        // Tests that were skipped never touched the jig.
+        for test in &summary.tests {
+            if test.verdict == TestVerdict::Skip {
+                continue;
+            }
+            if let Some(unit) = self.tests.borrow().get(&test.test) {
+                names.extend(
+                    unit.borrow()
+                        .counts()
+                        .iter()
+                        .filter(|name| counters.iter().any(|c| c.name == **name))
+                        .cloned(),
+                );
+            }
+        }
+        if names.is_empty() {
+            return;
+        }
+
+        let mut config = self.cfg.locked();
+        let before: Vec<u64> = counters
+            .iter()
+            .map(|c| config.counter(jig_id, &c.name))
+            .collect();
+        if let Err(e) = config.increment_counters(jig_id, &names) {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                jig_id.clone(),
+                format!("unable to save counters: {}", e),
+            )));
+        }
+        let after: Vec<u64> = counters
+            .iter()
+            .map(|c| config.counter(jig_id, &c.name))
+            .collect();
+        drop(config);
+
+        for (counter, (before, after)) in counters.iter().zip(before.into_iter().zip(after)) {
+            match counter.limit {
+                Some(limit) if before < limit && after >= limit => {
+                    self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                        jig_id.clone(),
+                        format!(
+                            "maintenance due: {} reached {} (limit {})",
+                            counter.name, after, limit
+                        ),
+                    )));
+                    self.broadcast_message(ManagerStatusMessage::MaintenanceDue(
+                        jig_id.clone(),
+                        counter.name.clone(),
+                        after,
+                        limit,
+                    ));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// The count of every counter on the selected jigs.
+    fn counter_messages(&self) -> Vec<ManagerStatusMessage> {
+        let config = self.cfg.locked();
+        let mut messages = vec![];
+        for jig in self.selected_jigs.borrow().iter() {
+            let jig = jig.borrow();
+            for counter in jig.counters() {
+                messages.push(ManagerStatusMessage::Counter(
+                    jig.id().clone(),
+                    counter.name.clone(),
+                    config.counter(jig.id(), &counter.name),
+                    counter.limit,
+                ));
+            }
+        }
+        messages
+    }
+
+    /// Send the count of every counter on the selected jigs to the specified endpoint.
+    pub fn send_counters_to(&self, sender_name: &UnitName) {
+        let messages = self.counter_messages();
+        self.send_messages_to(sender_name, messages);
+    }
+
+    /// Send every entry in a jig's key-value store to the specified endpoint.
+    pub fn send_store_to(&self, sender_name: &UnitName, jig_id: &UnitName) {
+        let result = self.cfg.locked().store(jig_id);
+        match result {
+            Ok(entries) => {
+                let messages = entries
+                    .into_iter()
+                    .map(|(key, value)| {
+                        ManagerStatusMessage::StoreEntry(jig_id.clone(), key, value)
+                    })
+                    .collect();
+                self.send_messages_to(sender_name, messages);
+            }
+            Err(e) => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                sender_name.clone(),
+                format!("unable to read the store for jig {}: {}", jig_id, e),
+            ))),
+        }
+    }
+
+    /// Remove entries from a jig's key-value store, such as a calibration
+    /// constant that needs measuring again.
+    fn reset_store(&self, sender_name: &UnitName, jig_id: &UnitName, key: Option<&str>) {
+        let result = self.cfg.locked().clear_store(jig_id, key);
+        self.bc.broadcast(&UnitEvent::Log(match result {
+            Ok(ref removed) if removed.is_empty() => LogEntry::new_info(
+                sender_name.clone(),
+                format!("nothing to remove from the store for jig {}", jig_id),
+            ),
+            Ok(removed) => LogEntry::new_info(
+                sender_name.clone(),
+                format!(
+                    "removed {} from the store for jig {}",
+                    removed.join(", "),
+                    jig_id
+                ),
+            ),
+            Err(e) => LogEntry::new_error(
+                sender_name.clone(),
+                format!("unable to change the store for jig {}: {}", jig_id, e),
+            ),
+        }));
+    }
+
+    /// Set a jig counter back to zero, and tell everyone its new count.
+    fn reset_counter(&self, sender_name: &UnitName, jig_id: &UnitName, counter: &str) {
+        let limit = match self.jigs.borrow().get(jig_id) {
+            Some(jig) => match jig.borrow().counters().iter().find(|c| c.name == counter) {
+                Some(c) => c.limit,
+                None => {
+                    self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                        sender_name.clone(),
+                        format!(
+                            "unable to reset counter: {} has no counter {}",
+                            jig_id, counter
+                        ),
+                    )));
+                    return;
+                }
+            },
+            None => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender_name.clone(),
+                    format!("unable to reset counter: no jig {}", jig_id),
+                )));
+                return;
+            }
+        };
+        let (count, result) = {
+            let mut config = self.cfg.locked();
+            let count = config.counter(jig_id, counter);
+            (count, config.reset_counter(jig_id, counter))
+        };
+        self.bc.broadcast(&UnitEvent::Log(match result {
+            Ok(()) => LogEntry::new_info(
+                sender_name.clone(),
+                format!("reset counter {} on {} from {}", counter, jig_id, count),
+            ),
+            Err(e) => LogEntry::new_error(
+                sender_name.clone(),
+                format!("unable to save counters: {}", e),
+            ),
+        }));
+        self.broadcast_message(ManagerStatusMessage::Counter(
+            jig_id.clone(),
+            counter.to_owned(),
+            0,
+            limit,
+        ));
+    }
+
+    /// Switch the station between maintenance mode and production, and tell everyone.
+    fn set_maintenance(&self, sender_name: &UnitName, reason: &Option<String>) {
+        let result = self.cfg.locked().set_maintenance(reason.clone());
+        if let Err(e) = result {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                sender_name.clone(),
+                format!("unable to save mode: {}", e),
+            )));
+        }
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+            sender_name.clone(),
+            match *reason {
+                Some(ref r) => format!("entered maintenance mode: {}", r),
+                None => "returned to production".to_owned(),
+            },
+        )));
+        self.broadcast_message(ManagerStatusMessage::Mode(reason.clone()));
+    }
+
+    /// Stop running scenarios, and report the runs in a recording that's
+    /// being replayed to interfaces as though they were happening here.
+    pub fn set_replaying(&self, replaying: bool) {
+        self.replaying.set(replaying);
+    }
+
+    /// Why a jig can't be relied on, if it's degraded.
+    pub fn jig_problem(&self, jig_id: &UnitName) -> Option<String> {
+        self.degraded_jigs.borrow().get(jig_id).cloned()
+    }
+
+    /// Log what it cost to run a test or scenario, and tell the interfaces.
+    fn report_cost(&self, name: &UnitName, cost: Cost) {
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+            name.clone(),
+            format!("cost: {}", cost),
+        )));
+        self.broadcast_message(ManagerStatusMessage::Cost(name.clone(), cost));
+    }
+
+    /// Record the latest clock check, and warn everyone if it changed.
+    fn update_clock_status(&self, sender_name: &UnitName, problem: &Option<String>) {
+        if *self.clock_problem.borrow() == *problem {
+            return;
+        }
+        *self.clock_problem.borrow_mut() = problem.clone();
+        self.bc.broadcast(&UnitEvent::Log(match *problem {
+            Some(ref p) => LogEntry::new_error(
+                sender_name.clone(),
+                format!("system clock can't be trusted: {}", p),
+            ),
+            None => LogEntry::new_info(
+                sender_name.clone(),
+                "system clock can be trusted again".to_owned(),
+            ),
+        }));
+        self.broadcast_message(ManagerStatusMessage::Clock(problem.clone()));
+    }
+
+    /// If the clock can't be trusted, mark the run that just started so its
+    /// timestamps aren't relied upon later: in its results, and with a file
+    /// in its run directory.
+    fn flag_unsynced_run(&self) {
+        self.run_clock_unsynced
+            .set(self.clock_problem.borrow().is_some());
+        let problem = match *self.clock_problem.borrow() {
+            Some(ref p) => p.clone(),
+            None => return,
+        };
+        if let Some(run) = self.cfg.locked().run_directory() {
+            let path = run.join(CLOCK_UNSYNCED_FILE);
+            if let Err(e) = fs::write(&path, format!("{}\n", problem)) {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    UnitName::internal("manager"),
+                    format!("unable to write {}: {}", path.display(), e),
+                )));
+            }
+        }
+    }
+
+    /// Re-read the config file, then let everyone know which settings changed.
+    fn reload_config(&self, sender_name: &UnitName) {
+        let result = {
+            let mut cfg = self.cfg.locked();
+            if cfg.config_file().is_none() {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender_name.clone(),
+                    "unable to reload config: no config file specified".to_owned(),
+                )));
+                return;
+            }
+            cfg.reload()
+        };
+
+        match result {
+            Err(e) => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                sender_name.clone(),
+                format!("unable to reload config: {}", e),
+            ))),
+            Ok(change) => {
+                // A new StateDirectory can make this a different station.
+                self.bc.set_station(&self.cfg.locked().station().id);
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+                    sender_name.clone(),
+                    format!("config reloaded: {}", change),
+                )));
+                for setting in &change.applied {
+                    self.broadcast_message(ManagerStatusMessage::ConfigChanged(
+                        setting.clone(),
+                        false,
+                    ));
+                }
+                for setting in &change.restart_required {
+                    self.broadcast_message(ManagerStatusMessage::ConfigChanged(
+                        setting.clone(),
+                        true,
+                    ));
+                }
+                self.bc.broadcast(&UnitEvent::ConfigChanged(change));
+            }
+        }
+    }
+
+    pub fn send_hello_to(&self, sender_name: &UnitName) {
+        self.send_messages_to(
+            sender_name,
+            vec![ManagerStatusMessage::Hello(format!(
+                "Jig/20 1.0 exclave/{}",
+                version::build_id()
+            ))],
+        );
+    }
+
+    pub fn send_jig_to(&self, sender_name: &UnitName) {
+        let mut messages = vec![];
+        for jig_rc in self.selected_jigs.borrow().iter() {
+            let id = jig_rc.borrow().id().clone();
+            messages.push(ManagerStatusMessage::Jig(Some(id.clone())));
+            messages.extend(self.describe_messages(&id));
+        }
+        if messages.is_empty() {
+            messages.push(ManagerStatusMessage::Jig(None));
+        }
+        self.send_messages_to(sender_name, messages);
+    }
+
+    /// Send the state of every loaded unit to the specified endpoint.
+    pub fn send_unit_states_to(&self, sender_name: &UnitName) {
+        let mut states: Vec<(UnitName, UnitState)> = self.unit_states().into_iter().collect();
+        states.sort_by(|a, b| format!("{}", a.0).cmp(&format!("{}", b.0)));
+        let messages = states
+            .into_iter()
+            .map(|(id, state)| ManagerStatusMessage::UnitState(id, state))
+            .collect();
         self.send_messages_to(sender_name, messages);
     }
 
+    /// Stream an artifact saved during the current run to the specified interface.
+    pub fn send_artifact_to(&self, sender_name: &UnitName, test_name: &UnitName, artifact: &str) {
+        // Artifacts are stored by file name, so refuse anything that could
+        // point outside of the test's artifact directory.
+        if artifact.is_empty()
+            || artifact == "."
+            || artifact == ".."
+            || artifact.contains(['/', '\\'])
+        {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                sender_name.clone(),
+                format!("invalid artifact name: {}", artifact),
+            )));
+            return;
+        }
+        // Only tests have artifacts, and only loaded ones can be named.
+        if self.get_test_named(test_name).is_none() {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                sender_name.clone(),
+                format!("unable to fetch artifact: no test named {}", test_name),
+            )));
+            return;
+        }
+        let run_directory = match self.cfg.locked().run_directory() {
+            Some(d) => d,
+            None => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender_name.clone(),
+                    "unable to fetch artifact: no run directory".to_owned(),
+                )));
+                return;
+            }
+        };
+        let path = run_directory.join(test_name.id()).join(artifact);
+        // A symlink in the run directory still mustn't lead out of it.
+        let file = path.canonicalize().and_then(|real_path| {
+            if !real_path.starts_with(run_directory.canonicalize()?) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "outside of the run directory",
+                ));
+            }
+            File::open(real_path)
+        });
+        let file = match file {
+            Ok(f) => f,
+            Err(e) => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender_name.clone(),
+                    format!("unable to fetch artifact {}: {}", path.display(), e),
+                )));
+                return;
+            }
+        };
+
+        let result = match self.interfaces.borrow().get(sender_name) {
+            Some(interface) => interface.borrow().send_artifact(test_name, artifact, file),
+            None => return,
+        };
+        if let Err(e) = result {
+            self.deactivate(sender_name, format!("communication error: {}", e).as_str());
+        }
+    }
+
     /// Send all available scenarios to the specified endpoint.
     pub fn send_scenarios_to(&self, sender_name: &UnitName) {
-        let mut messages = vec![ManagerStatusMessage::Scenarios(
-            self.scenarios.borrow().keys().cloned().collect(),
-        )];
+        let mut messages = self.scenario_list_messages();
         for (scenario_id, scenario) in self.scenarios.borrow().iter() {
             messages.push(ManagerStatusMessage::Describe(
                 scenario_id.clone(),
@@ -1120,6 +3736,12 @@ impl UnitManager {
                 FieldType::Description,
                 scenario.borrow().description().clone(),
             ));
+            messages.extend(Self::presentation_messages(
+                scenario_id,
+                scenario.borrow().presentation(),
+            ));
+            messages.push(self.estimate_message(scenario_id));
+            messages.push(self.params_message(scenario_id));
         }
         self.send_messages_to(sender_name, messages);
     }
@@ -1143,6 +3765,7 @@ impl UnitManager {
                         FieldType::Description,
                         test.description().clone(),
                     ));
+                    messages.extend(Self::presentation_messages(test_id, test.presentation()));
                 }
                 messages.push(ManagerStatusMessage::Tests(
                     scenario.id().clone(),
@@ -1159,8 +3782,8 @@ impl UnitManager {
     pub fn send_tests_to(&self, sender_name: &UnitName, scenario_name_opt: &Option<UnitName>) {
         let scenario_id = match *scenario_name_opt {
             Some(ref n) => n.clone(),
-            None => match *self.current_scenario.borrow() {
-                Some(ref cs) => cs.borrow().id().clone(),
+            None => match self.current_scenario() {
+                Some(cs) => cs.borrow().id().clone(),
                 None => {
                     self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
                         sender_name.clone(),
@@ -1192,32 +3815,66 @@ impl UnitManager {
     }
 
     fn broadcast_selected_jig(&self) {
-        let jig_opt = self.current_jig.borrow();
-        match *jig_opt {
-            None => {}
-            Some(ref j) => {
-                let jig = j.borrow();
-                for (interface_id, _) in self.interfaces.borrow().iter() {
-                    let messages = vec![ManagerStatusMessage::Jig(Some(jig.id().clone()))];
-                    self.send_messages_to(interface_id, messages);
-                }
-            }
+        let messages: Vec<ManagerStatusMessage> = self
+            .selected_jigs
+            .borrow()
+            .iter()
+            .map(|j| ManagerStatusMessage::Jig(Some(j.borrow().id().clone())))
+            .collect();
+        for (interface_id, _) in self.interfaces.borrow().iter() {
+            self.send_messages_to(interface_id, messages.clone());
         }
     }
 
     fn broadcast_scenario_list(&self) {
-        let msg =
-            ManagerStatusMessage::Scenarios(self.scenarios.borrow().keys().cloned().collect());
+        let messages = self.scenario_list_messages();
         for (interface_id, _) in self.interfaces.borrow().iter() {
-            self.send_messages_to(interface_id, vec![msg.clone()]);
+            self.send_messages_to(interface_id, messages.clone());
         }
     }
 
+    /// The list of scenarios, followed by why each one that can't be
+    /// selected right now can't be.  With HideIncompatibleScenarios, those
+    /// are left out of the list instead.
+    fn scenario_list_messages(&self) -> Vec<ManagerStatusMessage> {
+        let mut incompatible: Vec<(UnitName, String)> = self
+            .incompatible_scenarios
+            .borrow()
+            .iter()
+            .map(|(id, reason)| (id.clone(), reason.clone()))
+            .collect();
+        let mut list = self.units(&UnitKind::Scenario);
+        for id in &list {
+            if !self.compatible_with_selected_jigs(id) {
+                let jigs: Vec<String> = self.unit_jigs(id).iter().map(|j| j.to_string()).collect();
+                incompatible.push((id.clone(), format!("needs jig {}", jigs.join(" or "))));
+            }
+        }
+        incompatible.sort();
+
+        if self.cfg.locked().hide_incompatible_scenarios() {
+            list.retain(|id| !incompatible.iter().any(|(i, _)| i == id));
+            return vec![ManagerStatusMessage::Scenarios(list)];
+        }
+        for (id, _) in &incompatible {
+            if !list.contains(id) {
+                list.push(id.clone());
+            }
+        }
+        list.sort();
+        let mut messages = vec![ManagerStatusMessage::Scenarios(list)];
+        messages.extend(
+            incompatible
+                .into_iter()
+                .map(|(id, reason)| ManagerStatusMessage::Incompatible(id, reason)),
+        );
+        messages
+    }
+
     fn broadcast_selected_scenario(&self) {
-        let opt = self.current_scenario.borrow();
-        match *opt {
+        match self.current_scenario() {
             None => {}
-            Some(ref j) => {
+            Some(j) => {
                 let scenario = j.borrow();
                 for (interface_id, _) in self.interfaces.borrow().iter() {
                     let messages = vec![
@@ -1233,80 +3890,146 @@ impl UnitManager {
         }
     }
 
+    /// The DESCRIBE messages for a loaded unit.
+    fn describe_messages(&self, id: &UnitName) -> Vec<ManagerStatusMessage> {
+        match self.unit_info(id) {
+            None => vec![],
+            Some(info) => {
+                let mut messages = vec![
+                    ManagerStatusMessage::Describe(id.clone(), FieldType::Name, info.name),
+                    ManagerStatusMessage::Describe(
+                        id.clone(),
+                        FieldType::Description,
+                        info.description,
+                    ),
+                ];
+                if matches!(
+                    *id.kind(),
+                    UnitKind::Jig | UnitKind::Scenario | UnitKind::Test
+                ) {
+                    messages.extend(Self::presentation_messages(id, &info.presentation));
+                }
+                if *id.kind() == UnitKind::Scenario {
+                    messages.push(self.estimate_message(id));
+                    messages.push(self.params_message(id));
+                }
+                messages
+            }
+        }
+    }
+
+    /// The DESCRIBE message for how long a scenario is expected to take, in
+    /// milliseconds, which is empty if there's no telling.
+    fn estimate_message(&self, scenario: &UnitName) -> ManagerStatusMessage {
+        ManagerStatusMessage::Describe(
+            scenario.clone(),
+            FieldType::Estimate,
+            self.estimated_duration(scenario)
+                .map(|estimate| estimate.duration.as_millis().to_string())
+                .unwrap_or_default(),
+        )
+    }
+
+    /// The DESCRIBE message for the parameters a scenario must (or may) be
+    /// started with, as they're declared, separated by "; ".  Those its
+    /// Matrix sets are left out.
+    fn params_message(&self, scenario: &UnitName) -> ManagerStatusMessage {
+        let params: Vec<String> = self
+            .scenarios
+            .borrow()
+            .get(scenario)
+            .map(|s| {
+                let s = s.borrow();
+                s.parameters()
+                    .iter()
+                    .filter(|p| !s.matrix().iter().any(|axis| axis.name() == p.name()))
+                    .map(|p| p.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        ManagerStatusMessage::Describe(scenario.clone(), FieldType::Params, params.join("; "))
+    }
+
+    /// How long a scenario is expected to take: its EstimatedDuration if it
+    /// has one, or else how long its recent runs took, if it has been run
+    /// through before.
+    pub fn estimated_duration(&self, scenario: &UnitName) -> Option<Estimate> {
+        let declared = self
+            .scenarios
+            .borrow()
+            .get(scenario)
+            .and_then(|s| s.borrow().estimated_duration());
+        match declared {
+            Some(duration) => Some(Estimate {
+                duration,
+                source: Source::Declared,
+            }),
+            None => self
+                .cfg
+                .locked()
+                .historical_duration(scenario)
+                .map(|duration| Estimate {
+                    duration,
+                    source: Source::History,
+                }),
+        }
+    }
+
+    /// Add a finished run to its scenario's run times, for estimates.
+    fn record_duration(&self, summary: &ScenarioSummary) {
+        let saved = self.cfg.locked().record_duration(summary);
+        if let Err(e) = saved {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                UnitName::internal("manager"),
+                format!("unable to save scenario durations: {}", e),
+            )));
+        }
+    }
+
+    /// The DESCRIBE messages for a unit's hints to GUIs.  Every hint is
+    /// sent, empty if it isn't set, so that GUIs forget any that a reloaded
+    /// unit no longer has.
+    fn presentation_messages(
+        id: &UnitName,
+        presentation: &Presentation,
+    ) -> Vec<ManagerStatusMessage> {
+        let text = |value: &Option<String>| value.clone().unwrap_or_default();
+        vec![
+            ManagerStatusMessage::Describe(id.clone(), FieldType::Icon, text(&presentation.icon)),
+            ManagerStatusMessage::Describe(id.clone(), FieldType::Color, text(&presentation.color)),
+            ManagerStatusMessage::Describe(id.clone(), FieldType::Group, text(&presentation.group)),
+            ManagerStatusMessage::Describe(
+                id.clone(),
+                FieldType::Hidden,
+                if presentation.hidden { "yes" } else { "no" }.to_owned(),
+            ),
+        ]
+    }
+
     fn broadcast_jig_named(&self, jig_id: &UnitName) {
-        let jigs = self.jigs.borrow();
-        let jig = match jigs.get(jig_id) {
-            Some(s) => s.clone(),
-            None => return,
-        };
+        let messages = self.describe_messages(jig_id);
         for (interface_id, _) in self.interfaces.borrow().iter() {
-            let jig = jig.borrow();
-            let messages = vec![
-                ManagerStatusMessage::Describe(
-                    jig.id().clone(),
-                    FieldType::Name,
-                    jig.name().clone(),
-                ),
-                ManagerStatusMessage::Describe(
-                    jig.id().clone(),
-                    FieldType::Description,
-                    jig.description().clone(),
-                ),
-            ];
-            self.send_messages_to(interface_id, messages);
+            self.send_messages_to(interface_id, messages.clone());
         }
     }
 
     fn broadcast_scenario_named(&self, scenario_id: &UnitName) {
-        let scenarios = self.scenarios.borrow();
-        let scenario = match scenarios.get(scenario_id) {
-            Some(s) => s.clone(),
-            None => return,
-        };
+        if !self.scenarios.borrow().contains_key(scenario_id) {
+            return;
+        }
 
+        // Rebroadcast the list of scenarios, since that may have changed.
         self.broadcast_scenario_list();
-        let messages = {
-            let scenario = scenario.borrow();
-            vec![
-                // Rebroadcast the list of scenarios, since that may have changed.
-                ManagerStatusMessage::Describe(
-                    scenario_id.clone(),
-                    FieldType::Name,
-                    scenario.name().clone(),
-                ),
-                ManagerStatusMessage::Describe(
-                    scenario_id.clone(),
-                    FieldType::Description,
-                    scenario.description().clone(),
-                ),
-            ]
-        };
+        let messages = self.describe_messages(scenario_id);
         for (interface_id, _) in self.interfaces.borrow().iter() {
             self.send_messages_to(interface_id, messages.clone());
         }
     }
 
     fn broadcast_test_named(&self, unit_id: &UnitName) {
-        let units = self.tests.borrow();
-        let unit = match units.get(unit_id) {
-            Some(s) => s.clone(),
-            None => return,
-        };
+        let messages = self.describe_messages(unit_id);
         for (interface_id, _) in self.interfaces.borrow().iter() {
-            let unit = unit.borrow();
-            let messages = vec![
-                ManagerStatusMessage::Describe(
-                    unit_id.clone(),
-                    FieldType::Name,
-                    unit.name().clone(),
-                ),
-                ManagerStatusMessage::Describe(
-                    unit_id.clone(),
-                    FieldType::Description,
-                    unit.description().clone(),
-                ),
-            ];
-            self.send_messages_to(interface_id, messages);
+            self.send_messages_to(interface_id, messages.clone());
         }
     }
 
@@ -1317,13 +4040,6 @@ impl UnitManager {
         }
     }
 
-    fn broadcast_finished(&self, unit_id: &UnitName, code: u32, message: &str) {
-        let msg = ManagerStatusMessage::Finished(unit_id.clone(), code, message.to_owned());
-        for (interface_id, _) in self.interfaces.borrow().iter() {
-            self.send_messages_to(interface_id, vec![msg.clone()]);
-        }
-    }
-
     fn broadcast_message(&self, msg: ManagerStatusMessage) {
         for (interface_id, _) in self.interfaces.borrow().iter() {
             self.send_messages_to(interface_id, vec![msg.clone()]);
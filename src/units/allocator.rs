@@ -0,0 +1,550 @@
+extern crate runny;
+extern crate systemd_parser;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use argv;
+use config::Config;
+use duration;
+use init;
+use schema::{DirectiveSchema, UnitSchema, ValueType};
+use specifier;
+use unit::{
+    parse_bool, UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
+    UnitIncompatibleReason, UnitKind, UnitName, UnitSelectError,
+};
+use unitfile;
+use unitmanager::UnitManager;
+
+use self::runny::Runny;
+use self::systemd_parser::items::DirectiveEntry;
+
+/// How the values in a pool are written.
+#[derive(Clone, Debug, PartialEq)]
+enum Format {
+    /// A MAC address, as six pairs of hex digits.
+    Mac { separator: char, uppercase: bool },
+
+    /// A number, padded with zeroes to a width, after a fixed prefix.
+    Serial { prefix: String, width: usize },
+}
+
+/// A range of values to hand out, in order.
+#[derive(Clone, Debug, PartialEq)]
+struct Pool {
+    format: Format,
+    first: u64,
+    last: u64,
+}
+
+impl Pool {
+    /// Make a pool from its first and last values, which must be written
+    /// the same way.
+    fn new(first: &str, last: &str) -> Option<Pool> {
+        let format = Self::format_of(first)?;
+        let pool = Pool {
+            first: Self::parse(&format, first)?,
+            last: Self::parse(&format, last)?,
+            format,
+        };
+        if pool.first <= pool.last {
+            Some(pool)
+        } else {
+            None
+        }
+    }
+
+    fn format_of(value: &str) -> Option<Format> {
+        let octets: Vec<&str> = value.split([':', '-']).collect();
+        if octets.len() == 6
+            && octets
+                .iter()
+                .all(|o| o.len() == 2 && o.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            return Some(Format::Mac {
+                separator: value.chars().nth(2).unwrap(),
+                uppercase: value.chars().any(|c| c.is_ascii_uppercase()),
+            });
+        }
+        let digits = value.len() - value.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 {
+            return None;
+        }
+        Some(Format::Serial {
+            prefix: value[..value.len() - digits].to_owned(),
+            width: digits,
+        })
+    }
+
+    fn parse(format: &Format, value: &str) -> Option<u64> {
+        match format {
+            Format::Mac { .. } => {
+                if Self::format_of(value).map(|f| matches!(f, Format::Mac { .. })) != Some(true) {
+                    return None;
+                }
+                let hex: String = value.chars().filter(|c| c.is_ascii_hexdigit()).collect();
+                u64::from_str_radix(&hex, 16).ok()
+            }
+            Format::Serial { ref prefix, .. } => value
+                .strip_prefix(prefix.as_str())
+                .filter(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()))
+                .and_then(|digits| digits.parse().ok()),
+        }
+    }
+
+    fn render(&self, value: u64) -> String {
+        match self.format {
+            Format::Mac {
+                separator,
+                uppercase,
+            } => {
+                let octets: Vec<String> = (0..6)
+                    .rev()
+                    .map(|i| {
+                        let octet = (value >> (i * 8)) & 0xff;
+                        if uppercase {
+                            format!("{:02X}", octet)
+                        } else {
+                            format!("{:02x}", octet)
+                        }
+                    })
+                    .collect();
+                octets.join(&separator.to_string())
+            }
+            Format::Serial { ref prefix, width } => {
+                format!("{}{:0width$}", prefix, value, width = width)
+            }
+        }
+    }
+
+    /// The value after the last one handed out, or None if the pool is used up.
+    fn next(&self, last: Option<&String>) -> Option<String> {
+        let next = match last.and_then(|last| Self::parse(&self.format, last)) {
+            Some(last) if last >= self.first => last.checked_add(1)?,
+            _ => self.first,
+        };
+        if next <= self.last {
+            Some(self.render(next))
+        } else {
+            None
+        }
+    }
+
+    /// How many values are left after the last one handed out.
+    fn remaining(&self, last: Option<&String>) -> u64 {
+        match last.and_then(|last| Self::parse(&self.format, last)) {
+            Some(last) if last >= self.last => 0,
+            Some(last) if last >= self.first => self.last - last,
+            _ => self.last - self.first + 1,
+        }
+    }
+}
+
+/// A struct defining an in-memory representation of a .allocator file
+#[derive(Clone)]
+pub struct AllocatorDescription {
+    /// The id of the unit (including the kind)
+    id: UnitName,
+
+    /// A short name
+    name: String,
+
+    /// A detailed description of this Allocator, up to one paragraph.
+    description: String,
+
+    /// A Vec<String> of jig names that this allocator is compatible with.
+    jigs: Vec<UnitName>,
+
+    /// The environment variable tests are given the value in.
+    variable: String,
+
+    /// The values to hand out, if they come from a pool.
+    pool: Option<Pool>,
+
+    /// A command that prints the value to hand out, if they come from a service.
+    exec_start: Option<String>,
+
+    /// How long the command may take.
+    timeout: Option<Duration>,
+
+    /// The working directory to start from when running the command
+    working_directory: Option<PathBuf>,
+
+    /// The path to the unit file
+    unit_directory: PathBuf,
+
+    /// True if this unit was disabled with a Disabled= directive
+    disabled: bool,
+}
+
+impl AllocatorDescription {
+    pub fn from_path(
+        unit_name: &UnitName,
+        path: &Path,
+    ) -> Result<AllocatorDescription, UnitDescriptionError> {
+        let unit_name = unit_name.clone();
+
+        // Parse the file into a systemd unit_file object
+        let mut contents = String::with_capacity(8192);
+        File::open(path)?.read_to_string(&mut contents)?;
+        Self::from_string(&contents, unit_name, path)
+    }
+
+    pub fn from_string(
+        contents: &str,
+        unit_name: UnitName,
+        path: &Path,
+    ) -> Result<AllocatorDescription, UnitDescriptionError> {
+        let parsed = unitfile::parse_to_keep(contents, &[])?;
+        Self::from_parsed(&parsed, unit_name, path)
+    }
+
+    /// Describe an allocator from a file that has already been parsed.
+    pub fn from_parsed(
+        parsed: &unitfile::ParsedFile,
+        unit_name: UnitName,
+        path: &Path,
+    ) -> Result<AllocatorDescription, UnitDescriptionError> {
+        let unit_file = parsed.unit()?;
+
+        if !unit_file.has_category("Allocator") {
+            return Err(UnitDescriptionError::MissingSection("Allocator".to_owned()));
+        }
+
+        let mut allocator_description = AllocatorDescription {
+            variable: Self::default_variable(&unit_name),
+            id: unit_name,
+            name: "".to_owned(),
+            description: "".to_owned(),
+            jigs: vec![],
+            pool: None,
+            exec_start: None,
+            timeout: None,
+            working_directory: None,
+            unit_directory: path.parent().unwrap().to_owned(),
+            disabled: false,
+        };
+        let mut first = None;
+        let mut last = None;
+
+        for entry in unit_file.lookup_by_category("Allocator") {
+            if let DirectiveEntry::Solo(ref directive) = entry {
+                match directive.key() {
+                    "Name" => {
+                        allocator_description.name = directive.value().unwrap_or("").to_owned()
+                    }
+                    "Description" => {
+                        allocator_description.description =
+                            directive.value().unwrap_or("").to_owned()
+                    }
+                    "Jigs" => {
+                        allocator_description.jigs = match directive.value() {
+                            Some(s) => UnitName::from_list(s, "jig")?,
+                            None => vec![],
+                        }
+                    }
+                    "Variable" => {
+                        let value = directive.value().unwrap_or("");
+                        if !Self::valid_variable(value) {
+                            return Err(UnitDescriptionError::InvalidValue(
+                                "Allocator".to_owned(),
+                                "Variable".to_owned(),
+                                value.to_owned(),
+                                vec!["an environment variable name, such as MAC_ADDRESS".to_owned()],
+                            ));
+                        }
+                        allocator_description.variable = value.to_owned();
+                    }
+                    "First" => first = directive.value().map(|s| s.trim().to_owned()),
+                    "Last" => last = directive.value().map(|s| s.trim().to_owned()),
+                    "ExecStart" => {
+                        allocator_description.exec_start = directive.value().map(|s| s.to_owned())
+                    }
+                    "Timeout" => {
+                        allocator_description.timeout = match directive.value() {
+                            None => None,
+                            Some(s) => Some(duration::parse(s)?),
+                        }
+                    }
+                    "WorkingDirectory" => {
+                        if let Some(wd) = directive.value() {
+                            allocator_description.working_directory = Some(PathBuf::from(wd));
+                        }
+                    }
+                    "Disabled" => {
+                        allocator_description.disabled =
+                            parse_bool("Allocator", "Disabled", directive.value())?
+                    }
+                    &_ => (),
+                }
+            }
+        }
+
+        match (first, last, &allocator_description.exec_start) {
+            (Some(first), Some(last), None) => {
+                allocator_description.pool = match Pool::new(&first, &last) {
+                    Some(pool) => Some(pool),
+                    None => {
+                        return Err(UnitDescriptionError::InvalidValue(
+                            "Allocator".to_owned(),
+                            "Last".to_owned(),
+                            last,
+                            vec![format!("a value after {}, written the same way", first)],
+                        ))
+                    }
+                }
+            }
+            (None, None, Some(exec_start)) => {
+                argv::check("Allocator", "ExecStart", exec_start)?;
+                specifier::check("Allocator", "ExecStart", exec_start)?;
+            }
+            (Some(_), None, None) => {
+                return Err(UnitDescriptionError::MissingValue(
+                    "Allocator".to_owned(),
+                    "Last".to_owned(),
+                ))
+            }
+            (None, Some(_), None) => {
+                return Err(UnitDescriptionError::MissingValue(
+                    "Allocator".to_owned(),
+                    "First".to_owned(),
+                ))
+            }
+            (None, None, None) => {
+                return Err(UnitDescriptionError::MissingValue(
+                    "Allocator".to_owned(),
+                    "First and Last, or ExecStart".to_owned(),
+                ))
+            }
+            (_, _, Some(exec_start)) => {
+                return Err(UnitDescriptionError::InvalidValue(
+                    "Allocator".to_owned(),
+                    "ExecStart".to_owned(),
+                    exec_start.to_owned(),
+                    vec!["nothing, when First and Last are given".to_owned()],
+                ))
+            }
+        }
+        Ok(allocator_description)
+    }
+
+    /// The directives that [Allocator] sections may contain.
+    pub fn schema() -> UnitSchema {
+        UnitSchema {
+            kind: UnitKind::Allocator,
+            section: "Allocator",
+            directives: vec![
+                DirectiveSchema::new("Name", ValueType::Text),
+                DirectiveSchema::new("Description", ValueType::Text),
+                DirectiveSchema::new("Jigs", ValueType::Units(UnitKind::Jig)),
+                DirectiveSchema::new("Variable", ValueType::Text),
+                DirectiveSchema::new("First", ValueType::Text),
+                DirectiveSchema::new("Last", ValueType::Text),
+                DirectiveSchema::new("ExecStart", ValueType::Command),
+                DirectiveSchema::new("Timeout", ValueType::Duration),
+                DirectiveSchema::new("WorkingDirectory", ValueType::Path),
+                DirectiveSchema::new("Disabled", ValueType::Bool).with_default("no"),
+            ],
+        }
+    }
+
+    /// The variable an allocator's value is given in if it doesn't say,
+    /// such as MAC_ADDRESS for "mac-address.allocator".
+    pub fn default_variable(name: &UnitName) -> String {
+        name.id()
+            .rsplit('/')
+            .next()
+            .unwrap_or("")
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+
+    fn valid_variable(name: &str) -> bool {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    /// Returns true if this allocator is supported on the named jig.
+    pub fn supports_jig(&self, name: &UnitName) -> bool {
+        self.jigs.contains(name)
+    }
+
+    /// Determine if a unit is compatible with this system.
+    pub fn is_compatible(
+        &self,
+        manager: &UnitManager,
+        _: &Config,
+    ) -> Result<(), UnitIncompatibleReason> {
+        if self.jigs.is_empty() {
+            return Ok(());
+        }
+        for jig_name in &self.jigs {
+            if manager.jig_is_loaded(jig_name) {
+                return Ok(());
+            }
+        }
+        Err(UnitIncompatibleReason::IncompatibleJig)
+    }
+
+    pub fn id(&self) -> &UnitName {
+        &self.id
+    }
+
+    /// Returns true if this unit was disabled from within its unit file.
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    pub fn load(
+        &self,
+        manager: &UnitManager,
+        config: &Config,
+    ) -> Result<Allocator, UnitIncompatibleReason> {
+        self.is_compatible(manager, config)?;
+
+        Ok(Allocator::new(self, manager, config))
+    }
+}
+
+pub struct Allocator {
+    description: AllocatorDescription,
+}
+
+impl Allocator {
+    pub fn new(desc: &AllocatorDescription, _: &UnitManager, _: &Config) -> Allocator {
+        Allocator {
+            description: desc.clone(),
+        }
+    }
+
+    pub fn id(&self) -> &UnitName {
+        &self.description.id
+    }
+
+    pub fn name(&self) -> &String {
+        &self.description.name
+    }
+
+    pub fn description(&self) -> &String {
+        &self.description.description
+    }
+
+    /// The jigs this unit is compatible with.  If empty, it works with any jig.
+    pub fn jigs(&self) -> &Vec<UnitName> {
+        &self.description.jigs
+    }
+
+    /// The environment variable tests are given the value in.
+    pub fn variable(&self) -> &str {
+        &self.description.variable
+    }
+
+    pub fn select(&self) -> Result<(), UnitSelectError> {
+        Ok(())
+    }
+
+    pub fn deselect(&self) -> Result<(), UnitDeselectError> {
+        Ok(())
+    }
+
+    pub fn activate(&self, _: &UnitManager, _: &Config) -> Result<(), UnitActivateError> {
+        Ok(())
+    }
+
+    pub fn deactivate(&self) -> Result<(), UnitDeactivateError> {
+        Ok(())
+    }
+
+    /// How many values are left in the pool, or None if values come from a
+    /// command.
+    pub fn remaining(&self, config: &Config) -> Option<u64> {
+        self.description
+            .pool
+            .as_ref()
+            .map(|pool| pool.remaining(config.last_allocation(self.id())))
+    }
+
+    /// Hand out a new value, which is marked as used in the state directory
+    /// before it is returned, so it's never handed out again, even if the
+    /// test that gets it fails or exclave is restarted.
+    pub fn allocate(&self, config: &mut Config) -> Result<String, String> {
+        let value = match self.description.pool {
+            Some(ref pool) => {
+                if config.state_directory().is_none() {
+                    return Err(
+                        "there's no StateDirectory to keep track of the values handed out"
+                            .to_owned(),
+                    );
+                }
+                let last = config.last_allocation(self.id());
+                match pool.next(last) {
+                    Some(value) => value,
+                    None => {
+                        return Err(format!(
+                            "every value has been handed out, up to {}",
+                            last.cloned().unwrap_or_default()
+                        ))
+                    }
+                }
+            }
+            None => self.request(config)?,
+        };
+        config
+            .record_allocation(self.id(), &value)
+            .map_err(|e| format!("unable to record {} as handed out: {}", value, e))?;
+        Ok(value)
+    }
+
+    /// Ask the ExecStart command for a value, which is the first line it prints.
+    fn request(&self, config: &Config) -> Result<String, String> {
+        let desc = &self.description;
+        let cmd = desc.exec_start.as_ref().unwrap();
+        let secrets = config
+            .resolve_command(self.id(), cmd, false)
+            .map_err(|e| e.to_string())?;
+        let mut running = Runny::new(&secrets.text)
+            .directory(&Some(
+                config.working_directory(&desc.unit_directory, &desc.working_directory),
+            ))
+            .timeout(desc.timeout.unwrap_or(*config.timeout()))
+            .path(config.paths().clone())
+            .start()
+            .map_err(|e| format!("unable to run {}: {:?}", cmd, e))?;
+        init::spawned(running.pid());
+        let mut output = String::new();
+        running.take_output().read_to_string(&mut output).ok();
+        let mut error = String::new();
+        running.take_error().read_to_string(&mut error).ok();
+        match running.wait() {
+            Ok(0) => (),
+            Ok(result) => {
+                return Err(format!(
+                    "{} returned {}: {}",
+                    cmd,
+                    result,
+                    secrets.mask(error.trim())
+                ))
+            }
+            Err(e) => return Err(format!("{} failed: {:?}", cmd, e)),
+        }
+        match output
+            .lines()
+            .map(|line| line.trim())
+            .find(|l| !l.is_empty())
+        {
+            Some(value) => Ok(value.to_owned()),
+            None => Err(format!("{} didn't print a value", cmd)),
+        }
+    }
+}
@@ -1,33 +1,103 @@
+extern crate humantime;
 extern crate runny;
 extern crate systemd_parser;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::mem;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use argv;
+use cbor::{self, Value};
+use chaos;
 use config::Config;
+use duration;
+use hooks::Hooks;
+use init;
+use parameter;
+use schema::{DirectiveSchema, UnitSchema, ValueType};
+use secrets::Resolved;
+use specifier;
+use supervisor::{Restart, RestartPolicy};
 use unit::{
-    UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
-    UnitIncompatibleReason, UnitName, UnitSelectError,
+    parse_bool, UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
+    UnitIncompatibleReason, UnitKind, UnitName, UnitSelectError,
 };
+use unitbroadcaster::ScenarioSummary;
+use unitfile;
 use unitmanager::{
     ManagerControlMessage, ManagerControlMessageContents, ManagerStatusMessage, UnitManager,
 };
+use version;
 
+use self::humantime::format_duration;
 use self::runny::running::{Running, RunningOutput};
 use self::runny::Runny;
 use self::systemd_parser::items::DirectiveEntry;
 
+/// How many bytes of an artifact to send in each DATA chunk.
+const ARTIFACT_CHUNK_SIZE: usize = 65536;
+
+/// A batch is sent early once it holds this many bytes, so that a burst
+/// can't build up a frame that takes a slow link ages to deliver.
+const MAX_BATCH_BYTES: usize = 16384;
+
+/// Verbs that change what the station does, which an observer (or a spare)
+/// may not send.
+const CONTROL_VERBS: &[&str] = &[
+    "scenario", "start", "abort", "rerun", "shutdown", "disable", "enable", "reset", "mode",
+    "reload", "input", "note", "log",
+];
+
+/// Update a running CRC-32 (IEEE 802.3) with more data.  Start with 0.
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
 #[derive(Clone, Copy)]
 enum InterfaceFormat {
     Text,
     Json,
 }
 
+/// How the records exclave sends to a text interface are delimited.  Every
+/// interface starts with Text, and may ask for another with FRAMING.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Framing {
+    /// One line per record, with special characters escaped.
+    Text,
+
+    /// Each record is a CBOR array, after its length as four big-endian bytes.
+    Cbor,
+}
+
+impl Framing {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Framing::Text => "text",
+            Framing::Cbor => "cbor",
+        }
+    }
+}
+
 /// A struct defining an in-memory representation of a .Interface file
 #[derive(Clone)]
 pub struct InterfaceDescription {
@@ -54,16 +124,63 @@ pub struct InterfaceDescription {
 
     /// The path of the unit file
     unit_directory: PathBuf,
+
+    /// True if this unit was disabled with a Disabled= directive
+    disabled: bool,
+
+    /// How often to send PING, if at all
+    ping_interval: Option<Duration>,
+
+    /// Deactivate the interface if it doesn't answer a PING for this long
+    ping_timeout: Option<Duration>,
+
+    /// Whether the interface may change the station's mode
+    supervisor: bool,
+
+    /// Whether the interface may only watch, with its control commands refused
+    observer: bool,
+
+    /// The group of redundant interfaces this one belongs to, only one of
+    /// which is in control at a time
+    failover_group: Option<String>,
+
+    /// How long to hold records back, to send them together in a BATCH
+    batch_window: Option<Duration>,
+
+    /// Whether to drop repeats of a LOG record that's already in the batch
+    coalesce_output: bool,
+
+    /// What to do if it exits without being stopped
+    restart: Restart,
+
+    /// Run ExecStart with the shell, rather than splitting it into arguments
+    shell: bool,
+
+    /// Commands to run when a scenario passes or fails
+    hooks: Hooks,
 }
 
 impl InterfaceDescription {
-    pub fn from_path(path: &Path) -> Result<InterfaceDescription, UnitDescriptionError> {
-        let unit_name = UnitName::from_path(path)?;
+    pub fn from_path(
+        unit_name: &UnitName,
+        path: &Path,
+    ) -> Result<InterfaceDescription, UnitDescriptionError> {
+        let unit_name = unit_name.clone();
 
         // Parse the file into a systemd unit_file object
         let mut contents = String::with_capacity(8192);
         File::open(path)?.read_to_string(&mut contents)?;
-        let unit_file = systemd_parser::parse_string(&contents)?;
+        let parsed = unitfile::parse_to_keep(&contents, &[])?;
+        Self::from_parsed(&parsed, unit_name, path)
+    }
+
+    /// Describe an interface from a file that has already been parsed.
+    pub fn from_parsed(
+        parsed: &unitfile::ParsedFile,
+        unit_name: UnitName,
+        path: &Path,
+    ) -> Result<InterfaceDescription, UnitDescriptionError> {
+        let unit_file = parsed.unit()?;
 
         if !unit_file.has_category("Interface") {
             return Err(UnitDescriptionError::MissingSection("Interface".to_owned()));
@@ -78,6 +195,17 @@ impl InterfaceDescription {
             exec_start: "".to_owned(),
             working_directory: None,
             unit_directory: path.parent().unwrap().to_owned(),
+            disabled: false,
+            ping_interval: None,
+            ping_timeout: None,
+            supervisor: false,
+            observer: false,
+            failover_group: None,
+            batch_window: None,
+            coalesce_output: false,
+            restart: Restart::default(),
+            shell: false,
+            hooks: Hooks::default(),
         };
 
         for entry in unit_file.lookup_by_category("Interface") {
@@ -131,13 +259,110 @@ impl InterfaceDescription {
                             },
                         }
                     }
+                    "Disabled" => {
+                        interface_description.disabled =
+                            parse_bool("Interface", "Disabled", directive.value())?
+                    }
+                    "Restart" | "RestartSec" | "StartLimitBurst" => {
+                        interface_description.restart.set(
+                            "Interface",
+                            directive.key(),
+                            directive.value(),
+                        )?
+                    }
+                    "PingInterval" => {
+                        interface_description.ping_interval = match directive.value() {
+                            None => None,
+                            Some(s) => Some(duration::parse(s)?),
+                        }
+                    }
+                    "PingTimeout" => {
+                        interface_description.ping_timeout = match directive.value() {
+                            None => None,
+                            Some(s) => Some(duration::parse(s)?),
+                        }
+                    }
+                    "Supervisor" => {
+                        interface_description.supervisor =
+                            parse_bool("Interface", "Supervisor", directive.value())?
+                    }
+                    "Observer" => {
+                        interface_description.observer =
+                            parse_bool("Interface", "Observer", directive.value())?
+                    }
+                    "FailoverGroup" => {
+                        interface_description.failover_group =
+                            directive.value().map(|s| s.to_owned())
+                    }
+                    "BatchWindow" => {
+                        interface_description.batch_window = match directive.value() {
+                            None => None,
+                            Some(s) => Some(duration::parse(s)?),
+                        }
+                    }
+                    "CoalesceOutput" => {
+                        interface_description.coalesce_output =
+                            parse_bool("Interface", "CoalesceOutput", directive.value())?
+                    }
+                    "Shell" => {
+                        interface_description.shell =
+                            parse_bool("Interface", "Shell", directive.value())?
+                    }
+                    "OnPass" | "OnFail" | "HookTimeoutSec" => interface_description.hooks.set(
+                        "Interface",
+                        directive.key(),
+                        directive.value(),
+                    )?,
                     &_ => (),
                 }
             }
         }
+        if !interface_description.shell {
+            argv::check("Interface", "ExecStart", &interface_description.exec_start)?;
+        }
+        specifier::check("Interface", "ExecStart", &interface_description.exec_start)?;
+        interface_description
+            .hooks
+            .check("Interface", interface_description.shell)?;
         Ok(interface_description)
     }
 
+    /// The directives that [Interface] sections may contain.
+    pub fn schema() -> UnitSchema {
+        UnitSchema {
+            kind: UnitKind::Interface,
+            section: "Interface",
+            directives: vec![
+                DirectiveSchema::new("Name", ValueType::Text),
+                DirectiveSchema::new("Description", ValueType::Text),
+                DirectiveSchema::new("Jigs", ValueType::Units(UnitKind::Jig)),
+                DirectiveSchema::new("WorkingDirectory", ValueType::Path),
+                DirectiveSchema::new("ExecStart", ValueType::Command).required(),
+                DirectiveSchema::new(
+                    "Format",
+                    ValueType::Choice(vec!["text".to_owned(), "json".to_owned()]),
+                )
+                .with_default("text"),
+                DirectiveSchema::new("Disabled", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("PingInterval", ValueType::Duration),
+                DirectiveSchema::new("PingTimeout", ValueType::Duration),
+                DirectiveSchema::new("Supervisor", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("Observer", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("FailoverGroup", ValueType::Text),
+                DirectiveSchema::new("BatchWindow", ValueType::Duration),
+                DirectiveSchema::new("CoalesceOutput", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("Restart", ValueType::Choice(RestartPolicy::choices()))
+                    .with_default("no"),
+                DirectiveSchema::new("RestartSec", ValueType::Duration).with_default("1s"),
+                DirectiveSchema::new("StartLimitBurst", ValueType::Integer).with_default("5"),
+                DirectiveSchema::new("Shell", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("OnPass", ValueType::Command),
+                DirectiveSchema::new("OnFail", ValueType::Command),
+                DirectiveSchema::new("HookTimeoutSec", ValueType::Duration).with_default("60s"),
+            ],
+        }
+    }
+
     /// Returns true if this test is supported on the named jig.
     pub fn supports_jig(&self, name: &UnitName) -> bool {
         self.jigs.contains(name)
@@ -164,6 +389,11 @@ impl InterfaceDescription {
         &self.id
     }
 
+    /// Returns true if this unit was disabled from within its unit file.
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
     pub fn load(
         &self,
         manager: &UnitManager,
@@ -175,10 +405,50 @@ impl InterfaceDescription {
     }
 }
 
+/// Records held back to be sent together in one BATCH.
+#[derive(Default)]
+struct Batch {
+    /// Each record, with its newline.
+    records: Vec<Vec<u8>>,
+
+    /// The size of all the records.
+    bytes: usize,
+
+    /// How many LOG records were dropped as repeats.
+    coalesced: usize,
+
+    /// The type, unit, and message of each LOG record held.
+    logs: HashSet<(String, UnitName, String)>,
+}
+
 pub struct Interface {
     desc: InterfaceDescription,
     process: RefCell<Option<Running>>,
     terminate_timeout: Duration,
+
+    /// Dropped to stop the thread that schedules PINGs.
+    heartbeat: RefCell<Option<Sender<()>>>,
+
+    /// The id of the most recent PING.
+    last_ping: Cell<u64>,
+
+    /// When the interface was started, or last answered a PING.
+    last_pong: Cell<Instant>,
+
+    /// Records waiting to be sent, if BatchWindow is set.
+    batch: RefCell<Batch>,
+
+    /// Told when a batch is started, so it's flushed once the window ends.
+    /// Dropped to stop the thread that does this.
+    batch_timer: RefCell<Option<Sender<()>>>,
+
+    /// How records are currently delimited.
+    framing: Cell<Framing>,
+
+    /// Set while another interface in the FailoverGroup is in control, so
+    /// that this one's control commands are refused.  Shared with the
+    /// thread that reads them.
+    spare: Arc<AtomicBool>,
 }
 
 impl Interface {
@@ -187,6 +457,15 @@ impl Interface {
             desc: desc.clone(),
             process: RefCell::new(None),
             terminate_timeout: *config.terminate_timeout(),
+            heartbeat: RefCell::new(None),
+            last_ping: Cell::new(0),
+            last_pong: Cell::new(Instant::now()),
+            batch: RefCell::new(Batch::default()),
+            batch_timer: RefCell::new(None),
+            framing: Cell::new(Framing::Text),
+            // Until the manager says it's in control, which it does as
+            // soon as the interface is running if nothing else is.
+            spare: Arc::new(AtomicBool::new(desc.failover_group.is_some())),
         }
     }
 
@@ -194,6 +473,49 @@ impl Interface {
         &self.desc.id
     }
 
+    pub fn name(&self) -> &String {
+        &self.desc.name
+    }
+
+    pub fn description(&self) -> &String {
+        &self.desc.description
+    }
+
+    /// The jigs this unit is compatible with.  If empty, it works with any jig.
+    pub fn jigs(&self) -> &Vec<UnitName> {
+        &self.desc.jigs
+    }
+
+    /// What to do if the interface exits without being stopped.
+    pub fn restart(&self) -> &Restart {
+        &self.desc.restart
+    }
+
+    /// The group of redundant interfaces this one belongs to, if any.
+    pub fn failover_group(&self) -> Option<&String> {
+        self.desc.failover_group.as_ref()
+    }
+
+    /// Put the interface in control of its FailoverGroup, or make it a
+    /// spare that only watches.
+    pub fn set_spare(&self, spare: bool) {
+        self.spare.store(spare, Ordering::SeqCst);
+    }
+
+    /// Run the OnPass or OnFail command for a finished scenario, if there is one.
+    pub fn run_hooks(
+        &self,
+        summary: &ScenarioSummary,
+        config: &Config,
+        ctrl: &Sender<ManagerControlMessage>,
+    ) {
+        let directory =
+            config.working_directory(&self.desc.unit_directory, &self.desc.working_directory);
+        self.desc
+            .hooks
+            .run(self.id(), summary, self.desc.shell, directory, config, ctrl);
+    }
+
     pub fn select(&self) -> Result<(), UnitSelectError> {
         Ok(())
     }
@@ -207,12 +529,15 @@ impl Interface {
         manager: &UnitManager,
         config: &Config,
     ) -> Result<(), UnitActivateError> {
-        let mut running = Runny::new(&self.desc.exec_start)
+        let secrets = config.resolve_command(self.id(), &self.desc.exec_start, self.desc.shell)?;
+        chaos::spawn(self.id())?;
+        let mut running = Runny::new(&secrets.text)
             .directory(&Some(config.working_directory(
                 &self.desc.unit_directory,
                 &self.desc.working_directory,
             )))
             .start()?;
+        init::spawned(running.pid());
 
         let stdout = running.take_output();
         let stderr = running.take_error();
@@ -224,13 +549,73 @@ impl Interface {
             // from stdout onto the control_sender channel.
             let thr_sender_id = control_sender_id.clone();
             let thr_sender = control_sender.clone();
-            thread::spawn(move || Self::text_read(thr_sender_id, thr_sender, stdout));
+            let supervisor = self.desc.supervisor;
+            let observer = self.desc.observer;
+            let spare = self.spare.clone();
+            thread::spawn(move || {
+                Self::text_read(
+                    thr_sender_id,
+                    supervisor,
+                    observer,
+                    spare,
+                    thr_sender,
+                    stdout,
+                )
+            });
             let thr_sender_id = control_sender_id.clone();
             let thr_sender = control_sender.clone();
-            thread::spawn(move || Self::text_read_stderr(thr_sender_id, thr_sender, stderr));
+            thread::spawn(move || {
+                Self::text_read_stderr(thr_sender_id, thr_sender, stderr, secrets)
+            });
         };
 
         *self.process.borrow_mut() = Some(running);
+        self.framing.set(Framing::Text);
+
+        if let Some(interval) = self.desc.ping_interval {
+            self.last_pong.set(Instant::now());
+            let (stop_sender, stop_receiver) = channel::<()>();
+            let thr_sender_id = control_sender_id.clone();
+            let thr_sender = control_sender.clone();
+            thread::spawn(move || {
+                // Once the stop sender is dropped, this returns Disconnected.
+                while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                    if thr_sender
+                        .send(ManagerControlMessage::new(
+                            &thr_sender_id,
+                            ManagerControlMessageContents::Heartbeat,
+                        ))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+            *self.heartbeat.borrow_mut() = Some(stop_sender);
+        }
+
+        if let Some(window) = self.desc.batch_window {
+            *self.batch.borrow_mut() = Batch::default();
+            let (timer_sender, timer_receiver) = channel::<()>();
+            let thr_sender_id = control_sender_id.clone();
+            let thr_sender = control_sender.clone();
+            thread::spawn(move || {
+                // Once the timer sender is dropped, this returns an error.
+                while timer_receiver.recv().is_ok() {
+                    thread::sleep(window);
+                    if thr_sender
+                        .send(ManagerControlMessage::new(
+                            &thr_sender_id,
+                            ManagerControlMessageContents::FlushBatch,
+                        ))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+            *self.batch_timer.borrow_mut() = Some(timer_sender);
+        }
 
         // Send some initial configuration to the client.
         control_sender
@@ -244,6 +629,10 @@ impl Interface {
     }
 
     pub fn deactivate(&self) -> Result<(), UnitDeactivateError> {
+        self.heartbeat.borrow_mut().take();
+        // Whatever is still held is sent, in case the interface is still listening.
+        self.flush_batch().ok();
+        self.batch_timer.borrow_mut().take();
         if let Some(process) = self.process.borrow_mut().take() {
             match process.terminate(Some(self.terminate_timeout)) {
                 Ok(retval) => match retval {
@@ -257,6 +646,34 @@ impl Interface {
         }
     }
 
+    /// Send the next PING, first making sure the previous ones were answered
+    /// in time.
+    pub fn heartbeat(&self) -> Result<(), Error> {
+        if let Some(timeout) = self.desc.ping_timeout {
+            if self.last_pong.get().elapsed() > timeout {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("no PONG received in {}", format_duration(timeout)),
+                ));
+            }
+        }
+        let id = self.last_ping.get() + 1;
+        self.last_ping.set(id);
+        self.output_message(ManagerStatusMessage::Ping(id))
+    }
+
+    /// Note that the interface answered a PING.  Returns false if no such
+    /// PING was ever sent.
+    pub fn pong(&self, id: &str) -> bool {
+        match id.parse::<u64>() {
+            Ok(id) if id > 0 && id <= self.last_ping.get() => {
+                self.last_pong.set(Instant::now());
+                true
+            }
+            _ => false,
+        }
+    }
+
     /// Cause a MessageControlContents to be written out.
     pub fn output_message(&self, msg: ManagerStatusMessage) -> Result<(), Error> {
         match self.desc.format {
@@ -265,6 +682,102 @@ impl Interface {
         }
     }
 
+    /// Stream an artifact to the interface as a series of DATA chunks.  Each
+    /// chunk header is followed by exactly that many raw bytes and a newline,
+    /// and a final END header carries the total size and checksum.
+    pub fn send_artifact<R: Read>(
+        &self,
+        test: &UnitName,
+        name: &str,
+        mut source: R,
+    ) -> Result<(), Error> {
+        if let InterfaceFormat::Json = self.desc.format {
+            return Err(Error::other("artifacts are only supported in text format"));
+        }
+        // Records sent before the artifact was asked for should arrive first.
+        self.flush_batch()?;
+        let mut process_opt = self.process.borrow_mut();
+        let process = match process_opt.as_mut() {
+            Some(p) => p,
+            None => return Err(Error::other("no process running")),
+        };
+
+        let framing = self.framing.get();
+        let prefix = format!(
+            "DATA {} {}",
+            Self::cfti_escape(test.id()),
+            Self::cfti_escape(name)
+        );
+        let data = |fields: Vec<Value>| {
+            let mut record = vec![Value::from("DATA"), test.id().as_str().into(), name.into()];
+            record.extend(fields);
+            Value::Array(record)
+        };
+        let mut buffer = vec![0; ARTIFACT_CHUNK_SIZE];
+        let mut index = 0;
+        let mut total = 0;
+        let mut total_crc = 0;
+        loop {
+            let count = source.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            let chunk = &buffer[..count];
+            match framing {
+                Framing::Text => {
+                    writeln!(
+                        process,
+                        "{} {} {} {:08x}",
+                        prefix,
+                        index,
+                        count,
+                        crc32(0, chunk)
+                    )?;
+                    process.write_all(chunk)?;
+                    writeln!(process)?;
+                }
+                Framing::Cbor => cbor::write_frame(
+                    process,
+                    &data(vec![
+                        Value::Unsigned(index),
+                        Value::Unsigned(count as u64),
+                        crc32(0, chunk).into(),
+                        Value::Bytes(chunk.to_vec()),
+                    ]),
+                )?,
+            }
+            index += 1;
+            total += count;
+            total_crc = crc32(total_crc, chunk);
+        }
+        match framing {
+            Framing::Text => writeln!(process, "{} END {} {:08x}", prefix, total, total_crc),
+            Framing::Cbor => cbor::write_frame(
+                process,
+                &data(vec![
+                    "END".into(),
+                    Value::Unsigned(total as u64),
+                    total_crc.into(),
+                ]),
+            ),
+        }
+    }
+
+    /// Switch to another framing.  The interface is told, in the framing it
+    /// has been using, that everything after is in the new one.
+    pub fn set_framing(&self, framing: Framing) -> Result<(), Error> {
+        self.flush_batch()?;
+        let mut process_opt = self.process.borrow_mut();
+        let process = match process_opt.as_mut() {
+            Some(p) => p,
+            None => return Err(Error::other("no process running")),
+        };
+        self.format_record(process, ManagerStatusMessage::Framing(framing))?;
+        process.flush()?;
+        self.framing.set(framing);
+        Ok(())
+    }
+
     fn json_write(&self, _: ManagerStatusMessage) -> Result<(), Error> {
         unimplemented!();
     }
@@ -276,100 +789,696 @@ impl Interface {
             .replace("\r", "\\r")
     }
 
-    /// Write a UnitInterfaceMessage to a Text-formatted output.
+    /// Write a UnitInterfaceMessage to a Text-formatted output, or add it
+    /// to the batch if BatchWindow is set.
     fn text_write(&self, msg: ManagerStatusMessage) -> Result<(), Error> {
         let mut process_opt = self.process.borrow_mut();
 
         if process_opt.is_none() {
-            return Err(Error::new(ErrorKind::Other, "no process running"));
+            return Err(Error::other("no process running"));
         }
 
         let process = process_opt.as_mut().unwrap();
 
+        if self.desc.batch_window.is_none() {
+            return self.format_record(process, msg);
+        }
+
+        let mut batch = self.batch.borrow_mut();
+        if let ManagerStatusMessage::Log(ref l) = msg {
+            let key = (
+                l.kind().as_str().to_owned(),
+                l.id().clone(),
+                l.message().clone(),
+            );
+            if self.desc.coalesce_output && batch.logs.contains(&key) {
+                batch.coalesced += 1;
+                return Ok(());
+            }
+            batch.logs.insert(key);
+        }
+        let mut record = vec![];
+        self.format_record(&mut record, msg)?;
+        if batch.records.is_empty() {
+            if let Some(ref timer) = *self.batch_timer.borrow() {
+                timer.send(()).ok();
+            }
+        }
+        batch.bytes += record.len();
+        batch.records.push(record);
+        if batch.bytes >= MAX_BATCH_BYTES {
+            Self::write_batch(process, &mut batch, self.framing.get())?;
+        }
+        Ok(())
+    }
+
+    /// Send everything held in the batch, if there is anything.
+    pub fn flush_batch(&self) -> Result<(), Error> {
+        let mut process_opt = self.process.borrow_mut();
+        match process_opt.as_mut() {
+            Some(process) => {
+                Self::write_batch(process, &mut self.batch.borrow_mut(), self.framing.get())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Send a batch as one BATCH frame, in a single write, and empty it.
+    fn write_batch<W: Write>(
+        out: &mut W,
+        batch: &mut Batch,
+        framing: Framing,
+    ) -> Result<(), Error> {
+        if batch.records.is_empty() {
+            return Ok(());
+        }
+        let batch = mem::take(batch);
+        let mut frame = vec![];
+        let header = (
+            batch.records.len() as u64,
+            batch.bytes as u64,
+            batch.coalesced as u64,
+        );
+        match framing {
+            Framing::Text => writeln!(frame, "BATCH {} {} {}", header.0, header.1, header.2)?,
+            Framing::Cbor => cbor::write_frame(
+                &mut frame,
+                &Value::Array(vec![
+                    "BATCH".into(),
+                    header.0.into(),
+                    header.1.into(),
+                    header.2.into(),
+                ]),
+            )?,
+        }
+        for record in batch.records {
+            frame.extend(record);
+        }
+        out.write_all(&frame)?;
+        out.flush()
+    }
+
+    /// Format a UnitInterfaceMessage in the interface's current framing.
+    fn format_record<W: Write>(&self, out: &mut W, msg: ManagerStatusMessage) -> Result<(), Error> {
+        match self.framing.get() {
+            Framing::Text => Self::text_format(out, msg),
+            Framing::Cbor => cbor::write_frame(out, &Self::cbor_record(msg)),
+        }
+    }
+
+    /// Format a UnitInterfaceMessage as a line of text.
+    fn text_format<W: Write>(out: &mut W, msg: ManagerStatusMessage) -> Result<(), Error> {
         match msg {
             ManagerStatusMessage::Jig(j) => match j {
                 Some(jig_name) => writeln!(
-                    process,
+                    out,
                     "JIG {}",
                     Self::cfti_escape(&format!("{}", jig_name))
                 ),
-                None => writeln!(process, "JIG"),
+                None => writeln!(out, "JIG"),
             },
             ManagerStatusMessage::Hello(id) => {
-                writeln!(process, "HELLO {}", Self::cfti_escape(&id))
+                writeln!(out, "HELLO {}", Self::cfti_escape(&id))
             }
             ManagerStatusMessage::Tests(scenario, tests) => {
-                write!(process, "TESTS {}", Self::cfti_escape(scenario.id()))?;
+                write!(out, "TESTS {}", Self::cfti_escape(scenario.id()))?;
                 for test in &tests {
-                    write!(process, " {}", Self::cfti_escape(test.id()))?;
+                    write!(out, " {}", Self::cfti_escape(test.id()))?;
                 }
-                writeln!(process)
+                writeln!(out)
             }
             ManagerStatusMessage::Scenario(name) => match name {
-                Some(s) => writeln!(process, "SCENARIO {}", Self::cfti_escape(s.id())),
-                None => writeln!(process, "SCENARIO"),
+                Some(s) => writeln!(out, "SCENARIO {}", Self::cfti_escape(s.id())),
+                None => writeln!(out, "SCENARIO"),
             },
             ManagerStatusMessage::Scenarios(list) => {
-                write!(process, "SCENARIOS")?;
+                write!(out, "SCENARIOS")?;
                 for scenario_name in list {
-                    write!(process, " {}", Self::cfti_escape(scenario_name.id()))?;
+                    write!(out, " {}", Self::cfti_escape(scenario_name.id()))?;
                 }
-                writeln!(process)
+                writeln!(out)
             }
             ManagerStatusMessage::Describe(id, field, value) => writeln!(
-                process,
+                out,
                 "DESCRIBE {}",
                 Self::cfti_escape(&format!("{} {} {} {}", id.kind(), field, id.id(), value))
             ),
             ManagerStatusMessage::Log(l) => writeln!(
-                process,
-                "LOG {}\t{}\t{}\t{}\t{}\t{}",
+                out,
+                "LOG {}\t{}\t{}\t{}\t{}\t{}\t{}",
                 l.kind().as_str(),
                 Self::cfti_escape(l.id().id()),
                 l.id().kind(),
                 l.secs(),
                 l.nsecs(),
-                Self::cfti_escape(l.message())
+                Self::cfti_escape(l.message()),
+                l.sequence()
             ),
             ManagerStatusMessage::Running(test) => {
-                writeln!(process, "RUNNING {}", Self::cfti_escape(test.id()))
+                writeln!(out, "RUNNING {}", Self::cfti_escape(test.id()))
             }
             ManagerStatusMessage::Skipped(test, reason) => writeln!(
-                process,
+                out,
                 "SKIP {} {}",
                 Self::cfti_escape(test.id()),
                 Self::cfti_escape(&reason)
             ),
-            ManagerStatusMessage::Finished(scenario, result, reason) => writeln!(
-                process,
-                "FINISH {} {} {}",
-                Self::cfti_escape(scenario.id()),
-                result,
+            ManagerStatusMessage::Retry(test, _code, reason) => writeln!(
+                out,
+                "RETRY {} {}",
+                Self::cfti_escape(test.id()),
                 Self::cfti_escape(&reason)
             ),
+            ManagerStatusMessage::Finished(summary) => {
+                write!(
+                    out,
+                    "FINISH {} {} {}",
+                    Self::cfti_escape(summary.scenario.id()),
+                    summary.code,
+                    Self::cfti_escape(&summary.reason)
+                )?;
+                write!(
+                    out,
+                    "\trun={}\tverdict={}\tduration-ms={}\tpassed={}\tfailed={}\tfaults={}\tskipped={}",
+                    Self::cfti_escape(summary.run_id.as_deref().unwrap_or("-")),
+                    summary.verdict(),
+                    summary.duration.as_millis(),
+                    summary.passed,
+                    summary.failed,
+                    summary.faults,
+                    summary.skipped
+                )?;
+                if let Some((test, reason)) = summary.first_failure {
+                    write!(
+                        out,
+                        "\tfirst-failure={} {}",
+                        Self::cfti_escape(test.id()),
+                        Self::cfti_escape(&reason)
+                    )?;
+                }
+                if let Some(parent) = summary.parent_run {
+                    write!(out, "\tparent-run={}", Self::cfti_escape(&parent))?;
+                }
+                if summary.maintenance {
+                    write!(out, "\tmode=maintenance")?;
+                }
+                if let Some(station) = summary.station {
+                    write!(out, "\tstation={}", Self::cfti_escape(&station.id))?;
+                }
+                writeln!(out)
+            }
             ManagerStatusMessage::Fail(test, _code, reason) => writeln!(
-                process,
+                out,
                 "FAIL {} {}",
                 Self::cfti_escape(test.id()),
                 Self::cfti_escape(&reason)
             ),
+            ManagerStatusMessage::Output(test, path) => writeln!(
+                out,
+                "OUTPUT {} {}",
+                Self::cfti_escape(test.id()),
+                Self::cfti_escape(&path.to_string_lossy())
+            ),
+            ManagerStatusMessage::Artifact(test, name, size) => writeln!(
+                out,
+                "ARTIFACT {} {} {}",
+                Self::cfti_escape(test.id()),
+                Self::cfti_escape(&name),
+                size
+            ),
+            ManagerStatusMessage::Fault(test, _code, reason) => writeln!(
+                out,
+                "FAULT {} {}",
+                Self::cfti_escape(test.id()),
+                Self::cfti_escape(&reason)
+            ),
             ManagerStatusMessage::Pass(test, reason) => writeln!(
-                process,
+                out,
                 "PASS {} {}",
                 Self::cfti_escape(test.id()),
                 Self::cfti_escape(&reason)
             ),
-            ManagerStatusMessage::Start(scenario) => {
-                writeln!(process, "START {}", Self::cfti_escape(scenario.id()))
+            ManagerStatusMessage::Start(scenario, jig) => match jig {
+                Some(jig) => writeln!(
+                    out,
+                    "START {} {}",
+                    Self::cfti_escape(scenario.id()),
+                    Self::cfti_escape(jig.id())
+                ),
+                None => writeln!(out, "START {}", Self::cfti_escape(scenario.id())),
+            },
+            ManagerStatusMessage::Estimate(scenario, estimate) => writeln!(
+                out,
+                "ESTIMATE {} {} {}",
+                Self::cfti_escape(scenario.id()),
+                estimate.duration.as_millis(),
+                estimate.source
+            ),
+            ManagerStatusMessage::UnitState(id, state) => writeln!(
+                out,
+                "UNIT {} {}",
+                Self::cfti_escape(&format!("{}", id)),
+                state
+            ),
+            ManagerStatusMessage::UnitFailed(id, failure) => writeln!(
+                out,
+                "ERROR {} {} {}",
+                Self::cfti_escape(&format!("{}", id)),
+                failure.code(),
+                Self::cfti_escape(failure.message())
+            ),
+            ManagerStatusMessage::Cost(id, cost) => writeln!(
+                out,
+                "COST {} {} {} {}",
+                Self::cfti_escape(id.id()),
+                cost.wall.as_millis(),
+                cost.cpu
+                    .map(|c| c.as_millis().to_string())
+                    .unwrap_or_else(|| "-".to_owned()),
+                cost.peak_memory_kb
+                    .map(|m| m.to_string())
+                    .unwrap_or_else(|| "-".to_owned())
+            ),
+            ManagerStatusMessage::Ping(id) => writeln!(out, "PING {}", id),
+            ManagerStatusMessage::Clock(problem) => match problem {
+                Some(p) => writeln!(out, "CLOCK unsynced {}", Self::cfti_escape(&p)),
+                None => writeln!(out, "CLOCK synced"),
+            },
+            ManagerStatusMessage::Aborted(scenario, by) => writeln!(
+                out,
+                "ABORTED {} {}",
+                Self::cfti_escape(scenario.id()),
+                Self::cfti_escape(&format!("{}", by))
+            ),
+            ManagerStatusMessage::Note(author, note) => writeln!(
+                out,
+                "NOTE {} {}",
+                Self::cfti_escape(&format!("{}", author)),
+                Self::cfti_escape(&note)
+            ),
+            ManagerStatusMessage::Stats(stats) => writeln!(
+                out,
+                "STATS {}\truns={}\tpassed={}\tfailed={}\tfaults={}\tskipped={}\tflakiness={}\twindow={}",
+                Self::cfti_escape(stats.test.id()),
+                stats.runs,
+                stats.passed,
+                stats.failed,
+                stats.faults,
+                stats.skipped,
+                stats.flakiness,
+                stats.window
+            ),
+            ManagerStatusMessage::Health(jig, problem) => match problem {
+                Some(p) => writeln!(
+                    out,
+                    "HEALTH {} degraded {}",
+                    Self::cfti_escape(jig.id()),
+                    Self::cfti_escape(&p)
+                ),
+                None => writeln!(out, "HEALTH {} ok", Self::cfti_escape(jig.id())),
+            },
+            ManagerStatusMessage::Incompatible(scenario, reason) => writeln!(
+                out,
+                "INCOMPATIBLE {} {}",
+                Self::cfti_escape(scenario.id()),
+                Self::cfti_escape(&reason)
+            ),
+            ManagerStatusMessage::Role(primary, group, primary_interface) => writeln!(
+                out,
+                "ROLE {} {} {}",
+                if primary { "primary" } else { "spare" },
+                Self::cfti_escape(&group),
+                Self::cfti_escape(primary_interface.id())
+            ),
+            ManagerStatusMessage::StoreEntry(jig, key, value) => writeln!(
+                out,
+                "STORE {} {} {}",
+                Self::cfti_escape(jig.id()),
+                Self::cfti_escape(&key),
+                Self::cfti_escape(&value)
+            ),
+            ManagerStatusMessage::Counter(jig, name, count, limit) => writeln!(
+                out,
+                "COUNTER {} {} {} {}",
+                Self::cfti_escape(jig.id()),
+                Self::cfti_escape(&name),
+                count,
+                limit
+                    .map(|l| l.to_string())
+                    .unwrap_or_else(|| "-".to_owned())
+            ),
+            ManagerStatusMessage::MaintenanceDue(jig, name, count, limit) => writeln!(
+                out,
+                "MAINTENANCE {} {} {} {}",
+                Self::cfti_escape(jig.id()),
+                Self::cfti_escape(&name),
+                count,
+                limit
+            ),
+            ManagerStatusMessage::Mode(maintenance) => match maintenance {
+                Some(reason) => writeln!(
+                    out,
+                    "MODE maintenance {}",
+                    Self::cfti_escape(&reason)
+                ),
+                None => writeln!(out, "MODE production"),
+            },
+            ManagerStatusMessage::Deprecated(old, new, user) => writeln!(
+                out,
+                "DEPRECATED {} {} {}",
+                Self::cfti_escape(old.id()),
+                Self::cfti_escape(new.id()),
+                Self::cfti_escape(user.id())
+            ),
+            ManagerStatusMessage::Flaky(test, flakiness, window) => writeln!(
+                out,
+                "FLAKY {} {} {}",
+                Self::cfti_escape(test.id()),
+                flakiness,
+                window
+            ),
+            ManagerStatusMessage::Schema(kind, section, directive) => {
+                write!(
+                    out,
+                    "SCHEMA {} {}\tsection={}\ttype={}\trequired={}",
+                    kind,
+                    directive.name,
+                    section,
+                    Self::cfti_escape(&directive.value_type.to_string()),
+                    if directive.required { "yes" } else { "no" }
+                )?;
+                if let Some(default) = directive.default {
+                    write!(out, "\tdefault={}", Self::cfti_escape(default))?;
+                }
+                writeln!(out)
+            }
+            ManagerStatusMessage::ConfigChanged(setting, restart_required) => writeln!(
+                out,
+                "CONFIG {} {}",
+                Self::cfti_escape(&setting),
+                if restart_required { "restart" } else { "live" }
+            ),
+            ManagerStatusMessage::Version => {
+                writeln!(out, "VERSION {}\t{}", version::build_id(), version::fields())
+            }
+            ManagerStatusMessage::Station(station) => {
+                let fields: Vec<String> = station
+                    .details()
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, Self::cfti_escape(value)))
+                    .collect();
+                writeln!(
+                    out,
+                    "STATION {}\t{}",
+                    Self::cfti_escape(&station.id),
+                    fields.join("\t")
+                )
+            }
+            ManagerStatusMessage::Snapshot(path) => writeln!(
+                out,
+                "SNAPSHOT {}",
+                Self::cfti_escape(&path.to_string_lossy())
+            ),
+            ManagerStatusMessage::Framing(framing) => {
+                writeln!(out, "FRAMING {}", framing.as_str())
             } /*
-              //            BroadcastMessageContents::Hello(name) => writeln!(stdin,
-              //                                                "HELLO {}", name),
-              //            BroadcastMessageContents::Ping(val) => writeln!(stdin,
-              //                                                "PING {}", val),
-              BroadcastMessageContents::Shutdown(reason) => writeln!(stdin, "EXIT {}", reason),
+               //            BroadcastMessageContents::Hello(name) => writeln!(stdin,
+               //                                                "HELLO {}", name),
+               //            BroadcastMessageContents::Ping(val) => writeln!(stdin,
+               //                                                "PING {}", val),
+               BroadcastMessageContents::Shutdown(reason) => writeln!(stdin, "EXIT {}", reason),
+
+               BroadcastMessageContents::Start(scenario) => writeln!(stdin, "START {}", scenario),
+               */
+        }
+    }
 
-              BroadcastMessageContents::Start(scenario) => writeln!(stdin, "START {}", scenario),
-              */
+    /// A UnitInterfaceMessage as a CBOR array: the verb, followed by the
+    /// same fields as the line of text, unescaped.  Tab-separated key=value
+    /// fields become a map, and "-" becomes null.
+    fn cbor_record(msg: ManagerStatusMessage) -> Value {
+        let id = |name: &UnitName| Value::from(name.id().as_str());
+        let record = |verb: &str, fields: Vec<Value>| {
+            let mut record = vec![Value::from(verb)];
+            record.extend(fields);
+            Value::Array(record)
+        };
+        let millis = |d: Duration| Value::Unsigned(d.as_millis() as u64);
+        match msg {
+            ManagerStatusMessage::Jig(j) => record("JIG", vec![Value::optional_text(j)]),
+            ManagerStatusMessage::Hello(name) => record("HELLO", vec![name.into()]),
+            ManagerStatusMessage::Tests(scenario, tests) => record(
+                "TESTS",
+                vec![id(&scenario), Value::Array(tests.iter().map(id).collect())],
+            ),
+            ManagerStatusMessage::Scenario(name) => record(
+                "SCENARIO",
+                vec![Value::optional_text(name.as_ref().map(|s| s.id()))],
+            ),
+            ManagerStatusMessage::Scenarios(list) => record(
+                "SCENARIOS",
+                vec![Value::Array(list.iter().map(id).collect())],
+            ),
+            ManagerStatusMessage::Describe(name, field, value) => record(
+                "DESCRIBE",
+                vec![
+                    name.kind().to_string().into(),
+                    field.to_string().into(),
+                    id(&name),
+                    value.into(),
+                ],
+            ),
+            ManagerStatusMessage::Log(l) => record(
+                "LOG",
+                vec![
+                    l.kind().as_str().into(),
+                    id(l.id()),
+                    l.id().kind().to_string().into(),
+                    l.secs().into(),
+                    l.nsecs().into(),
+                    l.message().as_str().into(),
+                    l.sequence().into(),
+                ],
+            ),
+            ManagerStatusMessage::Running(test) => record("RUNNING", vec![id(&test)]),
+            ManagerStatusMessage::Skipped(test, reason) => {
+                record("SKIP", vec![id(&test), reason.into()])
+            }
+            ManagerStatusMessage::Retry(test, _code, reason) => {
+                record("RETRY", vec![id(&test), reason.into()])
+            }
+            ManagerStatusMessage::Finished(summary) => {
+                let mut fields = vec![
+                    ("run".into(), Value::optional_text(summary.run_id.as_ref())),
+                    ("verdict".into(), summary.verdict().to_string().into()),
+                    ("duration-ms".into(), millis(summary.duration)),
+                    ("passed".into(), summary.passed.into()),
+                    ("failed".into(), summary.failed.into()),
+                    ("faults".into(), summary.faults.into()),
+                    ("skipped".into(), summary.skipped.into()),
+                ];
+                if let Some((ref test, ref reason)) = summary.first_failure {
+                    fields.push((
+                        "first-failure".into(),
+                        Value::Array(vec![id(test), reason.as_str().into()]),
+                    ));
+                }
+                if let Some(ref parent) = summary.parent_run {
+                    fields.push(("parent-run".into(), parent.as_str().into()));
+                }
+                if summary.maintenance {
+                    fields.push(("mode".into(), "maintenance".into()));
+                }
+                if let Some(ref station) = summary.station {
+                    fields.push(("station".into(), station.id.as_str().into()));
+                }
+                record(
+                    "FINISH",
+                    vec![
+                        id(&summary.scenario),
+                        summary.code.into(),
+                        summary.reason.as_str().into(),
+                        Value::Map(fields),
+                    ],
+                )
+            }
+            ManagerStatusMessage::Fail(test, _code, reason) => {
+                record("FAIL", vec![id(&test), reason.into()])
+            }
+            ManagerStatusMessage::Output(test, path) => record(
+                "OUTPUT",
+                vec![id(&test), path.to_string_lossy().into_owned().into()],
+            ),
+            ManagerStatusMessage::Artifact(test, name, size) => {
+                record("ARTIFACT", vec![id(&test), name.into(), size.into()])
+            }
+            ManagerStatusMessage::Fault(test, _code, reason) => {
+                record("FAULT", vec![id(&test), reason.into()])
+            }
+            ManagerStatusMessage::Pass(test, reason) => {
+                record("PASS", vec![id(&test), reason.into()])
+            }
+            ManagerStatusMessage::Start(scenario, jig) => record(
+                "START",
+                vec![
+                    id(&scenario),
+                    Value::optional_text(jig.as_ref().map(|j| j.id())),
+                ],
+            ),
+            ManagerStatusMessage::Estimate(scenario, estimate) => record(
+                "ESTIMATE",
+                vec![
+                    id(&scenario),
+                    Value::Unsigned(estimate.duration.as_millis() as u64),
+                    estimate.source.to_string().into(),
+                ],
+            ),
+            ManagerStatusMessage::UnitState(name, state) => record(
+                "UNIT",
+                vec![name.to_string().into(), state.to_string().into()],
+            ),
+            ManagerStatusMessage::UnitFailed(name, failure) => record(
+                "ERROR",
+                vec![
+                    name.to_string().into(),
+                    failure.code().into(),
+                    failure.message().as_str().into(),
+                ],
+            ),
+            ManagerStatusMessage::Cost(name, cost) => record(
+                "COST",
+                vec![
+                    id(&name),
+                    millis(cost.wall),
+                    cost.cpu.map(millis).unwrap_or(Value::Null),
+                    Value::optional_unsigned(cost.peak_memory_kb),
+                ],
+            ),
+            ManagerStatusMessage::Ping(ping) => record("PING", vec![ping.into()]),
+            ManagerStatusMessage::Clock(problem) => match problem {
+                Some(p) => record("CLOCK", vec!["unsynced".into(), p.into()]),
+                None => record("CLOCK", vec!["synced".into()]),
+            },
+            ManagerStatusMessage::Aborted(scenario, by) => {
+                record("ABORTED", vec![id(&scenario), by.to_string().into()])
+            }
+            ManagerStatusMessage::Note(author, note) => {
+                record("NOTE", vec![author.to_string().into(), note.into()])
+            }
+            ManagerStatusMessage::Stats(stats) => record(
+                "STATS",
+                vec![
+                    id(&stats.test),
+                    Value::Map(vec![
+                        ("runs".into(), stats.runs.into()),
+                        ("passed".into(), stats.passed.into()),
+                        ("failed".into(), stats.failed.into()),
+                        ("faults".into(), stats.faults.into()),
+                        ("skipped".into(), stats.skipped.into()),
+                        ("flakiness".into(), stats.flakiness.into()),
+                        ("window".into(), Value::Unsigned(stats.window as u64)),
+                    ]),
+                ],
+            ),
+            ManagerStatusMessage::Health(jig, problem) => match problem {
+                Some(p) => record("HEALTH", vec![id(&jig), "degraded".into(), p.into()]),
+                None => record("HEALTH", vec![id(&jig), "ok".into()]),
+            },
+            ManagerStatusMessage::Incompatible(scenario, reason) => {
+                record("INCOMPATIBLE", vec![id(&scenario), reason.into()])
+            }
+            ManagerStatusMessage::Role(primary, group, primary_interface) => record(
+                "ROLE",
+                vec![
+                    if primary { "primary" } else { "spare" }.into(),
+                    group.into(),
+                    id(&primary_interface),
+                ],
+            ),
+            ManagerStatusMessage::StoreEntry(jig, key, value) => {
+                record("STORE", vec![id(&jig), key.into(), value.into()])
+            }
+            ManagerStatusMessage::Counter(jig, name, count, limit) => record(
+                "COUNTER",
+                vec![
+                    id(&jig),
+                    name.into(),
+                    count.into(),
+                    Value::optional_unsigned(limit),
+                ],
+            ),
+            ManagerStatusMessage::MaintenanceDue(jig, name, count, limit) => record(
+                "MAINTENANCE",
+                vec![id(&jig), name.into(), count.into(), limit.into()],
+            ),
+            ManagerStatusMessage::Mode(maintenance) => match maintenance {
+                Some(reason) => record("MODE", vec!["maintenance".into(), reason.into()]),
+                None => record("MODE", vec!["production".into()]),
+            },
+            ManagerStatusMessage::Deprecated(old, new, user) => {
+                record("DEPRECATED", vec![id(&old), id(&new), id(&user)])
+            }
+            ManagerStatusMessage::Flaky(test, flakiness, window) => record(
+                "FLAKY",
+                vec![id(&test), flakiness.into(), Value::Unsigned(window as u64)],
+            ),
+            ManagerStatusMessage::Schema(kind, section, directive) => {
+                let mut fields = vec![
+                    ("section".into(), section.into()),
+                    ("type".into(), directive.value_type.to_string().into()),
+                    ("required".into(), directive.required.into()),
+                ];
+                if let Some(default) = directive.default {
+                    fields.push(("default".into(), default.into()));
+                }
+                record(
+                    "SCHEMA",
+                    vec![
+                        kind.to_string().into(),
+                        directive.name.into(),
+                        Value::Map(fields),
+                    ],
+                )
+            }
+            ManagerStatusMessage::ConfigChanged(setting, restart_required) => record(
+                "CONFIG",
+                vec![
+                    setting.into(),
+                    if restart_required { "restart" } else { "live" }.into(),
+                ],
+            ),
+            ManagerStatusMessage::Version => record(
+                "VERSION",
+                vec![
+                    version::build_id().into(),
+                    Value::Map(vec![
+                        ("version".into(), version::VERSION.into()),
+                        ("git".into(), version::GIT_VERSION.into()),
+                        ("commit".into(), version::GIT_HASH.into()),
+                        ("built".into(), version::BUILD_DATE.into()),
+                        ("features".into(), version::features().join(",").into()),
+                    ]),
+                ],
+            ),
+            ManagerStatusMessage::Station(station) => record(
+                "STATION",
+                vec![
+                    station.id.clone().into(),
+                    Value::Map(
+                        station
+                            .details()
+                            .into_iter()
+                            .map(|(key, value)| (key.into(), value.into()))
+                            .collect(),
+                    ),
+                ],
+            ),
+            ManagerStatusMessage::Snapshot(path) => {
+                record("SNAPSHOT", vec![path.to_string_lossy().into_owned().into()])
+            }
+            ManagerStatusMessage::Framing(framing) => {
+                record("FRAMING", vec![framing.as_str().into()])
+            }
         }
     }
 
@@ -412,6 +1521,7 @@ impl Interface {
         id: UnitName,
         control: Sender<ManagerControlMessage>,
         output: RunningOutput,
+        secrets: Resolved,
     ) {
         for line in BufReader::new(output).lines() {
             let line = line.expect("Unable to get next line");
@@ -419,7 +1529,7 @@ impl Interface {
             if control
                 .send(ManagerControlMessage::new(
                     &id,
-                    ManagerControlMessageContents::LogError(line),
+                    ManagerControlMessageContents::LogError(secrets.mask(&line)),
                 ))
                 .is_err()
             {
@@ -428,9 +1538,16 @@ impl Interface {
         }
     }
 
-    fn text_read(id: UnitName, control: Sender<ManagerControlMessage>, stdout: RunningOutput) {
+    fn text_read(
+        id: UnitName,
+        supervisor: bool,
+        mut observer: bool,
+        spare: Arc<AtomicBool>,
+        control: Sender<ManagerControlMessage>,
+        stdout: RunningOutput,
+    ) {
         for line in BufReader::new(stdout).lines() {
-            let line = line.expect("Unable to get next line");
+            let line = chaos::truncate(line.expect("Unable to get next line"));
             let mut words: Vec<String> = line
                 .split_whitespace()
                 .map(|x| Self::cfti_unescape(x.to_owned()))
@@ -445,10 +1562,19 @@ impl Interface {
             words.remove(0);
 
             let response = match verb.as_str() {
+                v if (observer || spare.load(Ordering::SeqCst)) && CONTROL_VERBS.contains(&v) => {
+                    ManagerControlMessageContents::Denied(v.to_owned())
+                }
+                "observe" => {
+                    observer = true;
+                    ManagerControlMessageContents::Log(
+                        "interface is now an observer".to_owned(),
+                    )
+                }
                 "scenarios" => ManagerControlMessageContents::Scenarios,
                 "scenario" => match UnitName::from_str(
                     words
-                        .get(0)
+                        .first()
                         .unwrap_or(&"".to_owned())
                         .to_lowercase()
                         .as_str(),
@@ -478,35 +1604,202 @@ impl Interface {
                 "jig" => ManagerControlMessageContents::Jig,
                 "log" => ManagerControlMessageContents::Log(words.join(" ")),
                 "start" => {
+                    // The scenario may be left out, and followed by parameters.
+                    let (scenario, assignments) = match words.first() {
+                        Some(word) if !word.contains('=') => (Some(word), &words[1..]),
+                        _ => (None, &words[..]),
+                    };
+                    match parameter::parse_assignments(assignments) {
+                        Err(e) => ManagerControlMessageContents::Error(e),
+                        Ok(parameters) => match scenario {
+                            None => ManagerControlMessageContents::StartScenario(None, parameters, None),
+                            Some(scenario) => match UnitName::from_str(
+                                scenario.to_lowercase().as_str(),
+                                "scenario",
+                            ) {
+                                Err(e) => ManagerControlMessageContents::Error(format!(
+                                    "Invalid scenario name: {}",
+                                    e
+                                )),
+                                Ok(o) => {
+                                    ManagerControlMessageContents::StartScenario(Some(o), parameters, None)
+                                }
+                            },
+                        },
+                    }
+                }
+                "shutdown" => {
                     if words.is_empty() {
-                        ManagerControlMessageContents::StartScenario(None)
+                        ManagerControlMessageContents::Shutdown(None)
                     } else {
-                        match UnitName::from_str(
-                            words
-                                .get(0)
-                                .unwrap_or(&"".to_owned())
-                                .to_lowercase()
-                                .as_str(),
-                            "scenario",
-                        ) {
+                        ManagerControlMessageContents::Shutdown(Some(words.join(" ")))
+                    }
+                }
+                "abort" => ManagerControlMessageContents::AbortTests,
+                "units" => ManagerControlMessageContents::UnitStates,
+                "stats" => ManagerControlMessageContents::Stats,
+                "counters" => ManagerControlMessageContents::Counters,
+                "store" => match words.first() {
+                    Some(jig) => match UnitName::from_str(jig.to_lowercase().as_str(), "jig") {
+                        Ok(jig_name) => ManagerControlMessageContents::Store(jig_name),
+                        Err(e) => ManagerControlMessageContents::Error(format!(
+                            "Invalid jig name specified: {}",
+                            e
+                        )),
+                    },
+                    None => ManagerControlMessageContents::Error(
+                        "store requires a jig name".to_owned(),
+                    ),
+                },
+                "schema" => match words.first().map(|x| x.to_lowercase()) {
+                    None => ManagerControlMessageContents::Schema(None),
+                    Some(suffix) => match UnitKind::from_suffix(suffix.trim_start_matches('.')) {
+                        Some(kind) => ManagerControlMessageContents::Schema(Some(kind)),
+                        None => ManagerControlMessageContents::Error(format!(
+                            "Unrecognized unit type: {}",
+                            suffix
+                        )),
+                    },
+                },
+                "fetch" => match (words.first(), words.get(1)) {
+                    (Some(test), Some(artifact)) => {
+                        match UnitName::from_str(test.to_lowercase().as_str(), "test") {
+                            Ok(test_name) => ManagerControlMessageContents::FetchArtifact(
+                                test_name,
+                                artifact.clone(),
+                            ),
                             Err(e) => ManagerControlMessageContents::Error(format!(
-                                "Invalid scenario name: {}",
+                                "Invalid test name specified: {}",
                                 e
                             )),
-                            Ok(o) => ManagerControlMessageContents::StartScenario(Some(o)),
+                        }
+                    }
+                    _ => ManagerControlMessageContents::Error(
+                        "fetch requires a test name and an artifact name".to_owned(),
+                    ),
+                },
+                "disable" | "enable" => {
+                    match UnitName::from_str(words.first().unwrap_or(&"".to_owned()), "") {
+                        Err(e) => ManagerControlMessageContents::Error(format!(
+                            "Invalid unit name: {}",
+                            e
+                        )),
+                        Ok(o) => {
+                            if verb == "disable" {
+                                ManagerControlMessageContents::DisableUnit(o)
+                            } else {
+                                ManagerControlMessageContents::EnableUnit(o)
+                            }
                         }
                     }
                 }
-                "shutdown" => {
+                "rerun" => match words.first().map(|x| x.to_lowercase()) {
+                    Some(ref what) if what == "failed" => {
+                        ManagerControlMessageContents::RerunFailed
+                    }
+                    _ => ManagerControlMessageContents::Error(format!(
+                        "Unrecognized rerun target: {}",
+                        words.join(" ")
+                    )),
+                },
+                "reset" => match (
+                    words.first().map(|x| x.to_lowercase()),
+                    words.get(1),
+                    words.get(2),
+                ) {
+                    (Some(ref what), Some(jig), Some(counter)) if what == "counter" => {
+                        match UnitName::from_str(jig.to_lowercase().as_str(), "jig") {
+                            Ok(jig_name) => ManagerControlMessageContents::ResetCounter(
+                                jig_name,
+                                counter.clone(),
+                            ),
+                            Err(e) => ManagerControlMessageContents::Error(format!(
+                                "Invalid jig name specified: {}",
+                                e
+                            )),
+                        }
+                    }
+                    (Some(ref what), Some(jig), key) if what == "store" => {
+                        match UnitName::from_str(jig.to_lowercase().as_str(), "jig") {
+                            Ok(jig_name) => {
+                                ManagerControlMessageContents::ResetStore(jig_name, key.cloned())
+                            }
+                            Err(e) => ManagerControlMessageContents::Error(format!(
+                                "Invalid jig name specified: {}",
+                                e
+                            )),
+                        }
+                    }
+                    _ => ManagerControlMessageContents::Error(
+                        "reset requires \"counter\", a jig name, and a counter name, or \"store\", a jig name, and optionally a key".to_owned(),
+                    ),
+                },
+                "mode" => match words.first().map(|x| x.to_lowercase()) {
+                    _ if !supervisor => ManagerControlMessageContents::Error(
+                        "only a supervisor interface may change the mode".to_owned(),
+                    ),
+                    Some(ref mode) if mode == "maintenance" => {
+                        ManagerControlMessageContents::SetMaintenance(Some(words[1..].join(" ")))
+                    }
+                    Some(ref mode) if mode == "production" => {
+                        ManagerControlMessageContents::SetMaintenance(None)
+                    }
+                    _ => ManagerControlMessageContents::Error(format!(
+                        "Unrecognized mode: {}",
+                        words.join(" ")
+                    )),
+                },
+                "reload" => match words.first().map(|x| x.to_lowercase()) {
+                    Some(ref what) if what == "config" => {
+                        ManagerControlMessageContents::ReloadConfig
+                    }
+                    _ => ManagerControlMessageContents::Error(format!(
+                        "Unrecognized reload target: {}",
+                        words.join(" ")
+                    )),
+                },
+                "input" => {
                     if words.is_empty() {
-                        ManagerControlMessageContents::Shutdown(None)
+                        ManagerControlMessageContents::Error(
+                            "input requires a test name".to_owned(),
+                        )
                     } else {
-                        ManagerControlMessageContents::Shutdown(Some(words.join(" ")))
+                        let test = words.remove(0);
+                        match UnitName::from_str(test.to_lowercase().as_str(), "test") {
+                            Ok(test_name) => {
+                                ManagerControlMessageContents::TestInput(test_name, words.join(" "))
+                            }
+                            Err(e) => ManagerControlMessageContents::Error(format!(
+                                "Invalid test name specified: {}",
+                                e
+                            )),
+                        }
                     }
                 }
-                "abort" => ManagerControlMessageContents::AbortTests,
+                "note" => {
+                    if words.is_empty() {
+                        ManagerControlMessageContents::Error("note requires some text".to_owned())
+                    } else {
+                        ManagerControlMessageContents::Note(words.join(" "))
+                    }
+                }
+                "snapshot" => ManagerControlMessageContents::Snapshot,
+                "version" => ManagerControlMessageContents::Version,
+                "station" => ManagerControlMessageContents::Station,
+                "pong" => ManagerControlMessageContents::Pong(words.join(" ")),
+                "framing" => match words.first().map(|x| x.to_lowercase()) {
+                    Some(ref framing) if framing == "text" => {
+                        ManagerControlMessageContents::Framing(Framing::Text)
+                    }
+                    Some(ref framing) if framing == "cbor" => {
+                        ManagerControlMessageContents::Framing(Framing::Cbor)
+                    }
+                    _ => ManagerControlMessageContents::Error(format!(
+                        "Unrecognized framing: {}",
+                        words.join(" ")
+                    )),
+                },
                 /*
-                "pong" => ControlMessageContents::Pong(words[0].to_lowercase()),
                 "hello" => ControlMessageContents::Hello(words.join(" ")),
                 */
                 v => ManagerControlMessageContents::Unimplemented(v.to_owned(), words.join(" ")),
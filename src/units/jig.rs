@@ -1,20 +1,59 @@
+extern crate humantime;
 extern crate runny;
 extern crate systemd_parser;
 
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, SystemTime};
 
+use argv;
 use config::Config;
+use duration;
+use hooks::Hooks;
+use init;
+use presentation::Presentation;
+use schema::{DirectiveSchema, UnitSchema, ValueType};
+use specifier;
 use unit::{
-    UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
-    UnitIncompatibleReason, UnitName, UnitSelectError,
+    parse_bool, UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
+    UnitIncompatibleReason, UnitKind, UnitName, UnitSelectError,
 };
-use unitmanager::UnitManager;
+use unitbroadcaster::ScenarioSummary;
+use unitfile;
+use unitmanager::{ManagerControlMessage, UnitManager};
+use units::test::TestDescription;
 
+use self::humantime::format_duration;
 use self::runny::Runny;
 use self::systemd_parser::items::DirectiveEntry;
 
+/// The kinds of named sections a jig file may have.
+pub const NAMED_SECTIONS: &[&str] = &["Override"];
+
+/// Test directives that a jig can't override.
+const NOT_OVERRIDDEN: &[&str] = &[
+    "Name",
+    "Jigs",
+    "Requires",
+    "RequiresFeatures",
+    "Suggests",
+    "Provides",
+    "Alias",
+    "Type",
+    "Disabled",
+];
+
+/// Something on a jig that wears out, counted across runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JigCounter {
+    pub name: String,
+
+    /// The count at which maintenance is due, if there is one.
+    pub limit: Option<u64>,
+}
+
 /// A struct defining an in-memory representation of a .jig file
 #[derive(Clone)]
 pub struct JigDescription {
@@ -27,6 +66,9 @@ pub struct JigDescription {
     /// A detailed description of this jig, up to one paragraph.
     description: String,
 
+    /// How operator GUIs should show it.
+    presentation: Presentation,
+
     /// Name of the scenario to run by default, if any
     default_scenario: Option<UnitName>,
 
@@ -41,11 +83,43 @@ pub struct JigDescription {
 
     /// A file whose existence indicates this jig is compatible
     test_file: Option<String>,
+
+    /// True if this unit was disabled with a Disabled= directive
+    disabled: bool,
+
+    /// How long a calibration of this jig stays good, if it needs calibrating
+    calibration_interval: Option<Duration>,
+
+    /// The scenario that calibrates this jig
+    calibration_scenario: Option<UnitName>,
+
+    /// Refuse to run other scenarios while the calibration has expired
+    require_calibration: bool,
+
+    /// Counters kept for this jig
+    counters: Vec<JigCounter>,
+
+    /// Counters that go up by one for every scenario run on this jig
+    count_runs: Vec<String>,
+
+    /// What the jig is fitted with, such as "rf-shield", for tests that
+    /// need it
+    features: Vec<String>,
+
+    /// Directives that replace those of tests run on this jig, from its
+    /// [Override "<test>"] sections, as (key, value).
+    overrides: Vec<(UnitName, unitfile::Directives)>,
+
+    /// Commands to run when a scenario run on this jig passes or fails
+    hooks: Hooks,
 }
 
 impl JigDescription {
-    pub fn from_path(path: &Path) -> Result<JigDescription, UnitDescriptionError> {
-        let unit_name = UnitName::from_path(path)?;
+    pub fn from_path(
+        unit_name: &UnitName,
+        path: &Path,
+    ) -> Result<JigDescription, UnitDescriptionError> {
+        let unit_name = unit_name.clone();
 
         // Parse the file into a systemd unit_file object
         let mut contents = String::with_capacity(8192);
@@ -58,7 +132,18 @@ impl JigDescription {
         unit_name: UnitName,
         path: &Path,
     ) -> Result<JigDescription, UnitDescriptionError> {
-        let unit_file = systemd_parser::parse_string(contents)?;
+        let parsed = unitfile::parse_to_keep(contents, NAMED_SECTIONS)?;
+        Self::from_parsed(&parsed, unit_name, path)
+    }
+
+    /// Describe a jig from a file that has already been parsed.
+    pub fn from_parsed(
+        parsed: &unitfile::ParsedFile,
+        unit_name: UnitName,
+        path: &Path,
+    ) -> Result<JigDescription, UnitDescriptionError> {
+        let unit_file = parsed.unit()?;
+        let named = parsed.named.clone();
 
         if !unit_file.has_category("Jig") {
             return Err(UnitDescriptionError::MissingSection("Jig".to_owned()));
@@ -68,17 +153,30 @@ impl JigDescription {
             id: unit_name,
             name: "".to_owned(),
             description: "".to_owned(),
+            presentation: Presentation::default(),
             default_scenario: None,
             working_directory: None,
             unit_directory: path.parent().unwrap().to_owned(),
             test_program: None,
             test_file: None,
+            disabled: false,
+            calibration_interval: None,
+            calibration_scenario: None,
+            require_calibration: false,
+            counters: vec![],
+            count_runs: vec![],
+            features: vec![],
+            overrides: vec![],
+            hooks: Hooks::default(),
         };
 
         for entry in unit_file.lookup_by_category("Jig") {
             if let DirectiveEntry::Solo(ref directive) = entry {
                 match directive.key() {
                     "Name" => jig_description.name = directive.value().unwrap_or("").to_owned(),
+                    "Icon" | "Color" | "Group" | "HideFromOperator" => jig_description
+                        .presentation
+                        .set("Jig", directive.key(), directive.value())?,
                     "Description" => {
                         jig_description.description = directive.value().unwrap_or("").to_owned()
                     }
@@ -97,15 +195,185 @@ impl JigDescription {
                         }
                     }
                     "TestProgram" => {
-                        jig_description.test_program = directive.value().map(|s| s.to_owned())
+                        jig_description.test_program = match directive.value() {
+                            Some(s) => {
+                                argv::check("Jig", "TestProgram", s)?;
+                                specifier::check("Jig", "TestProgram", s)?;
+                                Some(s.to_owned())
+                            }
+                            None => None,
+                        }
                     }
+                    "Disabled" => {
+                        jig_description.disabled = parse_bool("Jig", "Disabled", directive.value())?
+                    }
+                    "OnPass" | "OnFail" | "HookTimeoutSec" => {
+                        jig_description
+                            .hooks
+                            .set("Jig", directive.key(), directive.value())?
+                    }
+                    "CalibrationInterval" => {
+                        jig_description.calibration_interval = match directive.value() {
+                            Some(s) => Some(duration::parse(s)?),
+                            None => None,
+                        }
+                    }
+                    "CalibrationScenario" => {
+                        jig_description.calibration_scenario = match directive.value() {
+                            Some(s) => Some(UnitName::from_str(s, "scenario")?),
+                            None => None,
+                        }
+                    }
+                    "RequireCalibration" => {
+                        jig_description.require_calibration =
+                            parse_bool("Jig", "RequireCalibration", directive.value())?
+                    }
+                    "Counters" => {
+                        jig_description.counters = Self::split_list(directive.value())
+                            .into_iter()
+                            .map(|counter| Self::parse_counter(&counter))
+                            .collect::<Result<_, _>>()?
+                    }
+                    "CountRuns" => jig_description.count_runs = Self::split_list(directive.value()),
+                    "Features" => jig_description.features = Self::split_list(directive.value()),
                     &_ => (),
                 }
             }
         }
+
+        // There'd be no way to ever renew the calibration.
+        if jig_description.calibration_interval.is_some()
+            && jig_description.calibration_scenario.is_none()
+        {
+            return Err(UnitDescriptionError::MissingValue(
+                "Jig".to_owned(),
+                "CalibrationScenario".to_owned(),
+            ));
+        }
+        for name in &jig_description.count_runs {
+            if !jig_description.counters.iter().any(|c| c.name == *name) {
+                return Err(UnitDescriptionError::InvalidValue(
+                    "Jig".to_owned(),
+                    "CountRuns".to_owned(),
+                    name.clone(),
+                    jig_description
+                        .counters
+                        .iter()
+                        .map(|c| c.name.clone())
+                        .collect(),
+                ));
+            }
+        }
+        for section in named {
+            let test = UnitName::from_str(&section.name, "test")?;
+            if *test.kind() != UnitKind::Test {
+                return Err(UnitDescriptionError::InvalidValue(
+                    "Jig".to_owned(),
+                    "Override".to_owned(),
+                    section.name,
+                    vec!["the name of a test, such as \"flash.test\"".to_owned()],
+                ));
+            }
+            for (key, value) in &section.directives {
+                if !Self::can_override(key) {
+                    return Err(UnitDescriptionError::InvalidValue(
+                        format!("Override \"{}\"", section.name),
+                        key.clone(),
+                        value.clone().unwrap_or_default(),
+                        vec![format!(
+                            "a [Test] directive other than {}",
+                            NOT_OVERRIDDEN.join(", ")
+                        )],
+                    ));
+                }
+            }
+            match jig_description
+                .overrides
+                .iter_mut()
+                .find(|(name, _)| *name == test)
+            {
+                Some((_, directives)) => directives.extend(section.directives),
+                None => jig_description.overrides.push((test, section.directives)),
+            }
+        }
+        jig_description.hooks.check("Jig", false)?;
         Ok(jig_description)
     }
 
+    /// Whether a jig may override a test's directive.  Those that decide
+    /// which tests are loaded and the order they're run in can't be changed
+    /// once a scenario is running.
+    fn can_override(key: &str) -> bool {
+        !NOT_OVERRIDDEN.contains(&key)
+            && TestDescription::schema()
+                .directives
+                .iter()
+                .any(|directive| directive.name == key)
+    }
+
+    /// The directives that [Jig] sections may contain.
+    pub fn schema() -> UnitSchema {
+        UnitSchema {
+            kind: UnitKind::Jig,
+            section: "Jig",
+            directives: vec![
+                DirectiveSchema::new("Name", ValueType::Text),
+                DirectiveSchema::new("Description", ValueType::Text),
+                DirectiveSchema::new("Icon", ValueType::Text),
+                DirectiveSchema::new("Color", ValueType::Text),
+                DirectiveSchema::new("Group", ValueType::Text),
+                DirectiveSchema::new("HideFromOperator", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("WorkingDirectory", ValueType::Path),
+                DirectiveSchema::new("DefaultWorkingDirectory", ValueType::Path),
+                DirectiveSchema::new("TestFile", ValueType::Path),
+                DirectiveSchema::new("DefaultScenario", ValueType::Unit(UnitKind::Scenario)),
+                DirectiveSchema::new("TestProgram", ValueType::Command),
+                DirectiveSchema::new("Disabled", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("CalibrationInterval", ValueType::Duration),
+                DirectiveSchema::new("CalibrationScenario", ValueType::Unit(UnitKind::Scenario)),
+                DirectiveSchema::new("RequireCalibration", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("Counters", ValueType::Names),
+                DirectiveSchema::new("CountRuns", ValueType::Names),
+                DirectiveSchema::new("Features", ValueType::Names),
+                DirectiveSchema::new("OnPass", ValueType::Command),
+                DirectiveSchema::new("OnFail", ValueType::Command),
+                DirectiveSchema::new("HookTimeoutSec", ValueType::Duration).with_default("60s"),
+            ],
+        }
+    }
+
+    /// Split a space- or comma-separated list.
+    fn split_list(value: Option<&str>) -> Vec<String> {
+        value
+            .unwrap_or("")
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_owned())
+            .collect()
+    }
+
+    /// Parse a counter, given as "<name>" or "<name>:<limit>".
+    fn parse_counter(counter: &str) -> Result<JigCounter, UnitDescriptionError> {
+        let (name, limit) = match counter.split_once(':') {
+            Some((name, limit)) => match limit.parse() {
+                Ok(limit) => (name, Some(limit)),
+                Err(_) => {
+                    return Err(UnitDescriptionError::InvalidValue(
+                        "Jig".to_owned(),
+                        "Counters".to_owned(),
+                        counter.to_owned(),
+                        vec!["<name>".to_owned(), "<name>:<limit>".to_owned()],
+                    ))
+                }
+            },
+            None => (counter, None),
+        };
+        Ok(JigCounter {
+            name: name.to_owned(),
+            limit,
+        })
+    }
+
     /// Determine if a unit is compatible with this system.
     /// Returns Ok(()) if it is, and Err(String) if not.
     pub fn is_compatible(
@@ -126,13 +394,19 @@ impl JigDescription {
         if let Some(ref cmd_str) = self.test_program {
             use std::io::{BufRead, BufReader};
 
-            let running = Runny::new(cmd_str)
+            let secrets = config
+                .resolve_command(self.id(), cmd_str, false)
+                .map_err(|e| {
+                    UnitIncompatibleReason::TestProgramFailed(format!("{} ({})", cmd_str, e))
+                })?;
+            let running = Runny::new(&secrets.text)
                 .directory(&Some(
                     config.working_directory(&self.unit_directory, &self.working_directory),
                 ))
                 .timeout(*config.timeout())
                 .path(config.paths().clone())
                 .start()?;
+            init::spawned(running.pid());
 
             let mut reader = BufReader::new(running);
             let mut buf = String::new();
@@ -144,7 +418,8 @@ impl JigDescription {
             let result = reader.get_ref().result();
             if result != 0 {
                 return Err(UnitIncompatibleReason::TestProgramReturnedNonzero(
-                    result, buf,
+                    result,
+                    secrets.mask(&buf),
                 ));
             }
         }
@@ -155,6 +430,11 @@ impl JigDescription {
         &self.id
     }
 
+    /// Returns true if this unit was disabled from within its unit file.
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
     pub fn load(
         &self,
         manager: &UnitManager,
@@ -189,10 +469,87 @@ impl Jig {
         &self.description.description
     }
 
+    /// How operator GUIs should show the unit.
+    pub fn presentation(&self) -> &Presentation {
+        &self.description.presentation
+    }
+
     pub fn default_scenario(&self) -> &Option<UnitName> {
         &self.description.default_scenario
     }
 
+    /// The scenario that calibrates this jig, if it needs calibrating.
+    pub fn calibration_scenario(&self) -> Option<&UnitName> {
+        self.description.calibration_scenario.as_ref()
+    }
+
+    /// The counters kept for this jig.
+    pub fn counters(&self) -> &Vec<JigCounter> {
+        &self.description.counters
+    }
+
+    /// The counters that go up by one for every scenario run on this jig.
+    pub fn count_runs(&self) -> &Vec<String> {
+        &self.description.count_runs
+    }
+
+    /// What the jig is fitted with.
+    pub fn features(&self) -> &Vec<String> {
+        &self.description.features
+    }
+
+    /// Run the OnPass or OnFail command for a scenario that finished on this
+    /// jig, if there is one.
+    pub fn run_hooks(
+        &self,
+        summary: &ScenarioSummary,
+        config: &Config,
+        ctrl: &Sender<ManagerControlMessage>,
+    ) {
+        let directory = config.working_directory(
+            &self.description.unit_directory,
+            &self.description.working_directory,
+        );
+        self.description
+            .hooks
+            .run(self.id(), summary, false, directory, config, ctrl);
+    }
+
+    /// The directives this jig overrides for a test, as (key, value).
+    pub fn overrides_for(&self, test: &UnitName) -> &[(String, Option<String>)] {
+        self.description
+            .overrides
+            .iter()
+            .find(|(name, _)| name == test)
+            .map(|(_, directives)| directives.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Returns true if other scenarios must not run while the calibration has expired.
+    pub fn requires_calibration(&self) -> bool {
+        self.description.require_calibration
+    }
+
+    /// Why the jig's calibration can't be relied on, given when it was last
+    /// calibrated, or None if it's still good (or the jig doesn't need it).
+    pub fn calibration_problem(&self, last: Option<SystemTime>, now: SystemTime) -> Option<String> {
+        let interval = self.description.calibration_interval?;
+        let last = match last {
+            Some(last) => last,
+            None => return Some("never calibrated".to_owned()),
+        };
+        // A calibration from the future means the clock moved back, which
+        // isn't the jig's fault.
+        let age = now.duration_since(last).unwrap_or_default();
+        if age < interval {
+            return None;
+        }
+        Some(format!(
+            "calibration expired {} ago",
+            format_duration(Duration::from_secs((age - interval).as_secs()))
+        ))
+    }
+
     pub fn select(&self) -> Result<(), UnitSelectError> {
         Ok(())
     }
@@ -206,12 +563,18 @@ impl Jig {
         _manager: &UnitManager,
         config: &Config,
     ) -> Result<(), UnitActivateError> {
+        self.bind(config);
+        Ok(())
+    }
+
+    /// Make this the jig that tests run against, by using its working directory.
+    pub fn bind(&self, config: &Config) {
+        config.set_bound_jig(Some(self.id()));
         if let Some(ref wd) = self.description.working_directory {
             config.set_jig_working_directory(wd);
         } else {
             config.clear_jig_working_directory();
         }
-        Ok(())
     }
 
     pub fn deactivate(&self) -> Result<(), UnitDeactivateError> {
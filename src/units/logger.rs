@@ -4,18 +4,26 @@ extern crate systemd_parser;
 
 use std::cell::RefCell;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::io::{BufRead, BufReader, Error, Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 use std::thread;
 use std::time::Duration;
 
+use argv;
+use chaos;
 use config::Config;
+use init;
+use schema::{DirectiveSchema, UnitSchema, ValueType};
+use secrets::Resolved;
+use specifier;
+use supervisor::{Restart, RestartPolicy};
 use unit::{
-    UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
-    UnitIncompatibleReason, UnitName, UnitSelectError,
+    parse_bool, UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
+    UnitIncompatibleReason, UnitKind, UnitName, UnitSelectError,
 };
 use unitbroadcaster::LogEntry;
+use unitfile;
 use unitmanager::{
     ManagerControlMessage, ManagerControlMessageContents, ManagerStatusMessage, UnitManager,
 };
@@ -59,16 +67,38 @@ pub struct LoggerDescription {
 
     /// How long to wait for a terminate() call
     terminate_timeout: Duration,
+
+    /// True if this unit was disabled with a Disabled= directive
+    disabled: bool,
+
+    /// What to do if it exits without being stopped
+    restart: Restart,
+
+    /// Run ExecStart with the shell, rather than splitting it into arguments
+    shell: bool,
 }
 
 impl LoggerDescription {
-    pub fn from_path(path: &Path) -> Result<LoggerDescription, UnitDescriptionError> {
-        let unit_name = UnitName::from_path(path)?;
+    pub fn from_path(
+        unit_name: &UnitName,
+        path: &Path,
+    ) -> Result<LoggerDescription, UnitDescriptionError> {
+        let unit_name = unit_name.clone();
 
         // Parse the file into a systemd unit_file object
         let mut contents = String::with_capacity(8192);
         File::open(path)?.read_to_string(&mut contents)?;
-        let unit_file = systemd_parser::parse_string(&contents)?;
+        let parsed = unitfile::parse_to_keep(&contents, &[])?;
+        Self::from_parsed(&parsed, unit_name, path)
+    }
+
+    /// Describe a logger from a file that has already been parsed.
+    pub fn from_parsed(
+        parsed: &unitfile::ParsedFile,
+        unit_name: UnitName,
+        path: &Path,
+    ) -> Result<LoggerDescription, UnitDescriptionError> {
+        let unit_file = parsed.unit()?;
 
         if !unit_file.has_category("Logger") {
             return Err(UnitDescriptionError::MissingSection("Logger".to_owned()));
@@ -84,6 +114,9 @@ impl LoggerDescription {
             working_directory: None,
             unit_directory: path.parent().unwrap().to_owned(),
             terminate_timeout: Duration::from_secs(5),
+            disabled: false,
+            restart: Restart::default(),
+            shell: false,
         };
 
         for entry in unit_file.lookup_by_category("Logger") {
@@ -132,13 +165,53 @@ impl LoggerDescription {
                             },
                         }
                     }
+                    "Disabled" => {
+                        logger_description.disabled =
+                            parse_bool("Logger", "Disabled", directive.value())?
+                    }
+                    "Restart" | "RestartSec" | "StartLimitBurst" => logger_description
+                        .restart
+                        .set("Logger", directive.key(), directive.value())?,
+                    "Shell" => {
+                        logger_description.shell = parse_bool("Logger", "Shell", directive.value())?
+                    }
                     &_ => (),
                 }
             }
         }
+        if !logger_description.shell {
+            argv::check("Logger", "ExecStart", &logger_description.exec_start)?;
+        }
+        specifier::check("Logger", "ExecStart", &logger_description.exec_start)?;
         Ok(logger_description)
     }
 
+    /// The directives that [Logger] sections may contain.
+    pub fn schema() -> UnitSchema {
+        UnitSchema {
+            kind: UnitKind::Logger,
+            section: "Logger",
+            directives: vec![
+                DirectiveSchema::new("Name", ValueType::Text),
+                DirectiveSchema::new("Description", ValueType::Text),
+                DirectiveSchema::new("Jigs", ValueType::Units(UnitKind::Jig)),
+                DirectiveSchema::new("WorkingDirectory", ValueType::Path),
+                DirectiveSchema::new("ExecStart", ValueType::Command).required(),
+                DirectiveSchema::new(
+                    "Format",
+                    ValueType::Choice(vec!["tsv".to_owned(), "json".to_owned()]),
+                )
+                .with_default("tsv"),
+                DirectiveSchema::new("Disabled", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("Restart", ValueType::Choice(RestartPolicy::choices()))
+                    .with_default("no"),
+                DirectiveSchema::new("RestartSec", ValueType::Duration).with_default("1s"),
+                DirectiveSchema::new("StartLimitBurst", ValueType::Integer).with_default("5"),
+                DirectiveSchema::new("Shell", ValueType::Bool).with_default("no"),
+            ],
+        }
+    }
+
     /// Returns true if this test is supported on the named jig.
     pub fn supports_jig(&self, name: &UnitName) -> bool {
         self.jigs.contains(name)
@@ -165,6 +238,11 @@ impl LoggerDescription {
         &self.id
     }
 
+    /// Returns true if this unit was disabled from within its unit file.
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
     pub fn load(
         &self,
         manager: &UnitManager,
@@ -193,6 +271,24 @@ impl Logger {
         &self.description.id
     }
 
+    pub fn name(&self) -> &String {
+        &self.description.name
+    }
+
+    pub fn description(&self) -> &String {
+        &self.description.description
+    }
+
+    /// The jigs this unit is compatible with.  If empty, it works with any jig.
+    pub fn jigs(&self) -> &Vec<UnitName> {
+        &self.description.jigs
+    }
+
+    /// What to do if the unit exits without being stopped.
+    pub fn restart(&self) -> &Restart {
+        &self.description.restart
+    }
+
     pub fn select(&self) -> Result<(), UnitSelectError> {
         Ok(())
     }
@@ -201,14 +297,19 @@ impl Logger {
         Ok(())
     }
 
-    fn text_read(id: UnitName, control: Sender<ManagerControlMessage>, output: RunningOutput) {
+    fn text_read(
+        id: UnitName,
+        control: Sender<ManagerControlMessage>,
+        output: RunningOutput,
+        secrets: Resolved,
+    ) {
         for line in BufReader::new(output).lines() {
             let line = line.expect("Unable to get next line");
             // If the send fails, that means the other end has closed the pipe.
             if control
                 .send(ManagerControlMessage::new(
                     &id,
-                    ManagerControlMessageContents::LogError(line),
+                    ManagerControlMessageContents::LogError(secrets.mask(&line)),
                 ))
                 .is_err()
             {
@@ -222,12 +323,19 @@ impl Logger {
         manager: &UnitManager,
         config: &Config,
     ) -> Result<(), UnitActivateError> {
-        let mut running = Runny::new(self.description.exec_start.as_str())
+        let secrets = config.resolve_command(
+            self.id(),
+            &self.description.exec_start,
+            self.description.shell,
+        )?;
+        chaos::spawn(self.id())?;
+        let mut running = Runny::new(&secrets.text)
             .directory(&Some(config.working_directory(
                 &self.description.unit_directory,
                 &self.description.working_directory,
             )))
             .start()?;
+        init::spawned(running.pid());
 
         // Have stdout and stderr log their output.
         let control_sender = manager.get_control_channel();
@@ -236,8 +344,23 @@ impl Logger {
         let stderr = running.take_error();
         let thr_sender_id = control_sender_id.clone();
         let thr_sender = control_sender.clone();
-        thread::spawn(move || Self::text_read(thr_sender_id, thr_sender, stdout));
-        thread::spawn(move || Self::text_read(control_sender_id, control_sender, stderr));
+        let thr_secrets = secrets.clone();
+        // Once its output closes, the logger has exited.
+        thread::spawn(move || {
+            Self::text_read(
+                thr_sender_id.clone(),
+                thr_sender.clone(),
+                stdout,
+                thr_secrets,
+            );
+            thr_sender
+                .send(ManagerControlMessage::new(
+                    &thr_sender_id,
+                    ManagerControlMessageContents::ChildExited,
+                ))
+                .ok();
+        });
+        thread::spawn(move || Self::text_read(control_sender_id, control_sender, stderr, secrets));
 
         let control_sender = manager.get_control_channel();
         let control_sender_id = self.id().clone();
@@ -274,7 +397,7 @@ impl Logger {
         let mut process_opt = self.process.borrow_mut();
 
         if process_opt.is_none() {
-            return Err(Error::new(ErrorKind::Other, "no process running"));
+            return Err(Error::other("no process running"));
         }
 
         let process = process_opt.as_mut().unwrap();
@@ -313,13 +436,17 @@ impl Logger {
     fn tsv_write(&self, l: LogEntry, process: &mut Running) -> Result<(), Error> {
         writeln!(
             process,
-            "{}\t{}\t{}\t{}\t{}\t{}",
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
             l.kind().as_str(),
             Self::cfti_escape(l.id().id()),
             Self::cfti_escape(&format!("{}", l.id().kind())),
             l.secs(),
             l.nsecs(),
-            Self::cfti_escape(l.message())
+            Self::cfti_escape(l.message()),
+            l.sequence(),
+            Self::cfti_escape(l.boot_id()),
+            l.monotonic_nsecs(),
+            Self::cfti_escape(l.station())
         )
     }
 }
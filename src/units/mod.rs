@@ -1,6 +1,8 @@
+pub mod allocator;
 pub mod interface;
 pub mod jig;
 pub mod logger;
+pub mod notifier;
 pub mod scenario;
 pub mod test;
 pub mod trigger;
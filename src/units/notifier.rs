@@ -0,0 +1,567 @@
+extern crate serde_json;
+extern crate systemd_parser;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use config::Config;
+use results::http;
+use schema::{DirectiveSchema, UnitSchema, ValueType};
+use unit::{
+    parse_bool, UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
+    UnitIncompatibleReason, UnitKind, UnitName, UnitSelectError,
+};
+use unitbroadcaster::ScenarioSummary;
+use unitfile;
+use unitmanager::{ManagerControlMessage, ManagerControlMessageContents, UnitManager};
+use units::test::TestVerdict;
+
+use self::systemd_parser::items::DirectiveEntry;
+
+/// A struct defining an in-memory representation of a .notifier file
+#[derive(Clone)]
+pub struct NotifierDescription {
+    /// The id of the unit (including the kind)
+    id: UnitName,
+
+    /// A short name
+    name: String,
+
+    /// A detailed description of this Notifier, up to one paragraph.
+    description: String,
+
+    /// A Vec<String> of jig names that this notifier is compatible with.
+    jigs: Vec<UnitName>,
+
+    /// Notify when the same test fails this many runs in a row.
+    consecutive_failures: Option<u32>,
+
+    /// Notify when this many runs in a row hit a station fault.
+    consecutive_faults: Option<u32>,
+
+    /// Notify when fewer than this percentage of recent runs pass.
+    minimum_yield: Option<u32>,
+
+    /// How many recent runs minimum_yield is measured over.
+    yield_window: usize,
+
+    /// A webhook to POST each notification to, as JSON.
+    url: Option<String>,
+
+    /// A command to run for each notification, with the message on stdin.
+    exec_start: Option<String>,
+
+    /// The working directory to start from when running the command
+    working_directory: Option<PathBuf>,
+
+    /// The path to the unit file
+    unit_directory: PathBuf,
+
+    /// True if this unit was disabled with a Disabled= directive
+    disabled: bool,
+}
+
+impl NotifierDescription {
+    pub fn from_path(
+        unit_name: &UnitName,
+        path: &Path,
+    ) -> Result<NotifierDescription, UnitDescriptionError> {
+        let unit_name = unit_name.clone();
+
+        // Parse the file into a systemd unit_file object
+        let mut contents = String::with_capacity(8192);
+        File::open(path)?.read_to_string(&mut contents)?;
+        Self::from_string(&contents, unit_name, path)
+    }
+
+    pub fn from_string(
+        contents: &str,
+        unit_name: UnitName,
+        path: &Path,
+    ) -> Result<NotifierDescription, UnitDescriptionError> {
+        let parsed = unitfile::parse_to_keep(contents, &[])?;
+        Self::from_parsed(&parsed, unit_name, path)
+    }
+
+    /// Describe a notifier from a file that has already been parsed.
+    pub fn from_parsed(
+        parsed: &unitfile::ParsedFile,
+        unit_name: UnitName,
+        path: &Path,
+    ) -> Result<NotifierDescription, UnitDescriptionError> {
+        let unit_file = parsed.unit()?;
+
+        if !unit_file.has_category("Notifier") {
+            return Err(UnitDescriptionError::MissingSection("Notifier".to_owned()));
+        }
+
+        let mut notifier_description = NotifierDescription {
+            id: unit_name,
+            name: "".to_owned(),
+            description: "".to_owned(),
+            jigs: vec![],
+            consecutive_failures: None,
+            consecutive_faults: None,
+            minimum_yield: None,
+            yield_window: 20,
+            url: None,
+            exec_start: None,
+            working_directory: None,
+            unit_directory: path.parent().unwrap().to_owned(),
+            disabled: false,
+        };
+
+        let count = |key: &str, value: Option<&str>| -> Result<u32, UnitDescriptionError> {
+            let value = value.unwrap_or("");
+            match value.parse() {
+                Ok(n) if n > 0 => Ok(n),
+                _ => Err(UnitDescriptionError::InvalidValue(
+                    "Notifier".to_owned(),
+                    key.to_owned(),
+                    value.to_owned(),
+                    vec!["a number of runs".to_owned()],
+                )),
+            }
+        };
+
+        for entry in unit_file.lookup_by_category("Notifier") {
+            if let DirectiveEntry::Solo(ref directive) = entry {
+                match directive.key() {
+                    "Name" => {
+                        notifier_description.name = directive.value().unwrap_or("").to_owned()
+                    }
+                    "Description" => {
+                        notifier_description.description =
+                            directive.value().unwrap_or("").to_owned()
+                    }
+                    "Jigs" => {
+                        notifier_description.jigs = match directive.value() {
+                            Some(s) => UnitName::from_list(s, "jig")?,
+                            None => vec![],
+                        }
+                    }
+                    "ConsecutiveFailures" => {
+                        notifier_description.consecutive_failures =
+                            Some(count("ConsecutiveFailures", directive.value())?)
+                    }
+                    "ConsecutiveFaults" => {
+                        notifier_description.consecutive_faults =
+                            Some(count("ConsecutiveFaults", directive.value())?)
+                    }
+                    "MinimumYield" => {
+                        let value = directive.value().unwrap_or("");
+                        notifier_description.minimum_yield =
+                            match value.trim_end_matches('%').trim().parse() {
+                                Ok(percent) if percent <= 100 => Some(percent),
+                                _ => {
+                                    return Err(UnitDescriptionError::InvalidValue(
+                                        "Notifier".to_owned(),
+                                        "MinimumYield".to_owned(),
+                                        value.to_owned(),
+                                        vec!["a percentage from 0 to 100".to_owned()],
+                                    ))
+                                }
+                            }
+                    }
+                    "YieldWindow" => {
+                        notifier_description.yield_window =
+                            count("YieldWindow", directive.value())? as usize
+                    }
+                    "URL" => notifier_description.url = directive.value().map(|s| s.to_owned()),
+                    "ExecStart" => {
+                        notifier_description.exec_start = directive.value().map(|s| s.to_owned())
+                    }
+                    "WorkingDirectory" => {
+                        if let Some(wd) = directive.value() {
+                            notifier_description.working_directory = Some(PathBuf::from(wd));
+                        }
+                    }
+                    "Disabled" => {
+                        notifier_description.disabled =
+                            parse_bool("Notifier", "Disabled", directive.value())?
+                    }
+                    &_ => (),
+                }
+            }
+        }
+
+        if notifier_description.url.is_none() && notifier_description.exec_start.is_none() {
+            return Err(UnitDescriptionError::MissingValue(
+                "Notifier".to_owned(),
+                "URL or ExecStart".to_owned(),
+            ));
+        }
+        if let Some(ref url) = notifier_description.url {
+            if !url.starts_with("http://") {
+                return Err(UnitDescriptionError::InvalidValue(
+                    "Notifier".to_owned(),
+                    "URL".to_owned(),
+                    url.to_owned(),
+                    vec!["http://<host>/<path>".to_owned()],
+                ));
+            }
+        }
+        Ok(notifier_description)
+    }
+
+    /// The directives that [Notifier] sections may contain.
+    pub fn schema() -> UnitSchema {
+        UnitSchema {
+            kind: UnitKind::Notifier,
+            section: "Notifier",
+            directives: vec![
+                DirectiveSchema::new("Name", ValueType::Text),
+                DirectiveSchema::new("Description", ValueType::Text),
+                DirectiveSchema::new("Jigs", ValueType::Units(UnitKind::Jig)),
+                DirectiveSchema::new("ConsecutiveFailures", ValueType::Integer),
+                DirectiveSchema::new("ConsecutiveFaults", ValueType::Integer),
+                DirectiveSchema::new("MinimumYield", ValueType::Percentage),
+                DirectiveSchema::new("YieldWindow", ValueType::Integer).with_default("20"),
+                DirectiveSchema::new("URL", ValueType::Url),
+                DirectiveSchema::new("ExecStart", ValueType::Command),
+                DirectiveSchema::new("WorkingDirectory", ValueType::Path),
+                DirectiveSchema::new("Disabled", ValueType::Bool).with_default("no"),
+            ],
+        }
+    }
+
+    /// Returns true if this notifier is supported on the named jig.
+    pub fn supports_jig(&self, name: &UnitName) -> bool {
+        self.jigs.contains(name)
+    }
+
+    /// Determine if a unit is compatible with this system.
+    pub fn is_compatible(
+        &self,
+        manager: &UnitManager,
+        _: &Config,
+    ) -> Result<(), UnitIncompatibleReason> {
+        if self.jigs.is_empty() {
+            return Ok(());
+        }
+        for jig_name in &self.jigs {
+            if manager.jig_is_loaded(jig_name) {
+                return Ok(());
+            }
+        }
+        Err(UnitIncompatibleReason::IncompatibleJig)
+    }
+
+    pub fn id(&self) -> &UnitName {
+        &self.id
+    }
+
+    /// Returns true if this unit was disabled from within its unit file.
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    pub fn load(
+        &self,
+        manager: &UnitManager,
+        config: &Config,
+    ) -> Result<Notifier, UnitIncompatibleReason> {
+        self.is_compatible(manager, config)?;
+
+        Ok(Notifier::new(self, manager, config))
+    }
+}
+
+/// What a notifier remembers about recent runs.
+#[derive(Default)]
+struct History {
+    /// How many runs in a row each test has failed.
+    test_failures: HashMap<UnitName, u32>,
+
+    /// How many runs in a row have hit a station fault.
+    fault_runs: u32,
+
+    /// Whether each of the most recent runs passed, oldest first.
+    recent: VecDeque<bool>,
+
+    /// True once low yield has been reported, until the yield recovers.
+    low_yield: bool,
+}
+
+/// One condition that was met, and what to say about it.
+struct Notification {
+    condition: &'static str,
+    test: Option<UnitName>,
+    text: String,
+}
+
+pub struct Notifier {
+    description: NotifierDescription,
+    history: RefCell<History>,
+}
+
+impl Notifier {
+    pub fn new(desc: &NotifierDescription, _: &UnitManager, _: &Config) -> Notifier {
+        Notifier {
+            description: desc.clone(),
+            history: RefCell::new(History::default()),
+        }
+    }
+
+    pub fn id(&self) -> &UnitName {
+        &self.description.id
+    }
+
+    pub fn name(&self) -> &String {
+        &self.description.name
+    }
+
+    pub fn description(&self) -> &String {
+        &self.description.description
+    }
+
+    /// The jigs this unit is compatible with.  If empty, it works with any jig.
+    pub fn jigs(&self) -> &Vec<UnitName> {
+        &self.description.jigs
+    }
+
+    pub fn select(&self) -> Result<(), UnitSelectError> {
+        Ok(())
+    }
+
+    pub fn deselect(&self) -> Result<(), UnitDeselectError> {
+        Ok(())
+    }
+
+    /// Start watching runs afresh.
+    pub fn activate(&self, _: &UnitManager, _: &Config) -> Result<(), UnitActivateError> {
+        *self.history.borrow_mut() = History::default();
+        Ok(())
+    }
+
+    pub fn deactivate(&self) -> Result<(), UnitDeactivateError> {
+        Ok(())
+    }
+
+    /// Take note of a finished run, and send a notification for each
+    /// condition it meets.
+    pub fn scenario_finished(
+        &self,
+        summary: &ScenarioSummary,
+        config: &Config,
+        ctrl: &Sender<ManagerControlMessage>,
+    ) {
+        // Technicians exercising the station shouldn't set off alerts about production.
+        if summary.maintenance {
+            return;
+        }
+        for notification in self.check(summary) {
+            ctrl.send(ManagerControlMessage::new(
+                self.id(),
+                ManagerControlMessageContents::Log(format!("notifying: {}", notification.text)),
+            ))
+            .ok();
+            self.send(&notification, summary, config, ctrl);
+        }
+    }
+
+    /// Update the history with a run, returning the conditions it met.
+    fn check(&self, summary: &ScenarioSummary) -> Vec<Notification> {
+        let desc = &self.description;
+        let mut history = self.history.borrow_mut();
+        let mut notifications = vec![];
+
+        for test in &summary.tests {
+            match test.verdict {
+                TestVerdict::Fail | TestVerdict::Fault => {
+                    let count = history.test_failures.entry(test.test.clone()).or_insert(0);
+                    *count += 1;
+                    // Only notify once per streak, when it reaches the limit.
+                    if Some(*count) == desc.consecutive_failures {
+                        notifications.push(Notification {
+                            condition: "consecutive-failures",
+                            test: Some(test.test.clone()),
+                            text: format!(
+                                "test {} failed {} runs in a row: {}",
+                                test.test, count, test.reason
+                            ),
+                        });
+                    }
+                }
+                TestVerdict::Pass => {
+                    history.test_failures.remove(&test.test);
+                }
+                TestVerdict::Skip | TestVerdict::Retry => (),
+            }
+        }
+
+        if summary.faults > 0 {
+            history.fault_runs += 1;
+            if Some(history.fault_runs) == desc.consecutive_faults {
+                let reason = match summary.first_failure {
+                    Some((ref test, ref reason)) => format!("{}: {}", test, reason),
+                    None => summary.reason.clone(),
+                };
+                notifications.push(Notification {
+                    condition: "consecutive-faults",
+                    test: None,
+                    text: format!(
+                        "station faults in {} runs in a row, most recently {}",
+                        history.fault_runs, reason
+                    ),
+                });
+            }
+        } else {
+            history.fault_runs = 0;
+        }
+
+        if let Some(minimum) = desc.minimum_yield {
+            history
+                .recent
+                .push_back(summary.verdict() == TestVerdict::Pass);
+            while history.recent.len() > desc.yield_window {
+                history.recent.pop_front();
+            }
+            if history.recent.len() == desc.yield_window {
+                let passed = history.recent.iter().filter(|&&passed| passed).count();
+                let percent = passed * 100 / desc.yield_window;
+                if percent < minimum as usize {
+                    if !history.low_yield {
+                        history.low_yield = true;
+                        notifications.push(Notification {
+                            condition: "low-yield",
+                            test: None,
+                            text: format!(
+                                "yield is {}% over the last {} runs, below {}%",
+                                percent, desc.yield_window, minimum
+                            ),
+                        });
+                    }
+                } else {
+                    history.low_yield = false;
+                }
+            }
+        }
+
+        notifications
+    }
+
+    /// Send a notification to the webhook and the command, in the background.
+    fn send(
+        &self,
+        notification: &Notification,
+        summary: &ScenarioSummary,
+        config: &Config,
+        ctrl: &Sender<ManagerControlMessage>,
+    ) {
+        let station = config.station();
+        let context = [
+            ("EXCLAVE_NOTIFIER", self.id().to_string()),
+            ("EXCLAVE_CONDITION", notification.condition.to_owned()),
+            ("EXCLAVE_STATION", station.id.clone()),
+            ("EXCLAVE_LINE", station.line.clone().unwrap_or_default()),
+            ("EXCLAVE_SITE", station.site.clone().unwrap_or_default()),
+            ("EXCLAVE_SCENARIO", summary.scenario.to_string()),
+            (
+                "EXCLAVE_JIG",
+                summary
+                    .jig
+                    .as_ref()
+                    .map(|j| j.to_string())
+                    .unwrap_or_default(),
+            ),
+            ("EXCLAVE_SERIAL", summary.serial.clone().unwrap_or_default()),
+            ("EXCLAVE_RUN_ID", summary.run_id.clone().unwrap_or_default()),
+            (
+                "EXCLAVE_TEST",
+                notification
+                    .test
+                    .as_ref()
+                    .map(|t| t.to_string())
+                    .unwrap_or_default(),
+            ),
+        ];
+        // A short subject line, then the details.
+        let mut message = format!("{}\n\n", notification.text);
+        for (key, value) in &context {
+            if !value.is_empty() {
+                message.push_str(&format!("{}: {}\n", key, value));
+            }
+        }
+        message.push_str(&format!("last run: {}\n", summary));
+
+        // Secrets are filled in here, but errors only ever name the URL or
+        // command as written.
+        let resolve = |text: &Option<String>| {
+            text.as_ref()
+                .and_then(|text| match config.resolve_secrets(text) {
+                    Ok(resolved) => Some((text.clone(), resolved.text)),
+                    Err(e) => {
+                        ctrl.send(ManagerControlMessage::new(
+                            self.id(),
+                            ManagerControlMessageContents::LogError(format!(
+                                "unable to notify: {}",
+                                e
+                            )),
+                        ))
+                        .ok();
+                        None
+                    }
+                })
+        };
+        let url = resolve(&self.description.url);
+        let exec_start = resolve(&self.description.exec_start).map(|(_, cmd)| cmd);
+        let working_directory = config.working_directory(
+            &self.description.unit_directory,
+            &self.description.working_directory,
+        );
+        let id = self.id().clone();
+        let ctrl = ctrl.clone();
+        thread::spawn(move || {
+            let log_error = |text: String| {
+                ctrl.send(ManagerControlMessage::new(
+                    &id,
+                    ManagerControlMessageContents::LogError(text),
+                ))
+                .ok();
+            };
+            if let Some((url, resolved_url)) = url {
+                let mut payload = serde_json::Map::new();
+                // "text" is what chat webhooks such as Slack display.
+                payload.insert("text".to_owned(), message.clone().into());
+                for (key, value) in &context {
+                    let key = key.trim_start_matches("EXCLAVE_").to_lowercase();
+                    payload.insert(key, value.clone().into());
+                }
+                let body = serde_json::Value::Object(payload).to_string();
+                if let Err(e) = http::post(&resolved_url, "application/json", body.as_bytes()) {
+                    log_error(format!("unable to notify {}: {}", url, e));
+                }
+            }
+            if let Some(exec_start) = exec_start {
+                let result = Command::new("/bin/sh")
+                    .arg("-c")
+                    .arg(&exec_start)
+                    .current_dir(&working_directory)
+                    .envs(context.iter().map(|(k, v)| (k, v)))
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .and_then(|mut child| {
+                        child
+                            .stdin
+                            .take()
+                            .unwrap()
+                            .write_all(message.as_bytes())
+                            .ok();
+                        child.wait()
+                    });
+                match result {
+                    Ok(status) if status.success() => (),
+                    Ok(status) => log_error(format!("notification command exited with {}", status)),
+                    Err(e) => log_error(format!("unable to run notification command: {}", e)),
+                }
+            }
+        });
+    }
+}
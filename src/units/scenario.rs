@@ -1,31 +1,47 @@
 extern crate dependy;
-extern crate humantime;
 extern crate runny;
 extern crate systemd_parser;
 
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use self::dependy::{Dependency, Dependy};
-use self::humantime::{parse_duration, DurationError};
 use self::runny::running::Running;
 use self::runny::Runny;
 use self::systemd_parser::items::DirectiveEntry;
 
+use argv;
+use clock::Clock;
 use config::Config;
+use cost::Cost;
+use duration;
+use init;
+use parameter::{self, Axis, Parameter};
+use presentation::Presentation;
+use redact::{self, Redaction};
+use schema::{DirectiveSchema, UnitSchema, ValueType};
+use secrets::Resolved;
+use specifier::{self, Specifiers};
 use unit::{
-    UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
-    UnitIncompatibleReason, UnitName, UnitSelectError,
+    parse_aliases, parse_bool, UnitActivateError, UnitDeactivateError, UnitDescriptionError,
+    UnitDeselectError, UnitIncompatibleReason, UnitKind, UnitName, UnitSelectError,
 };
+use unitbroadcaster::{IterationSummary, ScenarioSummary, TestSummary};
+use unitfile;
 use unitmanager::{ManagerControlMessage, ManagerControlMessageContents, UnitManager};
-use units::test::Test;
+use units::test::{Test, TestVerdict};
+
+/// How long to wait, once a support command has exited, for the last of
+/// its output to be logged.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
 
 struct AssumptionDependency {
     name: UnitName,
@@ -35,12 +51,14 @@ struct AssumptionDependency {
 }
 
 impl AssumptionDependency {
-    pub fn new(name: UnitName) -> AssumptionDependency {
+    /// A test assumed to have passed, which can still be required by its
+    /// old names.
+    pub fn new(name: UnitName, aliases: Vec<UnitName>) -> AssumptionDependency {
         AssumptionDependency {
             name,
             requirements: vec![],
             suggestions: vec![],
-            provides: vec![],
+            provides: aliases,
         }
     }
 }
@@ -60,6 +78,58 @@ impl Dependency<UnitName> for AssumptionDependency {
     }
 }
 
+/// How many times a scenario runs through its tests, for burn-in.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Repeat {
+    /// Run the tests this many times.
+    Count(u32),
+
+    /// Start another pass through the tests until this long has passed since
+    /// the scenario started.  The pass that's running when it runs out is
+    /// finished.
+    UntilDuration(Duration),
+}
+
+impl Repeat {
+    fn forms() -> Vec<String> {
+        vec![
+            "count:<number>".to_owned(),
+            "until-duration:<duration>".to_owned(),
+        ]
+    }
+
+    fn parse(value: &str) -> Result<Repeat, UnitDescriptionError> {
+        let invalid = || {
+            UnitDescriptionError::InvalidValue(
+                "Scenario".to_owned(),
+                "Repeat".to_owned(),
+                value.to_owned(),
+                Self::forms(),
+            )
+        };
+        match value.trim().split_once(':') {
+            Some(("count", count)) => match count.trim().parse::<u32>() {
+                Ok(count) if count > 0 => Ok(Repeat::Count(count)),
+                _ => Err(invalid()),
+            },
+            Some(("until-duration", limit)) => match duration::parse(limit.trim()) {
+                Ok(limit) if limit > Duration::from_secs(0) => Ok(Repeat::UntilDuration(limit)),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Whether to start another pass, after `done` of them, with the
+    /// scenario `elapsed` into its run.
+    fn again(&self, done: u32, elapsed: Duration) -> bool {
+        match *self {
+            Repeat::Count(count) => done < count,
+            Repeat::UntilDuration(limit) => elapsed < limit,
+        }
+    }
+}
+
 /// A struct defining an in-memory representation of a .scenario file
 #[derive(Clone)]
 pub struct ScenarioDescription {
@@ -72,6 +142,9 @@ pub struct ScenarioDescription {
     /// A detailed description of this jig, up to one paragraph.
     description: String,
 
+    /// How operator GUIs should show it.
+    presentation: Presentation,
+
     /// A Vec<String> of jig names that this test is compatible with.
     jigs: Vec<UnitName>,
 
@@ -81,9 +154,15 @@ pub struct ScenarioDescription {
     /// A Vec<String> of tests that are considered to have passed without running them.
     assumptions: Vec<UnitName>,
 
+    /// Tests (or patterns) to remove from the list given in Tests.
+    excludes: Vec<UnitName>,
+
     /// The maximum duration, if any, for this scenario
     timeout: Option<Duration>,
 
+    /// How long the scenario is expected to take, if the unit file says.
+    estimated_duration: Option<Duration>,
+
     /// A default working directory to start from.  Overrides Jig and global config paths.
     working_directory: Option<PathBuf>,
 
@@ -108,13 +187,48 @@ pub struct ScenarioDescription {
     /// The maximum amount of time to allow the "failure" script to run for.
     exec_stop_failure_timeout: Option<Duration>,
 
+    /// Run the commands above with the shell, rather than splitting them into arguments.
+    shell: Option<bool>,
+
     // The maximum amount of failures before the scenario should be stopped.
     stop_after_failure_count: Option<u32>,
+
+    /// How many times to run through the tests, if more than once.
+    repeat: Option<Repeat>,
+
+    /// Whether to keep repeating after a pass in which a test failed,
+    /// rather than stopping there.
+    repeat_past_failures: Option<bool>,
+
+    /// The scenario this one is based on, if any.
+    inherits: Option<UnitName>,
+
+    /// The names this scenario had before it was renamed, which still refer
+    /// to it but are deprecated.
+    aliases: Vec<UnitName>,
+
+    /// Whether this scenario checks the station rather than testing
+    /// products, so it may run in maintenance mode.
+    diagnostic: Option<bool>,
+
+    /// Values that must (or may) be given when the scenario is started,
+    /// from its Param directives.
+    parameters: Vec<Parameter>,
+
+    /// The values to make a pass through the tests with, from its Matrix
+    /// directives.
+    matrix: Vec<Axis>,
+
+    /// True if this unit was disabled with a Disabled= directive
+    disabled: bool,
 }
 
 impl ScenarioDescription {
-    pub fn from_path(path: &Path) -> Result<ScenarioDescription, UnitDescriptionError> {
-        let unit_name = UnitName::from_path(path)?;
+    pub fn from_path(
+        unit_name: &UnitName,
+        path: &Path,
+    ) -> Result<ScenarioDescription, UnitDescriptionError> {
+        let unit_name = unit_name.clone();
 
         // Parse the file into a systemd unit_file object
         let mut contents = String::with_capacity(8192);
@@ -127,7 +241,17 @@ impl ScenarioDescription {
         unit_name: UnitName,
         path: &Path,
     ) -> Result<ScenarioDescription, UnitDescriptionError> {
-        let unit_file = systemd_parser::parse_string(contents)?;
+        let parsed = unitfile::parse_to_keep(contents, &[])?;
+        Self::from_parsed(&parsed, unit_name, path)
+    }
+
+    /// Describe a scenario from a file that has already been parsed.
+    pub fn from_parsed(
+        parsed: &unitfile::ParsedFile,
+        unit_name: UnitName,
+        path: &Path,
+    ) -> Result<ScenarioDescription, UnitDescriptionError> {
+        let unit_file = parsed.unit()?;
 
         if !unit_file.has_category("Scenario") {
             return Err(UnitDescriptionError::MissingSection("Scenario".to_owned()));
@@ -137,12 +261,15 @@ impl ScenarioDescription {
             id: unit_name,
             name: "".to_owned(),
             description: "".to_owned(),
+            presentation: Presentation::default(),
 
             jigs: vec![],
             tests: vec![],
             assumptions: vec![],
+            excludes: vec![],
 
             timeout: None,
+            estimated_duration: None,
 
             unit_directory: path.parent().unwrap().to_owned(),
             working_directory: None,
@@ -153,9 +280,46 @@ impl ScenarioDescription {
             exec_stop_success_timeout: None,
             exec_stop_failure: None,
             exec_stop_failure_timeout: None,
+            shell: None,
             stop_after_failure_count: None,
+            repeat: None,
+            repeat_past_failures: None,
+            inherits: None,
+            aliases: vec![],
+            diagnostic: None,
+            parameters: vec![],
+            matrix: vec![],
+            disabled: false,
         };
 
+        // Param and Matrix may be given more than once, so they're read on
+        // their own, before the directives that may only be given once.
+        for entry in unit_file.lookup_by_category("Scenario") {
+            let directives = match entry {
+                DirectiveEntry::Solo(directive) => vec![directive],
+                DirectiveEntry::Many(directives) => directives.iter().collect(),
+            };
+            for directive in directives {
+                match directive.key() {
+                    "Param" => {
+                        let parameter = Parameter::parse(directive.value().unwrap_or(""))?;
+                        scenario_description
+                            .parameters
+                            .retain(|p| p.name() != parameter.name());
+                        scenario_description.parameters.push(parameter);
+                    }
+                    "Matrix" => {
+                        let axis = Axis::parse(directive.value().unwrap_or(""))?;
+                        scenario_description
+                            .matrix
+                            .retain(|a| a.name() != axis.name());
+                        scenario_description.matrix.push(axis);
+                    }
+                    _ => (),
+                }
+            }
+        }
+
         // Use this value as ExecStopSuccess and/or ExecStopFailure if ExecStop is
         // specified, and either of these two are not specified.
         let mut exec_stop = None;
@@ -167,6 +331,9 @@ impl ScenarioDescription {
                     "Name" => {
                         scenario_description.name = directive.value().unwrap_or("").to_owned()
                     }
+                    "Icon" | "Color" | "Group" | "HideFromOperator" => scenario_description
+                        .presentation
+                        .set("Scenario", directive.key(), directive.value())?,
                     "Description" => {
                         scenario_description.description =
                             directive.value().unwrap_or("").to_owned()
@@ -179,7 +346,7 @@ impl ScenarioDescription {
                     }
                     "WorkingDirectory" => {
                         if let Some(wd) = directive.value() {
-                            PathBuf::from(wd);
+                            scenario_description.working_directory = Some(PathBuf::from(wd));
                         }
                     }
                     "Tests" => {
@@ -194,19 +361,35 @@ impl ScenarioDescription {
                             None => vec![],
                         }
                     }
+                    "Exclude" => {
+                        scenario_description.excludes = match directive.value() {
+                            Some(s) => UnitName::from_list(s, "test")?,
+                            None => vec![],
+                        }
+                    }
                     "ExecStart" => {
                         scenario_description.exec_start = directive.value().map(|s| s.to_owned())
                     }
+                    "Shell" => {
+                        scenario_description.shell =
+                            Some(parse_bool("Scenario", "Shell", directive.value())?)
+                    }
                     "ExecStartTimeout" => {
                         scenario_description.exec_start_timeout = match directive.value() {
                             None => None,
-                            Some(s) => Some(Self::parse_time(s)?),
+                            Some(s) => Some(duration::parse(s)?),
+                        }
+                    }
+                    "EstimatedDuration" => {
+                        scenario_description.estimated_duration = match directive.value() {
+                            None => None,
+                            Some(s) => Some(duration::parse(s)?),
                         }
                     }
                     "Timeout" => {
                         scenario_description.timeout = match directive.value() {
                             None => None,
-                            Some(s) => Some(Self::parse_time(s)?),
+                            Some(s) => Some(duration::parse(s)?),
                         }
                     }
                     "ExecStopSuccess" => {
@@ -216,7 +399,7 @@ impl ScenarioDescription {
                     "ExecStopSuccessTimeout" => {
                         scenario_description.exec_stop_success_timeout = match directive.value() {
                             None => None,
-                            Some(s) => Some(Self::parse_time(s)?),
+                            Some(s) => Some(duration::parse(s)?),
                         }
                     }
                     "ExecStopFail" => {
@@ -226,7 +409,7 @@ impl ScenarioDescription {
                     "ExecStopFailTimeout" => {
                         scenario_description.exec_stop_failure_timeout = match directive.value() {
                             None => None,
-                            Some(s) => Some(Self::parse_time(s)?),
+                            Some(s) => Some(duration::parse(s)?),
                         }
                     }
                     "ExecStopFailure" => {
@@ -236,14 +419,14 @@ impl ScenarioDescription {
                     "ExecStopFailureTimeout" => {
                         scenario_description.exec_stop_failure_timeout = match directive.value() {
                             None => None,
-                            Some(s) => Some(Self::parse_time(s)?),
+                            Some(s) => Some(duration::parse(s)?),
                         }
                     }
                     "ExecStop" => exec_stop = directive.value().map(|s| s.to_owned()),
                     "ExecStopTimeout" => {
                         exec_stop_timeout = match directive.value() {
                             None => None,
-                            Some(s) => Some(Self::parse_time(s)?),
+                            Some(s) => Some(duration::parse(s)?),
                         }
                     }
                     "StopAfterFailureCount" => {
@@ -252,6 +435,47 @@ impl ScenarioDescription {
                             Some(s) => Some(s.parse::<u32>()?),
                         }
                     }
+                    "Repeat" => {
+                        scenario_description.repeat = match directive.value() {
+                            None => None,
+                            Some(s) => Some(Repeat::parse(s)?),
+                        }
+                    }
+                    "RepeatOnFailure" => {
+                        scenario_description.repeat_past_failures = match directive.value() {
+                            None => None,
+                            Some(s) => match s.trim().to_lowercase().as_ref() {
+                                "stop" => Some(false),
+                                "continue" => Some(true),
+                                other => {
+                                    return Err(UnitDescriptionError::InvalidValue(
+                                        "Scenario".to_owned(),
+                                        "RepeatOnFailure".to_owned(),
+                                        other.to_owned(),
+                                        vec!["stop".to_owned(), "continue".to_owned()],
+                                    ))
+                                }
+                            },
+                        }
+                    }
+                    "Inherits" => {
+                        scenario_description.inherits = match directive.value() {
+                            Some(s) => Some(UnitName::from_str(s, "scenario")?),
+                            None => None,
+                        }
+                    }
+                    "Alias" => {
+                        scenario_description.aliases =
+                            parse_aliases("Scenario", directive.value(), "scenario")?
+                    }
+                    "Diagnostic" => {
+                        scenario_description.diagnostic =
+                            Some(parse_bool("Scenario", "Diagnostic", directive.value())?)
+                    }
+                    "Disabled" => {
+                        scenario_description.disabled =
+                            parse_bool("Scenario", "Disabled", directive.value())?
+                    }
                     &_ => (),
                 }
             }
@@ -275,14 +499,66 @@ impl ScenarioDescription {
             }
         }
 
+        for (key, command) in &[
+            ("ExecStart", &scenario_description.exec_start),
+            ("ExecStopSuccess", &scenario_description.exec_stop_success),
+            ("ExecStopFailure", &scenario_description.exec_stop_failure),
+        ] {
+            if let Some(command) = command {
+                if !scenario_description.shell.unwrap_or(false) {
+                    argv::check("Scenario", key, command)?;
+                }
+                specifier::check("Scenario", key, command)?;
+            }
+        }
+
         Ok(scenario_description)
     }
 
-    fn parse_time(time_str: &str) -> Result<Duration, DurationError> {
-        if let Ok(val) = time_str.parse::<u64>() {
-            Ok(Duration::from_secs(val))
-        } else {
-            parse_duration(time_str)
+    /// The directives that [Scenario] sections may contain.
+    pub fn schema() -> UnitSchema {
+        UnitSchema {
+            kind: UnitKind::Scenario,
+            section: "Scenario",
+            directives: vec![
+                DirectiveSchema::new("Name", ValueType::Text),
+                DirectiveSchema::new("Description", ValueType::Text),
+                DirectiveSchema::new("Icon", ValueType::Text),
+                DirectiveSchema::new("Color", ValueType::Text),
+                DirectiveSchema::new("Group", ValueType::Text),
+                DirectiveSchema::new("HideFromOperator", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("Jigs", ValueType::Units(UnitKind::Jig)),
+                DirectiveSchema::new("WorkingDirectory", ValueType::Path),
+                DirectiveSchema::new("Tests", ValueType::Units(UnitKind::Test)),
+                DirectiveSchema::new("Assume", ValueType::Units(UnitKind::Test)),
+                DirectiveSchema::new("Exclude", ValueType::Units(UnitKind::Test)),
+                DirectiveSchema::new("ExecStart", ValueType::Command),
+                DirectiveSchema::new("ExecStartTimeout", ValueType::Duration),
+                DirectiveSchema::new("Timeout", ValueType::Duration),
+                DirectiveSchema::new("EstimatedDuration", ValueType::Duration),
+                DirectiveSchema::new("ExecStopSuccess", ValueType::Command),
+                DirectiveSchema::new("ExecStopSuccessTimeout", ValueType::Duration),
+                DirectiveSchema::new("ExecStopFail", ValueType::Command),
+                DirectiveSchema::new("ExecStopFailTimeout", ValueType::Duration),
+                DirectiveSchema::new("ExecStopFailure", ValueType::Command),
+                DirectiveSchema::new("ExecStopFailureTimeout", ValueType::Duration),
+                DirectiveSchema::new("ExecStop", ValueType::Command),
+                DirectiveSchema::new("ExecStopTimeout", ValueType::Duration),
+                DirectiveSchema::new("Shell", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("StopAfterFailureCount", ValueType::Integer),
+                DirectiveSchema::new("Repeat", ValueType::Text),
+                DirectiveSchema::new(
+                    "RepeatOnFailure",
+                    ValueType::Choice(vec!["stop".to_owned(), "continue".to_owned()]),
+                )
+                .with_default("stop"),
+                DirectiveSchema::new("Inherits", ValueType::Unit(UnitKind::Scenario)),
+                DirectiveSchema::new("Alias", ValueType::Units(UnitKind::Scenario)),
+                DirectiveSchema::new("Diagnostic", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("Param", ValueType::Text),
+                DirectiveSchema::new("Matrix", ValueType::Text),
+                DirectiveSchema::new("Disabled", ValueType::Bool).with_default("no"),
+            ],
         }
     }
 
@@ -290,6 +566,139 @@ impl ScenarioDescription {
         &self.id
     }
 
+    /// Returns true if this unit was disabled from within its unit file.
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    /// The names this scenario had before it was renamed.
+    pub fn aliases(&self) -> &Vec<UnitName> {
+        &self.aliases
+    }
+
+    /// The scenario this one inherits from, if any.
+    pub fn inherits(&self) -> &Option<UnitName> {
+        &self.inherits
+    }
+
+    /// Merge this scenario on top of the one it inherits from.  Lists of
+    /// tests (Tests, Assume, and Exclude) are appended to the parent's, and
+    /// any other directive that is set replaces the parent's value.  Disabled
+    /// and HideFromOperator are never inherited, so a base scenario may be
+    /// disabled or hidden on its own, and neither is Alias, since the parent's
+    /// old names are still the parent's.  EstimatedDuration isn't either, since
+    /// the child runs different tests.  Parameters and the axes of the Matrix
+    /// are added to the parent's, replacing any of the same name.
+    pub fn inherit(&self, parent: &ScenarioDescription) -> ScenarioDescription {
+        fn or<T: Clone>(child: &Option<T>, parent: &Option<T>) -> Option<T> {
+            child.clone().or_else(|| parent.clone())
+        }
+        fn append(parent: &[UnitName], child: &[UnitName]) -> Vec<UnitName> {
+            let mut list = parent.to_vec();
+            list.extend(child.iter().filter(|t| !parent.contains(t)).cloned());
+            list
+        }
+        let or_string = |child: &String, parent: &String| {
+            if child.is_empty() {
+                parent.clone()
+            } else {
+                child.clone()
+            }
+        };
+
+        let mut parameters: Vec<Parameter> = parent
+            .parameters
+            .iter()
+            .filter(|p| !self.parameters.iter().any(|c| c.name() == p.name()))
+            .cloned()
+            .collect();
+        parameters.extend(self.parameters.iter().cloned());
+        let mut matrix: Vec<Axis> = parent
+            .matrix
+            .iter()
+            .filter(|a| !self.matrix.iter().any(|c| c.name() == a.name()))
+            .cloned()
+            .collect();
+        matrix.extend(self.matrix.iter().cloned());
+
+        ScenarioDescription {
+            id: self.id.clone(),
+            name: or_string(&self.name, &parent.name),
+            description: or_string(&self.description, &parent.description),
+            presentation: self.presentation.inherit(&parent.presentation),
+            jigs: if self.jigs.is_empty() {
+                parent.jigs.clone()
+            } else {
+                self.jigs.clone()
+            },
+            tests: append(&parent.tests, &self.tests),
+            assumptions: append(&parent.assumptions, &self.assumptions),
+            excludes: append(&parent.excludes, &self.excludes),
+            timeout: or(&self.timeout, &parent.timeout),
+            estimated_duration: self.estimated_duration,
+            working_directory: or(&self.working_directory, &parent.working_directory),
+            unit_directory: self.unit_directory.clone(),
+            exec_start: or(&self.exec_start, &parent.exec_start),
+            exec_start_timeout: or(&self.exec_start_timeout, &parent.exec_start_timeout),
+            exec_stop_success: or(&self.exec_stop_success, &parent.exec_stop_success),
+            exec_stop_success_timeout: or(
+                &self.exec_stop_success_timeout,
+                &parent.exec_stop_success_timeout,
+            ),
+            exec_stop_failure: or(&self.exec_stop_failure, &parent.exec_stop_failure),
+            exec_stop_failure_timeout: or(
+                &self.exec_stop_failure_timeout,
+                &parent.exec_stop_failure_timeout,
+            ),
+            shell: or(&self.shell, &parent.shell),
+            stop_after_failure_count: or(
+                &self.stop_after_failure_count,
+                &parent.stop_after_failure_count,
+            ),
+            repeat: or(&self.repeat, &parent.repeat),
+            repeat_past_failures: or(&self.repeat_past_failures, &parent.repeat_past_failures),
+            inherits: parent.inherits.clone(),
+            aliases: self.aliases.clone(),
+            diagnostic: or(&self.diagnostic, &parent.diagnostic),
+            parameters,
+            matrix,
+            disabled: self.disabled,
+        }
+    }
+
+    /// Returns true if one of this scenario's test patterns matches the named test,
+    /// meaning the scenario must be reloaded when that test comes or goes.
+    pub fn matches_test(&self, name: &UnitName) -> bool {
+        self.tests
+            .iter()
+            .chain(self.assumptions.iter())
+            .chain(self.excludes.iter())
+            .filter(|t| t.is_pattern())
+            .any(|pattern| {
+                pattern.matches(name)
+                    || pattern
+                        .resolve(self.id.namespace(), |p| p.matches(name))
+                        .matches(name)
+            })
+    }
+
+    /// The jigs this scenario can run on, or none if it can run on any.
+    pub fn jigs(&self) -> &Vec<UnitName> {
+        &self.jigs
+    }
+
+    /// Returns true if this scenario names the test in its Tests,
+    /// Assumptions, or Excludes, either directly or with a pattern.
+    pub fn refers_to_test(&self, name: &UnitName) -> bool {
+        self.matches_test(name)
+            || self
+                .tests
+                .iter()
+                .chain(self.assumptions.iter())
+                .chain(self.excludes.iter())
+                .any(|t| t.resolve(self.id.namespace(), |n| n == name) == *name)
+    }
+
     /// Returns true if this scenario is supported on the named jig.
     pub fn supports_jig(&self, name: &UnitName) -> bool {
         self.jigs.contains(name)
@@ -300,7 +709,7 @@ impl ScenarioDescription {
         &self,
         manager: &UnitManager,
         _: &Config,
-    ) -> Result<(Vec<UnitName>, Dependy<UnitName>), UnitIncompatibleReason> {
+    ) -> Result<Resolution, UnitIncompatibleReason> {
         // If there is at least one jig present, ensure that it is loaded.
         if !self.jigs.is_empty() {
             let mut loaded = false;
@@ -325,47 +734,225 @@ impl ScenarioDescription {
         manager: &UnitManager,
         config: &Config,
     ) -> Result<Scenario, UnitIncompatibleReason> {
-        let (test_order, graph) = self.is_compatible(manager, config)?;
-        Ok(Scenario::new(self, test_order, manager, graph))
+        let resolution = self.is_compatible(manager, config)?;
+        Ok(Scenario::new(self, resolution, manager))
     }
 
     pub fn get_test_order(
         &self,
         manager: &UnitManager,
-    ) -> Result<(Vec<UnitName>, Dependy<UnitName>), UnitIncompatibleReason> {
-        // Create a new dependency graph
-        let mut graph = Dependy::new();
-
-        // Add each possible test into the dependency graph
-        {
-            let tests_rc = manager.get_tests();
-            let tests = tests_rc.borrow();
-            for (test_name, test) in tests.iter() {
-                if self.assumptions.contains(test_name) {
-                    let assumption_dep = AssumptionDependency::new(test_name.clone());
-                    graph.add_dependency(&assumption_dep);
+    ) -> Result<Resolution, UnitIncompatibleReason> {
+        // Tests may be named relative to this scenario's namespace, and
+        // patterns expand to every matching test that is currently loaded.
+        let loaded_tests: Vec<UnitName> = manager.get_tests().borrow().keys().cloned().collect();
+        let resolve = |names: &Vec<UnitName>| -> Vec<UnitName> {
+            let mut resolved = vec![];
+            for name in names {
+                if name.is_pattern() {
+                    let pattern = name.resolve(self.id.namespace(), |p| {
+                        loaded_tests.iter().any(|t| p.matches(t))
+                    });
+                    let mut matches: Vec<UnitName> = loaded_tests
+                        .iter()
+                        .filter(|t| pattern.matches(t) && !resolved.contains(*t))
+                        .cloned()
+                        .collect();
+                    matches.sort();
+                    resolved.extend(matches);
                 } else {
-                    graph.add_dependency(&*test.borrow());
+                    // Renamed tests may still be listed by their old names.
+                    let name = name.resolve(self.id.namespace(), |n| {
+                        manager.get_test_named(n).is_some() || manager.alias_target(n).is_some()
+                    });
+                    resolved.push(manager.unalias(&name, &self.id));
                 }
             }
+            resolved
+        };
+        let assumptions = resolve(&self.assumptions);
+
+        // Excluded tests are dropped from the list, though they may still
+        // be run if another test requires them.
+        let excludes = resolve(&self.excludes);
+        let test_names: Vec<UnitName> = resolve(&self.tests)
+            .into_iter()
+            .filter(|name| !excludes.contains(name))
+            .collect();
+
+        // The graph only holds the tests this scenario involves: those it
+        // lists, every test that provides a name they require or suggest,
+        // and so on, in turn.  On a station with hundreds of tests, that's
+        // far fewer than all of them, and a change to any other test leaves
+        // the scenario alone.  What's found along the way is kept, so that
+        // if none of it has changed since the last time, the test order is
+        // reused.
+        let mut inputs = ResolutionInputs {
+            tests: test_names.clone(),
+            assumptions: assumptions.clone(),
+            lookups: vec![],
+            involved: vec![],
+        };
+        let mut involved = vec![];
+        let mut looked_up = HashSet::new();
+        let mut added = HashSet::new();
+        let mut wanted: Vec<UnitName> = test_names.iter().chain(&assumptions).cloned().collect();
+        while let Some(name) = wanted.pop() {
+            if !looked_up.insert(name.clone()) {
+                continue;
+            }
+            // If nothing provides it, that's left for the graph to report.
+            let providers = manager.test_providers(&name);
+            inputs.lookups.push((name, providers.clone()));
+            for test_name in providers {
+                if !added.insert(test_name.clone()) {
+                    continue;
+                }
+                let test = match manager.get_test_named(&test_name) {
+                    Some(test) => test,
+                    None => continue,
+                };
+                let assumed = assumptions.contains(&test_name);
+                {
+                    let test = test.borrow();
+                    let mut description = InvolvedTest {
+                        name: test_name.clone(),
+                        assumed,
+                        aliases: test.aliases().clone(),
+                        requirements: vec![],
+                        suggestions: vec![],
+                        provides: vec![],
+                    };
+                    if !assumed {
+                        description.requirements = test.requirements().clone();
+                        description.suggestions = test.suggestions().clone();
+                        description.provides = test.provides().clone();
+                        wanted.extend(test.requirements().iter().cloned());
+                        wanted.extend(test.suggestions().iter().cloned());
+                    }
+                    inputs.involved.push(description);
+                }
+                involved.push((test_name, test, assumed));
+            }
         }
+        let resolution = match manager.cached_resolution(&self.id, &inputs) {
+            Some(resolution) => resolution,
+            None => {
+                // Where several tests provide the same name, the graph uses
+                // whichever was added last.  A test with the name itself is
+                // added after any that provide it, and otherwise the first
+                // of them by name is added last.
+                involved.sort_by_key(|(test_name, _, _)| {
+                    (looked_up.contains(test_name), Reverse(test_name.clone()))
+                });
+                let mut graph = Dependy::new();
+                for (test_name, test, assumed) in &involved {
+                    let test = test.borrow();
+                    if *assumed {
+                        let assumption_dep =
+                            AssumptionDependency::new(test_name.clone(), test.aliases().clone());
+                        graph.add_dependency(&assumption_dep);
+                    } else {
+                        graph.add_dependency(&*test);
+                    }
+                }
+                let test_sequence = graph.resolve_named_dependencies(&test_names)?;
+                let requires = test_sequence
+                    .iter()
+                    .map(|name| {
+                        let parents = graph.required_parents_of_named(name);
+                        (name.clone(), parents.into_iter().cloned().collect())
+                    })
+                    .collect();
+                let resolution = Resolution {
+                    test_sequence,
+                    test_order: vec![],
+                    requires,
+                    involved: looked_up.union(&added).cloned().collect(),
+                };
+                manager.cache_resolution(&self.id, inputs, &resolution);
+                resolution
+            }
+        };
 
-        let mut test_names = vec![];
-        for test_name in &self.tests {
-            test_names.push(test_name.clone());
-        }
-
-        let test_sequence = graph.resolve_named_dependencies(&test_names)?;
         let mut test_order = vec![];
-        for test_name in test_sequence {
+        for test_name in &resolution.test_sequence {
+            // The graph follows old names in Requires and Suggests on its
+            // own, but whoever still uses them should hear about it.
+            if let Some(test) = manager.get_test_named(test_name) {
+                let test = test.borrow();
+                for name in test.requirements().iter().chain(test.suggestions()) {
+                    manager.unalias(name, test_name);
+                }
+            }
             // Only add the test to the test order if it's not an assumption.
-            if !self.assumptions.contains(&test_name) {
-                test_order.push(test_name);
+            if !assumptions.contains(test_name) {
+                test_order.push(test_name.clone());
             }
         }
 
-        // let test_order = trimmed_order;
-        Ok((test_order, graph))
+        Ok(Resolution {
+            test_order,
+            ..resolution
+        })
+    }
+}
+
+/// Everything that went into resolving a scenario's tests.  A resolution is
+/// only reused if all of it is the same.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResolutionInputs {
+    /// The tests the scenario lists, once patterns and excludes are applied.
+    tests: Vec<UnitName>,
+
+    /// The tests the scenario assumes have already passed.
+    assumptions: Vec<UnitName>,
+
+    /// Each name a test was looked up by, and the tests that provide it.
+    lookups: Vec<(UnitName, Vec<UnitName>)>,
+
+    /// Each test involved, as far as resolving it goes.
+    involved: Vec<InvolvedTest>,
+}
+
+/// The parts of a test that decide how a scenario's tests resolve.
+#[derive(Clone, Debug, PartialEq)]
+struct InvolvedTest {
+    name: UnitName,
+    assumed: bool,
+    aliases: Vec<UnitName>,
+    requirements: Vec<UnitName>,
+    suggestions: Vec<UnitName>,
+    provides: Vec<UnitName>,
+}
+
+/// How a scenario's tests were resolved: the order they run in, and what
+/// each of them requires, which is all that's kept of the dependency graph
+/// once it's been resolved.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Resolution {
+    /// Every test involved, assumptions included, in dependency order.
+    test_sequence: Vec<UnitName>,
+
+    /// The tests to run, in order.
+    pub test_order: Vec<UnitName>,
+
+    /// The tests each test requires, by the names they were loaded as.
+    requires: HashMap<UnitName, Vec<UnitName>>,
+
+    /// Every test involved, and every name a test was looked up by.
+    involved: HashSet<UnitName>,
+}
+
+impl Resolution {
+    /// The tests that the named test requires.
+    pub fn required_by(&self, name: &UnitName) -> &[UnitName] {
+        self.requires.get(name).map(|r| &r[..]).unwrap_or(&[])
+    }
+
+    /// Whether the named test, or a test providing that name, could change
+    /// how the tests resolve.
+    pub fn involves(&self, name: &UnitName) -> bool {
+        self.involved.contains(name)
     }
 }
 
@@ -401,11 +988,17 @@ pub enum TestState {
     /// A test (or daemon) passed successfully.
     Pass,
 
-    /// A test (or daemon) was skipped.
-    Skip,
+    /// A test (or daemon) was skipped, and why, if there's a reason to give.
+    Skip(String),
 
     /// A test (or daemon) failed for some reason.
     Fail(String),
+
+    /// A test (or daemon) couldn't run properly because of a station fault.
+    Fault(String),
+
+    /// A test isn't part of this run, because only failed tests are being rerun.
+    Excluded,
 }
 
 pub struct Scenario {
@@ -421,12 +1014,30 @@ pub struct Scenario {
     /// The results of each individual test.
     test_states: HashMap<UnitName, Rc<RefCell<TestState>>>,
 
+    /// The exit code and cost of each test that finished in the current run
+    /// (or pass through the tests, if the scenario repeats).
+    finished: RefCell<HashMap<UnitName, (i32, Cost)>>,
+
+    /// How each finished pass through the tests went, if the scenario repeats.
+    iterations: RefCell<Vec<IterationSummary>>,
+
+    /// The values of each pass through the matrix, in turn.  There's one,
+    /// with nothing in it, if there's no Matrix.
+    combinations: Vec<Vec<(String, String)>>,
+
+    /// When the current pass through the tests started, and how many
+    /// failures and faults there had been by then.
+    iteration_start: RefCell<(Instant, u32)>,
+
     /// The result of the ExecStart run program (if any).
     exec_start_state: Rc<RefCell<TestState>>,
 
     /// How many tests have failed in this particular run.
     failures: Rc<RefCell<u32>>,
 
+    /// How many tests hit a station fault in this particular run.
+    faults: Rc<RefCell<u32>>,
+
     /// The current state of the scenario, when activated.
     state: Rc<RefCell<ScenarioState>>,
 
@@ -434,28 +1045,47 @@ pub struct Scenario {
     /// Used for PreStart and PostFinish scripts.
     support_wd: Rc<RefCell<PathBuf>>,
 
-    /// The dependency graph of tests.
-    graph: Dependy<UnitName>,
+    /// The order of the tests, and what each requires.
+    resolution: Resolution,
 
     /// When the test was started.
     start_time: Instant,
 
+    /// The clock that start_time was read from.
+    clock: Clock,
+
+    /// Where support commands' secrets are read from, if anywhere.
+    secrets_file: Option<PathBuf>,
+
+    /// What specifiers in support commands stand for, other than %S.
+    specifiers: Specifiers,
+
+    /// The jig the scenario is running on, and the features it's fitted with.
+    jig: Option<(UnitName, Vec<String>)>,
+
+    /// The rules applied to support commands' output before it's logged.
+    redactions: Vec<Redaction>,
+
     /// The currently-executing program (if any)
     program: Rc<RefCell<Option<Running>>>,
+
+    /// If set, the next run only includes these tests.  See rerun_failed().
+    rerun: Option<Vec<UnitName>>,
+
+    /// The name of the current (or last) run's directory in the LogDirectory.
+    run_id: Option<String>,
+
+    /// The run that the current run is retesting, if it's a rerun.
+    parent_run: Option<String>,
 }
 
 impl Scenario {
-    fn new(
-        desc: &ScenarioDescription,
-        test_order: Vec<UnitName>,
-        manager: &UnitManager,
-        graph: Dependy<UnitName>,
-    ) -> Scenario {
+    fn new(desc: &ScenarioDescription, resolution: Resolution, manager: &UnitManager) -> Scenario {
         let mut tests = HashMap::new();
         let mut test_sequence = vec![];
         let mut test_state = HashMap::new();
 
-        for test_name in test_order {
+        for test_name in resolution.test_order.iter().cloned() {
             let test = manager
                 .get_test_named(&test_name)
                 .expect("Unable to check out requested test from library");
@@ -469,13 +1099,26 @@ impl Scenario {
             tests,
             test_sequence,
             test_states: test_state,
+            finished: RefCell::new(HashMap::new()),
+            iterations: RefCell::new(vec![]),
+            combinations: parameter::combinations(&desc.matrix),
+            iteration_start: RefCell::new((Instant::now(), 0)),
             exec_start_state: Rc::new(RefCell::new(TestState::Pending)),
             state: Rc::new(RefCell::new(ScenarioState::Idle)),
             support_wd: Rc::new(RefCell::new(desc.unit_directory.clone())),
             failures: Rc::new(RefCell::new(0)),
-            graph,
+            faults: Rc::new(RefCell::new(0)),
+            resolution,
             start_time: Instant::now(),
+            clock: Clock::system(),
+            secrets_file: None,
+            specifiers: Specifiers::default(),
+            jig: None,
+            redactions: vec![],
             program: Rc::new(RefCell::new(None)),
+            rerun: None,
+            run_id: None,
+            parent_run: None,
         }
     }
 
@@ -495,6 +1138,11 @@ impl Scenario {
         &self.description.id
     }
 
+    /// The jigs this unit is compatible with.  If empty, it works with any jig.
+    pub fn jigs(&self) -> &Vec<UnitName> {
+        &self.description.jigs
+    }
+
     pub fn select(&self) -> Result<(), UnitSelectError> {
         Ok(())
     }
@@ -513,12 +1161,33 @@ impl Scenario {
 
         // Start afresh and reset our failure count.
         *self.failures.borrow_mut() = 0;
-        self.start_time = Instant::now();
+        *self.faults.borrow_mut() = 0;
+        self.clock = config.clock().clone();
+        self.secrets_file = config.secrets_file().map(PathBuf::from);
+        self.redactions = config.redactions().to_vec();
+        self.start_time = self.clock.instant();
         *self.state.borrow_mut() = ScenarioState::Idle;
         *self.exec_start_state.borrow_mut() = TestState::Pending;
         self.test_states.iter().for_each(|(_, item)| {
             *item.borrow_mut() = TestState::Pending;
         });
+        self.finished.borrow_mut().clear();
+        self.iterations.borrow_mut().clear();
+        *self.iteration_start.borrow_mut() = (self.start_time, 0);
+        for test in &self.test_sequence {
+            test.borrow().reset_retries();
+        }
+
+        // A rerun only runs some of the tests, and remembers which run it's redoing.
+        self.parent_run = None;
+        if let Some(rerun) = self.rerun.take() {
+            for (test_name, state) in &self.test_states {
+                if !rerun.contains(test_name) {
+                    *state.borrow_mut() = TestState::Excluded;
+                }
+            }
+            self.parent_run = self.run_id.clone();
+        }
 
         // Re-assign our working directory.
         if let Some(ref wd) = &self.description.working_directory {
@@ -527,6 +1196,30 @@ impl Scenario {
             config.clear_scenario_working_directory();
         }
 
+        // Give this run a fresh directory for test output, if configured.
+        self.run_id = match config.start_run(self.id()) {
+            Ok(run) => run.and_then(|run| {
+                run.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            }),
+            Err(e) => {
+                ctrl.send(ManagerControlMessage::new(
+                    self.id(),
+                    ManagerControlMessageContents::LogError(format!(
+                        "unable to create run directory: {}",
+                        e
+                    )),
+                ))
+                .ok();
+                None
+            }
+        };
+        self.specifiers = config.specifiers(self.id());
+        self.jig = config.bound_jig().map(|jig| {
+            let features = manager.jig_features(&jig);
+            (jig, features)
+        });
+
         // Since `config` doesn't get passed around anymore, create a copy of the `working_directory`
         // so that we can run support commands.
         *self.support_wd.borrow_mut() = config.working_directory(
@@ -548,8 +1241,32 @@ impl Scenario {
         Ok(())
     }
 
+    /// Returns true if this scenario checks the station rather than
+    /// testing products, so it may run in maintenance mode.
+    pub fn is_diagnostic(&self) -> bool {
+        self.description.diagnostic.unwrap_or(false)
+    }
+
+    /// The values that must (or may) be given when the scenario is started.
+    pub fn parameters(&self) -> &[Parameter] {
+        &self.description.parameters
+    }
+
+    /// The axes of the matrix the scenario makes a pass through its tests
+    /// for each combination of.
+    pub fn matrix(&self) -> &[Axis] {
+        &self.description.matrix
+    }
+
+    /// The values the matrix gives the pass through the tests that's
+    /// running, as (name, value), or nothing if there's no Matrix.
+    pub fn matrix_parameters(&self) -> Vec<(String, String)> {
+        let pass = self.iterations.borrow().len();
+        self.combinations[pass % self.combinations.len()].clone()
+    }
+
     pub fn uses_test(&self, test_name: &UnitName) -> bool {
-        self.tests.get(test_name).is_some()
+        self.tests.contains_key(test_name)
     }
 
     pub fn name(&self) -> &String {
@@ -560,10 +1277,62 @@ impl Scenario {
         &self.description.description
     }
 
+    /// How operator GUIs should show the unit.
+    pub fn presentation(&self) -> &Presentation {
+        &self.description.presentation
+    }
+
+    /// How long the unit file says the scenario takes, if it does.
+    pub fn estimated_duration(&self) -> Option<Duration> {
+        self.description.estimated_duration
+    }
+
     pub fn indicate_failure(&self) {
         *self.failures.borrow_mut() += 1;
     }
 
+    /// Arrange for the next run to only include the tests that failed or
+    /// were skipped in the last one, along with the tests they depend on.
+    /// Returns the tests that will be run, in order.
+    pub fn rerun_failed(&mut self) -> Result<Vec<UnitName>, String> {
+        if *self.state.borrow() != ScenarioState::ScenarioFinished {
+            return Err("scenario hasn't finished a run".to_owned());
+        }
+        let failed: Vec<UnitName> = self
+            .test_sequence()
+            .into_iter()
+            .filter(|test_name| match *self.test_states[test_name].borrow() {
+                TestState::Pass | TestState::Running | TestState::Excluded => false,
+                TestState::Pending
+                | TestState::Skip(_)
+                | TestState::Fail(_)
+                | TestState::Fault(_) => true,
+            })
+            .collect();
+        if failed.is_empty() {
+            return Err("no tests failed or were skipped".to_owned());
+        }
+        // Walk up the requirements, since the graph's own resolution would
+        // also pull in every test that merely comes earlier in the scenario.
+        let mut needed = failed;
+        let mut i = 0;
+        while i < needed.len() {
+            for parent in self.resolution.required_by(&needed[i]) {
+                if !needed.contains(parent) {
+                    needed.push(parent.clone());
+                }
+            }
+            i += 1;
+        }
+        let rerun: Vec<UnitName> = self
+            .test_sequence()
+            .into_iter()
+            .filter(|test_name| needed.contains(test_name))
+            .collect();
+        self.rerun = Some(rerun.clone());
+        Ok(rerun)
+    }
+
     // Given the current state, figure out the next test to run (if any)
     pub fn advance(
         &self,
@@ -587,21 +1356,71 @@ impl Scenario {
                     ))
                     .ok();
                 }
-                let result = match last_result {
-                    0 => TestState::Pass,
-                    r => {
+                let verdict = self.test_sequence[step].borrow().verdict(last_result);
+                let output_reason = self.test_sequence[step].borrow().output_reason(last_result);
+                let result = match verdict {
+                    TestVerdict::Pass => TestState::Pass,
+                    TestVerdict::Fail => {
                         *self.failures.borrow_mut() += 1;
+                        ctrl.send(ManagerControlMessage::new(
+                            last_unit,
+                            ManagerControlMessageContents::LogError(match output_reason {
+                                Some(ref reason) => format!("test failed: {}", reason),
+                                None => {
+                                    format!("test failed with nonzero return code: {}", last_result)
+                                }
+                            }),
+                        ))
+                        .ok();
+                        TestState::Fail(output_reason.unwrap_or_else(|| {
+                            format!("test exited with nonzero return code: {}", last_result)
+                        }))
+                    }
+                    TestVerdict::Fault => {
+                        *self.faults.borrow_mut() += 1;
                         ctrl.send(ManagerControlMessage::new(
                             last_unit,
                             ManagerControlMessageContents::LogError(format!(
-                                "test failed with nonzero return code: {}",
-                                r
+                                "test hit a station fault with return code: {}",
+                                last_result
                             )),
                         ))
                         .ok();
-                        TestState::Fail(format!("test exited with nonzero return code: {}", r))
+                        TestState::Fault(format!("station fault with return code: {}", last_result))
+                    }
+                    TestVerdict::Skip => TestState::Skip(String::new()),
+                    TestVerdict::Retry => {
+                        let test = self.test_sequence[step].borrow();
+                        let attempt = test.add_retry();
+                        ctrl.send(ManagerControlMessage::new(
+                            last_unit,
+                            ManagerControlMessageContents::Log(format!(
+                                "test asked to be retried with return code {} (retry {})",
+                                last_result, attempt
+                            )),
+                        ))
+                        .ok();
+                        // Run the same step again, rather than moving on.
+                        if !test.is_daemon() {
+                            ctrl.send(ManagerControlMessage::new(
+                                self.id(),
+                                ManagerControlMessageContents::StopTest(test_id.clone()),
+                            ))
+                            .ok();
+                        }
+                        ctrl.send(ManagerControlMessage::new(
+                            self.id(),
+                            ManagerControlMessageContents::StartTest(test_id),
+                        ))
+                        .ok();
+                        return;
                     }
                 };
+                if let Some(cost) = self.test_sequence[step].borrow().cost() {
+                    self.finished
+                        .borrow_mut()
+                        .insert(test_id.clone(), (last_result, cost));
+                }
                 *self.test_states.get(&test_id).unwrap().borrow_mut() = result;
                 /* Run the test's STOP command */
                 if !self.test_sequence[step].borrow().is_daemon() {
@@ -685,7 +1504,37 @@ impl Scenario {
             ManagerControlMessageContents::Log(format!("{}: starting [{}]", testname, cmd)),
         ))
         .ok();
-        let mut run_cmd = Runny::new(cmd);
+        // The serial number is whichever the run's tests have reported by now.
+        let specifiers = Specifiers {
+            serial: self.serial(),
+            ..self.specifiers.clone()
+        };
+        // Only the command as written is logged, never with its secrets.
+        let secrets = match argv::resolve(
+            self.secrets_file.as_deref(),
+            cmd,
+            self.description.shell.unwrap_or(false),
+            &specifiers,
+        ) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                ctrl.send(ManagerControlMessage::new(
+                    self.id(),
+                    ManagerControlMessageContents::LogError(format!(
+                        "{}: unable to run command: {}",
+                        testname, e
+                    )),
+                ))
+                .ok();
+                ctrl.send(ManagerControlMessage::new(
+                    self.id(),
+                    ManagerControlMessageContents::AdvanceScenario(1),
+                ))
+                .ok();
+                return;
+            }
+        };
+        let mut run_cmd = Runny::new(&secrets.text);
         if let Some(timeout) = *timeout {
             run_cmd.timeout(timeout);
         }
@@ -709,8 +1558,9 @@ impl Scenario {
                 return;
             }
         };
+        init::spawned(running.pid());
 
-        self.log_output(ctrl, &mut running);
+        let output_done = self.log_output(ctrl, &mut running, &secrets);
 
         // Keep a waiter around in a separate thread to send that AdvanceScenario message upon completion.
         let thr_waiter = running.waiter();
@@ -720,6 +1570,13 @@ impl Scenario {
         let thr_testname = testname.to_owned();
         thread::spawn(move || {
             thr_waiter.wait();
+            // Let the last lines it printed be logged before moving on,
+            // unless something it left running is still holding them open.
+            for _ in 0..2 {
+                if output_done.recv_timeout(OUTPUT_DRAIN_TIMEOUT).is_err() {
+                    break;
+                }
+            }
             thr_control
                 .send(ManagerControlMessage::new(
                     &id,
@@ -740,13 +1597,23 @@ impl Scenario {
         *self.program.borrow_mut() = Some(running);
     }
 
-    fn log_output(&self, control: &Sender<ManagerControlMessage>, process: &mut Running) {
+    fn log_output(
+        &self,
+        control: &Sender<ManagerControlMessage>,
+        process: &mut Running,
+        secrets: &Resolved,
+    ) -> Receiver<()> {
+        let (done, output_done) = mpsc::channel();
         let stdout = process.take_output();
         let thr_control = control.clone();
         let id = self.id().clone();
+        let thr_secrets = secrets.clone();
+        let thr_redactions = self.redactions.clone();
+        let thr_done = done.clone();
         thread::spawn(move || {
             for line in BufReader::new(stdout).lines() {
                 let line = line.expect("Unable to get next line");
+                let line = redact::apply(&thr_redactions, &thr_secrets.mask(&line));
                 if thr_control
                     .send(ManagerControlMessage::new(
                         &id,
@@ -757,14 +1624,19 @@ impl Scenario {
                     break;
                 }
             }
+            thr_done.send(()).ok();
         });
 
         let stderr = process.take_error();
         let thr_control = control.clone();
         let id = self.id().clone();
+        let thr_secrets = secrets.clone();
+        let thr_redactions = self.redactions.clone();
+        let thr_done = done;
         thread::spawn(move || {
             for line in BufReader::new(stderr).lines() {
                 let line = line.expect("Unable to get next line");
+                let line = redact::apply(&thr_redactions, &thr_secrets.mask(&line));
                 if thr_control
                     .send(ManagerControlMessage::new(
                         &id,
@@ -775,13 +1647,16 @@ impl Scenario {
                     break;
                 }
             }
+            thr_done.send(()).ok();
         });
+        output_done
     }
 
     /// Find the next state.
     /// If we're idle, start the test.
     /// The state order goes:
     /// Idle -> [PreStart] -> Test(0) -> ... -> Test(n) -> [PostSuccess/Fail] -> Idle
+    /// With Repeat, Test(n) may be followed by Test(0) again.
     ///
     fn find_next_state(
         &self,
@@ -789,7 +1664,6 @@ impl Scenario {
         ctrl: &Sender<ManagerControlMessage>,
     ) -> ScenarioState {
         let test_count = self.tests.len();
-        let failure_count = *self.failures.borrow();
 
         let new_state = match current_state {
             ScenarioState::Idle => {
@@ -802,30 +1676,10 @@ impl Scenario {
             ScenarioState::PreStart => ScenarioState::Running(0),
 
             // If we just finished running a test, determine the next test to run.
-            ScenarioState::Running(i) if (i + 1) < test_count => {
-                match self.description.stop_after_failure_count {
-                    Some(count) => {
-                        if failure_count >= count {
-                            ScenarioState::PostFailure
-                        } else {
-                            ScenarioState::Running(i + 1)
-                        }
-                    }
-                    None => ScenarioState::Running(i + 1),
-                }
-            }
-            ScenarioState::Running(i) if (i + 1) >= test_count && failure_count > 0 => {
-                ScenarioState::PostFailure
-            }
-            ScenarioState::Running(i) if (i + 1) >= test_count && failure_count == 0 => {
-                ScenarioState::PostSuccess
-            }
-            ScenarioState::Running(i) => {
-                panic!(
-                    "Got into a weird state. Running({}), test_count: {}, failure_count: {}",
-                    i, test_count, failure_count
-                )
+            ScenarioState::Running(i) if (i + 1) < test_count && !self.too_many_failures() => {
+                ScenarioState::Running(i + 1)
             }
+            ScenarioState::Running(_) => self.end_iteration(ctrl),
             ScenarioState::PostFailure => ScenarioState::ScenarioFinished,
             ScenarioState::PostSuccess => ScenarioState::ScenarioFinished,
             ScenarioState::ScenarioFinished => ScenarioState::ScenarioFinished,
@@ -871,15 +1725,20 @@ impl Scenario {
                     // If the test isn't Pending (i.e. if it's skipped or failed), don't run it.
                     false
                 }
-                // Make sure all required dependencies succeeded.
-                else if !self.all_dependencies_succeeded(test_name) {
-                    *self.test_states.get(test_name).unwrap().borrow_mut() = TestState::Skip;
+                // Make sure the jig has what the test needs, and all
+                // required dependencies succeeded.
+                else if let Some(reason) = self.missing_features(&test).or_else(|| {
+                    if self.all_dependencies_succeeded(test_name) {
+                        None
+                    } else {
+                        Some("dependency failed".to_owned())
+                    }
+                }) {
+                    *self.test_states.get(test_name).unwrap().borrow_mut() =
+                        TestState::Skip(reason.clone());
                     ctrl.send(ManagerControlMessage::new(
                         self.id(),
-                        ManagerControlMessageContents::Skip(
-                            test_name.clone(),
-                            "dependency failed".to_owned(),
-                        ),
+                        ManagerControlMessageContents::Skip(test_name.clone(), reason),
                     ))
                     .ok();
                     false
@@ -899,8 +1758,156 @@ impl Scenario {
         }
     }
 
+    /// Why a test can't run on the scenario's jig, if it needs features
+    /// the jig isn't fitted with.
+    fn missing_features(&self, test: &Test) -> Option<String> {
+        let features: &[String] = self.jig.as_ref().map_or(&[], |(_, f)| f);
+        let missing: Vec<&str> = test
+            .requires_features()
+            .iter()
+            .filter(|feature| !features.contains(feature))
+            .map(|feature| feature.as_str())
+            .collect();
+        if missing.is_empty() {
+            return None;
+        }
+        Some(match self.jig {
+            Some((ref jig, _)) => format!("jig {} has no {}", jig, missing.join(", ")),
+            None => format!("no jig to provide {}", missing.join(", ")),
+        })
+    }
+
+    /// True if StopAfterFailureCount has been reached.  Failures count
+    /// across every pass through the tests, if the scenario repeats.
+    fn too_many_failures(&self) -> bool {
+        // Station faults stop a scenario the same way test failures do.
+        let failure_count = *self.failures.borrow() + *self.faults.borrow();
+        match self.description.stop_after_failure_count {
+            Some(count) => failure_count >= count,
+            None => false,
+        }
+    }
+
+    /// Decide what to do once a pass through the tests is over: go round
+    /// again, if the scenario repeats and there's no reason to stop, or
+    /// move on to ExecStopSuccess or ExecStopFailure.
+    fn end_iteration(&self, ctrl: &Sender<ManagerControlMessage>) -> ScenarioState {
+        let failure_count = *self.failures.borrow() + *self.faults.borrow();
+        let matrix = !self.description.matrix.is_empty();
+        if self.description.repeat.is_none() && !matrix {
+            return if failure_count > 0 || self.too_many_failures() {
+                ScenarioState::PostFailure
+            } else {
+                ScenarioState::PostSuccess
+            };
+        }
+        let iteration = self.record_iteration(ctrl);
+
+        // A pass in which nothing ran, such as when the scenario has run out
+        // of time, would only be followed by more of the same.
+        let ran = iteration.passed + iteration.failed + iteration.faults > 0;
+        let failed = !iteration.is_pass();
+        let again = ran
+            && !self.too_many_failures()
+            && !self.scenario_timed_out()
+            && (!failed || self.description.repeat_past_failures.unwrap_or(matrix))
+            && self.again(iteration.iteration);
+        if !again {
+            return if failure_count > 0 || self.too_many_failures() {
+                ScenarioState::PostFailure
+            } else {
+                ScenarioState::PostSuccess
+            };
+        }
+
+        // Daemons keep running from one pass to the next.
+        for state in self.test_states.values() {
+            let mut state = state.borrow_mut();
+            if !matches!(*state, TestState::Running | TestState::Excluded) {
+                *state = TestState::Pending;
+            }
+        }
+        self.finished.borrow_mut().clear();
+        for test in &self.test_sequence {
+            test.borrow().reset_retries();
+        }
+        *self.iteration_start.borrow_mut() = (self.clock.instant(), failure_count);
+        let passes = self.combinations.len() as u32;
+        let mut message = match self.description.repeat {
+            Some(Repeat::Count(count)) => format!(
+                "starting iteration {} of {}",
+                iteration.iteration + 1,
+                count * passes
+            ),
+            Some(Repeat::UntilDuration(_)) => {
+                format!("starting iteration {}", iteration.iteration + 1)
+            }
+            None => format!(
+                "starting iteration {} of {}",
+                iteration.iteration + 1,
+                passes
+            ),
+        };
+        let values = self.matrix_parameters();
+        if !values.is_empty() {
+            let values: Vec<String> = values
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            message.push_str(&format!(" ({})", values.join(", ")));
+        }
+        ctrl.send(ManagerControlMessage::new(
+            self.id(),
+            ManagerControlMessageContents::Log(message),
+        ))
+        .ok();
+        ScenarioState::Running(0)
+    }
+
+    /// Whether to start another pass, after `done` of them.  Each pass
+    /// through the matrix is finished before Repeat is asked whether to go
+    /// round again.
+    fn again(&self, done: u32) -> bool {
+        let passes = self.combinations.len() as u32;
+        if !done.is_multiple_of(passes) {
+            return true;
+        }
+        match self.description.repeat {
+            Some(ref repeat) => repeat.again(done / passes, self.elapsed()),
+            None => false,
+        }
+    }
+
+    /// Note how the pass through the tests that just ended went, and log it.
+    fn record_iteration(&self, ctrl: &Sender<ManagerControlMessage>) -> IterationSummary {
+        let (started, _) = *self.iteration_start.borrow();
+        let pass = self.pass_summary(0, "");
+        let iteration = IterationSummary {
+            iteration: self.iterations.borrow().len() as u32 + 1,
+            parameters: self.matrix_parameters(),
+            duration: self.clock.instant().duration_since(started),
+            passed: pass.passed,
+            failed: pass.failed,
+            faults: pass.faults,
+            skipped: pass.skipped,
+            first_failure: pass.first_failure,
+            verdicts: pass
+                .tests
+                .into_iter()
+                .map(|test| (test.test, test.verdict))
+                .collect(),
+        };
+        ctrl.send(ManagerControlMessage::new(
+            self.id(),
+            ManagerControlMessageContents::Log(iteration.to_string()),
+        ))
+        .ok();
+        self.iterations.borrow_mut().push(iteration.clone());
+        iteration
+    }
+
     fn all_dependencies_succeeded(&self, test_name: &UnitName) -> bool {
-        for parent_name in self.graph.required_parents_of_named(test_name) {
+        for parent_name in self.resolution.required_by(test_name) {
             if self.description.assumptions.contains(parent_name) {
                 return true;
             }
@@ -921,20 +1928,23 @@ impl Scenario {
         true
     }
 
+    /// How long the scenario has been running.
+    fn elapsed(&self) -> Duration {
+        self.clock.instant().duration_since(self.start_time)
+    }
+
     fn scenario_timed_out(&self) -> bool {
         match self.description.timeout {
             None => false,
             Some(timeout) => {
-                let now = Instant::now();
-                let scenario_elapsed_time = now.duration_since(self.start_time);
+                let scenario_elapsed_time = self.elapsed();
                 scenario_elapsed_time >= timeout
             }
         }
     }
 
     fn make_timeout(&self, test_max_time: &Option<Duration>) -> Option<Duration> {
-        let now = Instant::now();
-        let scenario_elapsed_time = now.duration_since(self.start_time);
+        let scenario_elapsed_time = self.elapsed();
 
         // If the test would take longer than the scenario has left, limit the test time.
         if let Some(test_max_time) = *test_max_time {
@@ -955,6 +1965,7 @@ impl Scenario {
     // Post messages and terminate tests.
     fn finish_scenario(&self, ctrl: &Sender<ManagerControlMessage>) {
         let failures = *self.failures.borrow();
+        let faults = *self.faults.borrow();
         for test in &self.test_sequence {
             // Stop the test.  This will catch normal tests and daemons.
             ctrl.send(ManagerControlMessage::new(
@@ -969,24 +1980,166 @@ impl Scenario {
             ManagerControlMessageContents::StopTest(self.id().clone()),
         ))
         .ok();
-        if failures > 0 {
+        // Scenarios that repeat say how many passes they made.
+        let over = match self.iterations.borrow().len() {
+            0 => String::new(),
+            count => format!(", after {} iterations", count),
+        };
+        if faults > 0 {
+            ctrl.send(ManagerControlMessage::new(
+                self.id(),
+                ManagerControlMessageContents::ScenarioFinished(
+                    failures + faults + 500,
+                    format!(
+                        "{} tests failed and {} tests hit a station fault{}",
+                        failures, faults, over
+                    ),
+                ),
+            ))
+            .ok();
+        } else if failures > 0 {
             ctrl.send(ManagerControlMessage::new(
                 self.id(),
                 ManagerControlMessageContents::ScenarioFinished(
                     failures + 500,
-                    "at least one test failed".to_owned(),
+                    format!("at least one test failed{}", over),
                 ),
             ))
             .ok();
         } else {
             ctrl.send(ManagerControlMessage::new(
                 self.id(),
-                ManagerControlMessageContents::ScenarioFinished(200, "all tests passed".to_owned()),
+                ManagerControlMessageContents::ScenarioFinished(
+                    200,
+                    format!("all tests passed{}", over),
+                ),
             ))
             .ok();
         }
     }
 
+    /// Summarize the run that just finished with the given code and reason.
+    /// If the scenario repeats, the counts and first failure are taken from
+    /// every pass through the tests, and the tests from the last one.
+    pub fn summary(&self, code: u32, reason: &str) -> ScenarioSummary {
+        let mut summary = self.pass_summary(code, reason);
+        let iterations = self.iterations.borrow();
+        if iterations.is_empty() {
+            return summary;
+        }
+        summary.passed = iterations.iter().map(|i| i.passed).sum();
+        summary.failed = iterations.iter().map(|i| i.failed).sum();
+        summary.faults = iterations.iter().map(|i| i.faults).sum();
+        summary.skipped = iterations.iter().map(|i| i.skipped).sum();
+        summary.first_failure = iterations
+            .iter()
+            .filter_map(|i| i.first_failure.clone())
+            .next();
+        summary.iterations = iterations.clone();
+        summary
+    }
+
+    /// The serial number that the first of the tests that have finished in
+    /// this run reported, if any did.
+    fn serial(&self) -> Option<String> {
+        let finished = self.finished.borrow();
+        self.test_sequence
+            .iter()
+            .map(|test| test.borrow())
+            .filter(|test| finished.contains_key(test.id()))
+            .find_map(|test| test.serial())
+    }
+
+    /// Summarize the current pass through the tests.
+    fn pass_summary(&self, code: u32, reason: &str) -> ScenarioSummary {
+        let mut summary = ScenarioSummary {
+            scenario: self.id().clone(),
+            jig: None,
+            serial: None,
+            allocations: vec![],
+            parameters: vec![],
+            payload: None,
+            run_id: self.run_id.clone(),
+            parent_run: self.parent_run.clone(),
+            station: None,
+            finished: None,
+            maintenance: false,
+            clock_unsynced: false,
+            notes: vec![],
+            code,
+            log_path: None,
+            cpu: None,
+            peak_memory_kb: None,
+            reason: reason.to_owned(),
+            duration: self.elapsed(),
+            passed: 0,
+            failed: 0,
+            faults: 0,
+            skipped: 0,
+            first_failure: None,
+            tests: vec![],
+            iterations: vec![],
+        };
+        let finished = self.finished.borrow();
+        for test in &self.test_sequence {
+            let test = test.borrow();
+            let test_id = test.id().clone();
+            let (verdict, reason) = match *self.test_states[&test_id].borrow() {
+                TestState::Pass | TestState::Running => {
+                    summary.passed += 1;
+                    (TestVerdict::Pass, String::new())
+                }
+                TestState::Pending => {
+                    summary.skipped += 1;
+                    (TestVerdict::Skip, "never ran".to_owned())
+                }
+                TestState::Skip(ref reason) => {
+                    summary.skipped += 1;
+                    (TestVerdict::Skip, reason.clone())
+                }
+                TestState::Fail(ref reason) => {
+                    summary.failed += 1;
+                    (TestVerdict::Fail, reason.clone())
+                }
+                TestState::Fault(ref reason) => {
+                    summary.faults += 1;
+                    (TestVerdict::Fault, reason.clone())
+                }
+                TestState::Excluded => continue,
+            };
+            if summary.first_failure.is_none()
+                && (verdict == TestVerdict::Fail || verdict == TestVerdict::Fault)
+            {
+                summary.first_failure = Some((test_id.clone(), reason.clone()));
+            }
+            // Only tests that finished in this run have measurements from it.
+            let (code, cost) = match finished.get(&test_id) {
+                Some(&(code, cost)) => (Some(code), Some(cost)),
+                None => (None, None),
+            };
+            let measurements = if cost.is_some() {
+                if summary.serial.is_none() {
+                    summary.serial = test.serial();
+                }
+                test.measurements()
+            } else {
+                vec![]
+            };
+            summary.tests.push(TestSummary {
+                test: test_id,
+                verdict,
+                reason,
+                duration: cost.map(|c| c.wall),
+                measurements,
+                code,
+                log_path: cost.and_then(|_| test.output_path()),
+                cpu: cost.and_then(|c| c.cpu),
+                peak_memory_kb: cost.and_then(|c| c.peak_memory_kb),
+            });
+        }
+        summary
+    }
+
     // Determine if Scenario is running or idle
     pub fn is_running(&self) -> bool {
         let s = self.state.borrow();
@@ -1,39 +1,243 @@
 extern crate dependy;
-extern crate humantime;
 extern crate regex;
 extern crate runny;
 extern crate systemd_parser;
 
-use std::cell::RefCell;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use self::dependy::Dependency;
-use self::humantime::{parse_duration, DurationError};
 use self::regex::Regex;
-use self::runny::running::{RunningOutput, RunningWaiter};
+use self::runny::running::{Running, RunningInput, RunningOutput, RunningWaiter};
 use self::runny::Runny;
-use self::systemd_parser::items::DirectiveEntry;
+use self::systemd_parser::items::{DirectiveEntry, SystemdUnit};
 
+use agent::{self, Output};
+use argv;
+use chaos;
+use check::Check;
+use concurrency::{self, Slot};
 use config::Config;
+use container::{self, Container, Device, Mount};
+use coredump::{self, Crash};
+use cost::{Cost, CostMeter};
+use duration;
+use fetch::Fetch;
+use init;
+use poison::Lock;
+use presentation::Presentation;
+use profile;
+use redact::{self, Redaction};
+use schema::{DirectiveSchema, UnitSchema, ValueType};
+use secrets::Resolved;
+use specifier;
+use store;
+use supervisor::{Restart, RestartPolicy};
+use sys::{self, KillSignal, TerminalSize};
 use unit::{
-    UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
-    UnitIncompatibleReason, UnitName, UnitSelectError,
+    parse_aliases, parse_bool, UnitActivateError, UnitDeactivateError, UnitDescriptionError,
+    UnitDeselectError, UnitIncompatibleReason, UnitKind, UnitName, UnitSelectError,
 };
+use unitfile;
 use unitmanager::{ManagerControlMessage, ManagerControlMessageContents, UnitManager};
 
+/// The result Runny reports for a program that was killed by a signal.
+const KILLED_BY_SIGNAL: i32 = -2;
+
+/// The window size given to Tty= tests when exclave isn't running in a terminal.
+const DEFAULT_TTY_SIZE: TerminalSize = TerminalSize {
+    rows: 24,
+    columns: 80,
+};
+
+/// Handles each line a test prints: the raw bytes are saved to the test's
+/// output file (if any), and the line is turned into text for logging.
+#[derive(Clone)]
+struct OutputCapture {
+    file: Option<Arc<Mutex<File>>>,
+    sanitize: bool,
+    success_pattern: Option<Regex>,
+    failure_pattern: Option<Regex>,
+    measurement_pattern: Option<Regex>,
+    serial_pattern: Option<Regex>,
+    matches: Arc<Mutex<PatternMatches>>,
+    secrets: Resolved,
+    redactions: Vec<Redaction>,
+}
+
+/// The first lines of a run's output to match SuccessPattern and FailurePattern,
+/// and what it reported with MeasurementPattern and SerialPattern.
+#[derive(Debug, Default)]
+struct PatternMatches {
+    success: Option<String>,
+    failure: Option<String>,
+    measurements: Vec<(String, String)>,
+    serial: Option<String>,
+}
+
+impl OutputCapture {
+    fn capture(&self, raw: &[u8]) -> String {
+        // Everything after this, including the patterns, only sees the
+        // output with secrets masked and redactions applied.
+        let masked;
+        let raw = if self.secrets.values.is_empty() && self.redactions.is_empty() {
+            raw
+        } else {
+            masked = redact::apply(
+                &self.redactions,
+                &self.secrets.mask(&String::from_utf8_lossy(raw)),
+            );
+            masked.as_bytes()
+        };
+        if let Some(ref file) = self.file {
+            let mut file = file.locked();
+            file.write_all(raw).and_then(|_| file.write_all(b"\n")).ok();
+        }
+        let text = String::from_utf8_lossy(raw);
+        let text = text.trim_end_matches('\r');
+        let clean = sanitize_output(text);
+        self.check_patterns(&clean);
+        if self.sanitize {
+            clean
+        } else {
+            text.to_owned()
+        }
+    }
+
+    fn check_patterns(&self, line: &str) {
+        if self.success_pattern.is_none()
+            && self.failure_pattern.is_none()
+            && self.measurement_pattern.is_none()
+            && self.serial_pattern.is_none()
+        {
+            return;
+        }
+        let mut matches = self.matches.locked();
+        if matches.success.is_none() && self.success_pattern.iter().any(|r| r.is_match(line)) {
+            matches.success = Some(line.to_owned());
+        }
+        if matches.failure.is_none() && self.failure_pattern.iter().any(|r| r.is_match(line)) {
+            matches.failure = Some(line.to_owned());
+        }
+        if let Some(captures) = self
+            .measurement_pattern
+            .as_ref()
+            .and_then(|r| r.captures(line))
+        {
+            // Lines whose value isn't a number are ignored.
+            let value = captures
+                .name("value")
+                .map(|m| m.as_str().trim())
+                .unwrap_or("");
+            if value.parse::<f64>().map(|v| v.is_finite()).unwrap_or(false) {
+                let name = captures
+                    .name("name")
+                    .map(|m| m.as_str())
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or("value");
+                matches
+                    .measurements
+                    .push((name.to_owned(), value.to_owned()));
+            }
+        }
+        if matches.serial.is_none() {
+            if let Some(captures) = self.serial_pattern.as_ref().and_then(|r| r.captures(line)) {
+                let serial = captures.get(1).or_else(|| captures.get(0)).unwrap();
+                matches.serial = Some(serial.as_str().trim().to_owned());
+            }
+        }
+    }
+}
+
+/// Remove ANSI escape sequences and control characters (other than tabs)
+/// from a line of output, so it can't upset line-oriented protocols.
+pub fn sanitize_output(line: &str) -> String {
+    let mut clean = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            match chars.next() {
+                // CSI sequences end with a character in the range '@' to '~'.
+                Some('[') => {
+                    for c in &mut chars {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC sequences end with BEL or ESC-backslash.
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                // Everything else is a two-character sequence.
+                _ => (),
+            }
+        } else if c == '\t' || !c.is_control() {
+            clean.push(c);
+        }
+    }
+    clean
+}
+
+/// How long to wait for a test's output to drain after the test exits.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[derive(Debug, PartialEq, Clone)]
 enum TestType {
     Simple,
     Daemon,
 }
 
+/// What a test's result says about the device under test.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+pub enum TestVerdict {
+    /// The test ran, and the device is good.
+    Pass,
+
+    /// The test ran, and the device is bad.
+    Fail,
+
+    /// The test couldn't tell whether the device is good, because of a problem
+    /// with the station (e.g. a missing program or an unreachable fixture).
+    Fault,
+
+    /// The test doesn't apply to this device, and was skipped.
+    Skip,
+
+    /// The test hit a problem that may go away if it's run again.
+    Retry,
+}
+
+impl fmt::Display for TestVerdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TestVerdict::Pass => write!(f, "pass"),
+            TestVerdict::Fail => write!(f, "fail"),
+            TestVerdict::Fault => write!(f, "fault"),
+            TestVerdict::Skip => write!(f, "skip"),
+            TestVerdict::Retry => write!(f, "retry"),
+        }
+    }
+}
+
 /// A struct defining an in-memory representation of a .test file
 #[derive(Clone)]
 pub struct TestDescription {
@@ -46,9 +250,15 @@ pub struct TestDescription {
     /// A detailed description of this jig, up to one paragraph.
     description: String,
 
+    /// How operator GUIs should show it.
+    presentation: Presentation,
+
     /// A Vec<String> of jig names that this test is compatible with.
     jigs: Vec<UnitName>,
 
+    /// Features the jig must have for this test to run, or else it's skipped.
+    requires_features: Vec<String>,
+
     /// A Vec<String> of test names that must successfully complete for this test to run.
     requires: Vec<UnitName>,
 
@@ -61,6 +271,10 @@ pub struct TestDescription {
     /// want to skip when fixing things in the factory.
     provides: Vec<UnitName>,
 
+    /// The names this test had before it was renamed, which still refer to
+    /// it but are deprecated.
+    aliases: Vec<UnitName>,
+
     /// The maximum duration this test can be run for.
     timeout: Option<Duration>,
 
@@ -78,9 +292,16 @@ pub struct TestDescription {
     /// If present, the daemon won't be considered "ready" until this string is matched.
     test_daemon_ready: Option<Regex>,
 
+    /// Restart, RestartSec, and StartLimitBurst: whether a daemon is started
+    /// again if it exits while the scenario is still running.
+    restart: Restart,
+
     /// ExecStart: The command to run as part of this test.
     exec_start: String,
 
+    /// Shell: Run ExecStart with the shell, rather than splitting it into arguments.
+    shell: bool,
+
     /// ExecStopFail: When stopping tests, if the test failed, then this stop command will be run.
     exec_stop_failure: Option<String>,
 
@@ -92,11 +313,104 @@ pub struct TestDescription {
 
     /// The path to the unit file
     unit_directory: PathBuf,
+
+    /// Ranges of exit codes that indicate a station fault, rather than a bad device.
+    fault_exit_status: Vec<(i32, i32)>,
+
+    /// Verdicts for ranges of exit codes, checked before anything else.
+    exit_status_map: Vec<(i32, i32, TestVerdict)>,
+
+    /// The verdict for exit codes that aren't in exit_status_map, if set.
+    exit_status_default: Option<TestVerdict>,
+
+    /// How many times the test may be run again when its exit code means "retry".
+    max_retries: u32,
+
+    /// If set, the test only passes if a line of its output matches this.
+    success_pattern: Option<Regex>,
+
+    /// If a line of the test's output matches this, the test fails.
+    failure_pattern: Option<Regex>,
+
+    /// If true, a match of success_pattern or failure_pattern decides the
+    /// verdict whatever the exit code, rather than only being able to fail
+    /// a test that would otherwise pass.
+    output_overrides_exit_status: bool,
+
+    /// Lines of output that match this report a measurement: a number in the
+    /// "value" group, named by the "name" group if there is one.
+    measurement_pattern: Option<Regex>,
+
+    /// The first line of output to match this gives the serial number of the
+    /// device under test, from the first group if there is one.
+    serial_pattern: Option<Regex>,
+
+    /// Files the test produces (relative to its working directory) that are
+    /// kept with the run, such as images or waveform dumps.
+    artifacts: Vec<PathBuf>,
+
+    /// Counters on the jig that go up by one each time this test runs.
+    counts: Vec<String>,
+
+    /// Allocators whose values the test is given in its environment.
+    allocate: Vec<UnitName>,
+
+    /// Files downloaded into the cache before the test starts, whose paths
+    /// the test is given in its environment.
+    fetch: Vec<Fetch>,
+
+    /// Built-in checks that are run in place of ExecStart.
+    checks: Vec<Check>,
+
+    /// The agent, from the settings file, that ExecStart is run on, if it
+    /// isn't run here.
+    agent: Option<String>,
+
+    /// The pinned image ExecStart is run in, and what it's given from the
+    /// station, if it's run in a container.
+    container: Option<Container>,
+
+    /// The group, from ConcurrencyLimit in the settings file, that limits
+    /// how many tests like this one run at once.
+    concurrency_group: Option<String>,
+
+    /// Run the test as if it were started from a terminal: stderr joins stdout
+    /// on the pseudo-terminal, and the terminal is given a window size.
+    tty: bool,
+
+    /// The signal sent to the test's process group to stop it.
+    kill_signal: KillSignal,
+
+    /// How long to wait after sending kill_signal before sending SIGKILL.
+    timeout_stop: Duration,
+
+    /// If false, never follow kill_signal with SIGKILL.
+    send_sigkill: bool,
+
+    /// If the test is killed by a signal, save its core dump with the run.
+    save_core_dump: bool,
+
+    /// Text written to the test's stdin when it starts.
+    stdin_data: Option<String>,
+
+    /// A file (relative to the working directory) whose contents are written
+    /// to the test's stdin when it starts, after stdin_data.
+    stdin_file: Option<PathBuf>,
+
+    /// True if this unit was disabled with a Disabled= directive
+    disabled: bool,
+
+    /// The directives in the [Test] section, as (key, value), so that a jig
+    /// can override some of them.
+    directives: Vec<(String, Option<String>)>,
 }
 
 impl TestDescription {
-    pub fn from_path(path: &Path) -> Result<TestDescription, UnitDescriptionError> {
-        let unit_name = UnitName::from_path(path)?;
+    pub fn from_path(
+        unit_name: &UnitName,
+        path: &Path,
+    ) -> Result<TestDescription, UnitDescriptionError> {
+        let unit_name = unit_name.clone();
 
         // Parse the file into a systemd unit_file object
         let mut contents = String::with_capacity(8192);
@@ -109,8 +423,43 @@ impl TestDescription {
         unit_name: UnitName,
         path: &Path,
     ) -> Result<TestDescription, UnitDescriptionError> {
-        let unit_file = systemd_parser::parse_string(contents)?;
+        let parsed = unitfile::parse_to_keep(contents, &[])?;
+        Self::from_parsed(&parsed, unit_name, path)
+    }
+
+    /// Describe a test from a file that has already been parsed.
+    pub fn from_parsed(
+        parsed: &unitfile::ParsedFile,
+        unit_name: UnitName,
+        path: &Path,
+    ) -> Result<TestDescription, UnitDescriptionError> {
+        let unit_file = parsed.unit()?;
+        Self::from_unit(&unit_file, unit_name, path.parent().unwrap().to_owned())
+    }
+
+    /// The test, with some of its directives replaced, as by a jig's
+    /// [Override] section.  Each directive given replaces every value the
+    /// test gave it.
+    pub fn with_overrides(
+        &self,
+        overrides: &[(String, Option<String>)],
+    ) -> Result<TestDescription, UnitDescriptionError> {
+        let mut directives: Vec<(String, Option<String>)> = self
+            .directives
+            .iter()
+            .filter(|(key, _)| !overrides.iter().any(|(overridden, _)| overridden == key))
+            .cloned()
+            .collect();
+        directives.extend(overrides.iter().cloned());
+        let unit_file = unitfile::from_directives("Test", &directives)?;
+        Self::from_unit(&unit_file, self.id.clone(), self.unit_directory.clone())
+    }
 
+    fn from_unit(
+        unit_file: &SystemdUnit,
+        unit_name: UnitName,
+        unit_directory: PathBuf,
+    ) -> Result<TestDescription, UnitDescriptionError> {
         if !unit_file.has_category("Test") {
             return Err(UnitDescriptionError::MissingSection("Test".to_owned()));
         }
@@ -119,12 +468,15 @@ impl TestDescription {
             id: unit_name,
             name: "".to_owned(),
             description: "".to_owned(),
+            presentation: Presentation::default(),
 
             jigs: vec![],
+            requires_features: vec![],
 
             requires: vec![],
             suggests: vec![],
             provides: vec![],
+            aliases: vec![],
 
             timeout: None,
             exec_stop_success_timeout: None,
@@ -133,18 +485,104 @@ impl TestDescription {
             test_type: TestType::Simple,
 
             test_daemon_ready: None,
+            restart: Restart::default(),
 
             exec_start: "".to_owned(),
+            shell: false,
             exec_stop_failure: None,
             exec_stop_success: None,
             working_directory: None,
-            unit_directory: path.parent().unwrap().to_owned(),
+            unit_directory,
+            fault_exit_status: vec![],
+            exit_status_map: vec![],
+            exit_status_default: None,
+            max_retries: 1,
+            success_pattern: None,
+            failure_pattern: None,
+            output_overrides_exit_status: false,
+            measurement_pattern: None,
+            serial_pattern: None,
+            artifacts: vec![],
+            counts: vec![],
+            allocate: vec![],
+            fetch: vec![],
+            checks: vec![],
+            agent: None,
+            container: None,
+            concurrency_group: None,
+            tty: false,
+            kill_signal: KillSignal::Terminate,
+            timeout_stop: Duration::from_secs(0),
+            send_sigkill: true,
+            save_core_dump: false,
+            stdin_data: None,
+            stdin_file: None,
+            disabled: false,
+            directives: vec![],
         };
+        for entry in unit_file.lookup_by_category("Test") {
+            let directives = match entry {
+                DirectiveEntry::Solo(directive) => vec![directive],
+                DirectiveEntry::Many(directives) => directives.iter().collect(),
+            };
+            for directive in directives {
+                test_description.directives.push((
+                    directive.key().to_owned(),
+                    directive.value().map(|v| v.to_owned()),
+                ));
+            }
+        }
+
+        // Fetch, Check, ContainerMount, and ContainerDevice may be given more
+        // than once, so they're read on their own, before the directives that
+        // may only be given once.
+        let mut mounts = vec![];
+        let mut devices = vec![];
+        for entry in unit_file.lookup_by_category("Test") {
+            let directives = match entry {
+                DirectiveEntry::Solo(directive) => vec![directive],
+                DirectiveEntry::Many(directives) => directives.iter().collect(),
+            };
+            for directive in directives {
+                let value = directive.value().unwrap_or("");
+                let invalid = |forms| {
+                    UnitDescriptionError::InvalidValue(
+                        "Test".to_owned(),
+                        directive.key().to_owned(),
+                        value.to_owned(),
+                        forms,
+                    )
+                };
+                match directive.key() {
+                    "Fetch" => test_description
+                        .fetch
+                        .push(value.parse().map_err(|_| invalid(Fetch::forms()))?),
+                    "Check" => test_description
+                        .checks
+                        .push(value.parse().map_err(|_| invalid(Check::forms()))?),
+                    "ContainerMount" => {
+                        mounts.push(value.parse().map_err(|_| invalid(Mount::forms()))?)
+                    }
+                    "ContainerDevice" => {
+                        devices.push(value.parse().map_err(|_| invalid(Device::forms()))?)
+                    }
+                    _ => (),
+                }
+            }
+        }
 
         for entry in unit_file.lookup_by_category("Test") {
             if let DirectiveEntry::Solo(directive) = entry {
                 match directive.key() {
                     "Name" => test_description.name = directive.value().unwrap_or("").to_owned(),
+                    "Icon" | "Color" | "Group" | "HideFromOperator" => test_description
+                        .presentation
+                        .set("Test", directive.key(), directive.value())?,
+                    "Restart" | "RestartSec" | "StartLimitBurst" => {
+                        test_description
+                            .restart
+                            .set("Test", directive.key(), directive.value())?
+                    }
                     "Description" => {
                         test_description.description = directive.value().unwrap_or("").to_owned()
                     }
@@ -154,12 +592,24 @@ impl TestDescription {
                             None => vec![],
                         }
                     }
+                    "RequiresFeatures" => {
+                        test_description.requires_features = directive
+                            .value()
+                            .unwrap_or("")
+                            .split(|c: char| c == ',' || c.is_whitespace())
+                            .filter(|s| !s.is_empty())
+                            .map(|s| s.to_owned())
+                            .collect()
+                    }
                     "Provides" => {
                         test_description.provides = match directive.value() {
                             Some(s) => UnitName::from_list(s, "test")?,
                             None => vec![],
                         }
                     }
+                    "Alias" => {
+                        test_description.aliases = parse_aliases("Test", directive.value(), "test")?
+                    }
                     "Requires" => {
                         test_description.requires = match directive.value() {
                             Some(s) => UnitName::from_list(s, "test")?,
@@ -214,10 +664,13 @@ impl TestDescription {
                             Some(s) => s.to_owned(),
                         }
                     }
+                    "Shell" => {
+                        test_description.shell = parse_bool("Test", "Shell", directive.value())?
+                    }
                     "Timeout" => {
                         test_description.timeout = match directive.value() {
                             None => None,
-                            Some(s) => Some(Self::parse_time(s)?),
+                            Some(s) => Some(duration::parse(s)?),
                         }
                     }
                     "ExecStopSuccess" => {
@@ -226,7 +679,7 @@ impl TestDescription {
                     "ExecStopSuccessTimeout" => {
                         test_description.exec_stop_success_timeout = match directive.value() {
                             None => None,
-                            Some(s) => Some(Self::parse_time(s)?),
+                            Some(s) => Some(duration::parse(s)?),
                         }
                     }
                     "ExecStopFailure" => {
@@ -235,34 +688,382 @@ impl TestDescription {
                     "ExecStopFailureTimeout" => {
                         test_description.exec_stop_failure_timeout = match directive.value() {
                             None => None,
-                            Some(s) => Some(Self::parse_time(s)?),
+                            Some(s) => Some(duration::parse(s)?),
                         }
                     }
+                    "FaultExitStatus" => {
+                        test_description.fault_exit_status =
+                            Self::parse_exit_status(directive.value().unwrap_or(""))?
+                    }
+                    "ExitStatusMap" => {
+                        let (map, default) =
+                            Self::parse_exit_status_map(directive.value().unwrap_or(""))?;
+                        test_description.exit_status_map = map;
+                        test_description.exit_status_default = default;
+                    }
+                    "MaxRetries" => {
+                        let value = directive.value().unwrap_or("");
+                        test_description.max_retries = value.parse().map_err(|_| {
+                            UnitDescriptionError::InvalidValue(
+                                "Test".to_owned(),
+                                "MaxRetries".to_owned(),
+                                value.to_owned(),
+                                vec!["a number of retries".to_owned()],
+                            )
+                        })?
+                    }
+                    "SuccessPattern" => {
+                        test_description.success_pattern = match directive.value() {
+                            Some(s) => Some(Regex::new(s)?),
+                            None => None,
+                        }
+                    }
+                    "FailurePattern" => {
+                        test_description.failure_pattern = match directive.value() {
+                            Some(s) => Some(Regex::new(s)?),
+                            None => None,
+                        }
+                    }
+                    "MeasurementPattern" => {
+                        test_description.measurement_pattern = match directive.value() {
+                            Some(s) => {
+                                let regex = Regex::new(s)?;
+                                if !regex.capture_names().any(|name| name == Some("value")) {
+                                    return Err(UnitDescriptionError::InvalidValue(
+                                        "Test".to_owned(),
+                                        "MeasurementPattern".to_owned(),
+                                        s.to_owned(),
+                                        vec!["a regular expression with a (?P<value>...) group"
+                                            .to_owned()],
+                                    ));
+                                }
+                                Some(regex)
+                            }
+                            None => None,
+                        }
+                    }
+                    "SerialPattern" => {
+                        test_description.serial_pattern = match directive.value() {
+                            Some(s) => Some(Regex::new(s)?),
+                            None => None,
+                        }
+                    }
+                    "OutputOverridesExitStatus" => {
+                        test_description.output_overrides_exit_status =
+                            parse_bool("Test", "OutputOverridesExitStatus", directive.value())?
+                    }
+                    "Artifacts" => {
+                        test_description.artifacts = directive
+                            .value()
+                            .unwrap_or("")
+                            .split_whitespace()
+                            .map(PathBuf::from)
+                            .collect()
+                    }
+                    "Counts" => {
+                        test_description.counts = directive
+                            .value()
+                            .unwrap_or("")
+                            .split(|c: char| c == ',' || c.is_whitespace())
+                            .filter(|s| !s.is_empty())
+                            .map(|s| s.to_owned())
+                            .collect()
+                    }
+                    "Allocate" => {
+                        test_description.allocate = match directive.value() {
+                            Some(s) => UnitName::from_list(s, "allocator")?,
+                            None => vec![],
+                        }
+                    }
+                    "Agent" => {
+                        test_description.agent = directive.value().map(|s| s.trim().to_owned())
+                    }
+                    "Container" => {
+                        let image = directive.value().unwrap_or("").trim();
+                        if !container::pinned_image(image) {
+                            return Err(UnitDescriptionError::InvalidValue(
+                                "Test".to_owned(),
+                                "Container".to_owned(),
+                                image.to_owned(),
+                                container::image_forms(),
+                            ));
+                        }
+                        test_description.container = Some(Container {
+                            image: image.to_owned(),
+                            mounts: vec![],
+                            devices: vec![],
+                        });
+                    }
+                    "ConcurrencyGroup" => {
+                        let value = directive.value().unwrap_or("").trim();
+                        if !concurrency::valid_group(value) {
+                            return Err(UnitDescriptionError::InvalidValue(
+                                "Test".to_owned(),
+                                "ConcurrencyGroup".to_owned(),
+                                value.to_owned(),
+                                vec!["a name made of letters, digits, -, _, and .".to_owned()],
+                            ));
+                        }
+                        test_description.concurrency_group = Some(value.to_owned())
+                    }
+                    "Tty" => test_description.tty = parse_bool("Test", "Tty", directive.value())?,
+                    "KillSignal" => {
+                        let value = directive.value().unwrap_or("");
+                        test_description.kill_signal = value.parse().map_err(|_| {
+                            UnitDescriptionError::InvalidValue(
+                                "Test".to_owned(),
+                                "KillSignal".to_owned(),
+                                value.to_owned(),
+                                KillSignal::names(),
+                            )
+                        })?
+                    }
+                    "TimeoutStopSec" => {
+                        test_description.timeout_stop =
+                            duration::parse(directive.value().unwrap_or("0"))?
+                    }
+                    "SendSIGKILL" => {
+                        test_description.send_sigkill =
+                            parse_bool("Test", "SendSIGKILL", directive.value())?
+                    }
+                    "SaveCoreDump" => {
+                        test_description.save_core_dump =
+                            parse_bool("Test", "SaveCoreDump", directive.value())?
+                    }
+                    "StdinData" => {
+                        test_description.stdin_data = directive
+                            .value()
+                            .map(|s| format!("{}\n", s.replace("\\n", "\n")))
+                    }
+                    "StdinFile" => {
+                        test_description.stdin_file = directive.value().map(PathBuf::from)
+                    }
+                    "Disabled" => {
+                        test_description.disabled =
+                            parse_bool("Test", "Disabled", directive.value())?
+                    }
                     &_ => (),
                 }
             }
         }
-        if test_description.exec_start.is_empty() {
-            return Err(UnitDescriptionError::MissingValue(
-                "Test".to_owned(),
-                "ExecStart".to_owned(),
-            ));
+        // Checks are run in place of a program, not as well as one.
+        match (
+            test_description.exec_start.is_empty(),
+            test_description.checks.is_empty(),
+        ) {
+            (true, true) => {
+                return Err(UnitDescriptionError::MissingValue(
+                    "Test".to_owned(),
+                    "ExecStart".to_owned(),
+                ))
+            }
+            (false, false) => {
+                return Err(UnitDescriptionError::InvalidValue(
+                    "Test".to_owned(),
+                    "ExecStart".to_owned(),
+                    test_description.exec_start,
+                    vec!["nothing, when Check is given".to_owned()],
+                ))
+            }
+            (false, true) => {
+                if !test_description.shell {
+                    argv::check("Test", "ExecStart", &test_description.exec_start)?;
+                }
+                specifier::check("Test", "ExecStart", &test_description.exec_start)?;
+            }
+            _ => (),
+        }
+        // Agents only run a program and pass back what it prints.
+        if let Some(ref agent) = test_description.agent {
+            if test_description.test_type != TestType::Simple
+                || !test_description.fetch.is_empty()
+                || !test_description.checks.is_empty()
+            {
+                return Err(UnitDescriptionError::InvalidValue(
+                    "Test".to_owned(),
+                    "Agent".to_owned(),
+                    agent.to_owned(),
+                    vec!["nothing, for daemons or tests that use Fetch or Check".to_owned()],
+                ));
+            }
+        }
+        // Mounts and devices are only for tests run in a container, which
+        // has to run a program here.
+        match test_description.container {
+            Some(ref mut container) => {
+                if test_description.agent.is_some() || !test_description.checks.is_empty() {
+                    return Err(UnitDescriptionError::InvalidValue(
+                        "Test".to_owned(),
+                        "Container".to_owned(),
+                        container.image.clone(),
+                        vec!["nothing, for tests that use Agent or Check".to_owned()],
+                    ));
+                }
+                container.mounts = mounts;
+                container.devices = devices;
+            }
+            None if !mounts.is_empty() || !devices.is_empty() => {
+                return Err(UnitDescriptionError::MissingValue(
+                    "Test".to_owned(),
+                    "Container".to_owned(),
+                ))
+            }
+            None => (),
         }
         Ok(test_description)
     }
 
-    fn parse_time(time_str: &str) -> Result<Duration, DurationError> {
-        if let Ok(val) = time_str.parse::<u64>() {
-            Ok(Duration::from_secs(val))
-        } else {
-            parse_duration(time_str)
+    /// The directives that [Test] sections may contain.
+    pub fn schema() -> UnitSchema {
+        UnitSchema {
+            kind: UnitKind::Test,
+            section: "Test",
+            directives: vec![
+                DirectiveSchema::new("Name", ValueType::Text),
+                DirectiveSchema::new("Description", ValueType::Text),
+                DirectiveSchema::new("Icon", ValueType::Text),
+                DirectiveSchema::new("Color", ValueType::Text),
+                DirectiveSchema::new("Group", ValueType::Text),
+                DirectiveSchema::new("HideFromOperator", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("Jigs", ValueType::Units(UnitKind::Jig)),
+                DirectiveSchema::new("RequiresFeatures", ValueType::Names),
+                DirectiveSchema::new("Provides", ValueType::Units(UnitKind::Test)),
+                DirectiveSchema::new("Alias", ValueType::Units(UnitKind::Test)),
+                DirectiveSchema::new("Requires", ValueType::Units(UnitKind::Test)),
+                DirectiveSchema::new("Suggests", ValueType::Units(UnitKind::Test)),
+                DirectiveSchema::new("DaemonReadyText", ValueType::Regex),
+                DirectiveSchema::new("Restart", ValueType::Choice(RestartPolicy::choices()))
+                    .with_default("no"),
+                DirectiveSchema::new("RestartSec", ValueType::Duration).with_default("1s"),
+                DirectiveSchema::new("StartLimitBurst", ValueType::Integer).with_default("5"),
+                DirectiveSchema::new(
+                    "Type",
+                    ValueType::Choice(vec!["simple".to_owned(), "daemon".to_owned()]),
+                )
+                .with_default("simple"),
+                DirectiveSchema::new("WorkingDirectory", ValueType::Path),
+                DirectiveSchema::new("ExecStart", ValueType::Command),
+                DirectiveSchema::new("Shell", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("Check", ValueType::Text),
+                DirectiveSchema::new("Agent", ValueType::Text),
+                DirectiveSchema::new("Container", ValueType::Text),
+                DirectiveSchema::new("ContainerMount", ValueType::Text),
+                DirectiveSchema::new("ContainerDevice", ValueType::Text),
+                DirectiveSchema::new("ConcurrencyGroup", ValueType::Text),
+                DirectiveSchema::new("Timeout", ValueType::Duration),
+                DirectiveSchema::new("ExecStopSuccess", ValueType::Command),
+                DirectiveSchema::new("ExecStopSuccessTimeout", ValueType::Duration),
+                DirectiveSchema::new("ExecStopFailure", ValueType::Command),
+                DirectiveSchema::new("ExecStopFailureTimeout", ValueType::Duration),
+                DirectiveSchema::new("FaultExitStatus", ValueType::ExitStatus),
+                DirectiveSchema::new("ExitStatusMap", ValueType::ExitStatusMap),
+                DirectiveSchema::new("MaxRetries", ValueType::Integer).with_default("1"),
+                DirectiveSchema::new("SuccessPattern", ValueType::Regex),
+                DirectiveSchema::new("FailurePattern", ValueType::Regex),
+                DirectiveSchema::new("MeasurementPattern", ValueType::Regex),
+                DirectiveSchema::new("SerialPattern", ValueType::Regex),
+                DirectiveSchema::new("OutputOverridesExitStatus", ValueType::Bool)
+                    .with_default("no"),
+                DirectiveSchema::new("Artifacts", ValueType::Paths),
+                DirectiveSchema::new("Counts", ValueType::Names),
+                DirectiveSchema::new("Allocate", ValueType::Units(UnitKind::Allocator)),
+                DirectiveSchema::new("Fetch", ValueType::Text),
+                DirectiveSchema::new("Tty", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("KillSignal", ValueType::Choice(KillSignal::names()))
+                    .with_default("SIGTERM"),
+                DirectiveSchema::new("TimeoutStopSec", ValueType::Duration).with_default("0"),
+                DirectiveSchema::new("SendSIGKILL", ValueType::Bool).with_default("yes"),
+                DirectiveSchema::new("SaveCoreDump", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("StdinData", ValueType::Text),
+                DirectiveSchema::new("StdinFile", ValueType::Path),
+                DirectiveSchema::new("Disabled", ValueType::Bool).with_default("no"),
+            ],
+        }
+    }
+
+    /// Parse a list of exit codes and inclusive ranges, such as "2 10-19".
+    fn parse_exit_status(list: &str) -> Result<Vec<(i32, i32)>, UnitDescriptionError> {
+        list.split([',', ' '])
+            .filter(|s| !s.is_empty())
+            .map(Self::parse_exit_range)
+            .collect()
+    }
+
+    /// Parse an exit code or an inclusive range of them, such as "10-19".
+    fn parse_exit_range(item: &str) -> Result<(i32, i32), UnitDescriptionError> {
+        // Split on the first '-' after the first character, so that
+        // negative codes such as "-1" still parse.
+        Ok(match item.char_indices().skip(1).find(|&(_, c)| c == '-') {
+            Some((pos, _)) => (item[..pos].parse::<i32>()?, item[pos + 1..].parse::<i32>()?),
+            None => {
+                let code = item.parse::<i32>()?;
+                (code, code)
+            }
+        })
+    }
+
+    /// Parse a table of exit codes and the verdicts they mean, such as
+    /// "0=pass 2=skip 3=retry 10-19=fault *=fail".  "*" sets the verdict
+    /// for every code that isn't listed.
+    #[allow(clippy::type_complexity)]
+    fn parse_exit_status_map(
+        list: &str,
+    ) -> Result<(Vec<(i32, i32, TestVerdict)>, Option<TestVerdict>), UnitDescriptionError> {
+        let mut map = vec![];
+        let mut default = None;
+        for item in list.split([',', ' ']).filter(|s| !s.is_empty()) {
+            let invalid = || {
+                UnitDescriptionError::InvalidValue(
+                    "Test".to_owned(),
+                    "ExitStatusMap".to_owned(),
+                    item.to_owned(),
+                    ["pass", "fail", "fault", "skip", "retry"]
+                        .iter()
+                        .map(|v| format!("<code>={}", v))
+                        .collect(),
+                )
+            };
+            let (codes, verdict) = item.split_once('=').ok_or_else(invalid)?;
+            let verdict = match verdict.to_lowercase().as_str() {
+                "pass" => TestVerdict::Pass,
+                "fail" => TestVerdict::Fail,
+                "fault" => TestVerdict::Fault,
+                "skip" => TestVerdict::Skip,
+                "retry" => TestVerdict::Retry,
+                _ => return Err(invalid()),
+            };
+            if codes == "*" {
+                default = Some(verdict);
+            } else {
+                let (low, high) = Self::parse_exit_range(codes)?;
+                map.push((low, high, verdict));
+            }
         }
+        Ok((map, default))
     }
 
     pub fn id(&self) -> &UnitName {
         &self.id
     }
 
+    /// The names this test had before it was renamed.
+    pub fn aliases(&self) -> &Vec<UnitName> {
+        &self.aliases
+    }
+
+    /// Returns true if this unit was disabled from within its unit file.
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    /// Returns true if this test Requires or Suggests the named test.
+    pub fn uses_test(&self, name: &UnitName) -> bool {
+        self.requires
+            .iter()
+            .chain(self.suggests.iter())
+            .any(|t| t.resolve(self.id.namespace(), |n| n == name) == *name)
+    }
+
     /// Returns true if this test is supported on the named jig.
     pub fn supports_jig(&self, name: &UnitName) -> bool {
         self.jigs.contains(name)
@@ -279,19 +1080,452 @@ impl TestDescription {
 
 pub struct Test {
     description: TestDescription,
-    program: Rc<RefCell<Option<RunningWaiter>>>,
+
+    /// The description as the unit file gives it, without any jig's overrides.
+    written: TestDescription,
+
+    /// The names other tests can require this one by: what it provides, and
+    /// its old names.
+    provides: Vec<UnitName>,
+    program: Rc<RefCell<Option<Arc<RunningWaiter>>>>,
+
+    /// The connection to the agent running the test, if it's run on one.
+    remote: RefCell<Option<TcpStream>>,
+
+    /// How many times the test has been retried in the current scenario run.
+    retries: Cell<u32>,
+
+    /// Process ID of the most recent run, which leads its process group.
+    pid: Cell<i32>,
+
+    /// When the most recent run was started.
+    started: Cell<SystemTime>,
+
+    /// The stdin of the most recent run, for operator input.
+    input: Arc<Mutex<Option<RunningInput>>>,
     result_arc: Arc<Mutex<Option<i32>>>,
     last_line: Arc<Mutex<String>>,
+
+    /// Lines of the most recent run's output that matched its patterns.
+    pattern_matches: Arc<Mutex<PatternMatches>>,
+
+    /// Where the output of the most recent run was saved, if anywhere.
+    output_path: RefCell<Option<PathBuf>>,
+
+    /// Measures the cost of the most recent run.
+    cost_meter: RefCell<Option<CostMeter>>,
+
+    /// The values allocated for the next run, as (variable, value).
+    allocations: RefCell<Vec<(String, String)>>,
+
+    /// The values of the scenario's parameters for the next run, as
+    /// (variable, value).
+    parameters: RefCell<Vec<(String, String)>>,
+
+    /// The slot in the test's concurrency group that it holds while it runs.
+    slot: RefCell<Option<Slot>>,
+
+    /// Set to stop waiting for a slot, while the test is waiting for one.
+    waiting: RefCell<Option<Arc<AtomicBool>>>,
+
+    /// The runtime and name of the container the most recent run is in, if
+    /// it's run in one.
+    container_run: RefCell<Option<(String, String)>>,
+
+    /// Set while a daemon that exited is being started again, so that the
+    /// scenario isn't advanced a second time.
+    respawning: Cell<bool>,
 }
 
 impl Test {
     pub fn new(desc: &TestDescription) -> Test {
         Test {
             description: desc.clone(),
+            written: desc.clone(),
+            provides: desc
+                .provides
+                .iter()
+                .chain(desc.aliases.iter())
+                .cloned()
+                .collect(),
             program: Rc::new(RefCell::new(None)),
+            remote: RefCell::new(None),
+            retries: Cell::new(0),
+            pid: Cell::new(0),
+            started: Cell::new(UNIX_EPOCH),
+            input: Arc::new(Mutex::new(None)),
             result_arc: Arc::new(Mutex::new(None)),
             last_line: Arc::new(Mutex::new("".to_owned())),
+            pattern_matches: Arc::new(Mutex::new(PatternMatches::default())),
+            output_path: RefCell::new(None),
+            cost_meter: RefCell::new(None),
+            allocations: RefCell::new(vec![]),
+            parameters: RefCell::new(vec![]),
+            slot: RefCell::new(None),
+            waiting: RefCell::new(None),
+            container_run: RefCell::new(None),
+            respawning: Cell::new(false),
+        }
+    }
+
+    /// Where the output of the most recent run was saved, if anywhere.
+    pub fn output_path(&self) -> Option<PathBuf> {
+        self.output_path.borrow().clone()
+    }
+
+    /// What the most recent run has cost so far, if the test has been run.
+    pub fn cost(&self) -> Option<Cost> {
+        self.cost_meter.borrow().as_ref().map(|m| m.cost())
+    }
+
+    /// Wrap a command so that it sees a terminal on stderr as well as on
+    /// stdin and stdout, sized to match the one exclave is running in.
+    /// Runny already starts every program on a pseudo-terminal, but leaves
+    /// stderr on a pipe and the window size at zero, which some tools take
+    /// to mean they aren't on a terminal at all.
+    #[cfg(unix)]
+    fn tty_command(cmd: &str) -> String {
+        let size = sys::terminal_size(&io::stdout()).unwrap_or(DEFAULT_TTY_SIZE);
+        format!(
+            "/bin/sh -c 'stty rows {} cols {} 2>/dev/null; exec \"$0\" \"$@\" 2>&1' {}",
+            size.rows, size.columns, cmd
+        )
+    }
+
+    /// There are no pseudo-terminals to wrap the command in on this platform.
+    #[cfg(not(unix))]
+    fn tty_command(cmd: &str) -> String {
+        cmd.to_owned()
+    }
+
+    /// Wrap a command so that it runs with extra environment variables.
+    /// Runny doesn't pass an environment through, so it's set with env(1).
+    #[cfg(unix)]
+    fn env_command(vars: &[(&str, &str)], cmd: &str) -> String {
+        let mut wrapped = "/usr/bin/env".to_owned();
+        for (name, value) in vars {
+            // Quoted for Runny, which splits the command like a shell would.
+            wrapped.push_str(&format!(" '{}={}'", name, value.replace('\'', "'\"'\"'")));
         }
+        format!("{} {}", wrapped, cmd)
+    }
+
+    /// There's no env(1) to set variables with on this platform.
+    #[cfg(not(unix))]
+    fn env_command(_vars: &[(&str, &str)], cmd: &str) -> String {
+        cmd.to_owned()
+    }
+
+    /// Get the store of the jig the test is running against ready, and
+    /// return where it is, if there's a jig and a state directory.
+    fn store_path(&self, ctrl: &Sender<ManagerControlMessage>, config: &Config) -> Option<PathBuf> {
+        let jig = config.bound_jig()?;
+        let result = config.store_path(&jig).and_then(|path| match path {
+            Some(path) => store::tidy(&path).map(|_| Some(path)),
+            None => Ok(None),
+        });
+        match result {
+            Ok(path) => path,
+            Err(e) => {
+                ctrl.send(ManagerControlMessage::new(
+                    self.id(),
+                    ManagerControlMessageContents::LogError(format!(
+                        "unable to prepare the store for jig {}: {}",
+                        jig, e
+                    )),
+                ))
+                .ok();
+                None
+            }
+        }
+    }
+
+    /// Run the test's command on an agent, passing along what it prints and
+    /// how it exits as if it had been run here.
+    fn activate_remote(
+        &self,
+        agent: &str,
+        cmd: String,
+        ctrl: &Sender<ManagerControlMessage>,
+        config: &Config,
+        secrets: Resolved,
+    ) -> Result<(), UnitActivateError> {
+        let id = self.id().clone();
+        let request = agent::Request {
+            command: cmd,
+            directory: self.description.working_directory.clone(),
+            timeout: self.description.timeout,
+            stop_timeout: Some(self.description.timeout_stop),
+        };
+        let connection = match config.agent_address(agent) {
+            Some(address) => config
+                .agent_key()
+                .and_then(|key| agent::Connection::start(address, &key, &request)),
+            None => Err(format!("agent {} isn't in the settings file", agent)),
+        };
+        let mut connection = match connection {
+            Ok(connection) => connection,
+            Err(e) => {
+                let e = secrets.mask(&e);
+                Self::report_start_failure(&id, ctrl, format!("unable to start test: {}", e));
+                return Err(UnitActivateError::AgentFailed(e));
+            }
+        };
+        *self.program.borrow_mut() = None;
+        *self.remote.borrow_mut() = connection.stopper().ok();
+
+        let output = self.capture_output(ctrl, config, secrets);
+        let ctrl = ctrl.clone();
+        let last_line = self.last_line.clone();
+        let result_arc = self.result_arc.clone();
+        thread::spawn(move || {
+            let result = loop {
+                match connection.next_output() {
+                    Some(Output::Stdout(line)) => {
+                        let line = output.capture(line.as_bytes());
+                        *last_line.locked() = line.clone();
+                        ctrl.send(ManagerControlMessage::new(
+                            &id,
+                            ManagerControlMessageContents::Log(line),
+                        ))
+                        .ok();
+                    }
+                    Some(Output::Stderr(line)) => {
+                        let line = output.capture(line.as_bytes());
+                        *last_line.locked() = line.clone();
+                        ctrl.send(ManagerControlMessage::new(
+                            &id,
+                            ManagerControlMessageContents::LogError(line),
+                        ))
+                        .ok();
+                    }
+                    Some(Output::Exit(code)) => break code,
+                    None => {
+                        ctrl.send(ManagerControlMessage::new(
+                            &id,
+                            ManagerControlMessageContents::LogError(
+                                "lost the connection to the agent".to_owned(),
+                            ),
+                        ))
+                        .ok();
+                        break -1;
+                    }
+                }
+            };
+            Self::send_finished_once(&id, &ctrl, result, &result_arc, &last_line);
+            ctrl.send(ManagerControlMessage::new(
+                &id,
+                ManagerControlMessageContents::AdvanceScenario(result),
+            ))
+            .ok();
+        });
+        Ok(())
+    }
+
+    /// Run the test's Checks on their own thread, reporting each as a line
+    /// of output, as if a program had printed it.  The test passes if every
+    /// check does.
+    fn run_checks(&self, ctrl: &Sender<ManagerControlMessage>, config: &Config) {
+        *self.program.borrow_mut() = None;
+        let output = self.capture_output(ctrl, config, Resolved::default());
+        let directory = config.working_directory(
+            &self.description.unit_directory,
+            &self.description.working_directory,
+        );
+        let checks = self.description.checks.clone();
+        let id = self.id().clone();
+        let ctrl = ctrl.clone();
+        let last_line = self.last_line.clone();
+        let result_arc = self.result_arc.clone();
+        thread::spawn(move || {
+            let mut result = 0;
+            for check in &checks {
+                let line = match check.run(&directory) {
+                    Ok(message) => format!("pass: {}", message),
+                    Err(message) => {
+                        result = 1;
+                        format!("fail: {}", message)
+                    }
+                };
+                let line = output.capture(line.as_bytes());
+                *last_line.locked() = line.clone();
+                ctrl.send(ManagerControlMessage::new(
+                    &id,
+                    ManagerControlMessageContents::Log(line),
+                ))
+                .ok();
+            }
+            Self::send_finished_once(&id, &ctrl, result, &result_arc, &last_line);
+            ctrl.send(ManagerControlMessage::new(
+                &id,
+                ManagerControlMessageContents::AdvanceScenario(result),
+            ))
+            .ok();
+        });
+    }
+
+    /// Make sure every file the test fetches is in the cache, returning the
+    /// variables that give their paths, as (variable, path).
+    fn fetch_files(&self, config: &Config) -> Result<Vec<(String, String)>, String> {
+        if self.description.fetch.is_empty() {
+            return Ok(vec![]);
+        }
+        let cache = match config.fetch_cache() {
+            Ok(Some(cache)) => cache,
+            Ok(None) => return Err("Fetch needs a StateDirectory or FetchCache".to_owned()),
+            Err(e) => return Err(format!("unable to create the fetch cache: {}", e)),
+        };
+        let mut fetched = vec![];
+        for fetch in &self.description.fetch {
+            // The URL may carry a token, which is kept out of any messages.
+            let url = config
+                .resolve_secrets(fetch.url())
+                .map_err(|e| format!("unable to fetch {}: {}", fetch.url(), e))?;
+            let path = fetch
+                .fetch(&cache, &url.text)
+                .map_err(|e| format!("unable to fetch {}: {}", fetch.url(), url.mask(&e)))?;
+            fetched.push((
+                fetch.variable().to_owned(),
+                path.to_string_lossy().into_owned(),
+            ));
+        }
+        Ok(fetched)
+    }
+
+    /// Set up capture of this run's output, saving it to "<test>.log" in
+    /// the current run directory if there is one, with the secrets that
+    /// went into the command masked and the Redact rules applied.
+    fn capture_output(
+        &self,
+        ctrl: &Sender<ManagerControlMessage>,
+        config: &Config,
+        secrets: Resolved,
+    ) -> OutputCapture {
+        *self.pattern_matches.locked() = PatternMatches::default();
+        OutputCapture {
+            file: self.create_output_file(ctrl, config),
+            sanitize: config.sanitize_output(),
+            success_pattern: self.description.success_pattern.clone(),
+            failure_pattern: self.description.failure_pattern.clone(),
+            measurement_pattern: self.description.measurement_pattern.clone(),
+            serial_pattern: self.description.serial_pattern.clone(),
+            matches: self.pattern_matches.clone(),
+            secrets,
+            redactions: config.redactions().to_vec(),
+        }
+    }
+
+    fn create_output_file(
+        &self,
+        ctrl: &Sender<ManagerControlMessage>,
+        config: &Config,
+    ) -> Option<Arc<Mutex<File>>> {
+        *self.output_path.borrow_mut() = None;
+        let path = config
+            .run_directory()?
+            .join(format!("{}.log", self.id().id()));
+        // Namespaced tests are saved in a subdirectory named after their namespace.
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        match File::create(&path) {
+            Ok(f) => {
+                *self.output_path.borrow_mut() = Some(path);
+                Some(Arc::new(Mutex::new(f)))
+            }
+            Err(e) => {
+                ctrl.send(ManagerControlMessage::new(
+                    self.id(),
+                    ManagerControlMessageContents::LogError(format!(
+                        "unable to create output file {}: {}",
+                        path.display(),
+                        e
+                    )),
+                ))
+                .ok();
+                None
+            }
+        }
+    }
+
+    /// Copy this test's artifacts into "<test>/" in the current run directory,
+    /// returning the name and size of each one that was saved.
+    pub fn save_artifacts(
+        &self,
+        ctrl: &Sender<ManagerControlMessage>,
+        config: &Config,
+    ) -> Vec<(String, u64)> {
+        let working_directory = config.working_directory(
+            &self.description.unit_directory,
+            &self.description.working_directory,
+        );
+        self.description
+            .artifacts
+            .iter()
+            .filter_map(|artifact| {
+                self.save_artifact(&working_directory.join(artifact), ctrl, config)
+            })
+            .collect()
+    }
+
+    /// Copy one file into "<test>/" in the current run directory, returning
+    /// its name and size if it was saved.
+    fn save_artifact(
+        &self,
+        source: &Path,
+        ctrl: &Sender<ManagerControlMessage>,
+        config: &Config,
+    ) -> Option<(String, u64)> {
+        let run_directory = config.run_directory()?.join(self.id().id());
+        let name = source.file_name()?.to_string_lossy().into_owned();
+        let result = fs::create_dir_all(&run_directory)
+            .and_then(|_| fs::copy(source, run_directory.join(&name)));
+        match result {
+            Ok(size) => Some((name, size)),
+            Err(e) => {
+                ctrl.send(ManagerControlMessage::new(
+                    self.id(),
+                    ManagerControlMessageContents::LogError(format!(
+                        "unable to save artifact {}: {}",
+                        source.display(),
+                        e
+                    )),
+                ))
+                .ok();
+                None
+            }
+        }
+    }
+
+    /// If the most recent run was killed by a signal, find out which signal
+    /// it was and whether it left a core dump.  Only simple tests are
+    /// examined, since daemons also use the same result for their own errors.
+    pub fn examine_crash(&self, result: i32, config: &Config) -> Option<Crash> {
+        if result != KILLED_BY_SIGNAL || self.description.test_type != TestType::Simple {
+            return None;
+        }
+        let working_directory = config.working_directory(
+            &self.description.unit_directory,
+            &self.description.working_directory,
+        );
+        Some(coredump::examine(
+            &working_directory,
+            self.pid.get(),
+            self.started.get(),
+        ))
+    }
+
+    /// Save the core dump from a crash with the run, if SaveCoreDump is set.
+    pub fn save_core_dump(
+        &self,
+        crash: &Crash,
+        ctrl: &Sender<ManagerControlMessage>,
+        config: &Config,
+    ) -> Option<(String, u64)> {
+        if !self.description.save_core_dump {
+            return None;
+        }
+        self.save_artifact(crash.core.as_ref()?, ctrl, config)
     }
 
     pub fn select(&self, manager: &UnitManager) -> Result<(), UnitSelectError> {
@@ -317,6 +1551,198 @@ impl Test {
         Ok(())
     }
 
+    /// Classify the result of running this test.  Negative results come from
+    /// exclave itself, and mean the test couldn't be started or monitored.
+    /// SuccessPattern and FailurePattern can then fail a passing test, or
+    /// decide the verdict outright with OutputOverridesExitStatus.
+    /// A test that asks to be retried once it's out of retries is a fault.
+    pub fn verdict(&self, result: i32) -> TestVerdict {
+        let desc = &self.description;
+        let verdict = self.exit_verdict(result);
+        let verdict = match self.output_verdict() {
+            Some((output, _)) if result >= 0 && desc.output_overrides_exit_status => output,
+            Some((TestVerdict::Fail, _)) if verdict == TestVerdict::Pass => TestVerdict::Fail,
+            _ => verdict,
+        };
+        if verdict == TestVerdict::Retry && self.retries.get() >= desc.max_retries {
+            TestVerdict::Fault
+        } else {
+            verdict
+        }
+    }
+
+    /// The reason the test's output gave for its verdict, if it decided it.
+    pub fn output_reason(&self, result: i32) -> Option<String> {
+        let (output, reason) = self.output_verdict()?;
+        if output == self.verdict(result) && output != self.exit_verdict(result) {
+            Some(reason)
+        } else {
+            None
+        }
+    }
+
+    /// What SuccessPattern and FailurePattern make of the most recent run's
+    /// output, and why.  A failure match beats a success match.
+    fn output_verdict(&self) -> Option<(TestVerdict, String)> {
+        let matches = self.pattern_matches.locked();
+        if let Some(ref line) = matches.failure {
+            return Some((
+                TestVerdict::Fail,
+                format!("output matched FailurePattern: {}", line),
+            ));
+        }
+        self.description.success_pattern.as_ref()?;
+        Some(match matches.success {
+            Some(ref line) => (
+                TestVerdict::Pass,
+                format!("output matched SuccessPattern: {}", line),
+            ),
+            None => (
+                TestVerdict::Fail,
+                "output never matched SuccessPattern".to_owned(),
+            ),
+        })
+    }
+
+    /// The measurements the most recent run reported, as (name, value).
+    pub fn measurements(&self) -> Vec<(String, String)> {
+        self.pattern_matches.locked().measurements.clone()
+    }
+
+    /// The serial number the most recent run reported, if any.
+    pub fn serial(&self) -> Option<String> {
+        self.pattern_matches.locked().serial.clone()
+    }
+
+    /// The jig counters that go up each time this test runs.
+    pub fn counts(&self) -> &Vec<String> {
+        &self.description.counts
+    }
+
+    /// The allocators whose values this test needs.
+    pub fn allocate(&self) -> &Vec<UnitName> {
+        &self.description.allocate
+    }
+
+    /// Give the next run the values allocated for it, as (variable, value).
+    pub fn set_allocations(&self, allocations: Vec<(String, String)>) {
+        *self.allocations.borrow_mut() = allocations;
+    }
+
+    /// Give the next run the values of the scenario's parameters, as
+    /// (variable, value).
+    pub fn set_parameters(&self, parameters: Vec<(String, String)>) {
+        *self.parameters.borrow_mut() = parameters;
+    }
+
+    /// The names this test had before it was renamed.
+    pub fn aliases(&self) -> &Vec<UnitName> {
+        &self.description.aliases
+    }
+
+    /// Use a jig's overrides for the test's directives from the next run on,
+    /// or go back to the unit file's if there are none.
+    pub fn set_overrides(
+        &mut self,
+        overrides: &[(String, Option<String>)],
+    ) -> Result<(), UnitDescriptionError> {
+        self.description = if overrides.is_empty() {
+            self.written.clone()
+        } else {
+            self.written.with_overrides(overrides)?
+        };
+        Ok(())
+    }
+
+    /// The group that limits how many tests like this run at once, if any.
+    pub fn concurrency_group(&self) -> Option<&str> {
+        self.description.concurrency_group.as_deref()
+    }
+
+    /// Hold a slot in the test's concurrency group until the test finishes.
+    pub fn set_slot(&self, slot: Slot) {
+        *self.slot.borrow_mut() = Some(slot);
+    }
+
+    /// Give back the test's slot, if it holds one.
+    pub fn release_slot(&self) {
+        self.slot.borrow_mut().take();
+    }
+
+    /// Note that the test is waiting for a slot, until `cancelled` is set.
+    pub fn wait_for_slot(&self, cancelled: Arc<AtomicBool>) {
+        *self.waiting.borrow_mut() = Some(cancelled);
+    }
+
+    /// Stop waiting for a slot.  Returns true if the test was still waiting.
+    pub fn stop_waiting(&self) -> bool {
+        match self.waiting.borrow_mut().take() {
+            Some(cancelled) => !cancelled.swap(true, Ordering::SeqCst),
+            None => false,
+        }
+    }
+
+    /// The verdict from the exit code alone.
+    fn exit_verdict(&self, result: i32) -> TestVerdict {
+        let desc = &self.description;
+        if result < 0 {
+            TestVerdict::Fault
+        } else if let Some(&(_, _, verdict)) = desc
+            .exit_status_map
+            .iter()
+            .find(|&&(low, high, _)| result >= low && result <= high)
+        {
+            verdict
+        } else if result == 0 {
+            desc.exit_status_default.unwrap_or(TestVerdict::Pass)
+        } else if desc
+            .fault_exit_status
+            .iter()
+            .any(|&(low, high)| result >= low && result <= high)
+            // The runtime couldn't start the container.
+            || (desc.container.is_some() && result == container::RUNTIME_FAILED)
+        {
+            TestVerdict::Fault
+        } else {
+            desc.exit_status_default.unwrap_or(TestVerdict::Fail)
+        }
+    }
+
+    /// Note that the test is being run again, returning how many times it
+    /// has been retried.
+    pub fn add_retry(&self) -> u32 {
+        self.retries.set(self.retries.get() + 1);
+        self.retries.get()
+    }
+
+    /// Start counting retries afresh, for a new scenario run.
+    pub fn reset_retries(&self) {
+        self.retries.set(0);
+    }
+
+    /// Report that the test couldn't be started, and move the scenario on.
+    pub fn report_start_failure(
+        id: &UnitName,
+        ctrl: &Sender<ManagerControlMessage>,
+        message: String,
+    ) {
+        ctrl.send(ManagerControlMessage::new(
+            id,
+            ManagerControlMessageContents::LogError(message.clone()),
+        ))
+        .unwrap();
+        ctrl.send(ManagerControlMessage::new(
+            id,
+            ManagerControlMessageContents::TestFinished(-3, message),
+        ))
+        .ok();
+        ctrl.send(ManagerControlMessage::new(
+            id,
+            ManagerControlMessageContents::AdvanceScenario(-3),
+        ))
+        .ok();
+    }
+
     /// Send the "test finished" message and update the local result value.
     /// This ensures that we only send the "Finished" result once.
     pub fn send_finished_once(
@@ -326,15 +1752,12 @@ impl Test {
         result_arc: &Arc<Mutex<Option<i32>>>,
         last_line: &Arc<Mutex<String>>,
     ) {
-        let mut result = result_arc.lock().unwrap();
+        let mut result = result_arc.locked();
 
         if result.is_none() {
             ctrl.send(ManagerControlMessage::new(
                 id,
-                ManagerControlMessageContents::TestFinished(
-                    result_val,
-                    last_line.lock().unwrap().clone(),
-                ),
+                ManagerControlMessageContents::TestFinished(result_val, last_line.locked().clone()),
             ))
             .ok();
             *result = Some(result_val);
@@ -350,7 +1773,9 @@ impl Test {
         let ctrl = manager.get_control_channel();
         let id = self.id().clone();
 
-        *self.result_arc.lock().unwrap() = None;
+        *self.result_arc.locked() = None;
+        *self.cost_meter.borrow_mut() = Some(CostMeter::start(config.clock()));
+        self.started.set(SystemTime::now());
 
         // Announce to the world that we've started considering this test.
         ctrl.send(ManagerControlMessage::new(
@@ -359,60 +1784,122 @@ impl Test {
         ))
         .ok();
 
-        let cmd = &self.description.exec_start;
+        if !self.description.checks.is_empty() {
+            self.run_checks(&ctrl, config);
+            return Ok(());
+        }
+
+        // Secrets are only filled in now, and whatever the test prints has
+        // them masked out again.
+        let secrets =
+            match config.resolve_command(&id, &self.description.exec_start, self.description.shell)
+            {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    Self::report_start_failure(&id, &ctrl, format!("unable to start test: {}", e));
+                    return Err(UnitActivateError::SecretFailed(e));
+                }
+            };
+        let cmd = if self.description.tty {
+            Self::tty_command(&secrets.text)
+        } else {
+            secrets.text.clone()
+        };
+        let fetched = match self.fetch_files(config) {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                Self::report_start_failure(&id, &ctrl, format!("unable to start test: {}", e));
+                return Err(UnitActivateError::FetchFailed(e));
+            }
+        };
+        // The store is on this machine, so tests run on an agent can't use it.
+        let store_path = match self.description.agent {
+            Some(_) => None,
+            None => self
+                .store_path(&ctrl, config)
+                .map(|path| path.to_string_lossy().into_owned()),
+        };
+        let allocations = self.allocations.borrow();
+        let parameters = self.parameters.borrow();
+        let vars: Vec<(&str, &str)> = store_path
+            .iter()
+            .map(|path| ("EXCLAVE_STORE", path.as_str()))
+            .chain(
+                allocations
+                    .iter()
+                    .chain(parameters.iter())
+                    .chain(fetched.iter())
+                    .map(|(name, value)| (name.as_str(), value.as_str())),
+            )
+            .collect();
+        let directory = config.working_directory(
+            &self.description.unit_directory,
+            &self.description.working_directory,
+        );
+        // Containers are given the variables themselves, along with the
+        // files they name.
+        let cmd = match self.description.container {
+            Some(ref container) => {
+                let runtime = config.container_runtime().to_owned();
+                let name = container::container_name(&id);
+                let files: Vec<&Path> = store_path
+                    .iter()
+                    .chain(fetched.iter().map(|(_, path)| path))
+                    .map(Path::new)
+                    .collect();
+                let cmd = container.command(&runtime, &name, &directory, &files, &vars, &cmd);
+                *self.container_run.borrow_mut() = Some((runtime, name));
+                cmd
+            }
+            None if vars.is_empty() => cmd,
+            None => Self::env_command(&vars, &cmd),
+        };
+        if let Some(ref agent) = self.description.agent {
+            return self.activate_remote(agent, cmd, &ctrl, config, secrets);
+        }
         let timeout = &self.description.timeout;
 
-        let mut cmd = Runny::new(cmd);
+        let mut cmd = Runny::new(&cmd);
         if let Some(timeout) = *timeout {
             cmd.timeout(timeout);
         }
-        cmd.directory(&Some(config.working_directory(
-            &self.description.unit_directory,
-            &self.description.working_directory,
-        )));
-        let mut running = match cmd.start() {
+        cmd.directory(&Some(directory));
+        let start = || chaos::spawn(&id).and_then(|()| cmd.start());
+        let started = match *self.cost_meter.borrow_mut() {
+            Some(ref mut meter) => meter.spawn(start, Running::pid),
+            None => start(),
+        };
+        let mut running = match started {
             Ok(r) => r,
             Err(e) => {
-                ctrl.send(ManagerControlMessage::new(
-                    &id,
-                    ManagerControlMessageContents::LogError(format!(
-                        "unable to start test: {:?}",
-                        e
-                    )),
-                ))
-                .unwrap();
-                ctrl.send(ManagerControlMessage::new(
-                    &id,
-                    ManagerControlMessageContents::TestFinished(
-                        -3,
-                        format!("unable to start test: {:?}", e),
-                    ),
-                ))
-                .ok();
-                ctrl.send(ManagerControlMessage::new(
-                    &id,
-                    ManagerControlMessageContents::AdvanceScenario(-3),
-                ))
-                .ok();
+                Self::report_start_failure(&id, &ctrl, format!("unable to start test: {:?}", e));
                 return Err(UnitActivateError::ExecFailed(e));
             }
         };
+        init::spawned(running.pid());
+        profile::test_running(&id);
 
         // Keep track of the last line, which we can use to report test status.
         let last_line = self.last_line.clone();
+        let output = self.capture_output(&ctrl, config, secrets);
 
         let waiter = running.waiter();
+        self.pid.set(running.pid());
+        self.feed_input(running.take_input(), &ctrl, config);
         let thr_control = ctrl.clone();
         let thr_last_line = self.last_line.clone();
         let thr_result_arc = self.result_arc.clone();
         match self.description.test_type {
             TestType::Daemon => {
                 let daemon_ready_string = self.description.test_daemon_ready.clone();
+                // A daemon that's being restarted has already advanced the scenario.
+                let advance = !self.respawning.get();
+                let supervised = self.description.restart.policy != RestartPolicy::No;
 
                 thread::spawn(move || {
-                    Self::log_error(&id, &ctrl, running.take_error(), &last_line);
+                    Self::log_error(&id, &ctrl, running.take_error(), &last_line, &output);
                     let buf_reader = BufReader::new(running.take_output());
-                    let buf_lines = buf_reader.lines();
+                    let buf_lines = buf_reader.split(b'\n');
                     let mut buf_iter = buf_lines;
                     if let Some(ref r) = daemon_ready_string {
                         let mut found = false;
@@ -428,14 +1915,16 @@ impl Test {
                                             )),
                                         ))
                                         .unwrap();
-                                    thr_control
-                                        .send(ManagerControlMessage::new(
-                                            &id,
-                                            ManagerControlMessageContents::AdvanceScenario(-2),
-                                        ))
-                                        .ok();
+                                    if advance {
+                                        thr_control
+                                            .send(ManagerControlMessage::new(
+                                                &id,
+                                                ManagerControlMessageContents::AdvanceScenario(-2),
+                                            ))
+                                            .ok();
+                                    }
                                     running.terminate(Some(Duration::from_secs(1))).ok();
-                                    // thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::TestFinished(-2, thr_last_line.lock().unwrap().clone()))).ok();
+                                    // thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::TestFinished(-2, thr_last_line.locked().clone()))).ok();
                                     Self::send_finished_once(
                                         &id,
                                         &thr_control,
@@ -445,7 +1934,8 @@ impl Test {
                                     );
                                     return;
                                 }
-                                Ok(line) => {
+                                Ok(raw) => {
+                                    let line = output.capture(&raw);
                                     thr_control
                                         .send(ManagerControlMessage::new(
                                             &id,
@@ -469,12 +1959,14 @@ impl Test {
                                     ),
                                 ))
                                 .unwrap();
-                            thr_control
-                                .send(ManagerControlMessage::new(
-                                    &id,
-                                    ManagerControlMessageContents::AdvanceScenario(-1),
-                                ))
-                                .ok();
+                            if advance {
+                                thr_control
+                                    .send(ManagerControlMessage::new(
+                                        &id,
+                                        ManagerControlMessageContents::AdvanceScenario(-1),
+                                    ))
+                                    .ok();
+                            }
                             running.terminate(Some(Duration::from_secs(1))).ok();
                             Self::send_finished_once(
                                 &id,
@@ -483,7 +1975,7 @@ impl Test {
                                 &thr_result_arc,
                                 &thr_last_line,
                             );
-                            //                            thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::TestFinished(-1, thr_last_line.lock().unwrap().clone()))).ok();
+                            //                            thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::TestFinished(-1, thr_last_line.locked().clone()))).ok();
                             return;
                         }
                     }
@@ -491,10 +1983,11 @@ impl Test {
                     let thr_thr_control = thr_control.clone();
                     let thr_thr_last_line = last_line.clone();
                     let thr_id = id.clone();
+                    let thr_output = output.clone();
                     thread::spawn(move || {
                         for line in buf_iter {
-                            let line = line.expect("Unable to get next line");
-                            *thr_thr_last_line.lock().unwrap() = line.clone();
+                            let line = thr_output.capture(&line.expect("Unable to get next line"));
+                            *thr_thr_last_line.locked() = line.clone();
                             if thr_thr_control
                                 .send(ManagerControlMessage::new(
                                     &thr_id,
@@ -508,28 +2001,45 @@ impl Test {
                     });
 
                     // Advance to the next test while this one hangs out.
-                    thr_control
-                        .send(ManagerControlMessage::new(
-                            &id,
-                            ManagerControlMessageContents::AdvanceScenario(0),
-                        ))
-                        .ok();
+                    if advance {
+                        thr_control
+                            .send(ManagerControlMessage::new(
+                                &id,
+                                ManagerControlMessageContents::AdvanceScenario(0),
+                            ))
+                            .ok();
+                    }
                     running.wait().ok();
-                    Self::send_finished_once(
-                        &id,
-                        &thr_control,
-                        running.result(),
-                        &thr_result_arc,
-                        &thr_last_line,
-                    );
+                    // Unless it was stopped, the manager decides whether one
+                    // that may be restarted has finished.
+                    if supervised && thr_result_arc.locked().is_none() {
+                        thr_control
+                            .send(ManagerControlMessage::new(
+                                &id,
+                                ManagerControlMessageContents::DaemonExited(running.result()),
+                            ))
+                            .ok();
+                    } else {
+                        Self::send_finished_once(
+                            &id,
+                            &thr_control,
+                            running.result(),
+                            &thr_result_arc,
+                            &thr_last_line,
+                        );
+                    }
                 });
             }
             TestType::Simple => {
                 // Keep a waiter around in a separate thread to send that AdvanceScenario message upon completion.
-                Self::log_output(&id, &ctrl, running.take_output(), &last_line);
-                Self::log_error(&id, &ctrl, running.take_error(), &last_line);
+                let output_done =
+                    Self::log_output(&id, &ctrl, running.take_output(), &last_line, &output);
+                Self::log_error(&id, &ctrl, running.take_error(), &last_line, &output);
                 thread::spawn(move || {
                     running.wait().ok();
+                    // Let the last few lines of output get logged before announcing
+                    // the result, but don't hang if a child is holding stdout open.
+                    output_done.recv_timeout(OUTPUT_DRAIN_TIMEOUT).ok();
                     Self::send_finished_once(
                         &id,
                         &thr_control,
@@ -546,13 +2056,18 @@ impl Test {
                 });
             }
         }
-        *self.program.borrow_mut() = Some(waiter);
+        *self.program.borrow_mut() = Some(Arc::new(waiter));
 
         Ok(())
     }
 
     pub fn deactivate(&self, manager: &UnitManager) -> Result<(), UnitDeactivateError> {
-        if let Some(ref running) = *self.program.borrow_mut() {
+        self.stop_waiting();
+        self.release_slot();
+        if let Some(stopper) = self.remote.borrow_mut().take() {
+            agent::stop(&stopper);
+        }
+        if let Some(running) = self.program.borrow_mut().take() {
             // For Daemons, if they haven't failed so far, then they might fail when we tell them
             // to quit.  Since they've fulfilled their purpose, issue a "pass" message.
             if self.description.test_type == TestType::Daemon {
@@ -564,21 +2079,173 @@ impl Test {
                     &self.last_line,
                 );
             }
-            running.terminate(&None);
+            let finished = self.result_arc.locked().is_some();
+            let container_run = self.container_run.borrow_mut().take();
+            self.stop(running.clone());
+            // Stopping the runtime doesn't always stop the container, so it's
+            // removed once the runtime has gone.
+            if let (false, Some((runtime, name))) = (finished, container_run) {
+                thread::spawn(move || {
+                    running.wait();
+                    container::remove(&runtime, &name);
+                });
+            }
         }
         Ok(())
     }
 
+    /// Write StdinData and StdinFile to the test's stdin, then keep it for
+    /// operator input.  Writing happens on its own thread, since a program
+    /// that doesn't read its input would otherwise block exclave.
+    fn feed_input(
+        &self,
+        mut input: RunningInput,
+        ctrl: &Sender<ManagerControlMessage>,
+        config: &Config,
+    ) {
+        let mut data = self
+            .description
+            .stdin_data
+            .clone()
+            .unwrap_or_default()
+            .into_bytes();
+        if let Some(ref file) = self.description.stdin_file {
+            let path = config
+                .working_directory(
+                    &self.description.unit_directory,
+                    &self.description.working_directory,
+                )
+                .join(file);
+            match fs::read(&path) {
+                Ok(contents) => data.extend(contents),
+                Err(e) => {
+                    ctrl.send(ManagerControlMessage::new(
+                        self.id(),
+                        ManagerControlMessageContents::LogError(format!(
+                            "unable to read StdinFile {}: {}",
+                            path.display(),
+                            e
+                        )),
+                    ))
+                    .ok();
+                }
+            }
+        }
+
+        let shared = self.input.clone();
+        let mut slot = shared.locked();
+        *slot = None;
+        if data.is_empty() {
+            *slot = Some(input);
+            return;
+        }
+        drop(slot);
+        thread::spawn(move || {
+            input.write_all(&data).and_then(|_| input.flush()).ok();
+            *shared.locked() = Some(input);
+        });
+    }
+
+    /// Send a line of operator input to the running test.
+    pub fn send_input(&self, text: &str) -> io::Result<()> {
+        match *self.input.locked() {
+            Some(ref mut input) => {
+                input.write_all(format!("{}\n", text).as_bytes())?;
+                input.flush()
+            }
+            None => Err(io::Error::other("test is not accepting input")),
+        }
+    }
+
+    /// Stop the program by sending KillSignal to its process group, then
+    /// SIGKILL if it's still running after TimeoutStopSec (unless SendSIGKILL
+    /// is turned off).
+    fn stop(&self, running: Arc<RunningWaiter>) {
+        let desc = &self.description;
+        // Runny sends SIGTERM itself, followed by SIGKILL after a timeout,
+        // so use that where possible (and where signals aren't supported).
+        let signalled = desc.kill_signal != KillSignal::Terminate
+            && sys::signal_process_group(self.pid.get(), desc.kill_signal).is_ok();
+        if !signalled {
+            let sent = !desc.send_sigkill
+                && sys::signal_process_group(self.pid.get(), KillSignal::Terminate).is_ok();
+            if !sent {
+                running.terminate(&Some(desc.timeout_stop));
+            }
+            return;
+        }
+        if !desc.send_sigkill {
+            return;
+        }
+
+        let timeout = desc.timeout_stop;
+        thread::spawn(move || {
+            let (sender, receiver) = channel();
+            let exit_waiter = running.clone();
+            thread::spawn(move || {
+                exit_waiter.wait();
+                sender.send(()).ok();
+            });
+            if receiver.recv_timeout(timeout).is_err() {
+                running.terminate(&None);
+            }
+        });
+    }
+
     /// is_daemon() can be used to determine if a test should be stopped
     /// now, or when the scenario is finished.
     pub fn is_daemon(&self) -> bool {
         self.description.test_type == TestType::Daemon
     }
 
+    /// How a daemon is restarted if it exits on its own.  Simple tests are
+    /// never restarted.
+    pub fn restart(&self) -> Restart {
+        match self.description.test_type {
+            TestType::Daemon => self.description.restart.clone(),
+            TestType::Simple => Restart::default(),
+        }
+    }
+
+    /// Start a daemon that exited on its own again, in the same run.  It
+    /// doesn't advance the scenario, which has already moved on.
+    pub fn respawn(
+        &mut self,
+        manager: &UnitManager,
+        config: &Config,
+    ) -> Result<(), UnitActivateError> {
+        self.respawning.set(true);
+        let result = self.activate(manager, config);
+        self.respawning.set(false);
+        result
+    }
+
+    /// Report that the most recent run finished, unless that's already been
+    /// reported.
+    pub fn report_finished(&self, ctrl: &Sender<ManagerControlMessage>, result: i32) {
+        Self::send_finished_once(
+            &self.description.id,
+            ctrl,
+            result,
+            &self.result_arc,
+            &self.last_line,
+        );
+    }
+
     pub fn id(&self) -> &UnitName {
         &self.description.id
     }
 
+    /// The jigs this unit is compatible with.  If empty, it works with any jig.
+    pub fn jigs(&self) -> &Vec<UnitName> {
+        &self.description.jigs
+    }
+
+    /// The features a jig must have for this test to run on it.
+    pub fn requires_features(&self) -> &Vec<String> {
+        &self.description.requires_features
+    }
+
     pub fn name(&self) -> &String {
         &self.description.name
     }
@@ -587,6 +2254,11 @@ impl Test {
         &self.description.description
     }
 
+    /// How operator GUIs should show the unit.
+    pub fn presentation(&self) -> &Presentation {
+        &self.description.presentation
+    }
+
     pub fn timeout(&self) -> &Option<Duration> {
         &self.description.timeout
     }
@@ -596,14 +2268,20 @@ impl Test {
         control: &Sender<ManagerControlMessage>,
         stdout: RunningOutput,
         last_line: &Arc<Mutex<String>>,
-    ) {
+        output: &OutputCapture,
+    ) -> Receiver<()> {
         let thr_control = control.clone();
         let thr_last_line = last_line.clone();
         let thr_id = id.clone();
+        let thr_output = output.clone();
+        // The sender is dropped when the thread exits, which disconnects the receiver.
+        let (done_sender, done_receiver) = channel::<()>();
         thread::spawn(move || {
-            for line in BufReader::new(stdout).lines() {
-                let line = line.expect("Unable to get next line");
-                *thr_last_line.lock().unwrap() = line.clone();
+            let _done_sender = done_sender;
+            for line in BufReader::new(stdout).split(b'\n') {
+                let line = thr_output.capture(&line.expect("Unable to get next line"));
+                let line = chaos::truncate(line);
+                *thr_last_line.locked() = line.clone();
                 if thr_control
                     .send(ManagerControlMessage::new(
                         &thr_id,
@@ -615,6 +2293,7 @@ impl Test {
                 }
             }
         });
+        done_receiver
     }
 
     fn log_error(
@@ -622,14 +2301,16 @@ impl Test {
         control: &Sender<ManagerControlMessage>,
         stderr: RunningOutput,
         last_line: &Arc<Mutex<String>>,
+        output: &OutputCapture,
     ) {
         let thr_control = control.clone();
         let thr_last_line = last_line.clone();
         let thr_id = id.clone();
+        let thr_output = output.clone();
         thread::spawn(move || {
-            for line in BufReader::new(stderr).lines() {
-                let line = line.expect("Unable to get next line");
-                *thr_last_line.lock().unwrap() = line.clone();
+            for line in BufReader::new(stderr).split(b'\n') {
+                let line = thr_output.capture(&line.expect("Unable to get next line"));
+                *thr_last_line.locked() = line.clone();
                 if thr_control
                     .send(ManagerControlMessage::new(
                         &thr_id,
@@ -655,6 +2336,6 @@ impl Dependency<UnitName> for Test {
         &self.description.suggests
     }
     fn provides(&self) -> &Vec<UnitName> {
-        &self.description.provides
+        &self.provides
     }
 }
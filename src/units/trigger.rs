@@ -5,26 +5,115 @@ use std::cell::RefCell;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
+use argv;
+use chaos;
 use config::Config;
+use duration;
+use evdev::{self, Device, Keys};
+use init;
+use interlock::{RequiredState, DEFAULT_WINDOW};
+use schema::{DirectiveSchema, UnitSchema, ValueType};
+use secrets::Resolved;
+use specifier;
+use supervisor::{Restart, RestartPolicy};
 use unit::{
-    UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
-    UnitIncompatibleReason, UnitName, UnitSelectError,
+    parse_bool, UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
+    UnitIncompatibleReason, UnitKind, UnitName, UnitSelectError,
 };
+use unitfile;
 use unitmanager::{ManagerControlMessage, ManagerControlMessageContents, UnitManager};
 
 use self::runny::running::{Running, RunningOutput};
 use self::runny::Runny;
 use self::systemd_parser::items::DirectiveEntry;
 
+/// What comes before the payload on a START line.
+const PAYLOAD_PREFIX: &str = "payload=";
+
 #[derive(Clone, Copy)]
 enum TriggerFormat {
     Text,
     Json,
 }
 
+/// What a trigger does when it fires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TriggerAction {
+    /// Start the current scenario, or the one it names.
+    Start,
+
+    /// Abort the running scenario, as for an emergency stop button.
+    Abort,
+}
+
+/// Where a START from a trigger leads.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Route {
+    /// Start a scenario, or the current one if None.
+    Start(Option<UnitName>),
+
+    /// Abort the running scenario.
+    Abort,
+
+    /// Run the tests that failed last time again, as with RERUN FAILED.
+    RerunFailed,
+}
+
+/// One entry in a trigger's mapping table, from a Map directive such as
+/// "Map=A* start board-a".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapEntry {
+    /// The input, such as a scanned barcode, this entry is for.  If it ends
+    /// in "*", it's a prefix that inputs start with.
+    input: String,
+
+    /// What to do with inputs that match.
+    route: Route,
+}
+
+impl MapEntry {
+    pub fn parse(value: &str) -> Result<MapEntry, UnitDescriptionError> {
+        let invalid =
+            || {
+                UnitDescriptionError::InvalidValue(
+                "Trigger".to_owned(),
+                "Map".to_owned(),
+                value.to_owned(),
+                vec!["an input, then \"start\" and an optional scenario, \"abort\", or \"rerun\""
+                    .to_owned()],
+            )
+            };
+        let words: Vec<&str> = value.split_whitespace().collect();
+        let route = match words.get(1..) {
+            Some(["start"]) => Route::Start(None),
+            Some(["start", scenario]) => Route::Start(Some(
+                UnitName::from_str(scenario, "scenario").map_err(|_| invalid())?,
+            )),
+            Some(["abort"]) => Route::Abort,
+            Some(["rerun"]) => Route::RerunFailed,
+            _ => return Err(invalid()),
+        };
+        Ok(MapEntry {
+            input: words[0].to_owned(),
+            route,
+        })
+    }
+
+    /// Whether an input is one this entry is for.
+    pub fn matches(&self, input: &str) -> bool {
+        match self.input.strip_suffix('*') {
+            Some(prefix) => input.starts_with(prefix),
+            None => input == self.input,
+        }
+    }
+}
+
 /// A struct defining an in-memory representation of a .Trigger file
 #[derive(Clone)]
 pub struct TriggerDescription {
@@ -51,16 +140,73 @@ pub struct TriggerDescription {
 
     /// The path to the unit file
     unit_directory: PathBuf,
+
+    /// True if this unit was disabled with a Disabled= directive
+    disabled: bool,
+
+    /// What to do if it exits without being stopped
+    restart: Restart,
+
+    /// Run ExecStart with the shell, rather than splitting it into arguments
+    shell: bool,
+
+    /// Triggers that must all send START within the window for this one to
+    /// start a run
+    all_of: Vec<UnitName>,
+
+    /// How long every trigger in all_of has to send START in
+    window: Duration,
+
+    /// States that other triggers must have reported for this one to start a run
+    required_states: Vec<RequiredState>,
+
+    /// An input device to read key presses from, instead of running ExecStart
+    device: Option<Device>,
+
+    /// The key on the device that starts the current scenario
+    start_key: Option<u16>,
+
+    /// The key on the device that aborts the running tests
+    abort_key: Option<u16>,
+
+    /// Whether START from this trigger starts or aborts a run
+    action: TriggerAction,
+
+    /// Where START leads, by its payload, in the order they were given
+    map: Vec<MapEntry>,
 }
 
 impl TriggerDescription {
-    pub fn from_path(path: &Path) -> Result<TriggerDescription, UnitDescriptionError> {
-        let unit_name = UnitName::from_path(path)?;
+    pub fn from_path(
+        unit_name: &UnitName,
+        path: &Path,
+    ) -> Result<TriggerDescription, UnitDescriptionError> {
+        let unit_name = unit_name.clone();
 
         // Parse the file into a systemd unit_file object
         let mut contents = String::with_capacity(8192);
         File::open(path)?.read_to_string(&mut contents)?;
-        let unit_file = systemd_parser::parse_string(&contents)?;
+        let parsed = unitfile::parse_to_keep(&contents, &[])?;
+        Self::from_parsed(&parsed, unit_name, path)
+    }
+
+    /// Describe a trigger from the contents of a unit file.
+    pub fn from_string(
+        contents: &str,
+        unit_name: UnitName,
+        path: &Path,
+    ) -> Result<TriggerDescription, UnitDescriptionError> {
+        let parsed = unitfile::parse_to_keep(contents, &[])?;
+        Self::from_parsed(&parsed, unit_name, path)
+    }
+
+    /// Describe a trigger from a file that has already been parsed.
+    pub fn from_parsed(
+        parsed: &unitfile::ParsedFile,
+        unit_name: UnitName,
+        path: &Path,
+    ) -> Result<TriggerDescription, UnitDescriptionError> {
+        let unit_file = parsed.unit()?;
 
         if !unit_file.has_category("Trigger") {
             return Err(UnitDescriptionError::MissingSection("Trigger".to_owned()));
@@ -75,10 +221,26 @@ impl TriggerDescription {
             exec_start: "".to_owned(),
             working_directory: None,
             unit_directory: path.parent().unwrap().to_owned(),
+            disabled: false,
+            restart: Restart::default(),
+            shell: false,
+            all_of: vec![],
+            window: DEFAULT_WINDOW,
+            required_states: vec![],
+            device: None,
+            start_key: None,
+            abort_key: None,
+            action: TriggerAction::Start,
+            map: vec![],
         };
 
         for entry in unit_file.lookup_by_category("Trigger") {
-            if let DirectiveEntry::Solo(ref directive) = entry {
+            // Map and RequireState may be given more than once.
+            let directives = match entry {
+                DirectiveEntry::Solo(directive) => vec![directive],
+                DirectiveEntry::Many(directives) => directives.iter().collect(),
+            };
+            for directive in directives {
                 match directive.key() {
                     "Name" => {
                         interface_description.name = directive.value().unwrap_or("").to_owned()
@@ -126,13 +288,173 @@ impl TriggerDescription {
                             },
                         }
                     }
+                    "Action" => {
+                        interface_description.action = match directive.value() {
+                            None => TriggerAction::Start,
+                            Some(s) => match s.to_lowercase().as_ref() {
+                                "start" => TriggerAction::Start,
+                                "abort" => TriggerAction::Abort,
+                                other => {
+                                    return Err(UnitDescriptionError::InvalidValue(
+                                        "Trigger".to_owned(),
+                                        "Action".to_owned(),
+                                        other.to_owned(),
+                                        vec!["start".to_owned(), "abort".to_owned()],
+                                    ))
+                                }
+                            },
+                        }
+                    }
+                    "Disabled" => {
+                        interface_description.disabled =
+                            parse_bool("Trigger", "Disabled", directive.value())?
+                    }
+                    "Restart" | "RestartSec" | "StartLimitBurst" => {
+                        interface_description.restart.set(
+                            "Trigger",
+                            directive.key(),
+                            directive.value(),
+                        )?
+                    }
+                    "Shell" => {
+                        interface_description.shell =
+                            parse_bool("Trigger", "Shell", directive.value())?
+                    }
+                    "AllOf" => {
+                        interface_description.all_of = match directive.value() {
+                            Some(s) => UnitName::from_list(s, "trigger")?,
+                            None => vec![],
+                        }
+                    }
+                    "Window" => {
+                        interface_description.window = match directive.value() {
+                            Some(s) => duration::parse(s)?,
+                            None => DEFAULT_WINDOW,
+                        }
+                    }
+                    "Map" => {
+                        if let Some(s) = directive.value() {
+                            interface_description.map.push(MapEntry::parse(s)?);
+                        }
+                    }
+                    "RequireState" => {
+                        if let Some(s) = directive.value() {
+                            interface_description
+                                .required_states
+                                .push(RequiredState::parse(s)?);
+                        }
+                    }
+                    "Device" => {
+                        interface_description.device = directive.value().and_then(Device::parse)
+                    }
+                    "StartKey" | "AbortKey" => {
+                        let key = match directive.value().map(|s| (s, evdev::key_code(s))) {
+                            Some((_, Some(code))) => code,
+                            Some((s, None)) => {
+                                return Err(UnitDescriptionError::InvalidValue(
+                                    "Trigger".to_owned(),
+                                    directive.key().to_owned(),
+                                    s.to_owned(),
+                                    vec!["a key's name, such as KEY_B or BTN_0, or its code"
+                                        .to_owned()],
+                                ))
+                            }
+                            None => {
+                                return Err(UnitDescriptionError::MissingValue(
+                                    "Trigger".to_owned(),
+                                    directive.key().to_owned(),
+                                ))
+                            }
+                        };
+                        if directive.key() == "StartKey" {
+                            interface_description.start_key = Some(key);
+                        } else {
+                            interface_description.abort_key = Some(key);
+                        }
+                    }
                     &_ => (),
                 }
             }
         }
+        if interface_description
+            .all_of
+            .contains(&interface_description.id)
+        {
+            return Err(UnitDescriptionError::InvalidValue(
+                "Trigger".to_owned(),
+                "AllOf".to_owned(),
+                interface_description.id.to_string(),
+                vec!["triggers other than this one".to_owned()],
+            ));
+        }
+        // Triggers that read a device, or are made of others, needn't run anything.
+        if interface_description.device.is_some() {
+            if !interface_description.exec_start.is_empty() {
+                return Err(UnitDescriptionError::InvalidValue(
+                    "Trigger".to_owned(),
+                    "ExecStart".to_owned(),
+                    interface_description.exec_start,
+                    vec!["nothing, when Device is given".to_owned()],
+                ));
+            }
+            if interface_description.start_key.is_none()
+                && interface_description.abort_key.is_none()
+            {
+                return Err(UnitDescriptionError::MissingValue(
+                    "Trigger".to_owned(),
+                    "StartKey".to_owned(),
+                ));
+            }
+            return Ok(interface_description);
+        }
+        if interface_description.exec_start.is_empty() && !interface_description.all_of.is_empty() {
+            return Ok(interface_description);
+        }
+        if !interface_description.shell {
+            argv::check("Trigger", "ExecStart", &interface_description.exec_start)?;
+        }
+        specifier::check("Trigger", "ExecStart", &interface_description.exec_start)?;
         Ok(interface_description)
     }
 
+    /// The directives that [Trigger] sections may contain.
+    pub fn schema() -> UnitSchema {
+        UnitSchema {
+            kind: UnitKind::Trigger,
+            section: "Trigger",
+            directives: vec![
+                DirectiveSchema::new("Name", ValueType::Text),
+                DirectiveSchema::new("Description", ValueType::Text),
+                DirectiveSchema::new("Jigs", ValueType::Units(UnitKind::Jig)),
+                DirectiveSchema::new("WorkingDirectory", ValueType::Path),
+                DirectiveSchema::new("ExecStart", ValueType::Command),
+                DirectiveSchema::new(
+                    "Format",
+                    ValueType::Choice(vec!["text".to_owned(), "json".to_owned()]),
+                )
+                .with_default("text"),
+                DirectiveSchema::new("Disabled", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("Restart", ValueType::Choice(RestartPolicy::choices()))
+                    .with_default("no"),
+                DirectiveSchema::new("RestartSec", ValueType::Duration).with_default("1s"),
+                DirectiveSchema::new("StartLimitBurst", ValueType::Integer).with_default("5"),
+                DirectiveSchema::new("Shell", ValueType::Bool).with_default("no"),
+                DirectiveSchema::new("AllOf", ValueType::Units(UnitKind::Trigger)),
+                DirectiveSchema::new("Window", ValueType::Duration).with_default("500ms"),
+                DirectiveSchema::new("RequireState", ValueType::Text),
+                DirectiveSchema::new("Device", ValueType::Text),
+                DirectiveSchema::new("StartKey", ValueType::Text),
+                DirectiveSchema::new("AbortKey", ValueType::Text),
+                DirectiveSchema::new(
+                    "Action",
+                    ValueType::Choice(vec!["start".to_owned(), "abort".to_owned()]),
+                )
+                .with_default("start"),
+                DirectiveSchema::new("Map", ValueType::Text),
+            ],
+        }
+    }
+
     /// Returns true if this test is supported on the named jig.
     pub fn supports_jig(&self, name: &UnitName) -> bool {
         self.jigs.contains(name)
@@ -159,6 +481,11 @@ impl TriggerDescription {
         &self.id
     }
 
+    /// Returns true if this unit was disabled from within its unit file.
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
     pub fn load(
         &self,
         manager: &UnitManager,
@@ -173,6 +500,9 @@ impl TriggerDescription {
 pub struct Trigger {
     description: TriggerDescription,
     process: RefCell<Option<Running>>,
+
+    /// Set to tell the thread reading the Device, if any, to stop.
+    stop_reading: RefCell<Option<Arc<AtomicBool>>>,
 }
 
 impl Trigger {
@@ -180,6 +510,7 @@ impl Trigger {
         Trigger {
             description: desc.clone(),
             process: RefCell::new(None),
+            stop_reading: RefCell::new(None),
         }
     }
 
@@ -187,6 +518,61 @@ impl Trigger {
         &self.description.id
     }
 
+    pub fn name(&self) -> &String {
+        &self.description.name
+    }
+
+    pub fn description(&self) -> &String {
+        &self.description.description
+    }
+
+    /// The jigs this unit is compatible with.  If empty, it works with any jig.
+    pub fn jigs(&self) -> &Vec<UnitName> {
+        &self.description.jigs
+    }
+
+    /// What to do if the unit exits without being stopped.
+    pub fn restart(&self) -> &Restart {
+        &self.description.restart
+    }
+
+    /// The triggers that must all send START for this one to start a run.
+    pub fn all_of(&self) -> &[UnitName] {
+        &self.description.all_of
+    }
+
+    /// How long every trigger in all_of() has to send START in.
+    pub fn window(&self) -> Duration {
+        self.description.window
+    }
+
+    /// The states other triggers must be in for this one to start a run.
+    pub fn required_states(&self) -> &[RequiredState] {
+        &self.description.required_states
+    }
+
+    /// Where a START from this trigger leads: to the first Map entry that
+    /// matches its payload, if there are any, or else to its Action.
+    pub fn route(
+        &self,
+        scenario: &Option<UnitName>,
+        payload: &Option<String>,
+    ) -> Result<Route, String> {
+        if self.description.map.is_empty() {
+            return Ok(match self.description.action {
+                TriggerAction::Start => Route::Start(scenario.clone()),
+                TriggerAction::Abort => Route::Abort,
+            });
+        }
+        let input = payload.as_deref().unwrap_or("");
+        self.description
+            .map
+            .iter()
+            .find(|entry| entry.matches(input))
+            .map(|entry| entry.route.clone())
+            .ok_or_else(|| format!("nothing in Map matches \"{}\"", input))
+    }
+
     pub fn select(&self) -> Result<(), UnitSelectError> {
         Ok(())
     }
@@ -200,12 +586,26 @@ impl Trigger {
         manager: &UnitManager,
         config: &Config,
     ) -> Result<(), UnitActivateError> {
-        let mut running = Runny::new(self.description.exec_start.as_str())
+        if let Some(ref device) = self.description.device {
+            return self.read_device(device, manager);
+        }
+        // Triggers made only of others have nothing to run.
+        if self.description.exec_start.is_empty() {
+            return Ok(());
+        }
+        let secrets = config.resolve_command(
+            self.id(),
+            &self.description.exec_start,
+            self.description.shell,
+        )?;
+        chaos::spawn(self.id())?;
+        let mut running = Runny::new(&secrets.text)
             .directory(&Some(config.working_directory(
                 &self.description.unit_directory,
                 &self.description.working_directory,
             )))
             .start()?;
+        init::spawned(running.pid());
 
         let stdout = running.take_output();
         let stderr = running.take_error();
@@ -221,7 +621,9 @@ impl Trigger {
                 thread::spawn(move || Self::text_read(thr_sender_id, thr_sender, stdout));
                 let thr_sender_id = control_sender_id.clone();
                 let thr_sender = control_sender.clone();
-                thread::spawn(move || Self::text_read_stderr(thr_sender_id, thr_sender, stderr));
+                thread::spawn(move || {
+                    Self::text_read_stderr(thr_sender_id, thr_sender, stderr, secrets)
+                });
             }
             TriggerFormat::Json => {}
         };
@@ -239,7 +641,67 @@ impl Trigger {
         Ok(())
     }
 
+    /// Start a thread that turns presses of the StartKey and AbortKey on the
+    /// Device into START and ABORT.
+    fn read_device(&self, device: &Device, manager: &UnitManager) -> Result<(), UnitActivateError> {
+        let mut keys = Keys::open(device).map_err(|e| {
+            UnitActivateError::DeviceFailed(format!("unable to read {}: {}", device, e))
+        })?;
+        let stop = Arc::new(AtomicBool::new(false));
+        *self.stop_reading.borrow_mut() = Some(stop.clone());
+        let id = self.id().clone();
+        let control = manager.get_control_channel();
+        let start_key = self.description.start_key;
+        let abort_key = self.description.abort_key;
+        thread::spawn(move || {
+            let error = loop {
+                let code = match keys.next_press() {
+                    Ok(code) => code,
+                    Err(e) => break e,
+                };
+                // The thread can't be woken while it waits for a key, so it
+                // only notices it was stopped once the next one comes.
+                if stop.load(Ordering::SeqCst) {
+                    return;
+                }
+                let msg = if Some(code) == start_key {
+                    ManagerControlMessageContents::StartScenario(None, vec![], None)
+                } else if Some(code) == abort_key {
+                    ManagerControlMessageContents::AbortTests
+                } else {
+                    continue;
+                };
+                if control.send(ManagerControlMessage::new(&id, msg)).is_err() {
+                    return;
+                }
+            };
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+            // The device was unplugged, or otherwise went away.
+            control
+                .send(ManagerControlMessage::new(
+                    &id,
+                    ManagerControlMessageContents::LogError(format!(
+                        "unable to read device: {}",
+                        error
+                    )),
+                ))
+                .ok();
+            control
+                .send(ManagerControlMessage::new(
+                    &id,
+                    ManagerControlMessageContents::ChildExited,
+                ))
+                .ok();
+        });
+        Ok(())
+    }
+
     pub fn deactivate(&self) -> Result<(), UnitDeactivateError> {
+        if let Some(stop) = self.stop_reading.borrow_mut().take() {
+            stop.store(true, Ordering::SeqCst);
+        }
         if let Some(process) = self.process.borrow_mut().take() {
             match process.terminate(None) {
                 Ok(retval) => match retval {
@@ -264,6 +726,7 @@ impl Trigger {
         id: UnitName,
         control: Sender<ManagerControlMessage>,
         output: RunningOutput,
+        secrets: Resolved,
     ) {
         for line in BufReader::new(output).lines() {
             let line = line.expect("Unable to get next line");
@@ -271,7 +734,7 @@ impl Trigger {
             if control
                 .send(ManagerControlMessage::new(
                     &id,
-                    ManagerControlMessageContents::LogError(line),
+                    ManagerControlMessageContents::LogError(secrets.mask(&line)),
                 ))
                 .is_err()
             {
@@ -280,6 +743,25 @@ impl Trigger {
         }
     }
 
+    /// Pick out the scenario and payload from a line such as
+    /// "START board payload=012345678905".  Either may be left out, and the
+    /// payload is the rest of the line, spaces and all.
+    pub fn parse_start(line: &str) -> (Option<&str>, Option<String>) {
+        // Skip the verb.
+        let rest = line.trim_start();
+        let rest = rest[rest.find(char::is_whitespace).unwrap_or(rest.len())..].trim_start();
+        let (scenario, rest) = if rest.is_empty() || rest.starts_with(PAYLOAD_PREFIX) {
+            (None, rest)
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            (Some(&rest[..end]), rest[end..].trim_start())
+        };
+        let payload = rest
+            .strip_prefix(PAYLOAD_PREFIX)
+            .map(|payload| Self::cfti_unescape(payload.to_owned()));
+        (scenario, payload)
+    }
+
     fn text_read(id: UnitName, control: Sender<ManagerControlMessage>, stdout: RunningOutput) {
         for line in BufReader::new(stdout).lines() {
             let line = line.expect("Unable to get next line");
@@ -290,29 +772,33 @@ impl Trigger {
 
             // Don't crash if we get a blank line.
             let msg = if words.is_empty() {
-                ManagerControlMessageContents::StartScenario(None)
+                ManagerControlMessageContents::StartScenario(None, vec![], None)
             } else {
                 let verb = words[0].to_lowercase();
                 words.remove(0);
 
                 match verb.as_str() {
+                    "state" => ManagerControlMessageContents::TriggerState(words.join(" ")),
+                    "abort" => ManagerControlMessageContents::AbortTests,
                     "stop" => ManagerControlMessageContents::Unimplemented(
                         "stop".to_owned(),
                         "Unable to stop tests".to_owned(),
                     ),
                     "start" => {
-                        if !words.is_empty() {
-                            match UnitName::from_str(&words[0], "test") {
-                                Ok(name) => {
-                                    ManagerControlMessageContents::StartScenario(Some(name))
-                                }
-                                Err(_) => ManagerControlMessageContents::Unimplemented(
-                                    words[0].clone(),
-                                    "name could not be decoded".to_owned(),
-                                ),
+                        let (scenario, payload) = Self::parse_start(&line);
+                        match scenario.map(|s| UnitName::from_str(s, "scenario")) {
+                            None => {
+                                ManagerControlMessageContents::StartScenario(None, vec![], payload)
                             }
-                        } else {
-                            ManagerControlMessageContents::StartScenario(None)
+                            Some(Ok(name)) => ManagerControlMessageContents::StartScenario(
+                                Some(name),
+                                vec![],
+                                payload,
+                            ),
+                            Some(Err(_)) => ManagerControlMessageContents::Unimplemented(
+                                words[0].clone(),
+                                "name could not be decoded".to_owned(),
+                            ),
                         }
                     }
                     v => {
@@ -0,0 +1,353 @@
+// Lifecycle of units held by the UnitManager.  Every change to a unit's
+// state goes through UnitTransition::apply(), which rejects transitions
+// that make no sense (e.g. activating a unit that was never selected).
+//
+// Where the manager knows what state a unit is in, such as when it loads,
+// selects, or activates one, it holds a Known unit, whose type says what
+// state it's in, and whose methods are the transitions out of that state.
+// Those can't be written wrong without failing to compile.  Everything else,
+// such as a test exiting or STOP from an interface, is checked by apply()
+// when it arrives, and an invalid one is logged and ignored.
+use std::fmt;
+use std::marker::PhantomData;
+
+use unit::{UnitFailure, UnitName};
+use unitbroadcaster::UnitStatusEvent;
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum UnitState {
+    /// The unit has been loaded, and may be selected.
+    Loaded,
+
+    /// The unit has been selected, and may be activated.
+    Selected,
+
+    /// The unit is currently in use.
+    Active,
+}
+
+impl fmt::Display for UnitState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnitState::Loaded => write!(f, "loaded"),
+            UnitState::Selected => write!(f, "selected"),
+            UnitState::Active => write!(f, "active"),
+        }
+    }
+}
+
+/// Something that happened to a unit, which may move it to a new UnitState.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum UnitTransition {
+    /// The unit was loaded (or reloaded) into the manager.
+    Loaded,
+
+    /// The unit couldn't be loaded.
+    LoadFailed(UnitFailure),
+
+    /// The unit was selected.
+    Selected,
+
+    /// The unit couldn't be selected.
+    SelectFailed(UnitFailure),
+
+    /// The unit was started.
+    Activated,
+
+    /// The unit couldn't be started, or stopped unexpectedly.
+    ActivationFailed(UnitFailure),
+
+    /// The unit was stopped.
+    Deactivated(String /* reason */),
+
+    /// The unit was stopped, but reported an error when doing so.
+    DeactivationFailed(UnitFailure),
+
+    /// The unit was deselected.
+    Deselected(String /* reason */),
+
+    /// The unit was deselected because it doesn't work with the current jig.
+    Incompatible(String /* reason */),
+
+    /// The unit was removed from the manager.
+    Unloaded,
+}
+
+impl fmt::Display for UnitTransition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnitTransition::Loaded => write!(f, "load"),
+            UnitTransition::LoadFailed(_) => write!(f, "load failure"),
+            UnitTransition::Selected => write!(f, "select"),
+            UnitTransition::SelectFailed(_) => write!(f, "select failure"),
+            UnitTransition::Activated => write!(f, "activate"),
+            UnitTransition::ActivationFailed(_) => write!(f, "activation failure"),
+            UnitTransition::Deactivated(_) => write!(f, "deactivate"),
+            UnitTransition::DeactivationFailed(_) => write!(f, "deactivation failure"),
+            UnitTransition::Deselected(_) => write!(f, "deselect"),
+            UnitTransition::Incompatible(_) => write!(f, "incompatibility"),
+            UnitTransition::Unloaded => write!(f, "unload"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidTransition {
+    id: UnitName,
+    transition: UnitTransition,
+    from: Option<UnitState>,
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.from {
+            None => write!(
+                f,
+                "invalid {} of {}: unit is not loaded",
+                self.transition, self.id
+            ),
+            Some(ref state) => write!(
+                f,
+                "invalid {} of {}: unit is {}",
+                self.transition, self.id, state
+            ),
+        }
+    }
+}
+
+impl UnitTransition {
+    /// Determine the state a unit ends up in after this transition.  A result
+    /// of None means the unit is no longer known to the manager.
+    pub fn apply(
+        &self,
+        id: &UnitName,
+        from: Option<UnitState>,
+    ) -> Result<Option<UnitState>, InvalidTransition> {
+        let to = match (self, from) {
+            (UnitTransition::Loaded, None) | (UnitTransition::Loaded, Some(UnitState::Loaded)) => {
+                Some(UnitState::Loaded)
+            }
+            (UnitTransition::LoadFailed(_), None)
+            | (UnitTransition::LoadFailed(_), Some(UnitState::Loaded)) => from,
+            (UnitTransition::Selected, Some(UnitState::Loaded)) => Some(UnitState::Selected),
+            (UnitTransition::SelectFailed(_), None)
+            | (UnitTransition::SelectFailed(_), Some(UnitState::Loaded)) => from,
+            (UnitTransition::Activated, Some(UnitState::Selected)) => Some(UnitState::Active),
+            (UnitTransition::ActivationFailed(_), Some(UnitState::Selected))
+            | (UnitTransition::ActivationFailed(_), Some(UnitState::Active)) => {
+                Some(UnitState::Selected)
+            }
+            (UnitTransition::Deactivated(_), Some(UnitState::Active))
+            | (UnitTransition::DeactivationFailed(_), Some(UnitState::Active)) => {
+                Some(UnitState::Selected)
+            }
+            (UnitTransition::Deselected(_), Some(UnitState::Selected)) => Some(UnitState::Loaded),
+            (UnitTransition::Incompatible(_), Some(UnitState::Loaded)) => Some(UnitState::Loaded),
+            (UnitTransition::Unloaded, Some(UnitState::Loaded)) => None,
+            (_, from) => {
+                return Err(InvalidTransition {
+                    id: id.clone(),
+                    transition: self.clone(),
+                    from,
+                })
+            }
+        };
+        Ok(to)
+    }
+
+    /// The status event announcing this transition, if it should be announced.
+    pub fn status_event(&self, id: &UnitName) -> Option<UnitStatusEvent> {
+        match self {
+            UnitTransition::Loaded => Some(UnitStatusEvent::new_loaded(id)),
+            UnitTransition::LoadFailed(ref r) => {
+                Some(UnitStatusEvent::new_load_failed(id, r.clone()))
+            }
+            UnitTransition::Selected => Some(UnitStatusEvent::new_selected(id)),
+            UnitTransition::SelectFailed(ref r) => {
+                Some(UnitStatusEvent::new_select_failed(id, r.clone()))
+            }
+            UnitTransition::Activated => Some(UnitStatusEvent::new_active(id)),
+            UnitTransition::ActivationFailed(ref r) => {
+                Some(UnitStatusEvent::new_active_failed(id, r.clone()))
+            }
+            UnitTransition::Deactivated(ref r) => {
+                Some(UnitStatusEvent::new_deactivate_success(id, r.clone()))
+            }
+            UnitTransition::DeactivationFailed(ref r) => {
+                Some(UnitStatusEvent::new_deactivate_failure(id, r.clone()))
+            }
+            UnitTransition::Deselected(ref r) => {
+                Some(UnitStatusEvent::new_deselected(id, r.clone()))
+            }
+            UnitTransition::Incompatible(ref r) => {
+                Some(UnitStatusEvent::new_incompatible(id, r.clone()))
+            }
+            UnitTransition::Unloaded => None,
+        }
+    }
+}
+
+/// A UnitState as a type of its own, or not being loaded as one.
+pub trait TypedState {
+    /// The state this type stands for, or None for not loaded.
+    const STATE: Option<UnitState>;
+}
+
+/// The unit isn't loaded.
+pub struct Unloaded;
+
+/// The unit is in UnitState::Loaded.
+pub struct Loaded;
+
+/// The unit is in UnitState::Selected.
+pub struct Selected;
+
+/// The unit is in UnitState::Active.
+pub struct Active;
+
+impl TypedState for Unloaded {
+    const STATE: Option<UnitState> = None;
+}
+
+impl TypedState for Loaded {
+    const STATE: Option<UnitState> = Some(UnitState::Loaded);
+}
+
+impl TypedState for Selected {
+    const STATE: Option<UnitState> = Some(UnitState::Selected);
+}
+
+impl TypedState for Active {
+    const STATE: Option<UnitState> = Some(UnitState::Active);
+}
+
+/// States a unit may be loaded, or fail to load or be selected, from.
+pub trait Unselected: TypedState {}
+impl Unselected for Unloaded {}
+impl Unselected for Loaded {}
+
+/// States a unit may fail to start, or stop unexpectedly, from.
+pub trait InUse: TypedState {}
+impl InUse for Selected {}
+impl InUse for Active {}
+
+/// A unit that's known to be in the state `S`.
+pub struct Known<'a, S> {
+    id: &'a UnitName,
+    state: PhantomData<S>,
+}
+
+impl<'a, S: TypedState> Known<'a, S> {
+    /// The unit, if `state` is the one it's in now.
+    pub fn check(id: &'a UnitName, state: Option<UnitState>) -> Option<Self> {
+        if state == S::STATE {
+            Some(Known {
+                id,
+                state: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn id(&self) -> &'a UnitName {
+        self.id
+    }
+
+    fn step<T>(self, transition: UnitTransition) -> Step<'a, T> {
+        Step {
+            id: self.id,
+            from: S::STATE,
+            transition,
+            to: PhantomData,
+        }
+    }
+}
+
+impl<'a, S: Unselected> Known<'a, S> {
+    pub fn load(self) -> Step<'a, Loaded> {
+        self.step(UnitTransition::Loaded)
+    }
+
+    pub fn load_failed(self, failure: UnitFailure) -> Step<'a, S> {
+        self.step(UnitTransition::LoadFailed(failure))
+    }
+
+    pub fn select_failed(self, failure: UnitFailure) -> Step<'a, S> {
+        self.step(UnitTransition::SelectFailed(failure))
+    }
+}
+
+impl<'a> Known<'a, Loaded> {
+    pub fn select(self) -> Step<'a, Selected> {
+        self.step(UnitTransition::Selected)
+    }
+
+    pub fn incompatible(self, reason: String) -> Step<'a, Loaded> {
+        self.step(UnitTransition::Incompatible(reason))
+    }
+
+    pub fn unload(self) -> Step<'a, Unloaded> {
+        self.step(UnitTransition::Unloaded)
+    }
+}
+
+impl<'a> Known<'a, Selected> {
+    pub fn activate(self) -> Step<'a, Active> {
+        self.step(UnitTransition::Activated)
+    }
+
+    pub fn deselect(self, reason: String) -> Step<'a, Loaded> {
+        self.step(UnitTransition::Deselected(reason))
+    }
+}
+
+impl<'a, S: InUse> Known<'a, S> {
+    pub fn activation_failed(self, failure: UnitFailure) -> Step<'a, Selected> {
+        self.step(UnitTransition::ActivationFailed(failure))
+    }
+}
+
+impl<'a> Known<'a, Active> {
+    pub fn deactivate(self, reason: String) -> Step<'a, Selected> {
+        self.step(UnitTransition::Deactivated(reason))
+    }
+
+    pub fn deactivation_failed(self, failure: UnitFailure) -> Step<'a, Selected> {
+        self.step(UnitTransition::DeactivationFailed(failure))
+    }
+}
+
+/// A transition of a Known unit, which leaves it in the state `S`.
+pub struct Step<'a, S> {
+    id: &'a UnitName,
+    from: Option<UnitState>,
+    transition: UnitTransition,
+    to: PhantomData<S>,
+}
+
+impl<'a, S: TypedState> Step<'a, S> {
+    pub fn id(&self) -> &'a UnitName {
+        self.id
+    }
+
+    pub fn from(&self) -> Option<UnitState> {
+        self.from
+    }
+
+    pub fn to(&self) -> Option<UnitState> {
+        S::STATE
+    }
+
+    pub fn transition(&self) -> &UnitTransition {
+        &self.transition
+    }
+
+    /// The unit, in the state this leaves it in, once it's been made.
+    pub fn done(self) -> Known<'a, S> {
+        Known {
+            id: self.id,
+            state: PhantomData,
+        }
+    }
+}
@@ -1,19 +1,49 @@
 extern crate notify;
 
+use std::collections::HashMap;
+use std::env;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use chaos;
+use poison::Lock;
+use profile::{self, Timing};
+use sha256;
+use unit::UnitKind;
 use unitbroadcaster::*;
+use unitcache::{self, SharedUnitCache, UnitCache};
 
 use self::notify::{RecommendedWatcher, RecursiveMode, Watcher};
 
+/// Unit directories, and the kind of unit each is limited to, if any.
+type SearchPaths = Arc<Mutex<Vec<(PathBuf, Option<UnitKind>)>>>;
+
+/// The SHA-256 of each unit file's contents when it was last announced, by
+/// absolute path, so that rewriting a file with the same contents, as rsync
+/// and touch do, doesn't reload it.
+type Digests = Arc<Mutex<HashMap<PathBuf, String>>>;
+
+/// The most unit files to parse at once while scanning a unit directory.
+const MAX_SCAN_THREADS: usize = 8;
+
 pub struct UnitWatcher {
-    paths: Vec<PathBuf>,
+    /// Unit directories being watched.  Shared with the watcher thread, so
+    /// units in subdirectories can be given a namespace.
+    paths: SearchPaths,
+    digests: Digests,
     watcher: RecommendedWatcher,
     broadcaster: UnitBroadcaster,
+
+    /// Where to parse the units found by scanning a directory, if anywhere.
+    unit_cache: Option<SharedUnitCache>,
+
+    /// Whether tests are left to be parsed once they're needed, rather than
+    /// when they're found.
+    lazy_tests: bool,
 }
 
 impl UnitWatcher {
@@ -28,33 +58,85 @@ impl UnitWatcher {
         // This is a simple loop, but you may want to use more complex logic here,
         // for example to handle I/O.
         let thread_broadcaster = broadcaster.clone();
+        let paths = Arc::new(Mutex::new(vec![]));
+        let thread_paths = paths.clone();
+        let digests = Arc::new(Mutex::new(HashMap::new()));
+        let thread_digests = digests.clone();
         thread::spawn(move || {
             loop {
                 match watcher_rx.recv() {
                     Ok(event) => {
+                        // A "<unit>.disabled" marker appearing or disappearing
+                        // means the unit it sits next to needs to be reloaded,
+                        // even though the unit itself hasn't changed.  Each
+                        // path is only checked once, since the unit could be
+                        // removed between two checks.
+                        let marked = match event {
+                            notify::DebouncedEvent::Create(ref path)
+                            | notify::DebouncedEvent::Remove(ref path) => Self::marked_unit(path),
+                            notify::DebouncedEvent::Rename(ref from, ref to) => {
+                                Self::marked_unit(to).or_else(|| Self::marked_unit(from))
+                            }
+                            _ => None,
+                        };
+                        let (event, marker) = match marked {
+                            Some(unit) => (notify::DebouncedEvent::Write(unit), true),
+                            None => (event, false),
+                        };
+
                         // Convert the DebouncedEvent into a UnitEvent
+                        let event_for = |new: fn(&Path, &Path) -> Option<UnitStatusEvent>,
+                                         path: &Path| {
+                            Self::event_for(&thread_paths, new, path)
+                        };
                         let status_event = match event {
                             notify::DebouncedEvent::Create(path) => {
-                                UnitStatusEvent::new_added(&path)
+                                event_for(UnitStatusEvent::new_added, &path)
                             }
                             notify::DebouncedEvent::Write(path) => {
-                                UnitStatusEvent::new_updated(&path)
+                                event_for(UnitStatusEvent::new_updated, &path)
                             }
                             notify::DebouncedEvent::Remove(path) => {
-                                UnitStatusEvent::new_removed(&path)
+                                event_for(UnitStatusEvent::new_removed, &path)
                             }
                             // Convert Rename() into removed/added
                             notify::DebouncedEvent::Rename(old_name, new_name) => {
-                                if let Some(evt) = UnitStatusEvent::new_removed(&old_name) {
+                                if let Some(evt) =
+                                    event_for(UnitStatusEvent::new_removed, &old_name)
+                                {
+                                    Self::forget_contents(&thread_digests, &old_name);
                                     thread_broadcaster.broadcast(&UnitEvent::Status(evt));
                                 }
-                                UnitStatusEvent::new_added(&new_name)
+                                event_for(UnitStatusEvent::new_added, &new_name)
                             }
                             _ => None,
                         };
 
+                        // Files that were written, or replaced, with the same
+                        // contents they already had aren't announced.
+                        let status_event = status_event.filter(|evt| match evt.status {
+                            UnitStatus::Added(ref path) | UnitStatus::Updated(ref path) => {
+                                marker || Self::record_contents(&thread_digests, path)
+                            }
+                            UnitStatus::Removed(ref path) => {
+                                Self::forget_contents(&thread_digests, path);
+                                true
+                            }
+                            _ => true,
+                        });
+
                         // Send a copy of the message to each of the listeners.
                         if let Some(evt) = status_event {
+                            // As if the file changed again while this change
+                            // was being handled.
+                            if let Some(delay) = chaos::watcher_race() {
+                                let broadcaster = thread_broadcaster.clone();
+                                let evt = evt.clone();
+                                thread::spawn(move || {
+                                    thread::sleep(delay);
+                                    broadcaster.broadcast(&UnitEvent::Status(evt));
+                                });
+                            }
                             thread_broadcaster.broadcast(&UnitEvent::Status(evt));
                         }
                     }
@@ -64,25 +146,179 @@ impl UnitWatcher {
         });
 
         UnitWatcher {
-            paths: vec![],
+            paths,
+            digests,
             broadcaster: broadcaster.clone(),
             watcher,
+            unit_cache: None,
+            lazy_tests: false,
         }
     }
 
+    /// Parse the units found by scanning a directory into a cache, several
+    /// at a time, before announcing them, so that the library doesn't have
+    /// to parse them one after another as it loads them.
+    pub fn set_unit_cache(&mut self, cache: &SharedUnitCache) {
+        self.unit_cache = Some(cache.clone());
+    }
+
+    /// Don't parse tests ahead of time, since with LazyTests most of them
+    /// are never parsed at all.
+    pub fn set_lazy_tests(&mut self, lazy: bool) {
+        self.lazy_tests = lazy;
+    }
+
     pub fn add_path(&mut self, config_dir: &str) -> Result<(), io::Error> {
-        let dir = Path::new(config_dir);
+        self.add_search_path(Path::new(config_dir), None)
+    }
+
+    /// Watch a directory for units, ignoring any that aren't of `kind`, if
+    /// it's given.
+    pub fn add_search_path(&mut self, dir: &Path, kind: Option<UnitKind>) -> Result<(), io::Error> {
+        // Events from the watcher carry absolute paths.
+        let absolute_dir = if dir.is_absolute() {
+            dir.to_owned()
+        } else {
+            env::current_dir()?.join(dir)
+        };
+        self.paths.locked().push((absolute_dir, kind.clone()));
+        // Watched first, so that nothing that changes during a long scan
+        // is missed.
+        self.watch(dir).expect("Unable to watch directory");
+        let started = Instant::now();
+        let mut found = vec![];
+        Self::scan(dir, dir, kind.as_ref(), &mut found)?;
+        self.announce(found);
+        profile::record(Timing::Scan, started.elapsed());
+        Ok(())
+    }
+
+    /// Find every unit under `dir`, including those in subdirectories.
+    fn scan(
+        root: &Path,
+        dir: &Path,
+        kind: Option<&UnitKind>,
+        found: &mut Vec<UnitStatusEvent>,
+    ) -> Result<(), io::Error> {
         for entry in dir.read_dir()? {
-            if let Some(evt) = UnitStatusEvent::new_added(&entry?.path()) {
-                self.broadcaster.broadcast(&UnitEvent::Status(evt));
+            let path = entry?.path();
+            if path.is_dir() {
+                Self::scan(root, &path, kind, found)?;
+            } else if let Some(evt) = UnitStatusEvent::new_added(root, &path) {
+                if kind.map(|kind| evt.name().kind() == kind).unwrap_or(true) {
+                    found.push(evt);
+                }
             }
         }
-
-        self.watch(dir).expect("Unable to watch directory");
-        self.paths.push(dir.to_owned());
         Ok(())
     }
 
+    /// Announce units that were found by a scan, on up to MAX_SCAN_THREADS
+    /// threads, noting what each holds.  With a unit cache, each is parsed
+    /// first, and announced as soon as it's done.  Files that can't be parsed
+    /// are announced anyway, so the library can report why.
+    fn announce(&self, found: Vec<UnitStatusEvent>) {
+        let threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .clamp(1, MAX_SCAN_THREADS)
+            .min(found.len());
+        let queue = Mutex::new(found.into_iter());
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let evt = match queue.locked().next() {
+                        Some(evt) => evt,
+                        None => break,
+                    };
+                    if let UnitStatus::Added(ref path) = evt.status {
+                        Self::record_contents(&self.digests, path);
+                        let lazy = self.lazy_tests && *evt.name().kind() == UnitKind::Test;
+                        if let (Some(cache), false) = (self.unit_cache.as_ref(), lazy) {
+                            let allowed = unitcache::named_sections(evt.name().kind());
+                            UnitCache::preload(cache, path, allowed).ok();
+                        }
+                    }
+                    self.broadcaster.broadcast(&UnitEvent::Status(evt));
+                });
+            }
+        });
+    }
+
+    /// Make an event for the unit at `path`, relative to the unit directory
+    /// it's in, unless that directory is only for other kinds of unit.  A
+    /// directory may be listed more than once, for different kinds.
+    fn event_for(
+        paths: &SearchPaths,
+        new: fn(&Path, &Path) -> Option<UnitStatusEvent>,
+        path: &Path,
+    ) -> Option<UnitStatusEvent> {
+        let paths = paths.locked();
+        let roots: Vec<&(PathBuf, Option<UnitKind>)> = paths
+            .iter()
+            .filter(|(root, _)| path.starts_with(root))
+            .collect();
+        let depth = match roots
+            .iter()
+            .map(|(root, _)| root.components().count())
+            .max()
+        {
+            Some(depth) => depth,
+            None => return new(path.parent().unwrap_or_else(|| Path::new("")), path),
+        };
+        roots
+            .into_iter()
+            .filter(|(root, _)| root.components().count() == depth)
+            .filter_map(|(root, kind)| {
+                new(root, path).filter(|evt| {
+                    kind.as_ref()
+                        .map(|kind| evt.name().kind() == kind)
+                        .unwrap_or(true)
+                })
+            })
+            .next()
+    }
+
+    /// Note what a unit file holds now.  Returns false if it holds the same
+    /// as the last time it was noted, so it hasn't really changed.  A file
+    /// that can't be read is always taken to have changed.
+    fn record_contents(digests: &Digests, path: &Path) -> bool {
+        let digest = match sha256::file(path) {
+            Ok(digest) => digest,
+            Err(_) => return true,
+        };
+        let previous = digests
+            .locked()
+            .insert(Self::absolute(path), digest.clone());
+        previous != Some(digest)
+    }
+
+    fn forget_contents(digests: &Digests, path: &Path) {
+        digests.locked().remove(&Self::absolute(path));
+    }
+
+    /// The path, relative to the current directory if it isn't absolute,
+    /// since events from the watcher carry absolute paths.
+    fn absolute(path: &Path) -> PathBuf {
+        env::current_dir()
+            .map(|dir| dir.join(path))
+            .unwrap_or_else(|_| path.to_owned())
+    }
+
+    /// If `path` is a "<unit>.disabled" marker for a unit file that exists,
+    /// return the path to that unit file.
+    fn marked_unit(path: &Path) -> Option<PathBuf> {
+        if path.extension().map(|e| e != "disabled").unwrap_or(true) {
+            return None;
+        }
+        let unit_path = path.with_extension("");
+        if unit_path.exists() {
+            Some(unit_path)
+        } else {
+            None
+        }
+    }
+
     fn watch(&mut self, path: &Path) -> notify::Result<()> {
         // Add a path to be watched. All files and directories at that path and
         // below will be monitored for changes.
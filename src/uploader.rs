@@ -0,0 +1,453 @@
+// Ships completed run directories to a central server.  Each run is packed
+// with "tar -cz" and piped into the UploadCommand from the settings file, so
+// any transport that can read from stdin (curl, sftp, ssh, ...) may be used.
+// Runs waiting to be uploaded are listed in the state directory, if there is
+// one, so they're still uploaded if exclave is restarted before they are.
+//
+// The same thread deletes runs from the LogDirectory once they're older than
+// LogRetention.  With RetentionExport, a run is only deleted once it's been
+// uploaded, and is uploaded first if it hasn't been.  Whatever the age,
+// runs are deleted, oldest first, while the disk has less than
+// MinimumFreeSpace free, even ones that haven't been uploaded: losing some
+// old results is better than having nowhere to write new ones.
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use config::Config;
+use poison::Lock;
+use sys;
+use unit::UnitName;
+use unitbroadcaster::{LogEntry, UnitBroadcaster, UnitEvent};
+
+/// How many bytes to read from the archive before checking the rate limit.
+const UPLOAD_CHUNK_SIZE: usize = 16384;
+
+/// Name of the file under the state directory that lists the runs waiting to
+/// be uploaded.
+const UPLOAD_SPOOL_FILE: &str = "upload-spool";
+
+/// Name of the file left in a run directory once it's been uploaded.
+pub const UPLOADED_MARKER: &str = ".uploaded";
+
+/// How often to look for runs to delete, in between runs finishing.
+const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Settings for shipping runs, copied out of the Config for the upload thread.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct UploadSettings {
+    /// Shell command that reads a .tar.gz archive on stdin and uploads it.
+    pub command: String,
+
+    /// If set, finished runs are uploaded in batches this often, rather than
+    /// as soon as they finish.
+    pub interval: Option<Duration>,
+
+    /// Maximum number of bytes per second to feed to the upload command.
+    pub rate_limit: Option<u64>,
+}
+
+/// Settings for deleting old runs, copied out of the Config.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct RetentionSettings {
+    /// Where the runs are.
+    pub log_directory: PathBuf,
+
+    /// Runs that started longer ago than this are deleted.
+    pub max_age: Option<Duration>,
+
+    /// If set, runs are uploaded before they're deleted for their age, and
+    /// kept until they have been.
+    pub export: bool,
+
+    /// The oldest runs are deleted while fewer bytes than this are free.
+    pub minimum_free_space: Option<u64>,
+}
+
+enum UploadRequest {
+    /// A run finished.  It should be uploaded with these settings, if there
+    /// are any, and old runs deleted with these.  Pending runs are listed in
+    /// the spool file, if there is one.
+    RunFinished(
+        PathBuf,
+        Option<UploadSettings>,
+        Option<PathBuf>,
+        Option<RetentionSettings>,
+    ),
+}
+
+pub struct Uploader {
+    config: Arc<Mutex<Config>>,
+    sender: Sender<UploadRequest>,
+}
+
+impl Uploader {
+    pub fn new(broadcaster: &UnitBroadcaster, config: &Arc<Mutex<Config>>) -> Uploader {
+        let (sender, receiver) = channel();
+        let broadcaster = broadcaster.clone();
+
+        thread::spawn(move || {
+            let mut pending: Vec<PathBuf> = vec![];
+            let mut spool: Option<PathBuf> = None;
+            let mut settings: Option<UploadSettings> = None;
+            let mut retention: Option<RetentionSettings> = None;
+            let mut next_batch = Instant::now();
+            let mut next_check = Instant::now();
+            loop {
+                let interval = settings.as_ref().and_then(|s| s.interval);
+                let wake = match (interval, retention.is_some()) {
+                    (Some(_), true) => Some(next_batch.min(next_check)),
+                    (Some(_), false) => Some(next_batch),
+                    (None, true) => Some(next_check),
+                    (None, false) => None,
+                };
+                let request = match wake {
+                    Some(wake) => {
+                        let now = Instant::now();
+                        if wake <= now {
+                            Err(RecvTimeoutError::Timeout)
+                        } else {
+                            receiver.recv_timeout(wake - now)
+                        }
+                    }
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                let (ship, check) = match request {
+                    Ok(UploadRequest::RunFinished(run, new_settings, new_spool, new_retention)) => {
+                        // Runs left over from before a restart go first.
+                        if spool.is_none() {
+                            if let Some(ref path) = new_spool {
+                                pending.extend(Self::read_spool(path));
+                            }
+                        }
+                        spool = new_spool;
+                        if new_settings.is_some() && !pending.contains(&run) {
+                            pending.push(run);
+                        }
+                        Self::write_spool(&broadcaster, spool.as_ref(), &pending);
+                        let ship = new_settings.as_ref().is_some_and(|s| s.interval.is_none());
+                        settings = new_settings;
+                        retention = new_retention;
+                        (ship, true)
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        let now = Instant::now();
+                        let ship = match interval.filter(|_| next_batch <= now) {
+                            Some(interval) => {
+                                next_batch = now + interval;
+                                true
+                            }
+                            None => false,
+                        };
+                        (ship, retention.is_some() && next_check <= now)
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                };
+                if let Some(settings) = settings.as_ref().filter(|_| ship) {
+                    // Runs that fail to upload stay pending, and are retried
+                    // along with the next batch.
+                    pending.retain(|run| !Self::ship(&broadcaster, run, settings));
+                    Self::write_spool(&broadcaster, spool.as_ref(), &pending);
+                }
+                if let Some(retention) = retention.as_ref().filter(|_| check) {
+                    next_check = Instant::now() + RETENTION_CHECK_INTERVAL;
+                    Self::reap(&broadcaster, retention, settings.as_ref(), &mut pending);
+                    Self::write_spool(&broadcaster, spool.as_ref(), &pending);
+                }
+            }
+        });
+
+        Uploader {
+            config: config.clone(),
+            sender,
+        }
+    }
+
+    pub fn process_message(&self, evt: &UnitEvent) {
+        if let UnitEvent::RunFinished(ref run) = *evt {
+            let config = self.config.locked();
+            let settings = config.upload_settings();
+            let retention = config.retention_settings();
+            if settings.is_none() && retention.is_none() {
+                return;
+            }
+            let spool = match config.create_state_directory() {
+                Ok(dir) => dir.map(|dir| dir.join(UPLOAD_SPOOL_FILE)),
+                Err(_) => None,
+            };
+            self.sender
+                .send(UploadRequest::RunFinished(
+                    run.clone(),
+                    settings,
+                    spool,
+                    retention,
+                ))
+                .ok();
+        }
+    }
+
+    /// The runs listed in a spool file that are still there to be uploaded.
+    fn read_spool(path: &Path) -> Vec<PathBuf> {
+        fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(PathBuf::from)
+            .filter(|run| run.is_dir())
+            .collect()
+    }
+
+    /// List the runs that are waiting to be uploaded, or remove the spool
+    /// file if there aren't any.
+    fn write_spool(broadcaster: &UnitBroadcaster, path: Option<&PathBuf>, pending: &[PathBuf]) {
+        let path = match path {
+            Some(path) => path,
+            None => return,
+        };
+        let result = if pending.is_empty() {
+            match fs::remove_file(path) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                other => other,
+            }
+        } else {
+            let mut contents = String::new();
+            for run in pending {
+                contents.push_str(&format!("{}\n", run.display()));
+            }
+            fs::write(path, contents)
+        };
+        if let Err(e) = result {
+            broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                UnitName::internal("uploader"),
+                format!("unable to write {}: {}", path.display(), e),
+            )));
+        }
+    }
+
+    /// Upload one run, logging the outcome.  Returns true if it was uploaded.
+    fn ship(broadcaster: &UnitBroadcaster, run: &Path, settings: &UploadSettings) -> bool {
+        let id = UnitName::internal("uploader");
+        match Self::upload(run, settings) {
+            Ok(size) => {
+                broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_info(
+                    id,
+                    format!("uploaded {} ({} bytes)", run.display(), size),
+                )));
+                // So the run isn't uploaded again before it's deleted.
+                fs::write(run.join(UPLOADED_MARKER), "").ok();
+                true
+            }
+            Err(e) => {
+                broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    id,
+                    format!("unable to upload {}: {}", run.display(), e),
+                )));
+                false
+            }
+        }
+    }
+
+    /// Delete runs that are older than LogRetention, and then the oldest
+    /// runs while there's less than MinimumFreeSpace free.  The newest run
+    /// is always kept, since it may still be being written to.
+    fn reap(
+        broadcaster: &UnitBroadcaster,
+        retention: &RetentionSettings,
+        settings: Option<&UploadSettings>,
+        pending: &mut Vec<PathBuf>,
+    ) {
+        let mut runs = Self::runs(&retention.log_directory);
+        runs.pop();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut kept = vec![];
+        for (started, run) in runs {
+            let expired = retention
+                .max_age
+                .is_some_and(|age| started.saturating_add(age.as_secs()) <= now);
+            if !expired {
+                kept.push(run);
+                continue;
+            }
+            if retention.export && !run.join(UPLOADED_MARKER).exists() {
+                // Runs that are already waiting to be uploaded are retried
+                // with the next batch, rather than here.
+                let uploaded = !pending.contains(&run)
+                    && settings.is_some_and(|settings| Self::ship(broadcaster, &run, settings));
+                if !uploaded {
+                    kept.push(run);
+                    continue;
+                }
+            }
+            pending.retain(|p| p != &run);
+            Self::delete(broadcaster, &run, "it's older than LogRetention".to_owned());
+        }
+
+        let minimum = match retention.minimum_free_space {
+            Some(minimum) => minimum,
+            None => return,
+        };
+        for run in kept {
+            let free = match sys::free_space(&retention.log_directory) {
+                Some(free) if free < minimum => free,
+                _ => break,
+            };
+            let reason = format!("only {} bytes are free", free);
+            if settings.is_some() && !run.join(UPLOADED_MARKER).exists() {
+                broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    UnitName::internal("uploader"),
+                    format!(
+                        "deleting {} before it was uploaded, since {}",
+                        run.display(),
+                        reason
+                    ),
+                )));
+            }
+            pending.retain(|p| p != &run);
+            Self::delete(broadcaster, &run, reason);
+        }
+    }
+
+    /// The run directories in a LogDirectory, oldest first, along with when
+    /// each started, in seconds since the epoch, as given by its name.
+    /// Anything else in the directory is left alone.
+    fn runs(log_directory: &Path) -> Vec<(u64, PathBuf)> {
+        let entries = match fs::read_dir(log_directory) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        let mut runs: Vec<(u64, PathBuf)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let started = name.split_once('-')?.0.parse().ok()?;
+                Some((started, entry.path()))
+            })
+            .collect();
+        runs.sort();
+        runs
+    }
+
+    fn delete(broadcaster: &UnitBroadcaster, run: &Path, reason: String) {
+        let id = UnitName::internal("uploader");
+        match fs::remove_dir_all(run) {
+            Ok(()) => broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_info(
+                id,
+                format!("deleted {}, since {}", run.display(), reason),
+            ))),
+            Err(e) => broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                id,
+                format!("unable to delete {}: {}", run.display(), e),
+            ))),
+        }
+    }
+
+    fn upload(run: &Path, settings: &UploadSettings) -> io::Result<u64> {
+        let (parent, name) = match (run.parent(), run.file_name()) {
+            (Some(p), Some(n)) => (p, n),
+            _ => return Err(io::Error::other("not a run directory")),
+        };
+
+        let mut tar = Command::new("tar")
+            .arg("-czf")
+            .arg("-")
+            .arg("-C")
+            .arg(parent)
+            .arg(name)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut upload = match Self::shell(&settings.command)
+            .env(
+                "EXCLAVE_ARCHIVE",
+                format!("{}.tar.gz", name.to_string_lossy()),
+            )
+            .env("EXCLAVE_RUN_DIRECTORY", run)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(u) => u,
+            Err(e) => {
+                tar.kill().ok();
+                tar.wait().ok();
+                return Err(e);
+            }
+        };
+
+        let copied = Self::copy_limited(
+            tar.stdout.as_mut().unwrap(),
+            upload.stdin.as_mut().unwrap(),
+            settings.rate_limit,
+        );
+        // Close stdin so the upload command sees the end of the archive.
+        drop(upload.stdin.take());
+        if copied.is_err() {
+            tar.kill().ok();
+        }
+        let tar_status = tar.wait()?;
+        let upload_status = upload.wait()?;
+        let size = copied?;
+
+        if !tar_status.success() {
+            return Err(io::Error::other(format!("tar exited with {}", tar_status)));
+        }
+        if !upload_status.success() {
+            return Err(io::Error::other(format!(
+                "upload command exited with {}",
+                upload_status
+            )));
+        }
+        Ok(size)
+    }
+
+    /// Copy everything from `source` to `dest`, sleeping as needed to stay
+    /// under `rate_limit` bytes per second.
+    fn copy_limited<R: Read, W: Write>(
+        source: &mut R,
+        dest: &mut W,
+        rate_limit: Option<u64>,
+    ) -> io::Result<u64> {
+        let start = Instant::now();
+        let mut buffer = [0; UPLOAD_CHUNK_SIZE];
+        let mut total = 0;
+        loop {
+            let count = source.read(&mut buffer)?;
+            if count == 0 {
+                return Ok(total);
+            }
+            dest.write_all(&buffer[..count])?;
+            total += count as u64;
+            if let Some(rate) = rate_limit.filter(|&r| r > 0) {
+                let due = Duration::from_secs_f64(total as f64 / rate as f64);
+                let elapsed = start.elapsed();
+                if due > elapsed {
+                    thread::sleep(due - elapsed);
+                }
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn shell(command: &str) -> Command {
+        let mut cmd = Command::new("/bin/sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+
+    #[cfg(windows)]
+    fn shell(command: &str) -> Command {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    }
+}
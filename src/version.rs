@@ -0,0 +1,82 @@
+// Which build of exclave is running, so that results and logs can be traced
+// back to the exact code that produced them.  The commit and build date are
+// embedded by build.rs when exclave is compiled.  The build is given at
+// startup, in HELLO, in answer to VERSION, in every result record, and in
+// snapshots.
+use std::fmt::Write;
+
+/// The version from Cargo.toml.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What "git describe --tags" said, or "no-git-version" if there's no tag.
+pub const GIT_VERSION: &str = env!("GIT_VERSION");
+
+/// The commit that was built, with "-modified" after it if there were
+/// uncommitted changes, or "unknown" if it wasn't built from a checkout.
+pub const GIT_HASH: &str = env!("GIT_HASH");
+
+/// When exclave was built, in UTC, as "YYYY-MM-DDTHH:MM:SSZ".
+pub const BUILD_DATE: &str = env!("BUILD_DATE");
+
+/// The optional features exclave was built with.
+pub fn features() -> Vec<&'static str> {
+    let mut features = vec![];
+    if cfg!(feature = "postgres") {
+        features.push("postgres");
+    }
+    features
+}
+
+/// A short name for this build, as stored with results: the version,
+/// followed by the first twelve characters of the commit, such as
+/// "0.2.6+9889faf1a2b3", or just the version if the commit isn't known.
+pub fn build_id() -> String {
+    match GIT_HASH {
+        "unknown" => VERSION.to_owned(),
+        hash => {
+            let modified = hash.ends_with("-modified");
+            format!(
+                "{}+{}{}",
+                VERSION,
+                &hash[..hash.len().min(12)],
+                if modified { "-modified" } else { "" }
+            )
+        }
+    }
+}
+
+/// Everything known about this build, as tab-separated "key=value" fields:
+/// "version", "git", "commit", "built", and "features" (separated by commas).
+pub fn fields() -> String {
+    let mut fields = String::new();
+    for (key, value) in &[
+        ("version", VERSION.to_owned()),
+        ("git", GIT_VERSION.to_owned()),
+        ("commit", GIT_HASH.to_owned()),
+        ("built", BUILD_DATE.to_owned()),
+        ("features", features().join(",")),
+    ] {
+        if !fields.is_empty() {
+            fields.push('\t');
+        }
+        write!(fields, "{}={}", key, value).unwrap();
+    }
+    fields
+}
+
+/// The version, followed by the rest of what's known about the build, for
+/// logs and "--version".
+pub fn summary() -> String {
+    let features = features();
+    format!(
+        "{} (commit {}, built {}, features: {})",
+        VERSION,
+        GIT_HASH,
+        BUILD_DATE,
+        if features.is_empty() {
+            "none".to_owned()
+        } else {
+            features.join(", ")
+        }
+    )
+}